@@ -0,0 +1,123 @@
+// キャプチャから書き込みまでのホットパスのベンチマーク (cargo bench)
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::executor::block_on;
+use rdb_tunnel::bench::FrameGenerator;
+use rdb_tunnel::db_write::{parse_and_analyze_packet, PacketData};
+use rdb_tunnel::packet_header::{parse_ip_header, EthernetHeader, Ipv6Header, UdpHeader};
+use rdb_tunnel::transport::timescale::{insert_statement, CHUNK_SIZE};
+use std::hint::black_box;
+use std::net::Ipv6Addr;
+use tokio_postgres::types::Type;
+
+// packetsテーブルに挿入する列の型 (insert_statementのパラメータ順)
+const INSERT_TYPES: [Type; 12] = [
+    Type::MACADDR,
+    Type::MACADDR,
+    Type::INT4,
+    Type::INET,
+    Type::INET,
+    Type::INT4,
+    Type::INT4,
+    Type::INT4,
+    Type::TIMESTAMPTZ,
+    Type::BYTEA,
+    Type::BYTEA,
+    Type::TEXT,
+];
+
+const SRC_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+
+fn tcp_frame(size: usize) -> Vec<u8> {
+    FrameGenerator::new(size).next_frame().to_vec()
+}
+
+// IPv6/UDPのイーサネットフレーム
+fn udp_ipv6_frame(payload: usize) -> Vec<u8> {
+    let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+    let ethernet = EthernetHeader { dst_mac: [0x33, 0x33, 0, 0, 0, 1], src_mac: SRC_MAC, ether_type: 0x86DD };
+    let udp = UdpHeader { src_port: 40000, dst_port: 5000, payload_len: payload as u16 };
+
+    let mut frame = ethernet.to_bytes().to_vec();
+    frame.extend_from_slice(&Ipv6Header::new(src, dst, 17, (UdpHeader::LEN + payload) as u16).to_bytes());
+    frame.extend_from_slice(&udp.to_bytes());
+    frame.resize(frame.len() + payload, 0);
+    frame
+}
+
+fn arp_request() -> Vec<u8> {
+    let ethernet = EthernetHeader { dst_mac: [0xff; 6], src_mac: SRC_MAC, ether_type: 0x0806 };
+    let mut frame = ethernet.to_bytes().to_vec();
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    frame.extend_from_slice(&SRC_MAC);
+    frame.extend_from_slice(&[10, 0, 0, 1]);
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&[10, 0, 0, 2]);
+    frame
+}
+
+fn parse_packets(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_and_analyze_packet");
+    for (name, frame) in [
+        ("tcp_ipv4_64", tcp_frame(64)),
+        ("tcp_ipv4_1514", tcp_frame(1514)),
+        ("udp_ipv6_512", udp_ipv6_frame(512 - 62)),
+        ("arp", arp_request()),
+    ] {
+        // 解析はフレームのバッファを共有するため、cloneは参照カウントの増加のみ
+        let frame = Bytes::from(frame);
+        group.throughput(Throughput::Bytes(frame.len() as u64));
+        group.bench_function(name, |b| {
+            b.iter(|| block_on(parse_and_analyze_packet(black_box(frame.clone()))).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn headers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("header");
+    let mut generator = FrameGenerator::new(512);
+    group.bench_function("build_parse_ipv4", |b| {
+        b.iter(|| {
+            let frame = generator.next_frame();
+            parse_ip_header(black_box(&frame[14..])).unwrap().dst_ip
+        })
+    });
+
+    let frame = udp_ipv6_frame(64);
+    group.bench_function("parse_ipv6", |b| {
+        b.iter(|| parse_ip_header(black_box(&frame[14..])).unwrap().dst_ip)
+    });
+    group.finish();
+}
+
+fn batch_insert(c: &mut Criterion) {
+    let mut generator = FrameGenerator::new(512);
+    let packets: Vec<PacketData> = (0..CHUNK_SIZE)
+        .map(|_| block_on(parse_and_analyze_packet(Bytes::copy_from_slice(generator.next_frame()))).unwrap())
+        .collect();
+
+    let mut group = c.benchmark_group("batch_insert");
+    group.throughput(Throughput::Elements(CHUNK_SIZE as u64));
+    group.bench_function("statement", |b| b.iter(|| insert_statement(black_box(&packets)).0.len()));
+
+    // tokio-postgresがバインド時に行うパラメータのバイナリ表現への変換
+    group.bench_function("encode_params", |b| {
+        b.iter_batched_ref(
+            || BytesMut::with_capacity(CHUNK_SIZE * 1024),
+            |buffer| {
+                let (_, params) = insert_statement(black_box(&packets));
+                for (i, param) in params.iter().enumerate() {
+                    param.to_sql_checked(&INSERT_TYPES[i % INSERT_TYPES.len()], buffer).unwrap();
+                }
+                buffer.len()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse_packets, headers, batch_insert);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+// gRPC制御APIのコードを生成する (protocの代わりにprotoxでprotoファイルを解析する)
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let proto = "proto/rdb_tunnel.proto";
+    println!("cargo:rerun-if-changed={}", proto);
+
+    let file_descriptors = protox::compile([proto], ["proto"])?;
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_fds(file_descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,33 @@
+-- packetsテーブルの作成
+-- 旧 packet-log.sql で作成したデータベースもそのまま引き継げるように、全て存在確認付きで実行する
+CREATE TABLE IF NOT EXISTS packets
+(
+    id          BIGSERIAL,
+    src_mac     MACADDR     NOT NULL,
+    dst_mac     MACADDR     NOT NULL,
+    ether_type  INTEGER     NOT NULL,
+    src_ip      INET        NOT NULL,
+    dst_ip      INET        NOT NULL,
+    src_port    INTEGER,
+    dst_port    INTEGER,
+    ip_protocol INTEGER     NOT NULL,
+    timestamp   TIMESTAMPTZ NOT NULL,
+    data        BYTEA,
+    raw_packet  BYTEA,
+    -- キャプチャしたインターフェース名
+    interface   TEXT
+);
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS interface TEXT;
+
+-- TimescaleDBが利用可能な場合はハイパーテーブルにする (チャンク間隔は [migrations] chunk_interval で変更する)
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb') THEN
+        CREATE EXTENSION IF NOT EXISTS timescaledb;
+        PERFORM create_hypertable('packets', 'timestamp', chunk_time_interval => INTERVAL '1 day', if_not_exists => TRUE);
+    END IF;
+END
+$$;
+
+CREATE INDEX IF NOT EXISTS idx_packets_timestamp ON packets(timestamp DESC);
+CREATE INDEX IF NOT EXISTS idx_packets_ips ON packets(src_ip, dst_ip);
@@ -0,0 +1,13 @@
+-- プロトコル別のビュー (* は作成時に展開されるため、後から追加した列は含まれない。0013で列を明示して再作成する)
+
+-- ICMPパケット (IPv4 ICMP と IPv6 ICMPv6)
+CREATE OR REPLACE VIEW icmp_packets AS
+SELECT *
+FROM packets
+WHERE ip_protocol IN (1, 58);
+
+-- ARPパケット (EtherType 0x0806)
+CREATE OR REPLACE VIEW arp_packets AS
+SELECT *
+FROM packets
+WHERE ether_type = 2054; -- 0x0806
@@ -0,0 +1,10 @@
+-- ノードごとに処理済みのパケットを記録する (ポーリングでの重複・取りこぼし防止)
+CREATE TABLE IF NOT EXISTS packet_deliveries
+(
+    node         INET        NOT NULL,
+    packet_id    BIGINT      NOT NULL,
+    delivered_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (node, packet_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_packet_deliveries_delivered_at ON packet_deliveries(delivered_at);
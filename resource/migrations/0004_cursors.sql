@@ -0,0 +1,7 @@
+-- ノードごとのポーリング位置 (再起動時に続きから取得する)
+CREATE TABLE IF NOT EXISTS cursors
+(
+    node           INET PRIMARY KEY,
+    last_timestamp TIMESTAMPTZ NOT NULL,
+    updated_at     TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- idの順序でポーリングするモード ([poller] mode = "sequence") 用
+CREATE INDEX IF NOT EXISTS idx_packets_id ON packets(id);
+ALTER TABLE cursors ADD COLUMN IF NOT EXISTS last_id BIGINT;
@@ -0,0 +1,2 @@
+-- 受信側で欠落と重複を数えるための、送信側が (送信元, 宛先) ごとに採番した連番
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS seq BIGINT;
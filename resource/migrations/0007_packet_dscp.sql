@@ -0,0 +1,3 @@
+-- IPパケットのDSCPとECN (書き換え規則を適用した後の値)
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS dscp SMALLINT;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS ecn SMALLINT;
@@ -0,0 +1,2 @@
+-- raw_packetとdataの圧縮方式 (0: なし, 1: zstd, 2: LZ4)
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS codec SMALLINT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- 1行の上限を超えるフレームを複数の行に分割して保存した場合の断片の情報 (分割していない行はNULL)
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS chunk_id BIGINT;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS chunk_index SMALLINT;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS chunk_count SMALLINT;
@@ -0,0 +1,8 @@
+-- ノードごとのトンネルのアドレスと、ノードの先にあるサブネット (対向ノードが経路を設定するために使用する)
+CREATE TABLE IF NOT EXISTS peers
+(
+    node           INET        PRIMARY KEY,
+    tunnel_address INET        NOT NULL,
+    subnets        CIDR[]      NOT NULL DEFAULT '{}',
+    updated_at     TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,7 @@
+-- カプセル化 (GRE・IP-in-IP) の種類 (外側のIPプロトコル番号) と内側のパケットの情報 ([decapsulation] record_inner が無効な場合はNULL)
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS encapsulation SMALLINT;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS inner_src_ip INET;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS inner_dst_ip INET;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS inner_src_port INTEGER;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS inner_dst_port INTEGER;
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS inner_ip_protocol INTEGER;
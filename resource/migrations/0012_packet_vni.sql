@@ -0,0 +1,2 @@
+-- VXLANでカプセル化されたパケットのVNI ([decapsulation] でVXLANの内側を取り出した場合のみ)
+ALTER TABLE packets ADD COLUMN IF NOT EXISTS vni INTEGER;
@@ -0,0 +1,18 @@
+-- プロトコル別のビューを列を明示して再作成する
+-- (ビューの * は作成時に展開されるため、0006以降で追加した列が含まれていなかった。packets に列を追加した場合はここと同様に末尾へ追加する)
+
+-- ICMPパケット (IPv4 ICMP と IPv6 ICMPv6)
+CREATE OR REPLACE VIEW icmp_packets AS
+SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port, ip_protocol, timestamp, data, raw_packet,
+       interface, seq, dscp, ecn, codec, chunk_id, chunk_index, chunk_count,
+       encapsulation, inner_src_ip, inner_dst_ip, inner_src_port, inner_dst_port, inner_ip_protocol, vni
+FROM packets
+WHERE ip_protocol IN (1, 58);
+
+-- ARPパケット (EtherType 0x0806)
+CREATE OR REPLACE VIEW arp_packets AS
+SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port, ip_protocol, timestamp, data, raw_packet,
+       interface, seq, dscp, ecn, codec, chunk_id, chunk_index, chunk_count,
+       encapsulation, inner_src_ip, inner_dst_ip, inner_src_port, inner_dst_port, inner_ip_protocol, vni
+FROM packets
+WHERE ether_type = 2054; -- 0x0806
@@ -0,0 +1,322 @@
+use crate::bans;
+use crate::firewall::{Chain, Filter, Policy, Rule, RuleStats};
+use chrono::{DateTime, Utc};
+use crate::http_server::AppState;
+use crate::management;
+use crate::metrics;
+use crate::probe::{self, PingError, PingRequest};
+use crate::rules::{self, RuleFormat};
+use crate::schedule::Schedule;
+use crate::setup_logger::{current_log_filter, set_log_filter};
+use crate::stream;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::error;
+
+// ピア一覧の既定の集計期間
+pub const DEFAULT_PEER_WINDOW_SECS: u64 = 300;
+
+// 管理API (/api/v1)
+pub fn routes(api_token: Option<String>) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/firewall/rules",
+            get(list_rules_handler).post(add_rule_handler).delete(remove_rule_handler),
+        )
+        .route("/firewall/rules/export", get(export_rules_handler))
+        .route("/firewall/rules/import", put(import_rules_handler))
+        .route("/bans", get(list_bans_handler).post(ban_handler).delete(unban_handler))
+        .route("/peers", get(peers_handler))
+        .route("/peers/traffic", get(peer_traffic_handler))
+        .route("/stats", get(stats_handler))
+        .route("/ping", post(ping_handler))
+        .route("/maintenance/flush", post(flush_handler))
+        .route("/maintenance/prune", post(prune_handler))
+        .route("/stream/packets", get(stream::packets_handler))
+        .route("/log/filter", get(get_log_filter_handler).put(put_log_filter_handler))
+        .layer(middleware::from_fn(move |request: Request, next: Next| {
+            let api_token = api_token.clone();
+            async move { authorize(api_token.as_deref(), request, next).await }
+        }))
+}
+
+async fn authorize(api_token: Option<&str>, request: Request, next: Next) -> Response {
+    let Some(expected) = api_token else {
+        return next.run(request).await;
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        // ブラウザのWebSocketはヘッダーを指定できないため、クエリパラメータも受け付ける
+        .or_else(|| access_token_param(request.uri()));
+    if provided.as_deref() != Some(expected) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+fn access_token_param(uri: &Uri) -> Option<String> {
+    #[derive(Deserialize)]
+    struct TokenQuery {
+        access_token: Option<String>,
+    }
+
+    Query::<TokenQuery>::try_from_uri(uri).ok()?.0.access_token
+}
+
+// chainを省略した場合はOUTPUTチェインを対象とする
+#[derive(Debug, Serialize, Deserialize)]
+struct RuleEntry {
+    #[serde(default)]
+    chain: Chain,
+    filter: Filter,
+    priority: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule: Option<Schedule>,
+    // 一覧でのみ返す統計情報
+    #[serde(default, skip_deserializing)]
+    hits: u64,
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    last_match: Option<DateTime<Utc>>,
+}
+
+impl RuleEntry {
+    fn new(chain: Chain, stats: RuleStats) -> Self {
+        Self {
+            chain,
+            filter: stats.rule.filter,
+            priority: stats.rule.priority,
+            schedule: stats.rule.schedule,
+            hits: stats.hits,
+            last_match: stats.last_match,
+        }
+    }
+
+    fn rule(&self) -> Rule {
+        Rule { filter: self.filter.clone(), priority: self.priority, schedule: self.schedule.clone() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FirewallRules {
+    chain: Chain,
+    policy: Policy,
+    rules: Vec<RuleEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RulesQuery {
+    #[serde(default)]
+    chain: Chain,
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoveRule {
+    #[serde(default)]
+    chain: Chain,
+    filter: Filter,
+}
+
+async fn list_rules_handler(Query(query): Query<RulesQuery>) -> impl IntoResponse {
+    let (policy, rules) = management::firewall_rules(query.chain);
+    Json(FirewallRules {
+        chain: query.chain,
+        policy,
+        rules: rules
+            .into_iter()
+            .map(|rule| RuleEntry::new(query.chain, rule))
+            .collect(),
+    })
+}
+
+async fn add_rule_handler(Json(entry): Json<RuleEntry>) -> Response {
+    match management::add_firewall_rule(entry.chain, entry.rule()) {
+        Ok(()) => (StatusCode::CREATED, Json(entry)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+async fn remove_rule_handler(Json(rule): Json<RemoveRule>) -> impl IntoResponse {
+    if management::remove_firewall_rule(rule.chain, &rule.filter) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    #[serde(default)]
+    format: RuleFormat,
+}
+
+async fn export_rules_handler(Query(query): Query<FormatQuery>) -> Response {
+    match rules::serialize(&management::export_firewall_rules(), query.format) {
+        Ok(text) => ([(header::CONTENT_TYPE, query.format.content_type())], text).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+// 全てのチェインをまとめて置き換える (検証に失敗した場合は何も変更しない)
+async fn import_rules_handler(Query(query): Query<FormatQuery>, body: String) -> Response {
+    match rules::parse(&body, query.format).and_then(management::import_firewall_rules) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BanRequest {
+    ip: IpAddr,
+    // 省略した場合は [bans] duration
+    #[serde(default, with = "humantime_serde")]
+    duration: Option<Duration>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnbanRequest {
+    ip: IpAddr,
+}
+
+async fn list_bans_handler() -> impl IntoResponse {
+    Json(bans::bans())
+}
+
+async fn ban_handler(Json(request): Json<BanRequest>) -> Response {
+    match bans::ban(request.ip, request.duration) {
+        Ok(ban) => (StatusCode::CREATED, Json(ban)).into_response(),
+        Err(e) => (StatusCode::CONFLICT, e).into_response(),
+    }
+}
+
+async fn unban_handler(Json(request): Json<UnbanRequest>) -> impl IntoResponse {
+    if bans::unban(request.ip).is_some() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PeersQuery {
+    window_secs: Option<u64>,
+}
+
+async fn peers_handler(State(state): State<AppState>, Query(query): Query<PeersQuery>) -> impl IntoResponse {
+    let window = Duration::from_secs(query.window_secs.unwrap_or(DEFAULT_PEER_WINDOW_SECS));
+    match management::list_peers(state.database.as_deref(), state.tunnel_network, window).await {
+        Ok(peers) => Json(peers).into_response(),
+        Err(e) => {
+            error!("ピア一覧の取得に失敗しました: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+async fn peer_traffic_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(management::peer_traffic(state.tunnel_network))
+}
+
+async fn stats_handler(State(state): State<AppState>) -> impl IntoResponse {
+    Json(metrics::snapshot(state.database.as_deref()))
+}
+
+async fn ping_handler(Json(request): Json<PingRequest>) -> impl IntoResponse {
+    let interval = Duration::from_millis(request.interval_ms);
+    let wait = Duration::from_millis(request.timeout_ms);
+    match probe::ping(request.peer, request.count, interval, wait).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e @ PingError::UnsupportedAddress(_)) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        Err(e @ PingError::NotPolling) => (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response(),
+        Err(e) => {
+            error!("プローブの送信に失敗しました: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FlushResult {
+    flushed: usize,
+}
+
+async fn flush_handler() -> impl IntoResponse {
+    match management::flush().await {
+        Ok(flushed) => Json(FlushResult { flushed }).into_response(),
+        Err(e) => {
+            error!("パケットバッファのフラッシュに失敗しました: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PruneRequest {
+    older_than_secs: u64,
+}
+
+async fn prune_handler(State(state): State<AppState>, Json(request): Json<PruneRequest>) -> impl IntoResponse {
+    match management::prune(state.database.as_deref(), Duration::from_secs(request.older_than_secs)).await {
+        Ok(summary) => Json(summary).into_response(),
+        Err(e) => {
+            error!("古いパケットの削除に失敗しました: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+async fn get_log_filter_handler() -> impl IntoResponse {
+    match current_log_filter() {
+        Some(filter) => (StatusCode::OK, filter).into_response(),
+        None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+// 本文にEnvFilter形式のディレクティブを指定する
+async fn put_log_filter_handler(body: String) -> impl IntoResponse {
+    match set_log_filter(body.trim()) {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::TaskState;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn rejects_log_filter_change_without_token() {
+        let state = AppState {
+            task_state: Arc::new(Mutex::new(TaskState::new())),
+            tap_name: "tap0".to_string(),
+            tunnel_network: "10.0.0.0/24".parse().unwrap(),
+            database: None,
+        };
+        let app = Router::new().nest("/api/v1", routes(Some("secret".to_string()))).with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/api/v1/log/filter", address);
+        let response = client.put(&url).body("trace").send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::config::BanConfig;
+use crate::events::{self, Alert, PipelineEvent};
+use crate::firewall::{Chain, Filter, Firewall, Policy, Rule, FIREWALL};
+use crate::metrics::{BANS_ACTIVE, BANS_TOTAL};
+use crate::schedule::Schedule;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+// 検知機能の通知に応じて、攻撃元のIPアドレスを一時的に遮断する (fail2banと同様)。
+// find_window の間に max_retry 回検知されたアドレスに、有効期限付きの優先度の高い遮断ルールを追加し、
+// 期限を過ぎたルールは定期的に削除する。繰り返し遮断されたアドレスは遮断する時間を倍にする (max_duration まで)
+
+lazy_static! {
+    static ref CONFIG: RwLock<BanConfig> = RwLock::new(BanConfig::default());
+    static ref BANS: Mutex<BanTable> = Mutex::new(BanTable::default());
+}
+
+pub fn configure(config: &BanConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+// 遮断の契機となった検知機能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Detector {
+    // 通信量・パケット数の急増
+    Rate,
+    // ポートスキャン・ホストスキャン
+    Scan,
+    // 認証の総当たり
+    BruteForce,
+    // 管理APIから手動で遮断した
+    Manual,
+}
+
+impl fmt::Display for Detector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Detector::Rate => "rate",
+            Detector::Scan => "scan",
+            Detector::BruteForce => "brute_force",
+            Detector::Manual => "manual",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub detector: Detector,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    // このアドレスを遮断した回数 (今回を含む)
+    pub count: u32,
+    // 遮断ルールを追加したチェイン (既存のルールがある・ホワイトリストのチェインには追加しない)
+    pub chains: Vec<Chain>,
+}
+
+#[derive(Debug, Default)]
+struct BanTable {
+    // アドレスごとの直近の検知時刻
+    strikes: HashMap<IpAddr, VecDeque<DateTime<Utc>>>,
+    bans: HashMap<IpAddr, Ban>,
+    // 遮断が終了したアドレスの遮断回数と終了時刻 (max_duration の間、遮断する時間の延長に使用する)
+    history: HashMap<IpAddr, (u32, DateTime<Utc>)>,
+}
+
+impl BanTable {
+    // 検知を記録し、条件を満たした場合は遮断する
+    fn report(
+        &mut self,
+        firewall: &mut Firewall,
+        config: &BanConfig,
+        ip: IpAddr,
+        detector: Detector,
+        now: DateTime<Utc>,
+    ) -> Option<Ban> {
+        if self.bans.contains_key(&ip) {
+            return None;
+        }
+        let strikes = self.strikes.entry(ip).or_default();
+        strikes.push_back(now);
+        while strikes.front().is_some_and(|&strike| now - strike >= to_chrono(config.find_window)) {
+            strikes.pop_front();
+        }
+        if strikes.len() < config.max_retry as usize {
+            return None;
+        }
+        self.strikes.remove(&ip);
+        let count = self.history.remove(&ip).map_or(0, |(count, _)| count) + 1;
+        let duration = config.duration.saturating_mul(1 << (count - 1).min(16)).min(config.max_duration);
+        self.ban(firewall, config, ip, detector, now, duration, count)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn ban(
+        &mut self,
+        firewall: &mut Firewall,
+        config: &BanConfig,
+        ip: IpAddr,
+        detector: Detector,
+        now: DateTime<Utc>,
+        duration: Duration,
+        count: u32,
+    ) -> Option<Ban> {
+        let until = now + to_chrono(duration);
+        let filter = Filter::IpAddress(ip);
+        let mut chains = Vec::new();
+        for chain in [Chain::Input, Chain::Output] {
+            // ホワイトリストではルールに一致したパケットを許可するため、遮断に使用できない
+            let rules = firewall.chain_mut(chain);
+            if rules.policy() != Policy::Blacklist || rules.contains(&filter) {
+                continue;
+            }
+            rules.insert(Rule {
+                filter: filter.clone(),
+                priority: config.priority,
+                schedule: Some(Schedule { valid_until: Some(until), ..Default::default() }),
+            });
+            chains.push(chain);
+        }
+        if chains.is_empty() {
+            return None;
+        }
+        let ban = Ban { ip, detector, since: now, until, count, chains };
+        self.bans.insert(ip, ban.clone());
+        Some(ban)
+    }
+
+    fn unban(&mut self, firewall: &mut Firewall, ip: IpAddr, now: DateTime<Utc>) -> Option<Ban> {
+        let ban = self.bans.remove(&ip)?;
+        for &chain in &ban.chains {
+            firewall.chain_mut(chain).remove_rule(&Filter::IpAddress(ip));
+        }
+        self.history.insert(ip, (ban.count, now));
+        Some(ban)
+    }
+
+    // 期限を過ぎた遮断と、インポートなどでルールが削除された遮断を解除する
+    fn sweep(&mut self, firewall: &mut Firewall, config: &BanConfig, now: DateTime<Utc>) -> Vec<Ban> {
+        let ended = self
+            .bans
+            .values()
+            .filter(|ban| {
+                ban.until <= now
+                    || !ban.chains.iter().any(|&chain| firewall.chain(chain).contains(&Filter::IpAddress(ban.ip)))
+            })
+            .map(|ban| ban.ip)
+            .collect::<Vec<_>>();
+        let unbanned = ended.into_iter().filter_map(|ip| self.unban(firewall, ip, now)).collect();
+
+        let find_window = to_chrono(config.find_window);
+        self.strikes.retain(|_, strikes| strikes.back().is_some_and(|&strike| now - strike < find_window));
+        let max_duration = to_chrono(config.max_duration);
+        self.history.retain(|_, (_, ended)| now - *ended < max_duration);
+        unbanned
+    }
+}
+
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::max_value())
+}
+
+fn record(ban: &Ban) {
+    let message = format!(
+        "{} を {} まで遮断しました (検知: {}, {}回目)",
+        ban.ip,
+        ban.until.format("%Y-%m-%d %H:%M:%S UTC"),
+        ban.detector,
+        ban.count
+    );
+    warn!("{}", message);
+    BANS_TOTAL.with_label_values(&[&ban.detector.to_string()]).inc();
+    events::publish(PipelineEvent::Alert(Alert {
+        timestamp: ban.since,
+        kind: "ban".to_string(),
+        severity: "warning".to_string(),
+        message,
+        src_ip: Some(ban.ip),
+        dst_ip: None,
+    }));
+}
+
+fn is_ignored(config: &BanConfig, ip: IpAddr) -> bool {
+    config.ignore.iter().any(|network| network.contains(ip))
+}
+
+// 検知機能から攻撃元を通知する。遮断した場合は遮断の内容を返す
+pub fn report(ip: IpAddr, detector: Detector) -> Option<Ban> {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || is_ignored(&config, ip) {
+        return None;
+    }
+    let mut bans = BANS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut firewall = FIREWALL.write().unwrap_or_else(|e| e.into_inner());
+    let ban = bans.report(&mut firewall, &config, ip, detector, Utc::now())?;
+    drop(firewall);
+    BANS_ACTIVE.set(bans.bans.len() as i64);
+    record(&ban);
+    Some(ban)
+}
+
+// 管理APIから遮断する。期間を省略した場合は duration を使用する
+pub fn ban(ip: IpAddr, duration: Option<Duration>) -> Result<Ban, String> {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    let duration = duration.unwrap_or(config.duration);
+    if duration.is_zero() {
+        return Err("遮断する期間は0より大きい値を指定してください".to_string());
+    }
+    let mut bans = BANS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut firewall = FIREWALL.write().unwrap_or_else(|e| e.into_inner());
+    let now = Utc::now();
+    let count = match bans.unban(&mut firewall, ip, now) {
+        Some(previous) => previous.count,
+        None => bans.history.remove(&ip).map_or(0, |(count, _)| count),
+    } + 1;
+    let ban = bans
+        .ban(&mut firewall, &config, ip, Detector::Manual, now, duration, count)
+        .ok_or_else(|| format!("{} の遮断ルールを追加できるチェインがありません (既存のルールまたはホワイトリスト)", ip))?;
+    drop(firewall);
+    BANS_ACTIVE.set(bans.bans.len() as i64);
+    record(&ban);
+    Ok(ban)
+}
+
+pub fn unban(ip: IpAddr) -> Option<Ban> {
+    let mut bans = BANS.lock().unwrap_or_else(|e| e.into_inner());
+    let ban = bans.unban(&mut FIREWALL.write().unwrap_or_else(|e| e.into_inner()), ip, Utc::now())?;
+    BANS_ACTIVE.set(bans.bans.len() as i64);
+    info!("{} の遮断を解除しました", ip);
+    Some(ban)
+}
+
+// 期限の早い順に返す
+pub fn bans() -> Vec<Ban> {
+    let mut bans = BANS.lock().unwrap_or_else(|e| e.into_inner()).bans.values().cloned().collect::<Vec<_>>();
+    bans.sort_by_key(|ban| (ban.until, ban.ip));
+    bans
+}
+
+// 期限を過ぎた遮断を定期的に解除する
+pub async fn start_unban_scheduler(mut shutdown: broadcast::Receiver<()>) {
+    let period = CONFIG.read().unwrap_or_else(|e| e.into_inner()).interval;
+    let mut interval = tokio::time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+
+        let config = CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut bans = BANS.lock().unwrap_or_else(|e| e.into_inner());
+        let unbanned = bans.sweep(&mut FIREWALL.write().unwrap_or_else(|e| e.into_inner()), &config, Utc::now());
+        BANS_ACTIVE.set(bans.bans.len() as i64);
+        for ban in unbanned {
+            info!("{} の遮断の期限が終了しました (検知: {})", ban.ip, ban.detector);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::IpFirewall;
+
+    fn config() -> BanConfig {
+        BanConfig {
+            enabled: true,
+            max_retry: 3,
+            find_window: Duration::from_secs(60),
+            duration: Duration::from_secs(600),
+            max_duration: Duration::from_secs(1800),
+            ..Default::default()
+        }
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn bans_after_repeated_detections_and_extends_repeat_offenders() {
+        let config = config();
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut firewall = Firewall::new(IpFirewall::new(Policy::Blacklist), IpFirewall::new(Policy::Whitelist));
+        let mut table = BanTable::default();
+
+        // find_window より前の検知は数えない
+        assert!(table.report(&mut firewall, &config, ip, Detector::Scan, at(0)).is_none());
+        assert!(table.report(&mut firewall, &config, ip, Detector::Scan, at(100)).is_none());
+        assert!(table.report(&mut firewall, &config, ip, Detector::Scan, at(110)).is_none());
+        let ban = table.report(&mut firewall, &config, ip, Detector::Scan, at(120)).unwrap();
+        assert_eq!(ban.until, at(720));
+        // ホワイトリストのチェインには追加しない
+        assert_eq!(ban.chains, vec![Chain::Input]);
+        let rule = &firewall.chain(Chain::Input).rules()[0];
+        assert_eq!((rule.filter.clone(), rule.priority), (Filter::IpAddress(ip), config.priority));
+
+        // 期限を過ぎると解除し、再び遮断した場合は期間を倍にする
+        assert!(table.sweep(&mut firewall, &config, at(719)).is_empty());
+        assert_eq!(table.sweep(&mut firewall, &config, at(720)).len(), 1);
+        assert!(firewall.chain(Chain::Input).rules().is_empty());
+        for secs in [800, 801] {
+            table.report(&mut firewall, &config, ip, Detector::BruteForce, at(secs));
+        }
+        let ban = table.report(&mut firewall, &config, ip, Detector::BruteForce, at(802)).unwrap();
+        assert_eq!((ban.count, ban.until), (2, at(802 + 1200)));
+    }
+
+    #[test]
+    fn keeps_existing_rules_and_releases_removed_bans() {
+        let config = config();
+        let ip: IpAddr = "203.0.113.8".parse().unwrap();
+        let mut input = IpFirewall::new(Policy::Blacklist);
+        input.add_rule(Filter::IpAddress(ip), 10);
+        let mut firewall = Firewall::new(input, IpFirewall::new(Policy::Blacklist));
+        let mut table = BanTable::default();
+
+        let ban = table.ban(&mut firewall, &config, ip, Detector::Manual, at(0), config.duration, 1).unwrap();
+        assert_eq!(ban.chains, vec![Chain::Output]);
+        // 解除しても既存のルールは残す
+        table.unban(&mut firewall, ip, at(10));
+        assert_eq!(firewall.chain(Chain::Input).rules()[0].priority, 10);
+        assert!(firewall.chain(Chain::Output).rules().is_empty());
+
+        // インポートなどでルールが削除された遮断は解除する
+        table.ban(&mut firewall, &config, ip, Detector::Manual, at(20), config.duration, 2).unwrap();
+        firewall.chain_mut(Chain::Output).remove_rule(&Filter::IpAddress(ip));
+        assert_eq!(table.sweep(&mut firewall, &config, at(30)).len(), 1);
+        assert!(table.bans.is_empty());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::buffer_pool::FRAME_POOL;
+use crate::cli::BenchArgs;
+use crate::db_write::{flush_packet_buffer, rdb_tunnel_packet_write, FLUSH_INTERVAL};
+use crate::metrics;
+use crate::packet_header::{EthernetHeader, Ipv4Header, TcpHeader};
+use pnet::datalink::{self, Channel::Ethernet};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+// 生成したフレームの送信元・宛先 (RFC 5737の文書用アドレスのため、どのノードにも注入されない)
+const BENCH_SRC_IP: Ipv4Addr = Ipv4Addr::new(198, 51, 100, 1);
+const BENCH_DST_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+const BENCH_SRC_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0xbe, 0x01];
+const BENCH_DST_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0xbe, 0x02];
+const BENCH_DST_PORT: u16 = 5001;
+// 書き込みパイプラインに渡すキャプチャ元のインターフェース名
+const BENCH_INTERFACE: &str = "bench";
+// 送信するフレーム数を再計算する間隔
+const PACE_INTERVAL: Duration = Duration::from_millis(1);
+
+// イーサネット/IPv4/TCPのフレームを生成する。送信元ポート (動的ポートの範囲) とIPのidを変えて複数のフローに見せる
+pub struct FrameGenerator {
+    frame: Vec<u8>,
+    ip: Ipv4Header,
+    tcp: TcpHeader,
+    sequence: u32,
+}
+
+impl FrameGenerator {
+    // sizeはイーサネットヘッダを含むフレーム長 (54〜1514)
+    pub fn new(size: usize) -> Self {
+        let ethernet = EthernetHeader { dst_mac: BENCH_DST_MAC, src_mac: BENCH_SRC_MAC, ether_type: 0x0800 };
+        let mut ip = Ipv4Header::new(BENCH_SRC_IP, BENCH_DST_IP, 6, (size - EthernetHeader::LEN - Ipv4Header::LEN) as u16);
+        ip.dont_fragment = true;
+        let tcp = TcpHeader::new(0, BENCH_DST_PORT, TcpHeader::ACK | TcpHeader::PSH);
+
+        let mut frame = Vec::with_capacity(size);
+        frame.extend_from_slice(&ethernet.to_bytes());
+        frame.resize(size, 0);
+        Self { frame, ip, tcp, sequence: 0 }
+    }
+
+    pub fn next_frame(&mut self) -> &[u8] {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        self.ip.identification = sequence as u16;
+        self.tcp.src_port = 49152 + (sequence % 16384) as u16;
+        self.tcp.sequence = sequence;
+
+        let tcp_start = EthernetHeader::LEN + Ipv4Header::LEN;
+        self.frame[EthernetHeader::LEN..tcp_start].copy_from_slice(&self.ip.to_bytes());
+        self.frame[tcp_start..tcp_start + self.tcp.header_len()].copy_from_slice(&self.tcp.to_bytes());
+        &self.frame
+    }
+}
+
+// 目標のパケットレートに合わせて、経過時間までに送信すべきフレーム数を返す
+struct Pacer {
+    pps: u64,
+    duration: Duration,
+    started: Instant,
+    generated: u64,
+    ticker: tokio::time::Interval,
+}
+
+impl Pacer {
+    fn new(pps: u64, duration: Duration) -> Self {
+        let mut ticker = interval(PACE_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        Self { pps, duration, started: Instant::now(), generated: 0, ticker }
+    }
+
+    // 計測時間が経過した場合はNone
+    async fn next_batch(&mut self) -> Option<u64> {
+        self.ticker.tick().await;
+        let elapsed = self.started.elapsed();
+        if elapsed >= self.duration {
+            return None;
+        }
+        let due = (self.pps as f64 * elapsed.as_secs_f64()) as u64;
+        let batch = due.saturating_sub(self.generated);
+        self.generated += batch;
+        Some(batch)
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+// 書き込み1回あたりの所要時間
+#[derive(Default)]
+struct FlushStats {
+    latencies: Vec<Duration>,
+    failed: u64,
+}
+
+impl FlushStats {
+    async fn flush(&mut self) {
+        let start = Instant::now();
+        match flush_packet_buffer().await {
+            Ok(0) => {}
+            Ok(_) => self.latencies.push(start.elapsed()),
+            Err(e) => {
+                self.failed += 1;
+                warn!("パケットバッファのフラッシュに失敗しました: {}", e);
+            }
+        }
+    }
+
+    fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        let rank = ((percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.clamp(1, sorted.len()) - 1])
+    }
+}
+
+// ライターと同じ間隔でバッファを送信し、停止の通知を受けたら残りを送信して終了する
+async fn flush_until(mut stop: oneshot::Receiver<()>) -> FlushStats {
+    let mut stats = FlushStats::default();
+    let mut ticker = interval(FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => stats.flush().await,
+            _ = &mut stop => {
+                stats.flush().await;
+                return stats;
+            }
+        }
+    }
+}
+
+fn format_latency(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{:.2}ms", latency.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+fn rate(count: u64, elapsed: Duration) -> f64 {
+    count as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+// 合成したフレームを書き込みパイプライン (ファイアウォール -> バッファ -> トランスポート) に流し、
+// 達成したスループット・書き込みの遅延・破棄数を表示する
+pub async fn run(args: BenchArgs) -> Result<(), String> {
+    info!("書き込みパイプラインの負荷試験を開始します: {}pps, {}バイト, {:?}", args.pps, args.size, args.duration);
+    let written_before = metrics::PACKETS_WRITTEN.get();
+    let firewall_drops_before = metrics::FIREWALL_DROPS.get();
+    let transport_drops_before = metrics::PACKETS_DROPPED.with_label_values(&["db_error"]).get();
+
+    let (stop, stopped) = oneshot::channel();
+    let writer = tokio::spawn(flush_until(stopped));
+
+    let mut frames = FrameGenerator::new(args.size as usize);
+    let mut pacer = Pacer::new(args.pps, args.duration);
+    let mut generated = 0u64;
+    while let Some(batch) = pacer.next_batch().await {
+        for _ in 0..batch {
+            rdb_tunnel_packet_write(FRAME_POOL.copy_from_slice(frames.next_frame()), BENCH_INTERFACE)
+                .await
+                .map_err(|e| e.to_string())?;
+            generated += 1;
+        }
+    }
+    let generate_elapsed = pacer.elapsed();
+
+    let _ = stop.send(());
+    let flush_stats = writer.await.map_err(|e| e.to_string())?;
+    let total_elapsed = pacer.elapsed();
+
+    let written = metrics::PACKETS_WRITTEN.get() - written_before;
+    let firewall_drops = metrics::FIREWALL_DROPS.get() - firewall_drops_before;
+    let transport_drops = metrics::PACKETS_DROPPED.with_label_values(&["db_error"]).get() - transport_drops_before;
+
+    println!("生成:       {} パケット ({:.0} pps, 目標 {} pps)", generated, rate(generated, generate_elapsed), args.pps);
+    println!(
+        "書き込み:   {} パケット ({:.0} pps, {:.1} Mbps)",
+        written,
+        rate(written, total_elapsed),
+        rate(written * args.size as u64 * 8, total_elapsed) / 1_000_000.0
+    );
+    println!(
+        "書き込み遅延: p50 {} / p95 {} / p99 {} / max {} ({} バッチ)",
+        format_latency(flush_stats.percentile(50.0)),
+        format_latency(flush_stats.percentile(95.0)),
+        format_latency(flush_stats.percentile(99.0)),
+        format_latency(flush_stats.percentile(100.0)),
+        flush_stats.latencies.len()
+    );
+    println!(
+        "破棄:       ファイアウォール {} / 書き込み失敗 {} ({} バッチ)",
+        firewall_drops, transport_drops, flush_stats.failed
+    );
+    Ok(())
+}
+
+// 合成したフレームを指定したインターフェース (実行中のトンネルのtap0など) に注入し、達成したスループットを表示する
+pub async fn inject(args: BenchArgs, interface_name: &str) -> Result<(), String> {
+    let interface = datalink::interfaces()
+        .into_iter()
+        .find(|interface| interface.name == interface_name)
+        .ok_or_else(|| format!("インターフェースが見つかりません: {}", interface_name))?;
+    let mut tx = match datalink::channel(&interface, Default::default()) {
+        Ok(Ethernet(tx, _)) => tx,
+        Ok(_) => return Err("未対応のチャネルタイプです".to_string()),
+        Err(e) => return Err(format!("{} を開けません: {}", interface_name, e)),
+    };
+
+    let mut frames = FrameGenerator::new(args.size as usize);
+    let mut pacer = Pacer::new(args.pps, args.duration);
+    let (mut sent, mut failed) = (0u64, 0u64);
+    while let Some(batch) = pacer.next_batch().await {
+        for _ in 0..batch {
+            match tx.send_to(frames.next_frame(), None) {
+                Some(Ok(_)) => sent += 1,
+                _ => failed += 1,
+            }
+        }
+    }
+    let elapsed = pacer.elapsed();
+
+    println!("注入:       {} パケット ({:.0} pps, 目標 {} pps)", sent, rate(sent, elapsed), args.pps);
+    println!("注入失敗:   {} パケット", failed);
+    println!("書き込みの遅延と破棄数は、注入先で実行中のトンネルの /metrics で確認してください");
+    Ok(())
+}
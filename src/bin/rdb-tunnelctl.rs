@@ -0,0 +1,89 @@
+// rdb-tunnelの制御ソケットに接続してコマンドを実行する
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Debug, Parser)]
+#[command(name = "rdb-tunnelctl", version, about = "rdb-tunnelの制御ソケットに接続して状態を確認する")]
+struct Cli {
+    /// 制御ソケットのパス ([control] socket)
+    #[arg(long, env = "RDB_TUNNEL_CONTROL_SOCKET", default_value = "/run/rdb-tunnel/control.sock")]
+    socket: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// タスクとヘルスチェックの状態
+    Status,
+    /// ファイアウォールルール一覧
+    Rules {
+        /// チェイン (input|output、既定はoutput)
+        #[arg(value_parser = ["input", "output"])]
+        chain: Option<String>,
+    },
+    /// 直近に通信したピア一覧
+    Peers {
+        /// 集計期間 (秒)
+        window_secs: Option<u64>,
+    },
+    /// 起動時からのピアごとの送受信量
+    PeerTraffic,
+    /// 統計情報
+    Stats,
+}
+
+impl Command {
+    fn to_line(&self) -> String {
+        match self {
+            Command::Status => "status".to_string(),
+            Command::Rules { chain: Some(chain) } => format!("rules {}", chain),
+            Command::Rules { chain: None } => "rules".to_string(),
+            Command::Peers { window_secs: Some(secs) } => format!("peers {}", secs),
+            Command::Peers { window_secs: None } => "peers".to_string(),
+            Command::PeerTraffic => "peer-traffic".to_string(),
+            Command::Stats => "stats".to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn request(socket: &PathBuf, line: &str) -> std::io::Result<serde_json::Value> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{}\n", line).as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    serde_json::from_str(&response).map_err(std::io::Error::other)
+}
+
+// 制御ソケットはUnixドメインソケットのため、他のOSでは管理API (HTTP) を使用する
+#[cfg(not(unix))]
+fn request(_socket: &PathBuf, _line: &str) -> std::io::Result<serde_json::Value> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "このOSでは制御ソケットを使用できません"))
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let reply = match request(&cli.socket, &cli.command.to_line()) {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("制御ソケットに接続できません ({}): {}", cli.socket.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if reply["ok"].as_bool() == Some(true) {
+        println!("{}", serde_json::to_string_pretty(&reply["data"]).unwrap_or_default());
+        ExitCode::SUCCESS
+    } else {
+        eprintln!("エラー: {}", reply["error"].as_str().unwrap_or("unknown error"));
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,50 @@
+use crate::config::BridgeConfig;
+use crate::error::InitProcessError;
+use crate::virtual_interface::{create_bridge, delete_link, set_bridge_port};
+use tracing::{info, warn};
+
+// 仮想NIC (TAP) と物理NICをLinuxブリッジで接続し、物理NICのL2セグメントをトンネルで延長する (brctlの手動設定が不要になる)
+
+// 作成・接続したブリッジ。終了時に remove で元に戻す
+pub struct Bridge {
+    name: String,
+    // 作成した場合のみ削除する
+    created: bool,
+    // ブリッジに接続したインターフェース
+    ports: Vec<String>,
+}
+
+// ブリッジを作成し、仮想NICと物理NICを接続する。途中で失敗した場合は元に戻す
+pub async fn install(config: &BridgeConfig, tap_name: &str) -> Result<Bridge, InitProcessError> {
+    let mut bridge = Bridge {
+        name: config.name.clone(),
+        created: create_bridge(&config.name).await?,
+        ports: Vec::new(),
+    };
+    for port in [tap_name, config.interface.as_str()] {
+        if let Err(e) = set_bridge_port(port, Some(&config.name)).await {
+            bridge.remove().await;
+            return Err(e);
+        }
+        bridge.ports.push(port.to_string());
+    }
+    info!("ブリッジ {} に {} を接続しました", bridge.name, bridge.ports.join(", "));
+    Ok(bridge)
+}
+
+impl Bridge {
+    // 接続したインターフェースをブリッジから外し、作成したブリッジを削除する
+    pub async fn remove(self) {
+        for port in &self.ports {
+            if let Err(e) = set_bridge_port(port, None).await {
+                warn!("{}", e);
+            }
+        }
+        if self.created {
+            match delete_link(&self.name).await {
+                Ok(()) => info!("ブリッジ {} を削除しました", self.name),
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use bytes::{Bytes, BytesMut};
+use std::sync::Mutex;
+
+// キャプチャしたフレームを格納するバッファの容量 (ジャンボフレームを除くイーサネットフレームの最大長を含む)
+pub const FRAME_BUFFER_SIZE: usize = 2048;
+// プールに保持するバッファ数の上限。書き込みの間隔 (100ms) に高負荷時に届くフレーム数を目安とする
+const MAX_POOLED_FRAMES: usize = 8192;
+
+// キャプチャから書き込みまでフレームのバッファを使い回し、定常状態ではパケットごとの確保を行わない
+pub static FRAME_POOL: BufferPool = BufferPool::new(FRAME_BUFFER_SIZE, MAX_POOLED_FRAMES);
+
+// 固定長のBytesMutのプール
+pub struct BufferPool {
+    buffers: Mutex<Vec<BytesMut>>,
+    buffer_size: usize,
+    max_pooled: usize,
+}
+
+impl BufferPool {
+    pub const fn new(buffer_size: usize, max_pooled: usize) -> Self {
+        Self { buffers: Mutex::new(Vec::new()), buffer_size, max_pooled }
+    }
+
+    // 空のバッファを取り出す。プールが空の場合は新たに確保する
+    pub fn get(&self) -> BytesMut {
+        self.buffers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(self.buffer_size))
+    }
+
+    // dataをコピーしたバッファを返す
+    pub fn copy_from_slice(&self, data: &[u8]) -> Bytes {
+        let mut buffer = self.get();
+        buffer.extend_from_slice(data);
+        buffer.freeze()
+    }
+
+    // 使い終わったバッファを返却する。容量が足りないもの・上限を超えた分は破棄する
+    pub fn put(&self, mut buffer: BytesMut) {
+        buffer.clear();
+        if buffer.capacity() < self.buffer_size {
+            return;
+        }
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.max_pooled {
+            buffers.push(buffer);
+        }
+    }
+
+    // copy_from_sliceで取り出したバッファを返却する。他から参照されている場合は返却しない
+    pub fn recycle(&self, bytes: Bytes) {
+        if let Ok(buffer) = bytes.try_into_mut() {
+            self.put(buffer);
+        }
+    }
+
+    // プールに保持しているバッファ数
+    pub fn pooled(&self) -> usize {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_recycled_buffer() {
+        let pool = BufferPool::new(FRAME_BUFFER_SIZE, 1);
+        let frame = pool.copy_from_slice(&[1; 100]);
+        let address = frame.as_ptr();
+        // 解析結果のスライスが残っている間は返却しない
+        let data = frame.slice(14..);
+        pool.recycle(frame.clone());
+        assert_eq!(pool.pooled(), 0);
+
+        drop(data);
+        pool.recycle(frame);
+        assert_eq!(pool.pooled(), 1);
+        let frame = pool.copy_from_slice(&[2; 60]);
+        assert_eq!(frame.as_ptr(), address);
+        assert_eq!(&frame[..], &[2; 60]);
+        assert_eq!(pool.pooled(), 0);
+    }
+
+    #[test]
+    fn drops_buffers_over_limit() {
+        let pool = BufferPool::new(FRAME_BUFFER_SIZE, 1);
+        pool.put(BytesMut::with_capacity(FRAME_BUFFER_SIZE));
+        pool.put(BytesMut::with_capacity(FRAME_BUFFER_SIZE));
+        pool.put(BytesMut::with_capacity(64));
+        assert_eq!(pool.pooled(), 1);
+    }
+}
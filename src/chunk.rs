@@ -0,0 +1,172 @@
+use crate::db_read::PacketInfo;
+use crate::metrics;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+// 1行に保存するraw_packetとdataの上限 (バイト)。これを超えるフレーム (GSOで結合されたフレームなど) は
+// 複数の行に分割して保存し、受信側で組み立て直す
+pub const MAX_ROW_SIZE: usize = 1500;
+// 組み立て後のフレームの上限 (IPパケットの最大長 + Ethernetヘッダー)
+pub const MAX_FRAME_SIZE: usize = 65535 + 14;
+// 1つのフレームを分割する行数の上限
+pub const MAX_CHUNKS: usize = MAX_FRAME_SIZE.div_ceil(MAX_ROW_SIZE);
+
+// 全ての断片が揃うまで待つ時間
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+// 組み立て中のフレーム数の上限 (これを超えた新しいフレームの断片は破棄する)
+const MAX_PENDING_FRAMES: usize = 256;
+
+// 分割して保存したフレームの断片の情報
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    // 同じフレームの断片に共通の識別子
+    pub id: i64,
+    pub index: i16,
+    pub count: i16,
+}
+
+impl Chunk {
+    // chunk_id, chunk_index, chunk_count 列の値から作成する (分割していない行はNone)
+    pub fn from_columns(id: Option<i64>, index: Option<i16>, count: Option<i16>) -> Option<Self> {
+        Some(Self { id: id?, index: index?, count: count? })
+    }
+
+    pub fn new_id() -> i64 {
+        rand::random()
+    }
+}
+
+// lenバイトをcount個に分割した場合の、index番目の範囲
+pub fn piece_range(len: usize, count: usize, index: usize) -> std::ops::Range<usize> {
+    let size = len.div_ceil(count.max(1));
+    (index * size).min(len)..((index + 1) * size).min(len)
+}
+
+// 組み立て中のフレーム
+struct PendingFrame {
+    first_seen: Instant,
+    pieces: Vec<Option<PacketInfo>>,
+    received: usize,
+}
+
+// 受信した断片を元のフレームに組み立てる
+#[derive(Default)]
+pub struct Reassembler {
+    pending: HashMap<(IpAddr, i64), PendingFrame>,
+}
+
+impl Reassembler {
+    // 断片を受け取り、フレームが揃った場合は組み立てたパケットを返す。分割されていないパケットはそのまま返す
+    pub fn push(&mut self, packet: PacketInfo, now: Instant) -> Option<PacketInfo> {
+        let Some(chunk) = packet.chunk else {
+            return Some(packet);
+        };
+        self.expire(now);
+
+        let (index, count) = (chunk.index as usize, chunk.count as usize);
+        if count == 0 || count > MAX_CHUNKS || index >= count {
+            debug!("不正な断片のため破棄: {} -> {} ({}/{})", packet.src_ip, packet.dst_ip, chunk.index, chunk.count);
+            metrics::PACKETS_DROPPED.with_label_values(&["incomplete"]).inc();
+            return None;
+        }
+        let key = (packet.src_ip, chunk.id);
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING_FRAMES {
+            metrics::PACKETS_DROPPED.with_label_values(&["incomplete"]).inc();
+            return None;
+        }
+        let frame = self.pending.entry(key).or_insert_with(|| PendingFrame {
+            first_seen: now,
+            pieces: vec![None; count],
+            received: 0,
+        });
+        match frame.pieces.get_mut(index) {
+            Some(piece @ None) => {
+                *piece = Some(packet);
+                frame.received += 1;
+            }
+            // 遡って再取得した断片や、分割数の異なる断片は無視する
+            _ => return None,
+        }
+        if frame.received < frame.pieces.len() {
+            return None;
+        }
+
+        let frame = self.pending.remove(&key)?;
+        let mut pieces = frame.pieces.into_iter().flatten();
+        let mut packet = pieces.next()?;
+        for piece in pieces {
+            packet.raw_packet.extend_from_slice(&piece.raw_packet);
+            packet.data.extend_from_slice(&piece.data);
+        }
+        packet.chunk = None;
+        Some(packet)
+    }
+
+    // 期限までに揃わなかったフレームを破棄する
+    fn expire(&mut self, now: Instant) {
+        self.pending.retain(|(src_ip, id), frame| {
+            let alive = now.saturating_duration_since(frame.first_seen) < REASSEMBLY_TIMEOUT;
+            if !alive {
+                debug!("断片が揃わなかったため破棄: {} (id {}, {}/{})", src_ip, id, frame.received, frame.pieces.len());
+                metrics::PACKETS_DROPPED.with_label_values(&["incomplete"]).inc();
+            }
+            alive
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::types::MacAddr;
+    use std::net::Ipv4Addr;
+
+    fn piece(frame: &[u8], index: usize, count: usize) -> PacketInfo {
+        let range = piece_range(frame.len(), count, index);
+        PacketInfo {
+            src_mac: MacAddr([0; 6]),
+            dst_mac: MacAddr([0; 6]),
+            ether_type: 0x0800,
+            src_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            dst_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            src_port: None,
+            dst_port: None,
+            ip_protocol: 17,
+            timestamp: chrono::Utc::now(),
+            data: Vec::new(),
+            raw_packet: frame[range].to_vec(),
+            seq: None,
+            codec: 0,
+            chunk: Some(Chunk { id: 7, index: index as i16, count: count as i16 }),
+        }
+    }
+
+    #[test]
+    fn reassembles_pieces_in_any_order() {
+        let frame = (0..4000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let now = Instant::now();
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(piece(&frame, 2, 3), now).is_none());
+        assert!(reassembler.push(piece(&frame, 0, 3), now).is_none());
+        // 同じ断片を再取得しても組み立てには影響しない
+        assert!(reassembler.push(piece(&frame, 0, 3), now).is_none());
+        let packet = reassembler.push(piece(&frame, 1, 3), now).unwrap();
+        assert_eq!(packet.raw_packet, frame);
+        assert_eq!(packet.chunk, None);
+        assert!(reassembler.pending.is_empty());
+    }
+
+    #[test]
+    fn discards_incomplete_frame_after_timeout() {
+        let frame = vec![1; 3000];
+        let now = Instant::now();
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(piece(&frame, 0, 2), now).is_none());
+        // 期限を過ぎてから残りの断片が届いても、新しいフレームとして待つ
+        assert!(reassembler.push(piece(&frame, 1, 2), now + REASSEMBLY_TIMEOUT).is_none());
+        assert_eq!(reassembler.pending.len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::probe::MAX_PING_COUNT;
+use crate::rules::RuleFormat;
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use ipnetwork::IpNetwork;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(name = "rdb-tunnel", version, about = "データベースを経由してパケットを転送するトンネル")]
+pub struct Cli {
+    // サブコマンドを指定しない場合はトンネルを起動する
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub device: DeviceArgs,
+}
+
+// 設定ファイルの [device] を上書きする
+#[derive(Debug, Args)]
+pub struct DeviceArgs {
+    /// キャプチャするインターフェース名
+    #[arg(long = "device")]
+    pub name: Option<String>,
+
+    /// このサブネットのアドレスを持つインターフェースを使用する
+    #[arg(long = "device-subnet")]
+    pub subnet: Option<IpNetwork>,
+
+    /// MACアドレスで選択する (末尾の * で前方一致)
+    #[arg(long = "device-mac")]
+    pub mac: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 実行中のrdb-tunnelの統計情報をリアルタイムに表示する
+    Top(TopArgs),
+    /// 指定時刻より古いパケットをデータベースから削除する
+    Prune(PruneArgs),
+    /// 合成したパケットを書き込みパイプラインに流し、スループットと書き込みの遅延を計測する
+    Bench(BenchArgs),
+    /// 実行中のrdb-tunnelから対向ノードへデータベースの経路でプローブを送信し、往復時間と損失を計測する
+    Ping(PingArgs),
+    /// 保存済みの小さいパケットからzstdの圧縮辞書を学習する ([writer] compression_dictionary)
+    TrainDictionary(TrainDictionaryArgs),
+    /// 実行中のrdb-tunnelのファイアウォールルールをJSON/YAMLでエクスポート・インポートする
+    Rules(RulesArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct TopArgs {
+    /// 管理APIのURL
+    #[arg(long, default_value = "http://127.0.0.1:9898")]
+    pub url: String,
+
+    /// 管理APIのトークン ([http] api_token)
+    #[arg(long, env = "RDB_TUNNEL_API_TOKEN")]
+    pub token: Option<String>,
+
+    /// 更新間隔 (秒)
+    #[arg(long, default_value_t = 1)]
+    pub interval: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct PruneArgs {
+    /// 削除する境界 (RFC 3339の時刻、または "24h" のように現在からの期間)
+    #[arg(long, value_parser = parse_before)]
+    pub before: DateTime<Utc>,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// 1秒あたりに生成するパケット数
+    #[arg(long, default_value_t = 10000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub pps: u64,
+
+    /// フレームのサイズ (イーサネットヘッダを含むバイト数)
+    #[arg(long, default_value_t = 512, value_parser = clap::value_parser!(u16).range(54..=1514))]
+    pub size: u16,
+
+    /// 計測する時間 ("30s" などの期間)
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub duration: Duration,
+
+    /// 書き込みパイプラインの代わりに、指定したインターフェース (実行中のトンネルのtap0など) に注入する
+    #[arg(long, value_name = "INTERFACE")]
+    pub inject: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct PingArgs {
+    /// 対向ノードのアドレス (対向ノードのポーリング対象のIPv4アドレス)
+    pub peer: IpAddr,
+
+    /// 送信するプローブ数
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=MAX_PING_COUNT as i64))]
+    pub count: u32,
+
+    /// プローブの送信間隔
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+
+    /// 応答を待つ時間
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
+
+    /// 管理APIのURL
+    #[arg(long, default_value = "http://127.0.0.1:9898")]
+    pub url: String,
+
+    /// 管理APIのトークン ([http] api_token)
+    #[arg(long, env = "RDB_TUNNEL_API_TOKEN")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct TrainDictionaryArgs {
+    /// 辞書の出力先
+    #[arg(long)]
+    pub output: PathBuf,
+
+    /// 学習に使用するパケット数 (新しい順)
+    #[arg(long, default_value_t = 10000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: u32,
+
+    /// 学習に使用するフレームの最大長 ([writer] dictionary_max_size)
+    #[arg(long, default_value_t = 256)]
+    pub max_packet_size: usize,
+
+    /// 辞書の最大サイズ (バイト)
+    #[arg(long, default_value_t = 16384)]
+    pub dictionary_size: usize,
+}
+
+fn parse_before(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let age = humantime::parse_duration(value)
+        .map_err(|e| format!("時刻または期間として解釈できません: {}", e))?;
+    let age = chrono::Duration::from_std(age).map_err(|e| e.to_string())?;
+    Ok(Utc::now() - age)
+}
+
+#[derive(Debug, Args)]
+pub struct RulesArgs {
+    #[command(subcommand)]
+    pub command: RulesCommand,
+
+    /// 管理APIのURL
+    #[arg(long, global = true, default_value = "http://127.0.0.1:9898")]
+    pub url: String,
+
+    /// 管理APIのトークン ([http] api_token)
+    #[arg(long, global = true, env = "RDB_TUNNEL_API_TOKEN")]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RulesCommand {
+    /// 全てのチェインのルールと方針を出力する
+    Export(RulesExportArgs),
+    /// ファイルのルールセットを検証し、全てのチェインをまとめて置き換える
+    Import(RulesImportArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RulesExportArgs {
+    /// 出力先 (省略した場合は標準出力)
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// 形式 (省略した場合は出力先の拡張子から判定し、それ以外はjson)
+    #[arg(long, value_enum)]
+    pub format: Option<RuleFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct RulesImportArgs {
+    /// ルールセットのファイル
+    pub file: PathBuf,
+
+    /// 形式 (省略した場合は拡張子から判定し、.yaml/.yml以外はjson)
+    #[arg(long, value_enum)]
+    pub format: Option<RuleFormat>,
+}
@@ -0,0 +1,298 @@
+use crate::chunk;
+use crate::config::WriterConfig;
+use crate::db_read::PacketInfo;
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+use tokio_postgres::types::{IsNull, ToSql, Type};
+use tracing::info;
+
+// 展開後のサイズの上限 (不正なデータで大きなバッファを確保しないようにする)
+pub const MAX_DECOMPRESSED_SIZE: usize = chunk::MAX_FRAME_SIZE;
+
+// 書き込み時の圧縮の設定
+static SETTINGS: RwLock<Settings> =
+    RwLock::new(Settings { codec: Codec::None, level: 0, min_size: 0, dictionary_max_size: 0 });
+// 小さいパケットの圧縮に使用するzstdの辞書 (書き込み・展開の両方で使用する)
+static DICTIONARY: RwLock<Option<Arc<Dictionary>>> = RwLock::new(None);
+
+thread_local! {
+    // zstdの圧縮コンテキストは確保が重いため、スレッドごとに使い回す (圧縮レベルや辞書が変わった場合は作り直す)
+    static ZSTD_COMPRESSOR: RefCell<Option<(i32, zstd::bulk::Compressor<'static>)>> = const { RefCell::new(None) };
+    static ZSTD_DECOMPRESSOR: RefCell<Option<zstd::bulk::Decompressor<'static>>> = const { RefCell::new(None) };
+    static ZSTD_DICT_COMPRESSOR: RefCell<Option<(i32, u32, zstd::bulk::Compressor<'static>)>> =
+        const { RefCell::new(None) };
+    static ZSTD_DICT_DECOMPRESSOR: RefCell<Option<(u32, zstd::bulk::Decompressor<'static>)>> =
+        const { RefCell::new(None) };
+}
+
+// raw_packetとdataの圧縮方式。codec列には番号を保存する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    None,
+    Zstd,
+    Lz4,
+    // 辞書を使用したzstd ([writer] compression_dictionary を設定した場合に小さいパケットに使用する)
+    #[serde(skip)]
+    ZstdDictionary,
+}
+
+impl Codec {
+    pub fn id(self) -> i16 {
+        match self {
+            Codec::None => 0,
+            Codec::Zstd => 1,
+            Codec::Lz4 => 2,
+            Codec::ZstdDictionary => 3,
+        }
+    }
+
+    pub fn from_id(id: i16) -> Option<Self> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Zstd),
+            2 => Some(Codec::Lz4),
+            3 => Some(Codec::ZstdDictionary),
+            _ => None,
+        }
+    }
+}
+
+impl ToSql for Codec {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.id().to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <i16 as ToSql>::accepts(ty)
+    }
+
+    fn to_sql_checked(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.id().to_sql_checked(ty, out)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum CompressionError {
+    #[error("未対応の圧縮方式です: {0}")]
+    UnknownCodec(i16),
+
+    #[error("zstdの処理に失敗しました: {0}")]
+    Zstd(#[from] std::io::Error),
+
+    #[error("LZ4の展開に失敗しました: {0}")]
+    Lz4(#[from] lz4_flex::block::DecompressError),
+
+    #[error("展開後のサイズが上限 ({MAX_DECOMPRESSED_SIZE} bytes) を超えています")]
+    TooLarge,
+
+    #[error("辞書が読み込まれていません")]
+    NoDictionary,
+
+    #[error("辞書が一致しません (パケット: {frame:?}, 読み込み済み: {loaded})")]
+    DictionaryMismatch { frame: Option<u32>, loaded: u32 },
+
+    #[error("辞書ファイル {0} を使用できません: {1}")]
+    InvalidDictionary(String, String),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    codec: Codec,
+    level: i32,
+    min_size: usize,
+    dictionary_max_size: usize,
+}
+
+#[derive(Debug)]
+struct Dictionary {
+    id: u32,
+    content: Vec<u8>,
+}
+
+// 書き込み時の圧縮の設定と辞書を反映する
+pub fn configure(config: &WriterConfig) -> Result<(), CompressionError> {
+    let dictionary = config.compression_dictionary.as_deref().map(load_dictionary).transpose()?;
+    if let (Some(dictionary), Some(path)) = (&dictionary, &config.compression_dictionary) {
+        info!(
+            "圧縮辞書を読み込みました: {} (ID {}, {} bytes)。{} bytes以下のパケットに使用します",
+            path.display(),
+            dictionary.id,
+            dictionary.content.len(),
+            config.dictionary_max_size
+        );
+    }
+    *DICTIONARY.write().unwrap_or_else(|e| e.into_inner()) = dictionary.map(Arc::new);
+    *SETTINGS.write().unwrap_or_else(|e| e.into_inner()) = Settings {
+        codec: config.compression,
+        level: config.compression_level,
+        min_size: config.compression_min_size,
+        dictionary_max_size: config.dictionary_max_size,
+    };
+    Ok(())
+}
+
+fn load_dictionary(path: &Path) -> Result<Dictionary, CompressionError> {
+    let invalid = |reason: String| CompressionError::InvalidDictionary(path.display().to_string(), reason);
+    let content = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+    // 辞書IDは展開時に同じ辞書かどうかを確認するために使用する
+    let id = zstd::zstd_safe::get_dict_id_from_dict(&content)
+        .ok_or_else(|| invalid("zstdの辞書ではありません".to_string()))?;
+    Ok(Dictionary { id: id.get(), content })
+}
+
+fn dictionary() -> Result<Arc<Dictionary>, CompressionError> {
+    DICTIONARY.read().unwrap_or_else(|e| e.into_inner()).clone().ok_or(CompressionError::NoDictionary)
+}
+
+// 書き込むパケットの圧縮方式。圧縮しない場合はNone
+pub fn codec_for(len: usize) -> Option<(Codec, i32)> {
+    let settings = *SETTINGS.read().unwrap_or_else(|e| e.into_inner());
+    if len < settings.min_size {
+        return None;
+    }
+    if len <= settings.dictionary_max_size && DICTIONARY.read().unwrap_or_else(|e| e.into_inner()).is_some() {
+        return Some((Codec::ZstdDictionary, settings.level));
+    }
+    (settings.codec != Codec::None).then_some((settings.codec, settings.level))
+}
+
+pub fn compress(codec: Codec, level: i32, input: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    match codec {
+        Codec::None => Ok(input.to_vec()),
+        Codec::Zstd => ZSTD_COMPRESSOR.with_borrow_mut(|compressor| {
+            let compressor = match compressor {
+                Some((current, compressor)) if *current == level => compressor,
+                _ => &mut compressor.insert((level, zstd::bulk::Compressor::new(level)?)).1,
+            };
+            Ok(compressor.compress(input)?)
+        }),
+        Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(input)),
+        Codec::ZstdDictionary => {
+            let dictionary = dictionary()?;
+            ZSTD_DICT_COMPRESSOR.with_borrow_mut(|compressor| {
+                let compressor = match compressor {
+                    Some((current, id, compressor)) if *current == level && *id == dictionary.id => compressor,
+                    _ => {
+                        let created = zstd::bulk::Compressor::with_dictionary(level, &dictionary.content)?;
+                        &mut compressor.insert((level, dictionary.id, created)).2
+                    }
+                };
+                Ok(compressor.compress(input)?)
+            })
+        }
+    }
+}
+
+pub fn decompress(codec: Codec, input: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    match codec {
+        Codec::None => Ok(input.to_vec()),
+        Codec::Zstd => ZSTD_DECOMPRESSOR.with_borrow_mut(|decompressor| {
+            let decompressor = match decompressor {
+                Some(decompressor) => decompressor,
+                None => decompressor.insert(zstd::bulk::Decompressor::new()?),
+            };
+            Ok(decompressor.decompress(input, MAX_DECOMPRESSED_SIZE)?)
+        }),
+        Codec::Lz4 => {
+            // 先頭4バイトが展開後のサイズ (リトルエンディアン)
+            let size = input.get(..4).map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]));
+            if size.is_some_and(|size| size as usize > MAX_DECOMPRESSED_SIZE) {
+                return Err(CompressionError::TooLarge);
+            }
+            Ok(lz4_flex::decompress_size_prepended(input)?)
+        }
+        Codec::ZstdDictionary => {
+            let dictionary = dictionary()?;
+            let frame = zstd::zstd_safe::get_dict_id_from_frame(input).map(|id| id.get());
+            if frame != Some(dictionary.id) {
+                return Err(CompressionError::DictionaryMismatch { frame, loaded: dictionary.id });
+            }
+            ZSTD_DICT_DECOMPRESSOR.with_borrow_mut(|decompressor| {
+                let decompressor = match decompressor {
+                    Some((id, decompressor)) if *id == dictionary.id => decompressor,
+                    _ => {
+                        let created = zstd::bulk::Decompressor::with_dictionary(&dictionary.content)?;
+                        &mut decompressor.insert((dictionary.id, created)).1
+                    }
+                };
+                Ok(decompressor.decompress(input, MAX_DECOMPRESSED_SIZE)?)
+            })
+        }
+    }
+}
+
+// 小さいパケットの標本からzstdの辞書を学習する
+pub fn train_dictionary(samples: &[Vec<u8>], max_size: usize) -> Result<Vec<u8>, CompressionError> {
+    Ok(zstd::dict::from_samples(samples, max_size)?)
+}
+
+// 圧縮して保存されたパケットを展開する
+pub fn decompress_packet(packet: &mut PacketInfo) -> Result<(), CompressionError> {
+    let codec = Codec::from_id(packet.codec).ok_or(CompressionError::UnknownCodec(packet.codec))?;
+    if codec != Codec::None {
+        packet.raw_packet = decompress(codec, &packet.raw_packet)?;
+        packet.data = decompress(codec, &packet.data)?;
+        packet.codec = Codec::None.id();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_each_codec() {
+        let input = b"GET / HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n".repeat(8);
+        for codec in [Codec::None, Codec::Zstd, Codec::Lz4] {
+            let compressed = compress(codec, 3, &input).unwrap();
+            if codec != Codec::None {
+                assert!(compressed.len() < input.len() / 2);
+            }
+            assert_eq!(decompress(codec, &compressed).unwrap(), input);
+            assert_eq!(Codec::from_id(codec.id()), Some(codec));
+        }
+        assert!(decompress(Codec::Lz4, &[0xff, 0xff, 0xff, 0xff, 0]).is_err());
+        assert!(decompress(Codec::Zstd, b"not zstd").is_err());
+    }
+
+    #[test]
+    fn round_trips_with_trained_dictionary() {
+        // 辞書はプロセス共通のため、パイプラインのテストと同時に差し替えない
+        let _guard = crate::test_support::PIPELINE_LOCK.blocking_lock();
+        // 宛先ポートと識別子だけが異なるDNS問い合わせに似たパケット
+        let samples = (0..1000u32)
+            .map(|i| {
+                let mut sample = b"\x00\x1a\x2b\x3c\x4d\x5e\x08\x00\x45\x00\x00\x3c".to_vec();
+                sample.extend_from_slice(&i.to_be_bytes());
+                sample.extend_from_slice(b"\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01");
+                sample.extend_from_slice(&(i * 7).to_le_bytes());
+                sample
+            })
+            .collect::<Vec<_>>();
+        let content = train_dictionary(&samples, 4096).unwrap();
+        let id = zstd::zstd_safe::get_dict_id_from_dict(&content).unwrap().get();
+        *DICTIONARY.write().unwrap() = Some(Arc::new(Dictionary { id, content }));
+
+        let input = &samples[42];
+        let compressed = compress(Codec::ZstdDictionary, 3, input).unwrap();
+        assert!(compressed.len() < compress(Codec::Zstd, 3, input).unwrap().len());
+        assert_eq!(&decompress(Codec::ZstdDictionary, &compressed).unwrap(), input);
+
+        // 別の辞書で圧縮されたパケットは展開しない
+        *DICTIONARY.write().unwrap() = Some(Arc::new(Dictionary { id: id.wrapping_add(1), content: Vec::new() }));
+        assert!(matches!(
+            decompress(Codec::ZstdDictionary, &compressed),
+            Err(CompressionError::DictionaryMismatch { .. })
+        ));
+        *DICTIONARY.write().unwrap() = None;
+        assert!(matches!(decompress(Codec::ZstdDictionary, &compressed), Err(CompressionError::NoDictionary)));
+    }
+}
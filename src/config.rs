@@ -0,0 +1,2084 @@
+use crate::compression::Codec;
+use crate::database::types::MacAddr;
+use crate::error::InitProcessError;
+use crate::firewall::{Filter, Policy};
+use crate::security::notify::Severity;
+use crate::secret_provider::SecretProviderChain;
+use crate::segmentation;
+use ipnetwork::IpNetwork;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// 設定ファイルのパス (RDB_TUNNEL_CONFIGで変更可能)
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// 設定ファイル (TOML) の内容。DB接続情報は機密情報を含むため環境変数から取得する
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub device: DeviceConfig,
+    pub capture: CaptureConfig,
+    pub interface: InterfaceConfig,
+    pub poller: PollerConfig,
+    pub writer: WriterConfig,
+    pub transport: TransportConfig,
+    pub http: HttpConfig,
+    pub grpc: GrpcConfig,
+    pub control: ControlConfig,
+    pub retention: RetentionConfig,
+    pub migrations: MigrationsConfig,
+    pub log: LogConfig,
+    pub telemetry: TelemetryConfig,
+    pub stats: StatsConfig,
+    pub shaper: ShaperConfig,
+    pub firewall: FirewallConfig,
+    pub nft_offload: NftOffloadConfig,
+    pub bans: BanConfig,
+    pub notify: NotifyConfig,
+    pub siem: SiemConfig,
+    pub security: SecurityConfig,
+    pub decapsulation: DecapsulationConfig,
+    pub qos: QosConfig,
+    pub nat: NatConfig,
+    pub routes: RoutesConfig,
+    pub policy_routing: PolicyRoutingConfig,
+    pub bridge: BridgeConfig,
+    pub supervisor: SupervisorConfig,
+    pub retry: RetryConfig,
+}
+
+impl Config {
+    // 設定ファイルが存在しない場合はデフォルト値を使用する
+    pub fn load() -> Result<Self, InitProcessError> {
+        let path = dotenv::var("RDB_TUNNEL_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+        let path = Path::new(&path);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| InitProcessError::ConfigError(format!("{}: {}", path.display(), e)))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| InitProcessError::ConfigError(format!("{}: {}", path.display(), e)))?;
+
+        #[cfg(not(feature = "kafka"))]
+        if config.transport.backend == TransportBackend::Kafka {
+            return Err(InitProcessError::ConfigError(
+                "[transport] backend = \"kafka\" を使用するには kafka フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        #[cfg(not(feature = "redis"))]
+        if config.transport.backend == TransportBackend::Redis {
+            return Err(InitProcessError::ConfigError(
+                "[transport] backend = \"redis\" を使用するには redis フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        #[cfg(not(feature = "mqtt"))]
+        if config.transport.backend == TransportBackend::Mqtt {
+            return Err(InitProcessError::ConfigError(
+                "[transport] backend = \"mqtt\" を使用するには mqtt フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        #[cfg(not(feature = "nats"))]
+        if config.transport.backend == TransportBackend::Nats {
+            return Err(InitProcessError::ConfigError(
+                "[transport] backend = \"nats\" を使用するには nats フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        #[cfg(not(feature = "sqlite"))]
+        if config.transport.backend == TransportBackend::Sqlite {
+            return Err(InitProcessError::ConfigError(
+                "[transport] backend = \"sqlite\" を使用するには sqlite フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        #[cfg(not(feature = "clickhouse"))]
+        if config.transport.backend == TransportBackend::ClickHouse || config.transport.clickhouse.mirror {
+            return Err(InitProcessError::ConfigError(
+                "ClickHouseを使用するには clickhouse フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        if config.stats.summary && config.stats.summary_interval.is_zero() {
+            return Err(InitProcessError::ConfigError("[stats] summary_interval は0より大きい値を指定してください".to_string()));
+        }
+
+        if config.shaper.rates().contains(&Some(0)) {
+            return Err(InitProcessError::ConfigError("[shaper] の上限は1以上を指定してください".to_string()));
+        }
+
+        if config.qos.remark.iter().any(|rule| rule.dscp > MAX_DSCP || rule.from_dscp.is_some_and(|dscp| dscp > MAX_DSCP)) {
+            return Err(InitProcessError::ConfigError(format!("[qos] DSCPは0から{}の値を指定してください", MAX_DSCP)));
+        }
+
+        if config.writer.compression == Codec::Zstd && !zstd::compression_level_range().contains(&config.writer.compression_level) {
+            return Err(InitProcessError::ConfigError(format!(
+                "[writer] compression_level は{:?}の範囲で指定してください",
+                zstd::compression_level_range()
+            )));
+        }
+
+        if config.writer.mtu < MIN_MTU || config.writer.mtu > MAX_MTU {
+            return Err(InitProcessError::ConfigError(format!(
+                "[writer] mtu は{}から{}の値を指定してください",
+                MIN_MTU, MAX_MTU
+            )));
+        }
+        if config.nat.enabled {
+            if config.nat.sources.is_empty() || config.nat.sources.iter().any(|network| !network.is_ipv4()) {
+                return Err(InitProcessError::ConfigError("[nat] sources にIPv4のネットワークを指定してください".to_string()));
+            }
+            let [start, end] = config.nat.ports;
+            if start == 0 || start > end {
+                return Err(InitProcessError::ConfigError("[nat] ports は1以上の [開始, 終了] を指定してください".to_string()));
+            }
+        }
+        if config.nat.tcp_timeout.is_zero() || config.nat.udp_timeout.is_zero() {
+            return Err(InitProcessError::ConfigError("[nat] のタイムアウトは0より大きい値を指定してください".to_string()));
+        }
+        for rule in &config.nat.forward {
+            let (listen, target) = (rule.listen, rule.target);
+            if !listen.is_ipv4() || !target.is_ipv4() || listen.port() == 0 || target.port() == 0 {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[[nat.forward]] {} -> {}: IPv4のアドレスと1以上のポートを指定してください",
+                    listen, target
+                )));
+            }
+            // 送信元NATの変換後のポートと重なると、応答と転送を区別できない
+            let [start, end] = config.nat.ports;
+            if config.nat.enabled && (start..=end).contains(&listen.port()) {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[[nat.forward]] {}: listen のポートは [nat] ports の範囲外を指定してください",
+                    listen
+                )));
+            }
+        }
+        if config.routes.enabled {
+            if config.transport.backend != TransportBackend::Timescale {
+                return Err(InitProcessError::ConfigError(
+                    "[routes] は [transport] backend = \"timescale\" の場合のみ使用できます".to_string(),
+                ));
+            }
+            if !cfg!(target_os = "linux") {
+                return Err(InitProcessError::ConfigError("[routes] はLinuxのみ対応しています".to_string()));
+            }
+            if config.routes.interval.is_zero() || config.routes.expire <= config.routes.interval {
+                return Err(InitProcessError::ConfigError(
+                    "[routes] expire は interval より長い時間を指定してください".to_string(),
+                ));
+            }
+        }
+        if let Some(dir) = &config.firewall.rules_dir {
+            if !dir.is_dir() {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[firewall] rules_dir のディレクトリが存在しません: {}",
+                    dir.display()
+                )));
+            }
+        }
+        if config.nft_offload.enabled {
+            if !cfg!(target_os = "linux") {
+                return Err(InitProcessError::ConfigError("[nft_offload] はLinuxのみ対応しています".to_string()));
+            }
+            if config.nft_offload.table.is_empty() || config.nft_offload.table == "rdb_tunnel" {
+                return Err(InitProcessError::ConfigError(
+                    "[nft_offload] table は空または rdb_tunnel ([policy_routing] が使用) 以外を指定してください".to_string(),
+                ));
+            }
+            if config.nft_offload.interval.is_zero() {
+                return Err(InitProcessError::ConfigError(
+                    "[nft_offload] interval は0より大きい値を指定してください".to_string(),
+                ));
+            }
+        }
+        let bans = &config.bans;
+        if bans.max_retry == 0 || bans.priority == 0 {
+            return Err(InitProcessError::ConfigError(
+                "[bans] max_retry と priority は1以上を指定してください".to_string(),
+            ));
+        }
+        if bans.find_window.is_zero() || bans.interval.is_zero() || bans.duration.is_zero() || bans.max_duration < bans.duration {
+            return Err(InitProcessError::ConfigError(
+                "[bans] find_window・interval・duration は0より大きく、max_duration は duration 以上を指定してください".to_string(),
+            ));
+        }
+        if config.notify.enabled {
+            let notify = &config.notify;
+            if notify.endpoints.is_empty() {
+                return Err(InitProcessError::ConfigError("[notify] endpoints を指定してください".to_string()));
+            }
+            if let Some(endpoint) = notify.endpoints.iter().find(|endpoint| !endpoint.url().starts_with("https://") && !endpoint.url().starts_with("http://")) {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[notify] endpoints ({}) の url は http:// または https:// で指定してください",
+                    endpoint.kind()
+                )));
+            }
+            if notify.max_batch == 0 || notify.batch_interval.is_zero() || notify.check_interval.is_zero() || notify.peer_timeout.is_zero() {
+                return Err(InitProcessError::ConfigError(
+                    "[notify] max_batch・batch_interval・check_interval・peer_timeout は0より大きい値を指定してください".to_string(),
+                ));
+            }
+        }
+        if config.siem.enabled {
+            if config.siem.sinks.is_empty() {
+                return Err(InitProcessError::ConfigError("[siem] sinks を指定してください".to_string()));
+            }
+            if config.siem.sinks.iter().any(|sink| sink.address.is_empty()) {
+                return Err(InitProcessError::ConfigError("[siem] sinks の address を指定してください".to_string()));
+            }
+            if config.siem.flow_interval.is_zero() {
+                return Err(InitProcessError::ConfigError("[siem] flow_interval は0より大きい値を指定してください".to_string()));
+            }
+        }
+        let dns = &config.security.dns;
+        if dns.max_label_length == 0 || dns.max_label_length > 63 {
+            return Err(InitProcessError::ConfigError(
+                "[security.dns] max_label_length は1から63の範囲で指定してください".to_string(),
+            ));
+        }
+        if dns.entropy_threshold <= 0.0 || dns.min_entropy_length == 0 || dns.max_txt_queries == 0 || dns.window.is_zero() {
+            return Err(InitProcessError::ConfigError(
+                "[security.dns] entropy_threshold・min_entropy_length・max_txt_queries・window は0より大きい値を指定してください".to_string(),
+            ));
+        }
+        let beacon = &config.security.beacon;
+        if beacon.min_connections < 3 {
+            return Err(InitProcessError::ConfigError(
+                "[security.beacon] min_connections は3以上を指定してください".to_string(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&beacon.score_threshold) || beacon.min_interval.is_zero() || beacon.max_interval < beacon.min_interval {
+            return Err(InitProcessError::ConfigError(
+                "[security.beacon] score_threshold は0から1の範囲で、max_interval は min_interval 以上を指定してください".to_string(),
+            ));
+        }
+        let arp = &config.security.arp;
+        if arp.binding_ttl.is_zero() || arp.block && (arp.block_duration.is_zero() || arp.priority == 0) {
+            return Err(InitProcessError::ConfigError(
+                "[security.arp] binding_ttl・block_duration・priority は0より大きい値を指定してください".to_string(),
+            ));
+        }
+        if config.security.dhcp.max_discover_clients == 0 || config.security.dhcp.window.is_zero() {
+            return Err(InitProcessError::ConfigError(
+                "[security.dhcp] max_discover_clients と window は0より大きい値を指定してください".to_string(),
+            ));
+        }
+        let brute_force = &config.security.brute_force;
+        if brute_force.max_attempts == 0 || brute_force.max_duration.is_zero() || brute_force.window.is_zero() {
+            return Err(InitProcessError::ConfigError(
+                "[security.brute_force] max_attempts・max_duration・window は0より大きい値を指定してください".to_string(),
+            ));
+        }
+        if config.security.smb.max_hosts == 0 || config.security.smb.window.is_zero() {
+            return Err(InitProcessError::ConfigError(
+                "[security.smb] max_hosts と window は0より大きい値を指定してください".to_string(),
+            ));
+        }
+        if config.security.modbus.rules.iter().any(|rule| rule.registers.is_some_and(|[start, end]| start > end)) {
+            return Err(InitProcessError::ConfigError(
+                "[security.modbus] rules の registers は [先頭, 末尾] の順に指定してください".to_string(),
+            ));
+        }
+        if config.security.sip.max_call_duration.is_zero() || config.security.sip.dscp.is_some_and(|dscp| dscp > 63) {
+            return Err(InitProcessError::ConfigError(
+                "[security.sip] max_call_duration は0より大きい値を、dscp は0から63の範囲で指定してください".to_string(),
+            ));
+        }
+        let flood = &config.security.flood;
+        if flood.window.is_zero() || (flood.enabled && flood.max_packets == 0 && flood.max_bytes == 0) {
+            return Err(InitProcessError::ConfigError(
+                "[security.flood] window は0より大きい値を、max_packets・max_bytes のいずれかに閾値を指定してください".to_string(),
+            ));
+        }
+        let reassembly = &config.security.reassembly;
+        if reassembly.max_stream_size == 0 || reassembly.max_streams == 0 || reassembly.timeout.is_zero() {
+            return Err(InitProcessError::ConfigError(
+                "[security.reassembly] max_stream_size・max_streams・timeout は0より大きい値を指定してください".to_string(),
+            ));
+        }
+        if config.security.yara.enabled {
+            if !cfg!(feature = "yara") {
+                return Err(InitProcessError::ConfigError(
+                    "[security.yara] を使用するには yara フィーチャーを有効にしてビルドしてください".to_string(),
+                ));
+            }
+            if config.security.yara.rules.is_empty() {
+                return Err(InitProcessError::ConfigError("[security.yara] rules にルールファイルを指定してください".to_string()));
+            }
+        }
+        if config.security.carving.enabled && config.security.carving.directory.as_os_str().is_empty() {
+            return Err(InitProcessError::ConfigError("[security.carving] directory に保存先を指定してください".to_string()));
+        }
+        if config.security.sip.enabled && config.security.sip.sources.is_empty() {
+            return Err(InitProcessError::ConfigError(
+                "[security.sip] sources に自拠点の端末のネットワークを指定してください".to_string(),
+            ));
+        }
+        if config.policy_routing.enabled {
+            let policy = &config.policy_routing;
+            if !cfg!(target_os = "linux") {
+                return Err(InitProcessError::ConfigError("[policy_routing] はLinuxのみ対応しています".to_string()));
+            }
+            // 0: 未指定, 253: default, 254: main, 255: local
+            if matches!(policy.table, 0 | 253..=255) {
+                return Err(InitProcessError::ConfigError(
+                    "[policy_routing] table は0・253・254・255以外を指定してください".to_string(),
+                ));
+            }
+            if policy.sources.is_empty() && policy.fwmark.is_none() {
+                return Err(InitProcessError::ConfigError(
+                    "[policy_routing] sources または fwmark を指定してください".to_string(),
+                ));
+            }
+            if !policy.cgroups.is_empty() && policy.fwmark.is_none() {
+                return Err(InitProcessError::ConfigError(
+                    "[policy_routing] cgroups を使用するには fwmark を指定してください".to_string(),
+                ));
+            }
+            if let Some(gateway) = policy.gateway {
+                if policy.sources.iter().any(|source| source.is_ipv4() != gateway.is_ipv4()) {
+                    return Err(InitProcessError::ConfigError(
+                        "[policy_routing] sources は gateway と同じアドレスファミリーを指定してください".to_string(),
+                    ));
+                }
+            }
+        }
+        if config.bridge.enabled {
+            if !cfg!(target_os = "linux") {
+                return Err(InitProcessError::ConfigError("[bridge] はLinuxのみ対応しています".to_string()));
+            }
+            if config.interface.mode != InterfaceMode::Tap {
+                return Err(InitProcessError::ConfigError(
+                    "[bridge] を使用するには [interface] mode = \"tap\" を指定してください".to_string(),
+                ));
+            }
+            if config.bridge.name.is_empty() || config.bridge.interface.is_empty() {
+                return Err(InitProcessError::ConfigError(
+                    "[bridge] name と interface を指定してください".to_string(),
+                ));
+            }
+        }
+        for (task, policy) in [
+            ("polling", &config.supervisor.polling),
+            ("writer", &config.supervisor.writer),
+            ("analysis", &config.supervisor.analysis),
+        ] {
+            if policy.initial_backoff.is_zero() || policy.max_backoff < policy.initial_backoff {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[supervisor.{}] initial_backoff は0より大きく max_backoff 以下にしてください",
+                    task
+                )));
+            }
+        }
+        if config.retry.max_attempts == 0 {
+            return Err(InitProcessError::ConfigError("[retry] max_attempts は1以上を指定してください".to_string()));
+        }
+        if config.retry.max_backoff < config.retry.initial_backoff {
+            return Err(InitProcessError::ConfigError(
+                "[retry] max_backoff は initial_backoff 以上にしてください".to_string(),
+            ));
+        }
+        if config.interface.name.is_empty() {
+            return Err(InitProcessError::ConfigError("[interface] name を指定してください".to_string()));
+        }
+        if let Some(mac) = &config.interface.mac {
+            if !mac.is_unicast() || mac.0 == [0; 6] {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[interface] mac にはユニキャストのMACアドレスを指定してください: {}",
+                    mac
+                )));
+            }
+            if config.interface.mode != InterfaceMode::Tap {
+                return Err(InitProcessError::ConfigError(
+                    "[interface] mac は mode = \"tap\" の場合のみ指定できます".to_string(),
+                ));
+            }
+        }
+        if let Some(mtu) = config.interface.mtu {
+            // IPv4の最小MTUからIPパケットの最大長までに限る
+            if !(68..=65535).contains(&mtu) {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[interface] mtu は68から65535の範囲で指定してください: {}",
+                    mtu
+                )));
+            }
+        }
+        if config.interface.txqueuelen == Some(0) {
+            return Err(InitProcessError::ConfigError("[interface] txqueuelen は1以上を指定してください".to_string()));
+        }
+        if config.interface.disable_ipv6 {
+            if let Some(address) = config.interface.addresses.iter().find(|address| address.is_ipv6()) {
+                return Err(InitProcessError::ConfigError(format!(
+                    "[interface] disable_ipv6 = true の場合はIPv6のアドレスを指定できません: {}",
+                    address
+                )));
+            }
+        }
+        if config.writer.workers == 0 {
+            return Err(InitProcessError::ConfigError("[writer] workers は1以上を指定してください".to_string()));
+        }
+
+        #[cfg(not(feature = "replication"))]
+        if config.poller.mode == PollMode::Replication {
+            return Err(InitProcessError::ConfigError(
+                "[poller] mode = \"replication\" を使用するには replication フィーチャーを有効にしてビルドしてください".to_string(),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+// キャプチャ/注入に使用するデバイスの選択条件 (全て未指定の場合は対話的に選択する)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    // インターフェース名 (例: "eth0")
+    pub name: Option<String>,
+    // このサブネットのアドレスを持つインターフェース (例: "192.168.0.0/24")
+    pub subnet: Option<IpNetwork>,
+    // MACアドレス。末尾の * で前方一致 (例: "52:54:00:*")
+    pub mac: Option<String>,
+}
+
+impl DeviceConfig {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.subnet.is_none() && self.mac.is_none()
+    }
+}
+
+impl fmt::Display for DeviceConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut conditions = Vec::new();
+        if let Some(name) = &self.name {
+            conditions.push(format!("name={}", name));
+        }
+        if let Some(subnet) = &self.subnet {
+            conditions.push(format!("subnet={}", subnet));
+        }
+        if let Some(mac) = &self.mac {
+            conditions.push(format!("mac={}", mac));
+        }
+        write!(f, "{}", conditions.join(", "))
+    }
+}
+
+// パケットキャプチャの設定
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    // キャプチャするインターフェース名の一覧。空の場合は [device] で選択したデバイス。
+    // 仮想NICは指定しなくても常にキャプチャする
+    pub interfaces: Vec<String>,
+}
+
+// 仮想NICの動作モード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceMode {
+    // L2 (Ethernetフレーム): Linux TAP / macOS feth
+    #[default]
+    Tap,
+    // L3 (IPパケット): Linux TUN / macOS utun / Windows wintun
+    Tun,
+}
+
+// 仮想NICの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InterfaceConfig {
+    pub mode: InterfaceMode,
+    // 仮想NICの名前。同じホストで複数のトンネルを起動する場合はインスタンスごとに変える
+    // (Linuxでは "tap%d" のように指定すると空いている番号を使用する。macOSでは指定できない)
+    pub name: String,
+    // 仮想NICのMACアドレス (tapモードのみ)。未指定の場合はOSが割り当てる
+    pub mac: Option<MacAddr>,
+    // 仮想NICのMTU。未指定の場合はOSの既定値 (1500)
+    pub mtu: Option<u32>,
+    // 送信キューの長さ (Linuxのみ)。未指定の場合はOSの既定値
+    pub txqueuelen: Option<u32>,
+    // TAP_IP/TAP_MASK に加えて設定するIPv4/IPv6アドレス
+    pub addresses: Vec<IpNetwork>,
+    // 仮想NICのIPv6を無効にする (Linuxのみ)。IPv6のアドレスは指定できない
+    pub disable_ipv6: bool,
+}
+
+impl Default for InterfaceConfig {
+    fn default() -> Self {
+        Self {
+            mode: InterfaceMode::default(),
+            name: "tap0".to_string(),
+            mac: None,
+            mtu: None,
+            txqueuelen: None,
+            addresses: Vec::new(),
+            disable_ipv6: false,
+        }
+    }
+}
+
+// ポーリングで新しいパケットを判定する方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PollMode {
+    // タイムスタンプで比較する (ノード間の時刻のずれの分だけ遡って取得する)
+    #[default]
+    Timestamp,
+    // 単調増加するidで比較する (時刻のずれや同一タイムスタンプの影響を受けない)
+    Sequence,
+    // ポーリングせず、論理レプリケーション (pgoutput) でコミットされた挿入を受信する
+    Replication,
+}
+
+// パケットのポーリングの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PollerConfig {
+    pub mode: PollMode,
+    // 連番の欠落から推定した送信元ノードごとの損失率 (%) がこれを超えた場合に警告する
+    pub loss_warning_percent: f64,
+}
+
+impl Default for PollerConfig {
+    fn default() -> Self {
+        Self {
+            mode: PollMode::default(),
+            loss_warning_percent: 1.0,
+        }
+    }
+}
+
+// [writer] mtu に指定できる範囲 (IPv4で全てのホストが受信できる最小の長さ〜IPパケットの最大長)
+const MIN_MTU: usize = 576;
+const MAX_MTU: usize = 65535;
+
+// パケットの書き込みの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriterConfig {
+    // 書き込みワーカー数。パケットはフローごとに同じワーカーへ振り分けるため、フロー内の順序は保たれる
+    pub workers: usize,
+    // raw_packetとdataを書き込む前に圧縮する方式 (none / zstd / lz4)
+    pub compression: Codec,
+    // zstdの圧縮レベル
+    pub compression_level: i32,
+    // これより短いフレームは圧縮しない (バイト)
+    pub compression_min_size: usize,
+    // 小さいパケットの圧縮に使用するzstdの辞書 (rdb-tunnel train-dictionaryで作成する)。受信側も同じ辞書で展開する
+    pub compression_dictionary: Option<PathBuf>,
+    // 辞書を使用するフレームの最大長 (バイト)
+    pub dictionary_max_size: usize,
+    // 同一のブロードキャスト・マルチキャストフレームをこの時間内に再度キャプチャした場合は書き込まない (0で無効)
+    #[serde(with = "humantime_serde")]
+    pub broadcast_dedup_window: Duration,
+    // トンネルのMTU (IPパケットの最大長)。これを超えるTCPフレーム (TSO/GSO) はセグメントに分割して書き込む
+    pub mtu: usize,
+    // MTUを超え、経路上で断片化できない (IPv4でDFが設定されている・IPv6) パケットを破棄し、
+    // 送信元へICMPv4 Fragmentation Needed / ICMPv6 Packet Too Bigを返す
+    pub icmp_too_big: bool,
+    // このサブネット宛のパケットのみ書き込む (スプリットトンネル)。空の場合は全てのパケットを書き込む
+    pub allowed_subnets: Vec<IpNetwork>,
+    // 終了時にバッファに残っているパケットを送信する時間の上限。超えた場合は破棄する
+    #[serde(with = "humantime_serde")]
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            compression: Codec::None,
+            compression_level: 3,
+            compression_min_size: 64,
+            compression_dictionary: None,
+            dictionary_max_size: 256,
+            broadcast_dedup_window: Duration::from_millis(50),
+            mtu: segmentation::DEFAULT_MTU,
+            icmp_too_big: true,
+            allowed_subnets: Vec::new(),
+            shutdown_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+// パケットを中継するバックエンド
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportBackend {
+    // PostgreSQL/TimescaleDBのpacketsテーブルを経由する
+    #[default]
+    Timescale,
+    // Kafkaのトピックを経由する (データベースは使用しない)
+    Kafka,
+    // Redis Streamsを経由する (データベースは使用しない)
+    Redis,
+    // MQTTブローカーを経由する (データベースは使用しない)
+    Mqtt,
+    // NATS JetStreamを経由する (データベースは使用しない)
+    Nats,
+    // SQLiteのファイルを経由する (開発・CI用。TimescaleDBは使用しない)
+    Sqlite,
+    // ClickHouseのテーブルを経由する (TimescaleDBは使用しない)
+    #[serde(rename = "clickhouse")]
+    ClickHouse,
+    // プロセス内のチャネルを経由する (自ノード宛のパケットのみ折り返す。動作確認・テスト用)
+    Memory,
+}
+
+impl fmt::Display for TransportBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportBackend::Timescale => write!(f, "timescale"),
+            TransportBackend::Kafka => write!(f, "kafka"),
+            TransportBackend::Redis => write!(f, "redis"),
+            TransportBackend::Mqtt => write!(f, "mqtt"),
+            TransportBackend::Nats => write!(f, "nats"),
+            TransportBackend::Sqlite => write!(f, "sqlite"),
+            TransportBackend::ClickHouse => write!(f, "clickhouse"),
+            TransportBackend::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+// パケットの中継方法の設定
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportConfig {
+    pub backend: TransportBackend,
+    pub kafka: KafkaConfig,
+    pub redis: RedisConfig,
+    pub mqtt: MqttConfig,
+    pub nats: NatsConfig,
+    pub sqlite: SqliteConfig,
+    pub clickhouse: ClickHouseConfig,
+}
+
+// Kafkaトランスポートの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    // ブローカーのアドレス (カンマ区切り)
+    pub brokers: String,
+    pub topic: String,
+    // librdkafkaに渡す追加の設定 (security.protocol など)
+    pub options: HashMap<String, String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            topic: "rdb-tunnel-packets".to_string(),
+            options: HashMap::new(),
+        }
+    }
+}
+
+// Redis Streamsトランスポートの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    // 接続先 (redis://[ユーザー:パスワード@]ホスト:ポート[/DB番号])
+    pub url: String,
+    pub stream: String,
+    // ストリームに保持するエントリー数の目安 (XADD MAXLEN ~)
+    pub max_len: usize,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        Self {
+            url: "redis://127.0.0.1:6379".to_string(),
+            stream: "rdb-tunnel-packets".to_string(),
+            max_len: 100_000,
+        }
+    }
+}
+
+// MQTTトランスポートの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    // パケットは <topic_prefix>/node/<IPアドレス> と <topic_prefix>/broadcast に送信する
+    pub topic_prefix: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            topic_prefix: "rdb-tunnel".to_string(),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+// NATS JetStreamトランスポートの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NatsConfig {
+    pub url: String,
+    // 存在しない場合は <subject_prefix>.> を対象として作成する
+    pub stream: String,
+    // パケットは <subject_prefix>.node.<IPアドレス> と <subject_prefix>.broadcast に送信する
+    pub subject_prefix: String,
+    // ストリームにメッセージを保持する期間
+    #[serde(with = "humantime_serde")]
+    pub max_age: Duration,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        Self {
+            url: "nats://localhost:4222".to_string(),
+            stream: "RDB_TUNNEL".to_string(),
+            subject_prefix: "rdb-tunnel".to_string(),
+            max_age: Duration::from_secs(5 * 60),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+// SQLiteトランスポートの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteConfig {
+    // 存在しない場合は作成する。ノード間で同じファイルを指定する
+    pub path: PathBuf,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("rdb-tunnel.sqlite3"),
+        }
+    }
+}
+
+// ClickHouseトランスポートの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickHouseConfig {
+    // HTTPインターフェースのURL
+    pub url: String,
+    pub database: String,
+    // 存在しない場合は日ごとにパーティションを分けて作成する
+    pub table: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    // テーブルの作成時に設定する保持期間。未指定の場合は削除しない
+    #[serde(with = "humantime_serde")]
+    pub ttl: Option<Duration>,
+    // backendが clickhouse 以外の場合に、送信したパケットをClickHouseにも書き込む
+    pub mirror: bool,
+}
+
+impl Default for ClickHouseConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8123".to_string(),
+            database: "default".to_string(),
+            table: "packets".to_string(),
+            user: None,
+            password: None,
+            ttl: None,
+            mirror: false,
+        }
+    }
+}
+
+// HTTPサーバー (メトリクス、ヘルスチェック、管理API) の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub listen: SocketAddr,
+    // 設定した場合、/api へのリクエストに `Authorization: Bearer <token>` を要求する
+    pub api_token: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 9898)),
+            api_token: None,
+        }
+    }
+}
+
+// gRPC制御APIの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+    // 設定した場合、メタデータに `authorization: Bearer <token>` を要求する
+    pub api_token: Option<String>,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::from(([127, 0, 0, 1], 50051)),
+            api_token: None,
+        }
+    }
+}
+
+// ローカル制御ソケット (rdb-tunnelctl) の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    pub socket: PathBuf,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            socket: PathBuf::from("/run/rdb-tunnel/control.sock"),
+        }
+    }
+}
+
+// 古いパケットの定期削除の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    // パケットの保持期間 ("24h", "7d" など)。未指定の場合は削除しない
+    #[serde(with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    // 宛先の全てのノードが処理済みとして記録したパケットを削除する (timescaleトランスポートのみ)
+    pub delete_delivered: bool,
+    // 削除処理の実行間隔
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            delete_delivered: false,
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+// 起動時のスキーマのマイグレーションとTimescaleDBのポリシーの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MigrationsConfig {
+    // 起動時に未適用のマイグレーションを適用する
+    pub enabled: bool,
+    // ハイパーテーブルのチャンク間隔。未指定の場合は変更しない
+    #[serde(with = "humantime_serde")]
+    pub chunk_interval: Option<Duration>,
+    // この期間を過ぎたチャンクを圧縮する
+    #[serde(with = "humantime_serde")]
+    pub compress_after: Option<Duration>,
+    // この期間を過ぎたチャンクをTimescaleDBのジョブで削除する
+    #[serde(with = "humantime_serde")]
+    pub drop_after: Option<Duration>,
+}
+
+impl Default for MigrationsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            chunk_interval: None,
+            compress_after: None,
+            drop_after: None,
+        }
+    }
+}
+
+// ログの出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // 人間向けのテキスト形式
+    #[default]
+    Text,
+    // Loki/ELKなどで取り込むためのJSON Lines形式
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("未対応のログ形式です: {}", other)),
+        }
+    }
+}
+
+// ログメッセージの言語 (メッセージIDは言語によらず共通)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLang {
+    #[default]
+    Ja,
+    En,
+}
+
+impl FromStr for LogLang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ja" => Ok(LogLang::Ja),
+            "en" => Ok(LogLang::En),
+            other => Err(format!("未対応のログの言語です: {}", other)),
+        }
+    }
+}
+
+// ログファイルを時間でローテーションする間隔
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
+// ログ出力の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    // LOG_FORMAT環境変数で上書き可能
+    pub format: LogFormat,
+    // RUST_LOG環境変数で上書き可能
+    pub level: String,
+    // LOG_LANG環境変数で上書き可能
+    pub lang: LogLang,
+    pub stdout: bool,
+    // 空文字列を指定するとファイルには出力しない
+    pub file: PathBuf,
+    pub rotation: LogRotation,
+    // このサイズを超えたらローテーションする
+    pub max_size_mb: Option<u64>,
+    // ローテーション済みファイルの保持期間
+    pub max_age_days: Option<u64>,
+    // ローテーション済みファイルの保持数
+    pub max_files: Option<usize>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            level: "info".to_string(),
+            lang: LogLang::Ja,
+            stdout: true,
+            file: PathBuf::from("application.log"),
+            rotation: LogRotation::Never,
+            max_size_mb: None,
+            max_age_days: None,
+            max_files: None,
+        }
+    }
+}
+
+// OpenTelemetry (OTLP) の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    // OTLP/HTTPのエンドポイント (/v1/traces, /v1/metrics は自動で付与)
+    pub endpoint: String,
+    pub service_name: String,
+    // トレースのサンプリング率 (0.0 - 1.0)
+    pub sample_ratio: f64,
+    // メトリクスの送信間隔 (秒)
+    pub metrics_interval_secs: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://localhost:4318".to_string(),
+            service_name: "rdb-tunnel".to_string(),
+            sample_ratio: 1.0,
+            metrics_interval_secs: 30,
+        }
+    }
+}
+
+// 注入するパケットの帯域制御 (トークンバケット) の設定。上限を指定しない項目は制限しない
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShaperConfig {
+    // 全体の上限
+    pub bytes_per_sec: Option<u64>,
+    pub packets_per_sec: Option<u64>,
+    // 送信元ノードごとの上限
+    pub peer_bytes_per_sec: Option<u64>,
+    pub peer_packets_per_sec: Option<u64>,
+    // この時間分の上限を超えるまでは、一度に届いたパケットを待たせずに注入する
+    #[serde(with = "humantime_serde")]
+    pub burst: Duration,
+    // 注入を待たせる時間の上限。これを超えて待つ必要があるパケットは破棄する
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+}
+
+impl ShaperConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.rates().iter().any(Option::is_some)
+    }
+
+    fn rates(&self) -> [Option<u64>; 4] {
+        [self.bytes_per_sec, self.packets_per_sec, self.peer_bytes_per_sec, self.peer_packets_per_sec]
+    }
+}
+
+impl Default for ShaperConfig {
+    fn default() -> Self {
+        Self {
+            bytes_per_sec: None,
+            packets_per_sec: None,
+            peer_bytes_per_sec: None,
+            peer_packets_per_sec: None,
+            burst: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+// DSCPの最大値 (6ビット)
+pub const MAX_DSCP: u8 = 63;
+
+// ファイアウォールの各チェインの既定の方針 (SIGHUPで再読み込み可能)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirewallConfig {
+    // 受信したパケットを仮想NICへ注入する前に適用する (INPUT)
+    pub input_policy: Policy,
+    // キャプチャしたパケットを書き込む前に適用する (OUTPUT)
+    pub output_policy: Policy,
+    // ルールセットのファイル (.json / .yaml / .yml) を置くディレクトリ。変更を監視して再読み込みする (起動時のみ)
+    pub rules_dir: Option<PathBuf>,
+}
+
+// 送信側チェインで遮断しているIPアドレスをnftablesのセットへ反映し、カーネルで破棄する設定 (Linuxのみ)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NftOffloadConfig {
+    pub enabled: bool,
+    // 作成するnftablesのテーブル (inet)。終了時に削除する
+    pub table: String,
+    // ルールの変更を確認する間隔
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for NftOffloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table: "rdb_tunnel_block".to_string(),
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+// 検知機能の通知に応じた一時的な遮断の設定 (SIGHUPで再読み込み可能。interval は起動時のみ)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BanConfig {
+    pub enabled: bool,
+    // find_window の間にこの回数検知されたアドレスを遮断する
+    pub max_retry: u32,
+    #[serde(with = "humantime_serde")]
+    pub find_window: Duration,
+    // 遮断する期間。繰り返し遮断されたアドレスは倍にする (max_duration まで)
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_duration: Duration,
+    // 追加する遮断ルールの優先度
+    pub priority: u8,
+    // 遮断しないアドレス (管理用の端末など)
+    pub ignore: Vec<IpNetwork>,
+    // 期限を過ぎた遮断を解除する間隔
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for BanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_retry: 5,
+            find_window: Duration::from_secs(10 * 60),
+            duration: Duration::from_secs(10 * 60),
+            max_duration: Duration::from_secs(24 * 60 * 60),
+            priority: 250,
+            ignore: Vec::new(),
+            interval: Duration::from_secs(30),
+        }
+    }
+}
+
+// アラートとトンネルの重大なイベントの通知の設定 (SIGHUPで再読み込み可能。各間隔は起動時のみ)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub enabled: bool,
+    // この重大度以上のアラートを通知する
+    pub min_severity: Severity,
+    // アラートをまとめて送信する間隔
+    #[serde(with = "humantime_serde")]
+    pub batch_interval: Duration,
+    // 1回に送信するアラートの上限 (超えた分は件数のみ通知する)
+    pub max_batch: usize,
+    // DBへの接続・対向ノード・書き込みバッファを確認する間隔
+    #[serde(with = "humantime_serde")]
+    pub check_interval: Duration,
+    // この時間パケットを受信していない対向ノードを停止とみなす
+    #[serde(with = "humantime_serde")]
+    pub peer_timeout: Duration,
+    // 書き込み待ちのパケットがこの件数に達した場合に通知する
+    pub buffer_threshold: i64,
+    pub endpoints: Vec<NotifyEndpoint>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_severity: Severity::Warning,
+            batch_interval: Duration::from_secs(10),
+            max_batch: 20,
+            check_interval: Duration::from_secs(30),
+            peer_timeout: Duration::from_secs(5 * 60),
+            buffer_threshold: 100_000,
+            endpoints: Vec::new(),
+        }
+    }
+}
+
+// 通知先。Webhookにはアラートの一覧をJSONで、Slack・Discordには本文を送信する
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum NotifyEndpoint {
+    Webhook { url: String },
+    Slack { url: String },
+    Discord { url: String },
+}
+
+impl NotifyEndpoint {
+    pub fn url(&self) -> &str {
+        match self {
+            NotifyEndpoint::Webhook { url } | NotifyEndpoint::Slack { url } | NotifyEndpoint::Discord { url } => url,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NotifyEndpoint::Webhook { .. } => "webhook",
+            NotifyEndpoint::Slack { .. } => "slack",
+            NotifyEndpoint::Discord { .. } => "discord",
+        }
+    }
+}
+
+// アラートとフローレコードをCEF/LEEF形式でSIEMへ送信する設定 (起動時のみ)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiemConfig {
+    pub enabled: bool,
+    // 対向ノードごとの通信量 (フローレコード) を送信する間隔
+    #[serde(with = "humantime_serde")]
+    pub flow_interval: Duration,
+    pub sinks: Vec<SiemSink>,
+}
+
+impl Default for SiemConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            flow_interval: Duration::from_secs(60),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiemSink {
+    // cef (ArcSight) / leef (QRadar)
+    pub format: SiemFormat,
+    pub transport: SiemTransport,
+    // syslogの受信アドレス ("siem.example.com:514")
+    pub address: String,
+    // アラートを送信する
+    pub alerts: bool,
+    // この重大度以上のアラートを送信する
+    pub min_severity: Severity,
+    // フローレコードを送信する
+    pub flows: bool,
+}
+
+impl Default for SiemSink {
+    fn default() -> Self {
+        Self {
+            format: SiemFormat::Cef,
+            transport: SiemTransport::Udp,
+            address: String::new(),
+            alerts: true,
+            min_severity: Severity::Warning,
+            flows: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SiemFormat {
+    #[default]
+    Cef,
+    Leef,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SiemTransport {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+// カプセル化 (GRE・IP-in-IP・VXLAN) されたパケットの内側の検査の設定 (SIGHUPで再読み込み可能)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DecapsulationConfig {
+    pub enabled: bool,
+    // 内側のパケットのアドレス・ポート・プロトコルを inner_* 列に保存する
+    pub record_inner: bool,
+    // VXLANとして解析するUDPの宛先ポート
+    pub vxlan_ports: Vec<u16>,
+}
+
+impl Default for DecapsulationConfig {
+    fn default() -> Self {
+        Self { enabled: false, record_inner: false, vxlan_ports: vec![4789] }
+    }
+}
+
+// IDPSの検知の設定 (SIGHUPで再読み込み可能)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    pub dns: DnsTunnelConfig,
+    pub beacon: BeaconConfig,
+    pub arp: ArpGuardConfig,
+    pub dhcp: DhcpGuardConfig,
+    pub brute_force: BruteForceConfig,
+    pub flood: FloodConfig,
+    pub smb: SmbConfig,
+    pub modbus: ModbusConfig,
+    pub sip: SipConfig,
+    pub reassembly: ReassemblyConfig,
+    pub yara: YaraConfig,
+    pub carving: FileCarvingConfig,
+}
+
+// DNSトンネリングの検知の閾値
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsTunnelConfig {
+    pub enabled: bool,
+    // これより長いラベルを含むクエリを検知する (ラベルの上限は63文字)
+    pub max_label_length: usize,
+    // サブドメインの1文字あたりのエントロピー (ビット) がこれ以上のクエリを検知する
+    pub entropy_threshold: f64,
+    // エントロピーを計算するサブドメインの最小の長さ (短い名前は誤検知しやすいため)
+    pub min_entropy_length: usize,
+    // window の間にクライアントごとのTXT/NULLクエリがこれを超えた場合に検知する
+    pub max_txt_queries: u32,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for DnsTunnelConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_label_length: 52,
+            entropy_threshold: 4.0,
+            min_entropy_length: 24,
+            max_txt_queries: 30,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+// 周期的な接続 (ビーコン) の検知の閾値
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BeaconConfig {
+    pub enabled: bool,
+    // 周期性を判定するために必要な接続の数
+    pub min_connections: usize,
+    // 接続の間隔の平均がこれより短い場合は検知しない (ポーリングなど)
+    #[serde(with = "humantime_serde")]
+    pub min_interval: Duration,
+    // これより間隔が空いた場合は接続を数え直す
+    #[serde(with = "humantime_serde")]
+    pub max_interval: Duration,
+    // 周期性のスコア (1 - 間隔の変動係数) がこれ以上の場合に検知する
+    pub score_threshold: f64,
+    // 同じ (送信元, 宛先, ポート) のアラートを再び発行するまでの時間
+    #[serde(with = "humantime_serde")]
+    pub realert: Duration,
+}
+
+impl Default for BeaconConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_connections: 8,
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(3600),
+            score_threshold: 0.9,
+            realert: Duration::from_secs(3600),
+        }
+    }
+}
+
+// ARPのなりすましの検知の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArpGuardConfig {
+    pub enabled: bool,
+    // ゲートウェイなど既知のIPアドレスとMACアドレスの対応。別のMACアドレスが名乗った場合は critical のアラートにする
+    pub gateways: Vec<ArpBinding>,
+    // 最後に確認してからこの時間が過ぎた対応は、MACアドレスが変わってもアラートにしない
+    #[serde(with = "humantime_serde")]
+    pub binding_ttl: Duration,
+    // なりすましたMACアドレスをファイアウォールで一時的に遮断する
+    pub block: bool,
+    #[serde(with = "humantime_serde")]
+    pub block_duration: Duration,
+    // 遮断するルールの優先度
+    pub priority: u8,
+}
+
+impl Default for ArpGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gateways: Vec::new(),
+            binding_ttl: Duration::from_secs(4 * 3600),
+            block: false,
+            block_duration: Duration::from_secs(600),
+            priority: 250,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArpBinding {
+    pub ip: Ipv4Addr,
+    pub mac: MacAddr,
+}
+
+// DHCPの枯渇攻撃と不正なDHCPサーバーの検知の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DhcpGuardConfig {
+    pub enabled: bool,
+    // window の間にDISCOVERを送信したMACアドレスの数がこれを超えた場合に検知する
+    pub max_discover_clients: usize,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    // 許可するDHCPサーバー。空の場合は不正なDHCPサーバーを検知しない
+    pub trusted_servers: Vec<IpAddr>,
+}
+
+impl Default for DhcpGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_discover_clients: 50,
+            window: Duration::from_secs(10),
+            trusted_servers: Vec::new(),
+        }
+    }
+}
+
+// 認証の総当たりの検知の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BruteForceConfig {
+    pub enabled: bool,
+    // 対象の宛先ポート
+    pub ports: Vec<u16>,
+    // この時間以内に終了した接続を認証の試行とみなす
+    #[serde(with = "humantime_serde")]
+    pub max_duration: Duration,
+    // window の間に同じ送信元からの試行がこれを超えた場合に検知する
+    pub max_attempts: usize,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for BruteForceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ports: vec![22, 21],
+            max_duration: Duration::from_secs(10),
+            max_attempts: 10,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+// 送信元ごとの通信量の急増の検知の閾値
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FloodConfig {
+    pub enabled: bool,
+    // window の間に1つの送信元のパケット数がこれを超えた場合に検知する (0の場合は数えない)
+    pub max_packets: u64,
+    // window の間に1つの送信元のバイト数がこれを超えた場合に検知する (0の場合は数えない)
+    pub max_bytes: u64,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_packets: 5000,
+            max_bytes: 0,
+            window: Duration::from_secs(1),
+        }
+    }
+}
+
+// SMB (ポート445) の検査の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmbConfig {
+    pub enabled: bool,
+    // ツリー接続とファイルのオープンをログに出力する
+    pub log_commands: bool,
+    // window の間に1つの送信元が445番に接続したホストの数がこれを超えた場合に検知する
+    pub max_hosts: usize,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for SmbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            log_commands: false,
+            max_hosts: 20,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+// Modbus/TCPの検査の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModbusConfig {
+    pub enabled: bool,
+    // Modbus/TCPの宛先ポート
+    pub ports: Vec<u16>,
+    // 規則に一致しない要求のうち、アラートを発行するファンクションコード
+    pub alert_function_codes: Vec<u8>,
+    // 上から順に評価し、最初に一致した規則を適用する
+    pub rules: Vec<ModbusRule>,
+}
+
+impl Default for ModbusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ports: vec![502],
+            // 書き込み (5, 6, 15, 16, 22, 23)・診断 (8)・ベンダー固有のUMAS (90)
+            alert_function_codes: vec![5, 6, 8, 15, 16, 22, 23, 90],
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModbusRule {
+    // 空の場合は全てに一致する
+    #[serde(default)]
+    pub function_codes: Vec<u8>,
+    #[serde(default)]
+    pub unit_ids: Vec<u8>,
+    // レジスタ・コイルのアドレスの範囲 [先頭, 末尾]。要求の範囲と重なる場合に一致する
+    #[serde(default)]
+    pub registers: Option<[u16; 2]>,
+    pub action: ModbusAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModbusAction {
+    Allow,
+    Alert,
+    Drop,
+}
+
+// SIPで学習したRTP・RTCPのピンホールの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SipConfig {
+    pub enabled: bool,
+    // SIPのUDPのポート
+    pub ports: Vec<u16>,
+    // 自拠点の端末のネットワーク。SDPのメディアのアドレスがこの範囲の場合のみピンホールを作成する
+    pub sources: Vec<IpNetwork>,
+    // BYE・CANCELを受信しない場合に通話を終了とみなすまでの時間 (SIPのメッセージを受信するたびに延長する)
+    #[serde(with = "humantime_serde")]
+    pub max_call_duration: Duration,
+    // 書き込むRTP・RTCPに設定するDSCP (既定はEF)。省略した場合は [qos] の規則に従う
+    pub dscp: Option<u8>,
+}
+
+impl Default for SipConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ports: vec![5060],
+            sources: Vec::new(),
+            max_call_duration: Duration::from_secs(4 * 3600),
+            dscp: Some(46),
+        }
+    }
+}
+
+// TCPストリームの再構成の設定 (再構成を使用する検査が有効な場合のみ再構成する)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReassemblyConfig {
+    // 1つのストリーム (片方向) で再構成する上限のバイト数。超えた部分は検査しない
+    pub max_stream_size: usize,
+    // 同時に再構成するストリームの数の上限
+    pub max_streams: usize,
+    // この時間データが届かないストリームは終了したとみなす
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for ReassemblyConfig {
+    fn default() -> Self {
+        Self {
+            max_stream_size: 1024 * 1024,
+            max_streams: 1024,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+// 再構成したTCPストリームのYARAスキャンの設定 (yaraフィーチャーが必要)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YaraConfig {
+    pub enabled: bool,
+    // YARAのルールファイル
+    pub rules: Vec<PathBuf>,
+    // 対象の送信元または宛先のポート (空の場合は全てのTCP)
+    pub ports: Vec<u16>,
+    // 1つのストリームのスキャンの制限時間 (秒単位)
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for YaraConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rules: Vec::new(),
+            ports: Vec::new(),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+// 再構成したHTTP・FTPのストリームからのファイルの抽出の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileCarvingConfig {
+    pub enabled: bool,
+    // メタデータ (files.jsonl) と抽出したファイルを保存するディレクトリ
+    pub directory: PathBuf,
+    // 抽出したファイル自体を <SHA-256> のファイル名で保存する場合はtrue (falseの場合はメタデータのみ)
+    pub store_files: bool,
+    // HTTPとして解析する送信元または宛先のポート
+    pub http_ports: Vec<u16>,
+    // FTPの制御接続の宛先のポート
+    pub ftp_ports: Vec<u16>,
+    // 対象の種類 (空の場合は全て)
+    pub mime_types: Vec<String>,
+    // このバイト数未満のファイルは記録しない
+    pub min_size: usize,
+}
+
+impl Default for FileCarvingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: PathBuf::from("/var/lib/rdb-tunnel/files"),
+            store_files: false,
+            http_ports: vec![80, 8080],
+            ftp_ports: vec![21],
+            mime_types: Vec::new(),
+            min_size: 1,
+        }
+    }
+}
+
+// 書き込むパケットのQoSの設定
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QosConfig {
+    // DSCPの書き換え規則。一致した規則のうち優先度が最も高いものを適用する (SIGHUPで再読み込み可能)
+    pub remark: Vec<RemarkRule>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemarkRule {
+    // ファイアウォールの規則と同じ条件。指定しない場合は全てのIPパケットに一致する
+    #[serde(default, rename = "match")]
+    pub filter: Option<Filter>,
+    // 指定した場合は、このDSCPのパケットのみ書き換える
+    #[serde(default)]
+    pub from_dscp: Option<u8>,
+    // 書き換え後のDSCP
+    pub dscp: u8,
+    #[serde(default)]
+    pub priority: u8,
+}
+
+// 送信元NAT (NAT44) の設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NatConfig {
+    pub enabled: bool,
+    // 送信元を自ノードのアドレスに書き換えるクライアントのネットワーク (IPv4)。宛先もこの範囲のパケットは書き換えない
+    pub sources: Vec<IpNetwork>,
+    // 変換後の送信元ポート (ICMPエコーの場合は識別子) に使用する範囲 [開始, 終了]
+    pub ports: [u16; 2],
+    // 通信がない変換を削除するまでの時間 (TCP / UDP・ICMP)
+    #[serde(with = "humantime_serde")]
+    pub tcp_timeout: Duration,
+    #[serde(with = "humantime_serde")]
+    pub udp_timeout: Duration,
+    // 静的ポート転送 (DNAT) の規則。enabled に関係なく適用する
+    pub forward: Vec<ForwardRule>,
+}
+
+impl Default for NatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sources: Vec::new(),
+            ports: [40000, 59999],
+            tcp_timeout: Duration::from_secs(2 * 60 * 60),
+            udp_timeout: Duration::from_secs(60),
+            forward: Vec::new(),
+        }
+    }
+}
+
+// ポート転送の対象のプロトコル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+// 対向ノードから listen 宛に届いたパケットの宛先を target に書き換えて注入し、target からの応答の送信元を listen に戻す
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardRule {
+    #[serde(default)]
+    pub protocol: ForwardProtocol,
+    // 転送するアドレスとポート (IPv4)。アドレスが 0.0.0.0 の場合は宛先のアドレスを問わない
+    pub listen: SocketAddr,
+    // 転送先のアドレスとポート (IPv4)
+    pub target: SocketAddr,
+}
+
+// 対向ノードのサブネットへの経路の自動設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutesConfig {
+    // 対向ノードが広告したサブネットへの経路を仮想NICに設定する (終了時に削除する)
+    pub enabled: bool,
+    // 自ノードの先にあるサブネット。対向ノードはこのサブネットへの経路を自ノード経由で設定する
+    pub advertise: Vec<IpNetwork>,
+    // 広告の更新と経路の同期の間隔
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+    // この時間広告を更新していないノードのサブネットへの経路は削除する
+    #[serde(with = "humantime_serde")]
+    pub expire: Duration,
+}
+
+impl Default for RoutesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            advertise: Vec::new(),
+            interval: Duration::from_secs(30),
+            expire: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+// 送信元アドレス・fwmark・cgroupで選択したトラフィックを仮想NICへ流すポリシールーティングの設定 (Linuxのみ)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyRoutingConfig {
+    pub enabled: bool,
+    // 仮想NICへのデフォルト経路を設定する経路表のID
+    pub table: u32,
+    // 規則の優先度 (小さいほど先に評価される。mainの32766より小さくする)
+    pub priority: u32,
+    // デフォルト経路のゲートウェイ (TAPでは出口となる対向ノードのトンネルのアドレス)。
+    // 指定した場合はそのアドレスファミリーのみ、未指定の場合はIPv4/IPv6の両方を仮想NICに直接送る
+    pub gateway: Option<IpAddr>,
+    // この送信元アドレスのパケットを仮想NICへ流す
+    pub sources: Vec<IpNetwork>,
+    // このfwmarkが付いたパケットを仮想NICへ流す
+    pub fwmark: Option<u32>,
+    // このcgroup (v2) のプロセスが送信するパケットにfwmarkを付ける (nftを使用する。例: "system.slice/app.service")
+    pub cgroups: Vec<String>,
+}
+
+impl Default for PolicyRoutingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            table: 100,
+            priority: 1000,
+            gateway: None,
+            sources: Vec::new(),
+            fwmark: None,
+            cgroups: Vec::new(),
+        }
+    }
+}
+
+// 仮想NIC (TAP) と物理NICをLinuxブリッジで接続し、L2セグメントをトンネルで延長する設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BridgeConfig {
+    pub enabled: bool,
+    // ブリッジの名前。存在しない場合は作成し、終了時に削除する
+    pub name: String,
+    // ブリッジに接続する物理NIC
+    pub interface: String,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            name: "br-rdb".to_string(),
+            interface: String::new(),
+        }
+    }
+}
+
+// 失敗したタスクの再起動の方針
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartPolicy {
+    // falseの場合は再起動せず、アプリケーションを終了する
+    pub restart: bool,
+    // 連続して再起動する回数の上限。超えた場合はアプリケーションを終了する
+    pub max_restarts: u32,
+    // 最初の再起動までの待機時間。失敗が続くごとに倍にする
+    #[serde(with = "humantime_serde")]
+    pub initial_backoff: Duration,
+    // 再起動までの待機時間の上限
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+    // この時間以上動作した後に失敗した場合は、連続した再起動の回数をリセットする
+    #[serde(with = "humantime_serde")]
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            restart: true,
+            max_restarts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            reset_after: Duration::from_secs(300),
+        }
+    }
+}
+
+// データベースの一時的なエラー (直列化の失敗・接続の切断・文のタイムアウトなど) を再試行する方針
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    // 最初の試行を含む最大試行回数 (1で再試行しない)
+    pub max_attempts: u32,
+    // 最初の再試行までの待機時間の上限。失敗が続くごとに倍にし、0からその値の間で無作為に待機する
+    #[serde(with = "humantime_serde")]
+    pub initial_backoff: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+// パイプラインのタスク (ポーリング・ライター・分析) ごとの再起動の方針
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub polling: RestartPolicy,
+    pub writer: RestartPolicy,
+    pub analysis: RestartPolicy,
+}
+
+// 統計情報の要約ログの設定
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    // 送受信のpps・Mbps、書き込み待ち、DBのレイテンシ、破棄数、アラート数を1行でログへ出力する
+    pub summary: bool,
+    #[serde(with = "humantime_serde")]
+    pub summary_interval: Duration,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            summary: true,
+            summary_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+// PostgreSQLのsslmodeに相当する接続モード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslMode {
+    // TLSを使用しない
+    Disable,
+    // サーバーが対応していればTLSを使用する (証明書は検証しない)
+    Prefer,
+    // TLSを必須とする (証明書は検証しない)
+    Require,
+    // TLSを必須とし、CA証明書による検証を行う (ホスト名は検証しない)
+    VerifyCa,
+    // TLSを必須とし、CA証明書とホスト名の両方を検証する
+    VerifyFull,
+}
+
+impl FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disable" => Ok(SslMode::Disable),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-ca" => Ok(SslMode::VerifyCa),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            other => Err(format!("未対応のsslmodeです: {}", other)),
+        }
+    }
+}
+
+// TLS接続の設定
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub ssl_mode: SslMode,
+    // サーバー証明書を検証するCA証明書 (PEM)。未指定の場合はwebpkiのルート証明書を使用
+    pub root_cert: Option<PathBuf>,
+    // クライアント証明書 (PEM)
+    pub client_cert: Option<PathBuf>,
+    // クライアント証明書の秘密鍵 (PEM)
+    pub client_key: Option<PathBuf>,
+}
+
+// 読み取り専用レプリカの接続先。認証情報とTLS設定はプライマリと共通
+#[derive(Debug, Clone)]
+pub struct ReplicaConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+// コネクションプール (bb8) の設定
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout: Duration,
+    pub max_lifetime: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    // bb8のデフォルト値と同じ
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            min_idle: None,
+            connection_timeout: Duration::from_secs(30),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            idle_timeout: Some(Duration::from_secs(10 * 60)),
+        }
+    }
+}
+
+impl PoolConfig {
+    fn from_env() -> Result<Self, InitProcessError> {
+        let default = PoolConfig::default();
+        // 0を指定した場合は無制限として扱う
+        let optional_duration = |key: &str, default: Option<Duration>| -> Result<Option<Duration>, InitProcessError> {
+            Ok(match parse_var::<u64>(key)? {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => default,
+            })
+        };
+
+        Ok(Self {
+            max_size: parse_var("TIMESCALE_DB_POOL_MAX_SIZE")?.unwrap_or(default.max_size),
+            min_idle: parse_var("TIMESCALE_DB_POOL_MIN_IDLE")?.or(default.min_idle),
+            connection_timeout: parse_var("TIMESCALE_DB_POOL_CONNECTION_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.connection_timeout),
+            max_lifetime: optional_duration("TIMESCALE_DB_POOL_MAX_LIFETIME_SECS", default.max_lifetime)?,
+            idle_timeout: optional_duration("TIMESCALE_DB_POOL_IDLE_TIMEOUT_SECS", default.idle_timeout)?,
+        })
+    }
+}
+
+// データベース接続の設定
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub database: String,
+    pub tls: TlsConfig,
+    pub pool: PoolConfig,
+    // 設定されている場合、パケットのポーリングはレプリカから行い、書き込みはプライマリに行う
+    pub replica: Option<ReplicaConfig>,
+}
+
+impl DatabaseConfig {
+    // 接続先は環境変数から、ユーザー名とパスワードはシークレットプロバイダから取得する
+    pub async fn load(secrets: &SecretProviderChain) -> Result<Self, InitProcessError> {
+        let tls = TlsConfig {
+            ssl_mode: optional_var("TIMESCALE_DB_SSLMODE")
+                .map(|mode| mode.parse::<SslMode>())
+                .transpose()
+                .map_err(InitProcessError::EnvVarParseError)?
+                .unwrap_or(SslMode::Disable),
+            root_cert: optional_var("TIMESCALE_DB_SSLROOTCERT").map(PathBuf::from),
+            client_cert: optional_var("TIMESCALE_DB_SSLCERT").map(PathBuf::from),
+            client_key: optional_var("TIMESCALE_DB_SSLKEY").map(PathBuf::from),
+        };
+
+        if tls.client_cert.is_some() != tls.client_key.is_some() {
+            return Err(InitProcessError::EnvVarError(
+                "TIMESCALE_DB_SSLCERTとTIMESCALE_DB_SSLKEYは両方指定する必要があります".to_string(),
+            ));
+        }
+
+        let port = required_var("TIMESCALE_DB_PORT")?
+            .parse::<u16>()
+            .map_err(|e| InitProcessError::EnvVarParseError(e.to_string()))?;
+
+        let replica = match optional_var("TIMESCALE_DB_REPLICA_HOST") {
+            Some(host) => Some(ReplicaConfig {
+                host,
+                port: optional_var("TIMESCALE_DB_REPLICA_PORT")
+                    .map(|port| port.parse::<u16>())
+                    .transpose()
+                    .map_err(|e| InitProcessError::EnvVarParseError(e.to_string()))?
+                    .unwrap_or(port),
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            host: required_var("TIMESCALE_DB_HOST")?,
+            port,
+            user: secrets.require("TIMESCALE_DB_USER").await?,
+            password: secrets.require("TIMESCALE_DB_PASSWORD").await?,
+            database: required_var("TIMESCALE_DB_DATABASE")?,
+            tls,
+            pool: PoolConfig::from_env()?,
+            replica,
+        })
+    }
+}
+
+fn required_var(key: &str) -> Result<String, InitProcessError> {
+    dotenv::var(key).map_err(|e| InitProcessError::EnvVarError(format!("{}: {}", key, e)))
+}
+
+fn optional_var(key: &str) -> Option<String> {
+    dotenv::var(key).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_var<T: FromStr>(key: &str) -> Result<Option<T>, InitProcessError>
+where
+    T::Err: std::fmt::Display,
+{
+    optional_var(key)
+        .map(|value| value.parse::<T>())
+        .transpose()
+        .map_err(|e| InitProcessError::EnvVarParseError(format!("{}: {}", key, e)))
+}
@@ -0,0 +1,182 @@
+use crate::api::DEFAULT_PEER_WINDOW_SECS;
+use crate::config::ControlConfig;
+use crate::firewall::Chain;
+use crate::health;
+use crate::http_server::AppState;
+use crate::management;
+use crate::metrics;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+// ソケットファイルの権限 (所有者とグループのみ接続可能)
+const SOCKET_MODE: u32 = 0o660;
+// ソケットを作成する一時ディレクトリの権限 (所有者のみアクセス可能)
+const PRIVATE_DIR_MODE: u32 = 0o700;
+
+// 1行に1コマンドを送信し、1行のJSONで応答する
+//   status               タスクとヘルスチェックの状態
+//   rules [input|output] ファイアウォールルール一覧 (既定はoutput)
+//   peers [window_secs]  直近に通信したピア一覧
+//   peer-traffic         起動時からのピアごとの送受信量
+//   stats                統計情報
+#[derive(Debug, Serialize)]
+struct Reply {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Reply {
+    fn ok(data: Value) -> Self {
+        Self { ok: true, data: Some(data), error: None }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self { ok: false, data: None, error: Some(message.into()) }
+    }
+}
+
+pub async fn start_control_socket(config: ControlConfig, state: AppState) -> Result<(), std::io::Error> {
+    if let Some(dir) = config.socket.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    // 前回の異常終了で残ったソケットファイルを削除する
+    match tokio::fs::remove_file(&config.socket).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let listener = bind_private(&config.socket).await?;
+    info!("制御ソケットを開始しました: {}", config.socket.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, state).await {
+                debug!("制御ソケットの接続でエラーが発生しました: {}", e);
+            }
+        });
+    }
+}
+
+// 所有者のみアクセスできる一時ディレクトリの中で作成して権限を設定してから移動し、
+// 権限を設定する前のソケットに他のユーザーが接続できないようにする
+async fn bind_private(socket: &Path) -> Result<UnixListener, std::io::Error> {
+    let name = socket.file_name().ok_or_else(|| std::io::Error::other("ソケットのパスにファイル名がありません"))?;
+    let private_dir = socket.with_file_name(format!(".{}.{}", name.to_string_lossy(), std::process::id()));
+    match tokio::fs::remove_dir_all(&private_dir).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    tokio::fs::DirBuilder::new().mode(PRIVATE_DIR_MODE).create(&private_dir).await?;
+
+    let private_socket = private_dir.join(name);
+    let bound = async {
+        let listener = UnixListener::bind(&private_socket)?;
+        tokio::fs::set_permissions(&private_socket, Permissions::from_mode(SOCKET_MODE)).await?;
+        tokio::fs::rename(&private_socket, socket).await?;
+        Ok(listener)
+    }
+    .await;
+    tokio::fs::remove_dir_all(&private_dir).await?;
+    bound
+}
+
+async fn handle_connection(stream: UnixStream, state: AppState) -> Result<(), std::io::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let reply = execute(line, &state).await;
+        let mut response = serde_json::to_vec(&reply).unwrap_or_else(|e| {
+            warn!("制御ソケットの応答のシリアライズに失敗しました: {}", e);
+            br#"{"ok":false,"error":"serialization failed"}"#.to_vec()
+        });
+        response.push(b'\n');
+        writer.write_all(&response).await?;
+    }
+    Ok(())
+}
+
+async fn execute(line: &str, state: &AppState) -> Reply {
+    let mut args = line.split_whitespace();
+    let command = args.next().unwrap_or_default();
+    let args = args.collect::<Vec<_>>();
+
+    match (command, args.as_slice()) {
+        ("status", []) => {
+            let report = health::readiness(&state.task_state, &state.tap_name).await;
+            to_reply(&report)
+        }
+        ("rules", args) if args.len() <= 1 => {
+            let chain = match args.first().copied() {
+                None | Some("output") => Chain::Output,
+                Some("input") => Chain::Input,
+                Some(other) => return Reply::error(format!("invalid chain: {}", other)),
+            };
+            let (policy, rules) = management::firewall_rules(chain);
+            Reply::ok(json!({ "chain": chain, "policy": policy, "rules": rules }))
+        }
+        ("peers", args) if args.len() <= 1 => {
+            let window_secs = match args.first().map(|arg| arg.parse::<u64>()) {
+                None => DEFAULT_PEER_WINDOW_SECS,
+                Some(Ok(secs)) => secs,
+                Some(Err(_)) => return Reply::error(format!("invalid window: {}", args[0])),
+            };
+            match management::list_peers(state.database.as_deref(), state.tunnel_network, Duration::from_secs(window_secs)).await {
+                Ok(peers) => to_reply(&peers),
+                Err(e) => Reply::error(e.to_string()),
+            }
+        }
+        ("peer-traffic", []) => to_reply(&management::peer_traffic(state.tunnel_network)),
+        ("stats", []) => to_reply(&metrics::snapshot(state.database.as_deref())),
+        _ => Reply::error(format!("unknown command: {}", line)),
+    }
+}
+
+fn to_reply<T: Serialize>(value: &T) -> Reply {
+    match serde_json::to_value(value) {
+        Ok(data) => Reply::ok(data),
+        Err(e) => Reply::error(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn binds_socket_with_restricted_mode() {
+        let dir = std::env::temp_dir().join(format!("rdb-tunnel-control-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let socket = dir.join("control.sock");
+
+        let _listener = bind_private(&socket).await.unwrap();
+        let mode = tokio::fs::metadata(&socket).await.unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, SOCKET_MODE);
+        // 一時ディレクトリは残さない
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name());
+        }
+        assert_eq!(names, ["control.sock"]);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
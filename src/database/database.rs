@@ -1,58 +1,131 @@
+use crate::config::{DatabaseConfig, PoolConfig};
 use crate::database::error::DbError;
+use crate::database::tls::{make_tls_connector, postgres_ssl_mode};
+use crate::stats::PoolStats;
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
-use std::sync::OnceLock;
-use tokio_postgres::NoTls;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use tracing::{error, info};
 
-pub static DATABASE: OnceLock<Database> = OnceLock::new();
+pub type PgPool = Pool<PostgresConnectionManager<MakeRustlsConnect>>;
 
 pub struct Database {
-    pub pool: Pool<PostgresConnectionManager<NoTls>>,
+    pub pool: PgPool,
+    // 読み取り専用レプリカ。未設定の場合はプライマリから読み取る
+    pub replica_pool: Option<PgPool>,
+    // 接続設定 (論理レプリケーションの接続に使用する)
+    #[cfg_attr(not(feature = "replication"), allow(dead_code))]
+    pub config: DatabaseConfig,
 }
 
 impl Database {
-    pub async fn new(connection_string: &str) -> Result<Self, DbError> {
-        let manager = PostgresConnectionManager::new_from_stringlike(connection_string, NoTls)?;
-        let pool = Pool::builder().build(manager).await?;
-        Ok(Self { pool })
+    pub async fn new(
+        pg_config: tokio_postgres::Config,
+        tls: MakeRustlsConnect,
+        config: &DatabaseConfig,
+    ) -> Result<Self, DbError> {
+        let pool = build_pool(pg_config, tls, &config.pool).await?;
+        Ok(Self { pool, replica_pool: None, config: config.clone() })
     }
 
-    pub async fn connect(
-        host: &str,
-        port: u16,
-        user: &str,
-        password: &str,
-        database: &str,
-    ) -> Result<(), DbError> {
-        let connection_string = format!(
-            "postgres://{}:{}@{}:{}/{}",
-            user, password, host, port, database
-        );
-        let db = Database::new(&connection_string).await?;
-        DATABASE.set(db).map_err(|_| DbError::Initialization)?;
+    // 接続プールを作成して接続を確認する。返したハンドルを使用する処理へ渡す
+    pub async fn connect(config: &DatabaseConfig) -> Result<Arc<Database>, DbError> {
+        let mut pg_config = tokio_postgres::Config::new();
+        pg_config
+            .host(&config.host)
+            .port(config.port)
+            .user(&config.user)
+            .password(&config.password)
+            .dbname(&config.database)
+            .ssl_mode(postgres_ssl_mode(config.tls.ssl_mode));
+        let tls = make_tls_connector(&config.tls)?;
+
+        let mut db = Database::new(pg_config.clone(), tls.clone(), config).await?;
+
+        if let Some(replica) = &config.replica {
+            let mut replica_config = pg_config.clone();
+            replica_config.host(&replica.host).port(replica.port);
+            // レプリカへの接続テスト
+            let (client, connection) = replica_config.connect(tls.clone()).await?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    error!("レプリカとの接続でエラーが発生しました: {}", e);
+                }
+            });
+            drop(client);
+
+            db.replica_pool = Some(build_pool(replica_config, tls.clone(), &config.pool).await?);
+            info!("読み取りレプリカを使用します: {}:{}", replica.host, replica.port);
+        }
 
         // 接続テスト
-        let (client, connection) = tokio_postgres::connect(&connection_string, NoTls).await?;
+        let (client, connection) = pg_config.connect(tls).await?;
 
         tokio::spawn(async move {
             if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
+                error!("データベースとの接続でエラーが発生しました: {}", e);
             }
         });
 
         // クライアントをドロップして接続を解放
         drop(client);
 
-        Ok(())
+        Ok(Arc::new(db))
+    }
+
+    // 読み取りに使用するプール (レプリカがあればレプリカ)
+    pub fn read_pool(&self) -> &PgPool {
+        self.replica_pool.as_ref().unwrap_or(&self.pool)
+    }
+
+    pub fn has_replica(&self) -> bool {
+        self.replica_pool.is_some()
+    }
+
+    // プールの使用状況
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        let mut stats = vec![PoolStats::from_pool("primary", &self.pool)];
+        if let Some(replica) = &self.replica_pool {
+            stats.push(PoolStats::from_pool("replica", replica));
+        }
+        stats
     }
 
-    // note: プーリングしているため、明示的な切断は不要
-    /*pub async fn disconnect() -> Result<(), DbError> {
-        DATABASE.get().unwrap().disconnect().await?;
-        Ok(())
-    }*/
+    // レプリカの適用遅延。レプリカ未設定、またはプライマリに接続している場合はNone
+    pub async fn replica_lag(&self) -> Result<Option<Duration>, DbError> {
+        let Some(pool) = &self.replica_pool else {
+            return Ok(None);
+        };
 
-    pub fn get_database() -> &'static Database {
-        DATABASE.get().expect("データベースが初期化されていません")
+        let client = pool.get().await?;
+        let row = client
+            .query_one(
+                "SELECT CASE WHEN pg_is_in_recovery()
+                    THEN EXTRACT(EPOCH FROM (now() - pg_last_xact_replay_timestamp()))::float8
+                END AS lag_seconds",
+                &[],
+            )
+            .await?;
+        let lag: Option<f64> = row.get("lag_seconds");
+        Ok(lag.map(|seconds| Duration::from_secs_f64(seconds.max(0.0))))
     }
-}
\ No newline at end of file
+}
+
+async fn build_pool(
+    pg_config: tokio_postgres::Config,
+    tls: MakeRustlsConnect,
+    pool_config: &PoolConfig,
+) -> Result<PgPool, DbError> {
+    let manager = PostgresConnectionManager::new(pg_config, tls);
+    let pool = Pool::builder()
+        .max_size(pool_config.max_size)
+        .min_idle(pool_config.min_idle)
+        .connection_timeout(pool_config.connection_timeout)
+        .max_lifetime(pool_config.max_lifetime)
+        .idle_timeout(pool_config.idle_timeout)
+        .build(manager)
+        .await?;
+    Ok(pool)
+}
@@ -1,3 +1,4 @@
+use crate::error::Retryable;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,9 +12,33 @@ pub enum DbError {
     #[error("JSON serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
-    #[error("Database initialization error")]
-    Initialization,
+    #[error("TLS設定エラー: {0}")]
+    Tls(String),
+
+    #[error("Schema mismatch: {0}")]
+    Schema(String),
+
+    #[cfg(feature = "replication")]
+    #[error("Replication error: {0}")]
+    Replication(#[from] pgwire_replication::PgWireError),
 
     #[error("Other error: {0}")]
     Other(String),
-}
\ No newline at end of file
+}
+
+impl Retryable for DbError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            // SQLSTATEがない場合は接続やI/Oのエラー。接続断 (08)・直列化の失敗 (40)・リソース不足 (53)・
+            // 文のタイムアウト (57014)・管理者による停止 (57P) は再試行で回復しうるが、構文や制約違反は失敗し続ける
+            DbError::Postgres(e) => e.code().is_none_or(|code| {
+                let code = code.code();
+                ["08", "40", "53", "57014", "57P"].iter().any(|class| code.starts_with(class))
+            }),
+            DbError::Pool(_) => true,
+            #[cfg(feature = "replication")]
+            DbError::Replication(_) => true,
+            DbError::Serialization(_) | DbError::Tls(_) | DbError::Schema(_) | DbError::Other(_) => false,
+        }
+    }
+}
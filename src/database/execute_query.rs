@@ -1,5 +1,6 @@
 use crate::database::database::Database;
 use crate::database::error::DbError;
+use crate::retry::retry;
 use async_trait::async_trait;
 use tokio_postgres::Row;
 
@@ -8,21 +9,41 @@ pub trait ExecuteQuery {
     async fn execute(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<u64, DbError>;
 
     async fn query(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<Vec<Row>, DbError>;
+
+    // 読み取りレプリカに対してクエリを実行する (レプリカ未設定の場合はプライマリ)
+    async fn query_replica(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<Vec<Row>, DbError>;
 }
 
+// 一時的なエラー (接続の切断・直列化の失敗・文のタイムアウトなど) は [retry] の方針で再試行する
 #[async_trait]
 impl ExecuteQuery for Database {
     async fn execute(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<u64, DbError> {
-        let client = self.pool.get().await?;
-        let stmt = client.prepare(query).await?;
-        let result = client.execute(&stmt, params).await?;
-        Ok(result)
+        retry("query", || async {
+            let client = self.pool.get().await?;
+            let stmt = client.prepare(query).await?;
+            let result = client.execute(&stmt, params).await?;
+            Ok(result)
+        })
+        .await
     }
 
     async fn query(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<Vec<Row>, DbError> {
-        let client = self.pool.get().await?;
-        let stmt = client.prepare(query).await?;
-        let rows = client.query(&stmt, params).await?;
-        Ok(rows)
+        retry("query", || async {
+            let client = self.pool.get().await?;
+            let stmt = client.prepare(query).await?;
+            let rows = client.query(&stmt, params).await?;
+            Ok(rows)
+        })
+        .await
+    }
+
+    async fn query_replica(&self, query: &str, params: &[&(dyn tokio_postgres::types::ToSql + Sync)]) -> Result<Vec<Row>, DbError> {
+        retry("query_replica", || async {
+            let client = self.read_pool().get().await?;
+            let stmt = client.prepare(query).await?;
+            let rows = client.query(&stmt, params).await?;
+            Ok(rows)
+        })
+        .await
     }
 }
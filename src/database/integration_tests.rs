@@ -0,0 +1,171 @@
+// TimescaleDBのコンテナに対して、マイグレーション・一括書き込み・ポーリング・保持期間による削除を通して確認する。
+// Dockerが必要なため通常のテストでは実行せず、`cargo test -- --ignored` で実行する
+use crate::config::{DatabaseConfig, MigrationsConfig, PoolConfig, SslMode, TlsConfig};
+use crate::database::database::Database;
+use crate::database::execute_query::ExecuteQuery;
+use crate::db_read::PacketPoller;
+use crate::db_write::{parse_and_analyze_packet, PacketData};
+use crate::test_support::{udp_frame, NODE_A, NODE_B};
+use crate::transport::timescale::TimescaleTransport;
+use crate::transport::PacketTransport;
+use chrono::Utc;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use testcontainers_modules::postgres::Postgres;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+use testcontainers_modules::testcontainers::{ContainerAsync, ImageExt};
+use tokio::sync::mpsc;
+
+const TIMESCALE_IMAGE: &str = "timescale/timescaledb";
+const TIMESCALE_TAG: &str = "2.17.2-pg16";
+
+// 一括書き込みが複数のチャンク (1000行単位) に分かれる件数
+const UNICAST_PACKETS: usize = 1500;
+
+// コンテナを起動して接続し、マイグレーションを適用する
+async fn start_database() -> (ContainerAsync<Postgres>, Arc<Database>) {
+    let container = Postgres::default()
+        .with_name(TIMESCALE_IMAGE)
+        .with_tag(TIMESCALE_TAG)
+        .start()
+        .await
+        .expect("TimescaleDBのコンテナを起動できません");
+    let host = container.get_host().await.expect("コンテナのホストを取得できません");
+    let port = container.get_host_port_ipv4(5432).await.expect("コンテナのポートを取得できません");
+
+    let config = DatabaseConfig {
+        host: host.to_string(),
+        port,
+        user: "postgres".to_string(),
+        password: "postgres".to_string(),
+        database: "postgres".to_string(),
+        tls: TlsConfig { ssl_mode: SslMode::Disable, root_cert: None, client_cert: None, client_key: None },
+        pool: PoolConfig::default(),
+        replica: None,
+    };
+    let db = Database::connect(&config).await.expect("データベースに接続できません");
+    db.run_migrations(&MigrationsConfig::default()).await.expect("マイグレーションに失敗しました");
+    (container, db)
+}
+
+async fn packet(src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) -> PacketData {
+    let mut packet = parse_and_analyze_packet(udp_frame(src, dst, 5000, payload).into())
+        .await
+        .expect("フレームを解析できません");
+    packet.interface = "test0".to_string();
+    packet
+}
+
+async fn count(db: &Database, query: &str) -> i64 {
+    let rows = db.query(query, &[]).await.expect("件数を取得できません");
+    rows[0].get(0)
+}
+
+#[tokio::test]
+#[ignore = "Dockerが必要です"]
+async fn timescale_end_to_end() {
+    let (_container, db) = start_database().await;
+    let transport = TimescaleTransport::new(db.clone());
+
+    // マイグレーション: スキーマが一致し、packetsがハイパーテーブルになっている
+    db.verify_schema().await.expect("スキーマが一致しません");
+    assert!(db.packets_is_hypertable().await.unwrap());
+    // 再適用しても変更されない
+    db.run_migrations(&MigrationsConfig::default()).await.unwrap();
+    assert_eq!(count(&db, "SELECT count(*) FROM schema_migrations").await, 13);
+
+    // 一括書き込み: チャンクに分けて1つのトランザクションで挿入する
+    let mut packets = Vec::new();
+    for i in 0..UNICAST_PACKETS {
+        packets.push(packet(NODE_A, NODE_B, i.to_string().as_bytes()).await);
+    }
+    packets.push(packet(NODE_B, NODE_A, b"reply").await);
+    packets.push(packet(NODE_A, Ipv4Addr::BROADCAST, b"discover").await);
+    // 同一時刻になると注入の順序が定まらないため、書き込み順に1マイクロ秒ずつずらす
+    let start = Utc::now() - chrono::Duration::seconds(1);
+    for (i, packet) in packets.iter_mut().enumerate() {
+        packet.timestamp = start + chrono::Duration::microseconds(i as i64);
+    }
+    transport.publish(&packets).await.expect("書き込みに失敗しました");
+    assert_eq!(count(&db, "SELECT count(*) FROM packets").await, packets.len() as i64);
+
+    // ポーリング: 自分宛とブロードキャストのみを書き込み順に注入し、処理済みとして記録する
+    let (sender, mut injected) = mpsc::unbounded_channel();
+    let poller = PacketPoller::with_channel(IpAddr::V4(NODE_B), sender);
+    poller.poll_and_send_packets(&db).await.expect("ポーリングに失敗しました");
+
+    let mut received = Vec::new();
+    while let Ok(packet) = injected.try_recv() {
+        received.push(packet);
+    }
+    assert_eq!(received.len(), UNICAST_PACKETS + 1);
+    assert!(received.iter().all(|packet| poller.is_addressed_to_node(packet)));
+    let unicast: Vec<_> = received.iter().filter(|packet| packet.dst_ip == IpAddr::V4(NODE_B)).collect();
+    for (i, packet) in unicast.iter().enumerate() {
+        assert_eq!(packet.data, i.to_string().as_bytes());
+    }
+    assert_eq!(
+        count(&db, "SELECT count(*) FROM packet_deliveries WHERE node = '10.0.0.2'").await,
+        (UNICAST_PACKETS + 1) as i64
+    );
+    assert_eq!(count(&db, "SELECT count(*) FROM cursors WHERE node = '10.0.0.2'").await, 1);
+
+    // 処理済みのパケットは再取得しない
+    poller.poll_and_send_packets(&db).await.unwrap();
+    assert!(injected.try_recv().is_err());
+
+    // 保持期間: 境界より古いパケットと処理済みの記録を削除する
+    let mut expired = packet(NODE_A, NODE_B, b"expired").await;
+    expired.timestamp = Utc::now() - chrono::Duration::days(3);
+    transport.publish(&[expired]).await.unwrap();
+
+    let before = Utc::now() - chrono::Duration::days(1);
+    let summary = db.prune_packets(before).await.expect("削除に失敗しました");
+    assert_eq!(summary.dropped_chunks, 1);
+    assert_eq!(count(&db, "SELECT count(*) FROM packets WHERE timestamp < now() - INTERVAL '1 day'").await, 0);
+    assert_eq!(count(&db, "SELECT count(*) FROM packets").await, packets.len() as i64);
+
+    let summary = db.prune_packets(Utc::now() + chrono::Duration::seconds(1)).await.unwrap();
+    assert_eq!(summary.dropped_chunks + summary.deleted, packets.len() as u64);
+    assert_eq!(count(&db, "SELECT count(*) FROM packets").await, 0);
+    assert_eq!(count(&db, "SELECT count(*) FROM packet_deliveries").await, 0);
+}
+
+#[tokio::test]
+#[ignore = "Dockerが必要です"]
+async fn deletes_only_fully_delivered_packets() {
+    let (_container, db) = start_database().await;
+    let transport = TimescaleTransport::new(db.clone());
+    let node_c = Ipv4Addr::new(10, 0, 0, 3);
+    for node in [NODE_A, NODE_B, node_c] {
+        db.execute("INSERT INTO cursors (node, last_timestamp) VALUES ($1::text::inet, now())", &[&node.to_string()])
+            .await
+            .unwrap();
+    }
+
+    transport
+        .publish(&[
+            packet(NODE_A, NODE_B, b"unicast").await,
+            packet(NODE_A, Ipv4Addr::BROADCAST, b"partial").await,
+            packet(NODE_A, Ipv4Addr::BROADCAST, b"complete").await,
+        ])
+        .await
+        .unwrap();
+    let ids = db.query("SELECT id FROM packets ORDER BY id", &[]).await.unwrap();
+    let ids = ids.iter().map(|row| row.get::<_, i64>(0)).collect::<Vec<_>>();
+    // ブロードキャストの1件目は NODE_C が処理していない
+    for (node, id) in [(NODE_B, ids[0]), (NODE_B, ids[1]), (NODE_B, ids[2]), (node_c, ids[2])] {
+        db.execute(
+            "INSERT INTO packet_deliveries (node, packet_id) VALUES ($1::text::inet, $2)",
+            &[&node.to_string(), &id],
+        )
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(db.delete_delivered_packets().await.expect("削除に失敗しました"), 2);
+    let remaining = db.query("SELECT id FROM packets", &[]).await.unwrap();
+    assert_eq!(remaining.iter().map(|row| row.get::<_, i64>(0)).collect::<Vec<_>>(), [ids[1]]);
+    // 残したパケットの処理済みの記録は削除しない
+    assert_eq!(count(&db, "SELECT count(*) FROM packet_deliveries").await, 1);
+}
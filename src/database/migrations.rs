@@ -0,0 +1,130 @@
+use crate::config::MigrationsConfig;
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::execute_query::ExecuteQuery;
+use tracing::info;
+
+// 適用順に並べたマイグレーション (バージョン, 名前, SQL)
+const MIGRATIONS: &[(i64, &str, &str)] = &[
+    (1, "create_packets", include_str!("../../resource/migrations/0001_create_packets.sql")),
+    (2, "packet_views", include_str!("../../resource/migrations/0002_packet_views.sql")),
+    (3, "packet_deliveries", include_str!("../../resource/migrations/0003_packet_deliveries.sql")),
+    (4, "cursors", include_str!("../../resource/migrations/0004_cursors.sql")),
+    (5, "sequence_polling", include_str!("../../resource/migrations/0005_sequence_polling.sql")),
+    (6, "packet_seq", include_str!("../../resource/migrations/0006_packet_seq.sql")),
+    (7, "packet_dscp", include_str!("../../resource/migrations/0007_packet_dscp.sql")),
+    (8, "packet_codec", include_str!("../../resource/migrations/0008_packet_codec.sql")),
+    (9, "packet_chunks", include_str!("../../resource/migrations/0009_packet_chunks.sql")),
+    (10, "peers", include_str!("../../resource/migrations/0010_peers.sql")),
+    (11, "packet_encapsulation", include_str!("../../resource/migrations/0011_packet_encapsulation.sql")),
+    (12, "packet_vni", include_str!("../../resource/migrations/0012_packet_vni.sql")),
+    (13, "packet_views_columns", include_str!("../../resource/migrations/0013_packet_views_columns.sql")),
+];
+
+// 複数のノードが同時に起動した場合にマイグレーションを直列化するためのロックキー
+const MIGRATION_LOCK_KEY: i64 = 0x7264_6274_756e;
+
+impl Database {
+    // 未適用のマイグレーションを適用し、TimescaleDBのポリシーを設定する
+    pub async fn run_migrations(&self, config: &MigrationsConfig) -> Result<(), DbError> {
+        let mut client = self.pool.get().await?;
+        let transaction = client.transaction().await?;
+        transaction
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])
+            .await?;
+        transaction
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version    BIGINT PRIMARY KEY,
+                    name       TEXT        NOT NULL,
+                    applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                )",
+            )
+            .await?;
+
+        let applied: Vec<i64> = transaction
+            .query("SELECT version FROM schema_migrations", &[])
+            .await?
+            .iter()
+            .map(|row| row.get("version"))
+            .collect();
+
+        for (version, name, sql) in MIGRATIONS {
+            if applied.contains(version) {
+                continue;
+            }
+            info!("マイグレーションを適用します: {:04}_{}", version, name);
+            transaction.batch_execute(sql).await?;
+            transaction
+                .execute(
+                    "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                    &[version, name],
+                )
+                .await?;
+        }
+        transaction.commit().await?;
+        drop(client);
+
+        self.configure_timescale(config).await
+    }
+
+    // チャンク間隔・圧縮・保持ポリシーを設定する。未指定の項目は変更しない
+    async fn configure_timescale(&self, config: &MigrationsConfig) -> Result<(), DbError> {
+        if !self.packets_is_hypertable().await? {
+            return Ok(());
+        }
+
+        if let Some(chunk_interval) = config.chunk_interval {
+            self.execute(
+                "SELECT set_chunk_time_interval('packets', make_interval(secs => $1))",
+                &[&chunk_interval.as_secs_f64()],
+            )
+            .await?;
+            info!("チャンク間隔を設定しました: {}", humantime::format_duration(chunk_interval));
+        }
+
+        if let Some(compress_after) = config.compress_after {
+            let rows = self
+                .query(
+                    "SELECT compression_enabled FROM timescaledb_information.hypertables
+                    WHERE hypertable_name = 'packets'",
+                    &[],
+                )
+                .await?;
+            let compression_enabled = rows.first().is_some_and(|row| row.get::<_, bool>("compression_enabled"));
+            if !compression_enabled {
+                self.execute(
+                    "ALTER TABLE packets SET (
+                        timescaledb.compress,
+                        timescaledb.compress_segmentby = 'interface',
+                        timescaledb.compress_orderby = 'timestamp DESC'
+                    )",
+                    &[],
+                )
+                .await?;
+            }
+            // 設定の変更を反映するため、既存のポリシーを置き換える
+            self.execute("SELECT remove_compression_policy('packets', if_exists => TRUE)", &[])
+                .await?;
+            self.execute(
+                "SELECT add_compression_policy('packets', compress_after => make_interval(secs => $1))",
+                &[&compress_after.as_secs_f64()],
+            )
+            .await?;
+            info!("圧縮ポリシーを設定しました: {} 経過後に圧縮", humantime::format_duration(compress_after));
+        }
+
+        if let Some(drop_after) = config.drop_after {
+            self.execute("SELECT remove_retention_policy('packets', if_exists => TRUE)", &[])
+                .await?;
+            self.execute(
+                "SELECT add_retention_policy('packets', drop_after => make_interval(secs => $1))",
+                &[&drop_after.as_secs_f64()],
+            )
+            .await?;
+            info!("保持ポリシーを設定しました: {} 経過後に削除", humantime::format_duration(drop_after));
+        }
+
+        Ok(())
+    }
+}
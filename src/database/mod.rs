@@ -1,3 +1,14 @@
+#[allow(clippy::module_inception)]
 pub mod database;
 pub mod error;
-pub mod execute_query;
\ No newline at end of file
+pub mod execute_query;
+pub mod tls;
+pub mod retention;
+pub mod samples;
+pub mod migrations;
+pub mod schema;
+pub mod types;
+#[cfg(test)]
+mod integration_tests;
+#[cfg(feature = "replication")]
+pub mod replication;
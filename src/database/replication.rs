@@ -0,0 +1,101 @@
+use crate::config::SslMode;
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::execute_query::ExecuteQuery;
+use pgwire_replication::{ReplicationConfig, TlsConfig};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::info;
+
+// パケットの挿入を配信するパブリケーション
+pub const PUBLICATION_NAME: &str = "rdb_tunnel_packets";
+
+// 受信が途絶えていても稼働状態を報告できるように、サーバーへの状態通知と待機の間隔を短くする
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Database {
+    // packetsテーブルへの挿入を配信するパブリケーションを作成する。
+    // ハイパーテーブルの場合は行がチャンクに格納されるため、全テーブルを対象にして受信側でチャンクを判別する
+    pub async fn ensure_packet_publication(&self) -> Result<(), DbError> {
+        let rows = self
+            .query("SELECT 1 FROM pg_publication WHERE pubname = $1", &[&PUBLICATION_NAME])
+            .await?;
+        if !rows.is_empty() {
+            return Ok(());
+        }
+
+        let target = if self.packets_is_hypertable().await? {
+            "ALL TABLES"
+        } else {
+            "TABLE packets"
+        };
+        self.execute(
+            &format!("CREATE PUBLICATION {} FOR {} WITH (publish = 'insert')", PUBLICATION_NAME, target),
+            &[],
+        )
+        .await?;
+        info!("パブリケーションを作成しました: {} (FOR {})", PUBLICATION_NAME, target);
+        Ok(())
+    }
+
+    // ノードごとのレプリケーションスロットを作成する。
+    // 既存のスロットは前回の確認済み位置を保持しているため、そのまま再開に使用する
+    pub async fn ensure_replication_slot(&self, slot: &str) -> Result<(), DbError> {
+        let rows = self
+            .query("SELECT 1 FROM pg_replication_slots WHERE slot_name = $1", &[&slot])
+            .await?;
+        if !rows.is_empty() {
+            info!("既存のレプリケーションスロットから再開します: {}", slot);
+            return Ok(());
+        }
+
+        self.query("SELECT pg_create_logical_replication_slot($1, 'pgoutput')", &[&slot])
+            .await?;
+        info!("レプリケーションスロットを作成しました: {}", slot);
+        Ok(())
+    }
+
+    // プライマリへのレプリケーション接続の設定 (値はバイナリ形式で受信する)
+    pub fn replication_config(&self, slot: &str) -> ReplicationConfig {
+        let config = &self.config;
+        ReplicationConfig::new(
+            config.host.as_str(),
+            config.user.as_str(),
+            config.password.as_str(),
+            config.database.as_str(),
+            slot,
+            PUBLICATION_NAME,
+        )
+        .with_port(config.port)
+        .with_tls(replication_tls(&config.tls))
+        .with_status_interval(STATUS_INTERVAL)
+        .with_wakeup_interval(STATUS_INTERVAL)
+        .with_binary(true)
+    }
+}
+
+// ノードのアドレスからスロット名を作成する (スロット名には英小文字・数字・_のみ使用できる)
+pub fn slot_name(node: IpAddr) -> String {
+    let address: String = node
+        .to_string()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    format!("rdb_tunnel_{}", address)
+}
+
+// tokio-postgres用と同じ規則でレプリケーション接続のTLS設定を作成する
+fn replication_tls(config: &crate::config::TlsConfig) -> TlsConfig {
+    let tls = match config.ssl_mode {
+        SslMode::Disable => TlsConfig::disabled(),
+        SslMode::Prefer => TlsConfig { mode: pgwire_replication::SslMode::Prefer, ..TlsConfig::disabled() },
+        // libpqと同様に、requireでもCA証明書が指定されていればverify-caとして扱う
+        SslMode::Require if config.root_cert.is_none() => TlsConfig::require(),
+        SslMode::Require | SslMode::VerifyCa => TlsConfig::verify_ca(config.root_cert.clone()),
+        SslMode::VerifyFull => TlsConfig::verify_full(config.root_cert.clone()),
+    };
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => tls.with_client_cert(cert, key),
+        _ => tls,
+    }
+}
@@ -0,0 +1,102 @@
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::execute_query::ExecuteQuery;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct PruneSummary {
+    // 削除したチャンク数 (TimescaleDBのハイパーテーブルの場合のみ)
+    pub dropped_chunks: u64,
+    // 行単位で削除したパケット数
+    pub deleted: u64,
+}
+
+impl Database {
+    // 指定時刻より古いパケットを削除し、削除した件数を返す
+    pub async fn delete_old_packets(&self, before: DateTime<Utc>) -> Result<u64, DbError> {
+        let deleted = self
+            .execute("DELETE FROM packets WHERE timestamp < $1", &[&before])
+            .await?;
+        info!("{} より古いパケットを {} 件削除しました", before, deleted);
+        Ok(deleted)
+    }
+
+    // 削除したパケットに対応する処理済みの記録を削除する
+    pub async fn delete_old_deliveries(&self, before: DateTime<Utc>) -> Result<u64, DbError> {
+        self.execute("DELETE FROM packet_deliveries WHERE delivered_at < $1", &[&before])
+            .await
+    }
+
+    // 宛先の全ての既知のノード (cursors にポーリング位置を記録したノード) が処理済みとして記録したパケットと、
+    // その処理済みの記録を削除し、削除したパケット数を返す。
+    // ブロードキャスト・マルチキャストは送信元以外の全てのノード、ユニキャストは宛先のノードを宛先とし、宛先が既知でないパケットは削除しない
+    pub async fn delete_delivered_packets(&self) -> Result<u64, DbError> {
+        let rows = self
+            .query(
+                "WITH recipients AS (
+                    SELECT p.id, c.node, EXISTS (
+                        SELECT 1 FROM packet_deliveries d WHERE d.node = c.node AND d.packet_id = p.id
+                    ) AS delivered
+                    FROM packets p
+                    JOIN cursors c ON c.node <> p.src_ip
+                        AND (c.node = p.dst_ip OR p.dst_ip = '255.255.255.255' OR p.dst_ip << '224.0.0.0/4')
+                    WHERE p.id IN (SELECT packet_id FROM packet_deliveries)
+                ),
+                removed AS (
+                    DELETE FROM packets
+                    WHERE id IN (SELECT id FROM recipients GROUP BY id HAVING bool_and(delivered))
+                    RETURNING id
+                ),
+                acknowledged AS (
+                    DELETE FROM packet_deliveries d USING removed WHERE d.packet_id = removed.id
+                )
+                SELECT count(*) FROM removed",
+                &[],
+            )
+            .await?;
+        let deleted = rows.first().map_or(0, |row| row.get::<_, i64>(0)) as u64;
+        info!("全ての宛先のノードが処理済みのパケットを {} 件削除しました", deleted);
+        Ok(deleted)
+    }
+
+    // 指定時刻より古いデータのみを含むチャンクを削除し、削除したチャンク数を返す
+    pub async fn drop_old_chunks(&self, before: DateTime<Utc>) -> Result<u64, DbError> {
+        let rows = self
+            .query("SELECT drop_chunks('packets', older_than => $1::timestamptz)", &[&before])
+            .await?;
+        info!("{} より古いチャンクを {} 個削除しました", before, rows.len());
+        Ok(rows.len() as u64)
+    }
+
+    // packetsテーブルがTimescaleDBのハイパーテーブルかどうか
+    pub async fn packets_is_hypertable(&self) -> Result<bool, DbError> {
+        let rows = self
+            .query("SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'", &[])
+            .await?;
+        if rows.is_empty() {
+            return Ok(false);
+        }
+
+        let rows = self
+            .query(
+                "SELECT 1 FROM timescaledb_information.hypertables WHERE hypertable_name = 'packets'",
+                &[],
+            )
+            .await?;
+        Ok(!rows.is_empty())
+    }
+
+    // ハイパーテーブルの場合は古いチャンクをまとめて削除し、境界をまたぐチャンクに残った行を個別に削除する
+    pub async fn prune_packets(&self, before: DateTime<Utc>) -> Result<PruneSummary, DbError> {
+        let dropped_chunks = if self.packets_is_hypertable().await? {
+            self.drop_old_chunks(before).await?
+        } else {
+            0
+        };
+        let deleted = self.delete_old_packets(before).await?;
+        self.delete_old_deliveries(before).await?;
+        Ok(PruneSummary { dropped_chunks, deleted })
+    }
+}
@@ -0,0 +1,19 @@
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::execute_query::ExecuteQuery;
+
+impl Database {
+    // 保存サイズがmax_size以下のパケットを新しい順に取得する (圧縮方式とraw_packetの組)
+    pub async fn sample_packets(&self, limit: i64, max_size: i32) -> Result<Vec<(i16, Vec<u8>)>, DbError> {
+        let rows = self
+            .query(
+                "SELECT codec, raw_packet FROM packets
+                 WHERE length(raw_packet) <= $1
+                 ORDER BY timestamp DESC
+                 LIMIT $2",
+                &[&max_size, &limit],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| (row.get("codec"), row.get("raw_packet"))).collect())
+    }
+}
@@ -0,0 +1,109 @@
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::execute_query::ExecuteQuery;
+use std::collections::HashMap;
+
+// db_write / db_read が使用するテーブルの列と型 (information_schemaのudt_name)
+const PACKETS_COLUMNS: &[(&str, &str)] = &[
+    ("id", "int8"),
+    ("src_mac", "macaddr"),
+    ("dst_mac", "macaddr"),
+    ("ether_type", "int4"),
+    ("src_ip", "inet"),
+    ("dst_ip", "inet"),
+    ("src_port", "int4"),
+    ("dst_port", "int4"),
+    ("ip_protocol", "int4"),
+    ("timestamp", "timestamptz"),
+    ("data", "bytea"),
+    ("raw_packet", "bytea"),
+    ("interface", "text"),
+    ("seq", "int8"),
+    ("dscp", "int2"),
+    ("ecn", "int2"),
+    ("codec", "int2"),
+    ("chunk_id", "int8"),
+    ("chunk_index", "int2"),
+    ("chunk_count", "int2"),
+    ("encapsulation", "int2"),
+    ("inner_src_ip", "inet"),
+    ("inner_dst_ip", "inet"),
+    ("inner_src_port", "int4"),
+    ("inner_dst_port", "int4"),
+    ("inner_ip_protocol", "int4"),
+    ("vni", "int4"),
+];
+
+const PACKET_DELIVERIES_COLUMNS: &[(&str, &str)] = &[
+    ("node", "inet"),
+    ("packet_id", "int8"),
+    ("delivered_at", "timestamptz"),
+];
+
+const CURSORS_COLUMNS: &[(&str, &str)] = &[
+    ("node", "inet"),
+    ("last_timestamp", "timestamptz"),
+    ("last_id", "int8"),
+    ("updated_at", "timestamptz"),
+];
+
+const PEERS_COLUMNS: &[(&str, &str)] = &[
+    ("node", "inet"),
+    ("tunnel_address", "inet"),
+    ("subnets", "_cidr"),
+    ("updated_at", "timestamptz"),
+];
+
+const TABLES: &[(&str, &[(&str, &str)])] = &[
+    ("packets", PACKETS_COLUMNS),
+    ("packet_deliveries", PACKET_DELIVERIES_COLUMNS),
+    ("cursors", CURSORS_COLUMNS),
+    ("peers", PEERS_COLUMNS),
+];
+
+impl Database {
+    // 使用するテーブルの列を検査し、不足・型の不一致をまとめて報告する
+    pub async fn verify_schema(&self) -> Result<(), DbError> {
+        let mut problems = Vec::new();
+        for (table, columns) in TABLES {
+            problems.extend(self.verify_table(table, columns).await?);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(DbError::Schema(problems.join("; ")))
+        }
+    }
+
+    async fn verify_table(&self, table: &str, columns: &[(&str, &str)]) -> Result<Vec<String>, DbError> {
+        let rows = self
+            .query(
+                "SELECT column_name::text AS column_name, udt_name::text AS udt_name
+                FROM information_schema.columns
+                WHERE table_schema = current_schema() AND table_name = $1",
+                &[&table],
+            )
+            .await?;
+        if rows.is_empty() {
+            return Ok(vec![format!("table \"{}\" does not exist", table)]);
+        }
+
+        let actual: HashMap<String, String> = rows
+            .iter()
+            .map(|row| (row.get("column_name"), row.get("udt_name")))
+            .collect();
+
+        Ok(columns
+            .iter()
+            .filter_map(|(column, expected)| match actual.get(*column) {
+                None => Some(format!("{}: missing column \"{}\" ({})", table, column, expected)),
+                Some(found) if found != expected => Some(format!(
+                    "{}: column \"{}\" has type {}, expected {}",
+                    table, column, found, expected
+                )),
+                Some(_) => None,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,187 @@
+use bytes::{Bytes, BytesMut};
+use ipnetwork::IpNetwork;
+use postgres_types::FromSql;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::net::IpAddr;
+use std::str::FromStr;
+use tokio_postgres::types::{IsNull, ToSql, Type};
+use tracing::error;
+
+// PostgreSQLのmacaddr型 (バイナリ形式は6バイト)。設定ファイルでは "02:00:00:00:00:01" の形式で指定する
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    // マルチキャスト (グループ) アドレスでないこと
+    pub fn is_unicast(&self) -> bool {
+        self.0[0] & 0x01 == 0
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let octets = s
+            .split([':', '-'])
+            .map(|octet| u8::from_str_radix(octet, 16).ok().filter(|_| octet.len() == 2))
+            .collect::<Option<Vec<_>>>()
+            .and_then(|octets| <[u8; 6]>::try_from(octets).ok())
+            .ok_or_else(|| format!("MACアドレスの形式が不正です: {}", s))?;
+        Ok(MacAddr(octets))
+    }
+}
+
+impl TryFrom<String> for MacAddr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mac_string = self.0.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":");
+        write!(f, "{}", mac_string)
+    }
+}
+
+impl ToSql for MacAddr {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.extend_from_slice(&self.0);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "macaddr"
+    }
+
+    fn to_sql_checked(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.to_sql(ty, out)
+    }
+}
+
+impl<'a> FromSql<'a> for MacAddr {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if raw.len() != 6 {
+            error!("MACアドレスの長さが不正です");
+            return Err("Invalid MAC address length".into());
+        }
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(raw);
+        Ok(MacAddr(addr))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "macaddr"
+    }
+}
+
+
+// PostgreSQLのinet型のためのラッパー構造体。単一のアドレスはホストのプレフィックス長で表す
+#[derive(Debug, Clone)]
+pub struct InetAddr(pub IpNetwork);
+
+impl InetAddr {
+    pub fn ip(&self) -> IpAddr {
+        self.0.ip()
+    }
+}
+
+impl From<IpAddr> for InetAddr {
+    fn from(addr: IpAddr) -> Self {
+        Self(IpNetwork::from(addr))
+    }
+}
+
+impl From<IpNetwork> for InetAddr {
+    fn from(network: IpNetwork) -> Self {
+        Self(network)
+    }
+}
+
+impl ToSql for InetAddr {
+    fn to_sql(
+        &self,
+        _ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        // family, prefix, is_cidr, アドレス長, アドレス
+        match self.0.ip() {
+            IpAddr::V4(addr) => {
+                out.extend_from_slice(&[2, self.0.prefix(), 0, 4]);     // AF_INET
+                out.extend_from_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                out.extend_from_slice(&[3, self.0.prefix(), 0, 16]);    // AF_INET6
+                out.extend_from_slice(&addr.octets());
+            }
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "inet"
+    }
+
+    fn to_sql_checked(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.to_sql(ty, out)
+    }
+}
+
+// PostgreSQLのbytea型。キャプチャしたフレームのバッファを共有したまま書き込む
+#[derive(Debug, Clone, Default)]
+pub struct Bytea(pub Bytes);
+
+impl Deref for Bytea {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Bytes> for Bytea {
+    fn from(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl ToSql for Bytea {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.0.as_ref().to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&[u8] as ToSql>::accepts(ty)
+    }
+
+    fn to_sql_checked(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.0.as_ref().to_sql_checked(ty, out)
+    }
+}
@@ -1,46 +1,66 @@
+use crate::chunk::{self, Chunk, Reassembler};
+use crate::compression;
 use crate::database::database::Database;
 use crate::database::error::DbError;
 use crate::database::execute_query::ExecuteQuery;
-use crate::db_write::MacAddr;
-use bytes::BytesMut;
-use log::{debug, error, info, trace};
+use crate::config::PollMode;
+use crate::database::types::MacAddr;
+use crate::error::Retryable;
+use crate::events::{self, Direction, PacketSummary, PipelineEvent};
+use crate::firewall::{Chain, FIREWALL};
+use crate::firewall_packet::FirewallPacket;
+use crate::health;
+use crate::messages::{message, MessageId};
+use crate::metrics;
+use crate::nat;
+use crate::probe;
+use crate::security::sip;
+use crate::sequence;
+use crate::setup_logger::LogThrottle;
+use crate::shaper;
+use crate::telemetry;
+use crate::traffic;
+use crate::transport::{transport, TransportError};
+use tracing::{debug, debug_span, error, info, info_span, trace, warn, Instrument};
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
 use pnet::datalink::Channel::Ethernet;
-use pnet::datalink::{self, NetworkInterface};
-use postgres_types::{FromSql, IsNull, ToSql, Type};
-use std::error::Error;
+use pnet::datalink::{self, DataLinkSender, NetworkInterface};
+use std::collections::HashMap;
 use std::net::IpAddr;
-use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use thiserror::Error;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
-use tokio::time::interval;
 
-#[derive(Debug)]
+#[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum PacketError {
+    #[error("ネットワークエラー: {0}")]
     NetworkError(String),
-    DatabaseError(DbError),
+
+    #[error("データベースエラー: {0}")]
+    DatabaseError(#[from] DbError),
+
+    #[error("デバイスエラー: {0}")]
     DeviceError(String),
+
+    #[error("トランスポートエラー: {0}")]
+    TransportError(#[from] TransportError),
 }
 
-impl std::fmt::Display for PacketError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Retryable for PacketError {
+    fn is_retryable(&self) -> bool {
         match self {
-            PacketError::NetworkError(msg) => write!(f, "ネットワークエラー: {}", msg),
-            PacketError::DatabaseError(e) => write!(f, "データベースエラー: {}", e),
-            PacketError::DeviceError(msg) => write!(f, "デバイスエラー: {}", msg),
+            PacketError::NetworkError(_) => true,
+            PacketError::DatabaseError(e) => e.is_retryable(),
+            PacketError::DeviceError(_) => false,
+            PacketError::TransportError(e) => e.is_retryable(),
         }
     }
 }
 
-impl std::error::Error for PacketError {}
-
-impl From<DbError> for PacketError {
-    fn from(err: DbError) -> Self {
-        PacketError::DatabaseError(err)
-    }
-}
-
 #[derive(Clone)]
 pub struct PacketInfo {
     pub src_mac: MacAddr,
@@ -54,31 +74,86 @@ pub struct PacketInfo {
     pub timestamp: chrono::DateTime<chrono::Utc>,
     pub data: Vec<u8>,
     pub raw_packet: Vec<u8>,
+    // 送信側で採番した連番 (連番のないパケットはNone)
+    pub seq: Option<i64>,
+    // raw_packetとdataの圧縮方式 (compression::Codecの番号)
+    pub codec: i16,
+    // 分割して保存したフレームの断片の場合の情報
+    pub chunk: Option<Chunk>,
+}
+
+// レプリカ遅延を考慮して遡る時間の上限
+const MAX_REPLICA_LOOKBACK: Duration = Duration::from_secs(30);
+// レプリカ使用時に遅延に上乗せする余裕
+const REPLICA_LAG_MARGIN: Duration = Duration::from_secs(1);
+// コミット順序の入れ替わりやノード間の時刻のずれで遅れて見える行を拾うために遡る時間。
+// 再取得した行は packet_deliveries の確認済み記録で除外する
+const OUT_OF_ORDER_LOOKBACK: Duration = Duration::from_secs(5);
+// idの順序でポーリングする場合に遡るidの数。
+// 先に採番された行が後からコミットされても取りこぼさないようにする (再取得した行は確認済み記録で除外)
+const SEQUENCE_LOOKBACK: i64 = 10_000;
+
+// NICの停止中などに、パケットごとの送信失敗を毎回ログへ出力しないようにする (失敗数はメトリクスで数える)
+static INJECT_ERRORS: LogThrottle = LogThrottle::new(Duration::from_secs(10));
+
+// 保存されたポーリング位置から再開する期間の上限。これより古い場合は直近から再開する
+pub const MAX_RESUME_AGE: Duration = Duration::from_secs(5 * 60);
+
+// 受信したパケットの注入先
+#[derive(Clone)]
+enum Injector {
+    // 仮想NICへ送信する。開いたチャネルは使い回し、送信に失敗した場合は次回開き直す
+    Interface(Arc<NetworkInterface>, Arc<std::sync::Mutex<Option<Box<dyn DataLinkSender>>>>),
+    // テストで注入されたパケットを検査するためのチャネル
+    #[cfg(test)]
+    Channel(tokio::sync::mpsc::UnboundedSender<PacketInfo>),
 }
 
 #[derive(Clone)]
 pub struct PacketPoller {
     last_timestamp: Arc<Mutex<Option<chrono::DateTime<chrono::Utc>>>>, // Changed from NaiveDateTime to DateTime<Utc>
+    // idの順序でポーリングする場合の取得済みの最大id
+    last_id: Arc<Mutex<Option<i64>>>,
+    mode: PollMode,
+    // 遡って再取得した行を重複して注入しないための、送信済みパケットのid
+    delivered_ids: Arc<Mutex<HashMap<i64, chrono::DateTime<chrono::Utc>>>>,
     is_first_poll: Arc<AtomicBool>,
     my_ip: IpAddr,
-    interface: Arc<NetworkInterface>,
+    injector: Injector,
     packets_sent: Arc<AtomicU64>,
     packets_failed: Arc<AtomicU64>,
+    // 分割して保存されたフレームの組み立て中の断片
+    reassembler: Arc<std::sync::Mutex<Reassembler>>,
 }
 
 impl PacketPoller {
-    pub fn new(my_ip: IpAddr, interface: NetworkInterface) -> Self {
+    pub fn new(my_ip: IpAddr, interface: NetworkInterface, mode: PollMode) -> Self {
+        let injector = Injector::Interface(Arc::new(interface), Arc::new(std::sync::Mutex::new(None)));
+        Self::with_injector(my_ip, injector, mode)
+    }
+
+    // 仮想NICの代わりにチャネルへ注入するポーラー (テスト用)
+    #[cfg(test)]
+    pub fn with_channel(my_ip: IpAddr, sender: tokio::sync::mpsc::UnboundedSender<PacketInfo>) -> Self {
+        Self::with_injector(my_ip, Injector::Channel(sender), PollMode::default())
+    }
+
+    fn with_injector(my_ip: IpAddr, injector: Injector, mode: PollMode) -> Self {
         Self {
             last_timestamp: Arc::new(Mutex::new(None)),
+            last_id: Arc::new(Mutex::new(None)),
+            mode,
+            delivered_ids: Arc::new(Mutex::new(HashMap::new())),
             is_first_poll: Arc::new(AtomicBool::new(true)),
             my_ip,
-            interface: Arc::new(interface),
+            injector,
             packets_sent: Arc::new(AtomicU64::new(0)),
             packets_failed: Arc::new(AtomicU64::new(0)),
+            reassembler: Arc::new(std::sync::Mutex::new(Reassembler::default())),
         }
     }
 
-    fn is_broadcast_ip(ip: &IpAddr) -> bool {
+    pub fn is_broadcast_ip(ip: &IpAddr) -> bool {
         match ip {
             IpAddr::V4(ipv4) => {
                 ipv4.is_broadcast() ||
@@ -89,8 +164,23 @@ impl PacketPoller {
         }
     }
 
+    #[cfg_attr(not(feature = "replication"), allow(dead_code))]
+    pub fn mode(&self) -> PollMode {
+        self.mode
+    }
+
+    #[cfg_attr(not(any(feature = "replication", feature = "kafka", feature = "redis", feature = "mqtt", feature = "nats", feature = "clickhouse")), allow(dead_code))]
+    pub fn node_ip(&self) -> IpAddr {
+        self.my_ip
+    }
+
+    // ポーリングのクエリと同じ条件 (自分宛・ブロードキャスト・マルチキャスト) で宛先を判定
+    pub fn is_addressed_to_node(&self, packet: &PacketInfo) -> bool {
+        packet.dst_ip == self.my_ip || Self::is_broadcast_ip(&packet.dst_ip)
+    }
+
     // パケットを処理対象とするかどうかを判定
-    fn should_process_packet(&self, packet: &PacketInfo) -> bool {
+    pub fn should_process_packet(&self, packet: &PacketInfo) -> bool {
         let is_tunnel_traffic = packet.src_ip.to_string().starts_with("192.168.0.") ||
             packet.dst_ip.to_string().starts_with("192.168.0."); // トンネルトラフィックの場合は処理
 
@@ -109,21 +199,70 @@ impl PacketPoller {
         is_for_me || is_broadcast || is_tunnel_traffic
     }
 
-    pub async fn poll_packets(&self) -> Result<Vec<PacketInfo>, PacketError> {
-        let db = Database::get_database();
+    // レプリカから読む場合、遅延中に書き込まれた行を取りこぼさないよう遡る時間
+    async fn replica_lookback(&self, db: &Database) -> chrono::Duration {
+        if !db.has_replica() {
+            return chrono::Duration::zero();
+        }
+
+        let lookback = match db.replica_lag().await {
+            Ok(Some(lag)) => {
+                debug!("レプリカ遅延: {}ms", lag.as_millis());
+                lag.min(MAX_REPLICA_LOOKBACK) + REPLICA_LAG_MARGIN
+            }
+            Ok(None) => REPLICA_LAG_MARGIN,
+            Err(e) => {
+                warn!("レプリカ遅延の取得に失敗したため最大値で遡ります: {}", e);
+                MAX_REPLICA_LOOKBACK + REPLICA_LAG_MARGIN
+            }
+        };
+        chrono::Duration::from_std(lookback).unwrap_or_else(|_| chrono::Duration::zero())
+    }
+
+    // 注入対象のパケットと、確認済みとして記録する取得済みの全パケットのidを返す
+    pub async fn poll_packets(&self, db: &Database) -> Result<(Vec<PacketInfo>, Vec<i64>), PacketError> {
+        let lookback = self.replica_lookback(db).await
+            + chrono::Duration::from_std(OUT_OF_ORDER_LOOKBACK).unwrap_or_else(|_| chrono::Duration::zero());
         let mut last_ts = self.last_timestamp.lock().await;
         let is_first = self.is_first_poll.load(Ordering::SeqCst);
+        let cursor = last_ts.map(|ts| ts - lookback);
+        let mut last_id = self.last_id.lock().await;
+        let sequence_from = match self.mode {
+            PollMode::Sequence => last_id.map(|id| id - SEQUENCE_LOOKBACK),
+            PollMode::Timestamp | PollMode::Replication => None,
+        };
 
-        const MAX_PACKET_SIZE: i64 = 1500;
+        // 分割して保存する前のバージョンが書き込んだ大きな行は取得しない
+        const MAX_PACKET_SIZE: i64 = chunk::MAX_ROW_SIZE as i64;
 
         let current_time = chrono::Utc::now();
         debug!("現在時刻: {}", current_time);
 
-        let (query, params): (_, Vec<&(dyn tokio_postgres::types::ToSql + Sync)>) = if is_first {
+        // idの順序でポーリングする場合も、取得済みのidがない間はタイムスタンプで直近のパケットを取得する
+        let (query, params): (_, Vec<&(dyn tokio_postgres::types::ToSql + Sync)>) = if let Some(from) = &sequence_from {
             (
                 "
-            SELECT src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port, 
-                ip_protocol, timestamp, data, raw_packet
+            SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+                ip_protocol, timestamp, data, raw_packet, seq, codec, chunk_id, chunk_index, chunk_count
+            FROM packets
+            WHERE id > $2
+                AND length(raw_packet) <= $1::bigint
+                AND (dst_ip = $3
+                    OR dst_ip = '255.255.255.255'
+                    OR dst_ip << '224.0.0.0/4'
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM packet_deliveries d WHERE d.node = $3 AND d.packet_id = packets.id
+                )
+            ORDER BY id ASC
+            ",
+                vec![&MAX_PACKET_SIZE, from, &self.my_ip]
+            )
+        } else if is_first {
+            (
+                "
+            SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port, 
+                ip_protocol, timestamp, data, raw_packet, seq, codec, chunk_id, chunk_index, chunk_count
             FROM packets
             WHERE length(raw_packet) <= $1::bigint
                 AND (dst_ip = $2
@@ -131,17 +270,20 @@ impl PacketPoller {
                     OR dst_ip << '224.0.0.0/4'
                 )
                 AND timestamp >= NOW() - INTERVAL '30 seconds'
+                AND NOT EXISTS (
+                    SELECT 1 FROM packet_deliveries d WHERE d.node = $2 AND d.packet_id = packets.id
+                )
             ORDER BY timestamp ASC
             ",
                 vec![&MAX_PACKET_SIZE, &self.my_ip]
             )
         } else {
-            match &*last_ts {
+            match &cursor {
                 Some(ts) => {
                     (
                         "
-                    SELECT src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
-                        ip_protocol, timestamp, data, raw_packet
+                    SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+                        ip_protocol, timestamp, data, raw_packet, seq, codec, chunk_id, chunk_index, chunk_count
                     FROM packets
                     WHERE timestamp > $2
                         AND length(raw_packet) <= $1::bigint
@@ -149,6 +291,9 @@ impl PacketPoller {
                             OR dst_ip = '255.255.255.255'
                             OR dst_ip << '224.0.0.0/4'
                         )
+                        AND NOT EXISTS (
+                            SELECT 1 FROM packet_deliveries d WHERE d.node = $3 AND d.packet_id = packets.id
+                        )
                     ORDER BY timestamp ASC
                     ",
                         vec![&MAX_PACKET_SIZE, ts, &self.my_ip]
@@ -159,8 +304,8 @@ impl PacketPoller {
                     *last_ts = Some(five_seconds_ago);
                     (
                         "
-                    SELECT src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
-                        ip_protocol, timestamp, data, raw_packet
+                    SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+                        ip_protocol, timestamp, data, raw_packet, seq, codec, chunk_id, chunk_index, chunk_count
                     FROM packets
                     WHERE length(raw_packet) <= $1::bigint
                         AND (dst_ip = $2
@@ -168,6 +313,9 @@ impl PacketPoller {
                             OR dst_ip << '224.0.0.0/4'
                         )
                         AND timestamp >= NOW() - INTERVAL '5 seconds'
+                        AND NOT EXISTS (
+                            SELECT 1 FROM packet_deliveries d WHERE d.node = $2 AND d.packet_id = packets.id
+                        )
                     ORDER BY timestamp ASC
                     ",
                         vec![&MAX_PACKET_SIZE, &self.my_ip]
@@ -180,24 +328,41 @@ impl PacketPoller {
         debug!("クエリパラメータ: {:?}", params);
         debug!("クエリ実行前のタイムスタンプ: {:?}", *last_ts);
 
-        let rows = match db.query(query, &params).await {
+        let poll_timer = metrics::POLL_LATENCY.start_timer();
+        let rows = db.query_replica(query, &params).await;
+        poll_timer.observe_duration();
+        let rows = match rows {
             Ok(rows) => rows,
             Err(e) => {
-                error!("データベースクエリエラー: {:?}", e);
+                debug!("データベースクエリエラー: {:?}", e);
                 debug!("エラー発生時のタイムスタンプを更新: {}", current_time);
                 *last_ts = Some(current_time);
                 return Err(PacketError::from(e));
             }
         };
 
-        info!("{}行のデータを取得しました", rows.len());
+        debug!("{}行のデータを取得しました", rows.len());
+
+        health::record_poll_success();
 
         let mut packet_infos: Vec<PacketInfo> = Vec::new();
+        let mut fetched_ids: Vec<i64> = Vec::new();
         let mut latest_timestamp = None;
+        let mut delivered_ids = self.delivered_ids.lock().await;
 
         for row in rows {
+            let id: i64 = row.get("id");
             let timestamp: chrono::DateTime<chrono::Utc> = row.get("timestamp");
             debug!("パケットのタイムスタンプを処理中: {}", timestamp);
+            if last_id.is_none_or(|last| last < id) {
+                *last_id = Some(id);
+            }
+
+            if delivered_ids.insert(id, timestamp).is_some() {
+                trace!("取得済みのパケットのためスキップ: id={}", id);
+                continue;
+            }
+            fetched_ids.push(id);
 
             if latest_timestamp.is_none() || latest_timestamp.unwrap() < timestamp {
                 latest_timestamp = Some(timestamp);
@@ -221,6 +386,9 @@ impl PacketPoller {
                 timestamp,
                 data: row.get("data"),
                 raw_packet: row.get("raw_packet"),
+                seq: row.get("seq"),
+                codec: row.get("codec"),
+                chunk: Chunk::from_columns(row.get("chunk_id"), row.get("chunk_index"), row.get("chunk_count")),
             };
 
             if self.should_process_packet(&packet_info) {
@@ -236,7 +404,12 @@ impl PacketPoller {
 
         let new_timestamp = latest_timestamp.unwrap_or(current_time);
         *last_ts = Some(new_timestamp);
-        info!("タイムスタンプを更新: {}", new_timestamp);
+
+        // 次回の取得範囲より古いidは重複判定に不要なため破棄
+        let retain_from = new_timestamp
+            - chrono::Duration::from_std(MAX_REPLICA_LOOKBACK + REPLICA_LAG_MARGIN + OUT_OF_ORDER_LOOKBACK).unwrap_or_else(|_| chrono::Duration::zero());
+        delivered_ids.retain(|_, timestamp| *timestamp >= retain_from);
+        debug!("タイムスタンプを更新: {}", new_timestamp);
         debug!("取得したパケット数: {}", packet_infos.len());
 
         if is_first {
@@ -244,96 +417,337 @@ impl PacketPoller {
             info!("初回ポーリング完了、フラグを更新しました");
         }
 
-        Ok(packet_infos)
+        Ok((packet_infos, fetched_ids))
     }
 
-    pub async fn poll_and_send_packets(&self) -> Result<(), PacketError> {
-        match self.poll_packets().await {
-            Ok(packets) => {
-                let packet_count = packets.len();
-                debug!("{}個のパケットを取得しました", packet_count);
+    // 前回保存したポーリング位置を復元する
+    pub async fn restore_cursor(&self, db: &Database) -> Result<(), DbError> {
+        let rows = db
+            .query("SELECT last_timestamp, last_id, updated_at FROM cursors WHERE node = $1", &[&self.my_ip])
+            .await?;
+        let Some(row) = rows.first() else {
+            info!("{}", message(MessageId::CursorMissing, &[]));
+            return Ok(());
+        };
 
-                for packet in packets {
-                    trace!("パケット送信中: {}: {} {}",
-                            packet.timestamp,
-                            packet.src_ip,
-                            packet.dst_ip
-                        );
-
-                    if packet.raw_packet.len() > 1500 {
-                        debug!("パケットサイズが大きすぎるためスキップ: {} bytes",
-                                    packet.raw_packet.len()
-                        );
-                        self.packets_failed.fetch_add(1, Ordering::SeqCst);
-                        continue;
-                    }
+        let saved: chrono::DateTime<chrono::Utc> = row.get("last_timestamp");
+        let saved_id: Option<i64> = row.get("last_id");
+        let updated_at: chrono::DateTime<chrono::Utc> = row.get("updated_at");
+        let oldest = chrono::Utc::now()
+            - chrono::Duration::from_std(MAX_RESUME_AGE).unwrap_or_else(|_| chrono::Duration::zero());
+        // 停止していた期間が長い場合は、溜まったパケットを全て注入せずに直近から再開する
+        let resume_from = if updated_at < oldest {
+            warn!("{}", message(MessageId::CursorTooOld, &[&updated_at, &oldest]));
+            oldest
+        } else {
+            info!("{}", message(MessageId::CursorResumed, &[&saved, &format!("{:?}", saved_id)]));
+            *self.last_id.lock().await = saved_id;
+            saved
+        };
 
-                    let (mut tx, _) = match datalink::channel(&self.interface, Default::default()) {
-                        Ok(Ethernet(tx, rx)) => (tx, rx),
-                        Ok(_) => {
-                            error!("未対応のチャネルタイプです");
-                            return Err(PacketError::NetworkError("未対応のチャネルタイプです".to_string()));
-                        }
-                        Err(e) => return Err(PacketError::NetworkError(e.to_string())),
+        *self.last_timestamp.lock().await = Some(resume_from);
+        self.is_first_poll.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // 現在のポーリング位置を保存する
+    async fn save_cursor(&self, db: &Database) -> Result<(), DbError> {
+        let Some(last_timestamp) = *self.last_timestamp.lock().await else {
+            return Ok(());
+        };
+        let last_id = *self.last_id.lock().await;
+        db
+            .execute(
+                "INSERT INTO cursors (node, last_timestamp, last_id) VALUES ($1, $2, $3)
+                ON CONFLICT (node) DO UPDATE SET
+                    last_timestamp = EXCLUDED.last_timestamp,
+                    last_id = EXCLUDED.last_id,
+                    updated_at = now()",
+                &[&self.my_ip, &last_timestamp, &last_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // このノードで処理したパケットを記録し、以降のポーリングで再取得しないようにする
+    async fn acknowledge(&self, db: &Database, ids: &[i64]) -> Result<(), DbError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        db
+            .execute(
+                "INSERT INTO packet_deliveries (node, packet_id)
+                SELECT $1, unnest($2::bigint[])
+                ON CONFLICT DO NOTHING",
+                &[&self.my_ip, &ids],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // パケットを仮想NICに注入し、成功数と失敗数を返す
+    pub async fn send_packets(&self, packets: Vec<PacketInfo>) -> Result<(u64, u64), PacketError> {
+        for packet in packets {
+            // 分割された断片は全て揃ってから展開・注入する
+            let reassembled = self.reassembler.lock().unwrap_or_else(|e| e.into_inner()).push(packet, Instant::now());
+            let Some(mut packet) = reassembled else {
+                continue;
+            };
+            if let Err(e) = compression::decompress_packet(&mut packet) {
+                warn!("パケットの展開に失敗したためスキップ: {} -> {}: {}", packet.src_ip, packet.dst_ip, e);
+                self.packets_failed.fetch_add(1, Ordering::SeqCst);
+                metrics::PACKETS_DROPPED.with_label_values(&["decompress"]).inc();
+                continue;
+            }
+            trace!("パケット送信中: {}: {} {}",
+                    packet.timestamp,
+                    packet.src_ip,
+                    packet.dst_ip
+                );
+
+            // トンネルの疎通確認用のプローブは仮想NICに注入しない
+            if probe::handle(&packet, self.my_ip) {
+                continue;
+            }
+            if let Some(seq) = packet.seq {
+                sequence::observe(packet.src_ip, packet.dst_ip, seq);
+            }
+            // 送信元NATで変換したフローへの応答は、宛先を元のクライアントに戻す
+            nat::translate_inbound(&mut packet);
+
+            // 宛先を戻した後のアドレスでINPUTチェインを適用する
+            if !input_allowed(&packet) {
+                trace!("不許可 (INPUT): {}:{:?} -> {}:{:?}", packet.src_ip, packet.src_port, packet.dst_ip, packet.dst_port);
+                metrics::FIREWALL_DROPS.inc();
+                metrics::PACKETS_DROPPED.with_label_values(&["firewall_input"]).inc();
+                if events::has_subscribers() {
+                    events::publish(PipelineEvent::Packet(inbound_summary(&packet, false)));
+                }
+                continue;
+            }
+
+            if packet.raw_packet.len() > chunk::MAX_FRAME_SIZE {
+                debug!("パケットサイズが大きすぎるためスキップ: {} bytes",
+                            packet.raw_packet.len()
+                );
+                self.packets_failed.fetch_add(1, Ordering::SeqCst);
+                metrics::PACKETS_DROPPED.with_label_values(&["oversize"]).inc();
+                continue;
+            }
+
+            // 下流のリンクが溢れないよう、帯域の上限に合わせて注入を遅らせる
+            match shaper::reserve(packet.src_ip, packet.raw_packet.len()) {
+                Some(delay) if !delay.is_zero() => tokio::time::sleep(delay).await,
+                Some(_) => {}
+                None => {
+                    debug!("帯域の上限を超えたためパケットを破棄: {} -> {}", packet.src_ip, packet.dst_ip);
+                    self.packets_failed.fetch_add(1, Ordering::SeqCst);
+                    metrics::PACKETS_DROPPED.with_label_values(&["shaped"]).inc();
+                    continue;
+                }
+            }
+
+            let result = match &self.injector {
+                Injector::Interface(interface, sender) => {
+                    let mut sender = sender.lock().unwrap_or_else(|e| e.into_inner());
+                    let tx = match sender.as_mut() {
+                        Some(tx) => tx,
+                        None => match datalink::channel(interface, Default::default()) {
+                            Ok(Ethernet(tx, _)) => sender.insert(tx),
+                            Ok(_) => {
+                                error!("未対応のチャネルタイプです");
+                                return Err(PacketError::NetworkError("未対応のチャネルタイプです".to_string()));
+                            }
+                            Err(e) => return Err(PacketError::NetworkError(e.to_string())),
+                        },
                     };
+                    let result = tx.send_to(&packet.raw_packet, None);
+                    if !matches!(result, Some(Ok(_))) {
+                        *sender = None;
+                    }
+                    result
+                }
+                #[cfg(test)]
+                Injector::Channel(sender) => {
+                    Some(sender.send(packet.clone()).map_err(|e| std::io::Error::other(e.to_string())))
+                }
+            };
 
-                    match tx.send_to(&*packet.raw_packet, None) {
-                        Some(Ok(_)) => {
-                            trace!("パケット送信完了: ip-prot:{} {} -> {}",
-                                packet.ip_protocol,
-                                packet.src_ip,
-                                packet.dst_ip,
-                            );
-                            self.packets_sent.fetch_add(1, Ordering::SeqCst);
-                        }
-                        Some(Err(e)) => {
-                            error!("パケット送信に失敗しました: {}", e);
-                            self.packets_failed.fetch_add(1, Ordering::SeqCst);
-                            continue;
-                        }
-                        None => {
-                            error!("宛先が指定されていないためスキップ");
-                            self.packets_failed.fetch_add(1, Ordering::SeqCst);
-                            continue;
-                        }
+            match result {
+                Some(Ok(_)) => {
+                    trace!("パケット送信完了: ip-prot:{} {} -> {}",
+                        packet.ip_protocol,
+                        packet.src_ip,
+                        packet.dst_ip,
+                    );
+                    self.packets_sent.fetch_add(1, Ordering::SeqCst);
+                    metrics::PACKETS_INJECTED.inc();
+                    traffic::record(
+                        Direction::Inbound,
+                        packet.ip_protocol as u8,
+                        packet.src_ip,
+                        traffic::service_port(
+                            packet.src_port.unwrap_or(0) as u16,
+                            packet.dst_port.unwrap_or(0) as u16,
+                        ),
+                        packet.raw_packet.len(),
+                    );
+                    if events::has_subscribers() {
+                        events::publish(PipelineEvent::Packet(inbound_summary(&packet, true)));
+                    }
+                    let latency = chrono::Utc::now() - packet.timestamp;
+                    if let Ok(latency) = latency.to_std() {
+                        telemetry::TUNNEL_LATENCY.record(latency.as_secs_f64(), &[]);
                     }
                 }
+                Some(Err(e)) => {
+                    if let Some(suppressed) = INJECT_ERRORS.allow() {
+                        error!("{}", message(MessageId::InjectFailed, &[&e, &suppressed]));
+                    }
+                    self.packets_failed.fetch_add(1, Ordering::SeqCst);
+                    metrics::PACKETS_DROPPED.with_label_values(&["inject_failed"]).inc();
+                    continue;
+                }
+                None => {
+                    error!("宛先が指定されていないためスキップ");
+                    self.packets_failed.fetch_add(1, Ordering::SeqCst);
+                    metrics::PACKETS_DROPPED.with_label_values(&["inject_failed"]).inc();
+                    continue;
+                }
+            }
+        }
+
+        let sent = self.packets_sent.load(Ordering::SeqCst);
+        let failed = self.packets_failed.load(Ordering::SeqCst);
+
+        // パケット送信数をリセット
+        self.packets_sent.store(0, Ordering::SeqCst);
+        self.packets_failed.store(0, Ordering::SeqCst);
+
+        Ok((sent, failed))
+    }
+
+    // 未処理のパケットを処理済みとして記録し、新たに記録できた (このノードでまだ処理していない) idを返す
+    #[cfg_attr(not(feature = "replication"), allow(dead_code))]
+    pub async fn claim(&self, db: &Database, ids: &[i64]) -> Result<Vec<i64>, DbError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let rows = db
+            .query(
+                "INSERT INTO packet_deliveries (node, packet_id)
+                SELECT $1, unnest($2::bigint[])
+                ON CONFLICT DO NOTHING
+                RETURNING packet_id",
+                &[&self.my_ip, &ids],
+            )
+            .await?;
+        Ok(rows.iter().map(|row| row.get("packet_id")).collect())
+    }
+
+    pub async fn poll_and_send_packets(&self, db: &Database) -> Result<(), PacketError> {
+        let tracer = telemetry::tracer();
+        let cx = Context::current_with_span(tracer.start("packet.poll_inject"));
 
-                let sent = self.packets_sent.load(Ordering::SeqCst);
-                let failed = self.packets_failed.load(Ordering::SeqCst);
-                info!("パケット処理完了 - 成功: {}, 失敗: {}", sent, failed);
+        let mut poll_span = tracer.start_with_context("packet.poll", &cx);
+        let polled = self.poll_packets(db).await;
+        if let Err(e) = &polled {
+            poll_span.set_status(Status::error(e.to_string()));
+        }
+        poll_span.end();
 
-                // パケット送信数をリセット
-                self.packets_sent.store(0, Ordering::SeqCst);
-                self.packets_failed.store(0, Ordering::SeqCst);
+        match polled {
+            Ok((packets, fetched_ids)) => {
+                let packet_count = packets.len();
+                debug!("{}個のパケットを取得しました", packet_count);
+                let batch_span = debug_span!("inject_batch", batch_size = packet_count);
+                let mut inject_span = tracer.start_with_context("packet.inject", &cx);
+                inject_span.set_attribute(KeyValue::new("batch.size", packet_count as i64));
+
+                let (sent, failed) = self.send_packets(packets).instrument(batch_span).await?;
+                inject_span.set_attribute(KeyValue::new("packets.sent", sent as i64));
+                inject_span.set_attribute(KeyValue::new("packets.failed", failed as i64));
+                inject_span.end();
+                debug!("パケット処理完了 - 成功: {}, 失敗: {}", sent, failed);
+
+                // 記録に失敗しても、メモリ上の送信済みidにより遡り範囲内の重複は防げる
+                if let Err(e) = self.acknowledge(db, &fetched_ids).await {
+                    warn!("処理済みパケットの記録に失敗しました: {}", e);
+                }
+                // 注入と記録が済んでから位置を保存し、再起動時に取りこぼさないようにする
+                if !fetched_ids.is_empty() {
+                    if let Err(e) = self.save_cursor(db).await {
+                        warn!("ポーリング位置の保存に失敗しました: {}", e);
+                    }
+                }
 
                 Ok(())
             }
             Err(e) => {
-                error!("ポーリングとパケット送信中のエラー: {:?}", e);
+                cx.span().set_status(Status::error(e.to_string()));
                 Err(e)
             }
         }
     }
 }
 
-pub async fn inject_packet(interface: NetworkInterface) -> Result<(), PacketError> {
+// 受信したパケットにファイアウォールのINPUTチェインを適用する
+fn input_allowed(packet: &PacketInfo) -> bool {
+    // SIPで学習した通話中のRTP・RTCPは、一致するルールがない場合に許可する
+    let media = packet.ip_protocol == 17
+        && sip::is_media(
+            (packet.src_ip, packet.src_port.unwrap_or(0) as u16),
+            (packet.dst_ip, packet.dst_port.unwrap_or(0) as u16),
+        );
+    let firewall_packet = FirewallPacket::new(
+        packet.src_ip,
+        packet.dst_ip,
+        packet.src_port.unwrap_or(0) as u16,
+        packet.dst_port.unwrap_or(0) as u16,
+        match packet.src_ip {
+            IpAddr::V4(_) => 4,
+            IpAddr::V6(_) => 6,
+        },
+    )
+    .with_macs(packet.src_mac.0, packet.dst_mac.0)
+    .with_ether_type(packet.ether_type as u16);
+    FIREWALL
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .chain(Chain::Input)
+        .check_with_pinhole(firewall_packet, media)
+}
+
+fn inbound_summary(packet: &PacketInfo, allowed: bool) -> PacketSummary {
+    PacketSummary {
+        timestamp: packet.timestamp,
+        direction: Direction::Inbound,
+        src_ip: packet.src_ip,
+        dst_ip: packet.dst_ip,
+        src_port: packet.src_port.unwrap_or(0) as u16,
+        dst_port: packet.dst_port.unwrap_or(0) as u16,
+        ip_protocol: packet.ip_protocol as u8,
+        length: packet.raw_packet.len(),
+        allowed,
+    }
+}
+
+pub async fn inject_packet(interface: NetworkInterface, mode: PollMode) -> Result<(), PacketError> {
     let my_ip = interface.ips
         .iter()
         .find(|ip| ip.is_ipv4())
         .map(|ip| ip.ip())
         .ok_or_else(|| PacketError::DeviceError("IPv4アドレスが見つかりません".to_string()))?;
 
-    info!("パケット転送を開始します: {}", my_ip);
-
-    let poller = PacketPoller::new(my_ip, interface);
-    let mut interval = interval(Duration::from_millis(500));
+    let span = info_span!("poller", interface = %interface.name, node = %my_ip);
 
-    loop {
-        interval.tick().await;
+    async move {
+        info!("{}", message(MessageId::ForwardingStarted, &[&my_ip]));
 
-        if let Err(e) = poller.poll_and_send_packets().await {
-            error!("パケット処理中にエラーが発生しました: {:?}", e);
-        }
+        let poller = PacketPoller::new(my_ip, interface, mode);
+        probe::set_node_ip(my_ip);
+        transport()?.subscribe(&poller).await
     }
+    .instrument(span)
+    .await
 }
\ No newline at end of file
@@ -0,0 +1,276 @@
+use crate::chunk::Chunk;
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::replication::slot_name;
+use crate::database::types::MacAddr;
+use crate::db_read::{PacketError, PacketInfo, PacketPoller, MAX_RESUME_AGE};
+use crate::health;
+use crate::transport::transport;
+use pgwire_replication::{ReplicationClient, ReplicationEvent};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tokio::time::{timeout, Duration};
+use tokio_postgres::types::{FromSql, Type};
+use tracing::{debug, info, trace, warn};
+
+// 変更が届かない間も稼働状態を報告する間隔
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+// pgoutputのRelationメッセージで通知されるテーブルの列定義
+struct Relation {
+    columns: Vec<(String, Type)>,
+    // packetsテーブル (またはそのチャンク) かどうか
+    is_packets: bool,
+}
+
+// pgoutput (proto_version 1, バイナリ形式) のメッセージからpacketsテーブルへの挿入を取り出す
+#[derive(Default)]
+struct PgOutputDecoder {
+    relations: HashMap<u32, Relation>,
+}
+
+impl PgOutputDecoder {
+    fn decode(&mut self, data: &[u8]) -> Result<Option<(i64, PacketInfo)>, String> {
+        let mut reader = Reader::new(data);
+        match reader.u8()? {
+            b'R' => {
+                let oid = reader.u32()?;
+                let namespace = reader.string()?;
+                let name = reader.string()?;
+                let _replica_identity = reader.u8()?;
+                let column_count = reader.i16()?;
+                let mut columns = Vec::with_capacity(column_count.max(0) as usize);
+                for _ in 0..column_count {
+                    let _flags = reader.u8()?;
+                    let column = reader.string()?;
+                    let type_oid = reader.u32()?;
+                    let _type_modifier = reader.i32()?;
+                    let ty = Type::from_oid(type_oid).unwrap_or(Type::UNKNOWN);
+                    columns.push((column, ty));
+                }
+                // ハイパーテーブルの場合、行はチャンク (_timescaledb_internal._hyper_*_chunk) に格納される
+                let is_packets = (namespace == "public" && name == "packets")
+                    || (namespace == "_timescaledb_internal"
+                        && ["id", "dst_ip", "raw_packet"]
+                            .iter()
+                            .all(|required| columns.iter().any(|(column, _)| column == required)));
+                debug!("リレーション情報を受信しました: {}.{} (packets: {})", namespace, name, is_packets);
+                self.relations.insert(oid, Relation { columns, is_packets });
+                Ok(None)
+            }
+            b'I' => {
+                let oid = reader.u32()?;
+                let Some(relation) = self.relations.get(&oid) else {
+                    return Err(format!("未知のリレーション (oid {}) への挿入を受信しました", oid));
+                };
+                if !relation.is_packets {
+                    return Ok(None);
+                }
+                if reader.u8()? != b'N' {
+                    return Err("挿入メッセージの形式が不正です".to_string());
+                }
+                let values = reader.tuple()?;
+                let row = Row { columns: &relation.columns, values: &values };
+                Ok(Some((row.get("id")?, row.packet_info()?)))
+            }
+            // Type, Origin, Truncate などは使用しない
+            _ => Ok(None),
+        }
+    }
+}
+
+// 受信した1行分の列の値
+struct Row<'a> {
+    columns: &'a [(String, Type)],
+    values: &'a [Option<&'a [u8]>],
+}
+
+impl Row<'_> {
+    fn get<'b, T: FromSql<'b>>(&'b self, name: &str) -> Result<T, String> {
+        let index = self
+            .columns
+            .iter()
+            .position(|(column, _)| column == name)
+            .ok_or_else(|| format!("列 {} がありません", name))?;
+        let raw = self.values.get(index).copied().flatten();
+        T::from_sql_nullable(&self.columns[index].1, raw)
+            .map_err(|e| format!("列 {} の変換に失敗しました: {}", name, e))
+    }
+
+    fn packet_info(&self) -> Result<PacketInfo, String> {
+        Ok(PacketInfo {
+            src_mac: self.get::<MacAddr>("src_mac")?,
+            dst_mac: self.get::<MacAddr>("dst_mac")?,
+            ether_type: self.get("ether_type")?,
+            src_ip: self.get::<IpAddr>("src_ip")?,
+            dst_ip: self.get::<IpAddr>("dst_ip")?,
+            src_port: self.get("src_port")?,
+            dst_port: self.get("dst_port")?,
+            ip_protocol: self.get("ip_protocol")?,
+            timestamp: self.get("timestamp")?,
+            data: self.get::<Option<Vec<u8>>>("data")?.unwrap_or_default(),
+            raw_packet: self.get::<Option<Vec<u8>>>("raw_packet")?.unwrap_or_default(),
+            // 列を追加する前に作成されたリレーションの情報には含まれない
+            seq: self.get("seq").ok().flatten(),
+            codec: self.get::<Option<i16>>("codec").ok().flatten().unwrap_or_default(),
+            chunk: Chunk::from_columns(
+                self.get("chunk_id").ok().flatten(),
+                self.get("chunk_index").ok().flatten(),
+                self.get("chunk_count").ok().flatten(),
+            ),
+        })
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("pgoutputメッセージが途中で終わっています".to_string());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let end = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| "pgoutputメッセージの文字列が終端していません".to_string())?;
+        let value = String::from_utf8_lossy(&self.data[..end]).into_owned();
+        self.data = &self.data[end + 1..];
+        Ok(value)
+    }
+
+    // TupleData: 列ごとに n (NULL), u (変更なしのTOAST値), t/b (長さ付きの値)
+    fn tuple(&mut self) -> Result<Vec<Option<&'a [u8]>>, String> {
+        let count = self.i16()?;
+        let mut values = Vec::with_capacity(count.max(0) as usize);
+        for _ in 0..count {
+            match self.u8()? {
+                b'n' | b'u' => values.push(None),
+                b'b' => {
+                    let len = self.i32()?;
+                    values.push(Some(self.take(len.max(0) as usize)?));
+                }
+                b't' => return Err("テキスト形式の値には対応していません".to_string()),
+                kind => return Err(format!("未知の列の種類です: {}", kind as char)),
+            }
+        }
+        Ok(values)
+    }
+}
+
+// コミットされたトランザクションに含まれるパケットを注入する
+async fn deliver(db: &Database, poller: &PacketPoller, batch: Vec<(i64, PacketInfo)>) -> Result<(), PacketError> {
+    let oldest = chrono::Utc::now()
+        - chrono::Duration::from_std(MAX_RESUME_AGE).unwrap_or_else(|_| chrono::Duration::zero());
+    let (fresh, stale): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .filter(|(_, packet)| poller.is_addressed_to_node(packet))
+        .partition(|(_, packet)| packet.timestamp >= oldest);
+    // 停止中にスロットへ溜まったパケットは、ポーリングの再開時と同様に注入しない
+    if !stale.is_empty() {
+        debug!("古いパケットを {} 件スキップしました", stale.len());
+    }
+    if fresh.is_empty() {
+        return Ok(());
+    }
+
+    // 再接続時にスロットから再送された行を重複して注入しないよう、記録できた行のみ注入する
+    let ids: Vec<i64> = fresh.iter().map(|(id, _)| *id).collect();
+    let claimed = poller.claim(db, &ids).await?;
+    let packets: Vec<PacketInfo> = fresh
+        .into_iter()
+        .filter(|(id, _)| claimed.contains(id))
+        .map(|(_, packet)| packet)
+        .filter(|packet| poller.should_process_packet(packet))
+        .collect();
+    if packets.is_empty() {
+        return Ok(());
+    }
+
+    let (sent, failed) = poller.send_packets(packets).await?;
+    trace!("パケット処理完了 - 成功: {}, 失敗: {}", sent, failed);
+    Ok(())
+}
+
+// 論理レプリケーションでpacketsテーブルへの挿入を受信し、コミットされ次第注入する。
+// 注入後にコミット位置をスロットへ確認済みとして通知し、再接続時はその位置から再開する
+pub async fn stream_packets(db: &Database, poller: &PacketPoller) -> Result<(), PacketError> {
+    let slot = slot_name(poller.node_ip());
+    db.ensure_packet_publication().await?;
+    db.ensure_replication_slot(&slot).await?;
+
+    let mut client = ReplicationClient::connect(db.replication_config(&slot))
+        .await
+        .map_err(DbError::from)?;
+    info!("論理レプリケーションでパケットの受信を開始します: スロット {}", slot);
+
+    let mut decoder = PgOutputDecoder::default();
+    let mut batch: Vec<(i64, PacketInfo)> = Vec::new();
+    loop {
+        let event = match timeout(HEARTBEAT_INTERVAL, client.recv()).await {
+            Ok(event) => event.map_err(DbError::from)?,
+            Err(_) => {
+                health::record_poller_heartbeat();
+                // 挿入がない間は、データベースに接続できていれば受信できているとみなす
+                if let Ok(transport) = transport() {
+                    if transport.health_check().await {
+                        health::record_poll_success();
+                    }
+                }
+                continue;
+            }
+        };
+        health::record_poller_heartbeat();
+        health::record_poll_success();
+
+        let Some(event) = event else {
+            return Err(PacketError::DatabaseError(DbError::Other(
+                "レプリケーションストリームが終了しました".to_string(),
+            )));
+        };
+        match event {
+            ReplicationEvent::Begin { .. } => batch.clear(),
+            ReplicationEvent::XLogData { data, .. } => match decoder.decode(&data) {
+                Ok(Some(packet)) => batch.push(packet),
+                Ok(None) => {}
+                Err(e) => warn!("レプリケーションメッセージの解析に失敗しました: {}", e),
+            },
+            ReplicationEvent::Commit { end_lsn, .. } => {
+                if !batch.is_empty() {
+                    debug!("{}個のパケットを受信しました", batch.len());
+                    deliver(db, poller, std::mem::take(&mut batch)).await?;
+                }
+                client.update_applied_lsn(end_lsn);
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,75 +1,44 @@
-use crate::database::database::Database;
-use crate::firewall::{Filter, IpFirewall, Policy};
+use crate::buffer_pool::FRAME_POOL;
+use crate::chunk::{self, Chunk};
+use crate::compression::{self, Codec};
+use crate::dedup;
+use crate::encapsulation;
+use crate::database::error::DbError;
+use crate::database::types::{Bytea, InetAddr, MacAddr};
+use crate::events::{self, Direction, PacketSummary, PipelineEvent};
+use crate::firewall::{Chain, FIREWALL};
 use crate::firewall_packet::FirewallPacket;
-use crate::packet_header::{parse_ip_header, parse_next_ip_header};
-use bytes::BytesMut;
+use crate::health;
+use crate::messages::{message, MessageId};
+use crate::metrics;
+use crate::nat::{self, Translation};
+use crate::pmtu;
+use crate::qos;
+use crate::security::{self, sip};
+use crate::segmentation;
+use crate::setup_logger::LogThrottle;
+use crate::sequence;
+use crate::split_tunnel;
+use crate::telemetry;
+use crate::traffic;
+use crate::transport::{transport, TransportError};
+use crate::packet_header::{parse_ip_header, EthernetHeader};
+use bytes::{Bytes, BytesMut};
 use chrono::Utc;
-use lazy_static::lazy_static;
-use log::{debug, error, info, trace};
-use tokio::sync::mpsc;
-use futures::StreamExt;
-use pnet::packet::ip::IpNextHeaderProtocol;
-use postgres_types::FromSql;
-use std::collections::HashMap;
+use opentelemetry::trace::{Link, Span, SpanContext, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use futures::future::join_all;
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+use std::collections::hash_map::DefaultHasher;
 use std::error::Error;
-use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::OnceLock;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio::task::JoinSet;
 use tokio::time::interval;
 use tokio_postgres::types::{IsNull, ToSql, Type};
-use crate::database::error::DbError;
-
-#[derive(Debug, Clone)]
-pub struct MacAddr(pub [u8; 6]);
-
-impl fmt::Display for MacAddr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mac_string = self.0.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":");
-        write!(f, "{}", mac_string)
-    }
-}
-
-impl ToSql for MacAddr {
-    fn to_sql(
-        &self,
-        _ty: &Type,
-        out: &mut BytesMut,
-    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
-        out.extend_from_slice(&self.0);
-        Ok(IsNull::No)
-    }
-
-    fn accepts(ty: &Type) -> bool {
-        ty.name() == "macaddr"
-    }
-
-    fn to_sql_checked(
-        &self,
-        ty: &Type,
-        out: &mut BytesMut,
-    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
-        self.to_sql(ty, out)
-    }
-}
-
-impl<'a> FromSql<'a> for MacAddr {
-    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
-        if raw.len() != 6 {
-            error!("MACアドレスの長さが不正です");
-            return Err("Invalid MAC address length".into());
-        }
-        let mut addr = [0u8; 6];
-        addr.copy_from_slice(raw);
-        Ok(MacAddr(addr))
-    }
-
-    fn accepts(ty: &Type) -> bool {
-        ty.name() == "macaddr"
-    }
-}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Protocol(i32);
@@ -212,202 +181,280 @@ impl ToSql for Protocol {
     }
 }
 
-// PostgreSQLのinet型のためのラッパー構造体
+// データベースに保存するパケット情報の構造体
 #[derive(Debug, Clone)]
-struct InetAddr(IpAddr);
+pub struct PacketData {
+    pub src_mac: MacAddr,
+    pub dst_mac: MacAddr,
+    pub ether_type: Protocol,
+    pub src_ip: InetAddr,
+    pub dst_ip: InetAddr,
+    pub src_port: i32,
+    pub dst_port: i32,
+    pub ip_protocol: Protocol,   // IPプロトコルを保存
+    pub timestamp: chrono::DateTime<Utc>,
+    pub data: Bytea,
+    pub raw_packet: Bytea,
+    // キャプチャしたインターフェース名
+    pub interface: String,
+    // 受信側で欠落と重複を数えるための (送信元, 宛先) ごとの連番
+    pub seq: Option<i64>,
+    // IPパケットのDSCPとECN (IP以外はNone)
+    pub dscp: Option<i16>,
+    pub ecn: Option<i16>,
+    // raw_packetとdataの圧縮方式
+    pub codec: Codec,
+    // 1行の上限を超えるフレームを分割した場合の断片の情報 (同じフレームの断片に共通のid, 何番目か, 断片の数)
+    pub chunk_id: Option<i64>,
+    pub chunk_index: Option<i16>,
+    pub chunk_count: Option<i16>,
+    // カプセル化 (GRE・IP-in-IP・VXLAN) の種類と内側のパケットの情報 ([decapsulation] record_inner が有効な場合のみ)
+    pub encapsulation: Option<i16>,
+    pub inner_src_ip: Option<InetAddr>,
+    pub inner_dst_ip: Option<InetAddr>,
+    pub inner_src_port: Option<i32>,
+    pub inner_dst_port: Option<i32>,
+    pub inner_ip_protocol: Option<i32>,
+    // VXLANのVNI (VXLANでカプセル化されたパケットの場合のみ)
+    pub vni: Option<i32>,
+    // キャプチャ時のスパン。一括書き込みのスパンからリンクする
+    trace_context: Option<SpanContext>,
+}
 
-impl ToSql for InetAddr {
-    fn to_sql(
-        &self,
-        _ty: &Type,
-        out: &mut BytesMut,
-    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
-        match self.0 {
-            IpAddr::V4(addr) => {
-                out.extend_from_slice(&[2]);     // AF_INET
-                out.extend_from_slice(&[32]);    // /32
-                out.extend_from_slice(&[1]);     // is_cidr
-                out.extend_from_slice(&[4]);     // IPv4 = 4 bytes
-                out.extend_from_slice(&addr.octets());
-            }
-            IpAddr::V6(addr) => {
-                out.extend_from_slice(&[3]);     // AF_INET6
-                out.extend_from_slice(&[128]);   // /128
-                out.extend_from_slice(&[1]);     // is_cidr
-                out.extend_from_slice(&[16]);    // IPv6 = 16 bytes
-                out.extend_from_slice(&addr.octets());
-            }
-        }
-        Ok(IsNull::No)
+impl PacketData {
+    // フレームのバッファをプールに返却する (dataも同じバッファを参照するため先に解放する)
+    fn recycle(self) {
+        let PacketData { data, raw_packet, .. } = self;
+        drop(data);
+        FRAME_POOL.recycle(raw_packet.0);
     }
 
-    fn accepts(ty: &Type) -> bool {
-        ty.name() == "inet"
+    // raw_packetを書き換える。dataは同じバッファを参照しているため、先に解放してから参照し直す
+    pub fn edit_frame<R>(&mut self, edit: impl FnOnce(&mut [u8]) -> R) -> R {
+        let data_offset = self.raw_packet.len() - self.data.len();
+        self.data = Bytea::default();
+        let raw_packet = std::mem::take(&mut self.raw_packet.0);
+        let mut frame = raw_packet.try_into_mut().unwrap_or_else(|shared| {
+            let mut frame = FRAME_POOL.get();
+            frame.extend_from_slice(&shared);
+            frame
+        });
+        let result = edit(&mut frame);
+        let frame = frame.freeze();
+        self.data = Bytea(frame.slice(data_offset..));
+        self.raw_packet = Bytea(frame);
+        result
     }
 
-    fn to_sql_checked(
-        &self,
-        ty: &Type,
-        out: &mut BytesMut,
-    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
-        self.to_sql(ty, out)
+    // 設定に従ってraw_packetとdataを圧縮する。小さくならない場合は圧縮しない
+    fn compress(&mut self) {
+        let Some((codec, level)) = compression::codec_for(self.raw_packet.len()) else {
+            return;
+        };
+        let compressed = compression::compress(codec, level, &self.raw_packet)
+            .and_then(|raw_packet| Ok((raw_packet, compression::compress(codec, level, &self.data)?)));
+        match compressed {
+            Ok((raw_packet, data)) if raw_packet.len() + data.len() < self.raw_packet.len() + self.data.len() => {
+                let data = std::mem::replace(&mut self.data, Bytea(data.into()));
+                drop(data);
+                let raw_packet = std::mem::replace(&mut self.raw_packet, Bytea(raw_packet.into()));
+                FRAME_POOL.recycle(raw_packet.0);
+                self.codec = codec;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("パケットの圧縮に失敗しました: {}", e),
+        }
     }
-}
 
-// データベースに保存するパケット情報の構造体
-#[derive(Debug, Clone)]
-struct PacketData {
-    src_mac: MacAddr,
-    dst_mac: MacAddr,
-    ether_type: Protocol,
-    src_ip: InetAddr,
-    dst_ip: InetAddr,
-    src_port: i32,
-    dst_port: i32,
-    ip_protocol: Protocol,   // IPプロトコルを保存
-    timestamp: chrono::DateTime<Utc>,
-    data: Vec<u8>,
-    raw_packet: Vec<u8>,
-}
-
-// パケット統計情報の収集用構造体
-#[derive(Debug)]
-struct PacketStats {
-    total_packets: AtomicU64,
-    total_bytes: AtomicU64,
-    protocol_counts: Arc<Mutex<HashMap<Protocol, u64>>>,
-    port_counts: Arc<Mutex<HashMap<u16, u64>>>,
-    last_reset: Arc<Mutex<SystemTime>>,
-}
-
-impl PacketStats {
-    fn new() -> Self {
-        Self {
-            total_packets: AtomicU64::new(0),
-            total_bytes: AtomicU64::new(0),
-            protocol_counts: Arc::new(Mutex::new(HashMap::new())),
-            port_counts: Arc::new(Mutex::new(HashMap::new())),
-            last_reset: Arc::new(Mutex::new(SystemTime::now())),
+    // 1行の上限を超える場合は、raw_packetとdataをそれぞれ同じ数の断片に分割した行にする (圧縮後に分割する)
+    fn push_chunks(self, rows: &mut Vec<PacketData>) {
+        let count = self.raw_packet.len().max(self.data.len()).div_ceil(chunk::MAX_ROW_SIZE);
+        if count <= 1 {
+            rows.push(self);
+            return;
+        }
+        if count > chunk::MAX_CHUNKS {
+            warn!("フレームが大きすぎるため破棄します: {} bytes", self.raw_packet.len());
+            metrics::PACKETS_DROPPED.with_label_values(&["oversize"]).inc();
+            self.recycle();
+            return;
+        }
+        let id = Chunk::new_id();
+        for index in 0..count {
+            let raw_packet = self.raw_packet.0.slice(chunk::piece_range(self.raw_packet.len(), count, index));
+            let data = self.data.0.slice(chunk::piece_range(self.data.len(), count, index));
+            rows.push(PacketData {
+                data: Bytea(data),
+                raw_packet: Bytea(raw_packet),
+                chunk_id: Some(id),
+                chunk_index: Some(index as i16),
+                chunk_count: Some(count as i16),
+                ..self.clone()
+            });
         }
     }
+}
 
-    // 統計情報の更新
-    async fn update(&self, protocol: Protocol, size: u64, src_port: u16, dst_port: u16) {
-        self.total_packets.fetch_add(1, Ordering::Relaxed);
-        self.total_bytes.fetch_add(size, Ordering::Relaxed);
+// 一括書き込みのスパンに付与するリンク数の上限
+const MAX_BATCH_SPAN_LINKS: usize = 128;
+// バッファをトランスポートへ送信する間隔
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+// トランスポートの停止中などに、フラッシュの失敗を毎回ログへ出力しないようにする (破棄数はメトリクスで数える)
+static FLUSH_ERRORS: LogThrottle = LogThrottle::new(Duration::from_secs(10));
+
+// 書き込みワーカーごとのバッファ
+#[derive(Default)]
+struct WriterShard {
+    buffer: Mutex<Vec<PacketData>>,
+    // 送信を終えたバッチ。容量を保ったまま次のフラッシュでバッファと入れ替える
+    spare: std::sync::Mutex<Vec<PacketData>>,
+}
 
-        let mut protocol_counts = self.protocol_counts.lock().await;
-        *protocol_counts.entry(protocol).or_insert(0) += 1;
+static WRITER_SHARDS: OnceLock<Box<[WriterShard]>> = OnceLock::new();
 
-        let mut port_counts = self.port_counts.lock().await;
-        if src_port > 0 {
-            *port_counts.entry(src_port).or_insert(0) += 1;
-        }
-        if dst_port > 0 {
-            *port_counts.entry(dst_port).or_insert(0) += 1;
-        }
+// 書き込みワーカー数を設定する。最初のパケットを書き込む前に呼び出す (呼び出さない場合は1)
+pub fn init_writer_shards(workers: usize) {
+    if WRITER_SHARDS.set((0..workers.max(1)).map(|_| WriterShard::default()).collect()).is_err() {
+        warn!("書き込みワーカー数は設定済みのため変更しません");
     }
 }
 
-lazy_static! {
-    static ref PACKET_BUFFER: Arc<Mutex<Vec<PacketData>>> = Arc::new(Mutex::new(Vec::new()));
-    static ref FIREWALL: IpFirewall = {
-        let mut fw = IpFirewall::new(Policy::Blacklist);
-        fw.add_rule(Filter::IpAddress("160.251.175.134".parse().unwrap()), 100);
-        fw.add_rule(Filter::Port(13432), 90);
-        fw.add_rule(Filter::Port(2222), 80);
-        fw
-    };
+fn writer_shards() -> &'static [WriterShard] {
+    WRITER_SHARDS.get_or_init(|| Box::new([WriterShard::default()]))
 }
 
+// フローを割り当てるワーカー。両方向のパケットが同じワーカーになるよう、送信元と宛先を並べ替えてハッシュする
+fn shard_index(packet: &PacketData, shards: usize) -> usize {
+    if shards == 1 {
+        return 0;
+    }
+    let source = (packet.src_ip.ip(), packet.src_port);
+    let destination = (packet.dst_ip.ip(), packet.dst_port);
+    let endpoints = if source <= destination { (source, destination) } else { (destination, source) };
+    let mut hasher = DefaultHasher::new();
+    (endpoints, packet.ip_protocol).hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+// ワーカーごとに同じ間隔でバッファをトランスポートへ送信する。いずれかのワーカーが終了するか、
+// この関数のFutureが破棄されると全てのワーカーを停止する
 pub async fn start_packet_writer() {
-    info!("パケットライターを開始します");
-    let mut interval_timer = interval(Duration::from_millis(100));
+    let shards = writer_shards();
+    info!("{}", message(MessageId::WriterStarted, &[&shards.len()]));
+
+    let mut workers = JoinSet::new();
+    for (index, shard) in shards.iter().enumerate() {
+        workers.spawn(async move {
+            let mut interval_timer = interval(FLUSH_INTERVAL);
+            loop {
+                interval_timer.tick().await;
+                health::record_writer_heartbeat();
+
+                if let Err(e) = flush_shard(index, shard).await {
+                    if let Some(suppressed) = FLUSH_ERRORS.allow() {
+                        error!("{}", message(MessageId::FlushFailed, &[&index, &e, &suppressed]));
+                    }
+                }
+            }
+        });
+    }
+    if let Some(Err(e)) = workers.join_next().await {
+        error!("{}", message(MessageId::WriterWorkerFailed, &[&e]));
+    }
+}
 
-    loop {
-        interval_timer.tick().await;
+// 全てのワーカーのバッファをトランスポートへ送信し、送信した件数を返す
+pub async fn flush_packet_buffer() -> Result<usize, TransportError> {
+    let results = join_all(writer_shards().iter().enumerate().map(|(index, shard)| flush_shard(index, shard))).await;
+    results.into_iter().try_fold(0, |total, result| result.map(|count| total + count))
+}
 
-        let packets = {
-            let mut buffer = PACKET_BUFFER.lock().await;
-            if buffer.is_empty() {
-                continue;
+// 終了時にバッファに残っているパケットを送信し、(送信した件数, 破棄した件数) を返す。
+// 各ワーカーのバッファの送信がtimeout以内に終わらない場合や送信に失敗した場合は、そのバッファのパケットを破棄する
+pub async fn drain_packet_buffer(timeout: Duration) -> (usize, usize) {
+    let results = join_all(writer_shards().iter().enumerate().map(|(index, shard)| async move {
+        let pending = shard.buffer.lock().await.len();
+        match tokio::time::timeout(timeout, flush_shard(index, shard)).await {
+            Ok(Ok(count)) => (count, 0),
+            Ok(Err(e)) => {
+                error!("{}", message(MessageId::ShutdownFlushFailed, &[&index, &e]));
+                (0, pending)
             }
-            buffer.drain(..).collect::<Vec<_>>()
-        };
-
-        if !packets.is_empty() {
-            let start = std::time::Instant::now();
-            match process_packets(packets).await {
-                Ok(_) => {
-                    let duration = start.elapsed();
-                    debug!("フラッシュ完了: 処理時間 {}ms", duration.as_millis());
-                }
-                Err(e) => {
-                    error!("パケットバッファのフラッシュに失敗しました: {}", e);
-                }
+            Err(_) => {
+                metrics::PACKETS_DROPPED.with_label_values(&["shutdown"]).inc_by(pending as u64);
+                (0, pending)
             }
         }
-    }
+    }))
+    .await;
+    results.into_iter().fold((0, 0), |(flushed, dropped), (count, lost)| (flushed + count, dropped + lost))
 }
 
-async fn process_packets(packets: Vec<PacketData>) -> Result<(), crate::database::error::DbError> {
-    const CHUNK_SIZE: usize = 1000;
-
-    let db = Database::get_database();
-    let mut client = db.pool.get().await?;
-    let transaction = client.transaction().await?;
-
-    let mut processed = 0;
-    let start_time = std::time::Instant::now();
-
-    for chunk in packets.chunks(CHUNK_SIZE) {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
-        for packet in chunk {
-            params.extend_from_slice(&[
-                &packet.src_mac,
-                &packet.dst_mac,
-                &packet.ether_type,
-                &packet.src_ip,
-                &packet.dst_ip,
-                &packet.src_port,
-                &packet.dst_port,
-                &packet.ip_protocol,
-                &packet.timestamp,
-                &packet.data,
-                &packet.raw_packet,
-            ]);
+// ワーカーのバッファ内のパケットを送信し、送信した件数を返す
+async fn flush_shard(index: usize, shard: &WriterShard) -> Result<usize, TransportError> {
+    let mut packets = {
+        let mut buffer = shard.buffer.lock().await;
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let spare = std::mem::take(&mut *shard.spare.lock().unwrap_or_else(|e| e.into_inner()));
+        metrics::BUFFER_DEPTH.sub(buffer.len() as i64);
+        std::mem::replace(&mut *buffer, spare)
+    };
+
+    let start = std::time::Instant::now();
+    let count = packets.len();
+    packets.iter_mut().for_each(PacketData::compress);
+    if packets.iter().any(|packet| packet.raw_packet.len().max(packet.data.len()) > chunk::MAX_ROW_SIZE) {
+        let mut rows = Vec::with_capacity(packets.len());
+        packets.drain(..).for_each(|packet| packet.push_chunks(&mut rows));
+        packets = rows;
+    }
+
+    let tracer = telemetry::tracer();
+    let links = packets
+        .iter()
+        .filter_map(|packet| packet.trace_context.clone())
+        .filter(|context| context.is_sampled())
+        .take(MAX_BATCH_SPAN_LINKS)
+        .map(Link::with_context)
+        .collect();
+    let mut span = tracer
+        .span_builder("packet.write_batch")
+        .with_links(links)
+        .with_attributes([KeyValue::new("batch.size", count as i64), KeyValue::new("batch.worker", index as i64)])
+        .start(&tracer);
+
+    let result = async { transport()?.publish(&packets).await }
+        .instrument(info_span!("write_batch", batch_size = count, worker = index))
+        .await;
+    match &result {
+        Ok(_) => {
+            let duration = start.elapsed();
+            metrics::DB_INSERT_LATENCY.observe(duration.as_secs_f64());
+            metrics::PACKETS_WRITTEN.inc_by(count as u64);
+            health::record_flush_success();
+            debug!("フラッシュ完了: 処理時間 {}ms", duration.as_millis());
+        }
+        Err(e) => {
+            metrics::PACKETS_DROPPED.with_label_values(&["db_error"]).inc_by(count as u64);
+            span.set_status(Status::error(e.to_string()));
         }
+    }
+    span.end();
+
+    // 送信に失敗したパケットも破棄されるため、成否にかかわらずバッファを返却する
+    packets.drain(..).for_each(PacketData::recycle);
+    *shard.spare.lock().unwrap_or_else(|e| e.into_inner()) = packets;
 
-        let placeholders: Vec<String> = (0..chunk.len())
-            .map(|i| {
-                format!("(${},${},${},${},${},${},${},${},${},${},${})",
-                        i * 11 + 1, i * 11 + 2, i * 11 + 3, i * 11 + 4, i * 11 + 5,
-                        i * 11 + 6, i * 11 + 7, i * 11 + 8, i * 11 + 9, i * 11 + 10,
-                        i * 11 + 11)
-            })
-            .collect();
-
-        let query = format!(
-            "INSERT INTO packets (
-                src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
-                ip_protocol, timestamp, data, raw_packet
-            ) VALUES {}",
-            placeholders.join(",")
-        );
-
-        transaction.execute(&query, &params).await?;
-        processed += chunk.len();
-    }
-
-    transaction.commit().await?;
-    info!("{}個のパケットを{}秒で一括挿入しました",
-        processed, start_time.elapsed().as_secs_f64());
-    Ok(())
+    result.map(|_| count)
 }
 
+
 // イーサネットパケットの解析
-async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData, crate::database::error::DbError> {
-    async fn inner_parse(ethernet_packet: &[u8], depth: u8) -> Result<PacketData, crate::database::error::DbError> {
+// dataとraw_packetは受け取ったバッファを共有する
+pub async fn parse_and_analyze_packet(ethernet_packet: Bytes) -> Result<PacketData, crate::database::error::DbError> {
+    async fn inner_parse(ethernet_packet: Bytes, depth: u8) -> Result<PacketData, crate::database::error::DbError> {
         if depth > 5 || ethernet_packet.len() < 14 {
             return Ok(create_empty_packet_data(ethernet_packet));
         }
@@ -427,16 +474,19 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
         let mut dst_ip = IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0));
         let mut payload_offset: usize = 14;
         let mut ip_protocol = Protocol::UNKNOWN;
+        let mut parsed_ip_header = None;
 
         let ether_type = u16::from_be_bytes([ethernet_packet[12], ethernet_packet[13]]);
         let ether_type_protocol = Protocol::from_u16(ether_type);
 
         match ether_type {
             0x0800 => { // IPv4
-                if ethernet_packet.len() > 23 {
+                // 送信元・宛先アドレスまでの固定長ヘッダ (20バイト)
+                if ethernet_packet.len() >= 34 {
                     if let Some(ip_header) = parse_ip_header(&ethernet_packet[14..]) {
                         src_ip = ip_header.src_ip;
                         dst_ip = ip_header.dst_ip;
+                        parsed_ip_header = Some(ip_header);
 
                         let ihl = (ethernet_packet[14] & 0x0F) as usize * 4;
                         payload_offset = 14 + ihl;
@@ -445,23 +495,21 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
                         ip_protocol = Protocol::ip(protocol as i32);
 
                         match protocol {
-                            6 | 17 => { // TCP or UDP
-                                if ethernet_packet.len() >= payload_offset + 4 {
-                                    src_port = u16::from_be_bytes([
-                                        ethernet_packet[payload_offset],
-                                        ethernet_packet[payload_offset + 1]
-                                    ]);
-                                    dst_port = u16::from_be_bytes([
-                                        ethernet_packet[payload_offset + 2],
-                                        ethernet_packet[payload_offset + 3]
-                                    ]);
-
-                                    if protocol == 6 && ethernet_packet.len() > payload_offset + 12 {
-                                        let tcp_offset = ((ethernet_packet[payload_offset + 12] >> 4) as usize) * 4;
-                                        payload_offset += tcp_offset;
-                                    } else {
-                                        payload_offset += 8;
-                                    }
+                            6 | 17 if ethernet_packet.len() >= payload_offset + 4 => { // TCP or UDP
+                                src_port = u16::from_be_bytes([
+                                    ethernet_packet[payload_offset],
+                                    ethernet_packet[payload_offset + 1]
+                                ]);
+                                dst_port = u16::from_be_bytes([
+                                    ethernet_packet[payload_offset + 2],
+                                    ethernet_packet[payload_offset + 3]
+                                ]);
+
+                                if protocol == 6 && ethernet_packet.len() > payload_offset + 12 {
+                                    let tcp_offset = ((ethernet_packet[payload_offset + 12] >> 4) as usize) * 4;
+                                    payload_offset += tcp_offset;
+                                } else {
+                                    payload_offset += 8;
                                 }
                             },
                             _ => {}
@@ -470,26 +518,34 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
                 }
             }
             0x86DD => { // IPv6
-                if ethernet_packet.len() > 54 {
+                // 固定長ヘッダ (40バイト)
+                if ethernet_packet.len() >= 54 {
                     if let Some(ip_header) = parse_ip_header(&ethernet_packet[14..]) {
                         src_ip = ip_header.src_ip;
                         dst_ip = ip_header.dst_ip;
+                        parsed_ip_header = Some(ip_header);
 
                         let next_header = ethernet_packet[20];
                         ip_protocol = Protocol::ip(next_header as i32);
                         payload_offset = 54;
 
                         match next_header {
-                            6 | 17 => { // TCP or UDP
-                                if ethernet_packet.len() >= payload_offset + 4 {
-                                    src_port = u16::from_be_bytes([
-                                        ethernet_packet[payload_offset],
-                                        ethernet_packet[payload_offset + 1]
-                                    ]);
-                                    dst_port = u16::from_be_bytes([
-                                        ethernet_packet[payload_offset + 2],
-                                        ethernet_packet[payload_offset + 3]
-                                    ]);
+                            6 | 17 if ethernet_packet.len() >= payload_offset + 4 => { // TCP or UDP
+                                src_port = u16::from_be_bytes([
+                                    ethernet_packet[payload_offset],
+                                    ethernet_packet[payload_offset + 1]
+                                ]);
+                                dst_port = u16::from_be_bytes([
+                                    ethernet_packet[payload_offset + 2],
+                                    ethernet_packet[payload_offset + 3]
+                                ]);
+
+                                // IPv4と同様に、トランスポート層のヘッダを除いた部分をdataとする
+                                if next_header == 6 && ethernet_packet.len() > payload_offset + 12 {
+                                    let tcp_offset = ((ethernet_packet[payload_offset + 12] >> 4) as usize) * 4;
+                                    payload_offset += tcp_offset;
+                                } else {
+                                    payload_offset += 8;
                                 }
                             },
                             _ => {}
@@ -498,7 +554,8 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
                 }
             }
             0x0806 => { // ARP
-                if ethernet_packet.len() >= 28 {
+                // IPv4のARP (送信元・対象のプロトコルアドレスまで28バイト)
+                if ethernet_packet.len() >= 42 {
                     let sender_ip_bytes = &ethernet_packet[28..32];
                     let target_ip_bytes = &ethernet_packet[38..42];
                     src_ip = IpAddr::V4(std::net::Ipv4Addr::new(
@@ -512,7 +569,12 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
                 }
             }
             _ => {
-                return Ok(create_empty_packet_data(ethernet_packet));
+                // IP以外のプロトコルもEtherTypeとMACアドレスでファイアウォールを適用できるようにする
+                let mut packet_data = create_empty_packet_data(ethernet_packet);
+                packet_data.src_mac = src_mac;
+                packet_data.dst_mac = dst_mac;
+                packet_data.ether_type = ether_type_protocol;
+                return Ok(packet_data);
             }
         }
 
@@ -520,14 +582,31 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
             src_mac,
             dst_mac,
             ether_type: ether_type_protocol,
-            src_ip: InetAddr(src_ip),
-            dst_ip: InetAddr(dst_ip),
+            src_ip: InetAddr::from(src_ip),
+            dst_ip: InetAddr::from(dst_ip),
             src_port: src_port as i32,
             dst_port: dst_port as i32,
             ip_protocol,
             timestamp: Utc::now(),
-            data: ethernet_packet[payload_offset..].to_vec(),
-            raw_packet: ethernet_packet.to_vec(),
+            // ヘッダ長の値がフレームより長い場合は空とする
+            data: Bytea(ethernet_packet.slice(payload_offset.min(ethernet_packet.len())..)),
+            raw_packet: Bytea(ethernet_packet),
+            interface: String::new(),
+            seq: None,
+            dscp: parsed_ip_header.map(|header| header.dscp() as i16),
+            ecn: parsed_ip_header.map(|header| header.ecn() as i16),
+            codec: Codec::None,
+            chunk_id: None,
+            chunk_index: None,
+            chunk_count: None,
+            encapsulation: None,
+            inner_src_ip: None,
+            inner_dst_ip: None,
+            inner_src_port: None,
+            inner_dst_port: None,
+            inner_ip_protocol: None,
+            vni: None,
+            trace_context: None,
         })
     }
 
@@ -535,37 +614,152 @@ async fn parse_and_analyze_packet(ethernet_packet: &[u8]) -> Result<PacketData,
 }
 
 // パケットの書き込みエントリーポイント
-pub async fn rdb_tunnel_packet_write(ethernet_packet: &[u8]) -> Result<(), crate::database::error::DbError> {
+pub async fn rdb_tunnel_packet_write(ethernet_packet: Bytes, interface: &str) -> Result<(), DbError> {
     if ethernet_packet.len() < 14 {
         error!("Invalid ethernet packet length");
         return Ok(());
     }
+    if dedup::is_duplicate(&ethernet_packet) {
+        trace!("重複したブロードキャストフレームを破棄しました ({})", interface);
+        metrics::PACKETS_DROPPED.with_label_values(&["duplicate"]).inc();
+        return Ok(());
+    }
+
+    // オフロードで結合されたTCPフレームは、MTUに収まるセグメントに分割してから書き込む
+    let mtu = segmentation::mtu();
+    if ethernet_packet.len() > EthernetHeader::LEN + mtu {
+        if let Some(segments) = segmentation::segment_tcp(&ethernet_packet, mtu) {
+            trace!("MTUを超えるTCPフレームを{}個のセグメントに分割しました ({} bytes)", segments.len(), ethernet_packet.len());
+            metrics::FRAMES_SEGMENTED.inc();
+            FRAME_POOL.recycle(ethernet_packet);
+            for segment in segments {
+                write_frame(segment, interface).await?;
+            }
+            return Ok(());
+        }
+        // 経路上で断片化できないパケットは破棄し、送信元にMTUを通知する
+        if pmtu::is_enabled() {
+            if let Some(reply) = pmtu::too_big_reply(&ethernet_packet, mtu) {
+                debug!("MTU ({} bytes) を超えるパケットを破棄しました ({} bytes)", mtu, ethernet_packet.len());
+                metrics::PACKETS_DROPPED.with_label_values(&["too_big"]).inc();
+                pmtu::send_reply(interface, &reply);
+                FRAME_POOL.recycle(ethernet_packet);
+                return Ok(());
+            }
+        }
+    }
+    write_frame(ethernet_packet, interface).await
+}
+
+async fn write_frame(ethernet_packet: Bytes, interface: &str) -> Result<(), DbError> {
+    let tracer = telemetry::tracer();
+    let mut capture_span = tracer.start("packet.capture");
+    capture_span.set_attribute(KeyValue::new("packet.size", ethernet_packet.len() as i64));
+    capture_span.set_attribute(KeyValue::new("packet.interface", interface.to_string()));
+    let cx = Context::current_with_span(capture_span);
 
     match parse_and_analyze_packet(ethernet_packet).await {
-        Ok(packet_data) => {
-            let firewall_packet = FirewallPacket::new(
-                packet_data.src_ip.0,
-                packet_data.dst_ip.0,
-                packet_data.src_port as u16,
-                packet_data.dst_port as u16,
-                match packet_data.src_ip.0 {
-                    IpAddr::V4(_) => 4,
-                    IpAddr::V6(_) => 6,
-                },
-            );
-
-            if FIREWALL.check(firewall_packet) {
+        Ok(mut packet_data) => {
+            // GRE・IP-in-IP・VXLANでカプセル化されたパケットは内側もファイアウォールとIDPSで検査する
+            let inner = encapsulation::decapsulate(&packet_data).await;
+            if let Some((kind, inner)) = &inner {
+                encapsulation::record(&mut packet_data, *kind, inner);
+            }
+            if !security::inspect(&packet_data) || inner.as_ref().is_some_and(|(_, inner)| !security::inspect(inner)) {
+                debug!("プロトコルの規則によりパケットを破棄: {}:{} -> {}:{}",
+                    packet_data.src_ip.ip(), packet_data.src_port,
+                    packet_data.dst_ip.ip(), packet_data.dst_port
+                );
+                metrics::PACKETS_DROPPED.with_label_values(&["idps"]).inc();
+                packet_data.recycle();
+                return Ok(());
+            }
+            // 対向ノード側のサブネット宛でないパケットはトンネルに流さない
+            if !split_tunnel::is_tunneled(packet_data.dst_ip.ip()) {
+                trace!("トンネルの対象外の宛先のため書き込みません: {}", packet_data.dst_ip.ip());
+                metrics::PACKETS_EXCLUDED.inc();
+                packet_data.recycle();
+                return Ok(());
+            }
+            packet_data.interface = interface.to_string();
+            let firewall_packet = to_firewall_packet(&packet_data);
+
+            // SIPで学習した通話中のRTP・RTCPは、一致するルールがない場合に許可する
+            let media = packet_data.ip_protocol == Protocol::UDP
+                && sip::is_media(
+                    (packet_data.src_ip.ip(), packet_data.src_port as u16),
+                    (packet_data.dst_ip.ip(), packet_data.dst_port as u16),
+                );
+            let allowed = {
+                let mut firewall_span = tracer.start_with_context("packet.firewall", &cx);
+                let allowed = {
+                    let firewall = FIREWALL.read().unwrap_or_else(|e| e.into_inner());
+                    let output = firewall.chain(Chain::Output);
+                    output.check_with_pinhole(firewall_packet, media)
+                        && inner.as_ref().is_none_or(|(_, inner)| output.check(to_firewall_packet(inner)))
+                };
+                firewall_span.set_attribute(KeyValue::new("firewall.allowed", allowed));
+                allowed
+            };
+
+            if allowed {
+                traffic::record(
+                    Direction::Outbound,
+                    packet_data.ip_protocol.0 as u8,
+                    packet_data.dst_ip.ip(),
+                    traffic::service_port(packet_data.src_port as u16, packet_data.dst_port as u16),
+                    packet_data.raw_packet.len(),
+                );
+            }
+
+            if events::has_subscribers() {
+                events::publish(PipelineEvent::Packet(PacketSummary {
+                    timestamp: packet_data.timestamp,
+                    direction: Direction::Outbound,
+                    src_ip: packet_data.src_ip.ip(),
+                    dst_ip: packet_data.dst_ip.ip(),
+                    src_port: packet_data.src_port as u16,
+                    dst_port: packet_data.dst_port as u16,
+                    ip_protocol: packet_data.ip_protocol.0 as u8,
+                    length: packet_data.raw_packet.len(),
+                    allowed,
+                }));
+            }
+
+            if allowed {
                 trace!("許可：firewall_packet: {}:{} -> {}:{}",
-                    packet_data.src_ip.0, packet_data.src_port,
-                    packet_data.dst_ip.0, packet_data.dst_port
+                    packet_data.src_ip.ip(), packet_data.src_port,
+                    packet_data.dst_ip.ip(), packet_data.dst_port
                 );
 
-                PACKET_BUFFER.lock().await.push(packet_data);
+                // 拠点内のクライアントの送信元は、ファイアウォールの判定後に自ノードのアドレスへ変換する
+                if nat::translate_outbound(&mut packet_data) == Translation::Exhausted {
+                    debug!("送信元NATのポートが不足しているためパケットを破棄: {}:{} -> {}:{}",
+                        packet_data.src_ip.ip(), packet_data.src_port,
+                        packet_data.dst_ip.ip(), packet_data.dst_port
+                    );
+                    metrics::PACKETS_DROPPED.with_label_values(&["nat_exhausted"]).inc();
+                    packet_data.recycle();
+                    return Ok(());
+                }
+                if let Some(dscp) = media.then(sip::media_dscp).flatten() {
+                    qos::mark(&mut packet_data, dscp);
+                } else {
+                    qos::remark(&mut packet_data, &firewall_packet);
+                }
+                packet_data.trace_context = Some(cx.span().span_context().clone());
+                packet_data.seq = sequence::next(packet_data.src_ip.ip(), packet_data.dst_ip.ip());
+                let shards = writer_shards();
+                let shard = &shards[shard_index(&packet_data, shards.len())];
+                shard.buffer.lock().await.push(packet_data);
+                metrics::BUFFER_DEPTH.inc();
             } else {
+                metrics::FIREWALL_DROPS.inc();
                 trace!("不許可：firewall_packet: {}:{} -> {}:{}",
-                    packet_data.src_ip.0, packet_data.src_port,
-                    packet_data.dst_ip.0, packet_data.dst_port
+                    packet_data.src_ip.ip(), packet_data.src_port,
+                    packet_data.dst_ip.ip(), packet_data.dst_port
                 );
+                packet_data.recycle();
             }
             Ok(())
         }
@@ -576,18 +770,394 @@ pub async fn rdb_tunnel_packet_write(ethernet_packet: &[u8]) -> Result<(), crate
     }
 }
 
-fn create_empty_packet_data(raw_packet: &[u8]) -> PacketData {
+// ファイアウォールで判定するパケットの情報
+fn to_firewall_packet(packet: &PacketData) -> FirewallPacket {
+    FirewallPacket::new(
+        packet.src_ip.ip(),
+        packet.dst_ip.ip(),
+        packet.src_port as u16,
+        packet.dst_port as u16,
+        match packet.src_ip.ip() {
+            IpAddr::V4(_) => 4,
+            IpAddr::V6(_) => 6,
+        },
+    )
+    .with_macs(packet.src_mac.0, packet.dst_mac.0)
+    .with_ether_type(packet.ether_type.as_i32() as u16)
+    .with_vni(packet.vni.map(|vni| vni as u32))
+}
+
+fn create_empty_packet_data(raw_packet: Bytes) -> PacketData {
     PacketData {
         src_mac: MacAddr([0; 6]),
         dst_mac: MacAddr([0; 6]),
         ether_type: Protocol::UNKNOWN,
-        src_ip: InetAddr(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0))),
-        dst_ip: InetAddr(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0))),
+        src_ip: InetAddr::from(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0))),
+        dst_ip: InetAddr::from(IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0))),
         src_port: 0,
         dst_port: 0,
         ip_protocol: Protocol::UNKNOWN,
         timestamp: Utc::now(),
-        data: Vec::new(),
-        raw_packet: raw_packet.to_vec(),
+        data: Bytea::default(),
+        raw_packet: Bytea(raw_packet),
+        interface: String::new(),
+        seq: None,
+        dscp: None,
+        ecn: None,
+        codec: Codec::None,
+        chunk_id: None,
+        chunk_index: None,
+        chunk_count: None,
+        encapsulation: None,
+        inner_src_ip: None,
+        inner_dst_ip: None,
+        inner_src_port: None,
+        inner_dst_port: None,
+        inner_ip_protocol: None,
+        vni: None,
+        trace_context: None,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{
+        arp_request, frame_spec, truncated_frame, udp_frame, Node, NODE_A, NODE_B, PIPELINE_LOCK,
+    };
+    use crate::config::{QosConfig, RemarkRule, WriterConfig};
+    use crate::firewall::{Filter, Rule};
+    use crate::management;
+    use crate::packet_header::internet_checksum;
+    use crate::transport::init_memory_transport;
+    use futures::executor::block_on;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::net::Ipv4Addr;
+
+    // ファイアウォールの既定の規則で拒否されるポート
+    const BLOCKED_PORT: u16 = 13432;
+
+    // テストで変更したプロセス共通の設定を、アサーションが失敗した場合も含めて元に戻す (PIPELINE_LOCK より後に宣言して保持する)
+    struct Restore<F: FnMut()>(F);
+
+    impl<F: FnMut()> Drop for Restore<F> {
+        fn drop(&mut self) {
+            (self.0)()
+        }
+    }
+
+    // 送信側のパイプライン (キャプチャ -> ファイアウォール -> バッファ) に渡す
+    async fn capture(frame: &[u8]) {
+        rdb_tunnel_packet_write(Bytes::copy_from_slice(frame), "test0").await.expect("フレームを書き込めません");
+    }
+
+    #[tokio::test]
+    async fn delivers_captured_frame_to_destination_node() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_a = Node::start(&transport, NODE_A);
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        let frame = udp_frame(NODE_A, NODE_B, 5000, b"hello");
+        capture(&frame).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+
+        let packet = node_b.receive().await;
+        assert_eq!(packet.src_ip, IpAddr::V4(NODE_A));
+        assert_eq!(packet.dst_ip, IpAddr::V4(NODE_B));
+        assert_eq!(packet.ether_type, Protocol::IP_V4.as_i32());
+        assert_eq!(packet.ip_protocol, Protocol::UDP.as_i32());
+        assert_eq!(packet.data, b"hello");
+        assert_eq!(packet.raw_packet, frame);
+
+        // 後続のパケットが届いた時点で、先に送信したパケットが届いていないことを確認する
+        capture(&udp_frame(NODE_B, NODE_A, 5000, b"reply")).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+        assert_eq!(node_a.receive().await.dst_ip, IpAddr::V4(NODE_A));
+        node_a.assert_nothing_injected();
+        node_b.assert_nothing_injected();
+    }
+
+    #[tokio::test]
+    async fn drains_buffered_frames_on_shutdown() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"first")).await;
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"second")).await;
+        assert_eq!(drain_packet_buffer(Duration::from_secs(1)).await, (2, 0));
+        assert_eq!(drain_packet_buffer(Duration::from_secs(1)).await, (0, 0));
+
+        assert_eq!(node_b.receive().await.data, b"first");
+        assert_eq!(node_b.receive().await.data, b"second");
+        node_b.assert_nothing_injected();
+    }
+
+    #[tokio::test]
+    async fn drops_frame_blocked_by_firewall() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        capture(&udp_frame(NODE_A, NODE_B, BLOCKED_PORT, b"blocked")).await;
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"allowed")).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+
+        assert_eq!(node_b.receive().await.data, b"allowed");
+        node_b.assert_nothing_injected();
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn drops_injected_frame_blocked_by_input_chain() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+        let input_port = 6000;
+        let rule = Rule { filter: Filter::Port(input_port), priority: 10, schedule: None };
+        management::add_firewall_rule(Chain::Input, rule).unwrap();
+        let _restore = Restore(|| {
+            management::remove_firewall_rule(Chain::Input, &Filter::Port(input_port));
+        });
+
+        // OUTPUTチェインでは許可されるが、受信側のINPUTチェインで破棄される
+        capture(&udp_frame(NODE_A, NODE_B, input_port, b"blocked")).await;
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"allowed")).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 2);
+
+        assert_eq!(node_b.receive().await.data, b"allowed");
+        node_b.assert_nothing_injected();
+    }
+
+    #[tokio::test]
+    async fn remarks_dscp_of_matching_frame() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+        qos::configure(&QosConfig {
+            remark: vec![RemarkRule { filter: Some(Filter::Port(5060)), from_dscp: None, dscp: 46, priority: 10 }],
+        });
+        let _restore = Restore(|| qos::configure(&QosConfig::default()));
+
+        let mut frame = udp_frame(NODE_A, NODE_B, 5060, b"invite");
+        // ECN (ECT(0)) は書き換えない
+        frame[15] = 0x02;
+        capture(&frame).await;
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"other")).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 2);
+
+        let remarked = node_b.receive().await;
+        assert_eq!(remarked.data, b"invite");
+        assert_eq!(remarked.raw_packet[15], (46 << 2) | 0x02);
+        assert_eq!(internet_checksum(&[&remarked.raw_packet[14..34]]), 0);
+        assert_eq!(node_b.receive().await.raw_packet[15], 0);
+    }
+
+    #[tokio::test]
+    async fn writes_only_frames_to_allowed_subnets() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+        split_tunnel::configure(&["10.0.0.2/32".parse().unwrap()]);
+        let _restore = Restore(|| split_tunnel::configure(&[]));
+
+        capture(&udp_frame(NODE_A, Ipv4Addr::new(192, 168, 0, 1), 5000, b"internet")).await;
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"tunneled")).await;
+        capture(&arp_request(NODE_A, NODE_B)).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 2);
+
+        assert_eq!(node_b.receive().await.data, b"tunneled");
+        assert_eq!(node_b.receive().await.ether_type, Protocol::ARP.as_i32());
+        node_b.assert_nothing_injected();
+    }
+
+    #[tokio::test]
+    async fn reassembles_frame_split_across_rows() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        // GSOで結合されたような、1行の上限を超えるフレーム
+        let payload = (0..4000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let frame = udp_frame(NODE_A, NODE_B, 5000, &payload);
+        capture(&frame).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+
+        let packet = node_b.receive().await;
+        assert_eq!(packet.raw_packet, frame);
+        assert_eq!(packet.data, payload);
+        assert_eq!(packet.chunk, None);
+        node_b.assert_nothing_injected();
+    }
+
+    #[tokio::test]
+    async fn delivers_compressed_frame_unchanged() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        let frame = udp_frame(NODE_A, NODE_B, 5000, &b"compressible ".repeat(32));
+        let _restore = Restore(|| compression::configure(&WriterConfig::default()).unwrap());
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            compression::configure(&WriterConfig { compression: codec, ..Default::default() }).unwrap();
+            capture(&frame).await;
+            assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+
+            let packet = node_b.receive().await;
+            assert_eq!(packet.raw_packet, frame);
+            assert_eq!(packet.data, b"compressible ".repeat(32));
+        }
+    }
+
+    #[tokio::test]
+    async fn ignores_truncated_frame() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+
+        capture(&truncated_frame()).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn delivers_arp_request_to_target_node() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        capture(&arp_request(NODE_A, NODE_B)).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+
+        let packet = node_b.receive().await;
+        assert_eq!(packet.ether_type, Protocol::ARP.as_i32());
+        assert_eq!(packet.src_ip, IpAddr::V4(NODE_A));
+        assert_eq!(packet.dst_ip, IpAddr::V4(NODE_B));
+    }
+
+    #[tokio::test]
+    async fn drops_frames_by_ether_type() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+        let rule = Rule { filter: Filter::EtherType(0x0806), priority: 10, schedule: None };
+        management::add_firewall_rule(Chain::Output, rule).unwrap();
+        let _restore = Restore(|| {
+            management::remove_firewall_rule(Chain::Output, &Filter::EtherType(0x0806));
+        });
+
+        capture(&arp_request(NODE_A, NODE_B)).await;
+        capture(&udp_frame(NODE_A, NODE_B, 5000, b"ipv4")).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+        assert_eq!(node_b.receive().await.data, b"ipv4");
+
+        // IP以外のフレームもEtherTypeとMACアドレスを保持する
+        let mut ipx = arp_request(NODE_A, NODE_B);
+        ipx[12..14].copy_from_slice(&0x8137u16.to_be_bytes());
+        let packet_data = parse_and_analyze_packet(Bytes::from(ipx)).await.unwrap();
+        assert_eq!(packet_data.ether_type, Protocol::IPX);
+        assert_eq!(packet_data.dst_mac, MacAddr([0xff; 6]));
+    }
+
+    #[tokio::test]
+    async fn delivers_broadcast_frame_to_every_node() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_a = Node::start(&transport, NODE_A);
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        capture(&udp_frame(NODE_A, Ipv4Addr::BROADCAST, 67, b"discover")).await;
+        assert_eq!(flush_packet_buffer().await.unwrap(), 1);
+
+        assert_eq!(node_a.receive().await.dst_ip, IpAddr::V4(Ipv4Addr::BROADCAST));
+        assert_eq!(node_b.receive().await.dst_ip, IpAddr::V4(Ipv4Addr::BROADCAST));
+    }
+
+    #[tokio::test]
+    async fn flushes_captured_frames_in_order() {
+        let _guard = PIPELINE_LOCK.lock().await;
+        let transport = init_memory_transport();
+        flush_packet_buffer().await.unwrap();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        for payload in [b"1", b"2", b"3"] {
+            capture(&udp_frame(NODE_A, NODE_B, 5000, payload)).await;
+        }
+        assert_eq!(flush_packet_buffer().await.unwrap(), 3);
+
+        for payload in [b"1", b"2", b"3"] {
+            assert_eq!(node_b.receive().await.data, payload);
+        }
+    }
+
+    #[tokio::test]
+    async fn assigns_both_directions_of_flow_to_same_worker() {
+        let packet = |src, dst, dst_port| async move {
+            parse_and_analyze_packet(udp_frame(src, dst, dst_port, b"flow").into()).await.unwrap()
+        };
+        let mut workers = std::collections::HashSet::new();
+        for dst_port in 5000..5064 {
+            let request = packet(NODE_A, NODE_B, dst_port).await;
+            let mut reply = request.clone();
+            std::mem::swap(&mut reply.src_ip, &mut reply.dst_ip);
+            std::mem::swap(&mut reply.src_port, &mut reply.dst_port);
+
+            let worker = shard_index(&request, 4);
+            assert_eq!(shard_index(&reply, 4), worker);
+            assert_eq!(shard_index(&request, 1), 0);
+            workers.insert(worker);
+        }
+        // 複数のフローが全てのワーカーに分散する
+        assert_eq!(workers.len(), 4);
+    }
+
+    proptest! {
+        // 生成したフレームを解析すると、生成時の各層の値が復元される
+        #[test]
+        fn parse_reproduces_built_headers(spec in frame_spec()) {
+            let frame = spec.to_frame();
+            let packet = block_on(parse_and_analyze_packet(Bytes::from(frame.clone()))).unwrap();
+            let (src_ip, dst_ip) = spec.addresses();
+            let (src_port, dst_port) = spec.transport().map(|transport| transport.ports()).unwrap_or_default();
+            let protocol = spec.transport().map(|transport| transport.protocol()).unwrap_or_default();
+
+            prop_assert_eq!(packet.src_mac.0, spec.src_mac);
+            prop_assert_eq!(packet.dst_mac.0, spec.dst_mac);
+            prop_assert_eq!(packet.ether_type, Protocol::from_u16(spec.ether_type()));
+            prop_assert_eq!(packet.src_ip.ip(), src_ip);
+            prop_assert_eq!(packet.dst_ip.ip(), dst_ip);
+            prop_assert_eq!(packet.ip_protocol, Protocol::from_u8(protocol));
+            prop_assert_eq!(packet.src_port, src_port as i32);
+            prop_assert_eq!(packet.dst_port, dst_port as i32);
+            prop_assert_eq!(&packet.data[..], spec.data(&frame));
+            prop_assert_eq!(&packet.raw_packet[..], frame);
+            // dataはコピーせず、raw_packetの末尾を共有する
+            prop_assert_eq!(packet.data.as_ptr_range().end, packet.raw_packet.as_ptr_range().end);
+        }
+
+        // 不正なフレームでもパニックしない
+        #[test]
+        fn parse_accepts_arbitrary_bytes(bytes in vec(any::<u8>(), 0..128)) {
+            let packet = block_on(parse_and_analyze_packet(Bytes::from(bytes.clone()))).unwrap();
+            prop_assert_eq!(&packet.raw_packet[..], bytes);
+        }
+
+        // IPv4・IPv6・ARPのヘッダの直後で途切れたフレームでもパニックしない
+        #[test]
+        fn parse_accepts_truncated_frames(spec in frame_spec(), cut in 0usize..128) {
+            let frame = Bytes::from(spec.to_frame());
+            let truncated = frame.slice(..frame.len().min(cut));
+            prop_assert!(block_on(parse_and_analyze_packet(truncated)).is_ok());
+        }
+    }
+}
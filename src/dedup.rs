@@ -0,0 +1,92 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// 物理インターフェースとtap0の両方でキャプチャされた同一のブロードキャスト・マルチキャストフレームを、
+// 内容のハッシュで検出して2回目以降を書き込まないようにする
+
+// 記録するフレームの上限 (これを超えた場合は古いものから忘れる)
+const MAX_TRACKED_FRAMES: usize = 4096;
+
+// 重複の検出。設定されていない場合は検出しない
+static CACHE: Mutex<Option<DedupCache>> = Mutex::new(None);
+
+#[derive(Debug)]
+struct DedupCache {
+    window: Duration,
+    // フレームのハッシュ -> 最初にキャプチャした時刻
+    seen: HashMap<u64, Instant>,
+    // 記録した順のハッシュ (期限切れの削除用)
+    order: VecDeque<(u64, Instant)>,
+}
+
+impl DedupCache {
+    fn new(window: Duration) -> Self {
+        Self { window, seen: HashMap::new(), order: VecDeque::new() }
+    }
+
+    // window以内に同じハッシュのフレームをキャプチャしていればtrue
+    fn check(&mut self, hash: u64, now: Instant) -> bool {
+        while let Some(&(oldest, at)) = self.order.front() {
+            if now.saturating_duration_since(at) < self.window && self.order.len() < MAX_TRACKED_FRAMES {
+                break;
+            }
+            self.order.pop_front();
+            if self.seen.get(&oldest) == Some(&at) {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        if self.seen.contains_key(&hash) {
+            return true;
+        }
+        self.seen.insert(hash, now);
+        self.order.push_back((hash, now));
+        false
+    }
+}
+
+// 設定を反映する (windowが0の場合は検出しない)
+pub fn configure(window: Duration) {
+    let cache = (!window.is_zero()).then(|| DedupCache::new(window));
+    if cache.is_some() {
+        info!("ブロードキャスト・マルチキャストの重複を {:?} 以内で検出します", window);
+    }
+    *CACHE.lock().unwrap_or_else(|e| e.into_inner()) = cache;
+}
+
+// 直前にキャプチャしたものと同一のブロードキャスト・マルチキャストフレームであればtrue
+pub fn is_duplicate(frame: &[u8]) -> bool {
+    // 宛先MACアドレスのI/Gビットが1のフレームのみ対象とする
+    if frame.first().is_none_or(|octet| octet & 0x01 == 0) {
+        return false;
+    }
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(cache) = cache.as_mut() else {
+        return false;
+    };
+    let mut hasher = DefaultHasher::new();
+    frame.hash(&mut hasher);
+    cache.check(hasher.finish(), Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_repeats_within_window() {
+        let now = Instant::now();
+        let mut cache = DedupCache::new(Duration::from_millis(50));
+        assert!(!cache.check(1, now));
+        assert!(cache.check(1, now + Duration::from_millis(10)));
+        assert!(!cache.check(2, now + Duration::from_millis(10)));
+        // 最初のキャプチャからwindowが経過すると、同じ内容でも書き込む
+        assert!(!cache.check(1, now + Duration::from_millis(60)));
+        assert!(cache.check(1, now + Duration::from_millis(70)));
+        assert_eq!(cache.seen.len(), 1);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::config::DecapsulationConfig;
+use crate::db_write::{parse_and_analyze_packet, PacketData, Protocol};
+use bytes::{BufMut, Bytes, BytesMut};
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+use tracing::trace;
+
+// GRE・IP-in-IP・VXLANでカプセル化されたパケットの内側を取り出し、ファイアウォールとIDPSで検査できるようにする。
+// 内側のIPパケットには外側のMACアドレスでイーサネットヘッダを付け、通常のパケットと同じ解析に渡す
+
+// 入れ子になったカプセル化を取り出す回数の上限
+const MAX_DEPTH: usize = 4;
+// GREのプロトコルタイプ (Transparent Ethernet Bridging)
+const GRE_ETHERNET: u16 = 0x6558;
+// VXLANヘッダのVNIが有効であることを示すフラグ (I)
+const VXLAN_VNI_VALID: u8 = 0x08;
+
+lazy_static! {
+    static ref CONFIG: RwLock<DecapsulationConfig> = RwLock::new(DecapsulationConfig::default());
+}
+
+pub fn configure(config: &DecapsulationConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+// 保存する encapsulation 列の値 (外側のIPプロトコル番号、VXLANはUDPのポート番号 4789)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encapsulation {
+    // IPv4・IPv6のIP-in-IP (4)
+    IpInIp,
+    // IPv6のカプセル化 (41)
+    Ipv6,
+    Gre,
+    // VXLAN (VNI)
+    Vxlan(u32),
+}
+
+impl Encapsulation {
+    pub fn code(self) -> i16 {
+        match self {
+            Encapsulation::IpInIp => 4,
+            Encapsulation::Ipv6 => 41,
+            Encapsulation::Gre => 47,
+            Encapsulation::Vxlan(_) => 4789,
+        }
+    }
+
+    // 保存する vni 列の値 (VXLAN以外はNone)
+    pub fn vni(self) -> Option<i32> {
+        match self {
+            Encapsulation::Vxlan(vni) => Some(vni as i32),
+            _ => None,
+        }
+    }
+}
+
+// 内側のIPパケットに外側のMACアドレスのイーサネットヘッダを付ける
+fn ethernet_frame(outer: &PacketData, ip_packet: &[u8]) -> Option<Bytes> {
+    let ether_type: u16 = match ip_packet.first()? >> 4 {
+        4 => 0x0800,
+        6 => 0x86dd,
+        _ => return None,
+    };
+    let mut frame = BytesMut::with_capacity(14 + ip_packet.len());
+    frame.put_slice(&outer.dst_mac.0);
+    frame.put_slice(&outer.src_mac.0);
+    frame.put_u16(ether_type);
+    frame.put_slice(ip_packet);
+    Some(frame.freeze())
+}
+
+// GREヘッダ (RFC 2784/2890) の長さとプロトコルタイプ。バージョン0のみ対応する
+fn gre_header(payload: &[u8]) -> Option<(usize, u16)> {
+    let flags = *payload.first()?;
+    if payload.get(1)? & 0x07 != 0 {
+        return None;
+    }
+    let protocol = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    // チェックサム (C)・キー (K)・シーケンス番号 (S) があればそれぞれ4バイト
+    let options = [0x80, 0x20, 0x10].iter().filter(|&&bit| flags & bit != 0).count();
+    Some((4 + options * 4, protocol))
+}
+
+// VXLANヘッダ (RFC 7348、8バイト) のVNIと内側のイーサネットフレーム
+fn vxlan(payload: &[u8]) -> Option<(u32, &[u8])> {
+    if payload.first()? & VXLAN_VNI_VALID == 0 {
+        return None;
+    }
+    let vni = u32::from_be_bytes([0, *payload.get(4)?, *payload.get(5)?, *payload.get(6)?]);
+    let frame = payload.get(8..).filter(|frame| frame.len() >= 14)?;
+    Some((vni, frame))
+}
+
+// カプセル化されたパケットであれば、種類と内側のイーサネットフレームを返す
+pub fn inner_frame(packet: &PacketData, vxlan_ports: &[u16]) -> Option<(Encapsulation, Bytes)> {
+    if packet.ether_type != Protocol::IP_V4 && packet.ether_type != Protocol::IP_V6 {
+        return None;
+    }
+    let payload = &packet.data;
+    match packet.ip_protocol.as_i32() {
+        4 => Some((Encapsulation::IpInIp, ethernet_frame(packet, payload)?)),
+        41 => Some((Encapsulation::Ipv6, ethernet_frame(packet, payload)?)),
+        47 => {
+            let (length, protocol) = gre_header(payload)?;
+            let inner = payload.get(length..)?;
+            let frame = match protocol {
+                GRE_ETHERNET if inner.len() >= 14 => Bytes::copy_from_slice(inner),
+                0x0800 | 0x86dd => ethernet_frame(packet, inner)?,
+                _ => return None,
+            };
+            Some((Encapsulation::Gre, frame))
+        }
+        17 if vxlan_ports.contains(&(packet.dst_port as u16)) => {
+            let (vni, frame) = vxlan(payload)?;
+            Some((Encapsulation::Vxlan(vni), Bytes::copy_from_slice(frame)))
+        }
+        _ => None,
+    }
+}
+
+// 最も内側のパケットを解析する。カプセル化されていない場合・無効な場合はNone
+// VXLANを経由した場合は、最も内側のVXLANのVNIを内側のパケットの vni に設定する
+pub async fn decapsulate(packet: &PacketData) -> Option<(Encapsulation, PacketData)> {
+    let vxlan_ports = {
+        let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+        if !config.enabled {
+            return None;
+        }
+        config.vxlan_ports.clone()
+    };
+    let (mut encapsulation, frame) = inner_frame(packet, &vxlan_ports)?;
+    let mut inner = parse_and_analyze_packet(frame).await.ok()?;
+    let mut vni = encapsulation.vni().or(packet.vni);
+    for _ in 1..MAX_DEPTH {
+        let Some((next, frame)) = inner_frame(&inner, &vxlan_ports) else {
+            break;
+        };
+        let Ok(parsed) = parse_and_analyze_packet(frame).await else {
+            break;
+        };
+        (encapsulation, inner) = (next, parsed);
+        vni = encapsulation.vni().or(vni);
+    }
+    inner.vni = vni;
+    trace!(
+        "カプセル化されたパケット ({:?}): {}:{} -> {}:{}",
+        encapsulation,
+        inner.src_ip.ip(),
+        inner.src_port,
+        inner.dst_ip.ip(),
+        inner.dst_port
+    );
+    Some((encapsulation, inner))
+}
+
+// VXLANのVNIを外側のパケットに記録し、record_inner が有効な場合は内側のパケットの情報も外側のパケットの列に記録する
+pub fn record(outer: &mut PacketData, encapsulation: Encapsulation, inner: &PacketData) {
+    outer.vni = inner.vni;
+    if !CONFIG.read().unwrap_or_else(|e| e.into_inner()).record_inner {
+        return;
+    }
+    outer.encapsulation = Some(encapsulation.code());
+    outer.inner_src_ip = Some(inner.src_ip.clone());
+    outer.inner_dst_ip = Some(inner.dst_ip.clone());
+    outer.inner_src_port = Some(inner.src_port);
+    outer.inner_dst_port = Some(inner.dst_port);
+    outer.inner_ip_protocol = Some(inner.ip_protocol.as_i32());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_header::{Ipv4Header, UdpHeader};
+    use std::net::Ipv4Addr;
+
+    fn ipv4(src: [u8; 4], dst: [u8; 4], protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Ipv4Header::new(Ipv4Addr::from(src), Ipv4Addr::from(dst), protocol, payload.len() as u16).to_bytes().to_vec();
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    fn frame(ip_packet: &[u8]) -> Bytes {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 0x02, 0x02, 0, 0, 0, 0, 0x01, 0x08, 0x00];
+        frame.extend_from_slice(ip_packet);
+        Bytes::from(frame)
+    }
+
+    fn inner_udp() -> Vec<u8> {
+        let udp = UdpHeader { src_port: 40000, dst_port: 53, payload_len: 4 };
+        let mut segment = udp.to_bytes().to_vec();
+        segment.extend_from_slice(b"test");
+        ipv4([10, 1, 0, 5], [10, 2, 0, 9], 17, &segment)
+    }
+
+    #[tokio::test]
+    async fn decapsulates_ip_in_ip_and_gre() {
+        configure(&DecapsulationConfig { enabled: true, record_inner: true, ..Default::default() });
+        let ipip = parse_and_analyze_packet(frame(&ipv4([192, 0, 2, 1], [192, 0, 2, 2], 4, &inner_udp()))).await.unwrap();
+        let (encapsulation, inner) = decapsulate(&ipip).await.unwrap();
+        assert_eq!(encapsulation, Encapsulation::IpInIp);
+        assert_eq!((inner.src_ip.ip(), inner.dst_port), ("10.1.0.5".parse().unwrap(), 53));
+        assert_eq!(inner.src_mac, ipip.src_mac);
+
+        // キーとシーケンス番号付きのGRE
+        let mut gre = vec![0x30, 0x00, 0x08, 0x00, 0, 0, 0, 7, 0, 0, 0, 1];
+        gre.extend_from_slice(&inner_udp());
+        let mut outer = parse_and_analyze_packet(frame(&ipv4([192, 0, 2, 1], [192, 0, 2, 2], 47, &gre))).await.unwrap();
+        let (encapsulation, inner) = decapsulate(&outer).await.unwrap();
+        assert_eq!(encapsulation, Encapsulation::Gre);
+        assert_eq!(inner.dst_ip.ip(), "10.2.0.9".parse::<std::net::IpAddr>().unwrap());
+        record(&mut outer, encapsulation, &inner);
+        assert_eq!((outer.encapsulation, outer.inner_ip_protocol, outer.inner_src_port), (Some(47), Some(17), Some(40000)));
+
+        // 非対応のGREのバージョン (PPTPの拡張GRE)
+        let mut pptp = vec![0x30, 0x01, 0x88, 0x0b, 0, 0, 0, 0, 0, 0, 0, 0];
+        pptp.extend_from_slice(&inner_udp());
+        let outer = parse_and_analyze_packet(frame(&ipv4([192, 0, 2, 1], [192, 0, 2, 2], 47, &pptp))).await.unwrap();
+        assert!(decapsulate(&outer).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn decapsulates_vxlan_with_vni() {
+        configure(&DecapsulationConfig { enabled: true, record_inner: true, ..Default::default() });
+        let vxlan = |flags: u8| {
+            let mut payload = vec![flags, 0, 0, 0, 0x01, 0x23, 0x45, 0];
+            payload.extend_from_slice(&frame(&inner_udp()));
+            let udp = UdpHeader { src_port: 51000, dst_port: 4789, payload_len: payload.len() as u16 };
+            let mut segment = udp.to_bytes().to_vec();
+            segment.extend_from_slice(&payload);
+            frame(&ipv4([192, 0, 2, 1], [192, 0, 2, 2], 17, &segment))
+        };
+        let mut outer = parse_and_analyze_packet(vxlan(0x08)).await.unwrap();
+        let (encapsulation, inner) = decapsulate(&outer).await.unwrap();
+        assert_eq!(encapsulation, Encapsulation::Vxlan(0x012345));
+        assert_eq!((inner.src_ip.ip(), inner.vni), ("10.1.0.5".parse().unwrap(), Some(0x012345)));
+        record(&mut outer, encapsulation, &inner);
+        assert_eq!((outer.vni, outer.encapsulation), (Some(0x012345), Some(4789)));
+
+        // Iフラグが無いヘッダはVXLANとして扱わない
+        let outer = parse_and_analyze_packet(vxlan(0x00)).await.unwrap();
+        assert!(decapsulate(&outer).await.is_none());
+    }
+}
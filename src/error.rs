@@ -1,6 +1,65 @@
+use crate::compression::CompressionError;
+use crate::database::error::DbError;
+use crate::db_read::PacketError;
+use crate::packet_analysis::PacketAnalysisError;
+use crate::secret_provider::SecretError;
+use crate::transport::TransportError;
 use thiserror::Error;
 
+// エラーの種類ごとの定義は各モジュールに置き、ここで1つの階層にまとめる。
+// 変換時は文字列にせず元のエラーを保持するため、source() で原因を辿れる
+
+// クレート全体のエラー
 #[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Init(#[from] InitProcessError),
+
+    #[error(transparent)]
+    Database(#[from] DbError),
+
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+
+    #[error(transparent)]
+    Packet(#[from] PacketError),
+
+    #[error(transparent)]
+    PacketAnalysis(#[from] PacketAnalysisError),
+
+    #[error(transparent)]
+    Compression(#[from] CompressionError),
+
+    #[error(transparent)]
+    Secret(#[from] SecretError),
+
+    // タスクのパニックや予期しない終了
+    #[error("{0}")]
+    Task(String),
+}
+
+// 時間をおいて再試行すれば回復する見込みがあるエラーか。
+// リトライやタスクの再起動の判断に使用し、設定の誤りなど再試行しても失敗し続けるエラーはfalseを返す
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::Init(_) | Error::Compression(_) => false,
+            Error::Database(e) => e.is_retryable(),
+            Error::Transport(e) => e.is_retryable(),
+            Error::Packet(e) => e.is_retryable(),
+            Error::PacketAnalysis(e) => e.is_retryable(),
+            Error::Secret(e) => e.is_retryable(),
+            Error::Task(_) => true,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum InitProcessError {
     #[error("ロガーのセットアップに失敗しました: {0}")]
     LoggerError(String),
@@ -14,8 +73,26 @@ pub enum InitProcessError {
     #[error("環境変数の解析に失敗しました: {0}")]
     EnvVarParseError(String),
 
+    #[error("設定ファイルの読み込みに失敗しました: {0}")]
+    ConfigError(String),
+
+    #[error("テレメトリの初期化に失敗しました: {0}")]
+    TelemetryError(String),
+
+    #[error("シークレットの取得に失敗しました: {0}")]
+    SecretError(#[from] SecretError),
+
     #[error("データベース接続エラー: {0}")]
-    DatabaseConnectionError(String),
+    DatabaseConnectionError(#[source] DbError),
+
+    #[error("マイグレーションに失敗しました: {0}")]
+    MigrationError(#[source] DbError),
+
+    #[error("データベースのスキーマが一致しません ([migrations] enabled = true で自動更新できます): {0}")]
+    SchemaError(#[source] DbError),
+
+    #[error("トランスポートの初期化に失敗しました: {0}")]
+    TransportError(#[from] TransportError),
 
     #[error("仮想インターフェースのエラー: {0}")]
     VirtualInterfaceError(String),
@@ -24,17 +101,8 @@ pub enum InitProcessError {
     DeviceSelectionError(String),
 
     #[error("パケット分析エラー: {0}")]
-    PacketAnalysisError(String),
-}
-
-#[derive(Error, Debug)]
-pub enum DatabaseError {
-    #[error("データベース接続エラー: {0}")]
-    ConnectionError(String),
-
-    #[error("クエリ実行エラー: {0}")]
-    QueryError(String),
+    PacketAnalysisError(#[from] PacketAnalysisError),
 
-    #[error("トランザクションエラー: {0}")]
-    TransactionError(String),
-}
\ No newline at end of file
+    #[error("サブコマンドの実行に失敗しました: {0}")]
+    CommandError(String),
+}
@@ -0,0 +1,97 @@
+use crate::security::{notify, siem};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+// 購読側の処理が追いつかない場合、古いイベントから破棄される
+const EVENT_CHANNEL_CAPACITY: usize = 4096;
+// 統計情報として保持する直近のアラート数
+const RECENT_ALERTS_CAPACITY: usize = 50;
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<PipelineEvent> = broadcast::channel(EVENT_CHANNEL_CAPACITY).0;
+    static ref RECENT_ALERTS: Mutex<VecDeque<Alert>> = Mutex::new(VecDeque::with_capacity(RECENT_ALERTS_CAPACITY));
+}
+
+// パケットの流れる方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    // キャプチャしてデータベースへ書き込むパケット
+    Outbound,
+    // データベースから取得して注入したパケット
+    Inbound,
+}
+
+// ダッシュボードや外部コントローラーへ配信するパケットの概要
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketSummary {
+    pub timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ip_protocol: u8,
+    pub length: usize,
+    // ファイアウォールで許可されたか
+    pub allowed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub timestamp: DateTime<Utc>,
+    pub kind: String,
+    pub severity: String,
+    pub message: String,
+    pub src_ip: Option<IpAddr>,
+    pub dst_ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PipelineEvent {
+    Packet(PacketSummary),
+    // 検知機能・一時的な遮断から発行される
+    Alert(Alert),
+}
+
+pub fn subscribe() -> broadcast::Receiver<PipelineEvent> {
+    EVENTS.subscribe()
+}
+
+// 購読者がいない場合はイベントを作成しない
+pub fn has_subscribers() -> bool {
+    EVENTS.receiver_count() > 0
+}
+
+pub fn publish(event: PipelineEvent) {
+    if let PipelineEvent::Alert(alert) = &event {
+        let mut recent = RECENT_ALERTS.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() == RECENT_ALERTS_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(alert.clone());
+        drop(recent);
+        notify::enqueue(alert);
+        siem::enqueue(alert);
+    }
+
+    // 購読者がいない場合のエラーは無視する
+    let _ = EVENTS.send(event);
+}
+
+// 新しい順に返す
+pub fn recent_alerts() -> Vec<Alert> {
+    RECENT_ALERTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
@@ -1,22 +1,175 @@
+use crate::config::FirewallConfig;
+use crate::schedule::Schedule;
+use chrono::{DateTime, Local, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::RwLock;
 
-#[derive(Debug, Eq, Hash, PartialEq)]
+lazy_static! {
+    // 管理APIから実行中に変更できるよう、ロックで保護する
+    pub static ref FIREWALL: RwLock<Firewall> = {
+        let mut output = IpFirewall::new(Policy::Blacklist);
+        output.add_rule(Filter::IpAddress("160.251.175.134".parse().unwrap()), 100);
+        output.add_rule(Filter::Port(13432), 90);
+        output.add_rule(Filter::Port(2222), 80);
+        RwLock::new(Firewall {
+            input: IpFirewall::new(Policy::Blacklist),
+            output,
+        })
+    };
+}
+
+// 各チェインの既定の方針を設定する (ルールは管理APIで変更する)
+pub fn configure(config: &FirewallConfig) {
+    let mut firewall = FIREWALL.write().unwrap_or_else(|e| e.into_inner());
+    firewall.input.set_policy(config.input_policy);
+    firewall.output.set_policy(config.output_policy);
+}
+
+// ルールを適用する方向
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Chain {
+    // 受信したパケットを仮想NICへ注入する前 (DB -> tap)
+    Input,
+    // キャプチャしたパケットを書き込む前 (キャプチャ -> DB)
+    #[default]
+    Output,
+}
+
+// 方向ごとに独立したルールと方針を持つファイアウォール
+#[derive(Debug)]
+pub struct Firewall {
+    input: IpFirewall,
+    output: IpFirewall,
+}
+
+impl Firewall {
+    pub fn new(input: IpFirewall, output: IpFirewall) -> Self {
+        Self { input, output }
+    }
+
+    pub fn chain(&self, chain: Chain) -> &IpFirewall {
+        match chain {
+            Chain::Input => &self.input,
+            Chain::Output => &self.output,
+        }
+    }
+
+    pub fn chain_mut(&mut self, chain: Chain) -> &mut IpFirewall {
+        match chain {
+            Chain::Input => &mut self.input,
+            Chain::Output => &mut self.output,
+        }
+    }
+}
+
+// VXLANのVNIの最大値 (24ビット)
+pub const MAX_VNI: u32 = 0x00ff_ffff;
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
 pub enum Filter {
     IpAddress(IpAddr),
     Port(u16),
     Protocol(u8),
+    // 送信元または宛先のMACアドレス ("02:00:00:00:00:01" の形式)
+    #[serde(with = "hex_octets")]
+    MacAddress([u8; 6]),
+    // 送信元または宛先のMACアドレスの先頭3バイト (ベンダーのOUI、"00:1b:21" の形式)
+    #[serde(with = "hex_octets")]
+    Oui([u8; 3]),
+    // イーサネットフレームのEtherType (例: ARPは2054 = 0x0806、IPXは33079 = 0x8137)
+    EtherType(u16),
+    // VXLANのVNI (24ビット)。[decapsulation] でVXLANの内側を取り出したパケットに一致する
+    Vni(u32),
 }
 
-#[derive(Debug)]
+impl Filter {
+    pub fn matches(&self, packet: &crate::firewall_packet::FirewallPacket) -> bool {
+        match self {
+            Filter::IpAddress(ip) => packet.src_ip == *ip || packet.dst_ip == *ip,
+            Filter::Port(port) => packet.src_port == *port || packet.dst_port == *port,
+            Filter::Protocol(protocol) => packet.ip_version == *protocol,
+            Filter::MacAddress(mac) => packet.src_mac == *mac || packet.dst_mac == *mac,
+            Filter::Oui(oui) => packet.src_mac.starts_with(oui) || packet.dst_mac.starts_with(oui),
+            Filter::EtherType(ether_type) => packet.ether_type == *ether_type,
+            Filter::Vni(vni) => packet.vni == Some(*vni),
+        }
+    }
+}
+
+// MACアドレスとOUIを ":" または "-" 区切りの16進数の文字列として読み書きする
+pub mod hex_octets {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn parse<const N: usize>(s: &str) -> Result<[u8; N], String> {
+        s.split([':', '-'])
+            .map(|octet| u8::from_str_radix(octet, 16).ok().filter(|_| octet.len() == 2))
+            .collect::<Option<Vec<_>>>()
+            .and_then(|octets| <[u8; N]>::try_from(octets).ok())
+            .ok_or_else(|| format!("{}バイトの16進数をコロン区切りで指定してください: {}", N, s))
+    }
+
+    pub fn format(octets: &[u8]) -> String {
+        octets.iter().map(|octet| format!("{:02x}", octet)).collect::<Vec<_>>().join(":")
+    }
+
+    pub fn serialize<S: Serializer, const N: usize>(octets: &[u8; N], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format(octets))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+        parse(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Policy {
+    // いずれかのルールに一致したパケットのみ許可する
     Whitelist,
+    // いずれかのルールに一致したパケットを拒否する
+    #[default]
     Blacklist,
 }
 
+// 優先度とスケジュール付きのルール (スケジュールを省略した場合は常に有効)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub filter: Filter,
+    pub priority: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+}
+
+// ルールの統計情報 (一致したパケット数と最後に一致した時刻)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleStats {
+    #[serde(flatten)]
+    pub rule: Rule,
+    pub hits: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_match: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct RuleState {
+    priority: u8,
+    schedule: Option<Schedule>,
+    // 判定を決定したパケット数 (読み取りロックのまま更新する)
+    hits: AtomicU64,
+    // 最後に一致した時刻 (UNIXエポックからのミリ秒、未一致の場合は0)
+    last_match_ms: AtomicI64,
+}
+
 #[derive(Debug)]
 pub struct IpFirewall {
-    rules: HashMap<Filter, u8>,
+    rules: HashMap<Filter, RuleState>,
     policy: Policy,
 }
 
@@ -29,51 +182,194 @@ impl IpFirewall {
     }
 
     pub fn add_rule(&mut self, filter: Filter, priority: u8) {
-        self.rules.insert(filter, priority);
+        self.insert(Rule { filter, priority, schedule: None });
     }
 
-    pub fn check(&self, packet: crate::firewall_packet::FirewallPacket) -> bool {
-        let mut block = false;
-        let mut allow = false;
-        let mut max_priority = 0;
-
-        for (filter, priority) in &self.rules {
-            if *priority > max_priority {
-                match filter {
-                    Filter::IpAddress(ip) => {
-                        if packet.src_ip == *ip || packet.dst_ip == *ip {
-                            max_priority = *priority;
-                            match self.policy {
-                                Policy::Whitelist => allow = true,
-                                Policy::Blacklist => block = true,
-                            }
-                        }
-                    }
-                    Filter::Port(port) => {
-                        if packet.src_port == *port || packet.dst_port == *port {
-                            max_priority = *priority;
-                            match self.policy {
-                                Policy::Whitelist => allow = true,
-                                Policy::Blacklist => block = true,
-                            }
-                        }
-                    }
-                    Filter::Protocol(protocol) => {
-                        if packet.ip_version == *protocol {
-                            max_priority = *priority;
-                            match self.policy {
-                                Policy::Whitelist => allow = true,
-                                Policy::Blacklist => block = true,
-                            }
-                        }
-                    }
-                }
+    // 同じフィルタが既に存在する場合は優先度とスケジュールを置き換える (統計情報は引き継ぐ)。
+    // 有効期間が終了したルールはこのときに削除する
+    pub fn insert(&mut self, rule: Rule) {
+        let now = Utc::now();
+        self.rules.retain(|_, state| !state.schedule.as_ref().is_some_and(|schedule| schedule.is_expired(now)));
+        match self.rules.get_mut(&rule.filter) {
+            Some(state) => {
+                state.priority = rule.priority;
+                state.schedule = rule.schedule;
+            }
+            None => {
+                self.rules.insert(
+                    rule.filter,
+                    RuleState {
+                        priority: rule.priority,
+                        schedule: rule.schedule,
+                        hits: AtomicU64::new(0),
+                        last_match_ms: AtomicI64::new(0),
+                    },
+                );
             }
         }
+    }
+
+    pub fn contains(&self, filter: &Filter) -> bool {
+        self.rules.contains_key(filter)
+    }
+
+    // 削除した場合はtrue
+    pub fn remove_rule(&mut self, filter: &Filter) -> bool {
+        self.rules.remove(filter).is_some()
+    }
+
+    // 優先度の高い順に返す
+    pub fn rules(&self) -> Vec<Rule> {
+        self.rule_stats().into_iter().map(|stats| stats.rule).collect()
+    }
+
+    // 統計情報付きのルールを優先度の高い順に返す
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        let mut rules = self.rules
+            .iter()
+            .map(|(filter, state)| RuleStats {
+                rule: Rule {
+                    filter: filter.clone(),
+                    priority: state.priority,
+                    schedule: state.schedule.clone(),
+                },
+                hits: state.hits.load(Ordering::Relaxed),
+                last_match: match state.last_match_ms.load(Ordering::Relaxed) {
+                    0 => None,
+                    unix_ms => DateTime::from_timestamp_millis(unix_ms),
+                },
+            })
+            .collect::<Vec<_>>();
+        rules.sort_by_key(|stats| std::cmp::Reverse(stats.rule.priority));
+        rules
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
 
-        match self.policy {
-            Policy::Whitelist => allow,
-            Policy::Blacklist => !block,
+    pub fn check(&self, packet: crate::firewall_packet::FirewallPacket) -> bool {
+        self.check_with_pinhole(packet, false)
+    }
+
+    // pinhole がtrueの場合は、一致するルールがないパケットを方針に関係なく許可する (ルールより優先度の低い許可)
+    pub fn check_with_pinhole(&self, packet: crate::firewall_packet::FirewallPacket, pinhole: bool) -> bool {
+        // 一致したルールのうち優先度が最も高いものが判定を決定する
+        let mut matched: Option<&RuleState> = None;
+        // スケジュール付きのルールがある場合のみ現在時刻を取得する
+        let mut now = None;
+
+        for (filter, state) in &self.rules {
+            if state.priority > matched.map_or(0, |matched| matched.priority)
+                && filter.matches(&packet)
+                && state.schedule.as_ref().is_none_or(|schedule| schedule.is_active(*now.get_or_insert_with(Local::now)))
+            {
+                matched = Some(state);
+            }
         }
+
+        let Some(matched) = matched else {
+            return pinhole || self.policy == Policy::Blacklist;
+        };
+        matched.hits.fetch_add(1, Ordering::Relaxed);
+        matched.last_match_ms.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+        self.policy == Policy::Whitelist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall_packet::FirewallPacket;
+
+    fn packet(dst_port: u16) -> FirewallPacket {
+        FirewallPacket::new("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap(), 40000, dst_port, 4)
+            .with_macs([0x00, 0x1b, 0x21, 0x00, 0x00, 0x01], [0x02, 0x00, 0x00, 0x00, 0x00, 0x02])
+    }
+
+    #[test]
+    fn pinhole_allows_only_unmatched_packets() {
+        let mut whitelist = IpFirewall::new(Policy::Whitelist);
+        whitelist.add_rule(Filter::Port(22), 10);
+        let mut blacklist = IpFirewall::new(Policy::Blacklist);
+        blacklist.add_rule(Filter::Port(23), 250);
+        assert!(whitelist.check_with_pinhole(packet(40000), true));
+        assert!(!whitelist.check_with_pinhole(packet(40000), false));
+        // 遮断ルールはピンホールより優先する
+        assert!(!blacklist.check_with_pinhole(packet(23), true));
+        assert!(blacklist.check_with_pinhole(packet(40000), true));
+    }
+
+    #[test]
+    fn applies_chains_independently() {
+        let mut firewall = Firewall {
+            input: IpFirewall::new(Policy::Whitelist),
+            output: IpFirewall::new(Policy::Blacklist),
+        };
+        firewall.chain_mut(Chain::Input).add_rule(Filter::Port(22), 10);
+        firewall.chain_mut(Chain::Output).add_rule(Filter::Port(23), 10);
+
+        // 受信側は許可したポートのみ、送信側は拒否したポート以外を通す
+        assert!(firewall.chain(Chain::Input).check(packet(22)));
+        assert!(!firewall.chain(Chain::Input).check(packet(23)));
+        assert!(!firewall.chain(Chain::Input).check(packet(80)));
+        assert!(firewall.chain(Chain::Output).check(packet(22)));
+        assert!(!firewall.chain(Chain::Output).check(packet(23)));
+        assert!(firewall.chain(Chain::Output).check(packet(80)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn applies_only_active_scheduled_rules() {
+        let mut firewall = IpFirewall::new(Policy::Blacklist);
+        let expired = Schedule {
+            valid_until: Some(Utc::now() - chrono::Duration::minutes(1)),
+            ..Default::default()
+        };
+        let active = Schedule {
+            valid_until: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        firewall.insert(Rule { filter: Filter::Port(22), priority: 10, schedule: Some(expired) });
+        assert!(firewall.check(packet(22)));
+
+        // 期限切れのルールは次に追加したときに削除される
+        firewall.insert(Rule { filter: Filter::Port(23), priority: 10, schedule: Some(active) });
+        assert!(!firewall.check(packet(23)));
+        assert_eq!(firewall.rules().len(), 1);
+    }
+
+    #[test]
+    fn matches_mac_address_and_oui() {
+        let mac: Filter = serde_json::from_str(r#"{"type": "mac_address", "value": "02:00:00:00:00:02"}"#).unwrap();
+        let oui: Filter = serde_json::from_str(r#"{"type": "oui", "value": "00-1B-21"}"#).unwrap();
+        assert!(mac.matches(&packet(80)));
+        assert!(oui.matches(&packet(80)));
+        assert!(!Filter::Oui([0x00, 0x1b, 0x22]).matches(&packet(80)));
+        assert_eq!(serde_json::to_string(&oui).unwrap(), r#"{"type":"oui","value":"00:1b:21"}"#);
+        assert!(serde_json::from_str::<Filter>(r#"{"type": "oui", "value": "00:1b"}"#).is_err());
+    }
+
+    #[test]
+    fn counts_hits_of_deciding_rule() {
+        let mut firewall = IpFirewall::new(Policy::Blacklist);
+        firewall.add_rule(Filter::Port(22), 10);
+        firewall.add_rule(Filter::IpAddress("10.0.0.2".parse().unwrap()), 20);
+        firewall.add_rule(Filter::Port(23), 5);
+
+        assert!(!firewall.check(packet(22)));
+        assert!(!firewall.check(packet(22)));
+        let stats = firewall.rule_stats();
+        // 優先度の高いルールのみ数える
+        assert_eq!(stats[0].hits, 2);
+        assert!(stats[0].last_match.is_some());
+        assert_eq!((stats[1].hits, stats[1].last_match), (0, None));
+
+        // 優先度を変更しても統計情報は引き継ぐ
+        firewall.add_rule(Filter::IpAddress("10.0.0.2".parse().unwrap()), 30);
+        assert_eq!(firewall.rule_stats()[0].hits, 2);
+    }
+}
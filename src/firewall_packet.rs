@@ -1,12 +1,17 @@
 use std::net::IpAddr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct FirewallPacket {
     pub src_ip: IpAddr,
     pub dst_ip: IpAddr,
     pub src_port: u16,
     pub dst_port: u16,
     pub ip_version: u8,
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    pub ether_type: u16,
+    // VXLANでカプセル化されたパケットのVNI
+    pub vni: Option<u32>,
 }
 
 impl FirewallPacket {
@@ -23,6 +28,26 @@ impl FirewallPacket {
             src_port,
             dst_port,
             ip_version,
+            src_mac: [0; 6],
+            dst_mac: [0; 6],
+            ether_type: if ip_version == 6 { 0x86DD } else { 0x0800 },
+            vni: None,
         }
     }
+
+    pub fn with_macs(mut self, src_mac: [u8; 6], dst_mac: [u8; 6]) -> Self {
+        self.src_mac = src_mac;
+        self.dst_mac = dst_mac;
+        self
+    }
+
+    pub fn with_ether_type(mut self, ether_type: u16) -> Self {
+        self.ether_type = ether_type;
+        self
+    }
+
+    pub fn with_vni(mut self, vni: Option<u32>) -> Self {
+        self.vni = vni;
+        self
+    }
 }
\ No newline at end of file
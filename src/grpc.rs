@@ -0,0 +1,374 @@
+use crate::api::DEFAULT_PEER_WINDOW_SECS;
+use crate::config::GrpcConfig;
+use crate::database::database::Database;
+use crate::events::{self, Direction, PipelineEvent};
+use crate::firewall::{hex_octets, Chain, Filter, Policy, Rule, MAX_VNI};
+use crate::schedule::Schedule;
+use chrono::{DateTime, Utc};
+use crate::management;
+use crate::metrics;
+use futures::Stream;
+use ipnetwork::IpNetwork;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+
+pub mod proto {
+    tonic::include_proto!("rdb_tunnel.v1");
+}
+
+use proto::tunnel_control_server::{TunnelControl, TunnelControlServer};
+
+pub struct ControlService {
+    tunnel_network: IpNetwork,
+    database: Option<Arc<Database>>,
+}
+
+pub async fn start_grpc_server(
+    config: GrpcConfig,
+    tunnel_network: IpNetwork,
+    database: Option<Arc<Database>>,
+) -> Result<(), tonic::transport::Error> {
+    let expected = match config.api_token.map(|token| format!("Bearer {}", token).parse::<MetadataValue<_>>()) {
+        None => None,
+        Some(Ok(expected)) => Some(expected),
+        Some(Err(_)) => {
+            error!("gRPCのAPIトークンに使用できない文字が含まれているため、gRPCサーバーを起動しません");
+            return Ok(());
+        }
+    };
+
+    let service = TunnelControlServer::with_interceptor(
+        ControlService { tunnel_network, database },
+        move |request: Request<()>| {
+            let Some(expected) = &expected else {
+                return Ok(request);
+            };
+            match request.metadata().get("authorization") {
+                Some(provided) if provided == expected => Ok(request),
+                _ => Err(Status::unauthenticated("invalid api token")),
+            }
+        },
+    );
+
+    info!("gRPCサーバーを開始しました: {}", config.listen);
+    Server::builder()
+        .add_service(service)
+        .serve(config.listen)
+        .await
+}
+
+impl TryFrom<proto::Filter> for Filter {
+    type Error = Status;
+
+    fn try_from(filter: proto::Filter) -> Result<Self, Self::Error> {
+        use proto::filter::Kind;
+
+        match filter.kind {
+            Some(Kind::IpAddress(ip)) => ip
+                .parse()
+                .map(Filter::IpAddress)
+                .map_err(|_| Status::invalid_argument(format!("invalid ip address: {}", ip))),
+            Some(Kind::Port(port)) => u16::try_from(port)
+                .map(Filter::Port)
+                .map_err(|_| Status::invalid_argument(format!("invalid port: {}", port))),
+            Some(Kind::Protocol(protocol)) => u8::try_from(protocol)
+                .map(Filter::Protocol)
+                .map_err(|_| Status::invalid_argument(format!("invalid protocol: {}", protocol))),
+            Some(Kind::MacAddress(mac)) => hex_octets::parse(&mac).map(Filter::MacAddress).map_err(Status::invalid_argument),
+            Some(Kind::Oui(oui)) => hex_octets::parse(&oui).map(Filter::Oui).map_err(Status::invalid_argument),
+            Some(Kind::EtherType(ether_type)) => u16::try_from(ether_type)
+                .map(Filter::EtherType)
+                .map_err(|_| Status::invalid_argument(format!("invalid ether type: {}", ether_type))),
+            Some(Kind::Vni(vni)) if vni <= MAX_VNI => Ok(Filter::Vni(vni)),
+            Some(Kind::Vni(vni)) => Err(Status::invalid_argument(format!("invalid vni: {}", vni))),
+            None => Err(Status::invalid_argument("filter is required")),
+        }
+    }
+}
+
+impl From<Filter> for proto::Filter {
+    fn from(filter: Filter) -> Self {
+        use proto::filter::Kind;
+
+        let kind = match filter {
+            Filter::IpAddress(ip) => Kind::IpAddress(ip.to_string()),
+            Filter::Port(port) => Kind::Port(port as u32),
+            Filter::Protocol(protocol) => Kind::Protocol(protocol as u32),
+            Filter::MacAddress(mac) => Kind::MacAddress(hex_octets::format(&mac)),
+            Filter::Oui(oui) => Kind::Oui(hex_octets::format(&oui)),
+            Filter::EtherType(ether_type) => Kind::EtherType(ether_type as u32),
+            Filter::Vni(vni) => Kind::Vni(vni),
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl From<proto::Chain> for Chain {
+    fn from(chain: proto::Chain) -> Self {
+        match chain {
+            proto::Chain::Output => Chain::Output,
+            proto::Chain::Input => Chain::Input,
+        }
+    }
+}
+
+// 未知のチェインはエラーにする (省略した場合は0のためOUTPUTになる)
+fn chain(value: i32) -> Result<Chain, Status> {
+    proto::Chain::try_from(value)
+        .map(Chain::from)
+        .map_err(|_| Status::invalid_argument(format!("invalid chain: {}", value)))
+}
+
+impl TryFrom<proto::Schedule> for Schedule {
+    type Error = Status;
+
+    fn try_from(schedule: proto::Schedule) -> Result<Self, Self::Error> {
+        let time = |unix_ms: i64| match unix_ms {
+            0 => Ok(None),
+            unix_ms => DateTime::<Utc>::from_timestamp_millis(unix_ms)
+                .map(Some)
+                .ok_or_else(|| Status::invalid_argument(format!("invalid time: {}", unix_ms))),
+        };
+        Ok(Self {
+            days: schedule
+                .days
+                .iter()
+                .map(|day| day.parse().map_err(|_| Status::invalid_argument(format!("invalid day: {}", day))))
+                .collect::<Result<_, _>>()?,
+            time_ranges: schedule
+                .time_ranges
+                .iter()
+                .map(|range| range.parse().map_err(Status::invalid_argument))
+                .collect::<Result<_, _>>()?,
+            valid_from: time(schedule.valid_from_unix_ms)?,
+            valid_until: time(schedule.valid_until_unix_ms)?,
+        })
+    }
+}
+
+impl From<Schedule> for proto::Schedule {
+    fn from(schedule: Schedule) -> Self {
+        Self {
+            days: schedule.days.iter().map(|day| day.to_string().to_lowercase()).collect(),
+            time_ranges: schedule.time_ranges.iter().map(ToString::to_string).collect(),
+            valid_from_unix_ms: schedule.valid_from.map_or(0, |time| time.timestamp_millis()),
+            valid_until_unix_ms: schedule.valid_until.map_or(0, |time| time.timestamp_millis()),
+        }
+    }
+}
+
+fn required_filter(filter: Option<proto::Filter>) -> Result<Filter, Status> {
+    filter
+        .ok_or_else(|| Status::invalid_argument("filter is required"))?
+        .try_into()
+}
+
+impl From<PipelineEvent> for proto::Event {
+    fn from(event: PipelineEvent) -> Self {
+        use proto::event::Event;
+
+        let event = match event {
+            PipelineEvent::Packet(packet) => Event::Packet(proto::PacketSummary {
+                timestamp_unix_ms: packet.timestamp.timestamp_millis(),
+                direction: match packet.direction {
+                    Direction::Outbound => proto::Direction::Outbound,
+                    Direction::Inbound => proto::Direction::Inbound,
+                } as i32,
+                src_ip: packet.src_ip.to_string(),
+                dst_ip: packet.dst_ip.to_string(),
+                src_port: packet.src_port as u32,
+                dst_port: packet.dst_port as u32,
+                ip_protocol: packet.ip_protocol as u32,
+                length: packet.length as u64,
+                allowed: packet.allowed,
+            }),
+            PipelineEvent::Alert(alert) => Event::Alert(proto::Alert {
+                timestamp_unix_ms: alert.timestamp.timestamp_millis(),
+                kind: alert.kind,
+                severity: alert.severity,
+                message: alert.message,
+                src_ip: alert.src_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+                dst_ip: alert.dst_ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            }),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl TunnelControl for ControlService {
+    async fn list_firewall_rules(
+        &self,
+        request: Request<proto::ListFirewallRulesRequest>,
+    ) -> Result<Response<proto::ListFirewallRulesResponse>, Status> {
+        let chain_value = request.into_inner().chain;
+        let (policy, rules) = management::firewall_rules(chain(chain_value)?);
+        Ok(Response::new(proto::ListFirewallRulesResponse {
+            policy: match policy {
+                Policy::Whitelist => "whitelist",
+                Policy::Blacklist => "blacklist",
+            }
+            .to_string(),
+            rules: rules
+                .into_iter()
+                .map(|stats| proto::FirewallRule {
+                    filter: Some(stats.rule.filter.into()),
+                    priority: stats.rule.priority as u32,
+                    chain: chain_value,
+                    schedule: stats.rule.schedule.map(Into::into),
+                    hits: stats.hits,
+                    last_match_unix_ms: stats.last_match.map_or(0, |time| time.timestamp_millis()),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn add_firewall_rule(
+        &self,
+        request: Request<proto::FirewallRule>,
+    ) -> Result<Response<proto::FirewallRule>, Status> {
+        let rule = request.into_inner();
+        let filter = required_filter(rule.filter.clone())?;
+        let priority = u8::try_from(rule.priority)
+            .map_err(|_| Status::invalid_argument(format!("invalid priority: {}", rule.priority)))?;
+
+        let schedule = rule.schedule.clone().map(Schedule::try_from).transpose()?;
+
+        management::add_firewall_rule(chain(rule.chain)?, Rule { filter, priority, schedule })
+            .map_err(Status::invalid_argument)?;
+        Ok(Response::new(rule))
+    }
+
+    async fn remove_firewall_rule(
+        &self,
+        request: Request<proto::RemoveFirewallRuleRequest>,
+    ) -> Result<Response<proto::RemoveFirewallRuleResponse>, Status> {
+        let request = request.into_inner();
+        let chain = chain(request.chain)?;
+        let filter = required_filter(request.filter)?;
+        Ok(Response::new(proto::RemoveFirewallRuleResponse {
+            removed: management::remove_firewall_rule(chain, &filter),
+        }))
+    }
+
+    async fn list_peers(
+        &self,
+        request: Request<proto::ListPeersRequest>,
+    ) -> Result<Response<proto::ListPeersResponse>, Status> {
+        let window_secs = match request.into_inner().window_secs {
+            0 => DEFAULT_PEER_WINDOW_SECS,
+            secs => secs,
+        };
+        let peers = management::list_peers(self.database.as_deref(), self.tunnel_network, Duration::from_secs(window_secs))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(Response::new(proto::ListPeersResponse {
+            peers: peers
+                .into_iter()
+                .map(|peer| proto::Peer {
+                    address: peer.address.to_string(),
+                    packets: peer.packets,
+                    bytes: peer.bytes,
+                    last_seen_unix_ms: peer.last_seen.timestamp_millis(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<proto::GetStatsRequest>,
+    ) -> Result<Response<proto::Stats>, Status> {
+        let stats = metrics::snapshot(self.database.as_deref());
+        Ok(Response::new(proto::Stats {
+            packets_captured: stats.packets_captured.into_iter().collect(),
+            packets_written: stats.packets_written,
+            packets_injected: stats.packets_injected,
+            packets_dropped: stats.packets_dropped.into_iter().collect(),
+            firewall_drops: stats.firewall_drops,
+            idps_alerts: stats.idps_alerts,
+            buffer_depth: stats.buffer_depth,
+            pools: stats
+                .pools
+                .into_iter()
+                .map(|pool| proto::PoolStats {
+                    name: pool.name.to_string(),
+                    connections: pool.connections,
+                    idle: pool.idle,
+                    in_use: pool.in_use,
+                    get_direct: pool.get_direct,
+                    get_waited: pool.get_waited,
+                    get_timed_out: pool.get_timed_out,
+                    total_wait_time_ms: pool.total_wait_time.as_millis() as u64,
+                })
+                .collect(),
+            peers: management::peer_traffic(self.tunnel_network)
+                .into_iter()
+                .map(|peer| proto::PeerTraffic {
+                    address: peer.address.to_string(),
+                    outbound_packets: peer.totals.outbound.packets,
+                    outbound_bytes: peer.totals.outbound.bytes,
+                    inbound_packets: peer.totals.inbound.packets,
+                    inbound_bytes: peer.totals.inbound.bytes,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn flush(
+        &self,
+        _request: Request<proto::FlushRequest>,
+    ) -> Result<Response<proto::FlushResponse>, Status> {
+        let flushed = management::flush()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(proto::FlushResponse { flushed: flushed as u64 }))
+    }
+
+    async fn prune(
+        &self,
+        request: Request<proto::PruneRequest>,
+    ) -> Result<Response<proto::PruneResponse>, Status> {
+        let older_than = Duration::from_secs(request.into_inner().older_than_secs);
+        let summary = management::prune(self.database.as_deref(), older_than)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(proto::PruneResponse {
+            deleted: summary.deleted,
+            dropped_chunks: summary.dropped_chunks,
+        }))
+    }
+
+    type StreamEventsStream = EventStream;
+
+    async fn stream_events(
+        &self,
+        request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let include_packets = request.into_inner().include_packets;
+        let receiver = events::subscribe();
+
+        let stream = futures::stream::unfold(receiver, move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(PipelineEvent::Packet(_)) if !include_packets => continue,
+                    Ok(event) => return Some((Ok(event.into()), receiver)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("イベントの配信が追いつかないため {} 件を破棄しました", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
@@ -0,0 +1,128 @@
+use crate::transport::transport;
+use chrono::{DateTime, TimeZone, Utc};
+use pnet::datalink;
+use serde::Serialize;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+// 最後のポーリング成功からこの時間を超えたら準備未完了とみなす
+const POLL_STALE_AFTER: Duration = Duration::from_secs(30);
+
+// 最後に成功したポーリング/フラッシュの時刻 (UNIXミリ秒、0は未実行)
+static LAST_POLL: AtomicI64 = AtomicI64::new(0);
+static LAST_FLUSH: AtomicI64 = AtomicI64::new(0);
+// ライター/ポーリングのループが最後に1周した時刻 (ウォッチドッグ用)
+static WRITER_HEARTBEAT: AtomicI64 = AtomicI64::new(0);
+static POLLER_HEARTBEAT: AtomicI64 = AtomicI64::new(0);
+
+// タスクの状態を追跡する構造体
+#[derive(Debug, Serialize)]
+pub struct TaskState {
+    pub polling_active: bool,
+    pub writer_active: bool,
+    pub analysis_active: bool,
+}
+
+impl TaskState {
+    pub fn new() -> Self {
+        Self {
+            polling_active: false,
+            writer_active: false,
+            analysis_active: false,
+        }
+    }
+
+    pub fn all_active(&self) -> bool {
+        self.polling_active && self.writer_active && self.analysis_active
+    }
+
+    pub fn any_active(&self) -> bool {
+        self.polling_active || self.writer_active || self.analysis_active
+    }
+}
+
+impl Default for TaskState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn record_poll_success() {
+    LAST_POLL.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+pub fn record_flush_success() {
+    LAST_FLUSH.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+pub fn record_writer_heartbeat() {
+    WRITER_HEARTBEAT.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+pub fn record_poller_heartbeat() {
+    POLLER_HEARTBEAT.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+}
+
+// ライターとポーリングのうち、応答が古い方の経過時間
+pub fn pipeline_heartbeat_age() -> Option<Duration> {
+    let oldest = load_timestamp(&WRITER_HEARTBEAT)?.min(load_timestamp(&POLLER_HEARTBEAT)?);
+    Some((Utc::now() - oldest).to_std().unwrap_or(Duration::ZERO))
+}
+
+fn load_timestamp(value: &AtomicI64) -> Option<DateTime<Utc>> {
+    match value.load(Ordering::Relaxed) {
+        0 => None,
+        millis => Utc.timestamp_millis_opt(millis).single(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    // トランスポートのバックエンド (データベースまたはブローカー) に接続できるか
+    pub database: bool,
+    pub tap_interface: bool,
+    pub tasks: TaskState,
+    pub last_poll: Option<DateTime<Utc>>,
+    pub last_flush: Option<DateTime<Utc>>,
+}
+
+// 生存確認: 全てのパイプラインタスクが動作しているか
+pub async fn liveness(task_state: &Arc<Mutex<TaskState>>) -> (bool, TaskState) {
+    let state = task_state.lock().await;
+    let snapshot = TaskState {
+        polling_active: state.polling_active,
+        writer_active: state.writer_active,
+        analysis_active: state.analysis_active,
+    };
+    (state.all_active(), snapshot)
+}
+
+// 準備完了確認: バックエンドへの接続、TAPインターフェース、タスク、ポーリングの鮮度を確認する
+pub async fn readiness(task_state: &Arc<Mutex<TaskState>>, tap_name: &str) -> HealthReport {
+    let (tasks_alive, tasks) = liveness(task_state).await;
+    let database = match transport() {
+        Ok(transport) => transport.health_check().await,
+        Err(_) => false,
+    };
+    let tap_interface = datalink::interfaces()
+        .iter()
+        .any(|iface| iface.name == tap_name && iface.is_up());
+
+    let last_poll = load_timestamp(&LAST_POLL);
+    let poll_fresh = last_poll.is_some_and(|ts| {
+        (Utc::now() - ts).to_std().map(|age| age <= POLL_STALE_AFTER).unwrap_or(true)
+    });
+
+    HealthReport {
+        healthy: tasks_alive && database && tap_interface && poll_fresh,
+        database,
+        tap_interface,
+        tasks,
+        last_poll,
+        // フラッシュはキャプチャしたパケットがある場合のみ行われるため、判定には含めない
+        last_flush: load_timestamp(&LAST_FLUSH),
+    }
+}
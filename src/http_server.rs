@@ -0,0 +1,65 @@
+use crate::api;
+use crate::config::HttpConfig;
+use crate::database::database::Database;
+use crate::health::{self, TaskState};
+use crate::metrics;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Json, Router};
+use axum::routing::get;
+use ipnetwork::IpNetwork;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub task_state: Arc<Mutex<TaskState>>,
+    pub tap_name: String,
+    // ピア一覧の集計対象とするトンネルのネットワーク
+    pub tunnel_network: IpNetwork,
+    // timescaleトランスポートの場合のみ接続する
+    pub database: Option<Arc<Database>>,
+}
+
+pub async fn start_http_server(config: HttpConfig, state: AppState) -> Result<(), std::io::Error> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
+        .nest("/api/v1", api::routes(config.api_token))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(config.listen).await?;
+    info!("HTTPサーバーを開始しました: http://{}", config.listen);
+    axum::serve(listener, app).await
+}
+
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match metrics::gather(state.database.as_deref()) {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+            body,
+        ).into_response(),
+        Err(e) => {
+            error!("メトリクスの出力に失敗しました: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+// 生存確認: パイプラインタスクが全て動作していれば200
+async fn healthz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let (alive, tasks) = health::liveness(&state.task_state).await;
+    let status = if alive { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(tasks))
+}
+
+// 準備完了確認: DB接続、TAPインターフェース、ポーリングの状況を含めて判定する
+async fn readyz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let report = health::readiness(&state.task_state, &state.tap_name).await;
+    let status = if report.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
@@ -0,0 +1,64 @@
+// ベンチマークからも内部のモジュールを利用できるよう、実装はライブラリにまとめる (エントリーポイントはmain.rs)
+pub mod select_device;
+pub mod chunk;
+pub mod compression;
+pub mod dedup;
+pub mod config;
+pub mod secret_provider;
+pub mod database;
+pub mod error;
+pub mod db_read;
+#[cfg(feature = "replication")]
+pub mod db_replication;
+pub mod packet_header;
+pub mod buffer_pool;
+pub mod db_write;
+pub mod firewall;
+pub mod schedule;
+pub mod rules;
+pub mod rules_watch;
+pub mod firewall_packet;
+pub mod virtual_interface;
+pub mod setup_logger;
+pub mod messages;
+pub mod rotating_file;
+pub mod packet_analysis;
+pub mod link_monitor;
+pub mod stats;
+pub mod metrics;
+pub mod http_server;
+pub mod api;
+pub mod peers;
+pub mod management;
+pub mod events;
+pub mod grpc;
+pub mod stream;
+pub mod traffic;
+pub mod sequence;
+pub mod cli;
+pub mod top;
+pub mod bench;
+pub mod probe;
+pub mod qos;
+pub mod shaper;
+pub mod segmentation;
+pub mod encapsulation;
+pub mod pmtu;
+pub mod nat;
+pub mod split_tunnel;
+pub mod routes;
+pub mod policy_routing;
+pub mod nft_offload;
+pub mod bans;
+pub mod security;
+pub mod bridge;
+pub mod supervisor;
+pub mod retry;
+#[cfg(unix)]
+pub mod control_socket;
+pub mod systemd;
+pub mod telemetry;
+pub mod transport;
+pub mod health;
+#[cfg(test)]
+pub mod test_support;
@@ -0,0 +1,182 @@
+use lazy_static::lazy_static;
+use pnet::datalink::{self, NetworkInterface};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use tokio::sync::watch;
+use tokio::time::{sleep, Duration};
+use tracing::{error, info, warn};
+
+// タスクが異常終了した場合やインターフェースが見つからない場合の再試行間隔
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum LinkState {
+    Up,
+    Down,
+    Removed,
+}
+
+lazy_static! {
+    // インターフェース名ごとのリンク状態。監視していない (未知の) インターフェースは稼働中として扱う
+    static ref LINK_STATES: watch::Sender<HashMap<String, LinkState>> = watch::channel(HashMap::new()).0;
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn update_link_state(name: &str, state: LinkState) {
+    LINK_STATES.send_if_modified(|states| {
+        let previous = states.insert(name.to_string(), state);
+        if previous == Some(state) {
+            return false;
+        }
+        match previous {
+            Some(previous) => info!("リンク状態が変化しました: {} {:?} -> {:?}", name, previous, state),
+            None => info!("リンク状態を検出しました: {} {:?}", name, state),
+        }
+        true
+    });
+}
+
+async fn wait_until_up(name: &str) {
+    let mut rx = LINK_STATES.subscribe();
+    let _ = rx
+        .wait_for(|states| states.get(name).is_none_or(|state| *state == LinkState::Up))
+        .await;
+}
+
+async fn wait_until_down(name: &str) {
+    let mut rx = LINK_STATES.subscribe();
+    if rx
+        .wait_for(|states| matches!(states.get(name), Some(LinkState::Down | LinkState::Removed)))
+        .await
+        .is_err()
+    {
+        std::future::pending::<()>().await;
+    }
+}
+
+// インターフェースのリンク状態に合わせてタスクを起動・停止する。
+// リンクがダウンまたは削除された場合はタスクを停止し、復帰後にインターフェースを再取得して再起動する
+pub async fn supervise<F, Fut, E>(name: String, task_name: &str, mut run: F)
+where
+    F: FnMut(NetworkInterface) -> Fut,
+    Fut: Future<Output=Result<(), E>>,
+    E: Display,
+{
+    loop {
+        wait_until_up(&name).await;
+
+        let Some(interface) = datalink::interfaces().into_iter().find(|iface| iface.name == name) else {
+            warn!("{} インターフェースが見つかりません。{}タスクの起動を待機します", name, task_name);
+            sleep(RESTART_DELAY).await;
+            continue;
+        };
+
+        info!("{} で{}タスクを起動します", name, task_name);
+        tokio::select! {
+            result = run(interface) => match result {
+                Ok(()) => return,
+                Err(e) => {
+                    error!("{} の{}タスクでエラーが発生しました。再起動します: {}", name, task_name, e);
+                    sleep(RESTART_DELAY).await;
+                }
+            },
+            _ = wait_until_down(&name) => {
+                warn!("{} のリンクがダウンしたため{}タスクを停止しました", name, task_name);
+            }
+        }
+    }
+}
+
+// rtnetlinkでリンクの追加・削除・状態変化を監視する。
+// 通知の取りこぼし (ENOBUFS) などで中断した場合は、現在の状態を取得し直して再開する
+#[cfg(target_os = "linux")]
+pub async fn start_link_monitor() {
+    loop {
+        if let Err(e) = linux::run_link_monitor().await {
+            error!("リンク状態の監視に失敗しました。再開します: {}", e);
+        }
+        sleep(RESTART_DELAY).await;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn start_link_monitor() {
+    info!("このOSではリンク状態の監視に対応していません");
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{update_link_state, LinkState};
+    use futures::{StreamExt, TryStreamExt};
+    use netlink_packet_core::NetlinkPayload;
+    use netlink_packet_route::link::{LinkAttribute, LinkFlag, LinkMessage};
+    use netlink_packet_route::RouteNetlinkMessage;
+    use netlink_sys::{AsyncSocket, SocketAddr};
+    use rtnetlink::constants::RTMGRP_LINK;
+    use rtnetlink::new_connection;
+
+    // リンク情報の一覧取得と通知が重なっても溢れないように受信バッファを拡張する
+    const RECEIVE_BUFFER_SIZE: usize = 1024 * 1024;
+
+    pub async fn run_link_monitor() -> Result<(), String> {
+        let (mut connection, handle, mut messages) = new_connection()
+            .map_err(|e| format!("netlink接続の作成に失敗: {}", e))?;
+        let socket = connection.socket_mut().socket_mut();
+        socket
+            .set_rx_buf_sz(RECEIVE_BUFFER_SIZE)
+            .map_err(|e| format!("受信バッファの設定に失敗: {}", e))?;
+        socket
+            .bind(&SocketAddr::new(0, RTMGRP_LINK))
+            .map_err(|e| format!("リンク通知の購読に失敗: {}", e))?;
+        tokio::spawn(connection);
+
+        // 現在のリンク状態を取得してから変更通知を待機する
+        let mut links = handle.link().get().execute();
+        while let Some(link) = links
+            .try_next()
+            .await
+            .map_err(|e| format!("リンク情報の取得に失敗: {}", e))?
+        {
+            if let Some(name) = link_name(&link) {
+                update_link_state(name, link_state(&link));
+            }
+        }
+
+        while let Some((message, _)) = messages.next().await {
+            match message.payload {
+                NetlinkPayload::InnerMessage(RouteNetlinkMessage::NewLink(link)) => {
+                    if let Some(name) = link_name(&link) {
+                        update_link_state(name, link_state(&link));
+                    }
+                }
+                NetlinkPayload::InnerMessage(RouteNetlinkMessage::DelLink(link)) => {
+                    if let Some(name) = link_name(&link) {
+                        update_link_state(name, LinkState::Removed);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err("netlinkの通知ストリームが終了しました".to_string())
+    }
+
+    fn link_name(link: &LinkMessage) -> Option<&str> {
+        link.attributes.iter().find_map(|attribute| match attribute {
+            LinkAttribute::IfName(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    // 管理上有効 (UP) かつキャリアを検出している (LOWER_UP) 場合に稼働中とみなす
+    fn link_state(link: &LinkMessage) -> LinkState {
+        let flags = &link.header.flags;
+        if flags.contains(&LinkFlag::Up) && flags.contains(&LinkFlag::LowerUp) {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        }
+    }
+}
@@ -1,89 +1,302 @@
-use crate::select_device::select_device;
+use rdb_tunnel::select_device::select_device;
+use clap::Parser;
 use dotenv::dotenv;
-use log::{error, info};
+use tracing::{error, info, info_span, warn, Instrument};
+use ipnetwork::IpNetwork;
 use std::sync::Arc;
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 use tokio::task::{self, JoinHandle};
 use tokio::time::{sleep, Duration};
-use tun_tap::{Iface, Mode};
-
-mod select_device;
-mod database;
-mod error;
-mod db_read;
-mod packet_header;
-mod db_write;
-mod firewall;
-mod firewall_packet;
-mod virtual_interface;
-mod setup_logger;
-mod packet_analysis;
-use crate::database::database::Database;
-use crate::db_read::inject_packet;
-use crate::db_write::start_packet_writer;
-use crate::error::InitProcessError;
-use crate::setup_logger::setup_logger;
-use crate::virtual_interface::setup_interface;
-
-// タスクの状態を追跡する構造体
-#[derive(Debug)]
-struct TaskState {
-    polling_active: bool,
-    writer_active: bool,
-    analysis_active: bool,
-}
 
-impl TaskState {
-    fn new() -> Self {
-        Self {
-            polling_active: false,
-            writer_active: false,
-            analysis_active: false,
-        }
-    }
-}
+use rdb_tunnel::cli::{Cli, Command};
+use rdb_tunnel::config::{Config, DatabaseConfig, RestartPolicy, TransportBackend};
+use rdb_tunnel::database::database::Database;
+use rdb_tunnel::db_read::inject_packet;
+use rdb_tunnel::db_write::{drain_packet_buffer, init_writer_shards, start_packet_writer};
+use rdb_tunnel::error::{Error, InitProcessError};
+use rdb_tunnel::health::TaskState;
+use rdb_tunnel::messages::{message, MessageId};
+use rdb_tunnel::http_server::AppState;
+use rdb_tunnel::secret_provider::SecretProviderChain;
+use rdb_tunnel::security::{self, notify, siem};
+use rdb_tunnel::setup_logger::setup_logger;
+use rdb_tunnel::virtual_interface::create_virtual_interface;
+use rdb_tunnel::{
+    bans, bench, bridge, compression, dedup, encapsulation, firewall, grpc, http_server, link_monitor, management, metrics, nat, nft_offload, packet_analysis, pmtu, probe,
+    policy_routing, qos, retry, routes, rules, rules_watch, select_device, sequence, segmentation, shaper, split_tunnel, stats, supervisor, systemd, telemetry, top, transport,
+};
+#[cfg(unix)]
+use rdb_tunnel::{control_socket, messages, setup_logger};
 
 #[tokio::main]
 async fn main() -> Result<(), InitProcessError> {
+    // .envの値もコマンドライン引数の既定値として使用する (存在しない場合は後で報告する)
+    let _ = dotenv();
+    let cli = Cli::parse();
+    let (prune_args, bench_args, train_args) = match cli.command {
+        Some(Command::Top(args)) => return top::run(args).await.map_err(InitProcessError::CommandError),
+        Some(Command::Ping(args)) => return probe::run(args).await.map_err(InitProcessError::CommandError),
+        Some(Command::Rules(args)) => return rules::run(args).await.map_err(InitProcessError::CommandError),
+        Some(Command::Prune(args)) => (Some(args), None, None),
+        // インターフェースへの注入は実行中のトンネルに対して行うため、トランスポートを初期化しない
+        Some(Command::Bench(args)) => match args.inject.clone() {
+            Some(interface) => {
+                return bench::inject(args, &interface).await.map_err(InitProcessError::CommandError)
+            }
+            None => (None, Some(args), None),
+        },
+        Some(Command::TrainDictionary(args)) => (None, None, Some(args)),
+        None => (None, None, None),
+    };
+
     // 初期化処理
-    setup_logger().map_err(|e| InitProcessError::LoggerError(e.to_string()))?;
+    let mut config = Config::load()?;
+    select_device::apply_overrides(&mut config.device, cli.device.name, cli.device.subnet, cli.device.mac);
+    setup_logger(&config.log).map_err(|e| InitProcessError::LoggerError(e.to_string()))?;
+    // パニックはバックトレース付きでログに出力し、パイプラインのタスクではスーパーバイザーが再起動する
+    supervisor::install_panic_hook();
     dotenv().map_err(|e| InitProcessError::EnvFileReadError(e.to_string()))?;
 
+    let telemetry_guard = telemetry::init_telemetry(&config.telemetry)?;
+    retry::configure(&config.retry);
+
     // 環境変数の取得
-    let timescale_host = dotenv::var("TIMESCALE_DB_HOST").map_err(|e| InitProcessError::EnvVarError(e.to_string()))?;
-    let timescale_user = dotenv::var("TIMESCALE_DB_USER").map_err(|e| InitProcessError::EnvVarError(e.to_string()))?;
-    let timescale_port = dotenv::var("TIMESCALE_DB_PORT")
-        .map_err(|e| InitProcessError::EnvVarError(e.to_string()))?
-        .parse::<u16>()
-        .map_err(|e| InitProcessError::EnvVarParseError(e.to_string()))?;
-    let timescale_password = dotenv::var("TIMESCALE_DB_PASSWORD").map_err(|e| InitProcessError::EnvVarError(e.to_string()))?;
-    let timescale_db = dotenv::var("TIMESCALE_DB_DATABASE").map_err(|e| InitProcessError::EnvVarError(e.to_string()))?;
+    let secrets = SecretProviderChain::from_env().await?;
     let tun_ip = dotenv::var("TAP_IP").map_err(|e| InitProcessError::EnvVarError(e.to_string()))?;
     let tun_mask = dotenv::var("TAP_MASK").map_err(|e| InitProcessError::EnvVarError(e.to_string()))?;
 
-    // データベース接続
-    Database::connect(&timescale_host, timescale_port, &timescale_user, &timescale_password, &timescale_db)
-        .await
-        .map_err(|e| InitProcessError::DatabaseConnectionError(e.to_string()))?;
+    // データベースはtimescaleトランスポートの場合のみ使用する
+    let uses_database = config.transport.backend == TransportBackend::Timescale;
+    let database = if uses_database {
+        Some(connect_database(&config, &secrets).await?)
+    } else {
+        None
+    };
 
-    // 仮想インターフェースのセットアップ
-    let virtual_interface = Iface::new("tap0", Mode::Tap)
-        .map_err(|e| InitProcessError::VirtualInterfaceError(e.to_string()))?;
-    info!("仮想NICの作成に成功しました: {}", virtual_interface.name());
+    if !uses_database && (prune_args.is_some() || train_args.is_some()) {
+        let command = if prune_args.is_some() { "prune" } else { "train-dictionary" };
+        return Err(InitProcessError::CommandError(format!(
+            "{}は [transport] backend = \"timescale\" の場合のみ使用できます (現在: {})",
+            command, config.transport.backend
+        )));
+    }
 
-    setup_interface("tap0", format!("{}/{}", tun_ip, tun_mask).as_str()).await?;
+    if let Some(args) = train_args {
+        let trained = management::train_dictionary(database.as_deref(), args.samples, args.max_packet_size, args.dictionary_size)
+            .await
+            .map_err(|e| InitProcessError::CommandError(e.to_string()))?;
+        std::fs::write(&args.output, &trained.dictionary).map_err(|e| {
+            InitProcessError::CommandError(format!("辞書を {} に書き込めません: {}", args.output.display(), e))
+        })?;
+        info!(
+            "{} 個のパケットから学習した辞書 ({} bytes) を {} に書き込みました",
+            trained.samples,
+            trained.dictionary.len(),
+            args.output.display()
+        );
+        return Ok(());
+    }
 
-    let interface = select_device()
+    if let Some(args) = prune_args {
+        let summary = management::prune_before(database.as_deref(), args.before)
+            .await
+            .map_err(|e| InitProcessError::CommandError(e.to_string()))?;
+        info!(
+            "{} より古いパケットを削除しました (チャンク {} 個, {} 件)",
+            args.before, summary.dropped_chunks, summary.deleted
+        );
+        return Ok(());
+    }
+
+    if let Some(db) = &database {
+        // スキーマが一致しない場合はバッチごとに失敗し続けるため、起動前に検出する
+        db.verify_schema()
+            .await
+            .map_err(InitProcessError::SchemaError)?;
+        info!("{}", message(MessageId::SchemaVerified, &[]));
+    }
+
+    transport::init_transport(&config.transport, database.clone()).await?;
+    init_writer_shards(config.writer.workers);
+    compression::configure(&config.writer).map_err(|e| InitProcessError::ConfigError(e.to_string()))?;
+    firewall::configure(&config.firewall);
+    bans::configure(&config.bans);
+    notify::configure(&config.notify);
+    security::configure(&config.security);
+    encapsulation::configure(&config.decapsulation);
+    shaper::configure(&config.shaper);
+    qos::configure(&config.qos);
+
+    if let Some(args) = bench_args {
+        metrics::init();
+        return bench::run(args).await.map_err(InitProcessError::CommandError);
+    }
+    // ベンチマークでは同じフレームを繰り返し書き込むため、重複の検出はトンネルの実行時のみ有効にする
+    dedup::configure(config.writer.broadcast_dedup_window);
+    segmentation::configure(config.writer.mtu);
+    pmtu::configure(config.writer.icmp_too_big);
+    split_tunnel::configure(&config.writer.allowed_subnets);
+
+    if let Some(db) = &database {
+        tokio::spawn(management::start_retention_task(db.clone(), config.retention.clone()));
+        tokio::spawn(stats::start_pool_stats_reporter(db.clone(), Duration::from_secs(60)));
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(reload_config_on_sighup());
+
+    // 仮想インターフェースのセットアップ (OSによって作成される名前が異なる)
+    let tap_address = format!("{}/{}", tun_ip, tun_mask)
+        .parse::<IpNetwork>()
+        .map_err(|e| InitProcessError::EnvVarParseError(e.to_string()))?;
+    let virtual_interface = create_virtual_interface(&config.interface, tap_address).await?;
+    info!("{}", message(MessageId::InterfaceCreated, &[&virtual_interface.name()]));
+
+    // メトリクスエンドポイント
+    metrics::init();
+    tokio::spawn(sequence::start_loss_reporter(Duration::from_secs(60), config.poller.loss_warning_percent));
+    if config.stats.summary {
+        tokio::spawn(stats::start_stats_summary_reporter(config.stats.summary_interval));
+    }
+    let task_state = Arc::new(Mutex::new(TaskState::new()));
+    let tunnel_network = IpNetwork::new(tap_address.network(), tap_address.prefix())
+        .map_err(|e| InitProcessError::EnvVarParseError(e.to_string()))?;
+    let http_state = AppState {
+        task_state: task_state.clone(),
+        tap_name: virtual_interface.name().to_string(),
+        tunnel_network,
+        database: database.clone(),
+    };
+    #[cfg(unix)]
+    let control_state = http_state.clone();
+    let http_config = config.http.clone();
+    tokio::spawn(async move {
+        if let Err(e) = http_server::start_http_server(http_config, http_state).await {
+            error!("{}", message(MessageId::HttpServerFailed, &[&e]));
+        }
+    });
+
+    #[cfg(unix)]
+    if config.control.enabled {
+        let control_config = config.control.clone();
+        tokio::spawn(async move {
+            if let Err(e) = control_socket::start_control_socket(control_config, control_state).await {
+                error!("{}", message(MessageId::ControlSocketFailed, &[&e]));
+            }
+        });
+    }
+
+    if config.grpc.enabled {
+        let grpc_config = config.grpc.clone();
+        let database = database.clone();
+        tokio::spawn(async move {
+            if let Err(e) = grpc::start_grpc_server(grpc_config, tunnel_network, database).await {
+                error!("{}", message(MessageId::GrpcServerFailed, &[&e]));
+            }
+        });
+    }
+
+    let interface = select_device(&config.device)
         .map_err(|e| InitProcessError::DeviceSelectionError(e.to_string()))?;
-    info!("デバイスの選択に成功しました: {}", interface.name);
+    info!("{}", message(MessageId::DeviceSelected, &[&interface.name]));
+
+    // 送信元NATの変換後のアドレスは、対向ノードが自ノード宛として送るデバイスのIPv4アドレス
+    if config.nat.enabled {
+        let address = interface
+            .ips
+            .iter()
+            .find_map(|ip| match ip.ip() {
+                std::net::IpAddr::V4(ip) => Some(ip),
+                std::net::IpAddr::V6(_) => None,
+            })
+            .ok_or_else(|| InitProcessError::DeviceSelectionError(message(MessageId::NatAddressMissing, &[])))?;
+        nat::configure(&config.nat, address);
+    }
+    nat::configure_forwards(&config.nat);
+
+    // リンク状態を監視し、ダウン・復帰に合わせてキャプチャ/転送タスクを再作成する
+    tokio::spawn(link_monitor::start_link_monitor());
 
     // シャットダウンチャネルの作成
     let (shutdown_tx, _) = broadcast::channel::<()>(1);
-    let task_state = Arc::new(Mutex::new(TaskState::new()));
+
+    // 対向ノードが広告したサブネットへの経路を仮想NICに設定する (ノードはポーリングと同じくデバイスのIPv4アドレスで識別する)
+    // [routes] はtimescaleトランスポートの場合のみ有効にできる
+    let routes_handle = if let (true, Some(db)) = (config.routes.enabled, &database) {
+        let node = interface
+            .ips
+            .iter()
+            .find(|ip| ip.is_ipv4())
+            .map(|ip| ip.ip())
+            .ok_or_else(|| InitProcessError::DeviceSelectionError(message(MessageId::NodeAddressMissing, &[])))?;
+        Some(tokio::spawn(routes::start_route_sync(
+            db.clone(),
+            config.routes.clone(),
+            node,
+            tap_address,
+            virtual_interface.name().to_string(),
+            config.interface.mode,
+            shutdown_tx.subscribe(),
+        )))
+    } else {
+        None
+    };
+
+    // 送信側チェインで遮断しているIPアドレスをnftablesのセットへ反映し、キャプチャの前にカーネルで破棄する
+    let nft_offload_handle = config
+        .nft_offload
+        .enabled
+        .then(|| tokio::spawn(nft_offload::start_sync(config.nft_offload.clone(), shutdown_tx.subscribe())));
+    // ルールセットのディレクトリを監視し、変更されたファイルのルールを再読み込みする
+    if let Some(dir) = config.firewall.rules_dir.clone() {
+        tokio::spawn(rules_watch::start(dir, shutdown_tx.subscribe()));
+    }
+    // 期限を過ぎた一時的な遮断を解除する (管理APIから遮断した場合も対象にするため常に起動する)
+    tokio::spawn(bans::start_unban_scheduler(shutdown_tx.subscribe()));
+    // 再構成中のTCPストリームのうちデータが届かなくなったものを検査に渡す (SIGHUPで有効にした場合も対象にするため常に起動する)
+    tokio::spawn(security::start_stream_sweeper(shutdown_tx.subscribe()));
+    // アラートとトンネルの重大なイベントを外部へ通知する
+    if config.notify.enabled {
+        tokio::spawn(notify::start(shutdown_tx.subscribe()));
+    }
+    // アラートとフローレコードをCEF/LEEF形式でSIEMへ送信する
+    if config.siem.enabled {
+        tokio::spawn(siem::start(config.siem.clone(), shutdown_tx.subscribe()));
+    }
 
     let polling_interface = interface.clone();
-    let analysis_interface = interface.clone();
+    let poll_mode = config.poller.mode;
+    let analysis_interfaces = packet_analysis::resolve_capture_interfaces(
+        &config.capture.interfaces,
+        &interface,
+        virtual_interface.name(),
+    )
+    .map_err(|e| InitProcessError::DeviceSelectionError(e.to_string()))?;
+    let capture_names = analysis_interfaces.iter().map(|iface| iface.name.as_str()).collect::<Vec<_>>().join(", ");
+    info!("{}", message(MessageId::CaptureInterfaces, &[&capture_names]));
+
+    // 仮想NICと物理NICをブリッジで接続し、物理NICのL2セグメントをトンネルで延長する
+    let mut bridge = if config.bridge.enabled {
+        Some(bridge::install(&config.bridge, virtual_interface.name()).await?)
+    } else {
+        None
+    };
+
+    // 送信元アドレス・fwmark・cgroupで選択したトラフィックを仮想NICへ流す
+    let mut policy_routing = if config.policy_routing.enabled {
+        match policy_routing::install(&config.policy_routing, virtual_interface.name()).await {
+            Ok(policy_routing) => Some(policy_routing),
+            Err(e) => {
+                if let Some(bridge) = bridge.take() {
+                    bridge.remove().await;
+                }
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
 
     let polling_shutdown = shutdown_tx.subscribe();
     let writer_shutdown = shutdown_tx.subscribe();
@@ -97,8 +310,13 @@ async fn main() -> Result<(), InitProcessError> {
         "ポーリング",
         task_state_polling,
         polling_shutdown,
-        || async {
-            inject_packet(polling_interface).await.map_err(|e| e.to_string())
+        config.supervisor.polling.clone(),
+        move || {
+            let name = polling_interface.name.clone();
+            async move {
+                link_monitor::supervise(name, "ポーリング", |interface| inject_packet(interface, poll_mode)).await;
+                Ok(())
+            }
         },
     );
 
@@ -106,6 +324,7 @@ async fn main() -> Result<(), InitProcessError> {
         "ライター",
         task_state_writer,
         writer_shutdown,
+        config.supervisor.writer.clone(),
         || async {
             start_packet_writer().await;
             Ok(())
@@ -116,62 +335,193 @@ async fn main() -> Result<(), InitProcessError> {
         "分析",
         task_state_analysis,
         analysis_shutdown,
-        || async {
-            packet_analysis::packet_analysis(analysis_interface)
-                .await
-                .map_err(|e| e.to_string())
+        config.supervisor.analysis.clone(),
+        move || {
+            let interfaces = analysis_interfaces.clone();
+            async move {
+                packet_analysis::packet_analysis(interfaces)
+                    .await
+                    .map_err(Error::from)
+            }
         },
     );
 
-    loop {
-        tokio::select! {
-            _ = polling_handle => {
-                error!("ポーリングタスクが予期せず終了しました");
-                break;
-            }
-            _ = writer_handle => {
-                error!("ライタータスクが予期せず終了しました");
-                break;
-            }
-            _ = analysis_handle => {
-                error!("分析タスクが予期せず終了しました");
-                break;
-            }
-            _ = tokio::signal::ctrl_c() => {
-                info!("シャットダウン信号を受信しました");
-                let _ = shutdown_tx.send(());
-
-                for _ in 0..10 {
-                    let state = task_state.lock().await;
-                    if !state.polling_active && !state.writer_active && !state.analysis_active {
-                        info!("全てのタスクが正常に終了しました");
-                        std::process::exit(0);
-                        return Ok(());
-                    }
-                    drop(state);
-                    sleep(Duration::from_millis(100)).await;
+    // DB接続、TAPの設定、パイプラインの起動が完了したことをsystemdへ通知する
+    systemd::ready(&format!("{} でパケットを転送しています", interface.name));
+    tokio::spawn(systemd::start_watchdog(task_state.clone()));
+
+    tokio::select! {
+        _ = polling_handle => {
+            error!("{}", message(MessageId::PollingTaskExited, &[]));
+        }
+        _ = writer_handle => {
+            error!("{}", message(MessageId::WriterTaskExited, &[]));
+        }
+        _ = analysis_handle => {
+            error!("{}", message(MessageId::AnalysisTaskExited, &[]));
+        }
+        signal = shutdown_signal() => {
+            info!("{}", message(MessageId::ShutdownSignal, &[&signal]));
+            systemd::stopping("シャットダウンしています");
+            let _ = shutdown_tx.send(());
+            // 仮想NICを削除する前に、設定した経路を削除する
+            if let Some(handle) = routes_handle {
+                let _ = tokio::time::timeout(Duration::from_secs(1), handle).await;
+            }
+            if let Some(handle) = nft_offload_handle {
+                let _ = tokio::time::timeout(Duration::from_secs(1), handle).await;
+            }
+            if let Some(policy_routing) = policy_routing.take() {
+                policy_routing.remove().await;
+            }
+            if let Some(bridge) = bridge.take() {
+                bridge.remove().await;
+            }
+
+            let mut stopped = false;
+            for _ in 0..10 {
+                if !task_state.lock().await.any_active() {
+                    stopped = true;
+                    break;
                 }
+                sleep(Duration::from_millis(100)).await;
+            }
 
-                error!("タスクの終了待機がタイムアウトしました");
-                break;
+            // キャプチャを停止した後、バッファに残っているパケットを送信する
+            let (flushed, dropped) = drain_packet_buffer(config.writer.shutdown_timeout).await;
+            if dropped > 0 {
+                warn!("{}", message(MessageId::ShutdownDropped, &[&flushed, &dropped]));
+            } else if flushed > 0 {
+                info!("{}", message(MessageId::ShutdownFlushed, &[&flushed]));
+            }
+
+            if stopped {
+                info!("{}", message(MessageId::ShutdownComplete, &[]));
+                if let Some(guard) = &telemetry_guard {
+                    guard.shutdown();
+                }
+                std::process::exit(0);
             }
+            error!("{}", message(MessageId::ShutdownTimeout, &[]));
         }
     }
 
-    error!("アプリケーションが異常終了します");
+    error!("{}", message(MessageId::AbnormalExit, &[]));
+    if let Some(policy_routing) = policy_routing.take() {
+        policy_routing.remove().await;
+    }
+    if let Some(bridge) = bridge.take() {
+        bridge.remove().await;
+    }
+    systemd::stopping("異常終了しました");
+    if let Some(guard) = &telemetry_guard {
+        guard.shutdown();
+    }
     std::process::exit(1);
 }
 
+// データベースに接続し、有効な場合はマイグレーションを適用する
+async fn connect_database(config: &Config, secrets: &SecretProviderChain) -> Result<Arc<Database>, InitProcessError> {
+    let database_config = DatabaseConfig::load(secrets).await?;
+    if database_config.pool.max_size < config.writer.workers as u32 {
+        warn!("{}", message(MessageId::PoolSmallerThanWorkers, &[&database_config.pool.max_size, &config.writer.workers]));
+    }
+    let db = Database::connect(&database_config)
+        .await
+        .map_err(InitProcessError::DatabaseConnectionError)?;
+
+    if config.migrations.enabled {
+        db.run_migrations(&config.migrations)
+            .await
+            .map_err(InitProcessError::MigrationError)?;
+    }
+    Ok(db)
+}
+
+// SIGINT (Ctrl+C)・SIGTERM (systemctl stop など)・SIGQUIT のいずれかを受信するまで待機し、受信したシグナルの名前を返す
+#[cfg(unix)]
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, Signal, SignalKind};
+
+    let register = |kind: SignalKind, name: &str| match signal(kind) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            error!("{}", message(MessageId::SignalHandlerFailed, &[&name, &e]));
+            None
+        }
+    };
+    // 登録に失敗したシグナルは待機しない
+    async fn recv(signal: &mut Option<Signal>) {
+        match signal {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    let mut terminate = register(SignalKind::terminate(), "SIGTERM");
+    let mut quit = register(SignalKind::quit(), "SIGQUIT");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = recv(&mut terminate) => "SIGTERM",
+        _ = recv(&mut quit) => "SIGQUIT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl+C"
+}
+
+// SIGHUPで設定ファイルを再読み込みし、変更可能な設定を反映する
+#[cfg(unix)]
+async fn reload_config_on_sighup() {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            error!("{}", message(MessageId::SignalHandlerFailed, &[&"SIGHUP", &e]));
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        info!("{}", message(MessageId::ConfigReloading, &[]));
+        match Config::load() {
+            Ok(config) => {
+                if let Err(e) = setup_logger::set_log_filter(&config.log.level) {
+                    error!("{}", message(MessageId::LogFilterReloadFailed, &[&e]));
+                }
+                if let Err(e) = messages::configure(&config.log) {
+                    error!("{}", message(MessageId::ConfigReloadFailed, &[&e]));
+                }
+                firewall::configure(&config.firewall);
+                bans::configure(&config.bans);
+                notify::configure(&config.notify);
+                security::configure(&config.security);
+                encapsulation::configure(&config.decapsulation);
+                shaper::configure(&config.shaper);
+                qos::configure(&config.qos);
+                retry::configure(&config.retry);
+            }
+            Err(e) => error!("{}", message(MessageId::ConfigReloadFailed, &[&e])),
+        }
+    }
+}
+
 fn spawn_monitored_task<F, Fut>(
     task_name: &'static str,
     task_state: Arc<Mutex<TaskState>>,
     mut shutdown: broadcast::Receiver<()>,
+    policy: RestartPolicy,
     future: F,
-) -> JoinHandle<Result<(), String>>
+) -> JoinHandle<Result<(), Error>>
 where
-    F: FnOnce() -> Fut + Send + 'static,
-    Fut: futures::Future<Output=Result<(), String>> + Send + 'static,
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: futures::Future<Output=Result<(), Error>> + Send + 'static,
 {
+    let span = info_span!("task", name = task_name);
     task::spawn(async move {
         {
             let mut state = task_state.lock().await;
@@ -183,10 +533,17 @@ where
             }
         }
 
+        // 失敗した場合は方針に従って再起動し、再起動できない場合のみ終了する
+        let label = match task_name {
+            "ポーリング" => "polling",
+            "ライター" => "writer",
+            "分析" => "analysis",
+            _ => "other",
+        };
         let result = tokio::select! {
-            result = future() => result,
+            result = supervisor::supervise(task_name, label, &policy, future) => result,
             _ = shutdown.recv() => {
-                info!("{}タスクをシャットダウンしています...", task_name);
+                info!("{}", message(MessageId::TaskShuttingDown, &[&task_name, &label]));
                 Ok(())
             }
         };
@@ -202,5 +559,5 @@ where
         }
 
         result
-    })
+    }.instrument(span))
 }
\ No newline at end of file
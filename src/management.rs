@@ -0,0 +1,170 @@
+use crate::database::database::Database;
+use crate::compression::{self, Codec};
+use crate::config::RetentionConfig;
+use crate::database::error::DbError;
+use crate::database::retention::PruneSummary;
+use crate::db_write::flush_packet_buffer;
+use crate::firewall::{Chain, Filter, Policy, Rule, RuleStats, FIREWALL};
+use crate::peers::{self, PeerSummary};
+use crate::rules::RuleSet;
+use crate::traffic::{self, PeerTraffic};
+use crate::transport::TransportError;
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+// REST APIとgRPC APIで共通の管理操作
+
+// 一致したパケット数と最後に一致した時刻を含む
+pub fn firewall_rules(chain: Chain) -> (Policy, Vec<RuleStats>) {
+    let firewall = FIREWALL.read().unwrap_or_else(|e| e.into_inner());
+    let chain = firewall.chain(chain);
+    (chain.policy(), chain.rule_stats())
+}
+
+// 同じフィルタが既に存在する場合は優先度とスケジュールを更新する
+pub fn add_firewall_rule(chain: Chain, rule: Rule) -> Result<(), String> {
+    if let Some(schedule) = &rule.schedule {
+        schedule.validate()?;
+    }
+    info!(
+        "ファイアウォールルールを追加しました ({:?}): {:?} (優先度 {}, スケジュール {:?})",
+        chain, rule.filter, rule.priority, rule.schedule
+    );
+    FIREWALL
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .chain_mut(chain)
+        .insert(rule);
+    Ok(())
+}
+
+pub fn remove_firewall_rule(chain: Chain, filter: &Filter) -> bool {
+    let removed = FIREWALL
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .chain_mut(chain)
+        .remove_rule(filter);
+    if removed {
+        info!("ファイアウォールルールを削除しました ({:?}): {:?}", chain, filter);
+    }
+    removed
+}
+
+pub fn export_firewall_rules() -> RuleSet {
+    RuleSet::from_firewall(&FIREWALL.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+// 検証に失敗した場合は現在のルールを変更しない
+pub fn import_firewall_rules(rule_set: RuleSet) -> Result<(), String> {
+    let (input, output) = (rule_set.input.rules.len(), rule_set.output.rules.len());
+    let firewall = rule_set.into_firewall()?;
+    *FIREWALL.write().unwrap_or_else(|e| e.into_inner()) = firewall;
+    info!("ファイアウォールルールを置き換えました (input {}件, output {}件)", input, output);
+    Ok(())
+}
+
+// データベースを使用しないトランスポートでは、データベースに依存する操作はエラーにする
+fn database(database: Option<&Database>) -> Result<&Database, DbError> {
+    database.ok_or_else(|| {
+        DbError::Other("データベースを使用していません ([transport] backend = \"timescale\" の場合のみ利用できます)".to_string())
+    })
+}
+
+pub async fn list_peers(
+    db: Option<&Database>,
+    tunnel_network: IpNetwork,
+    window: Duration,
+) -> Result<Vec<PeerSummary>, DbError> {
+    peers::list_peers(database(db)?, tunnel_network, window).await
+}
+
+// 起動時からの対向ノードごとの送受信量 (トンネルのネットワーク内のアドレスのみ)。データベースは使用しない
+pub fn peer_traffic(tunnel_network: IpNetwork) -> Vec<PeerTraffic> {
+    traffic::peer_totals()
+        .into_iter()
+        .filter(|peer| tunnel_network.contains(peer.address))
+        .collect()
+}
+
+// 送信待ちのパケットを即座にトランスポートへ送信する
+pub async fn flush() -> Result<usize, TransportError> {
+    let flushed = flush_packet_buffer().await?;
+    info!("パケットバッファを手動でフラッシュしました: {}件", flushed);
+    Ok(flushed)
+}
+
+// 指定した期間より古いパケットを削除する
+pub async fn prune(db: Option<&Database>, older_than: Duration) -> Result<PruneSummary, DbError> {
+    let age = chrono::Duration::from_std(older_than)
+        .map_err(|_| DbError::Other(format!("保持期間が大きすぎます: {:?}", older_than)))?;
+    prune_before(db, Utc::now() - age).await
+}
+
+// 指定時刻より古いパケットを削除する
+pub async fn prune_before(db: Option<&Database>, before: DateTime<Utc>) -> Result<PruneSummary, DbError> {
+    database(db)?.prune_packets(before).await
+}
+
+// 学習した圧縮辞書
+#[derive(Debug)]
+pub struct TrainedDictionary {
+    pub dictionary: Vec<u8>,
+    // 学習に使用したパケット数
+    pub samples: usize,
+}
+
+// 保存済みの小さいパケットを標本としてzstdの辞書を学習する (圧縮して保存されたパケットは展開してから使用する)
+pub async fn train_dictionary(
+    db: Option<&Database>,
+    samples: u32,
+    max_packet_size: usize,
+    dictionary_size: usize,
+) -> Result<TrainedDictionary, DbError> {
+    let rows = database(db)?.sample_packets(samples as i64, max_packet_size.min(i32::MAX as usize) as i32).await?;
+    let samples = rows
+        .into_iter()
+        .filter_map(|(codec, raw_packet)| {
+            let codec = Codec::from_id(codec)?;
+            compression::decompress(codec, &raw_packet).ok()
+        })
+        .filter(|raw_packet| raw_packet.len() <= max_packet_size)
+        .collect::<Vec<_>>();
+    let dictionary = compression::train_dictionary(&samples, dictionary_size)
+        .map_err(|e| DbError::Other(format!("{} 個のパケットから辞書を学習できませんでした: {}", samples.len(), e)))?;
+    Ok(TrainedDictionary { dictionary, samples: samples.len() })
+}
+
+// [retention] max_age または delete_delivered が設定されている場合にパケットを定期的に削除する
+pub async fn start_retention_task(db: Arc<Database>, config: RetentionConfig) {
+    if config.max_age.is_none() && !config.delete_delivered {
+        return;
+    }
+    if let Some(max_age) = config.max_age {
+        info!(
+            "保持期間 {} を超えたパケットを {} ごとに削除します",
+            humantime::format_duration(max_age),
+            humantime::format_duration(config.interval)
+        );
+    }
+    if config.delete_delivered {
+        info!("全ての宛先のノードが処理済みのパケットを {} ごとに削除します", humantime::format_duration(config.interval));
+    }
+
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        interval.tick().await;
+        if let Some(max_age) = config.max_age {
+            if let Err(e) = prune(Some(&db), max_age).await {
+                error!("古いパケットの定期削除に失敗しました: {}", e);
+            }
+        }
+        if config.delete_delivered {
+            if let Err(e) = db.delete_delivered_packets().await {
+                error!("処理済みのパケットの定期削除に失敗しました: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use crate::config::{LogConfig, LogLang};
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// 運用上重要なログメッセージのカタログ。[log] lang (LOG_LANG環境変数で上書き可能) で出力する言語を選択する。
+// メッセージIDは言語によらず固定のため、ログの検索やアラートの条件にはIDを使用する
+
+static LANG: AtomicU8 = AtomicU8::new(LogLang::Ja as u8);
+
+// LOG_LANG環境変数が設定されている場合は設定ファイルより優先する
+pub fn configure(config: &LogConfig) -> Result<(), String> {
+    let lang = match dotenv::var("LOG_LANG") {
+        Ok(lang) if !lang.is_empty() => lang.parse::<LogLang>()?,
+        _ => config.lang,
+    };
+    LANG.store(lang as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+fn language() -> LogLang {
+    match LANG.load(Ordering::Relaxed) {
+        value if value == LogLang::En as u8 => LogLang::En,
+        _ => LogLang::Ja,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageId {
+    // 起動・終了 (RDB1xxx)
+    SchemaVerified,
+    InterfaceCreated,
+    DeviceSelected,
+    CaptureInterfaces,
+    NatAddressMissing,
+    NodeAddressMissing,
+    PoolSmallerThanWorkers,
+    PollingTaskExited,
+    WriterTaskExited,
+    AnalysisTaskExited,
+    ShutdownSignal,
+    ShutdownFlushed,
+    ShutdownDropped,
+    ShutdownComplete,
+    ShutdownTimeout,
+    AbnormalExit,
+    SignalHandlerFailed,
+    TaskShuttingDown,
+    ConfigReloading,
+    ConfigReloadFailed,
+    LogFilterReloadFailed,
+    HttpServerFailed,
+    ControlSocketFailed,
+    GrpcServerFailed,
+    // パケットの転送 (RDB2xxx)
+    WriterStarted,
+    FlushFailed,
+    WriterWorkerFailed,
+    ShutdownFlushFailed,
+    ForwardingStarted,
+    PollFailed,
+    InjectFailed,
+    CursorMissing,
+    CursorTooOld,
+    CursorResumed,
+    // タスクの監視・再試行 (RDB3xxx)
+    Panic,
+    TaskFatal,
+    TaskFailed,
+    TaskRestarting,
+    RetryScheduled,
+}
+
+impl MessageId {
+    #[cfg(test)]
+    const ALL: [MessageId; 39] = [
+        MessageId::SchemaVerified,
+        MessageId::InterfaceCreated,
+        MessageId::DeviceSelected,
+        MessageId::CaptureInterfaces,
+        MessageId::NatAddressMissing,
+        MessageId::NodeAddressMissing,
+        MessageId::PoolSmallerThanWorkers,
+        MessageId::PollingTaskExited,
+        MessageId::WriterTaskExited,
+        MessageId::AnalysisTaskExited,
+        MessageId::ShutdownSignal,
+        MessageId::ShutdownFlushed,
+        MessageId::ShutdownDropped,
+        MessageId::ShutdownComplete,
+        MessageId::ShutdownTimeout,
+        MessageId::AbnormalExit,
+        MessageId::SignalHandlerFailed,
+        MessageId::TaskShuttingDown,
+        MessageId::ConfigReloading,
+        MessageId::ConfigReloadFailed,
+        MessageId::LogFilterReloadFailed,
+        MessageId::HttpServerFailed,
+        MessageId::ControlSocketFailed,
+        MessageId::GrpcServerFailed,
+        MessageId::WriterStarted,
+        MessageId::FlushFailed,
+        MessageId::WriterWorkerFailed,
+        MessageId::ShutdownFlushFailed,
+        MessageId::ForwardingStarted,
+        MessageId::PollFailed,
+        MessageId::InjectFailed,
+        MessageId::CursorMissing,
+        MessageId::CursorTooOld,
+        MessageId::CursorResumed,
+        MessageId::Panic,
+        MessageId::TaskFatal,
+        MessageId::TaskFailed,
+        MessageId::TaskRestarting,
+        MessageId::RetryScheduled,
+    ];
+
+    // (ID, 日本語, 英語)。{} は引数を順に、{0} などは指定した位置の引数を埋め込む
+    fn entry(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            MessageId::SchemaVerified => ("RDB1001", "データベースのスキーマを確認しました", "Verified database schema"),
+            MessageId::InterfaceCreated => ("RDB1002", "仮想NICの作成に成功しました: {}", "Created virtual interface: {}"),
+            MessageId::DeviceSelected => ("RDB1003", "デバイスの選択に成功しました: {}", "Selected device: {}"),
+            MessageId::CaptureInterfaces => ("RDB1004", "キャプチャ対象のインターフェース: {}", "Capturing on interfaces: {}"),
+            MessageId::NatAddressMissing => (
+                "RDB1005",
+                "送信元NATに使用するIPv4アドレスがありません",
+                "No IPv4 address available for source NAT",
+            ),
+            MessageId::NodeAddressMissing => ("RDB1006", "IPv4アドレスが見つかりません", "No IPv4 address found on the device"),
+            MessageId::PoolSmallerThanWorkers => (
+                "RDB1007",
+                "接続プールの最大数 ({}) が書き込みワーカー数 ({}) より少ないため、ワーカーが接続を待機します",
+                "Connection pool size ({}) is smaller than the number of writer workers ({}), workers will wait for connections",
+            ),
+            MessageId::PollingTaskExited => ("RDB1010", "ポーリングタスクが予期せず終了しました", "Polling task exited unexpectedly"),
+            MessageId::WriterTaskExited => ("RDB1011", "ライタータスクが予期せず終了しました", "Writer task exited unexpectedly"),
+            MessageId::AnalysisTaskExited => ("RDB1012", "分析タスクが予期せず終了しました", "Analysis task exited unexpectedly"),
+            MessageId::ShutdownSignal => ("RDB1020", "シャットダウン信号 ({}) を受信しました", "Received shutdown signal ({})"),
+            MessageId::ShutdownFlushed => (
+                "RDB1021",
+                "終了時にバッファのパケットを送信しました ({}件)",
+                "Flushed {} buffered packets on shutdown",
+            ),
+            MessageId::ShutdownDropped => (
+                "RDB1022",
+                "終了時にバッファのパケットを{}件送信し、{}件を破棄しました",
+                "Flushed {} buffered packets and dropped {} on shutdown",
+            ),
+            MessageId::ShutdownComplete => ("RDB1023", "全てのタスクが正常に終了しました", "All tasks stopped cleanly"),
+            MessageId::ShutdownTimeout => ("RDB1024", "タスクの終了待機がタイムアウトしました", "Timed out waiting for tasks to stop"),
+            MessageId::AbnormalExit => ("RDB1025", "アプリケーションが異常終了します", "Exiting abnormally"),
+            MessageId::SignalHandlerFailed => (
+                "RDB1026",
+                "{}ハンドラの登録に失敗しました: {}",
+                "Failed to register {} handler: {}",
+            ),
+            MessageId::TaskShuttingDown => ("RDB1027", "{0}タスクをシャットダウンしています...", "Shutting down {1} task..."),
+            MessageId::ConfigReloading => (
+                "RDB1030",
+                "SIGHUPを受信しました。設定ファイルを再読み込みします",
+                "Received SIGHUP, reloading configuration",
+            ),
+            MessageId::ConfigReloadFailed => (
+                "RDB1031",
+                "設定ファイルの再読み込みに失敗しました: {}",
+                "Failed to reload configuration: {}",
+            ),
+            MessageId::LogFilterReloadFailed => (
+                "RDB1032",
+                "ログレベルの反映に失敗しました: {}",
+                "Failed to apply log level: {}",
+            ),
+            MessageId::HttpServerFailed => ("RDB1040", "HTTPサーバーでエラーが発生しました: {}", "HTTP server error: {}"),
+            MessageId::ControlSocketFailed => ("RDB1041", "制御ソケットでエラーが発生しました: {}", "Control socket error: {}"),
+            MessageId::GrpcServerFailed => ("RDB1042", "gRPCサーバーでエラーが発生しました: {}", "gRPC server error: {}"),
+            MessageId::WriterStarted => (
+                "RDB2001",
+                "パケットライターを開始します (ワーカー数: {})",
+                "Starting packet writer ({} workers)",
+            ),
+            MessageId::FlushFailed => (
+                "RDB2002",
+                "パケットバッファのフラッシュに失敗しました (ワーカー{}): {} (前回から{}件を省略)",
+                "Failed to flush packet buffer (worker {}): {} ({} similar messages suppressed)",
+            ),
+            MessageId::WriterWorkerFailed => (
+                "RDB2003",
+                "書き込みワーカーが異常終了しました: {}",
+                "Writer worker terminated abnormally: {}",
+            ),
+            MessageId::ShutdownFlushFailed => (
+                "RDB2004",
+                "終了時のパケットバッファのフラッシュに失敗しました (ワーカー{}): {}",
+                "Failed to flush packet buffer on shutdown (worker {}): {}",
+            ),
+            MessageId::ForwardingStarted => ("RDB2010", "パケット転送を開始します: {}", "Starting packet forwarding: {}"),
+            MessageId::PollFailed => (
+                "RDB2011",
+                "パケット処理中にエラーが発生しました: {} (前回から{}件を省略)",
+                "Error while processing packets: {} ({} similar messages suppressed)",
+            ),
+            MessageId::InjectFailed => (
+                "RDB2012",
+                "パケット送信に失敗しました: {} (前回から{}件を省略)",
+                "Failed to send packet: {} ({} similar messages suppressed)",
+            ),
+            MessageId::CursorMissing => (
+                "RDB2020",
+                "保存されたポーリング位置がないため、直近のパケットから取得します",
+                "No saved polling position, starting from recent packets",
+            ),
+            MessageId::CursorTooOld => (
+                "RDB2021",
+                "保存されたポーリング位置 ({} に保存) が古すぎるため、{} から再開します",
+                "Saved polling position (saved at {}) is too old, resuming from {}",
+            ),
+            MessageId::CursorResumed => (
+                "RDB2022",
+                "保存されたポーリング位置から再開します: {} (id {})",
+                "Resuming from saved polling position: {} (id {})",
+            ),
+            MessageId::Panic => ("RDB3001", "パニックが発生しました: {} ({})\n{}", "Panicked: {} ({})\n{}"),
+            MessageId::TaskFatal => (
+                "RDB3002",
+                "{0}タスクが再試行できないエラーで失敗しました: {2}",
+                "{1} task failed with a non-retryable error: {2}",
+            ),
+            MessageId::TaskFailed => ("RDB3003", "{0}タスクが失敗しました: {2}", "{1} task failed: {2}"),
+            MessageId::TaskRestarting => (
+                "RDB3004",
+                "{0}タスクが失敗しました: {2} ({3}ms後に再起動します {4}/{5})",
+                "{1} task failed: {2} (restarting in {3}ms, {4}/{5})",
+            ),
+            MessageId::RetryScheduled => (
+                "RDB3010",
+                "データベース操作 ({}) に失敗したため{}ms後に再試行します ({}/{}): {}",
+                "Database operation ({}) failed, retrying in {}ms ({}/{}): {}",
+            ),
+        }
+    }
+
+    // 言語によらず固定のメッセージID
+    pub fn code(self) -> &'static str {
+        self.entry().0
+    }
+
+    fn template(self, lang: LogLang) -> &'static str {
+        let (_, ja, en) = self.entry();
+        match lang {
+            LogLang::Ja => ja,
+            LogLang::En => en,
+        }
+    }
+}
+
+// 設定した言語でメッセージを組み立てる (先頭にメッセージIDを付ける)
+pub fn message(id: MessageId, args: &[&dyn Display]) -> String {
+    render(id, language(), args)
+}
+
+fn render(id: MessageId, lang: LogLang, args: &[&dyn Display]) -> String {
+    let mut out = format!("[{}] ", id.code());
+    let mut rest = id.template(lang);
+    let mut next = 0;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            rest = &rest[start..];
+            break;
+        };
+        let index = match &rest[start + 1..end] {
+            "" => {
+                next += 1;
+                next - 1
+            }
+            position => position.parse().unwrap_or(usize::MAX),
+        };
+        if let Some(arg) = args.get(index) {
+            let _ = write!(out, "{}", arg);
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn renders_messages_in_each_language() {
+        assert_eq!(
+            render(MessageId::ShutdownDropped, LogLang::Ja, &[&3, &2]),
+            "[RDB1022] 終了時にバッファのパケットを3件送信し、2件を破棄しました"
+        );
+        assert_eq!(
+            render(MessageId::ShutdownDropped, LogLang::En, &[&3, &2]),
+            "[RDB1022] Flushed 3 buffered packets and dropped 2 on shutdown"
+        );
+        // 位置を指定した引数は言語ごとに異なる引数を選べる
+        let args: [&dyn Display; 3] = [&"ポーリング", &"polling", &"closed"];
+        assert_eq!(render(MessageId::TaskFailed, LogLang::Ja, &args), "[RDB3003] ポーリングタスクが失敗しました: closed");
+        assert_eq!(render(MessageId::TaskFailed, LogLang::En, &args), "[RDB3003] polling task failed: closed");
+    }
+
+    #[test]
+    fn message_ids_are_unique() {
+        let codes = MessageId::ALL.iter().map(|id| id.code()).collect::<HashSet<_>>();
+        assert_eq!(codes.len(), MessageId::ALL.len());
+        for id in MessageId::ALL {
+            let placeholders = |lang| id.template(lang).matches('{').count();
+            assert!(id.code().starts_with("RDB"));
+            // 位置を指定したメッセージ以外は、言語によって引数の数が変わらないようにする
+            if !id.template(LogLang::Ja).contains("{0}") {
+                assert_eq!(placeholders(LogLang::Ja), placeholders(LogLang::En), "{:?}", id);
+            }
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use crate::database::database::Database;
+use crate::events::{self, Alert};
+use crate::sequence::{self, PeerDelivery};
+use crate::stats::PoolStats;
+use crate::traffic::{self, DirectionalTotals, PeerTraffic, PortTraffic, TrafficTotals};
+use lazy_static::lazy_static;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// DBアクセスのレイテンシ用バケット (秒)
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new_custom(Some("rdb_tunnel".to_string()), None)
+        .expect("メトリクスレジストリの作成に失敗しました");
+
+    // キャプチャしたパケット数 (インターフェース別)
+    pub static ref PACKETS_CAPTURED: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("packets_captured_total", "Packets captured from network interfaces"),
+        &["interface"],
+    ));
+
+    // データベースに書き込んだパケット数
+    pub static ref PACKETS_WRITTEN: IntCounter = register(IntCounter::new(
+        "packets_written_total",
+        "Packets written to the database",
+    ));
+
+    // データベースから取得して注入したパケット数
+    pub static ref PACKETS_INJECTED: IntCounter = register(IntCounter::new(
+        "packets_injected_total",
+        "Packets read from the database and injected into the interface",
+    ));
+
+    // 破棄したパケット数 (理由別)
+    pub static ref PACKETS_DROPPED: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("packets_dropped_total", "Packets dropped in the pipeline"),
+        &["reason"],
+    ));
+
+    // ファイアウォールで遮断したパケット数
+    pub static ref FIREWALL_DROPS: IntCounter = register(IntCounter::new(
+        "firewall_drops_total",
+        "Packets blocked by the firewall",
+    ));
+
+    // MTUに収まるよう分割した、オフロードで結合されたTCPフレーム数
+    pub static ref FRAMES_SEGMENTED: IntCounter = register(IntCounter::new(
+        "frames_segmented_total",
+        "Oversized TCP frames (TSO/GSO) split into MTU-sized segments",
+    ));
+
+    // 宛先が allowed_subnets の範囲外のため書き込まなかったパケット数
+    pub static ref PACKETS_EXCLUDED: IntCounter = register(IntCounter::new(
+        "packets_excluded_total",
+        "Captured packets not written because the destination is outside allowed_subnets",
+    ));
+
+    // 一時的なエラーで再試行したデータベース操作の回数 (操作別)
+    pub static ref DB_RETRIES: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("db_retries_total", "Database operations retried after a transient error"),
+        &["operation"],
+    ));
+
+    // 失敗して再起動したパイプラインのタスクの回数 (タスク別)
+    pub static ref TASK_RESTARTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("task_restarts_total", "Pipeline tasks restarted by the supervisor after failing"),
+        &["task"],
+    ));
+
+    // パニックしたパイプラインのタスクの回数 (タスク別)
+    pub static ref TASK_PANICS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("task_panics_total", "Pipeline tasks that panicked"),
+        &["task"],
+    ));
+
+    // 送信元NATの変換表の件数
+    pub static ref NAT_MAPPINGS: IntGauge = register(IntGauge::new(
+        "nat_mappings",
+        "Active source NAT mappings",
+    ));
+
+    // ポート転送中の接続数
+    pub static ref NAT_FORWARD_SESSIONS: IntGauge = register(IntGauge::new(
+        "nat_forward_sessions",
+        "Active port forwarding sessions",
+    ));
+
+    // DSCPを書き換えたパケット数
+    pub static ref PACKETS_REMARKED: IntCounter = register(IntCounter::new(
+        "packets_remarked_total",
+        "Packets whose DSCP was rewritten by a remark rule",
+    ));
+
+    // IDPSのアラート数
+    pub static ref IDPS_ALERTS: IntCounter = register(IntCounter::new(
+        "idps_alerts_total",
+        "Alerts raised by the IDPS",
+    ));
+
+    // 検知により一時的に遮断した回数 (検知機能別)
+    pub static ref BANS_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("bans_total", "Addresses temporarily banned"),
+        &["detector"],
+    ));
+
+    // 遮断中のアドレス数
+    pub static ref BANS_ACTIVE: IntGauge = register(IntGauge::new(
+        "bans_active",
+        "Addresses currently banned",
+    ));
+
+    // アラートの通知の送信数 (結果別)
+    pub static ref NOTIFICATIONS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("notifications_total", "Alert notifications posted to endpoints"),
+        &["result"],
+    ));
+
+    // SIEMへ送信したイベント数 (結果別)
+    pub static ref SIEM_EVENTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("siem_events_total", "Events exported to SIEM sinks"),
+        &["result"],
+    ));
+
+    // 書き込み待ちバッファのパケット数
+    pub static ref BUFFER_DEPTH: IntGauge = register(IntGauge::new(
+        "write_buffer_depth",
+        "Packets waiting in the write buffer",
+    ));
+
+    // 一括挿入のレイテンシ
+    pub static ref DB_INSERT_LATENCY: Histogram = register(Histogram::with_opts(
+        HistogramOpts::new("db_insert_duration_seconds", "Latency of batched packet inserts")
+            .buckets(LATENCY_BUCKETS.to_vec()),
+    ));
+
+    // ポーリングクエリのレイテンシ
+    pub static ref POLL_LATENCY: Histogram = register(Histogram::with_opts(
+        HistogramOpts::new("poll_duration_seconds", "Latency of packet polling queries")
+            .buckets(LATENCY_BUCKETS.to_vec()),
+    ));
+
+    // コネクションプールの接続数 (状態別)
+    pub static ref POOL_CONNECTIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Connections managed by the database pool"),
+        &["pool", "state"],
+    ));
+
+    // コネクションプールの取得待ち累計時間 (ミリ秒)
+    pub static ref POOL_WAIT_TIME: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("db_pool_wait_milliseconds_total", "Accumulated time spent waiting for a pooled connection"),
+        &["pool"],
+    ));
+
+    // コネクションプールの取得回数 (結果別)
+    pub static ref POOL_GETS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("db_pool_gets_total", "Connection checkouts from the database pool"),
+        &["pool", "result"],
+    ));
+
+    // 中継したパケット数とバイト数 (IPプロトコル・方向別)
+    pub static ref TRAFFIC_PACKETS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("traffic_packets_total", "Packets tunneled by IP protocol and direction"),
+        &["protocol", "direction"],
+    ));
+    pub static ref TRAFFIC_BYTES: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("traffic_bytes_total", "Bytes tunneled by IP protocol and direction"),
+        &["protocol", "direction"],
+    ));
+
+    // 通信量上位のポート・ピアのバイト数 (ラベルの数を抑えるため上位のみ)
+    pub static ref PORT_TRAFFIC_BYTES: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("port_traffic_bytes_total", "Bytes tunneled by service port for the busiest ports"),
+        &["port", "direction"],
+    ));
+    pub static ref PEER_TRAFFIC_BYTES: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("peer_traffic_bytes_total", "Bytes tunneled by peer address for the busiest peers"),
+        &["peer", "direction"],
+    ));
+    pub static ref PEER_TRAFFIC_PACKETS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("peer_traffic_packets_total", "Packets tunneled by peer address for the busiest peers"),
+        &["peer", "direction"],
+    ));
+
+    // 連番から推定した送信元ノードごとの損失数と重複数 (遅れて届いたパケットは損失から差し引くため減ることがある)
+    pub static ref PEER_PACKETS_LOST: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("peer_packets_lost_total", "Packets estimated lost from sequence gaps by sending peer"),
+        &["peer"],
+    ));
+    pub static ref PEER_PACKETS_DUPLICATED: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("peer_packets_duplicated_total", "Packets delivered more than once by sending peer"),
+        &["peer"],
+    ));
+}
+
+// lazy_staticは初回参照時に登録されるため、起動時に全メトリクスを登録しておく
+pub fn init() {
+    lazy_static::initialize(&PACKETS_CAPTURED);
+    lazy_static::initialize(&PACKETS_WRITTEN);
+    lazy_static::initialize(&PACKETS_INJECTED);
+    lazy_static::initialize(&PACKETS_DROPPED);
+    lazy_static::initialize(&FIREWALL_DROPS);
+    lazy_static::initialize(&PACKETS_REMARKED);
+    lazy_static::initialize(&PACKETS_EXCLUDED);
+    lazy_static::initialize(&TASK_RESTARTS);
+    lazy_static::initialize(&DB_RETRIES);
+    lazy_static::initialize(&TASK_PANICS);
+    lazy_static::initialize(&NAT_MAPPINGS);
+    lazy_static::initialize(&NAT_FORWARD_SESSIONS);
+    lazy_static::initialize(&IDPS_ALERTS);
+    lazy_static::initialize(&BANS_TOTAL);
+    lazy_static::initialize(&BANS_ACTIVE);
+    lazy_static::initialize(&NOTIFICATIONS);
+    lazy_static::initialize(&SIEM_EVENTS);
+    lazy_static::initialize(&BUFFER_DEPTH);
+    lazy_static::initialize(&DB_INSERT_LATENCY);
+    lazy_static::initialize(&POLL_LATENCY);
+    lazy_static::initialize(&POOL_CONNECTIONS);
+    lazy_static::initialize(&POOL_WAIT_TIME);
+    lazy_static::initialize(&POOL_GETS);
+    lazy_static::initialize(&TRAFFIC_PACKETS);
+    lazy_static::initialize(&TRAFFIC_BYTES);
+    lazy_static::initialize(&PORT_TRAFFIC_BYTES);
+    lazy_static::initialize(&PEER_TRAFFIC_BYTES);
+    lazy_static::initialize(&PEER_TRAFFIC_PACKETS);
+    lazy_static::initialize(&PEER_PACKETS_LOST);
+    lazy_static::initialize(&PEER_PACKETS_DUPLICATED);
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("メトリクスの定義が不正です");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("メトリクスの登録に失敗しました");
+    metric
+}
+
+// スクレイプ時点の値で更新するメトリクス (接続プールはデータベースを使用する場合のみ)
+fn refresh(database: Option<&Database>) {
+    refresh_traffic();
+
+    for PeerDelivery { peer, totals } in sequence::peer_totals() {
+        PEER_PACKETS_LOST.with_label_values(&[&peer.to_string()]).set(totals.lost as i64);
+        PEER_PACKETS_DUPLICATED.with_label_values(&[&peer.to_string()]).set(totals.duplicates as i64);
+    }
+
+    let Some(db) = database else {
+        return;
+    };
+
+    for stats in db.pool_stats() {
+        POOL_CONNECTIONS.with_label_values(&[stats.name, "in_use"]).set(stats.in_use as i64);
+        POOL_CONNECTIONS.with_label_values(&[stats.name, "idle"]).set(stats.idle as i64);
+        POOL_WAIT_TIME.with_label_values(&[stats.name]).set(stats.total_wait_time.as_millis() as i64);
+        POOL_GETS.with_label_values(&[stats.name, "direct"]).set(stats.get_direct as i64);
+        POOL_GETS.with_label_values(&[stats.name, "waited"]).set(stats.get_waited as i64);
+        POOL_GETS.with_label_values(&[stats.name, "timed_out"]).set(stats.get_timed_out as i64);
+    }
+}
+
+fn refresh_traffic() {
+    let set = |gauge: &IntGaugeVec, label: &str, totals: &DirectionalTotals, value: fn(&TrafficTotals) -> u64| {
+        gauge.with_label_values(&[label, "outbound"]).set(value(&totals.outbound) as i64);
+        gauge.with_label_values(&[label, "inbound"]).set(value(&totals.inbound) as i64);
+    };
+
+    for (protocol, totals) in traffic::protocol_totals() {
+        set(&TRAFFIC_PACKETS, &protocol, &totals, |totals| totals.packets);
+        set(&TRAFFIC_BYTES, &protocol, &totals, |totals| totals.bytes);
+    }
+
+    // 上位から外れたポート・ピアの系列は削除する
+    PORT_TRAFFIC_BYTES.reset();
+    for port in traffic::top_ports(TOP_PORTS) {
+        set(&PORT_TRAFFIC_BYTES, &port.port.to_string(), &port.totals, |totals| totals.bytes);
+    }
+    PEER_TRAFFIC_BYTES.reset();
+    PEER_TRAFFIC_PACKETS.reset();
+    for peer in traffic::top_peers(TOP_PEERS) {
+        let address = peer.address.to_string();
+        set(&PEER_TRAFFIC_BYTES, &address, &peer.totals, |totals| totals.bytes);
+        set(&PEER_TRAFFIC_PACKETS, &address, &peer.totals, |totals| totals.packets);
+    }
+}
+
+// Prometheusのテキスト形式で出力する
+pub fn gather(database: Option<&Database>) -> Result<String, prometheus::Error> {
+    refresh(database);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+// 統計情報に含める通信量上位のピア数とポート数
+const TOP_PEERS: usize = 20;
+const TOP_PORTS: usize = 20;
+
+// ヒストグラムの累計値 (差分から区間の平均を求められる)
+#[derive(Debug, Serialize)]
+pub struct LatencyTotals {
+    pub count: u64,
+    pub sum_secs: f64,
+}
+
+impl LatencyTotals {
+    fn from_histogram(histogram: &Histogram) -> Self {
+        Self {
+            count: histogram.get_sample_count(),
+            sum_secs: histogram.get_sample_sum(),
+        }
+    }
+}
+
+// 管理APIで返す現在の統計値
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub packets_captured: BTreeMap<String, u64>,
+    pub packets_written: u64,
+    pub packets_injected: u64,
+    pub packets_dropped: BTreeMap<String, u64>,
+    pub firewall_drops: u64,
+    pub idps_alerts: u64,
+    pub buffer_depth: i64,
+    pub db_insert_latency: LatencyTotals,
+    pub poll_latency: LatencyTotals,
+    pub protocols: BTreeMap<String, DirectionalTotals>,
+    pub peers: Vec<PeerTraffic>,
+    pub ports: Vec<PortTraffic>,
+    pub delivery: Vec<PeerDelivery>,
+    pub pools: Vec<PoolStats>,
+    pub recent_alerts: Vec<Alert>,
+}
+
+pub fn snapshot(database: Option<&Database>) -> StatsSnapshot {
+    StatsSnapshot {
+        packets_captured: counter_vec_values(&PACKETS_CAPTURED),
+        packets_written: PACKETS_WRITTEN.get(),
+        packets_injected: PACKETS_INJECTED.get(),
+        packets_dropped: counter_vec_values(&PACKETS_DROPPED),
+        firewall_drops: FIREWALL_DROPS.get(),
+        idps_alerts: IDPS_ALERTS.get(),
+        buffer_depth: BUFFER_DEPTH.get(),
+        db_insert_latency: LatencyTotals::from_histogram(&DB_INSERT_LATENCY),
+        poll_latency: LatencyTotals::from_histogram(&POLL_LATENCY),
+        protocols: traffic::protocol_totals(),
+        peers: traffic::top_peers(TOP_PEERS),
+        ports: traffic::top_ports(TOP_PORTS),
+        delivery: sequence::peer_totals(),
+        pools: database.map(|db| db.pool_stats()).unwrap_or_default(),
+        recent_alerts: events::recent_alerts(),
+    }
+}
+
+// ラベルが1つのカウンタを ラベル値 -> 値 の形に変換する
+fn counter_vec_values(counter: &IntCounterVec) -> BTreeMap<String, u64> {
+    counter
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter_map(|metric| {
+            let label = metric.get_label().first()?.value().to_string();
+            Some((label, metric.get_counter().get_value() as u64))
+        })
+        .collect()
+}
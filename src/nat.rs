@@ -0,0 +1,611 @@
+use crate::config::{ForwardProtocol, ForwardRule, NatConfig};
+use crate::database::types::{InetAddr, MacAddr};
+use crate::db_read::PacketInfo;
+use crate::db_write::{PacketData, Protocol};
+use crate::metrics;
+use crate::packet_header::{internet_checksum, transport_checksum, EthernetHeader, IcmpHeader, Ipv4Header};
+use ipnetwork::IpNetwork;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, trace};
+
+// 拠点内のクライアントからトンネルへ出ていくIPv4パケットの送信元を自ノードのアドレスとポートに書き換え (NAT44)、
+// 戻りのパケットは変換表 (conntrack) から元のクライアントへ戻す。対向ノードにクライアントのネットワークへの経路は不要になる
+// また、静的ポート転送 (DNAT) の規則に一致した対向ノードからのパケットは宛先を拠点内のサーバーに書き換えて注入する
+
+const ICMP_PROTOCOL: u8 = 1;
+const TCP_PROTOCOL: u8 = 6;
+const UDP_PROTOCOL: u8 = 17;
+
+// 期限切れの変換を削除する間隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+// 変換表。設定されていない場合は変換しない
+static TABLE: Mutex<Option<Conntrack>> = Mutex::new(None);
+// ポート転送の規則と接続の表。規則がない場合は変換しない
+static FORWARDS: Mutex<Option<PortForwards>> = Mutex::new(None);
+
+// 送信元NATの結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Translation {
+    // 変換の対象外
+    Skipped,
+    Translated,
+    // 変換後のポートが不足しているため送信できない
+    Exhausted,
+}
+
+// 変換に使用するIPv4パケットの位置とアドレス・ポート (ICMPエコーの場合は識別子)
+struct Flow {
+    protocol: u8,
+    header_len: usize,
+    packet_len: usize,
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+    src_port: u16,
+    dst_port: u16,
+}
+
+impl Flow {
+    // ICMPエコーの識別子はポートと同じ位置に書き換える
+    fn port_offsets(&self) -> (usize, usize) {
+        match self.protocol {
+            ICMP_PROTOCOL => (4, 4),
+            _ => (0, 2),
+        }
+    }
+
+    // 送信元のアドレスとポートを書き換え、チェックサムを計算し直す
+    fn rewrite_source(&self, ip: &mut [u8], address: Ipv4Addr, port: u16) {
+        ip[12..16].copy_from_slice(&address.octets());
+        let port_offset = self.header_len + self.port_offsets().0;
+        ip[port_offset..port_offset + 2].copy_from_slice(&port.to_be_bytes());
+        update_checksums(ip, self);
+    }
+
+    // 宛先のアドレスとポートを書き換え、チェックサムを計算し直す
+    fn rewrite_destination(&self, ip: &mut [u8], address: Ipv4Addr, port: u16) {
+        ip[16..20].copy_from_slice(&address.octets());
+        let port_offset = self.header_len + self.port_offsets().1;
+        ip[port_offset..port_offset + 2].copy_from_slice(&port.to_be_bytes());
+        update_checksums(ip, self);
+    }
+}
+
+// フレームから変換の対象となるフローを取り出す。ICMPは指定したタイプのエコーのみ対象とする
+fn parse_flow(frame: &[u8], icmp_type: u8) -> Option<Flow> {
+    if frame.get(12..14)? != [0x08, 0x00] {
+        return None;
+    }
+    let ip = &frame[EthernetHeader::LEN..];
+    if ip.first()? >> 4 != 4 {
+        return None;
+    }
+    let header_len = (ip[0] & 0x0F) as usize * 4;
+    if header_len < Ipv4Header::LEN || ip.len() < header_len {
+        return None;
+    }
+    // 断片化されたパケットは2つ目以降にポートがないため変換しない
+    if u16::from_be_bytes([ip[6], ip[7]]) & 0x3FFF != 0 {
+        return None;
+    }
+    let packet_len = (u16::from_be_bytes([ip[2], ip[3]]) as usize).min(ip.len());
+    let l4 = ip.get(header_len..packet_len)?;
+    let protocol = ip[9];
+    let (src_port, dst_port) = match protocol {
+        TCP_PROTOCOL if l4.len() >= 20 => (u16::from_be_bytes([l4[0], l4[1]]), u16::from_be_bytes([l4[2], l4[3]])),
+        UDP_PROTOCOL if l4.len() >= 8 => (u16::from_be_bytes([l4[0], l4[1]]), u16::from_be_bytes([l4[2], l4[3]])),
+        ICMP_PROTOCOL if l4.len() >= IcmpHeader::LEN && l4[0] == icmp_type => {
+            let identifier = u16::from_be_bytes([l4[4], l4[5]]);
+            (identifier, identifier)
+        }
+        _ => return None,
+    };
+    Some(Flow {
+        protocol,
+        header_len,
+        packet_len,
+        src_ip: Ipv4Addr::from(<[u8; 4]>::try_from(&ip[12..16]).ok()?),
+        dst_ip: Ipv4Addr::from(<[u8; 4]>::try_from(&ip[16..20]).ok()?),
+        src_port,
+        dst_port,
+    })
+}
+
+// 書き換えたIPv4パケットのヘッダとTCP/UDP/ICMPのチェックサムを計算し直す
+fn update_checksums(ip: &mut [u8], flow: &Flow) {
+    let Flow { protocol, header_len, packet_len, .. } = *flow;
+    ip[10..12].fill(0);
+    let checksum = internet_checksum(&[&ip[..header_len]]);
+    ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    let src_ip = IpAddr::from(<[u8; 4]>::try_from(&ip[12..16]).unwrap_or_default());
+    let dst_ip = IpAddr::from(<[u8; 4]>::try_from(&ip[16..20]).unwrap_or_default());
+    let offset = match protocol {
+        TCP_PROTOCOL => 16,
+        // UDPのチェックサムが0 (未計算) の場合はそのままにする
+        UDP_PROTOCOL if ip[header_len + 6..header_len + 8] != [0, 0] => 6,
+        ICMP_PROTOCOL => 2,
+        _ => return,
+    };
+    let checksum_range = header_len + offset..header_len + offset + 2;
+    ip[checksum_range.clone()].fill(0);
+    let l4 = &ip[header_len..packet_len];
+    let checksum = match protocol {
+        ICMP_PROTOCOL => internet_checksum(&[l4]),
+        _ => match transport_checksum(src_ip, dst_ip, protocol, l4, &[]) {
+            0 if protocol == UDP_PROTOCOL => 0xFFFF,
+            checksum => checksum,
+        },
+    };
+    ip[checksum_range].copy_from_slice(&checksum.to_be_bytes());
+}
+
+// 変換表の1件
+struct Mapping {
+    internal_ip: Ipv4Addr,
+    internal_port: u16,
+    // クライアントのMACアドレスと、クライアントが宛先にしたゲートウェイのMACアドレス (戻りのフレームに使用する)
+    client_mac: [u8; 6],
+    gateway_mac: [u8; 6],
+    last_seen: Instant,
+}
+
+struct Conntrack {
+    address: Ipv4Addr,
+    sources: Vec<IpNetwork>,
+    ports: (u16, u16),
+    tcp_timeout: Duration,
+    udp_timeout: Duration,
+    // (プロトコル, クライアントのアドレス, ポート) -> 変換後のポート
+    outbound: HashMap<(u8, Ipv4Addr, u16), u16>,
+    // (プロトコル, 変換後のポート) -> 変換
+    inbound: HashMap<(u8, u16), Mapping>,
+    // 次に割り当てを試すポート
+    next_port: u16,
+    last_sweep: Instant,
+}
+
+impl Conntrack {
+    fn new(config: &NatConfig, address: Ipv4Addr, now: Instant) -> Self {
+        Self {
+            address,
+            sources: config.sources.clone(),
+            ports: (config.ports[0], config.ports[1]),
+            tcp_timeout: config.tcp_timeout,
+            udp_timeout: config.udp_timeout,
+            outbound: HashMap::new(),
+            inbound: HashMap::new(),
+            next_port: config.ports[0],
+            last_sweep: now,
+        }
+    }
+
+    fn is_expired(&self, protocol: u8, mapping: &Mapping, now: Instant) -> bool {
+        let timeout = if protocol == TCP_PROTOCOL { self.tcp_timeout } else { self.udp_timeout };
+        now.saturating_duration_since(mapping.last_seen) >= timeout
+    }
+
+    fn is_source(&self, ip: Ipv4Addr) -> bool {
+        self.sources.iter().any(|network| network.contains(IpAddr::V4(ip)))
+    }
+
+    // 一定間隔で期限切れの変換を削除する
+    fn sweep(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        self.last_sweep = now;
+        let expired = self
+            .inbound
+            .iter()
+            .filter(|((protocol, _), mapping)| self.is_expired(*protocol, mapping, now))
+            .map(|(&key, mapping)| (key, mapping.internal_ip, mapping.internal_port))
+            .collect::<Vec<_>>();
+        for ((protocol, port), internal_ip, internal_port) in expired {
+            self.inbound.remove(&(protocol, port));
+            self.outbound.remove(&(protocol, internal_ip, internal_port));
+        }
+        metrics::NAT_MAPPINGS.set(self.inbound.len() as i64);
+    }
+
+    // 未使用 (または期限切れ) の変換後のポートを割り当てる
+    fn allocate(&mut self, protocol: u8, now: Instant) -> Option<u16> {
+        let (start, end) = self.ports;
+        for _ in start..=end {
+            let port = self.next_port;
+            self.next_port = if port >= end { start } else { port + 1 };
+            match self.inbound.get(&(protocol, port)) {
+                None => return Some(port),
+                Some(mapping) if self.is_expired(protocol, mapping, now) => {
+                    let key = (protocol, mapping.internal_ip, mapping.internal_port);
+                    self.outbound.remove(&key);
+                    self.inbound.remove(&(protocol, port));
+                    return Some(port);
+                }
+                Some(_) => {}
+            }
+        }
+        None
+    }
+
+    // クライアントから出ていくフレームの送信元を書き換え、変換後のポートを返す
+    fn translate_outbound(&mut self, frame: &mut [u8], now: Instant) -> Result<Option<u16>, ()> {
+        let Some(flow) = parse_flow(frame, IcmpHeader::ECHO_REQUEST) else {
+            return Ok(None);
+        };
+        // 拠点内どうしの通信と自ノードが送信したパケットは書き換えない
+        if !self.is_source(flow.src_ip) || self.is_source(flow.dst_ip) || flow.src_ip == self.address {
+            return Ok(None);
+        }
+        self.sweep(now);
+
+        let key = (flow.protocol, flow.src_ip, flow.src_port);
+        let existing = self.outbound.get(&key).copied().filter(|port| {
+            self.inbound.get(&(flow.protocol, *port)).is_some_and(|mapping| !self.is_expired(flow.protocol, mapping, now))
+        });
+        let port = match existing {
+            Some(port) => port,
+            None => {
+                let port = self.allocate(flow.protocol, now).ok_or(())?;
+                self.outbound.insert(key, port);
+                port
+            }
+        };
+        let mut client_mac = [0; 6];
+        let mut gateway_mac = [0; 6];
+        gateway_mac.copy_from_slice(&frame[0..6]);
+        client_mac.copy_from_slice(&frame[6..12]);
+        self.inbound.insert(
+            (flow.protocol, port),
+            Mapping { internal_ip: flow.src_ip, internal_port: flow.src_port, client_mac, gateway_mac, last_seen: now },
+        );
+        metrics::NAT_MAPPINGS.set(self.inbound.len() as i64);
+
+        flow.rewrite_source(&mut frame[EthernetHeader::LEN..], self.address, port);
+        Ok(Some(port))
+    }
+
+    // 変換したフローへの応答の宛先を元のクライアントに戻す。戻した場合はクライアントのアドレスとポートを返す
+    fn translate_inbound(&mut self, frame: &mut [u8], now: Instant) -> Option<(Ipv4Addr, u16, [u8; 6])> {
+        let flow = parse_flow(frame, IcmpHeader::ECHO_REPLY)?;
+        if flow.dst_ip != self.address {
+            return None;
+        }
+        let protocol = flow.protocol;
+        let mapping = self.inbound.get(&(protocol, flow.dst_port))?;
+        if self.is_expired(protocol, mapping, now) {
+            return None;
+        }
+        let mapping = self.inbound.get_mut(&(protocol, flow.dst_port))?;
+        mapping.last_seen = now;
+
+        frame[0..6].copy_from_slice(&mapping.client_mac);
+        frame[6..12].copy_from_slice(&mapping.gateway_mac);
+        flow.rewrite_destination(&mut frame[EthernetHeader::LEN..], mapping.internal_ip, mapping.internal_port);
+        Some((mapping.internal_ip, mapping.internal_port, mapping.client_mac))
+    }
+}
+
+// ポート転送の規則 (IPv4)
+struct Forward {
+    protocol: u8,
+    // Noneの場合は宛先のアドレスを問わない
+    listen_ip: Option<Ipv4Addr>,
+    listen_port: u16,
+    target_ip: Ipv4Addr,
+    target_port: u16,
+}
+
+impl Forward {
+    fn new(rule: &ForwardRule) -> Option<Self> {
+        let (IpAddr::V4(listen_ip), IpAddr::V4(target_ip)) = (rule.listen.ip(), rule.target.ip()) else {
+            return None;
+        };
+        Some(Self {
+            protocol: match rule.protocol {
+                ForwardProtocol::Tcp => TCP_PROTOCOL,
+                ForwardProtocol::Udp => UDP_PROTOCOL,
+            },
+            listen_ip: (!listen_ip.is_unspecified()).then_some(listen_ip),
+            listen_port: rule.listen.port(),
+            target_ip,
+            target_port: rule.target.port(),
+        })
+    }
+
+    fn matches(&self, flow: &Flow) -> bool {
+        self.protocol == flow.protocol
+            && self.listen_port == flow.dst_port
+            && self.listen_ip.is_none_or(|ip| ip == flow.dst_ip)
+    }
+}
+
+// 転送した接続。応答の送信元を対向ノードが宛先にしたアドレスとポートに戻すために使用する
+struct Session {
+    listen_ip: Ipv4Addr,
+    listen_port: u16,
+    last_seen: Instant,
+}
+
+struct PortForwards {
+    forwards: Vec<Forward>,
+    tcp_timeout: Duration,
+    udp_timeout: Duration,
+    // (プロトコル, 転送先のアドレス, ポート, 接続元のアドレス, ポート) -> 接続
+    sessions: HashMap<(u8, Ipv4Addr, u16, Ipv4Addr, u16), Session>,
+    // 転送先から送信されたフレームで学習したMACアドレス (転送するフレームの宛先に使用する)
+    neighbors: HashMap<Ipv4Addr, [u8; 6]>,
+    last_sweep: Instant,
+}
+
+impl PortForwards {
+    fn new(config: &NatConfig, now: Instant) -> Self {
+        Self {
+            forwards: config.forward.iter().filter_map(Forward::new).collect(),
+            tcp_timeout: config.tcp_timeout,
+            udp_timeout: config.udp_timeout,
+            sessions: HashMap::new(),
+            neighbors: HashMap::new(),
+            last_sweep: now,
+        }
+    }
+
+    fn is_expired(&self, protocol: u8, session: &Session, now: Instant) -> bool {
+        let timeout = if protocol == TCP_PROTOCOL { self.tcp_timeout } else { self.udp_timeout };
+        now.saturating_duration_since(session.last_seen) >= timeout
+    }
+
+    // 一定間隔で期限切れの接続を削除する
+    fn sweep(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        self.last_sweep = now;
+        let (tcp_timeout, udp_timeout) = (self.tcp_timeout, self.udp_timeout);
+        self.sessions.retain(|(protocol, ..), session| {
+            let timeout = if *protocol == TCP_PROTOCOL { tcp_timeout } else { udp_timeout };
+            now.saturating_duration_since(session.last_seen) < timeout
+        });
+        metrics::NAT_FORWARD_SESSIONS.set(self.sessions.len() as i64);
+    }
+
+    // 規則に一致した対向ノードからのフレームの宛先を転送先に書き換え、転送先のアドレスとポートを返す
+    fn translate_inbound(&mut self, frame: &mut [u8], now: Instant) -> Option<(Ipv4Addr, u16, Option<[u8; 6]>)> {
+        let flow = parse_flow(frame, IcmpHeader::ECHO_REQUEST)?;
+        let forward = self.forwards.iter().find(|forward| forward.matches(&flow))?;
+        let (target_ip, target_port) = (forward.target_ip, forward.target_port);
+        self.sweep(now);
+
+        self.sessions.insert(
+            (flow.protocol, target_ip, target_port, flow.src_ip, flow.src_port),
+            Session { listen_ip: flow.dst_ip, listen_port: flow.dst_port, last_seen: now },
+        );
+        metrics::NAT_FORWARD_SESSIONS.set(self.sessions.len() as i64);
+
+        // 転送先のMACアドレスを学習していない場合は宛先のMACアドレスを変えない (自ノードでルーティングする)
+        let target_mac = self.neighbors.get(&target_ip).copied();
+        if let Some(mac) = target_mac {
+            frame[0..6].copy_from_slice(&mac);
+        }
+        flow.rewrite_destination(&mut frame[EthernetHeader::LEN..], target_ip, target_port);
+        Some((target_ip, target_port, target_mac))
+    }
+
+    // 転送先からの応答の送信元を対向ノードが宛先にしたアドレスとポートに戻す
+    fn translate_outbound(&mut self, frame: &mut [u8], now: Instant) -> Option<(Ipv4Addr, u16)> {
+        let flow = parse_flow(frame, IcmpHeader::ECHO_REPLY)?;
+        if !self.forwards.iter().any(|forward| forward.target_ip == flow.src_ip) {
+            return None;
+        }
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&frame[6..12]);
+        self.neighbors.insert(flow.src_ip, mac);
+
+        let key = (flow.protocol, flow.src_ip, flow.src_port, flow.dst_ip, flow.dst_port);
+        let session = self.sessions.get(&key)?;
+        if self.is_expired(flow.protocol, session, now) {
+            return None;
+        }
+        let session = self.sessions.get_mut(&key)?;
+        session.last_seen = now;
+        let (listen_ip, listen_port) = (session.listen_ip, session.listen_port);
+        flow.rewrite_source(&mut frame[EthernetHeader::LEN..], listen_ip, listen_port);
+        Some((listen_ip, listen_port))
+    }
+}
+
+// 設定を反映する。addressは変換後の送信元 (対向ノードが自ノード宛として送るアドレス)
+pub fn configure(config: &NatConfig, address: Ipv4Addr) {
+    let table = config.enabled.then(|| Conntrack::new(config, address, Instant::now()));
+    if table.is_some() {
+        let sources = config.sources.iter().map(|network| network.to_string()).collect::<Vec<_>>();
+        info!("送信元NATを有効にしました: {} -> {}", sources.join(", "), address);
+    }
+    *TABLE.lock().unwrap_or_else(|e| e.into_inner()) = table;
+}
+
+// ポート転送の規則を反映する
+pub fn configure_forwards(config: &NatConfig) {
+    let forwards = (!config.forward.is_empty()).then(|| PortForwards::new(config, Instant::now()));
+    for rule in &config.forward {
+        info!("ポート転送を有効にしました: {:?} {} -> {}", rule.protocol, rule.listen, rule.target);
+    }
+    *FORWARDS.lock().unwrap_or_else(|e| e.into_inner()) = forwards;
+}
+
+// 書き込むパケットの送信元を自ノードのアドレスとポートに書き換える
+pub fn translate_outbound(packet: &mut PacketData) -> Translation {
+    // ポート転送した接続への応答は、送信元を対向ノードが宛先にしたアドレスとポートに戻す
+    if let Some(forwards) = FORWARDS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        let source = packet.src_ip.ip();
+        if let Some((listen_ip, listen_port)) = packet.edit_frame(|frame| forwards.translate_outbound(frame, Instant::now())) {
+            trace!("ポート転送の応答を変換しました: {}:{} -> {}:{}", source, packet.src_port, listen_ip, listen_port);
+            packet.src_ip = InetAddr::from(IpAddr::V4(listen_ip));
+            packet.src_port = listen_port as i32;
+            return Translation::Translated;
+        }
+    }
+
+    let mut table = TABLE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(table) = table.as_mut() else {
+        return Translation::Skipped;
+    };
+    let (source, address) = (packet.src_ip.ip(), table.address);
+    match packet.edit_frame(|frame| table.translate_outbound(frame, Instant::now())) {
+        Ok(Some(port)) => {
+            trace!("送信元を変換しました: {}:{} -> {}:{}", source, packet.src_port, address, port);
+            packet.src_ip = InetAddr::from(IpAddr::V4(address));
+            if packet.ip_protocol != Protocol::ICMP {
+                packet.src_port = port as i32;
+            }
+            Translation::Translated
+        }
+        Ok(None) => Translation::Skipped,
+        Err(()) => Translation::Exhausted,
+    }
+}
+
+// 受信したパケットがポート転送の規則に一致すれば宛先を転送先に、変換したフローへの応答であれば元のクライアントに戻す
+pub fn translate_inbound(packet: &mut PacketInfo) -> bool {
+    if let Some(forwards) = FORWARDS.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        if let Some((target_ip, target_port, target_mac)) = forwards.translate_inbound(&mut packet.raw_packet, Instant::now()) {
+            trace!("ポート転送しました: {}:{} -> {}:{}",
+                packet.dst_ip, packet.dst_port.unwrap_or(0), target_ip, target_port
+            );
+            packet.dst_ip = IpAddr::V4(target_ip);
+            packet.dst_port = Some(target_port as i32);
+            if let Some(mac) = target_mac {
+                packet.dst_mac = MacAddr(mac);
+            }
+            return true;
+        }
+    }
+
+    let mut table = TABLE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(table) = table.as_mut() else {
+        return false;
+    };
+    let Some((internal_ip, internal_port, client_mac)) = table.translate_inbound(&mut packet.raw_packet, Instant::now())
+    else {
+        return false;
+    };
+    trace!("宛先を変換しました: {} -> {}:{}", packet.dst_ip, internal_ip, internal_port);
+    packet.dst_ip = IpAddr::V4(internal_ip);
+    packet.dst_mac = MacAddr(client_mac);
+    if packet.ip_protocol != ICMP_PROTOCOL as i32 {
+        packet.dst_port = Some(internal_port as i32);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_header::{parse_ip_header, TcpHeader};
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 10, 5);
+    const NODE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn table(ports: [u16; 2]) -> Conntrack {
+        let config = NatConfig { enabled: true, sources: vec!["192.168.10.0/24".parse().unwrap()], ports, ..Default::default() };
+        Conntrack::new(&config, NODE, Instant::now())
+    }
+
+    fn tcp_frame(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), src_mac: [u8; 6], dst_mac: [u8; 6]) -> Vec<u8> {
+        let payload = b"hello";
+        let tcp = TcpHeader::new(src.1, dst.1, TcpHeader::ACK).to_bytes_with_checksum(src.0.into(), dst.0.into(), payload);
+        let mut frame = EthernetHeader { dst_mac, src_mac, ether_type: 0x0800 }.to_bytes().to_vec();
+        frame.extend_from_slice(&Ipv4Header::new(src.0, dst.0, 6, (tcp.len() + payload.len()) as u16).to_bytes());
+        frame.extend_from_slice(&tcp);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn assert_checksums(frame: &[u8]) {
+        let ip = &frame[EthernetHeader::LEN..];
+        let header = parse_ip_header(ip).unwrap();
+        assert_eq!(internet_checksum(&[&ip[..Ipv4Header::LEN]]), 0);
+        assert_eq!(transport_checksum(header.src_ip, header.dst_ip, 6, &ip[Ipv4Header::LEN..], &[]), 0);
+    }
+
+    #[test]
+    fn translates_flow_and_reply() {
+        let now = Instant::now();
+        let mut table = table([40000, 40010]);
+        let (client_mac, gateway_mac) = ([2, 0, 0, 0, 0, 5], [2, 0, 0, 0, 0, 1]);
+        let mut request = tcp_frame((CLIENT, 51000), (REMOTE, 443), client_mac, gateway_mac);
+        let port = table.translate_outbound(&mut request, now).unwrap().unwrap();
+        assert_eq!(port, 40000);
+        assert_eq!(request[EthernetHeader::LEN + 12..EthernetHeader::LEN + 16], NODE.octets());
+        assert_checksums(&request);
+        // 同じフローには同じポートを使う
+        let mut again = tcp_frame((CLIENT, 51000), (REMOTE, 443), client_mac, gateway_mac);
+        assert_eq!(table.translate_outbound(&mut again, now), Ok(Some(port)));
+
+        let mut reply = tcp_frame((REMOTE, 443), (NODE, port), [2, 0, 0, 0, 0, 2], [2, 0, 0, 0, 0, 1]);
+        assert_eq!(table.translate_inbound(&mut reply, now), Some((CLIENT, 51000, client_mac)));
+        assert_eq!(reply[0..6], client_mac);
+        assert_eq!(reply[6..12], gateway_mac);
+        assert_checksums(&reply);
+
+        // 拠点内どうしの通信と、変換していないポートへのパケットはそのまま
+        let mut local = tcp_frame((CLIENT, 51000), (Ipv4Addr::new(192, 168, 10, 6), 22), client_mac, gateway_mac);
+        assert_eq!(table.translate_outbound(&mut local, now), Ok(None));
+        let mut unknown = tcp_frame((REMOTE, 443), (NODE, 40005), [2; 6], [4; 6]);
+        assert_eq!(table.translate_inbound(&mut unknown, now), None);
+    }
+
+    #[test]
+    fn reuses_expired_ports_and_reports_exhaustion() {
+        let now = Instant::now();
+        let mut table = table([40000, 40000]);
+        let mut first = tcp_frame((CLIENT, 51000), (REMOTE, 443), [2; 6], [4; 6]);
+        assert_eq!(table.translate_outbound(&mut first, now), Ok(Some(40000)));
+        let mut second = tcp_frame((CLIENT, 51001), (REMOTE, 443), [2; 6], [4; 6]);
+        assert_eq!(table.translate_outbound(&mut second, now), Err(()));
+
+        let later = now + table.tcp_timeout;
+        let mut second = tcp_frame((CLIENT, 51001), (REMOTE, 443), [2; 6], [4; 6]);
+        assert_eq!(table.translate_outbound(&mut second, later), Ok(Some(40000)));
+        assert!(!table.outbound.contains_key(&(TCP_PROTOCOL, CLIENT, 51000)));
+    }
+
+    #[test]
+    fn forwards_port_and_restores_reply_source() {
+        let now = Instant::now();
+        let server = Ipv4Addr::new(192, 168, 10, 20);
+        let rule = ForwardRule {
+            protocol: ForwardProtocol::Tcp,
+            listen: "0.0.0.0:8080".parse().unwrap(),
+            target: "192.168.10.20:80".parse().unwrap(),
+        };
+        let mut forwards = PortForwards::new(&NatConfig { forward: vec![rule], ..Default::default() }, now);
+        let (node_mac, server_mac) = ([2, 0, 0, 0, 0, 1], [2, 0, 0, 0, 0, 20]);
+
+        // 転送先のMACアドレスを学習するまでは宛先のMACアドレスを変えない
+        let mut request = tcp_frame((REMOTE, 51000), (NODE, 8080), [2, 0, 0, 0, 0, 2], node_mac);
+        assert_eq!(forwards.translate_inbound(&mut request, now), Some((server, 80, None)));
+        assert_eq!(request[0..6], node_mac);
+        assert_eq!(request[EthernetHeader::LEN + 16..EthernetHeader::LEN + 20], server.octets());
+        assert_checksums(&request);
+
+        let mut reply = tcp_frame((server, 80), (REMOTE, 51000), server_mac, node_mac);
+        assert_eq!(forwards.translate_outbound(&mut reply, now), Some((NODE, 8080)));
+        assert_eq!(reply[EthernetHeader::LEN + 12..EthernetHeader::LEN + 16], NODE.octets());
+        assert_checksums(&reply);
+
+        let mut next = tcp_frame((REMOTE, 51000), (NODE, 8080), [2, 0, 0, 0, 0, 2], node_mac);
+        assert_eq!(forwards.translate_inbound(&mut next, now), Some((server, 80, Some(server_mac))));
+        assert_eq!(next[0..6], server_mac);
+
+        // 規則に一致しないポートと、転送していない接続からのパケットはそのまま
+        let mut other = tcp_frame((REMOTE, 51000), (NODE, 8081), [2; 6], node_mac);
+        assert_eq!(forwards.translate_inbound(&mut other, now), None);
+        let mut unrelated = tcp_frame((server, 80), (REMOTE, 52000), server_mac, node_mac);
+        assert_eq!(forwards.translate_outbound(&mut unrelated, now), None);
+    }
+}
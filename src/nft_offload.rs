@@ -0,0 +1,157 @@
+use crate::bans::{self, Ban};
+use crate::config::NftOffloadConfig;
+use crate::firewall::{Chain, Filter, Firewall, FIREWALL};
+use crate::policy_routing::nft;
+use chrono::Local;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+// [bans] で遮断しているIPアドレスをnftablesのセットへ反映し、
+// 遮断する通信をキャプチャの経路に到達する前にカーネルで破棄する (Linuxのみ)。
+// OUTPUTチェインのブラックリストのルールは「トンネルに流さない」という意味 (DBのホストなど) のため、遮断の対象にしない
+
+// 遮断するアドレス (IPv4, IPv6)
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Blocked {
+    v4: BTreeSet<IpAddr>,
+    v6: BTreeSet<IpAddr>,
+}
+
+// 送信側チェインに遮断ルールを追加した遮断のうち、ルールが現在有効なアドレスを選択する
+fn blocked_addresses(firewall: &Firewall, bans: &[Ban]) -> Blocked {
+    let mut blocked = Blocked::default();
+    let chain = firewall.chain(Chain::Output);
+    let now = Local::now();
+    for ban in bans.iter().filter(|ban| ban.chains.contains(&Chain::Output)) {
+        let ip = ban.ip;
+        let active = chain.rules().iter().any(|rule| {
+            rule.filter == Filter::IpAddress(ip) && rule.schedule.as_ref().is_none_or(|schedule| schedule.is_active(now))
+        });
+        if !active {
+            continue;
+        }
+        match ip {
+            IpAddr::V4(_) => blocked.v4.insert(ip),
+            IpAddr::V6(_) => blocked.v6.insert(ip),
+        };
+    }
+    blocked
+}
+
+fn elements(addresses: &BTreeSet<IpAddr>) -> String {
+    addresses.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
+
+// 遮断するアドレスのセットと、送受信・転送のパケットを破棄するチェイン (既存のテーブルは1つのトランザクションで置き換える)
+fn nft_script(table: &str, blocked: &Blocked) -> String {
+    let mut script = format!("table inet {0} {{}}\ndelete table inet {0}\ntable inet {0} {{\n", table);
+    for (name, kind, addresses) in [("blocked_v4", "ipv4_addr", &blocked.v4), ("blocked_v6", "ipv6_addr", &blocked.v6)] {
+        script.push_str(&format!("    set {} {{\n        type {}\n", name, kind));
+        if !addresses.is_empty() {
+            script.push_str(&format!("        elements = {{ {} }}\n", elements(addresses)));
+        }
+        script.push_str("    }\n");
+    }
+    for (chain, hook, direction) in [("prerouting", "prerouting", "saddr"), ("output", "output", "daddr"), ("forward", "forward", "daddr")] {
+        script.push_str(&format!(
+            "    chain {} {{\n        type filter hook {} priority raw; policy accept;\n        ip {2} @blocked_v4 drop\n        ip6 {2} @blocked_v6 drop\n    }}\n",
+            chain, hook, direction
+        ));
+    }
+    script.push_str("}\n");
+    script
+}
+
+// 遮断を定期的に確認し、変更があった場合にnftablesのセットを更新する。
+// 終了時にテーブルを削除する
+pub async fn start_sync(config: NftOffloadConfig, mut shutdown: broadcast::Receiver<()>) {
+    let mut applied: Option<Blocked> = None;
+    let mut interval = tokio::time::interval(config.interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+
+        let bans = bans::bans();
+        let blocked = blocked_addresses(&FIREWALL.read().unwrap_or_else(|e| e.into_inner()), &bans);
+        if applied.as_ref() == Some(&blocked) {
+            continue;
+        }
+        // 失敗した場合は次回に再試行する
+        match nft(&["-f", "-"], Some(&nft_script(&config.table, &blocked))) {
+            Ok(()) => {
+                info!(
+                    "nftablesのセットを更新しました: {} (IPv4 {}件, IPv6 {}件)",
+                    config.table,
+                    blocked.v4.len(),
+                    blocked.v6.len()
+                );
+                applied = Some(blocked);
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    if applied.is_some() {
+        match nft(&["delete", "table", "inet", &config.table], None) {
+            Ok(()) => info!("nftablesのテーブルを削除しました: {}", config.table),
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bans::Detector;
+    use crate::firewall::{IpFirewall, Policy, Rule};
+    use crate::schedule::Schedule;
+    use chrono::{Duration, Utc};
+
+    fn ban(ip: &str, chains: Vec<Chain>) -> Ban {
+        let now = Utc::now();
+        Ban { ip: ip.parse().unwrap(), detector: Detector::Manual, since: now, until: now + Duration::hours(1), count: 1, chains }
+    }
+
+    fn rule(ip: &str, schedule: Option<Schedule>) -> Rule {
+        Rule { filter: Filter::IpAddress(ip.parse().unwrap()), priority: 250, schedule }
+    }
+
+    #[test]
+    fn offloads_only_banned_addresses() {
+        // 既定のルール (DBのホストをトンネルに流さない) は遮断ではない
+        assert_eq!(blocked_addresses(&FIREWALL.read().unwrap(), &[]), Blocked::default());
+
+        let until = Schedule { valid_until: Some(Utc::now() + Duration::hours(1)), ..Default::default() };
+        let expired = Schedule { valid_until: Some(Utc::now() - Duration::hours(1)), ..Default::default() };
+        let mut output = IpFirewall::new(Policy::Blacklist);
+        output.insert(rule("203.0.113.1", Some(until.clone())));
+        output.insert(rule("2001:db8::1", Some(until.clone())));
+        output.insert(rule("203.0.113.3", Some(expired)));
+        output.insert(rule("198.51.100.1", None));
+        let mut input = IpFirewall::new(Policy::Blacklist);
+        input.insert(rule("203.0.113.4", Some(until)));
+        let firewall = Firewall::new(input, output);
+        let bans = [
+            ban("203.0.113.1", vec![Chain::Input, Chain::Output]),
+            ban("2001:db8::1", vec![Chain::Output]),
+            // 期限を過ぎたルール・INPUTチェインのみの遮断・ルールが削除された遮断は反映しない
+            ban("203.0.113.3", vec![Chain::Output]),
+            ban("203.0.113.4", vec![Chain::Input]),
+            ban("203.0.113.5", vec![Chain::Output]),
+        ];
+
+        let blocked = blocked_addresses(&firewall, &bans);
+        assert_eq!(elements(&blocked.v4), "203.0.113.1");
+        assert_eq!(elements(&blocked.v6), "2001:db8::1");
+
+        let script = nft_script("rdb_tunnel_block", &blocked);
+        assert!(script.starts_with("table inet rdb_tunnel_block {}\ndelete table inet rdb_tunnel_block\n"));
+        assert!(script.contains("elements = { 203.0.113.1 }\n"));
+        assert!(script.contains("ip saddr @blocked_v4 drop\n"));
+    }
+}
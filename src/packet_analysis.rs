@@ -1,14 +1,23 @@
+use crate::buffer_pool::FRAME_POOL;
 use crate::db_write::rdb_tunnel_packet_write;
-use log::{debug, error, info};
+use crate::link_monitor;
+use crate::metrics;
+use crate::pmtu;
+use tracing::{error, info, info_span, Instrument, Span};
 use pnet::datalink;
 use pnet::datalink::Channel::Ethernet;
 use pnet::datalink::NetworkInterface;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tokio::sync::mpsc;
-use crate::error::InitProcessError;
+use tokio::runtime::Handle;
+use tokio::task::{self, JoinSet};
+use crate::error::Retryable;
 
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum PacketAnalysisError {
     #[error("ネットワークエラー: {0}")]
     NetworkError(String),
@@ -20,14 +29,46 @@ pub enum PacketAnalysisError {
     InterfaceError(String),
 }
 
-impl From<PacketAnalysisError> for InitProcessError {
-    fn from(err: PacketAnalysisError) -> Self {
-        InitProcessError::PacketAnalysisError(err.to_string())
+impl Retryable for PacketAnalysisError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            PacketAnalysisError::NetworkError(_) | PacketAnalysisError::IoError(_) => true,
+            // インターフェースが存在しないなど、設定を見直す必要がある
+            PacketAnalysisError::InterfaceError(_) => false,
+        }
+    }
+}
+
+// 受信のタイムアウト。停止要求を確認する間隔を兼ねる
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+// キャプチャタスクが破棄された (リンクダウンなどで停止された) ことを受信スレッドへ伝える
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
     }
 }
 
 async fn handle_interface(interface: NetworkInterface) -> Result<(), PacketAnalysisError> {
-    let (_, mut rx) = match datalink::channel(&interface, Default::default()) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let _stop_on_drop = StopOnDrop(stop.clone());
+    let runtime = Handle::current();
+    let span = Span::current();
+
+    // pnetの受信はブロッキングのため、ランタイムのワーカーを占有しないよう専用のスレッドで実行する
+    task::spawn_blocking(move || span.in_scope(|| capture_loop(interface, &stop, &runtime)))
+        .await
+        .map_err(|e| PacketAnalysisError::NetworkError(e.to_string()))?
+}
+
+fn capture_loop(interface: NetworkInterface, stop: &AtomicBool, runtime: &Handle) -> Result<(), PacketAnalysisError> {
+    let config = datalink::Config {
+        read_timeout: Some(READ_TIMEOUT),
+        ..Default::default()
+    };
+    let (tx, mut rx) = match datalink::channel(&interface, config) {
         Ok(Ethernet(tx, rx)) => (tx, rx),
         Ok(_) => return Err(PacketAnalysisError::InterfaceError(
             "未対応のチャンネルタイプです".to_string()
@@ -36,77 +77,82 @@ async fn handle_interface(interface: NetworkInterface) -> Result<(), PacketAnaly
     };
 
     info!("インターフェース {} でパケット受信を開始しました", interface.name);
+    // MTUを超えるパケットへのICMPは、受信したインターフェースから返す
+    pmtu::register_sender(&interface.name, tx);
+    let captured = metrics::PACKETS_CAPTURED.with_label_values(&[interface.name.as_str()]);
+    let interface_name: Arc<str> = Arc::from(interface.name.as_str());
 
-    loop {
+    while !stop.load(Ordering::Relaxed) {
         match rx.next() {
             Ok(ethernet_packet) => {
-                let packet_data = ethernet_packet.to_vec();
-                tokio::spawn(async move {
-                    if let Err(e) = rdb_tunnel_packet_write(&packet_data).await {
+                captured.inc();
+                // pnetの受信バッファは次の読み取りで上書きされるため、プールのバッファに1回だけコピーする
+                let packet_data = FRAME_POOL.copy_from_slice(ethernet_packet);
+                let interface_name = interface_name.clone();
+                runtime.spawn(async move {
+                    if let Err(e) = rdb_tunnel_packet_write(packet_data, &interface_name).await {
                         error!("パケットの書き込みに失敗しました: {}", e);
                     }
-                });
+                }.in_current_span());
             }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
             Err(e) => {
                 error!("パケットの読み取り中にエラーが発生しました: {}", e);
-                return Err(PacketAnalysisError::NetworkError(e.to_string()));
-            }
-        }
-    }
-}
-
-pub async fn packet_analysis(interface: NetworkInterface) -> Result<(), PacketAnalysisError> {
-    let interfaces = datalink::interfaces();
-    let tap0_interface = interfaces
-        .into_iter()
-        .find(|iface| iface.name == "tap0")
-        .ok_or_else(|| PacketAnalysisError::InterfaceError(
-            "tap0 インターフェースが見つかりません".to_string()
-        ))?;
-
-    let interface_handle = tokio::spawn(async move {
-        if let Err(e) = handle_interface(interface).await {
-            error!("メインインターフェースでエラーが発生: {}", e);
-        }
-    });
-
-    let tap0_handle = tokio::spawn(async move {
-        if let Err(e) = handle_interface(tap0_interface).await {
-            error!("tap0インターフェースでエラーが発生: {}", e);
-        }
-    });
-
-    tokio::select! {
-        result1 = interface_handle => {
-            if let Err(e) = result1 {
-                error!("メインインターフェースのタスクでエラーが発生: {}", e);
-                return Err(PacketAnalysisError::NetworkError(e.to_string()));
-            }
-        }
-        result2 = tap0_handle => {
-            if let Err(e) = result2 {
-                error!("tap0インターフェースのタスクでエラーが発生: {}", e);
+                pmtu::unregister_sender(&interface.name);
                 return Err(PacketAnalysisError::NetworkError(e.to_string()));
             }
         }
     }
 
+    pmtu::unregister_sender(&interface.name);
+    info!("インターフェース {} でのパケット受信を停止しました", interface.name);
     Ok(())
 }
 
-pub fn check_interfaces() -> Result<(), PacketAnalysisError> {
-    let interfaces = datalink::interfaces();
+// キャプチャするインターフェースを決定する。
+// 設定が空の場合は選択したデバイスを使用し、仮想NICは常に含める
+pub fn resolve_capture_interfaces(
+    names: &[String],
+    selected: &NetworkInterface,
+    virtual_interface: &str,
+) -> Result<Vec<NetworkInterface>, PacketAnalysisError> {
+    let available = datalink::interfaces();
+    let find = |name: &str| {
+        available
+            .iter()
+            .find(|iface| iface.name == name)
+            .cloned()
+            .ok_or_else(|| PacketAnalysisError::InterfaceError(format!("{} インターフェースが見つかりません", name)))
+    };
 
-    println!("利用可能なインターフェース:");
-    for iface in interfaces.iter() {
-        println!("- {}: {}", iface.name, if iface.is_up() { "UP" } else { "DOWN" });
+    let mut interfaces = if names.is_empty() {
+        vec![selected.clone()]
+    } else {
+        names.iter().map(|name| find(name)).collect::<Result<Vec<_>, _>>()?
+    };
+    if !interfaces.iter().any(|iface| iface.name == virtual_interface) {
+        interfaces.push(find(virtual_interface)?);
     }
+    Ok(interfaces)
+}
 
-    if !interfaces.iter().any(|iface| iface.name == "tap0") {
-        return Err(PacketAnalysisError::InterfaceError(
-            "tap0インターフェースが見つかりません".to_string()
-        ));
+// インターフェースごとにキャプチャタスクを起動し、いずれかが終了するまで待機する。
+// リンクがダウン・削除された場合はキャプチャを停止し、復帰後に再開する
+pub async fn packet_analysis(interfaces: Vec<NetworkInterface>) -> Result<(), PacketAnalysisError> {
+    let mut tasks = JoinSet::new();
+    for interface in interfaces {
+        let span = info_span!("capture", interface = %interface.name);
+        tasks.spawn(
+            link_monitor::supervise(interface.name, "キャプチャ", handle_interface).instrument(span)
+        );
     }
 
-    Ok(())
+    match tasks.join_next().await {
+        Some(Ok(())) => Ok(()),
+        Some(Err(e)) => {
+            error!("キャプチャタスクでエラーが発生: {}", e);
+            Err(PacketAnalysisError::NetworkError(e.to_string()))
+        }
+        None => Err(PacketAnalysisError::InterfaceError("キャプチャするインターフェースがありません".to_string())),
+    }
 }
\ No newline at end of file
@@ -6,11 +6,22 @@ pub struct IpHeader {
     pub protocol: u8,
     pub src_ip: IpAddr,
     pub dst_ip: IpAddr,
+    // IPv4のTOS・IPv6のトラフィッククラス (上位6ビットがDSCP、下位2ビットがECN)
+    pub traffic_class: u8,
+}
+
+impl IpHeader {
+    pub fn dscp(&self) -> u8 {
+        self.traffic_class >> 2
+    }
+
+    pub fn ecn(&self) -> u8 {
+        self.traffic_class & 0x03
+    }
 }
 
 pub fn parse_ip_header(data: &[u8]) -> Option<IpHeader> {
     let version = (data[0] >> 4) & 0xF;
-    //println!("version: {}", version);
     match version {
         4 => Some(parse_ipv4_header(data)),
         6 => Some(parse_ipv6_header(data)),
@@ -28,6 +39,7 @@ fn parse_ipv4_header(data: &[u8]) -> IpHeader {
         protocol,
         src_ip: IpAddr::V4(src_ip),
         dst_ip: IpAddr::V4(dst_ip),
+        traffic_class: data[1],
     }
 }
 
@@ -59,17 +71,352 @@ fn parse_ipv6_header(data: &[u8]) -> IpHeader {
         protocol,
         src_ip: IpAddr::V6(src_ip),
         dst_ip: IpAddr::V6(dst_ip),
+        traffic_class: (data[0] << 4) | (data[1] >> 4),
+    }
+}
+
+// パケットを組み立てるためのヘッダ。to_bytesでネットワークバイトオーダーのバイト列にする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub dst_mac: [u8; 6],
+    pub src_mac: [u8; 6],
+    pub ether_type: u16,
+}
+
+impl EthernetHeader {
+    pub const LEN: usize = 14;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0..6].copy_from_slice(&self.dst_mac);
+        bytes[6..12].copy_from_slice(&self.src_mac);
+        bytes[12..14].copy_from_slice(&self.ether_type.to_be_bytes());
+        bytes
     }
 }
 
-pub struct NextIpHeader {
-    pub source_port: u16,
-    pub destination_port: u16,
+// オプションなしのIPv4ヘッダ。チェックサムはto_bytesで計算する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub src_ip: Ipv4Addr,
+    pub dst_ip: Ipv4Addr,
+    // ヘッダに続くデータの長さ
+    pub payload_len: u16,
 }
 
-pub fn parse_next_ip_header(data: &[u8]) -> NextIpHeader {
-    NextIpHeader {
-        source_port: u16::from_be_bytes([data[0], data[1]]),
-        destination_port: u16::from_be_bytes([data[2], data[3]]),
+impl Ipv4Header {
+    pub const LEN: usize = 20;
+
+    pub fn new(src_ip: Ipv4Addr, dst_ip: Ipv4Addr, protocol: u8, payload_len: u16) -> Self {
+        Self { identification: 0, dont_fragment: false, ttl: 64, protocol, src_ip, dst_ip, payload_len }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = 0x45;
+        bytes[2..4].copy_from_slice(&(Self::LEN as u16 + self.payload_len).to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.identification.to_be_bytes());
+        if self.dont_fragment {
+            bytes[6] = 0x40;
+        }
+        bytes[8] = self.ttl;
+        bytes[9] = self.protocol;
+        bytes[12..16].copy_from_slice(&self.src_ip.octets());
+        bytes[16..20].copy_from_slice(&self.dst_ip.octets());
+        let checksum = internet_checksum(&[&bytes]);
+        bytes[10..12].copy_from_slice(&checksum.to_be_bytes());
+        bytes
     }
-}
\ No newline at end of file
+}
+
+// 拡張ヘッダなしのIPv6ヘッダ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Header {
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub src_ip: Ipv6Addr,
+    pub dst_ip: Ipv6Addr,
+    // ヘッダに続くデータの長さ
+    pub payload_len: u16,
+}
+
+impl Ipv6Header {
+    pub const LEN: usize = 40;
+
+    pub fn new(src_ip: Ipv6Addr, dst_ip: Ipv6Addr, next_header: u8, payload_len: u16) -> Self {
+        Self { next_header, hop_limit: 64, src_ip, dst_ip, payload_len }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = 0x60;
+        bytes[4..6].copy_from_slice(&self.payload_len.to_be_bytes());
+        bytes[6] = self.next_header;
+        bytes[7] = self.hop_limit;
+        bytes[8..24].copy_from_slice(&self.src_ip.octets());
+        bytes[24..40].copy_from_slice(&self.dst_ip.octets());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TcpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub sequence: u32,
+    pub acknowledgment: u32,
+    pub flags: u8,
+    pub window: u16,
+    // 4バイト単位に満たない分は0 (End of Option List) で埋める。最大40バイト
+    pub options: Vec<u8>,
+}
+
+impl TcpHeader {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+
+    pub fn new(src_port: u16, dst_port: u16, flags: u8) -> Self {
+        Self { src_port, dst_port, sequence: 0, acknowledgment: 0, flags, window: u16::MAX, options: Vec::new() }
+    }
+
+    // オプションを含むヘッダ長
+    pub fn header_len(&self) -> usize {
+        20 + self.options.len().div_ceil(4) * 4
+    }
+
+    // チェックサムを0としたヘッダ
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let len = self.header_len();
+        let mut bytes = Vec::with_capacity(len);
+        bytes.extend_from_slice(&self.src_port.to_be_bytes());
+        bytes.extend_from_slice(&self.dst_port.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.acknowledgment.to_be_bytes());
+        bytes.extend_from_slice(&[((len / 4) as u8) << 4, self.flags]);
+        bytes.extend_from_slice(&self.window.to_be_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&self.options);
+        bytes.resize(len, 0);
+        bytes
+    }
+
+    // 擬似ヘッダとペイロードを含めたチェックサムを設定したヘッダ
+    pub fn to_bytes_with_checksum(&self, src_ip: IpAddr, dst_ip: IpAddr, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = self.to_bytes();
+        let checksum = transport_checksum(src_ip, dst_ip, 6, &bytes, payload);
+        bytes[16..18].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    // ヘッダに続くデータの長さ
+    pub payload_len: u16,
+}
+
+impl UdpHeader {
+    pub const LEN: usize = 8;
+
+    // チェックサムを0 (IPv4では未計算を表す) としたヘッダ
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0..2].copy_from_slice(&self.src_port.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
+        bytes[4..6].copy_from_slice(&(Self::LEN as u16 + self.payload_len).to_be_bytes());
+        bytes
+    }
+
+    // 擬似ヘッダとペイロードを含めたチェックサムを設定したヘッダ (IPv6では必須)
+    pub fn to_bytes_with_checksum(&self, src_ip: IpAddr, dst_ip: IpAddr, payload: &[u8]) -> [u8; Self::LEN] {
+        let mut bytes = self.to_bytes();
+        let checksum = match transport_checksum(src_ip, dst_ip, 17, &bytes, payload) {
+            // 計算結果が0の場合は0xFFFFとして送信する (RFC 768)
+            0 => 0xFFFF,
+            checksum => checksum,
+        };
+        bytes[6..8].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+}
+
+// ICMP (IPv4) のヘッダ。restはタイプごとの4バイト (エコーの場合は識別子とシーケンス番号)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IcmpHeader {
+    pub icmp_type: u8,
+    pub code: u8,
+    pub rest: [u8; 4],
+}
+
+impl IcmpHeader {
+    pub const LEN: usize = 8;
+    pub const ECHO_REPLY: u8 = 0;
+    pub const ECHO_REQUEST: u8 = 8;
+
+    pub fn echo(icmp_type: u8, identifier: u16, sequence: u16) -> Self {
+        let mut rest = [0; 4];
+        rest[0..2].copy_from_slice(&identifier.to_be_bytes());
+        rest[2..4].copy_from_slice(&sequence.to_be_bytes());
+        Self { icmp_type, code: 0, rest }
+    }
+
+    // チェックサムを0としたヘッダ
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = self.icmp_type;
+        bytes[1] = self.code;
+        bytes[4..8].copy_from_slice(&self.rest);
+        bytes
+    }
+
+    // ペイロードを含めたチェックサムを設定したヘッダ
+    pub fn to_bytes_with_checksum(&self, payload: &[u8]) -> [u8; Self::LEN] {
+        let mut bytes = self.to_bytes();
+        let checksum = internet_checksum(&[&bytes, payload]);
+        bytes[2..4].copy_from_slice(&checksum.to_be_bytes());
+        bytes
+    }
+}
+
+// IPパケットのDSCPを書き換える (ECNはそのまま)。IPv4の場合はヘッダチェックサムも更新する。
+// IPヘッダとして短すぎる場合は書き換えずにfalseを返す
+pub fn set_dscp(ip_packet: &mut [u8], dscp: u8) -> bool {
+    let Some(&first) = ip_packet.first() else {
+        return false;
+    };
+    match first >> 4 {
+        4 => {
+            let header_len = (first & 0x0F) as usize * 4;
+            if header_len < Ipv4Header::LEN || ip_packet.len() < header_len {
+                return false;
+            }
+            ip_packet[1] = (dscp << 2) | (ip_packet[1] & 0x03);
+            ip_packet[10..12].fill(0);
+            let checksum = internet_checksum(&[&ip_packet[..header_len]]);
+            ip_packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            true
+        }
+        6 if ip_packet.len() >= Ipv6Header::LEN => {
+            let ecn = (ip_packet[1] >> 4) & 0x03;
+            let traffic_class = (dscp << 2) | ecn;
+            ip_packet[0] = (first & 0xF0) | (traffic_class >> 4);
+            ip_packet[1] = (traffic_class << 4) | (ip_packet[1] & 0x0F);
+            true
+        }
+        _ => false,
+    }
+}
+
+// インターネットチェックサム (RFC 1071)。partsを連結したバイト列に対して計算する
+pub fn internet_checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut odd: Option<u8> = None;
+    for part in parts {
+        for &byte in *part {
+            match odd.take() {
+                Some(high) => sum += u16::from_be_bytes([high, byte]) as u32,
+                None => odd = Some(byte),
+            }
+        }
+    }
+    if let Some(high) = odd {
+        sum += u16::from_be_bytes([high, 0]) as u32;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// TCP・UDPのチェックサム (IPv4/IPv6の擬似ヘッダを含む)
+pub fn transport_checksum(src_ip: IpAddr, dst_ip: IpAddr, protocol: u8, header: &[u8], payload: &[u8]) -> u16 {
+    let length = (header.len() + payload.len()) as u32;
+    match (src_ip, dst_ip) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut pseudo = [0u8; 12];
+            pseudo[0..4].copy_from_slice(&src.octets());
+            pseudo[4..8].copy_from_slice(&dst.octets());
+            pseudo[9] = protocol;
+            pseudo[10..12].copy_from_slice(&(length as u16).to_be_bytes());
+            internet_checksum(&[&pseudo, header, payload])
+        }
+        (src, dst) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let mut pseudo = [0u8; 40];
+            pseudo[0..16].copy_from_slice(&to_v6(src).octets());
+            pseudo[16..32].copy_from_slice(&to_v6(dst).octets());
+            pseudo[32..36].copy_from_slice(&length.to_be_bytes());
+            pseudo[39] = protocol;
+            internet_checksum(&[&pseudo, header, payload])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_checksum_matches_reference_header() {
+        // 4500 0073 0000 4000 4011 b861 c0a8 0001 c0a8 00c7
+        let mut header = Ipv4Header::new(Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(192, 168, 0, 199), 17, 0x73 - 20);
+        header.dont_fragment = true;
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[10..12], &[0xb8, 0x61]);
+        assert_eq!(internet_checksum(&[&bytes]), 0);
+    }
+
+    #[test]
+    fn transport_checksums_verify() {
+        let payload = b"hello, world";
+        for (src, dst) in [
+            (IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])),
+            ("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap()),
+        ] {
+            let mut tcp = TcpHeader::new(40000, 80, TcpHeader::SYN);
+            tcp.options = vec![2, 4, 0x05, 0xb4, 1];
+            let header = tcp.to_bytes_with_checksum(src, dst, payload);
+            assert_eq!(header.len(), 28);
+            assert_eq!(transport_checksum(src, dst, 6, &header, payload), 0);
+
+            let udp = UdpHeader { src_port: 40000, dst_port: 53, payload_len: payload.len() as u16 };
+            let header = udp.to_bytes_with_checksum(src, dst, payload);
+            assert_eq!(transport_checksum(src, dst, 17, &header, payload), 0);
+        }
+
+        let icmp = IcmpHeader::echo(IcmpHeader::ECHO_REQUEST, 1, 2).to_bytes_with_checksum(payload);
+        assert_eq!(internet_checksum(&[&icmp, payload]), 0);
+    }
+
+    #[test]
+    fn remarks_dscp_and_keeps_ecn() {
+        let mut ipv4 = Ipv4Header::new(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), 17, 0).to_bytes();
+        ipv4[1] = 0x01;
+        assert!(set_dscp(&mut ipv4, 46));
+        let header = parse_ip_header(&ipv4).unwrap();
+        assert_eq!((header.dscp(), header.ecn()), (46, 1));
+        assert_eq!(internet_checksum(&[&ipv4]), 0);
+
+        let src = "2001:db8::1".parse().unwrap();
+        let mut ipv6 = Ipv6Header::new(src, "2001:db8::2".parse().unwrap(), 17, 0).to_bytes();
+        ipv6[1] = 0x2A;
+        assert!(set_dscp(&mut ipv6, 10));
+        let header = parse_ip_header(&ipv6).unwrap();
+        assert_eq!((header.version, header.dscp(), header.ecn()), (6, 10, 2));
+        assert_eq!(ipv6[1] & 0x0F, 0x0A);
+
+        assert!(!set_dscp(&mut ipv4[..10], 0));
+    }
+}
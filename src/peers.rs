@@ -0,0 +1,105 @@
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::database::execute_query::ExecuteQuery;
+use crate::database::types::InetAddr;
+use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::Duration;
+
+// トンネル内で通信している対向ノード
+#[derive(Debug, Serialize)]
+pub struct PeerSummary {
+    pub address: IpAddr,
+    pub packets: i64,
+    pub bytes: i64,
+    pub last_seen: DateTime<Utc>,
+}
+
+// 直近 `window` の間にトンネルのネットワーク内から送信したノードを集計する
+pub async fn list_peers(
+    db: &Database,
+    tunnel_network: IpNetwork,
+    window: Duration,
+) -> Result<Vec<PeerSummary>, DbError> {
+    let since = Utc::now() - chrono::Duration::from_std(window).unwrap_or(chrono::Duration::zero());
+    let network = InetAddr::from(tunnel_network);
+
+    let rows = db
+        .query_replica(
+            "SELECT src_ip, count(*) AS packets, coalesce(sum(length(raw_packet)), 0)::bigint AS bytes, max(timestamp) AS last_seen
+            FROM packets
+            WHERE timestamp > $1
+                AND src_ip << $2
+            GROUP BY src_ip
+            ORDER BY last_seen DESC",
+            &[&since, &network],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| PeerSummary {
+            address: row.get("src_ip"),
+            packets: row.get("packets"),
+            bytes: row.get("bytes"),
+            last_seen: row.get("last_seen"),
+        })
+        .collect())
+}
+
+// 対向ノードが広告しているサブネット
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRoutes {
+    pub node: IpAddr,
+    pub tunnel_address: IpAddr,
+    pub subnets: Vec<IpNetwork>,
+}
+
+// 自ノードのトンネルのアドレスと、自ノードの先にあるサブネットを広告する
+pub async fn advertise(
+    db: &Database,
+    node: IpAddr,
+    tunnel_address: IpAddr,
+    subnets: &[IpNetwork],
+) -> Result<(), DbError> {
+    let subnets = subnets.iter().map(|network| network.to_string()).collect::<Vec<_>>();
+    db.execute(
+        "INSERT INTO peers (node, tunnel_address, subnets) VALUES ($1, $2, $3::text[]::cidr[])
+        ON CONFLICT (node) DO UPDATE SET
+            tunnel_address = EXCLUDED.tunnel_address,
+            subnets = EXCLUDED.subnets,
+            updated_at = now()",
+        &[&InetAddr::from(node), &InetAddr::from(tunnel_address), &subnets],
+    )
+    .await?;
+    Ok(())
+}
+
+// 直近 `max_age` の間に広告した自ノード以外のノードのサブネットを取得する
+pub async fn list_peer_routes(db: &Database, node: IpAddr, max_age: Duration) -> Result<Vec<PeerRoutes>, DbError> {
+    let since = Utc::now() - chrono::Duration::from_std(max_age).unwrap_or(chrono::Duration::zero());
+    let rows = db
+        .query_replica(
+            "SELECT node, tunnel_address, subnets::text[] AS subnets
+            FROM peers
+            WHERE node <> $1 AND updated_at > $2
+            ORDER BY node",
+            &[&InetAddr::from(node), &since],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| PeerRoutes {
+            node: row.get("node"),
+            tunnel_address: row.get("tunnel_address"),
+            subnets: row
+                .get::<_, Vec<String>>("subnets")
+                .iter()
+                .filter_map(|subnet| subnet.parse().ok())
+                .collect(),
+        })
+        .collect())
+}
@@ -0,0 +1,230 @@
+use crate::packet_header::{internet_checksum, transport_checksum, EthernetHeader, Ipv4Header, Ipv6Header};
+use lazy_static::lazy_static;
+use pnet::datalink::DataLinkSender;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+// トンネルのMTUを超えたため破棄したパケットの送信元へ、ICMPv4 Fragmentation Needed (タイプ3 コード4) または
+// ICMPv6 Packet Too Big を返し、送信元のPath MTU Discoveryが機能するようにする。
+// ICMPの送信元アドレスには元のパケットの宛先アドレスを使用する (トンネルはL2で中継するため、自身のアドレスを持たない)
+
+// 1秒あたりに送信するICMPの上限
+const MAX_REPLIES_PER_SEC: u32 = 100;
+// ICMPv4のエラーに含める元のパケットの長さの上限 (RFC 1812: ICMPを含めて576バイト以内)
+const MAX_ICMPV4_QUOTE: usize = 576 - Ipv4Header::LEN - 8;
+// ICMPv6のエラーに含める元のパケットの長さの上限 (RFC 4443: IPv6の最小MTUを超えない)
+const MAX_ICMPV6_QUOTE: usize = 1280 - Ipv6Header::LEN - 8;
+
+const ICMPV4_DEST_UNREACHABLE: u8 = 3;
+const ICMPV4_FRAGMENTATION_NEEDED: u8 = 4;
+const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+const ICMPV6_NEXT_HEADER: u8 = 58;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    // キャプチャ中のインターフェースの送信チャネル (インターフェース名 -> 送信側)
+    static ref SENDERS: Mutex<HashMap<String, Box<dyn DataLinkSender>>> = Mutex::new(HashMap::new());
+    // 送信数の上限を数える区間の開始時刻と送信数
+    static ref RATE: Mutex<(Instant, u32)> = Mutex::new((Instant::now(), 0));
+}
+
+// 設定を反映する
+pub fn configure(enabled: bool) {
+    if !enabled {
+        info!("MTUを超えるパケットへのICMPの送信は無効です");
+    }
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// キャプチャ中のインターフェースの送信チャネルを登録する (ICMPはパケットを受信したインターフェースへ返す)
+pub fn register_sender(interface: &str, sender: Box<dyn DataLinkSender>) {
+    SENDERS.lock().unwrap_or_else(|e| e.into_inner()).insert(interface.to_string(), sender);
+}
+
+pub fn unregister_sender(interface: &str) {
+    SENDERS.lock().unwrap_or_else(|e| e.into_inner()).remove(interface);
+}
+
+// フレームのIPパケットがmtuを超え、かつ経路上で断片化できない (IPv4でDFが設定されている・IPv6) 場合に、
+// 送信元へ返すICMPのフレームを組み立てる。該当しない場合はNone
+pub fn too_big_reply(frame: &[u8], mtu: usize) -> Option<Vec<u8>> {
+    let ethernet = frame.get(..EthernetHeader::LEN)?;
+    // ブロードキャスト・マルチキャスト宛のフレームにはICMPのエラーを返さない (RFC 1812 4.3.2.7)
+    if ethernet[0] & 0x01 != 0 {
+        return None;
+    }
+    let ip_packet = &frame[EthernetHeader::LEN..];
+    let reply_ethernet = |ether_type: u16| EthernetHeader {
+        dst_mac: ethernet[6..12].try_into().unwrap_or_default(),
+        src_mac: ethernet[0..6].try_into().unwrap_or_default(),
+        ether_type,
+    };
+
+    match u16::from_be_bytes([ethernet[12], ethernet[13]]) {
+        0x0800 if ip_packet.len() >= Ipv4Header::LEN && ip_packet[0] >> 4 == 4 => {
+            let total_len = u16::from_be_bytes([ip_packet[2], ip_packet[3]]) as usize;
+            let dont_fragment = ip_packet[6] & 0x40 != 0;
+            if total_len.max(ip_packet.len()) <= mtu || !dont_fragment {
+                return None;
+            }
+            let src_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&ip_packet[12..16]).ok()?);
+            let dst_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&ip_packet[16..20]).ok()?);
+            if src_ip.is_unspecified() || src_ip.is_broadcast() || src_ip.is_multicast() || dst_ip.is_multicast() {
+                return None;
+            }
+            // ICMPのエラーに対してはICMPのエラーを返さない (RFC 1122)
+            let header_len = (ip_packet[0] & 0x0F) as usize * 4;
+            let icmp_type = ip_packet.get(header_len).copied();
+            if ip_packet[9] == 1 && icmp_type.is_some_and(|icmp_type| !is_icmpv4_query(icmp_type)) {
+                return None;
+            }
+
+            let quote = &ip_packet[..ip_packet.len().min(MAX_ICMPV4_QUOTE)];
+            let mut icmp = [0u8; 8];
+            icmp[0] = ICMPV4_DEST_UNREACHABLE;
+            icmp[1] = ICMPV4_FRAGMENTATION_NEEDED;
+            icmp[6..8].copy_from_slice(&(mtu.min(u16::MAX as usize) as u16).to_be_bytes());
+            let checksum = internet_checksum(&[&icmp, quote]);
+            icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+            let ip = Ipv4Header::new(dst_ip, src_ip, 1, (icmp.len() + quote.len()) as u16);
+            let mut reply = reply_ethernet(0x0800).to_bytes().to_vec();
+            reply.extend_from_slice(&ip.to_bytes());
+            reply.extend_from_slice(&icmp);
+            reply.extend_from_slice(quote);
+            Some(reply)
+        }
+        0x86DD if ip_packet.len() >= Ipv6Header::LEN && ip_packet[0] >> 4 == 6 => {
+            let payload_len = u16::from_be_bytes([ip_packet[4], ip_packet[5]]) as usize;
+            if (Ipv6Header::LEN + payload_len).max(ip_packet.len()) <= mtu {
+                return None;
+            }
+            let src_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&ip_packet[8..24]).ok()?);
+            let dst_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&ip_packet[24..40]).ok()?);
+            // マルチキャスト宛のパケットには、ICMPの送信元に使用できるアドレスがないため返さない
+            if src_ip.is_unspecified() || src_ip.is_multicast() || dst_ip.is_multicast() {
+                return None;
+            }
+            // ICMPv6のエラー (タイプ127以下) に対してはエラーを返さない
+            let icmp_type = ip_packet.get(Ipv6Header::LEN).copied();
+            if ip_packet[6] == ICMPV6_NEXT_HEADER && icmp_type.is_some_and(|icmp_type| icmp_type < 128) {
+                return None;
+            }
+
+            let quote = &ip_packet[..ip_packet.len().min(MAX_ICMPV6_QUOTE)];
+            let mut icmp = [0u8; 8];
+            icmp[0] = ICMPV6_PACKET_TOO_BIG;
+            icmp[4..8].copy_from_slice(&(mtu as u32).to_be_bytes());
+            let checksum =
+                transport_checksum(IpAddr::V6(dst_ip), IpAddr::V6(src_ip), ICMPV6_NEXT_HEADER, &icmp, quote);
+            icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+            let mut ip = Ipv6Header::new(dst_ip, src_ip, ICMPV6_NEXT_HEADER, (icmp.len() + quote.len()) as u16);
+            ip.hop_limit = 255;
+            let mut reply = reply_ethernet(0x86DD).to_bytes().to_vec();
+            reply.extend_from_slice(&ip.to_bytes());
+            reply.extend_from_slice(&icmp);
+            reply.extend_from_slice(quote);
+            Some(reply)
+        }
+        _ => None,
+    }
+}
+
+// 問い合わせ (エコーなど) のICMPv4タイプ。エラーのタイプにはICMPのエラーを返さない
+fn is_icmpv4_query(icmp_type: u8) -> bool {
+    matches!(icmp_type, 0 | 8 | 13 | 14)
+}
+
+// 送信数の上限を超えていなければ数えてtrueを返す
+fn allow_reply(now: Instant) -> bool {
+    let mut rate = RATE.lock().unwrap_or_else(|e| e.into_inner());
+    if now.saturating_duration_since(rate.0) >= Duration::from_secs(1) {
+        *rate = (now, 0);
+    }
+    if rate.1 >= MAX_REPLIES_PER_SEC {
+        return false;
+    }
+    rate.1 += 1;
+    true
+}
+
+// ICMPをパケットを受信したインターフェースへ送信する
+pub fn send_reply(interface: &str, reply: &[u8]) {
+    if !allow_reply(Instant::now()) {
+        debug!("ICMPの送信数が上限に達したため送信しません");
+        return;
+    }
+    let mut senders = SENDERS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(sender) = senders.get_mut(interface) else {
+        debug!("{} の送信チャネルがないため、ICMPを送信しません", interface);
+        return;
+    };
+    match sender.send_to(reply, None) {
+        Some(Ok(())) => {}
+        Some(Err(e)) => debug!("{} へのICMPの送信に失敗しました: {}", interface, e),
+        None => debug!("{} へのICMPの送信に失敗しました", interface),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_header::parse_ip_header;
+
+    fn frame(ether_type: u16, ip: &[u8], payload_len: usize) -> Vec<u8> {
+        let mut frame = EthernetHeader { dst_mac: [2; 6], src_mac: [4; 6], ether_type }.to_bytes().to_vec();
+        frame.extend_from_slice(ip);
+        frame.resize(frame.len() + payload_len, 0xAB);
+        frame
+    }
+
+    #[test]
+    fn builds_fragmentation_needed_for_df_packet() {
+        let (src, dst) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let mut header = Ipv4Header::new(src, dst, 17, 1600);
+        header.dont_fragment = true;
+        let reply = too_big_reply(&frame(0x0800, &header.to_bytes(), 1600), 1400).unwrap();
+
+        assert_eq!(&reply[0..6], &[4; 6]);
+        let ip = &reply[EthernetHeader::LEN..];
+        let parsed = parse_ip_header(ip).unwrap();
+        assert_eq!((parsed.src_ip, parsed.dst_ip, parsed.protocol), (IpAddr::V4(dst), IpAddr::V4(src), 1));
+        assert_eq!(internet_checksum(&[&ip[..Ipv4Header::LEN]]), 0);
+        let icmp = &ip[Ipv4Header::LEN..];
+        assert_eq!((icmp[0], icmp[1]), (3, 4));
+        assert_eq!(u16::from_be_bytes([icmp[6], icmp[7]]), 1400);
+        assert_eq!(internet_checksum(&[icmp]), 0);
+        assert!(ip.len() <= 576);
+
+        // DFが設定されていないパケット・MTU以内のパケットには返さない
+        header.dont_fragment = false;
+        assert!(too_big_reply(&frame(0x0800, &header.to_bytes(), 1600), 1400).is_none());
+        header.dont_fragment = true;
+        assert!(too_big_reply(&frame(0x0800, &header.to_bytes(), 1600), 1700).is_none());
+    }
+
+    #[test]
+    fn builds_packet_too_big_for_ipv6() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let header = Ipv6Header::new(src, dst, 17, 2000);
+        let reply = too_big_reply(&frame(0x86DD, &header.to_bytes(), 2000), 1500).unwrap();
+
+        let ip = &reply[EthernetHeader::LEN..];
+        assert!(ip.len() <= 1280);
+        let icmp = &ip[Ipv6Header::LEN..];
+        assert_eq!(icmp[0], ICMPV6_PACKET_TOO_BIG);
+        assert_eq!(u32::from_be_bytes([icmp[4], icmp[5], icmp[6], icmp[7]]), 1500);
+        assert_eq!(transport_checksum(IpAddr::V6(dst), IpAddr::V6(src), ICMPV6_NEXT_HEADER, icmp, &[]), 0);
+    }
+}
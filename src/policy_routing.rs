@@ -0,0 +1,180 @@
+use crate::config::PolicyRoutingConfig;
+use crate::error::InitProcessError;
+use crate::virtual_interface::{add_route, add_rule, delete_route, delete_rule, PolicyRule};
+use ipnetwork::IpNetwork;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::process::{Command, Stdio};
+use tracing::{info, warn};
+
+// 選択したトラフィックだけを仮想NICへ流すポリシールーティング。
+// 専用の経路表に仮想NICへのデフォルト経路を設定し、送信元アドレス・fwmarkの規則でその経路表を参照させる。
+// cgroupはnftablesでfwmarkを付けて選択する (アプリケーションごとのトンネル)
+
+// cgroupのfwmarkを付けるnftablesのテーブル
+const NFT_TABLE: &str = "rdb_tunnel";
+
+// 設定した経路・規則。終了時に remove で削除する
+pub struct PolicyRouting {
+    interface: String,
+    table: u32,
+    routes: Vec<(IpNetwork, Option<IpAddr>)>,
+    rules: Vec<PolicyRule>,
+    nft: bool,
+}
+
+// 経路表に設定するデフォルト経路 (宛先, ゲートウェイ)
+fn default_routes(config: &PolicyRoutingConfig) -> Vec<(IpNetwork, Option<IpAddr>)> {
+    let v4 = (IpNetwork::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).expect("0.0.0.0/0"), None);
+    let v6 = (IpNetwork::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0).expect("::/0"), None);
+    match config.gateway {
+        Some(gateway @ IpAddr::V4(_)) => vec![(v4.0, Some(gateway))],
+        Some(gateway @ IpAddr::V6(_)) => vec![(v6.0, Some(gateway))],
+        None => vec![v4, v6],
+    }
+}
+
+// 経路表を参照させる規則。fwmarkの規則は経路を設定したアドレスファミリーごとに追加する
+fn policy_rules(config: &PolicyRoutingConfig, routes: &[(IpNetwork, Option<IpAddr>)]) -> Vec<PolicyRule> {
+    let rule = |source, fwmark, ipv6| PolicyRule { source, fwmark, ipv6, table: config.table, priority: config.priority };
+    let mut rules = config.sources.iter().map(|&source| rule(Some(source), None, source.is_ipv6())).collect::<Vec<_>>();
+    if let Some(fwmark) = config.fwmark {
+        rules.extend(routes.iter().map(|(destination, _)| rule(None, Some(fwmark), destination.is_ipv6())));
+    }
+    rules
+}
+
+// cgroupのプロセスが送信するパケットにfwmarkを付けるnftablesの定義 (既存のテーブルは置き換える)
+fn nft_script(cgroups: &[String], fwmark: u32) -> String {
+    let mut script = format!("table inet {0} {{}}\ndelete table inet {0}\ntable inet {0} {{\n", NFT_TABLE);
+    script.push_str("    chain output {\n        type route hook output priority mangle; policy accept;\n");
+    for cgroup in cgroups {
+        let path = cgroup.trim_matches('/');
+        let level = path.split('/').count();
+        script.push_str(&format!(
+            "        socket cgroupv2 level {} \"{}\" meta mark set {:#x}\n",
+            level, path, fwmark
+        ));
+    }
+    script.push_str("    }\n}\n");
+    script
+}
+
+// nftを実行する (失敗時は標準エラー出力を含めて返す)
+pub(crate) fn nft(args: &[&str], stdin: Option<&str>) -> Result<(), InitProcessError> {
+    let error = |message: String| InitProcessError::VirtualInterfaceError(message);
+    let mut child = Command::new("nft")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| error(format!("nftの実行に失敗: {}", e)))?;
+    if let (Some(script), Some(mut input)) = (stdin, child.stdin.take()) {
+        input.write_all(script.as_bytes()).map_err(|e| error(format!("nftへの書き込みに失敗: {}", e)))?;
+    }
+    let output = child.wait_with_output().map_err(|e| error(format!("nftの実行に失敗: {}", e)))?;
+    if !output.status.success() {
+        return Err(error(format!(
+            "nft {} に失敗: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+// 経路表・規則・nftablesを設定する。途中で失敗した場合は設定済みのものを削除する
+pub async fn install(config: &PolicyRoutingConfig, interface: &str) -> Result<PolicyRouting, InitProcessError> {
+    let mut installed = PolicyRouting {
+        interface: interface.to_string(),
+        table: config.table,
+        routes: Vec::new(),
+        rules: Vec::new(),
+        nft: false,
+    };
+    let result = async {
+        let routes = default_routes(config);
+        for &(destination, gateway) in &routes {
+            add_route(interface, destination, gateway, config.table).await?;
+            installed.routes.push((destination, gateway));
+        }
+        for rule in policy_rules(config, &routes) {
+            add_rule(&rule).await?;
+            info!("ポリシールーティングの規則を追加しました: {}", rule);
+            installed.rules.push(rule);
+        }
+        if let (false, Some(fwmark)) = (config.cgroups.is_empty(), config.fwmark) {
+            nft(&["-f", "-"], Some(&nft_script(&config.cgroups, fwmark)))?;
+            installed.nft = true;
+            info!("cgroupのパケットにfwmark {:#x} を付けます: {}", fwmark, config.cgroups.join(", "));
+        }
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => Ok(installed),
+        Err(e) => {
+            installed.remove().await;
+            Err(e)
+        }
+    }
+}
+
+impl PolicyRouting {
+    // 設定した経路表・規則・nftablesを削除する
+    pub async fn remove(self) {
+        if self.nft {
+            if let Err(e) = nft(&["delete", "table", "inet", NFT_TABLE], None) {
+                warn!("{}", e);
+            }
+        }
+        for rule in &self.rules {
+            if let Err(e) = delete_rule(rule).await {
+                warn!("{}", e);
+            }
+        }
+        for &(destination, gateway) in &self.routes {
+            if let Err(e) = delete_route(&self.interface, destination, gateway, self.table).await {
+                warn!("{}", e);
+            }
+        }
+        if !self.rules.is_empty() {
+            info!("ポリシールーティングの規則を削除しました ({}件)", self.rules.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installs_rules_for_sources_and_fwmark() {
+        let config = PolicyRoutingConfig {
+            enabled: true,
+            gateway: Some("10.0.0.2".parse().unwrap()),
+            sources: vec!["192.168.10.0/24".parse().unwrap()],
+            fwmark: Some(0x64),
+            ..Default::default()
+        };
+        let routes = default_routes(&config);
+        assert_eq!(routes, vec![("0.0.0.0/0".parse().unwrap(), config.gateway)]);
+
+        let rules = policy_rules(&config, &routes).iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(rules, ["priority 1000 from 192.168.10.0/24 lookup 100", "priority 1000 from all fwmark 0x64 lookup 100"]);
+
+        // ゲートウェイがない場合はIPv4/IPv6の両方のfwmarkを対象にする
+        let config = PolicyRoutingConfig { gateway: None, sources: Vec::new(), ..config };
+        let routes = default_routes(&config);
+        assert_eq!(policy_rules(&config, &routes).iter().filter(|rule| rule.ipv6).count(), 1);
+    }
+
+    #[test]
+    fn marks_packets_of_cgroups() {
+        let script = nft_script(&["/system.slice/app.service".to_string()], 0x64);
+        assert!(script.starts_with("table inet rdb_tunnel {}\ndelete table inet rdb_tunnel\n"));
+        assert!(script.contains("socket cgroupv2 level 2 \"system.slice/app.service\" meta mark set 0x64\n"));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::cli::PingArgs;
+use crate::db_read::PacketInfo;
+use crate::db_write::parse_and_analyze_packet;
+use crate::packet_header::{EthernetHeader, Ipv4Header, UdpHeader};
+use crate::transport::{transport, TransportError};
+use bytes::Bytes;
+use futures::future::join_all;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, warn};
+
+// プローブを識別するペイロードの先頭
+const PROBE_MAGIC: &[u8; 8] = b"RDBTPING";
+const PROBE_REQUEST: u8 = 1;
+const PROBE_REPLY: u8 = 2;
+// マジック、種別、セッション、シーケンス番号
+const PROBE_LEN: usize = PROBE_MAGIC.len() + 1 + 4 + 4;
+// プローブは通常の通信では使われないUDPのポート0で送信し、仮想NICには注入しない
+const PROBE_PORT: u16 = 0;
+// 1回のpingで送信できるプローブ数の上限
+pub const MAX_PING_COUNT: u32 = 100;
+
+// ポーリング中のノードのアドレス (プローブの送信元)
+static NODE_IP: RwLock<Option<Ipv4Addr>> = RwLock::new(None);
+static NEXT_SESSION: AtomicU32 = AtomicU32::new(1);
+
+lazy_static! {
+    // 応答待ちのプローブ ((セッション, シーケンス番号) -> 応答の受信時刻の通知先)
+    static ref PENDING: Mutex<HashMap<(u32, u32), oneshot::Sender<Instant>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Error, Debug)]
+pub enum PingError {
+    #[error("ポーリングが開始されていないため、プローブを送信できません")]
+    NotPolling,
+
+    #[error("IPv4アドレスのみ指定できます: {0}")]
+    UnsupportedAddress(IpAddr),
+
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Probe {
+    kind: u8,
+    session: u32,
+    sequence: u32,
+}
+
+impl Probe {
+    fn frame(&self, src_ip: Ipv4Addr, dst_ip: Ipv4Addr) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(PROBE_LEN);
+        payload.extend_from_slice(PROBE_MAGIC);
+        payload.push(self.kind);
+        payload.extend_from_slice(&self.session.to_be_bytes());
+        payload.extend_from_slice(&self.sequence.to_be_bytes());
+
+        let udp = UdpHeader { src_port: PROBE_PORT, dst_port: PROBE_PORT, payload_len: PROBE_LEN as u16 };
+        let mut frame = Vec::with_capacity(EthernetHeader::LEN + Ipv4Header::LEN + UdpHeader::LEN + PROBE_LEN);
+        frame.extend_from_slice(&EthernetHeader { dst_mac: [0; 6], src_mac: [0; 6], ether_type: 0x0800 }.to_bytes());
+        frame.extend_from_slice(&Ipv4Header::new(src_ip, dst_ip, 17, (UdpHeader::LEN + PROBE_LEN) as u16).to_bytes());
+        frame.extend_from_slice(&udp.to_bytes_with_checksum(src_ip.into(), dst_ip.into(), &payload));
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    fn parse(packet: &PacketInfo) -> Option<Self> {
+        if packet.ip_protocol != 17
+            || packet.src_port != Some(PROBE_PORT as i32)
+            || packet.dst_port != Some(PROBE_PORT as i32)
+            || packet.data.len() < PROBE_LEN
+            || !packet.data.starts_with(PROBE_MAGIC)
+        {
+            return None;
+        }
+        let body = &packet.data[PROBE_MAGIC.len()..PROBE_LEN];
+        Some(Self {
+            kind: body[0],
+            session: u32::from_be_bytes(body[1..5].try_into().ok()?),
+            sequence: u32::from_be_bytes(body[5..9].try_into().ok()?),
+        })
+    }
+}
+
+// トランスポートへ直接送信する (ファイアウォールと書き込みバッファは経由しない)
+async fn publish(probe: Probe, src_ip: Ipv4Addr, dst_ip: Ipv4Addr) -> Result<(), TransportError> {
+    let mut packet = parse_and_analyze_packet(Bytes::from(probe.frame(src_ip, dst_ip))).await?;
+    packet.interface = "probe".to_string();
+    transport()?.publish(&[packet]).await
+}
+
+pub fn set_node_ip(ip: IpAddr) {
+    if let IpAddr::V4(ip) = ip {
+        *NODE_IP.write().unwrap_or_else(|e| e.into_inner()) = Some(ip);
+    }
+}
+
+// 受信したパケットがプローブの場合は処理してtrueを返す。
+// 自ノード宛の要求には応答を送信し、応答は待機中のpingへ通知する
+pub fn handle(packet: &PacketInfo, node_ip: IpAddr) -> bool {
+    let Some(probe) = Probe::parse(packet) else {
+        return false;
+    };
+
+    match probe.kind {
+        PROBE_REQUEST if packet.dst_ip == node_ip => {
+            let (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) = (node_ip, packet.src_ip) else {
+                return true;
+            };
+            debug!("{} からのプローブに応答します (seq={})", dst_ip, probe.sequence);
+            let reply = Probe { kind: PROBE_REPLY, ..probe };
+            match tokio::runtime::Handle::try_current() {
+                Ok(runtime) => {
+                    runtime.spawn(async move {
+                        if let Err(e) = publish(reply, src_ip, dst_ip).await {
+                            warn!("プローブの応答の送信に失敗しました: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("プローブの応答を送信できません: {}", e),
+            }
+        }
+        PROBE_REPLY => {
+            let waiter = PENDING.lock().unwrap_or_else(|e| e.into_inner()).remove(&(probe.session, probe.sequence));
+            if let Some(waiter) = waiter {
+                let _ = waiter.send(Instant::now());
+            }
+        }
+        _ => {}
+    }
+    true
+}
+
+// pingの結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingReport {
+    pub peer: IpAddr,
+    pub sent: u32,
+    pub received: u32,
+    pub loss_percent: f64,
+    // プローブごとの往復時間 (応答がない場合はnull)
+    pub rtt_ms: Vec<Option<f64>>,
+}
+
+impl PingReport {
+    fn received_rtts(&self) -> impl Iterator<Item = f64> + '_ {
+        self.rtt_ms.iter().flatten().copied()
+    }
+
+    pub fn min_ms(&self) -> Option<f64> {
+        self.received_rtts().reduce(f64::min)
+    }
+
+    pub fn max_ms(&self) -> Option<f64> {
+        self.received_rtts().reduce(f64::max)
+    }
+
+    pub fn avg_ms(&self) -> Option<f64> {
+        (self.received > 0).then(|| self.received_rtts().sum::<f64>() / self.received as f64)
+    }
+}
+
+// 対向ノードへデータベースの経路でプローブを送信し、応答までの往復時間と損失を計測する
+pub async fn ping(peer: IpAddr, count: u32, interval: Duration, wait: Duration) -> Result<PingReport, PingError> {
+    let IpAddr::V4(dst_ip) = peer else {
+        return Err(PingError::UnsupportedAddress(peer));
+    };
+    let src_ip = NODE_IP.read().unwrap_or_else(|e| e.into_inner()).ok_or(PingError::NotPolling)?;
+    let session = NEXT_SESSION.fetch_add(1, Ordering::Relaxed);
+    let count = count.clamp(1, MAX_PING_COUNT);
+
+    let mut replies = Vec::with_capacity(count as usize);
+    for sequence in 0..count {
+        if sequence > 0 {
+            sleep(interval).await;
+        }
+
+        let (waiter, reply) = oneshot::channel();
+        PENDING.lock().unwrap_or_else(|e| e.into_inner()).insert((session, sequence), waiter);
+        let sent_at = Instant::now();
+        if let Err(e) = publish(Probe { kind: PROBE_REQUEST, session, sequence }, src_ip, dst_ip).await {
+            PENDING.lock().unwrap_or_else(|e| e.into_inner()).remove(&(session, sequence));
+            return Err(e.into());
+        }
+        replies.push(async move {
+            let rtt = timeout(wait, reply).await.ok()?.ok()?.duration_since(sent_at);
+            Some(rtt.as_secs_f64() * 1000.0)
+        });
+    }
+
+    let rtt_ms = join_all(replies).await;
+    // タイムアウトしたプローブの待機を破棄する
+    PENDING.lock().unwrap_or_else(|e| e.into_inner()).retain(|(pending, _), _| *pending != session);
+
+    let received = rtt_ms.iter().filter(|rtt| rtt.is_some()).count() as u32;
+    Ok(PingReport {
+        peer,
+        sent: count,
+        received,
+        loss_percent: (count - received) as f64 / count as f64 * 100.0,
+        rtt_ms,
+    })
+}
+
+// pingの要求 (POST /api/v1/ping)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PingRequest {
+    pub peer: IpAddr,
+    pub count: u32,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+// 実行中のrdb-tunnelの管理APIを通じてpingを実行し、結果を表示する
+pub async fn run(args: PingArgs) -> Result<(), String> {
+    let request = PingRequest {
+        peer: args.peer,
+        count: args.count,
+        interval_ms: args.interval.as_millis() as u64,
+        timeout_ms: args.timeout.as_millis() as u64,
+    };
+    // 全てのプローブの送信と応答待ちが終わるまで待機する
+    let client = reqwest::Client::builder()
+        .timeout(args.interval * args.count + args.timeout + Duration::from_secs(5))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut http_request = client.post(format!("{}/api/v1/ping", args.url.trim_end_matches('/'))).json(&request);
+    if let Some(token) = &args.token {
+        http_request = http_request.bearer_auth(token);
+    }
+
+    println!("{} へデータベースの経路でプローブを {} 回送信します", args.peer, args.count);
+    let response = http_request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let status = response.status();
+        return Err(format!("{}: {}", status, response.text().await.unwrap_or_default()));
+    }
+    let report = response.json::<PingReport>().await.map_err(|e| e.to_string())?;
+
+    for (sequence, rtt) in report.rtt_ms.iter().enumerate() {
+        match rtt {
+            Some(rtt) => println!("seq={} rtt={:.1}ms", sequence, rtt),
+            None => println!("seq={} タイムアウト", sequence),
+        }
+    }
+    println!("--- {} ---", report.peer);
+    println!("送信 {}, 受信 {}, 損失 {:.1}%", report.sent, report.received, report.loss_percent);
+    if let (Some(min), Some(avg), Some(max)) = (report.min_ms(), report.avg_ms(), report.max_ms()) {
+        println!("rtt min/avg/max = {:.1}/{:.1}/{:.1} ms", min, avg, max);
+    }
+
+    if report.received == 0 {
+        return Err(format!("{} から応答がありませんでした", report.peer));
+    }
+    Ok(())
+}
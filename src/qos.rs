@@ -0,0 +1,60 @@
+use crate::config::{QosConfig, RemarkRule};
+use crate::db_write::PacketData;
+use crate::firewall_packet::FirewallPacket;
+use crate::metrics;
+use crate::packet_header::{set_dscp, EthernetHeader};
+use std::sync::RwLock;
+use tracing::{info, trace};
+
+// DSCPの書き換え規則 (優先度の高い順)
+static REMARK_RULES: RwLock<Vec<RemarkRule>> = RwLock::new(Vec::new());
+
+impl RemarkRule {
+    fn matches(&self, packet: &FirewallPacket, dscp: u8) -> bool {
+        self.from_dscp.is_none_or(|from| from == dscp)
+            && self.filter.as_ref().is_none_or(|filter| filter.matches(packet))
+    }
+}
+
+// 設定を反映する
+pub fn configure(config: &QosConfig) {
+    let mut rules = config.remark.clone();
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
+    if !rules.is_empty() {
+        info!("DSCPの書き換え規則: {}件", rules.len());
+    }
+    *REMARK_RULES.write().unwrap_or_else(|e| e.into_inner()) = rules;
+}
+
+// 規則に一致したIPパケットのDSCPを書き換える。書き換えた場合はtrue
+pub fn remark(packet: &mut PacketData, target: &FirewallPacket) -> bool {
+    let Some(current) = packet.dscp else {
+        return false;
+    };
+    let dscp = {
+        let rules = REMARK_RULES.read().unwrap_or_else(|e| e.into_inner());
+        match rules.iter().find(|rule| rule.matches(target, current as u8)) {
+            Some(rule) if rule.dscp as i16 != current => rule.dscp,
+            _ => return false,
+        }
+    };
+
+    let remarked = mark(packet, dscp);
+    if remarked {
+        trace!("DSCPを書き換えました: {} -> {} ({} -> {})", current, dscp, target.src_ip, target.dst_ip);
+    }
+    remarked
+}
+
+// IPパケットのDSCPを指定した値に書き換える (既に同じ値の場合は書き換えない)。書き換えた場合はtrue
+pub fn mark(packet: &mut PacketData, dscp: u8) -> bool {
+    if packet.dscp.is_none_or(|current| current == dscp as i16) {
+        return false;
+    }
+    let remarked = packet.edit_frame(|frame| set_dscp(&mut frame[EthernetHeader::LEN..], dscp));
+    if remarked {
+        packet.dscp = Some(dscp as i16);
+        metrics::PACKETS_REMARKED.inc();
+    }
+    remarked
+}
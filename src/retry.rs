@@ -0,0 +1,128 @@
+use crate::config::RetryConfig;
+use crate::error::Retryable;
+use crate::messages::{message, MessageId};
+use crate::metrics;
+use rand::Rng;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+// 直列化の失敗・接続の切断・文のタイムアウトなどの一時的なエラーを、ジッター付きの指数バックオフで再試行する。
+// 再試行しても回復しないエラー (Retryable) はそのまま返す
+
+static RETRY: RwLock<Option<RetryConfig>> = RwLock::new(None);
+
+pub fn configure(config: &RetryConfig) {
+    *RETRY.write().unwrap_or_else(|e| e.into_inner()) = Some(config.clone());
+}
+
+// 設定した方針でoperationを実行する (設定前は既定値を使用する)
+pub async fn retry<T, E, F, Fut>(operation: &str, run: F) -> Result<T, E>
+where
+    E: Retryable + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let config = RETRY.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default();
+    retry_with(&config, operation, run).await
+}
+
+// 再試行までの待機時間。上限を倍にしていき (max_backoffまで)、0から上限の間で無作為に選ぶ (フルジッター)
+fn backoff(config: &RetryConfig, attempt: u32) -> Duration {
+    let limit = config
+        .initial_backoff
+        .checked_mul(1u32.checked_shl(attempt).unwrap_or(u32::MAX))
+        .map_or(config.max_backoff, |delay| delay.min(config.max_backoff));
+    limit.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+// 指定した方針でoperationを実行し、一時的なエラーの場合は最大 max_attempts 回まで試行する
+pub async fn retry_with<T, E, F, Fut>(config: &RetryConfig, operation: &str, mut run: F) -> Result<T, E>
+where
+    E: Retryable + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match run().await {
+            Err(e) if e.is_retryable() && attempt + 1 < config.max_attempts => {
+                let delay = backoff(config, attempt);
+                attempt += 1;
+                metrics::DB_RETRIES.with_label_values(&[operation]).inc();
+                warn!(
+                    "{}",
+                    message(
+                        MessageId::RetryScheduled,
+                        &[&operation, &delay.as_millis(), &attempt, &(config.max_attempts - 1), &e]
+                    )
+                );
+                sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::error::DbError;
+    use crate::transport::TransportError;
+
+    fn config() -> RetryConfig {
+        RetryConfig {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_only_transient_errors() {
+        let mut attempts = 0;
+        let result: Result<(), TransportError> = retry_with(&config(), "test", || {
+            attempts += 1;
+            async { Err(TransportError::Database(DbError::Other("closed".to_string()))) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        // 一時的なエラーは成功するまで再試行する
+        let mut attempts = 0;
+        let result = retry_with(&config(), "test", || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    1 => Err(crate::error::Error::Task("reset".to_string())),
+                    _ => Ok(attempt),
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 2);
+
+        // 回数の上限で最後のエラーを返す
+        let mut attempts = 0;
+        let result: Result<(), _> = retry_with(&config(), "test", || {
+            attempts += 1;
+            async { Err(crate::error::Error::Task("reset".to_string())) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+    }
+
+    #[test]
+    fn jitters_backoff_within_limit() {
+        let config = config();
+        for attempt in 0..8 {
+            assert!(backoff(&config, attempt) <= config.max_backoff);
+        }
+    }
+}
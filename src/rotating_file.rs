@@ -0,0 +1,142 @@
+use crate::config::{LogConfig, LogRotation};
+use chrono::{DateTime, Local, Timelike};
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// サイズと時間でローテーションするログファイル
+pub struct RotatingFileWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: DateTime<Local>,
+    max_size: Option<u64>,
+    rotation: LogRotation,
+    max_age: Option<Duration>,
+    max_files: Option<usize>,
+}
+
+impl RotatingFileWriter {
+    pub fn new(path: PathBuf, config: &LogConfig) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        let writer = Self {
+            path,
+            file,
+            size,
+            opened_at: Local::now(),
+            max_size: config.max_size_mb.map(|mb| mb * 1024 * 1024),
+            rotation: config.rotation,
+            max_age: config.max_age_days.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            max_files: config.max_files,
+        };
+        writer.prune();
+        Ok(writer)
+    }
+
+    fn should_rotate(&self, incoming: usize) -> bool {
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + incoming as u64 > max_size {
+                return true;
+            }
+        }
+
+        let now = Local::now();
+        match self.rotation {
+            LogRotation::Never => false,
+            LogRotation::Hourly => {
+                now.date_naive() != self.opened_at.date_naive() || now.hour() != self.opened_at.hour()
+            }
+            LogRotation::Daily => now.date_naive() != self.opened_at.date_naive(),
+        }
+    }
+
+    // 現在のファイルを `<name>.<日時>` にリネームし、新しいファイルを開く
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        let suffix = Local::now().format("%Y%m%d-%H%M%S%.3f");
+        let rotated = PathBuf::from(format!("{}.{}", self.path.display(), suffix));
+        fs::rename(&self.path, &rotated)?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.opened_at = Local::now();
+        self.prune();
+        Ok(())
+    }
+
+    // 保持期間と保持数を超えたローテーション済みファイルを削除する
+    fn prune(&self) {
+        let mut rotated = match self.rotated_files() {
+            Ok(files) => files,
+            Err(_) => return,
+        };
+        // 新しい順に並べる
+        rotated.sort_by_key(|(_, modified)| Reverse(*modified));
+
+        let now = SystemTime::now();
+        for (index, (path, modified)) in rotated.iter().enumerate() {
+            let too_many = self.max_files.is_some_and(|max| index >= max);
+            let too_old = self.max_age.is_some_and(|max_age| {
+                now.duration_since(*modified).map(|age| age > max_age).unwrap_or(false)
+            });
+            if too_many || too_old {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn rotated_files(&self) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+        let dir = match self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => dir.to_path_buf(),
+            None => PathBuf::from("."),
+        };
+        let prefix = format!(
+            "{}.",
+            self.path.file_name().and_then(|name| name.to_str()).unwrap_or_default()
+        );
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !name.to_str().is_some_and(|name| name.starts_with(&prefix)) {
+                continue;
+            }
+            let modified = entry.metadata()?.modified()?;
+            files.push((entry.path(), modified));
+        }
+        Ok(files)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            // ローテーションに失敗してもログの書き込みは継続する
+            if let Err(e) = self.rotate() {
+                eprintln!("ログファイルのローテーションに失敗しました ({}): {}", self.path().display(), e);
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,150 @@
+use crate::config::{InterfaceMode, RoutesConfig};
+use crate::database::database::Database;
+use crate::peers::{self, PeerRoutes};
+use crate::virtual_interface::{add_route, delete_route, MAIN_TABLE};
+use ipnetwork::IpNetwork;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+// 自ノードの先にあるサブネットを peers テーブルに広告し、対向ノードが広告したサブネットへの経路を仮想NICに設定する。
+// 終了時には設定した経路を削除する (手動の ip route が不要になる)
+
+// 設定する経路 (宛先 -> ゲートウェイ)。ゲートウェイがない場合は仮想NICに直接送る
+type Routes = BTreeMap<IpNetwork, Option<IpAddr>>;
+
+// 対向ノードの広告から設定する経路を求める。
+// TAPでは対向ノードのトンネルのアドレスをゲートウェイにし、TUNでは仮想NICに直接送る
+fn desired_routes(peers: &[PeerRoutes], tunnel_network: IpNetwork, advertised: &[IpNetwork], mode: InterfaceMode) -> Routes {
+    let mut routes = Routes::new();
+    for peer in peers {
+        for &subnet in &peer.subnets {
+            // 仮想NICに直接接続しているネットワークと自ノードの先にあるサブネットには設定しない
+            if overlaps(subnet, tunnel_network) || advertised.iter().any(|&own| overlaps(subnet, own)) {
+                debug!("{} が広告したサブネット {} は自ノードのネットワークと重なるため経路を設定しません", peer.node, subnet);
+                continue;
+            }
+            let gateway = match mode {
+                InterfaceMode::Tap if subnet.is_ipv4() != peer.tunnel_address.is_ipv4() => continue,
+                InterfaceMode::Tap => Some(peer.tunnel_address),
+                InterfaceMode::Tun => None,
+            };
+            // 複数のノードが同じサブネットを広告している場合は先に取得したノードを使用する
+            routes.entry(subnet).or_insert(gateway);
+        }
+    }
+    routes
+}
+
+fn overlaps(a: IpNetwork, b: IpNetwork) -> bool {
+    a.contains(b.network()) || b.contains(a.network())
+}
+
+// 設定済みの経路を目的の経路に合わせる
+async fn sync_routes(interface: &str, desired: Routes, installed: &mut Routes) {
+    let stale = installed
+        .iter()
+        .filter(|(destination, gateway)| desired.get(destination) != Some(gateway))
+        .map(|(&destination, &gateway)| (destination, gateway))
+        .collect::<Vec<_>>();
+    for (destination, gateway) in stale {
+        match delete_route(interface, destination, gateway, MAIN_TABLE).await {
+            Ok(()) => info!("経路を削除しました: {}", destination),
+            Err(e) => warn!("{}", e),
+        }
+        installed.remove(&destination);
+    }
+
+    for (destination, gateway) in desired {
+        if installed.contains_key(&destination) {
+            continue;
+        }
+        match add_route(interface, destination, gateway, MAIN_TABLE).await {
+            Ok(()) => {
+                match gateway {
+                    Some(gateway) => info!("経路を設定しました: {} via {} dev {}", destination, gateway, interface),
+                    None => info!("経路を設定しました: {} dev {}", destination, interface),
+                }
+                installed.insert(destination, gateway);
+            }
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+// 広告と経路の同期を一定間隔で行い、シャットダウン時に設定した経路を削除する
+pub async fn start_route_sync(
+    db: Arc<Database>,
+    config: RoutesConfig,
+    node: IpAddr,
+    tunnel_address: IpNetwork,
+    interface: String,
+    mode: InterfaceMode,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    let tunnel_network = IpNetwork::new(tunnel_address.network(), tunnel_address.prefix()).unwrap_or(tunnel_address);
+    let mut installed = Routes::new();
+    let mut interval = tokio::time::interval(config.interval);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+
+        if let Err(e) = peers::advertise(&db, node, tunnel_address.ip(), &config.advertise).await {
+            warn!("サブネットの広告に失敗しました: {}", e);
+        }
+        // 取得に失敗した場合は設定済みの経路を維持する
+        match peers::list_peer_routes(&db, node, config.expire).await {
+            Ok(peers) => {
+                let desired = desired_routes(&peers, tunnel_network, &config.advertise, mode);
+                sync_routes(&interface, desired, &mut installed).await;
+            }
+            Err(e) => warn!("対向ノードのサブネットの取得に失敗しました: {}", e),
+        }
+    }
+
+    info!("設定した経路を削除しています ({}件)", installed.len());
+    sync_routes(&interface, Routes::new(), &mut installed).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(node: &str, tunnel_address: &str, subnets: &[&str]) -> PeerRoutes {
+        PeerRoutes {
+            node: node.parse().unwrap(),
+            tunnel_address: tunnel_address.parse().unwrap(),
+            subnets: subnets.iter().map(|subnet| subnet.parse().unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn routes_advertised_subnets_via_peer_tunnel_address() {
+        let peers = [
+            peer("192.168.0.2", "10.0.0.2", &["172.16.2.0/24", "10.0.0.0/16", "fd00:2::/64"]),
+            peer("192.168.0.3", "10.0.0.3", &["172.16.2.0/24", "172.16.3.0/24", "172.16.1.128/25"]),
+        ];
+        let tunnel_network = "10.0.0.0/24".parse().unwrap();
+        let advertised = ["172.16.1.0/24".parse().unwrap()];
+
+        let routes = desired_routes(&peers, tunnel_network, &advertised, InterfaceMode::Tap);
+        let expected = [
+            ("172.16.2.0/24", Some("10.0.0.2")),
+            ("172.16.3.0/24", Some("10.0.0.3")),
+        ];
+        assert_eq!(
+            routes,
+            expected.iter().map(|(subnet, gateway)| (subnet.parse().unwrap(), gateway.map(|ip| ip.parse().unwrap()))).collect()
+        );
+
+        // TUNではゲートウェイを使用しないため、アドレスファミリーが異なるサブネットも設定する
+        let routes = desired_routes(&peers, tunnel_network, &advertised, InterfaceMode::Tun);
+        assert_eq!(routes.len(), 3);
+        assert!(routes.values().all(Option::is_none));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::cli::{RulesArgs, RulesCommand};
+use crate::firewall::{Chain, Filter, Firewall, IpFirewall, Policy, Rule, MAX_VNI};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+// ファイアウォールの全てのルールをJSON/YAMLでエクスポート・インポートする。
+// ルールセットをgitで管理し、検証してから全てのチェインをまとめて置き換える
+
+// ルールセットの形式のバージョン (形式を変更した場合に上げる)
+pub const RULE_SET_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    pub version: u32,
+    #[serde(default)]
+    pub input: ChainRules,
+    #[serde(default)]
+    pub output: ChainRules,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainRules {
+    pub policy: Policy,
+    pub rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn from_firewall(firewall: &Firewall) -> Self {
+        let chain = |chain: Chain| ChainRules {
+            policy: firewall.chain(chain).policy(),
+            rules: firewall.chain(chain).rules(),
+        };
+        Self {
+            version: RULE_SET_VERSION,
+            input: chain(Chain::Input),
+            output: chain(Chain::Output),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.version != RULE_SET_VERSION {
+            return Err(format!("未対応のルールセットのバージョンです: {} (対応: {})", self.version, RULE_SET_VERSION));
+        }
+        for (name, chain) in [("input", &self.input), ("output", &self.output)] {
+            let mut filters = HashSet::new();
+            for rule in &chain.rules {
+                // 優先度0のルールは一致しても適用されない
+                if rule.priority == 0 {
+                    return Err(format!("{}: 優先度は1以上を指定してください: {:?}", name, rule.filter));
+                }
+                if matches!(rule.filter, Filter::Vni(vni) if vni > MAX_VNI) {
+                    return Err(format!("{}: VNIは24ビット (0-{}) で指定してください: {:?}", name, MAX_VNI, rule.filter));
+                }
+                if !filters.insert(&rule.filter) {
+                    return Err(format!("{}: 同じフィルタのルールが重複しています: {:?}", name, rule.filter));
+                }
+                if let Some(schedule) = &rule.schedule {
+                    schedule.validate().map_err(|e| format!("{}: {:?}: {}", name, rule.filter, e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 検証済みのルールセットからファイアウォールを組み立てる
+    pub fn into_firewall(self) -> Result<Firewall, String> {
+        self.validate()?;
+        let chain = |rules: ChainRules| {
+            let mut firewall = IpFirewall::new(rules.policy);
+            for rule in rules.rules {
+                firewall.insert(rule);
+            }
+            firewall
+        };
+        Ok(Firewall::new(chain(self.input), chain(self.output)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl RuleFormat {
+    // 拡張子から形式を判定する (.yaml / .yml 以外はJSON)
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => RuleFormat::Yaml,
+            _ => RuleFormat::Json,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            RuleFormat::Json => "application/json",
+            RuleFormat::Yaml => "application/yaml",
+        }
+    }
+}
+
+pub fn parse(text: &str, format: RuleFormat) -> Result<RuleSet, String> {
+    let rule_set: RuleSet = match format {
+        RuleFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string())?,
+        RuleFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string())?,
+    };
+    rule_set.validate()?;
+    Ok(rule_set)
+}
+
+pub fn serialize(rule_set: &RuleSet, format: RuleFormat) -> Result<String, String> {
+    match format {
+        RuleFormat::Json => serde_json::to_string_pretty(rule_set).map(|text| text + "\n").map_err(|e| e.to_string()),
+        RuleFormat::Yaml => serde_yaml::to_string(rule_set).map_err(|e| e.to_string()),
+    }
+}
+
+// 実行中のrdb-tunnelの管理APIを通じてルールをエクスポート・インポートする
+pub async fn run(args: RulesArgs) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let url = format!("{}/api/v1/firewall/rules", args.url.trim_end_matches('/'));
+
+    let request = match &args.command {
+        RulesCommand::Export(export) => {
+            let format = export.format.unwrap_or_else(|| export.output.as_deref().map_or(RuleFormat::Json, RuleFormat::from_path));
+            client.get(format!("{}/export", url)).query(&[("format", format)])
+        }
+        RulesCommand::Import(import) => {
+            let text = std::fs::read_to_string(&import.file)
+                .map_err(|e| format!("{} を読み込めません: {}", import.file.display(), e))?;
+            let format = import.format.unwrap_or_else(|| RuleFormat::from_path(&import.file));
+            // 送信する前に手元でも検証する
+            parse(&text, format).map_err(|e| format!("{}: {}", import.file.display(), e))?;
+            client
+                .put(format!("{}/import", url))
+                .query(&[("format", format)])
+                .header(reqwest::header::CONTENT_TYPE, format.content_type())
+                .body(text)
+        }
+    };
+    let request = match &args.token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    };
+
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let status = response.status();
+        return Err(format!("{}: {}", status, response.text().await.unwrap_or_default()));
+    }
+
+    match &args.command {
+        RulesCommand::Export(export) => {
+            let text = response.text().await.map_err(|e| e.to_string())?;
+            match &export.output {
+                Some(path) => {
+                    std::fs::write(path, text).map_err(|e| format!("{} に書き込めません: {}", path.display(), e))?;
+                    println!("ファイアウォールルールを {} にエクスポートしました", path.display());
+                }
+                None => print!("{}", text),
+            }
+        }
+        RulesCommand::Import(import) => {
+            println!("{} のファイアウォールルールをインポートしました", import.file.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+version: 1
+input:
+  policy: whitelist
+  rules:
+    - filter: { type: port, value: 22 }
+      priority: 10
+      schedule:
+        days: [mon, fri]
+        time_ranges: ["09:00-18:00"]
+output:
+  rules:
+    - filter: { type: oui, value: "00:1b:21" }
+      priority: 5
+"#;
+
+    #[test]
+    fn round_trips_rule_set_between_formats() {
+        let rule_set = parse(YAML, RuleFormat::Yaml).unwrap();
+        assert_eq!(rule_set.input.policy, Policy::Whitelist);
+        assert_eq!(rule_set.output.policy, Policy::Blacklist);
+        assert_eq!(rule_set.output.rules[0].filter, Filter::Oui([0x00, 0x1b, 0x21]));
+
+        for format in [RuleFormat::Json, RuleFormat::Yaml] {
+            let text = serialize(&rule_set, format).unwrap();
+            assert_eq!(parse(&text, format).unwrap(), rule_set);
+        }
+        let firewall = rule_set.clone().into_firewall().unwrap();
+        assert_eq!(RuleSet::from_firewall(&firewall), rule_set);
+    }
+
+    #[test]
+    fn rejects_invalid_rule_sets() {
+        assert!(parse(&YAML.replace("version: 1", "version: 2"), RuleFormat::Yaml).is_err());
+        assert!(parse(&YAML.replace("priority: 5", "priority: 0"), RuleFormat::Yaml).is_err());
+        assert!(parse(&YAML.replace("priority: 5", "priority: 5\n      comment: x"), RuleFormat::Yaml).is_err());
+        assert!(parse(&YAML.replace("type: oui", "type: vlan"), RuleFormat::Yaml).is_err());
+        let duplicated = YAML.replace("value: \"00:1b:21\" }\n      priority: 5", "value: \"00:1b:21\" }\n      priority: 5\n    - filter: { type: oui, value: \"00:1b:21\" }\n      priority: 6");
+        assert!(parse(&duplicated, RuleFormat::Yaml).unwrap_err().contains("重複"));
+    }
+}
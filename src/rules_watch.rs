@@ -0,0 +1,206 @@
+use crate::firewall::{Chain, Filter, Firewall, Rule, FIREWALL};
+use crate::rules::{self, RuleFormat, RuleSet};
+use notify::{RecursiveMode, Watcher};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
+
+// [firewall] rules_dir のルールセットのファイル (.json / .yaml / .yml) を監視し、変更された場合にルールを再読み込みする。
+// 読み込みに失敗したファイルはファイルごとにエラーを出力し、そのファイルの前回読み込んだルールを維持する。
+// ディレクトリから読み込んだルールのみを置き換え、管理APIで追加したルールや一時的な遮断はそのまま残す
+
+// 保存時に複数回発生する変更通知をまとめる時間
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+// ファイルごとの最後に読み込めたルールセットと、適用中のルールのフィルタ
+#[derive(Debug, Default)]
+struct RuleDirectory {
+    files: BTreeMap<PathBuf, RuleSet>,
+    applied: HashMap<Chain, HashSet<Filter>>,
+}
+
+fn is_rule_file(path: &Path) -> bool {
+    matches!(path.extension().and_then(|extension| extension.to_str()), Some("json" | "yaml" | "yml"))
+}
+
+impl RuleDirectory {
+    // ディレクトリのファイルを読み込む。読み込みに失敗したファイルは (パス, エラー) を返す
+    fn load(&mut self, dir: &Path) -> Result<Vec<(PathBuf, String)>, String> {
+        let mut paths = std::fs::read_dir(dir)
+            .map_err(|e| format!("{} を読み込めません: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && is_rule_file(path))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        // 削除されたファイルのルールは取り除く
+        self.files.retain(|path, _| paths.contains(path));
+        let mut errors = Vec::new();
+        for path in paths {
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| rules::parse(&text, RuleFormat::from_path(&path)));
+            match result {
+                Ok(rule_set) => {
+                    self.files.insert(path, rule_set);
+                }
+                Err(e) => errors.push((path, e)),
+            }
+        }
+        Ok(errors)
+    }
+
+    // 全てのファイルのルールをチェインごとにまとめる。複数のファイルに同じフィルタがある場合はエラーにする
+    fn merged(&self) -> Result<HashMap<Chain, Vec<Rule>>, String> {
+        let mut merged: HashMap<Chain, Vec<Rule>> = HashMap::new();
+        let mut sources: HashMap<(Chain, &Filter), &Path> = HashMap::new();
+        for (path, rule_set) in &self.files {
+            for (chain, rules) in [(Chain::Input, &rule_set.input.rules), (Chain::Output, &rule_set.output.rules)] {
+                for rule in rules {
+                    if let Some(other) = sources.insert((chain, &rule.filter), path) {
+                        return Err(format!(
+                            "{:?}: {:?} が {} と {} で重複しています",
+                            chain,
+                            rule.filter,
+                            other.display(),
+                            path.display()
+                        ));
+                    }
+                    merged.entry(chain).or_default().push(rule.clone());
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    // 前回適用したルールのうち無くなったものを削除し、ルールを追加・更新する (変更しないルールの統計情報は引き継ぐ)
+    fn apply(&mut self, firewall: &mut Firewall, merged: HashMap<Chain, Vec<Rule>>) -> usize {
+        let mut count = 0;
+        for chain in [Chain::Input, Chain::Output] {
+            let rules = merged.get(&chain).cloned().unwrap_or_default();
+            let filters = rules.iter().map(|rule| rule.filter.clone()).collect::<HashSet<_>>();
+            let previous = self.applied.insert(chain, filters.clone()).unwrap_or_default();
+            let target = firewall.chain_mut(chain);
+            for filter in previous.difference(&filters) {
+                target.remove_rule(filter);
+            }
+            count += rules.len();
+            for rule in rules {
+                target.insert(rule);
+            }
+        }
+        count
+    }
+
+    fn reload(&mut self, dir: &Path) {
+        let errors = match self.load(dir) {
+            Ok(errors) => errors,
+            Err(e) => {
+                error!("ファイアウォールルールの再読み込みに失敗しました: {}", e);
+                return;
+            }
+        };
+        for (path, e) in &errors {
+            error!("{} を読み込めません (前回のルールを維持します): {}", path.display(), e);
+        }
+        match self.merged() {
+            Ok(merged) => {
+                let count = self.apply(&mut FIREWALL.write().unwrap_or_else(|e| e.into_inner()), merged);
+                info!(
+                    "{} のファイアウォールルールを読み込みました (ファイル {}件, ルール {}件, エラー {}件)",
+                    dir.display(),
+                    self.files.len(),
+                    count,
+                    errors.len()
+                );
+            }
+            Err(e) => error!("ファイアウォールルールを適用できません (現在のルールを維持します): {}", e),
+        }
+    }
+}
+
+// 起動時にルールを読み込み、ディレクトリの変更を監視する
+pub async fn start(dir: PathBuf, mut shutdown: broadcast::Receiver<()>) {
+    let mut directory = RuleDirectory::default();
+    directory.reload(&dir);
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+        Ok(event) if event.paths.iter().any(|path| is_rule_file(path)) && !event.kind.is_access() => {
+            let _ = sender.send(());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("ファイアウォールルールのディレクトリの監視でエラーが発生しました: {}", e),
+    });
+    let _watcher = match watcher.and_then(|mut watcher| watcher.watch(&dir, RecursiveMode::NonRecursive).map(|_| watcher)) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("{} を監視できません: {}", dir.display(), e);
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            Some(()) = receiver.recv() => {}
+            _ = shutdown.recv() => break,
+        }
+        // 保存が完了するまで待ち、その間の通知をまとめる
+        tokio::time::sleep(DEBOUNCE).await;
+        while receiver.try_recv().is_ok() {}
+        directory.reload(&dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firewall::{IpFirewall, Policy};
+
+    fn write(dir: &Path, name: &str, text: &str) {
+        std::fs::write(dir.join(name), text).unwrap();
+    }
+
+    #[test]
+    fn reloads_directory_keeping_last_good_rules() {
+        let dir = std::env::temp_dir().join(format!("rdb-tunnel-rules-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write(&dir, "a.yaml", "version: 1\noutput:\n  rules:\n    - filter: { type: port, value: 23 }\n      priority: 10\n");
+        write(&dir, "b.json", r#"{"version": 1, "input": {"rules": [{"filter": {"type": "port", "value": 22}, "priority": 5}]}}"#);
+        write(&dir, "notes.txt", "not a rule set");
+
+        let mut firewall = Firewall::new(IpFirewall::new(Policy::Blacklist), IpFirewall::new(Policy::Blacklist));
+        // 管理APIで追加したルール
+        firewall.chain_mut(Chain::Output).add_rule(Filter::Port(80), 1);
+        let mut directory = RuleDirectory::default();
+        assert!(directory.load(&dir).unwrap().is_empty());
+        assert_eq!(directory.apply(&mut firewall, directory.merged().unwrap()), 2);
+        assert_eq!(firewall.chain(Chain::Output).rules().len(), 2);
+
+        // 読み込めないファイルは前回のルールを維持する
+        write(&dir, "a.yaml", "version: 1\noutput:\n  rules:\n    - filter: { type: port, value: 24 }\n");
+        let errors = directory.load(&dir).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.ends_with("a.yaml"));
+        let merged = directory.merged().unwrap();
+        assert_eq!(merged[&Chain::Output][0].filter, Filter::Port(23));
+
+        // 複数のファイルで重複したフィルタは適用しない
+        write(&dir, "c.yaml", "version: 1\ninput:\n  rules:\n    - filter: { type: port, value: 22 }\n      priority: 7\n");
+        directory.load(&dir).unwrap();
+        assert!(directory.merged().unwrap_err().contains("重複"));
+
+        // 削除したファイルのルールは取り除き、管理APIで追加したルールは残す
+        std::fs::remove_file(dir.join("a.yaml")).unwrap();
+        std::fs::remove_file(dir.join("c.yaml")).unwrap();
+        directory.load(&dir).unwrap();
+        directory.apply(&mut firewall, directory.merged().unwrap());
+        let filters = firewall.chain(Chain::Output).rules().into_iter().map(|rule| rule.filter).collect::<Vec<_>>();
+        assert_eq!(filters, [Filter::Port(80)]);
+        assert_eq!(firewall.chain(Chain::Input).rules()[0].filter, Filter::Port(22));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Datelike, Local, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// ルールを有効にする曜日・時間帯・期間。曜日と時間帯はノードのローカル時刻で判定する。
+// 指定しない項目は制限しない (全て省略した場合は常に有効)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    // 例: ["mon", "tue", "wed", "thu", "fri"]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<Weekday>,
+    // 例: ["09:00-12:00", "13:00-18:00"]。開始より終了が前の場合は日をまたぐ (例: "22:00-06:00")
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub time_ranges: Vec<TimeRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<DateTime<Utc>>,
+    // この時刻を過ぎると無効になる (一時的な遮断など)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl Schedule {
+    pub fn is_active(&self, now: DateTime<Local>) -> bool {
+        let utc = now.with_timezone(&Utc);
+        if self.valid_from.is_some_and(|from| utc < from) || self.valid_until.is_some_and(|until| utc >= until) {
+            return false;
+        }
+
+        let time = now.time();
+        // 日をまたぐ時間帯の後半は、開始した日の曜日で判定する
+        let day_matches = |weekday: Weekday| self.days.is_empty() || self.days.contains(&weekday);
+        if self.time_ranges.is_empty() {
+            return day_matches(now.weekday());
+        }
+        self.time_ranges.iter().any(|range| match range.contains(time) {
+            Some(Started::Today) => day_matches(now.weekday()),
+            Some(Started::Yesterday) => day_matches(now.weekday().pred()),
+            None => false,
+        })
+    }
+
+    // 有効期間が終了しており、今後有効になることがない
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.valid_until.is_some_and(|until| now >= until)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(from), Some(until)) = (self.valid_from, self.valid_until) {
+            if from >= until {
+                return Err("valid_from は valid_until より前の時刻を指定してください".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// 時間帯のどちらの日に開始した範囲に含まれるか
+enum Started {
+    Today,
+    Yesterday,
+}
+
+// 1日の中の時間帯 ("HH:MM-HH:MM")。終了時刻は含まない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    fn contains(&self, time: NaiveTime) -> Option<Started> {
+        if self.start < self.end {
+            (self.start <= time && time < self.end).then_some(Started::Today)
+        } else if time >= self.start {
+            Some(Started::Today)
+        } else if time < self.end {
+            Some(Started::Yesterday)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|e| format!("時刻は HH:MM の形式で指定してください: {}: {}", time, e))
+        };
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("時間帯は HH:MM-HH:MM の形式で指定してください: {}", s))?;
+        let range = TimeRange { start: parse(start)?, end: parse(end)? };
+        if range.start == range.end {
+            return Err(format!("時間帯の開始と終了が同じです: {}", s));
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start.format("%H:%M"), self.end.format("%H:%M"))
+    }
+}
+
+impl Serialize for TimeRange {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeRange {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        // 2024-01-01 は月曜日
+        Local.with_ymd_and_hms(2024, 1, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn matches_business_hours_on_weekdays() {
+        let schedule = Schedule {
+            days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+            time_ranges: vec!["09:00-18:00".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(schedule.is_active(at(1, 9, 0)));
+        assert!(schedule.is_active(at(5, 17, 59)));
+        assert!(!schedule.is_active(at(1, 18, 0)));
+        assert!(!schedule.is_active(at(1, 8, 59)));
+        // 土曜日
+        assert!(!schedule.is_active(at(6, 12, 0)));
+    }
+
+    #[test]
+    fn matches_overnight_range_by_start_day() {
+        let schedule = Schedule {
+            days: vec![Weekday::Fri],
+            time_ranges: vec!["22:00-06:00".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(schedule.is_active(at(5, 23, 0)));
+        // 金曜日の夜から続く土曜日の早朝
+        assert!(schedule.is_active(at(6, 5, 0)));
+        assert!(!schedule.is_active(at(5, 5, 0)));
+        assert!(!schedule.is_active(at(6, 23, 0)));
+    }
+
+    #[test]
+    fn expires_after_validity_window() {
+        let now = at(1, 12, 0);
+        let schedule = Schedule {
+            valid_until: Some(now.with_timezone(&Utc) + chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert!(schedule.is_active(now));
+        assert!(!schedule.is_active(at(1, 13, 0)));
+        assert!(schedule.is_expired((now + chrono::Duration::hours(2)).with_timezone(&Utc)));
+        assert!("09:00-09:00".parse::<TimeRange>().is_err());
+        assert!("9時-18時".parse::<TimeRange>().is_err());
+    }
+}
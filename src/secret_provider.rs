@@ -0,0 +1,186 @@
+use crate::error::Retryable;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use thiserror::Error;
+use tracing::debug;
+
+#[derive(Error, Debug)]
+pub enum SecretError {
+    #[error("シークレットファイルの読み込みに失敗しました ({0}): {1}")]
+    File(PathBuf, std::io::Error),
+
+    #[error("Vaultへのリクエストに失敗しました: {0}")]
+    Vault(String),
+
+    #[error("シークレットが見つかりません: {0}")]
+    NotFound(String),
+}
+
+impl Retryable for SecretError {
+    fn is_retryable(&self) -> bool {
+        // Vaultへの接続は一時的に失敗しうるが、ファイルやキーがない場合は設定の誤り
+        matches!(self, SecretError::Vault(_))
+    }
+}
+
+// パスワードなどの機密情報を取得するためのプロバイダ
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // 見つからない場合はOk(None)を返し、次のプロバイダに処理を委ねる
+    async fn get_secret(&self, key: &str) -> Result<Option<String>, SecretError>;
+}
+
+// 環境変数 (.envを含む) から取得する
+pub struct EnvSecretProvider;
+
+#[async_trait]
+impl SecretProvider for EnvSecretProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    async fn get_secret(&self, key: &str) -> Result<Option<String>, SecretError> {
+        Ok(dotenv::var(key).ok())
+    }
+}
+
+// `<KEY>_FILE` 環境変数が指すファイルから取得する (Docker/Kubernetes secrets形式)
+pub struct FileSecretProvider;
+
+#[async_trait]
+impl SecretProvider for FileSecretProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn get_secret(&self, key: &str) -> Result<Option<String>, SecretError> {
+        let path = match dotenv::var(format!("{}_FILE", key)) {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => return Ok(None),
+        };
+
+        let content = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| SecretError::File(path.clone(), e))?;
+
+        // ファイル末尾の改行は値に含めない
+        Ok(Some(content.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+// HashiCorp VaultのKV v2シークレットエンジンから取得する
+pub struct VaultSecretProvider {
+    client: reqwest::Client,
+    address: String,
+    token: String,
+    secret_path: String,
+}
+
+impl VaultSecretProvider {
+    pub fn new(address: String, token: String, secret_path: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            address: address.trim_end_matches('/').to_string(),
+            token,
+            secret_path: secret_path.trim_matches('/').to_string(),
+        }
+    }
+
+    // VAULT_ADDR / VAULT_TOKEN (またはVAULT_TOKEN_FILE) / VAULT_SECRET_PATH から作成する
+    pub async fn from_env() -> Result<Option<Self>, SecretError> {
+        let Ok(address) = dotenv::var("VAULT_ADDR") else {
+            return Ok(None);
+        };
+
+        let token = FileSecretProvider
+            .get_secret("VAULT_TOKEN")
+            .await?
+            .or_else(|| dotenv::var("VAULT_TOKEN").ok())
+            .ok_or_else(|| SecretError::NotFound("VAULT_TOKEN".to_string()))?;
+        let secret_path = dotenv::var("VAULT_SECRET_PATH")
+            .map_err(|_| SecretError::NotFound("VAULT_SECRET_PATH".to_string()))?;
+
+        Ok(Some(Self::new(address, token, secret_path)))
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultSecretProvider {
+    fn name(&self) -> &'static str {
+        "vault"
+    }
+
+    async fn get_secret(&self, key: &str) -> Result<Option<String>, SecretError> {
+        let url = format!("{}/v1/{}", self.address, self.secret_path);
+        let response = self.client
+            .get(&url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .map_err(|e| SecretError::Vault(e.to_string()))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let body: serde_json::Value = response
+            .error_for_status()
+            .map_err(|e| SecretError::Vault(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| SecretError::Vault(e.to_string()))?;
+
+        // KV v2は data.data に、KV v1は data にキーが格納される
+        let data = body.pointer("/data/data").or_else(|| body.get("data"));
+        Ok(data
+            .and_then(|data| data.get(key))
+            .and_then(|value| value.as_str())
+            .map(str::to_string))
+    }
+}
+
+// 複数のプロバイダを優先順に問い合わせる
+pub struct SecretProviderChain {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl SecretProviderChain {
+    pub fn new(providers: Vec<Box<dyn SecretProvider>>) -> Self {
+        Self { providers }
+    }
+
+    // 優先順位: シークレットファイル > Vault > 環境変数
+    pub async fn from_env() -> Result<Self, SecretError> {
+        let mut providers: Vec<Box<dyn SecretProvider>> = vec![Box::new(FileSecretProvider)];
+        if let Some(vault) = VaultSecretProvider::from_env().await? {
+            providers.push(Box::new(vault));
+        }
+        providers.push(Box::new(EnvSecretProvider));
+        Ok(Self::new(providers))
+    }
+
+    pub async fn require(&self, key: &str) -> Result<String, SecretError> {
+        self.get_secret(key)
+            .await?
+            .ok_or_else(|| SecretError::NotFound(key.to_string()))
+    }
+}
+
+#[async_trait]
+impl SecretProvider for SecretProviderChain {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    async fn get_secret(&self, key: &str) -> Result<Option<String>, SecretError> {
+        for provider in &self.providers {
+            if let Some(value) = provider.get_secret(key).await? {
+                debug!("シークレット {} を {} から取得しました", key, provider.name());
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,232 @@
+use crate::config::ArpGuardConfig;
+use crate::database::types::MacAddr;
+use crate::firewall::{Chain, Filter, Firewall, Policy, Rule, FIREWALL};
+use crate::schedule::Schedule;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// ブリッジしたL2セグメントのARPからIPアドレスとMACアドレスの対応を記録し、
+// 対応が予期せず変わった場合やゲートウェイのアドレスを別のMACアドレスが名乗った場合にアラートを発行する (設定によりMACアドレスを遮断する)
+
+// 同じIPアドレスのアラートを繰り返さない時間
+const REALERT: Duration = Duration::from_secs(60);
+// 記録するIPアドレスの数の上限
+const MAX_BINDINGS: usize = 65536;
+
+lazy_static! {
+    static ref CONFIG: RwLock<ArpGuardConfig> = RwLock::new(ArpGuardConfig::default());
+    static ref GUARD: Mutex<Guard> = Mutex::new(Guard::default());
+}
+
+pub fn configure(config: &ArpGuardConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Arp {
+    operation: u16,
+    sender_mac: [u8; 6],
+    sender_ip: Ipv4Addr,
+    target_ip: Ipv4Addr,
+}
+
+impl Arp {
+    // 送信元と対象のIPアドレスが同じARP (自身のアドレスの告知)
+    fn is_gratuitous(&self) -> bool {
+        self.sender_ip == self.target_ip
+    }
+}
+
+// イーサネットフレームのIPv4のARP (イーサネットヘッダ14バイト + ARP 28バイト)
+fn parse(frame: &[u8]) -> Option<Arp> {
+    let arp = frame.get(14..42)?;
+    if frame[12..14] != [0x08, 0x06] || arp[0..6] != [0x00, 0x01, 0x08, 0x00, 6, 4] {
+        return None;
+    }
+    Some(Arp {
+        operation: u16::from_be_bytes([arp[6], arp[7]]),
+        sender_mac: arp[8..14].try_into().ok()?,
+        sender_ip: Ipv4Addr::new(arp[14], arp[15], arp[16], arp[17]),
+        target_ip: Ipv4Addr::new(arp[24], arp[25], arp[26], arp[27]),
+    })
+}
+
+#[derive(Debug, PartialEq)]
+struct Finding {
+    severity: Severity,
+    ip: Ipv4Addr,
+    mac: [u8; 6],
+    message: String,
+}
+
+#[derive(Debug)]
+struct Binding {
+    mac: [u8; 6],
+    last_seen: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Guard {
+    bindings: HashMap<Ipv4Addr, Binding>,
+    alerted: HashMap<Ipv4Addr, Instant>,
+}
+
+impl Guard {
+    fn observe(&mut self, config: &ArpGuardConfig, arp: &Arp, now: Instant) -> Option<Finding> {
+        // 0.0.0.0 を送信元とするARP (アドレスの重複の確認) は対応を持たない
+        if arp.sender_ip.is_unspecified() || !MacAddr(arp.sender_mac).is_unicast() {
+            return None;
+        }
+        let sender_mac = MacAddr(arp.sender_mac);
+        let finding = if let Some(gateway) = config.gateways.iter().find(|gateway| gateway.ip == arp.sender_ip) {
+            (gateway.mac.0 != arp.sender_mac).then(|| Finding {
+                severity: Severity::Critical,
+                ip: arp.sender_ip,
+                mac: arp.sender_mac,
+                message: format!(
+                    "{} がゲートウェイ {} ({}) を名乗る{}ARPを送信しました",
+                    sender_mac,
+                    arp.sender_ip,
+                    gateway.mac,
+                    if arp.is_gratuitous() { "Gratuitous " } else { "" }
+                ),
+            })
+        } else {
+            match self.bindings.get(&arp.sender_ip) {
+                Some(binding) if binding.mac != arp.sender_mac && now.duration_since(binding.last_seen) < config.binding_ttl => Some(Finding {
+                    severity: Severity::Warning,
+                    ip: arp.sender_ip,
+                    mac: arp.sender_mac,
+                    message: format!(
+                        "{} のMACアドレスが {} から {} に変わりました (ARPの操作 {}{})",
+                        arp.sender_ip,
+                        MacAddr(binding.mac),
+                        sender_mac,
+                        arp.operation,
+                        if arp.is_gratuitous() { ", Gratuitous" } else { "" }
+                    ),
+                }),
+                _ => None,
+            }
+        };
+
+        if finding.is_none() && (self.bindings.contains_key(&arp.sender_ip) || self.bindings.len() < MAX_BINDINGS) {
+            self.bindings.insert(arp.sender_ip, Binding { mac: arp.sender_mac, last_seen: now });
+        }
+        let finding = finding?;
+        if self.alerted.get(&finding.ip).is_some_and(|alerted| now.duration_since(*alerted) < REALERT) {
+            return None;
+        }
+        self.alerted.retain(|_, alerted| now.duration_since(*alerted) < REALERT);
+        self.alerted.insert(finding.ip, now);
+        Some(finding)
+    }
+}
+
+// なりすましたMACアドレスをブラックリストのチェインで一時的に遮断する
+fn block(firewall: &mut Firewall, config: &ArpGuardConfig, mac: [u8; 6], now: DateTime<Utc>) -> Vec<Chain> {
+    let filter = Filter::MacAddress(mac);
+    let until = now + chrono::Duration::from_std(config.block_duration).unwrap_or(chrono::Duration::max_value());
+    let mut chains = Vec::new();
+    for chain in [Chain::Input, Chain::Output] {
+        let rules = firewall.chain_mut(chain);
+        if rules.policy() != Policy::Blacklist || rules.contains(&filter) {
+            continue;
+        }
+        rules.insert(Rule {
+            filter: filter.clone(),
+            priority: config.priority,
+            schedule: Some(Schedule { valid_until: Some(until), ..Default::default() }),
+        });
+        chains.push(chain);
+    }
+    chains
+}
+
+// キャプチャしたARPを検査する
+pub fn inspect(frame: &[u8]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled {
+        return;
+    }
+    let Some(arp) = parse(frame) else {
+        return;
+    };
+    let Some(finding) = GUARD.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, &arp, Instant::now()) else {
+        return;
+    };
+    raise("arp_spoofing", finding.severity, finding.message, Some(IpAddr::V4(finding.ip)), None);
+    if config.block {
+        let chains = block(&mut FIREWALL.write().unwrap_or_else(|e| e.into_inner()), &config, finding.mac, Utc::now());
+        if !chains.is_empty() {
+            info!("{} を {}秒間遮断しました ({:?})", MacAddr(finding.mac), config.block_duration.as_secs(), chains);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ArpBinding;
+    use crate::firewall::IpFirewall;
+
+    const GATEWAY: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const HOST: [u8; 6] = [0x02, 0, 0, 0, 0, 0x10];
+    const ATTACKER: [u8; 6] = [0x02, 0, 0, 0, 0, 0x66];
+
+    fn arp(operation: u16, mac: [u8; 6], sender: &str, target: &str) -> Vec<u8> {
+        let mut frame = vec![0xff; 6];
+        frame.extend_from_slice(&mac);
+        frame.extend_from_slice(&[0x08, 0x06, 0x00, 0x01, 0x08, 0x00, 6, 4]);
+        frame.extend_from_slice(&operation.to_be_bytes());
+        frame.extend_from_slice(&mac);
+        frame.extend_from_slice(&sender.parse::<Ipv4Addr>().unwrap().octets());
+        frame.extend_from_slice(&[0; 6]);
+        frame.extend_from_slice(&target.parse::<Ipv4Addr>().unwrap().octets());
+        frame
+    }
+
+    fn config() -> ArpGuardConfig {
+        ArpGuardConfig {
+            enabled: true,
+            gateways: vec![ArpBinding { ip: "192.168.10.1".parse().unwrap(), mac: MacAddr(GATEWAY) }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn alerts_on_changed_bindings_and_gateway_claims() {
+        let config = config();
+        let mut guard = Guard::default();
+        let now = Instant::now();
+        let mut observe = |frame: Vec<u8>, secs: u64| guard.observe(&config, &parse(&frame).unwrap(), now + Duration::from_secs(secs));
+
+        assert!(observe(arp(1, GATEWAY, "192.168.10.1", "192.168.10.10"), 0).is_none());
+        assert!(observe(arp(2, HOST, "192.168.10.10", "192.168.10.1"), 0).is_none());
+        // 別のMACアドレスがホストのアドレスを名乗る
+        let finding = observe(arp(2, ATTACKER, "192.168.10.10", "192.168.10.10"), 1).unwrap();
+        assert_eq!((finding.severity, finding.mac), (Severity::Warning, ATTACKER));
+        // ゲートウェイのアドレスを名乗るGratuitous ARP
+        let finding = observe(arp(2, ATTACKER, "192.168.10.1", "192.168.10.1"), 2).unwrap();
+        assert_eq!(finding.severity, Severity::Critical);
+        assert!(observe(arp(2, ATTACKER, "192.168.10.1", "192.168.10.1"), 3).is_none());
+        // binding_ttl を過ぎた対応は変わってもよい
+        assert!(observe(arp(1, ATTACKER, "192.168.10.20", "192.168.10.1"), 4).is_none());
+        assert!(observe(arp(1, HOST, "192.168.10.20", "192.168.10.1"), 4 + config.binding_ttl.as_secs()).is_none());
+    }
+
+    #[test]
+    fn blocks_spoofing_mac_in_blacklist_chains() {
+        let mut firewall = Firewall::new(IpFirewall::new(Policy::Whitelist), IpFirewall::new(Policy::Blacklist));
+        let chains = block(&mut firewall, &config(), ATTACKER, Utc::now());
+        assert_eq!(chains, [Chain::Output]);
+        assert!(firewall.chain(Chain::Output).contains(&Filter::MacAddress(ATTACKER)));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::config::BeaconConfig;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// 同じ宛先へ一定の間隔で接続を繰り返す通信 (C2のビーコン) を検知する。
+// フローのテーブルは持たないため、キャプチャしたパケットから接続の開始 (TCPのSYN、UDPは一定時間通信が途絶えた後の最初のパケット) を記録し、
+// 接続の間隔のばらつき (変動係数) から周期性のスコア (1 - 変動係数) を求める
+
+// この時間通信が途絶えた後のUDPのパケットを新しい接続とみなす
+const UDP_IDLE: Duration = Duration::from_secs(5);
+// スコアの計算に使う接続の数の上限
+const MAX_SAMPLES: usize = 32;
+// 状態を保持する (送信元, 宛先, ポート) の数の上限
+const MAX_TRACKED: usize = 16384;
+
+lazy_static! {
+    static ref CONFIG: RwLock<BeaconConfig> = RwLock::new(BeaconConfig::default());
+    static ref DETECTOR: Mutex<Detector> = Mutex::new(Detector::default());
+}
+
+pub fn configure(config: &BeaconConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Key {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    port: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Periodicity {
+    // 接続の間隔の平均と標準偏差
+    mean: Duration,
+    jitter: Duration,
+    score: f64,
+    connections: usize,
+}
+
+// 接続の開始時刻の間隔から周期性を求める
+fn periodicity(starts: &VecDeque<Instant>) -> Option<Periodicity> {
+    let intervals = starts
+        .iter()
+        .zip(starts.iter().skip(1))
+        .map(|(previous, next)| next.duration_since(*previous).as_secs_f64())
+        .collect::<Vec<_>>();
+    if intervals.is_empty() {
+        return None;
+    }
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    if mean <= 0.0 {
+        return None;
+    }
+    let variance = intervals.iter().map(|interval| (interval - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+    let jitter = variance.sqrt();
+    Some(Periodicity {
+        mean: Duration::from_secs_f64(mean),
+        jitter: Duration::from_secs_f64(jitter),
+        score: (1.0 - jitter / mean).max(0.0),
+        connections: starts.len(),
+    })
+}
+
+#[derive(Debug, Default)]
+struct Flow {
+    starts: VecDeque<Instant>,
+    last_seen: Option<Instant>,
+    alerted: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Detector {
+    flows: HashMap<Key, Flow>,
+}
+
+impl Detector {
+    // パケットを記録し、周期的な接続と判定した場合はスコアを返す
+    fn observe(&mut self, config: &BeaconConfig, key: Key, connection_start: bool, now: Instant) -> Option<Periodicity> {
+        if !self.flows.contains_key(&key) && self.flows.len() >= MAX_TRACKED {
+            self.flows
+                .retain(|_, flow| flow.last_seen.is_some_and(|last_seen| now.duration_since(last_seen) < config.max_interval));
+            if self.flows.len() >= MAX_TRACKED {
+                return None;
+            }
+        }
+        let flow = self.flows.entry(key).or_default();
+        let idle = flow.last_seen.is_none_or(|last_seen| now.duration_since(last_seen) >= UDP_IDLE);
+        flow.last_seen = Some(now);
+        if !(connection_start || key.protocol == 17 && idle) {
+            return None;
+        }
+        // max_interval より間隔が空いた場合は数え直す
+        if flow.starts.back().is_some_and(|last| now.duration_since(*last) > config.max_interval) {
+            flow.starts.clear();
+        }
+        flow.starts.push_back(now);
+        if flow.starts.len() > MAX_SAMPLES {
+            flow.starts.pop_front();
+        }
+        if flow.starts.len() < config.min_connections || flow.alerted.is_some_and(|alerted| now.duration_since(alerted) < config.realert) {
+            return None;
+        }
+        let periodicity = periodicity(&flow.starts)?;
+        if periodicity.mean < config.min_interval || periodicity.score < config.score_threshold {
+            return None;
+        }
+        flow.alerted = Some(now);
+        Some(periodicity)
+    }
+}
+
+// TCP・UDPのパケットを記録する (connection_start はTCPのSYN)
+pub fn observe(src: IpAddr, dst: IpAddr, protocol: u8, port: u16, connection_start: bool) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled {
+        return;
+    }
+    let key = Key { src, dst, protocol, port };
+    let detected = DETECTOR.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, key, connection_start, Instant::now());
+    if let Some(periodicity) = detected {
+        raise(
+            "beaconing",
+            Severity::Warning,
+            format!(
+                "{} が {}:{} ({}) へ周期的に接続しています (スコア {:.2}, 間隔 {:.1}秒, ゆらぎ {:.1}秒, 接続 {}件)",
+                src,
+                dst,
+                port,
+                if protocol == 6 { "tcp" } else { "udp" },
+                periodicity.score,
+                periodicity.mean.as_secs_f64(),
+                periodicity.jitter.as_secs_f64(),
+                periodicity.connections
+            ),
+            Some(src),
+            Some(dst),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(protocol: u8) -> Key {
+        Key { src: "192.168.10.5".parse().unwrap(), dst: "198.51.100.20".parse().unwrap(), protocol, port: 443 }
+    }
+
+    #[test]
+    fn detects_regular_connections_with_score() {
+        let config = BeaconConfig { enabled: true, min_connections: 6, ..Default::default() };
+        let mut detector = Detector::default();
+        let start = Instant::now();
+        // 60秒±1秒の間隔の接続。データのパケットは接続として数えない
+        let offsets = [0, 60, 121, 180, 241, 300];
+        let mut detected = None;
+        for offset in offsets {
+            let now = start + Duration::from_secs(offset);
+            assert!(detector.observe(&config, key(6), false, now).is_none());
+            detected = detector.observe(&config, key(6), true, now);
+        }
+        let periodicity = detected.unwrap();
+        assert!(periodicity.score > 0.95);
+        assert_eq!(periodicity.connections, 6);
+        // realert の間は再び発行しない
+        assert!(detector.observe(&config, key(6), true, start + Duration::from_secs(360)).is_none());
+
+        // 間隔が不規則な接続は検知しない
+        let mut detector = Detector::default();
+        for offset in [0, 15, 200, 230, 600, 610, 1200] {
+            assert!(detector.observe(&config, key(6), true, start + Duration::from_secs(offset)).is_none());
+        }
+    }
+
+    #[test]
+    fn counts_udp_packets_after_idle_gap_as_connections() {
+        let config = BeaconConfig { enabled: true, min_connections: 4, ..Default::default() };
+        let mut detector = Detector::default();
+        let start = Instant::now();
+        let mut detected = None;
+        for burst in 0..4 {
+            for packet in 0..3 {
+                let now = start + Duration::from_secs(burst * 30) + Duration::from_millis(packet * 100);
+                detected = detected.or(detector.observe(&config, key(17), false, now));
+            }
+        }
+        assert_eq!(detected.unwrap().mean, Duration::from_secs(30));
+    }
+}
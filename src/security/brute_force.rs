@@ -0,0 +1,149 @@
+use crate::bans::{self, Detector};
+use crate::config::BruteForceConfig;
+use crate::packet_header::TcpHeader;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tracing::info;
+
+// SSH・FTPなどへの短時間で終わる接続の繰り返し (認証の総当たり) を検知し、攻撃元を自動遮断 ([bans]) に通知する。
+// TCPストリームの追跡 (host_ids) は無いため、キャプチャしたパケットのSYNとFIN・RSTから接続の開始と終了を判定する
+
+// 追跡する接続・送信元の数の上限
+const MAX_TRACKED: usize = 65536;
+
+lazy_static! {
+    static ref CONFIG: RwLock<BruteForceConfig> = RwLock::new(BruteForceConfig::default());
+    static ref TRACKER: Mutex<Tracker> = Mutex::new(Tracker::default());
+}
+
+pub fn configure(config: &BruteForceConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+// クライアント側から見た接続 (送信元, 送信元ポート, 宛先, 宛先ポート)
+type Connection = (IpAddr, u16, IpAddr, u16);
+
+#[derive(Debug, PartialEq)]
+struct Finding {
+    src: IpAddr,
+    dst: IpAddr,
+    port: u16,
+    attempts: usize,
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    // 開始した接続と開始時刻
+    open: HashMap<Connection, Instant>,
+    // (送信元, 宛先ポート) ごとの短時間で終わった接続の終了時刻
+    attempts: HashMap<(IpAddr, u16), VecDeque<Instant>>,
+}
+
+impl Tracker {
+    fn observe(&mut self, config: &BruteForceConfig, src: (IpAddr, u16), dst: (IpAddr, u16), flags: u8, now: Instant) -> Option<Finding> {
+        if flags & (TcpHeader::SYN | TcpHeader::ACK) == TcpHeader::SYN && config.ports.contains(&dst.1) {
+            if self.open.len() >= MAX_TRACKED {
+                self.open.retain(|_, started| now.duration_since(*started) < config.max_duration);
+            }
+            if self.open.len() < MAX_TRACKED {
+                self.open.insert((src.0, src.1, dst.0, dst.1), now);
+            }
+            return None;
+        }
+        if flags & (TcpHeader::FIN | TcpHeader::RST) == 0 {
+            return None;
+        }
+        // 終了はクライアント・サーバーのどちらから送信される場合もある
+        let connection = [(src.0, src.1, dst.0, dst.1), (dst.0, dst.1, src.0, src.1)]
+            .into_iter()
+            .find(|connection| self.open.contains_key(connection))?;
+        let started = self.open.remove(&connection)?;
+        if now.duration_since(started) > config.max_duration {
+            return None;
+        }
+        let (client, _, server, port) = connection;
+        if !self.attempts.contains_key(&(client, port)) && self.attempts.len() >= MAX_TRACKED {
+            self.attempts.retain(|_, ends| ends.back().is_some_and(|end| now.duration_since(*end) < config.window));
+        }
+        let ends = self.attempts.entry((client, port)).or_default();
+        ends.push_back(now);
+        while ends.front().is_some_and(|end| now.duration_since(*end) >= config.window) {
+            ends.pop_front();
+        }
+        if ends.len() <= config.max_attempts {
+            return None;
+        }
+        // 次の検知は新たに max_attempts を超えた場合とする
+        let attempts = ends.len();
+        ends.clear();
+        Some(Finding { src: client, dst: server, port, attempts })
+    }
+}
+
+// TCPのパケットを記録する
+pub fn observe(src: (IpAddr, u16), dst: (IpAddr, u16), flags: u8) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled {
+        return;
+    }
+    let finding = TRACKER.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, src, dst, flags, Instant::now());
+    let Some(finding) = finding else {
+        return;
+    };
+    raise(
+        "brute_force",
+        Severity::Warning,
+        format!(
+            "{} から {}:{} への短時間の接続が {}秒間に{}件ありました (総当たりの疑い)",
+            finding.src,
+            finding.dst,
+            finding.port,
+            config.window.as_secs(),
+            finding.attempts
+        ),
+        Some(finding.src),
+        Some(finding.dst),
+    );
+    if let Some(ban) = bans::report(finding.src, Detector::BruteForce) {
+        info!("総当たりの攻撃元 {} を遮断しました (〜{})", ban.ip, ban.until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn counts_short_lived_connections_to_watched_ports() {
+        let config = BruteForceConfig { enabled: true, max_attempts: 3, ..Default::default() };
+        let mut tracker = Tracker::default();
+        let attacker: IpAddr = "203.0.113.7".parse().unwrap();
+        let server: IpAddr = "192.168.10.20".parse().unwrap();
+        let now = Instant::now();
+        let mut findings = Vec::new();
+        for (i, port) in (40000u16..40005).enumerate() {
+            let start = now + Duration::from_secs(i as u64);
+            assert!(tracker.observe(&config, (attacker, port), (server, 22), TcpHeader::SYN, start).is_none());
+            assert!(tracker.observe(&config, (attacker, port), (server, 22), TcpHeader::ACK | TcpHeader::PSH, start).is_none());
+            // サーバーが切断する
+            let end = start + Duration::from_secs(2);
+            findings.extend(tracker.observe(&config, (server, 22), (attacker, port), TcpHeader::FIN | TcpHeader::ACK, end));
+        }
+        assert_eq!(findings, [Finding { src: attacker, dst: server, port: 22, attempts: 4 }]);
+
+        // 長く続いた接続と対象外のポートは数えない
+        let mut tracker = Tracker::default();
+        for port in 40000u16..40010 {
+            tracker.observe(&config, (attacker, port), (server, 22), TcpHeader::SYN, now);
+            assert!(tracker.observe(&config, (attacker, port), (server, 22), TcpHeader::RST, now + config.max_duration * 2).is_none());
+            tracker.observe(&config, (attacker, port), (server, 80), TcpHeader::SYN, now);
+            assert!(tracker.observe(&config, (attacker, port), (server, 80), TcpHeader::RST, now).is_none());
+        }
+    }
+}
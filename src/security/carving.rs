@@ -0,0 +1,397 @@
+use crate::config::FileCarvingConfig;
+use crate::security::reassembly::Stream;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// 再構成したHTTP・FTPのストリームから転送されたファイルを取り出し、マジックナンバー (無い場合はContent-Type) で種類を判定して、
+// SHA-256とメタデータを directory の files.jsonl に記録する (store_files が有効な場合はファイル自体も <SHA-256> に保存する)。
+// FTPは制御接続 (PORT・EPRT・227・229) からデータ接続のアドレスを、RETR・STOR からファイル名を学習する
+
+// FTPのデータ接続を待つ時間
+const FTP_DATA_TIMEOUT: Duration = Duration::from_secs(120);
+// FTPの学習したデータ接続・ファイル名の数の上限
+const MAX_FTP_ENTRIES: usize = 4096;
+// メタデータを記録するファイル
+const INDEX_FILE: &str = "files.jsonl";
+
+// 先頭のバイト列と種類
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"MZ", "application/x-dosexec"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF8", "image/gif"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage"),
+    (b"%!PS", "application/postscript"),
+];
+
+lazy_static! {
+    static ref CONFIG: RwLock<FileCarvingConfig> = RwLock::new(FileCarvingConfig::default());
+    static ref FTP: Mutex<FtpState> = Mutex::new(FtpState::default());
+}
+
+pub fn configure(config: &FileCarvingConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+// ストリームから取り出したファイル
+#[derive(Debug, PartialEq, Eq)]
+struct Carved<'a> {
+    name: Option<String>,
+    content_type: Option<String>,
+    data: Cow<'a, [u8]>,
+}
+
+// files.jsonl に記録するメタデータ
+#[derive(Debug, Serialize)]
+struct FileRecord {
+    timestamp: DateTime<Utc>,
+    protocol: &'static str,
+    src_ip: IpAddr,
+    src_port: u16,
+    dst_ip: IpAddr,
+    dst_port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    mime: String,
+    size: usize,
+    sha256: String,
+    // ストリームを max_stream_size で打ち切ったため、ファイルが途中までの可能性がある場合はtrue
+    truncated: bool,
+    // 保存したファイル (directory からの相対パス)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stored: Option<String>,
+}
+
+// マジックナンバーで判定し、判定できない場合はContent-Typeを使用する
+fn detect_mime(data: &[u8], content_type: Option<&str>) -> String {
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, mime)| mime.to_string())
+        .or_else(|| content_type.map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase()))
+        .filter(|mime| !mime.is_empty())
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// チャンク形式の本文を復元する (復元した本文と消費したバイト数)
+fn dechunk(data: &[u8]) -> (Vec<u8>, usize) {
+    let mut body = Vec::new();
+    let mut position = 0;
+    while let Some(line_end) = data[position..].windows(2).position(|window| window == b"\r\n") {
+        let line = String::from_utf8_lossy(&data[position..position + line_end]);
+        let Ok(size) = usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16) else {
+            break;
+        };
+        position += line_end + 2;
+        if size == 0 {
+            // トレーラーと空行
+            match data[position..].windows(2).position(|window| window == b"\r\n") {
+                Some(end) => position += end + 2,
+                None => position = data.len(),
+            }
+            break;
+        }
+        let end = (position + size).min(data.len());
+        body.extend_from_slice(&data[position..end]);
+        position = (end + 2).min(data.len());
+    }
+    (body, position)
+}
+
+// Content-Disposition の filename
+fn disposition_name(value: &str) -> Option<String> {
+    value
+        .split(';')
+        .filter_map(|part| part.trim().strip_prefix("filename="))
+        .map(|name| name.trim_matches('"').to_string())
+        .find(|name| !name.is_empty())
+}
+
+// HTTPのストリーム (片方向) に含まれるメッセージの本文を全て取り出す
+fn http_files(data: &[u8]) -> Vec<Carved<'_>> {
+    let mut files = Vec::new();
+    let mut rest = data;
+    while let Some(end) = rest.windows(4).position(|window| window == b"\r\n\r\n").map(|position| position + 4) {
+        let Ok(head) = std::str::from_utf8(&rest[..end]) else {
+            break;
+        };
+        let mut lines = head.split("\r\n");
+        let start = lines.next().unwrap_or("");
+        let (status, uri) = if let Some(status) = start.strip_prefix("HTTP/") {
+            (status.split_whitespace().nth(1).and_then(|code| code.parse::<u16>().ok()).or(Some(0)), None)
+        } else if start.ends_with(" HTTP/1.1") || start.ends_with(" HTTP/1.0") {
+            (None, start.split_whitespace().nth(1))
+        } else {
+            break;
+        };
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim()))
+            .collect::<HashMap<_, _>>();
+        let body = &rest[end..];
+        let chunked = headers.get("transfer-encoding").is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+        let length = headers.get("content-length").and_then(|value| value.parse::<usize>().ok());
+        let (content, consumed): (Cow<[u8]>, usize) = match status {
+            // 本文の無い応答
+            Some(code) if code < 200 || code == 204 || code == 304 => (Cow::Borrowed(&[]), 0),
+            _ if chunked => {
+                let (content, consumed) = dechunk(body);
+                (Cow::Owned(content), consumed)
+            }
+            _ if length.is_some() => {
+                let length = length.unwrap_or(0).min(body.len());
+                (Cow::Borrowed(&body[..length]), length)
+            }
+            // 長さの無い応答は接続の終了まで
+            Some(_) => (Cow::Borrowed(body), body.len()),
+            None => (Cow::Borrowed(&[]), 0),
+        };
+        if !content.is_empty() {
+            let name = headers
+                .get("content-disposition")
+                .and_then(|value| disposition_name(value))
+                .or_else(|| uri.and_then(|uri| uri.split('?').next()?.rsplit('/').next()).filter(|name| !name.is_empty()).map(str::to_string));
+            files.push(Carved { name, content_type: headers.get("content-type").map(|value| value.to_string()), data: content });
+        }
+        rest = &body[consumed..];
+    }
+    files
+}
+
+// FTPのアドレスの表記 (h1,h2,h3,h4,p1,p2)
+fn ftp_address(value: &str) -> Option<(IpAddr, u16)> {
+    let numbers = value
+        .trim_matches(|c: char| !c.is_ascii_digit())
+        .split(',')
+        .map(|number| number.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [a, b, c, d, high, low] = numbers[..] else {
+        return None;
+    };
+    Some((IpAddr::from([a, b, c, d]), u16::from_be_bytes([high, low])))
+}
+
+#[derive(Debug, Default)]
+struct FtpState {
+    // データ接続の待ち受けのアドレス -> (制御接続のクライアントとサーバー, 期限)
+    data: HashMap<(IpAddr, u16), ((IpAddr, IpAddr), Instant)>,
+    // 制御接続 (クライアント, サーバー) で最後に RETR・STOR したファイル名
+    names: HashMap<(IpAddr, IpAddr), String>,
+}
+
+impl FtpState {
+    fn expect(&mut self, endpoint: (IpAddr, u16), control: (IpAddr, IpAddr), now: Instant) {
+        if self.data.len() >= MAX_FTP_ENTRIES {
+            self.data.retain(|_, (_, until)| *until > now);
+            if self.data.len() >= MAX_FTP_ENTRIES {
+                return;
+            }
+        }
+        self.data.insert(endpoint, (control, now + FTP_DATA_TIMEOUT));
+    }
+
+    // 制御接続の1行を解析する (client はFTPのクライアント、server はサーバー)
+    fn control(&mut self, line: &str, from_client: bool, client: IpAddr, server: IpAddr, now: Instant) {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let control = (client, server);
+        match (from_client, command.to_ascii_uppercase().as_str()) {
+            (true, "PORT") => {
+                if let Some(endpoint) = ftp_address(argument) {
+                    self.expect(endpoint, control, now);
+                }
+            }
+            // EPRT |1|192.0.2.1|6446|
+            (true, "EPRT") => {
+                let fields = argument.split('|').collect::<Vec<_>>();
+                if let (Some(Ok(ip)), Some(Ok(port))) = (fields.get(2).map(|ip| ip.parse()), fields.get(3).map(|port| port.parse())) {
+                    self.expect((ip, port), control, now);
+                }
+            }
+            (true, "RETR" | "STOR" | "STOU" | "APPE") if !argument.is_empty() => {
+                if self.names.len() >= MAX_FTP_ENTRIES {
+                    self.names.clear();
+                }
+                self.names.insert(control, argument.trim().to_string());
+            }
+            // 227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)
+            (false, "227") => {
+                if let Some(endpoint) = argument.split_once('(').and_then(|(_, address)| ftp_address(address.split(')').next()?)) {
+                    self.expect(endpoint, control, now);
+                }
+            }
+            // 229 Entering Extended Passive Mode (|||port|)
+            (false, "229") => {
+                let port = argument.split('|').filter_map(|field| field.parse::<u16>().ok()).next();
+                if let Some(port) = port {
+                    self.expect((server, port), control, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // ストリームがデータ接続であれば、ファイル名 (不明な場合はNone) を返す
+    fn data_stream(&self, stream: &Stream, now: Instant) -> Option<Option<String>> {
+        let (control, _) = [stream.src, stream.dst]
+            .iter()
+            .find_map(|endpoint| self.data.get(endpoint).filter(|(_, until)| *until > now))?;
+        Some(self.names.get(control).cloned())
+    }
+}
+
+// FTPの制御接続のセグメントを解析する
+pub fn inspect_control(src: (IpAddr, u16), dst: (IpAddr, u16), payload: &[u8]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || payload.is_empty() {
+        return;
+    }
+    let from_client = config.ftp_ports.contains(&dst.1);
+    if !from_client && !config.ftp_ports.contains(&src.1) {
+        return;
+    }
+    let (client, server) = if from_client { (src.0, dst.0) } else { (dst.0, src.0) };
+    let now = Instant::now();
+    let mut ftp = FTP.lock().unwrap_or_else(|e| e.into_inner());
+    for line in String::from_utf8_lossy(payload).lines() {
+        ftp.control(line.trim_end(), from_client, client, server, now);
+    }
+}
+
+fn store(directory: &Path, record: &FileRecord, data: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    if let Some(stored) = &record.stored {
+        let path = directory.join(stored);
+        if !path.exists() {
+            fs::write(path, data)?;
+        }
+    }
+    let mut index = OpenOptions::new().create(true).append(true).open(directory.join(INDEX_FILE))?;
+    writeln!(index, "{}", serde_json::to_string(record)?)
+}
+
+// 終了したストリームからファイルを取り出して記録する
+pub fn extract(stream: &Arc<Stream>) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if !config.enabled {
+        return;
+    }
+    let is_http = config.http_ports.contains(&stream.src.1) || config.http_ports.contains(&stream.dst.1);
+    let ftp_name = if is_http {
+        None
+    } else {
+        match FTP.lock().unwrap_or_else(|e| e.into_inner()).data_stream(stream, Instant::now()) {
+            Some(name) => Some(name),
+            None => return,
+        }
+    };
+    let stream = stream.clone();
+    let task = move || {
+        let (protocol, files) = match ftp_name {
+            Some(name) => ("ftp", vec![Carved { name, content_type: None, data: Cow::Borrowed(&stream.data[..]) }]),
+            None => ("http", http_files(&stream.data)),
+        };
+        for file in files.into_iter().filter(|file| file.data.len() >= config.min_size) {
+            let mime = detect_mime(&file.data, file.content_type.as_deref());
+            if !config.mime_types.is_empty() && !config.mime_types.contains(&mime) {
+                continue;
+            }
+            let sha256 = sha256(&file.data);
+            let record = FileRecord {
+                timestamp: Utc::now(),
+                protocol,
+                src_ip: stream.src.0,
+                src_port: stream.src.1,
+                dst_ip: stream.dst.0,
+                dst_port: stream.dst.1,
+                name: file.name,
+                mime,
+                size: file.data.len(),
+                stored: config.store_files.then(|| sha256.clone()),
+                sha256,
+                truncated: stream.truncated,
+            };
+            info!(
+                "ファイルを抽出しました ({}): {}:{} -> {}:{} {} {} {}バイト sha256={}",
+                protocol,
+                record.src_ip,
+                record.src_port,
+                record.dst_ip,
+                record.dst_port,
+                record.name.as_deref().unwrap_or("-"),
+                record.mime,
+                record.size,
+                record.sha256
+            );
+            if let Err(e) = store(&config.directory, &record, &file.data) {
+                warn!("抽出したファイルを保存できません: {}: {}", config.directory.display(), e);
+            }
+        }
+    };
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn_blocking(task)),
+        Err(_) => task(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carves_http_bodies_by_length_and_chunks() {
+        let mut stream = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\nabc".to_vec();
+        stream.extend_from_slice(b"HTTP/1.1 304 Not Modified\r\n\r\n");
+        stream.extend_from_slice(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n");
+        stream.extend_from_slice(b"Content-Disposition: attachment; filename=\"report.pdf\"\r\n\r\n");
+        stream.extend_from_slice(b"5\r\n%PDF-\r\n4;ext=1\r\n1.7\n\r\n0\r\n\r\n");
+        let files = http_files(&stream);
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].data.as_ref(), detect_mime(&files[0].data, files[0].content_type.as_deref())), (&b"abc"[..], "text/plain".to_string()));
+        assert_eq!(sha256(&files[0].data), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!((files[1].name.as_deref(), files[1].data.as_ref()), (Some("report.pdf"), &b"%PDF-1.7\n"[..]));
+        assert_eq!(detect_mime(&files[1].data, None), "application/pdf");
+
+        // アップロード (PUT) はURIの最後の要素をファイル名とする
+        let upload = b"PUT /upload/tool.exe?x=1 HTTP/1.1\r\nContent-Length: 4\r\n\r\nMZ\x90\x00";
+        assert_eq!(http_files(upload)[0].name.as_deref(), Some("tool.exe"));
+        assert!(http_files(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").is_empty());
+    }
+
+    #[test]
+    fn learns_ftp_data_connections_from_control_channel() {
+        let mut ftp = FtpState::default();
+        let now = Instant::now();
+        let client: IpAddr = "192.168.10.5".parse().unwrap();
+        let server: IpAddr = "198.51.100.21".parse().unwrap();
+        ftp.control("227 Entering Passive Mode (198,51,100,21,195,80).", false, client, server, now);
+        ftp.control("RETR firmware.bin", true, client, server, now);
+        ftp.control("229 Entering Extended Passive Mode (|||6446|)", false, client, server, now);
+
+        let stream = |port| Stream { src: (server, port), dst: (client, 40000), data: b"data".to_vec(), truncated: false };
+        assert_eq!(ftp.data_stream(&stream(50000), now), Some(Some("firmware.bin".to_string())));
+        assert_eq!(ftp.data_stream(&stream(6446), now), Some(Some("firmware.bin".to_string())));
+        assert_eq!(ftp.data_stream(&stream(50001), now), None);
+        assert_eq!(ftp.data_stream(&stream(50000), now + FTP_DATA_TIMEOUT), None);
+    }
+}
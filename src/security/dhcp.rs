@@ -0,0 +1,222 @@
+use crate::config::DhcpGuardConfig;
+use crate::database::types::MacAddr;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// トンネルを通るDHCPを検査し、ランダムなMACアドレスによるDISCOVERの大量送信 (アドレスの枯渇攻撃) と
+// 許可していないDHCPサーバーからのOFFER・ACK (不正なDHCPサーバー) を検知する
+
+// DHCPのメッセージの種類 (オプション53)
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_ACK: u8 = 5;
+// 同じDHCPサーバーのアラートを繰り返さない時間
+const REALERT: Duration = Duration::from_secs(300);
+
+lazy_static! {
+    static ref CONFIG: RwLock<DhcpGuardConfig> = RwLock::new(DhcpGuardConfig::default());
+    static ref GUARD: Mutex<Guard> = Mutex::new(Guard::default());
+}
+
+pub fn configure(config: &DhcpGuardConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Dhcp {
+    message_type: u8,
+    client_mac: [u8; 6],
+    your_ip: Ipv4Addr,
+    // オプション54 (無い場合は送信元のIPアドレスを使う)
+    server_id: Option<Ipv4Addr>,
+}
+
+// BOOTPの固定長の部分 (236バイト) とマジッククッキーに続くオプションを解析する
+fn parse(payload: &[u8]) -> Option<Dhcp> {
+    if payload.len() < 240 || payload[1] != 1 || payload[2] != 6 || payload[236..240] != [99, 130, 83, 99] {
+        return None;
+    }
+    let mut message_type = None;
+    let mut server_id = None;
+    let mut offset = 240;
+    while let Some(&code) = payload.get(offset) {
+        match code {
+            // Pad
+            0 => {
+                offset += 1;
+                continue;
+            }
+            // End
+            255 => break,
+            _ => {}
+        }
+        let length = *payload.get(offset + 1)? as usize;
+        let value = payload.get(offset + 2..offset + 2 + length)?;
+        match (code, value) {
+            (53, [kind]) => message_type = Some(*kind),
+            (54, [a, b, c, d]) => server_id = Some(Ipv4Addr::new(*a, *b, *c, *d)),
+            _ => {}
+        }
+        offset += 2 + length;
+    }
+    Some(Dhcp {
+        message_type: message_type?,
+        client_mac: payload[28..34].try_into().ok()?,
+        your_ip: Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]),
+        server_id,
+    })
+}
+
+#[derive(Debug, PartialEq)]
+struct Finding {
+    kind: &'static str,
+    severity: Severity,
+    ip: Option<IpAddr>,
+    message: String,
+}
+
+#[derive(Debug, Default)]
+struct Guard {
+    window_start: Option<Instant>,
+    // window の間にDISCOVERを送信したクライアントのMACアドレス
+    clients: HashSet<[u8; 6]>,
+    starvation_alerted: bool,
+    servers_alerted: HashMap<IpAddr, Instant>,
+}
+
+impl Guard {
+    fn observe(&mut self, config: &DhcpGuardConfig, dhcp: &Dhcp, src_ip: IpAddr, src_mac: [u8; 6], now: Instant) -> Option<Finding> {
+        match dhcp.message_type {
+            DHCP_DISCOVER => {
+                if self.window_start.is_none_or(|start| now.duration_since(start) >= config.window) {
+                    self.window_start = Some(now);
+                    self.clients.clear();
+                    self.starvation_alerted = false;
+                }
+                // 上限を超えた後は数え続ける必要がない
+                if self.clients.len() <= config.max_discover_clients {
+                    self.clients.insert(dhcp.client_mac);
+                }
+                if self.clients.len() <= config.max_discover_clients || std::mem::replace(&mut self.starvation_alerted, true) {
+                    return None;
+                }
+                Some(Finding {
+                    kind: "dhcp_starvation",
+                    severity: Severity::Warning,
+                    ip: None,
+                    message: format!(
+                        "{}秒間に {}件 を超えるMACアドレスからDHCP DISCOVERを受信しました (送信元 {}, クライアント {})",
+                        config.window.as_secs(),
+                        config.max_discover_clients,
+                        MacAddr(src_mac),
+                        MacAddr(dhcp.client_mac)
+                    ),
+                })
+            }
+            DHCP_OFFER | DHCP_ACK if !config.trusted_servers.is_empty() => {
+                let server = dhcp.server_id.map(IpAddr::V4).unwrap_or(src_ip);
+                if config.trusted_servers.contains(&server)
+                    || self.servers_alerted.get(&server).is_some_and(|alerted| now.duration_since(*alerted) < REALERT)
+                {
+                    return None;
+                }
+                self.servers_alerted.retain(|_, alerted| now.duration_since(*alerted) < REALERT);
+                self.servers_alerted.insert(server, now);
+                Some(Finding {
+                    kind: "rogue_dhcp_server",
+                    severity: Severity::Critical,
+                    ip: Some(server),
+                    message: format!(
+                        "許可していないDHCPサーバー {} ({}) が {} に {} を{}しました",
+                        server,
+                        MacAddr(src_mac),
+                        MacAddr(dhcp.client_mac),
+                        dhcp.your_ip,
+                        if dhcp.message_type == DHCP_OFFER { "提示" } else { "割り当て" }
+                    ),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// DHCPのパケット (UDPのポート67・68) を検査する
+pub fn inspect(src_ip: IpAddr, src_mac: [u8; 6], src_port: u16, dst_port: u16, payload: &[u8]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || !matches!((src_port, dst_port), (67 | 68, 67 | 68)) {
+        return;
+    }
+    let Some(dhcp) = parse(payload) else {
+        return;
+    };
+    let finding = GUARD.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, &dhcp, src_ip, src_mac, Instant::now());
+    if let Some(finding) = finding {
+        raise(finding.kind, finding.severity, finding.message, finding.ip, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+
+    fn dhcp(message_type: u8, client_mac: [u8; 6], server_id: Option<&str>) -> Vec<u8> {
+        let mut payload = vec![0; 240];
+        payload[0] = if message_type == DHCP_DISCOVER { 1 } else { 2 };
+        payload[1] = 1;
+        payload[2] = 6;
+        payload[16..20].copy_from_slice(&[192, 168, 10, 50]);
+        payload[28..34].copy_from_slice(&client_mac);
+        payload[236..240].copy_from_slice(&[99, 130, 83, 99]);
+        payload.extend_from_slice(&[53, 1, message_type]);
+        if let Some(server_id) = server_id {
+            payload.extend_from_slice(&[54, 4]);
+            payload.extend_from_slice(&server_id.parse::<Ipv4Addr>().unwrap().octets());
+        }
+        payload.push(255);
+        payload
+    }
+
+    #[test]
+    fn detects_discover_flood_once_per_window() {
+        let config = DhcpGuardConfig { enabled: true, max_discover_clients: 3, ..Default::default() };
+        let mut guard = Guard::default();
+        let now = Instant::now();
+        let unspecified = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        let mut kinds = Vec::new();
+        for i in 0..6u8 {
+            let discover = parse(&dhcp(DHCP_DISCOVER, [0x02, 0, 0, 0, 1, i], None)).unwrap();
+            kinds.extend(guard.observe(&config, &discover, unspecified, [0x02, 0, 0, 0, 1, i], now).map(|finding| finding.kind));
+        }
+        assert_eq!(kinds, ["dhcp_starvation"]);
+        // 同じクライアントの再送は数えない
+        let discover = parse(&dhcp(DHCP_DISCOVER, [0x02, 0, 0, 0, 2, 0], None)).unwrap();
+        for _ in 0..5 {
+            assert!(guard.observe(&config, &discover, unspecified, SERVER_MAC, now + config.window).is_none());
+        }
+    }
+
+    #[test]
+    fn detects_offers_from_untrusted_servers() {
+        let config = DhcpGuardConfig { enabled: true, trusted_servers: vec!["192.168.10.1".parse().unwrap()], ..Default::default() };
+        let mut guard = Guard::default();
+        let now = Instant::now();
+        let client = [0x02, 0, 0, 0, 0, 0x10];
+        let src: IpAddr = "192.168.10.1".parse().unwrap();
+
+        let trusted = parse(&dhcp(DHCP_OFFER, client, Some("192.168.10.1"))).unwrap();
+        assert!(guard.observe(&config, &trusted, src, SERVER_MAC, now).is_none());
+        // サーバー識別子が無い場合は送信元のアドレスで判定する
+        let rogue = parse(&dhcp(DHCP_ACK, client, None)).unwrap();
+        let finding = guard.observe(&config, &rogue, "192.168.10.66".parse().unwrap(), SERVER_MAC, now).unwrap();
+        assert_eq!((finding.kind, finding.ip), ("rogue_dhcp_server", Some("192.168.10.66".parse().unwrap())));
+        assert!(guard.observe(&config, &rogue, "192.168.10.66".parse().unwrap(), SERVER_MAC, now).is_none());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::config::DnsTunnelConfig;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+// DNSトンネリングの兆候 (長すぎるラベル、エントロピーの高いサブドメイン、クライアントごとのTXT/NULLクエリの多発) を検知する
+
+// クエリの種類
+const QTYPE_NULL: u16 = 10;
+const QTYPE_TXT: u16 = 16;
+// 状態を保持するクライアント数の上限 (これを超えた新しいクライアントのクエリ数は数えない)
+const MAX_TRACKED_CLIENTS: usize = 4096;
+// アラートに含めるドメイン名の長さの上限
+const MAX_NAME_IN_ALERT: usize = 100;
+
+lazy_static! {
+    static ref CONFIG: RwLock<DnsTunnelConfig> = RwLock::new(DnsTunnelConfig::default());
+    static ref DETECTOR: Mutex<Detector> = Mutex::new(Detector::default());
+}
+
+pub fn configure(config: &DnsTunnelConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Reason {
+    LongLabel,
+    HighEntropy,
+    ExcessiveTxt,
+}
+
+#[derive(Debug)]
+struct Finding {
+    reason: Reason,
+    message: String,
+}
+
+#[derive(Debug)]
+struct Query {
+    name: String,
+    qtype: u16,
+}
+
+// DNSメッセージの最初の質問を取り出す (応答と圧縮された名前は対象外)
+fn parse_query(payload: &[u8]) -> Option<Query> {
+    if payload.len() < 12 || payload[2] & 0x80 != 0 || u16::from_be_bytes([payload[4], payload[5]]) == 0 {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut offset = 12;
+    loop {
+        let length = *payload.get(offset)? as usize;
+        offset += 1;
+        if length == 0 {
+            break;
+        }
+        if length & 0xc0 != 0 {
+            return None;
+        }
+        let label = payload.get(offset..offset + length)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += length;
+    }
+    let qtype = u16::from_be_bytes([*payload.get(offset)?, *payload.get(offset + 1)?]);
+    Some(Query { name: labels.join("."), qtype })
+}
+
+// 1文字あたりのシャノンエントロピー (ビット)
+fn entropy(text: &str) -> f64 {
+    let mut counts = HashMap::new();
+    for byte in text.bytes() {
+        *counts.entry(byte.to_ascii_lowercase()).or_insert(0usize) += 1;
+    }
+    let length = text.len() as f64;
+    counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn truncated(name: &str) -> String {
+    if name.len() <= MAX_NAME_IN_ALERT {
+        return name.to_string();
+    }
+    let mut end = MAX_NAME_IN_ALERT;
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &name[..end])
+}
+
+#[derive(Debug)]
+struct Client {
+    window_start: Instant,
+    txt_queries: u32,
+    // 理由ごとの最後のアラートの時刻 (window の間は同じ理由のアラートを繰り返さない)
+    alerted: HashMap<Reason, Instant>,
+}
+
+#[derive(Debug, Default)]
+struct Detector {
+    clients: HashMap<IpAddr, Client>,
+}
+
+impl Detector {
+    fn inspect(&mut self, config: &DnsTunnelConfig, client: IpAddr, query: &Query, now: Instant) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let labels = query.name.split('.').collect::<Vec<_>>();
+        if let Some(label) = labels.iter().find(|label| label.len() > config.max_label_length) {
+            findings.push(Finding {
+                reason: Reason::LongLabel,
+                message: format!("{} のDNSクエリに長いラベル ({}文字) があります: {}", client, label.len(), truncated(&query.name)),
+            });
+        }
+        // 登録されたドメイン (末尾の2ラベル) を除いたサブドメインのエントロピー
+        let subdomain = labels[..labels.len().saturating_sub(2)].concat();
+        if subdomain.len() >= config.min_entropy_length {
+            let entropy = entropy(&subdomain);
+            if entropy >= config.entropy_threshold {
+                findings.push(Finding {
+                    reason: Reason::HighEntropy,
+                    message: format!(
+                        "{} のDNSクエリのサブドメインのエントロピーが高い値です ({:.2}ビット/文字): {}",
+                        client,
+                        entropy,
+                        truncated(&query.name)
+                    ),
+                });
+            }
+        }
+
+        if !self.clients.contains_key(&client) && self.clients.len() >= MAX_TRACKED_CLIENTS {
+            self.clients.retain(|_, state| now.duration_since(state.window_start) < config.window);
+        }
+        let tracked = self.clients.len() < MAX_TRACKED_CLIENTS || self.clients.contains_key(&client);
+        if !tracked {
+            return findings;
+        }
+        let state = self.clients.entry(client).or_insert_with(|| Client {
+            window_start: now,
+            txt_queries: 0,
+            alerted: HashMap::new(),
+        });
+        if now.duration_since(state.window_start) >= config.window {
+            state.window_start = now;
+            state.txt_queries = 0;
+        }
+        if matches!(query.qtype, QTYPE_TXT | QTYPE_NULL) {
+            state.txt_queries += 1;
+            if state.txt_queries == config.max_txt_queries + 1 {
+                findings.push(Finding {
+                    reason: Reason::ExcessiveTxt,
+                    message: format!(
+                        "{} のTXT/NULLのDNSクエリが多すぎます ({}秒間に{}件を超えました)",
+                        client,
+                        config.window.as_secs(),
+                        config.max_txt_queries
+                    ),
+                });
+            }
+        }
+        findings.retain(|finding| match state.alerted.get(&finding.reason) {
+            Some(&last) if now.duration_since(last) < config.window => false,
+            _ => {
+                state.alerted.insert(finding.reason, now);
+                true
+            }
+        });
+        findings
+    }
+}
+
+// クライアントが送信したDNSクエリ (UDPの宛先ポート53) を検査する
+pub fn inspect(client: IpAddr, server: IpAddr, dst_port: u16, payload: &[u8]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || dst_port != 53 {
+        return;
+    }
+    let Some(query) = parse_query(payload) else {
+        return;
+    };
+    let findings = DETECTOR.lock().unwrap_or_else(|e| e.into_inner()).inspect(&config, client, &query, Instant::now());
+    for finding in findings {
+        raise("dns_tunneling", Severity::Warning, finding.message, Some(client), Some(server));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TEST_WINDOW: Duration = Duration::from_secs(60);
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut payload = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        for label in name.split('.') {
+            payload.push(label.len() as u8);
+            payload.extend_from_slice(label.as_bytes());
+        }
+        payload.push(0);
+        payload.extend_from_slice(&qtype.to_be_bytes());
+        payload.extend_from_slice(&1u16.to_be_bytes());
+        payload
+    }
+
+    fn config() -> DnsTunnelConfig {
+        DnsTunnelConfig { enabled: true, max_txt_queries: 3, window: TEST_WINDOW, ..Default::default() }
+    }
+
+    #[test]
+    fn parses_queries_only() {
+        let parsed = parse_query(&query("www.example.com", 1)).unwrap();
+        assert_eq!((parsed.name.as_str(), parsed.qtype), ("www.example.com", 1));
+        let mut response = query("www.example.com", 1);
+        response[2] |= 0x80;
+        assert!(parse_query(&response).is_none());
+        assert!(parse_query(&query("www.example.com", 1)[..14]).is_none());
+    }
+
+    #[test]
+    fn flags_tunneling_patterns_once_per_window() {
+        let config = config();
+        let client: IpAddr = "192.168.10.5".parse().unwrap();
+        let mut detector = Detector::default();
+        let now = Instant::now();
+        let reasons = |findings: Vec<Finding>| findings.into_iter().map(|finding| finding.reason).collect::<Vec<_>>();
+
+        let normal = parse_query(&query("mail.google.com", 1)).unwrap();
+        assert!(detector.inspect(&config, client, &normal, now).is_empty());
+
+        let encoded = parse_query(&query("MZXW6YTBOI4DKNJWG4YTCMRTGQ2TMNZYHEYDC7RTGQ2TMNZYPL3XKVA5.q7.t.example.com", 1)).unwrap();
+        assert_eq!(reasons(detector.inspect(&config, client, &encoded, now)), [Reason::LongLabel, Reason::HighEntropy]);
+        // 同じ理由のアラートは window の間繰り返さない
+        assert!(detector.inspect(&config, client, &encoded, now).is_empty());
+        assert_eq!(detector.inspect(&config, client, &encoded, now + TEST_WINDOW).len(), 2);
+
+        let txt = parse_query(&query("a.example.com", QTYPE_TXT)).unwrap();
+        for _ in 0..3 {
+            assert!(detector.inspect(&config, client, &txt, now).is_empty());
+        }
+        assert_eq!(reasons(detector.inspect(&config, client, &txt, now)), [Reason::ExcessiveTxt]);
+    }
+}
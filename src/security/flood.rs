@@ -0,0 +1,128 @@
+use crate::bans::{self, Detector};
+use crate::config::FloodConfig;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tracing::info;
+
+// 送信元ごとのパケット数・バイト数を window ごとに数え、急増 (フラッド) を検知して攻撃元を自動遮断 ([bans]) に通知する
+
+// 追跡する送信元の数の上限
+const MAX_TRACKED: usize = 65536;
+
+lazy_static! {
+    static ref CONFIG: RwLock<FloodConfig> = RwLock::new(FloodConfig::default());
+    static ref COUNTER: Mutex<Counter> = Mutex::new(Counter::default());
+}
+
+pub fn configure(config: &FloodConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug)]
+struct Usage {
+    // window の開始時刻
+    since: Instant,
+    packets: u64,
+    bytes: u64,
+    // この window で検知済みの場合はtrue
+    reported: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct Finding {
+    src: IpAddr,
+    packets: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    sources: HashMap<IpAddr, Usage>,
+}
+
+impl Counter {
+    fn observe(&mut self, config: &FloodConfig, src: IpAddr, size: usize, now: Instant) -> Option<Finding> {
+        if !self.sources.contains_key(&src) && self.sources.len() >= MAX_TRACKED {
+            self.sources.retain(|_, usage| now.duration_since(usage.since) < config.window);
+            if self.sources.len() >= MAX_TRACKED {
+                return None;
+            }
+        }
+        let usage = self.sources.entry(src).or_insert(Usage { since: now, packets: 0, bytes: 0, reported: false });
+        if now.duration_since(usage.since) >= config.window {
+            *usage = Usage { since: now, packets: 0, bytes: 0, reported: false };
+        }
+        usage.packets += 1;
+        usage.bytes += size as u64;
+        let exceeded = (config.max_packets > 0 && usage.packets > config.max_packets)
+            || (config.max_bytes > 0 && usage.bytes > config.max_bytes);
+        if !exceeded || usage.reported {
+            return None;
+        }
+        // 同じ window では1回のみ検知する
+        usage.reported = true;
+        Some(Finding { src, packets: usage.packets, bytes: usage.bytes })
+    }
+}
+
+// 書き込むパケットを送信元ごとに数える
+pub fn observe(src: IpAddr, size: usize) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled {
+        return;
+    }
+    let finding = COUNTER.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, src, size, Instant::now());
+    let Some(finding) = finding else {
+        return;
+    };
+    raise(
+        "packet_flood",
+        Severity::Warning,
+        format!(
+            "{} からのパケットが {}ミリ秒間に{}件 ({}バイト) を超えました (フラッドの疑い)",
+            finding.src,
+            config.window.as_millis(),
+            finding.packets,
+            finding.bytes
+        ),
+        Some(finding.src),
+        None,
+    );
+    if let Some(ban) = bans::report(finding.src, Detector::Rate) {
+        info!("通信量が急増した送信元 {} を遮断しました (〜{})", ban.ip, ban.until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_each_source_once_per_window() {
+        let config = FloodConfig { enabled: true, max_packets: 3, max_bytes: 0, window: Duration::from_secs(1) };
+        let mut counter = Counter::default();
+        let attacker: IpAddr = "203.0.113.7".parse().unwrap();
+        let client: IpAddr = "192.168.10.5".parse().unwrap();
+        let now = Instant::now();
+        let findings = (0..6).filter_map(|_| counter.observe(&config, attacker, 100, now)).collect::<Vec<_>>();
+        assert_eq!(findings, [Finding { src: attacker, packets: 4, bytes: 400 }]);
+        assert!((0..3).all(|_| counter.observe(&config, client, 1500, now).is_none()));
+
+        // 次の window は新たに数える
+        let next = now + config.window;
+        assert!((0..3).all(|_| counter.observe(&config, attacker, 100, next).is_none()));
+        assert!(counter.observe(&config, attacker, 100, next).is_some());
+
+        let config = FloodConfig { max_packets: 0, max_bytes: 3000, ..config };
+        let mut counter = Counter::default();
+        assert!(counter.observe(&config, client, 1500, now).is_none());
+        assert!(counter.observe(&config, client, 1500, now).is_none());
+        assert_eq!(counter.observe(&config, client, 1, now), Some(Finding { src: client, packets: 3, bytes: 3001 }));
+    }
+}
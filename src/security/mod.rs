@@ -0,0 +1,136 @@
+use crate::config::SecurityConfig;
+use crate::db_write::{PacketData, Protocol};
+use crate::events::{self, Alert, PipelineEvent};
+use crate::metrics::IDPS_ALERTS;
+use crate::packet_header::TcpHeader;
+use chrono::Utc;
+use self::notify::Severity;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+// キャプチャしたパケットの異常を検知する
+pub mod arp;
+pub mod beacon;
+pub mod brute_force;
+pub mod carving;
+pub mod dhcp;
+pub mod dns;
+pub mod flood;
+pub mod modbus;
+pub mod reassembly;
+pub mod sip;
+pub mod smb;
+#[cfg(feature = "yara")]
+pub mod yara_scan;
+
+// 検知したアラートへの対応 (通知など)
+pub mod notify;
+pub mod siem;
+
+// 各検知の設定を反映する (SIGHUPで再読み込み可能)
+pub fn configure(config: &SecurityConfig) {
+    dns::configure(&config.dns);
+    beacon::configure(&config.beacon);
+    arp::configure(&config.arp);
+    dhcp::configure(&config.dhcp);
+    brute_force::configure(&config.brute_force);
+    flood::configure(&config.flood);
+    smb::configure(&config.smb);
+    modbus::configure(&config.modbus);
+    sip::configure(&config.sip);
+    #[cfg(feature = "yara")]
+    yara_scan::configure(&config.yara);
+    carving::configure(&config.carving);
+    let yara = cfg!(feature = "yara") && config.yara.enabled;
+    reassembly::configure(&config.reassembly, yara || config.carving.enabled);
+}
+
+// 書き込む前のパケットを各検知に渡す。プロトコルの規則で破棄する場合はfalseを返す
+pub fn inspect(packet: &PacketData) -> bool {
+    if packet.ether_type == Protocol::ARP {
+        arp::inspect(&packet.raw_packet);
+        return true;
+    }
+    let (src, dst) = (packet.src_ip.ip(), packet.dst_ip.ip());
+    flood::observe(src, packet.raw_packet.len());
+    if packet.ip_protocol == Protocol::UDP {
+        dns::inspect(src, dst, packet.dst_port as u16, &packet.data);
+        dhcp::inspect(src, packet.src_mac.0, packet.src_port as u16, packet.dst_port as u16, &packet.data);
+        sip::inspect(packet.src_port as u16, (dst, packet.dst_port as u16), &packet.data);
+        beacon::observe(src, dst, 17, packet.dst_port as u16, false);
+    } else if packet.ip_protocol == Protocol::TCP {
+        let flags = tcp_flags(&packet.raw_packet).unwrap_or(0);
+        let syn = flags & (TcpHeader::SYN | TcpHeader::ACK) == TcpHeader::SYN;
+        beacon::observe(src, dst, 6, packet.dst_port as u16, syn);
+        brute_force::observe((src, packet.src_port as u16), (dst, packet.dst_port as u16), flags);
+        smb::inspect(src, dst, packet.dst_port as u16, syn, &packet.data);
+        if let Some(seq) = tcp_seq(&packet.raw_packet) {
+            let (src_port, dst_port) = (packet.src_port as u16, packet.dst_port as u16);
+            carving::inspect_control((src, src_port), (dst, dst_port), &packet.data);
+            for stream in reassembly::observe((src, src_port), (dst, dst_port), seq, flags, &packet.data) {
+                inspect_stream(stream);
+            }
+        }
+        return modbus::inspect(src, dst, packet.dst_port as u16, &packet.data);
+    }
+    true
+}
+
+// データが届かなくなったTCPストリームを定期的に終了し、各検査に渡す
+pub async fn start_stream_sweeper(mut shutdown: broadcast::Receiver<()>) {
+    let mut interval = tokio::time::interval(reassembly::SWEEP_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.recv() => break,
+        }
+        for stream in reassembly::sweep() {
+            inspect_stream(stream);
+        }
+    }
+}
+
+// 再構成が終了したTCPストリームを各検査に渡す
+fn inspect_stream(stream: reassembly::Stream) {
+    let stream = Arc::new(stream);
+    #[cfg(feature = "yara")]
+    yara_scan::scan(&stream);
+    carving::extract(&stream);
+}
+
+// イーサネットフレームのTCPヘッダ (IPv6は拡張ヘッダが無い場合のみ)
+fn tcp_header(frame: &[u8]) -> Option<&[u8]> {
+    let offset = match u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]) {
+        0x0800 => 14 + (*frame.get(14)? & 0x0f) as usize * 4,
+        0x86dd => 54,
+        _ => return None,
+    };
+    frame.get(offset..)
+}
+
+// TCPヘッダのフラグ
+pub(crate) fn tcp_flags(frame: &[u8]) -> Option<u8> {
+    tcp_header(frame)?.get(13).copied()
+}
+
+// TCPヘッダのシーケンス番号
+fn tcp_seq(frame: &[u8]) -> Option<u32> {
+    let header = tcp_header(frame)?;
+    Some(u32::from_be_bytes(header.get(4..8)?.try_into().ok()?))
+}
+
+// 検知した異常をアラートとして発行する
+pub(crate) fn raise(kind: &str, severity: Severity, message: String, src_ip: Option<IpAddr>, dst_ip: Option<IpAddr>) {
+    IDPS_ALERTS.inc();
+    warn!("{}: {}", kind, message);
+    events::publish(PipelineEvent::Alert(Alert {
+        timestamp: Utc::now(),
+        kind: kind.to_string(),
+        severity: severity.as_str().to_string(),
+        message,
+        src_ip,
+        dst_ip,
+    }));
+}
@@ -0,0 +1,195 @@
+use crate::config::{ModbusAction, ModbusConfig, ModbusRule};
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+// Modbus/TCPの要求 (ユニットID・ファンクションコード・レジスタの範囲) を解析し、
+// 危険なファンクションコード (書き込み・診断など) のアラートと、規則による要求の破棄を行う
+
+// 同じ (送信元, 宛先, ファンクションコード) のアラートを繰り返さない時間
+const REALERT: Duration = Duration::from_secs(60);
+// アラートの抑止のために記録する数の上限
+const MAX_ALERTED: usize = 4096;
+
+lazy_static! {
+    static ref CONFIG: RwLock<ModbusConfig> = RwLock::new(ModbusConfig::default());
+    static ref ALERTED: Mutex<HashMap<(IpAddr, IpAddr, u8), Instant>> = Mutex::new(HashMap::new());
+}
+
+pub fn configure(config: &ModbusConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Request {
+    unit_id: u8,
+    function_code: u8,
+    // 対象のレジスタ・コイルの範囲 (先頭, 末尾)
+    registers: Option<(u16, u16)>,
+}
+
+fn range(data: &[u8], with_quantity: bool) -> Option<(u16, u16)> {
+    let start = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+    if !with_quantity {
+        return Some((start, start));
+    }
+    let quantity = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]);
+    Some((start, start.saturating_add(quantity.max(1) - 1)))
+}
+
+// セグメントに含まれるMBAPヘッダ (7バイト) 付きの要求を全て解析する
+fn parse(payload: &[u8]) -> Vec<Request> {
+    let mut requests = Vec::new();
+    let mut rest = payload;
+    while rest.len() >= 8 && rest[2..4] == [0, 0] {
+        // length はユニットIDからの長さ
+        let length = u16::from_be_bytes([rest[4], rest[5]]) as usize;
+        let Some(pdu) = rest.get(7..6 + length).filter(|_| length >= 2) else {
+            break;
+        };
+        let data = &pdu[1..];
+        let registers = match pdu[0] {
+            // 読み出し・複数の書き込み (先頭アドレスと数)
+            1..=4 | 15 | 16 | 23 => range(data, true),
+            // 1つの書き込み・マスク書き込み
+            5 | 6 | 22 => range(data, false),
+            _ => None,
+        };
+        requests.push(Request { unit_id: rest[6], function_code: pdu[0], registers });
+        rest = &rest[6 + length..];
+    }
+    requests
+}
+
+fn matches(rule: &ModbusRule, request: &Request) -> bool {
+    (rule.function_codes.is_empty() || rule.function_codes.contains(&request.function_code))
+        && (rule.unit_ids.is_empty() || rule.unit_ids.contains(&request.unit_id))
+        && match (rule.registers, request.registers) {
+            (None, _) => true,
+            (Some([start, end]), Some((first, last))) => first <= end && start <= last,
+            (Some(_), None) => false,
+        }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Allow,
+    Alert,
+    Drop,
+}
+
+// 最初に一致した規則、一致しない場合は alert_function_codes で判定する
+fn verdict(config: &ModbusConfig, request: &Request) -> Verdict {
+    match config.rules.iter().find(|rule| matches(rule, request)).map(|rule| rule.action) {
+        Some(ModbusAction::Allow) => Verdict::Allow,
+        Some(ModbusAction::Alert) => Verdict::Alert,
+        Some(ModbusAction::Drop) => Verdict::Drop,
+        None if config.alert_function_codes.contains(&request.function_code) => Verdict::Alert,
+        None => Verdict::Allow,
+    }
+}
+
+fn describe(request: &Request) -> String {
+    match request.registers {
+        Some((start, end)) if start == end => {
+            format!("ユニット {} ファンクションコード {} アドレス {}", request.unit_id, request.function_code, start)
+        }
+        Some((start, end)) => {
+            format!("ユニット {} ファンクションコード {} アドレス {}-{}", request.unit_id, request.function_code, start, end)
+        }
+        None => format!("ユニット {} ファンクションコード {}", request.unit_id, request.function_code),
+    }
+}
+
+// Modbus/TCPの要求を検査する。破棄する場合はfalseを返す
+pub fn inspect(src: IpAddr, dst: IpAddr, dst_port: u16, payload: &[u8]) -> bool {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || !config.ports.contains(&dst_port) {
+        return true;
+    }
+    let mut allowed = true;
+    for request in parse(payload) {
+        let verdict = verdict(&config, &request);
+        debug!("Modbus: {} -> {} {} ({:?})", src, dst, describe(&request), verdict);
+        if verdict == Verdict::Allow {
+            continue;
+        }
+        allowed &= verdict != Verdict::Drop;
+        let now = Instant::now();
+        let mut alerted = ALERTED.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (src, dst, request.function_code);
+        if alerted.get(&key).is_some_and(|last| now.duration_since(*last) < REALERT) {
+            continue;
+        }
+        if alerted.len() >= MAX_ALERTED {
+            alerted.retain(|_, last| now.duration_since(*last) < REALERT);
+        }
+        alerted.insert(key, now);
+        drop(alerted);
+        let (kind, action) = match verdict {
+            Verdict::Drop => ("modbus_blocked", "を破棄しました"),
+            _ => ("modbus_function", "を検知しました"),
+        };
+        raise(
+            kind,
+            Severity::Warning,
+            format!("{} から {} へのModbusの要求 ({}) {}", src, dst, describe(&request), action),
+            Some(src),
+            Some(dst),
+        );
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adu(unit_id: u8, pdu: &[u8]) -> Vec<u8> {
+        let mut adu = vec![0x00, 0x01, 0x00, 0x00];
+        adu.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        adu.push(unit_id);
+        adu.extend_from_slice(pdu);
+        adu
+    }
+
+    #[test]
+    fn parses_requests_in_segment() {
+        let mut payload = adu(1, &[3, 0x00, 0x10, 0x00, 0x04]);
+        payload.extend(adu(2, &[6, 0x00, 0x20, 0x12, 0x34]));
+        payload.extend(adu(3, &[8, 0x00, 0x01]));
+        assert_eq!(
+            parse(&payload),
+            [
+                Request { unit_id: 1, function_code: 3, registers: Some((16, 19)) },
+                Request { unit_id: 2, function_code: 6, registers: Some((32, 32)) },
+                Request { unit_id: 3, function_code: 8, registers: None },
+            ]
+        );
+        // 途中で切れた要求とMBAP以外は解析しない
+        assert!(parse(&payload[..5]).is_empty());
+        assert!(parse(b"GET / HTTP/1.1\r\n").is_empty());
+    }
+
+    #[test]
+    fn applies_first_matching_rule_before_dangerous_codes() {
+        let config = ModbusConfig {
+            enabled: true,
+            rules: vec![
+                ModbusRule { function_codes: vec![6], unit_ids: vec![2], registers: Some([0, 99]), action: ModbusAction::Drop },
+                ModbusRule { function_codes: vec![6], unit_ids: vec![], registers: None, action: ModbusAction::Allow },
+            ],
+            ..Default::default()
+        };
+        let request = |unit_id, function_code, address| Request { unit_id, function_code, registers: Some((address, address)) };
+        assert_eq!(verdict(&config, &request(2, 6, 50)), Verdict::Drop);
+        assert_eq!(verdict(&config, &request(2, 6, 500)), Verdict::Allow);
+        assert_eq!(verdict(&config, &request(2, 16, 50)), Verdict::Alert);
+        assert_eq!(verdict(&config, &request(2, 3, 50)), Verdict::Allow);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::config::{NotifyConfig, NotifyEndpoint};
+use crate::events::{self, Alert, PipelineEvent};
+use crate::metrics::{self, BUFFER_DEPTH};
+use crate::sequence::{self, PeerDelivery};
+use crate::telemetry::hostname;
+use crate::transport::transport;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+// アラートとトンネルの重大なイベント (対向ノードの停止、DBへの接続断、書き込みバッファの滞留) を
+// Webhook・Slack・Discordへ通知する。batch_interval ごとにまとめて送信し、1回に送信する件数を max_batch に制限する
+
+// Discordのメッセージの文字数の上限
+const DISCORD_MAX_CHARS: usize = 2000;
+
+lazy_static! {
+    static ref CONFIG: RwLock<NotifyConfig> = RwLock::new(NotifyConfig::default());
+    static ref PENDING: Mutex<Pending> = Mutex::new(Pending::default());
+}
+
+// 通知が有効な場合のみアラートを溜める
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn configure(config: &NotifyConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+    ENABLED.store(config.enabled && !config.endpoints.is_empty(), Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    // 未知の重大度は warning として扱う
+    pub(crate) fn of(alert: &Alert) -> Self {
+        match alert.severity.as_str() {
+            "info" => Severity::Info,
+            "critical" => Severity::Critical,
+            _ => Severity::Warning,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    alerts: Vec<Alert>,
+    // max_batch を超えたため送信しないアラートの数
+    suppressed: usize,
+}
+
+// 発行されたアラートを次の送信に加える
+pub fn enqueue(alert: &Alert) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if Severity::of(alert) < config.min_severity {
+        return;
+    }
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    if pending.alerts.len() < config.max_batch {
+        pending.alerts.push(alert.clone());
+    } else {
+        pending.suppressed += 1;
+    }
+}
+
+fn alert(kind: &str, severity: Severity, message: String, src_ip: Option<IpAddr>) -> Alert {
+    Alert {
+        timestamp: Utc::now(),
+        kind: kind.to_string(),
+        severity: severity.as_str().to_string(),
+        message,
+        src_ip,
+        dst_ip: None,
+    }
+}
+
+// トンネルの状態の変化を検出する (状態が変わったときのみアラートを発行する)
+#[derive(Debug, Default)]
+struct Monitor {
+    database_up: Option<bool>,
+    // 対向ノードごとの受信数・最後に受信数が増えた時刻・停止中か
+    peers: HashMap<IpAddr, (u64, DateTime<Utc>, bool)>,
+    buffer_full: bool,
+}
+
+impl Monitor {
+    fn check(
+        &mut self,
+        config: &NotifyConfig,
+        database_up: bool,
+        peers: &[PeerDelivery],
+        buffer_depth: i64,
+        now: DateTime<Utc>,
+    ) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        match (self.database_up.replace(database_up), database_up) {
+            (Some(true) | None, false) => alerts.push(alert(
+                "database_unreachable",
+                Severity::Critical,
+                "トランスポートのバックエンド (データベース) に接続できません".to_string(),
+                None,
+            )),
+            (Some(false), true) => alerts.push(alert(
+                "database_recovered",
+                Severity::Info,
+                "トランスポートのバックエンド (データベース) への接続が回復しました".to_string(),
+                None,
+            )),
+            _ => {}
+        }
+
+        let peer_timeout = chrono::Duration::from_std(config.peer_timeout).unwrap_or(chrono::Duration::max_value());
+        for delivery in peers {
+            let (received, changed, down) = self.peers.entry(delivery.peer).or_insert((delivery.totals.received, now, false));
+            if delivery.totals.received != *received {
+                *received = delivery.totals.received;
+                *changed = now;
+                if std::mem::take(down) {
+                    alerts.push(alert(
+                        "peer_up",
+                        Severity::Info,
+                        format!("{} からのパケットの受信が再開しました", delivery.peer),
+                        Some(delivery.peer),
+                    ));
+                }
+            } else if !*down && now - *changed >= peer_timeout {
+                *down = true;
+                alerts.push(alert(
+                    "peer_down",
+                    Severity::Critical,
+                    format!("{} から {}秒間パケットを受信していません", delivery.peer, config.peer_timeout.as_secs()),
+                    Some(delivery.peer),
+                ));
+            }
+        }
+
+        let buffer_full = buffer_depth >= config.buffer_threshold;
+        if buffer_full && !self.buffer_full {
+            alerts.push(alert(
+                "buffer_overflow",
+                Severity::Critical,
+                format!("書き込み待ちのパケットが {}件 に達しました (閾値 {}件)", buffer_depth, config.buffer_threshold),
+                None,
+            ));
+        }
+        self.buffer_full = buffer_full;
+        alerts
+    }
+}
+
+// 状態を定期的に確認してアラートを発行し、溜まったアラートをまとめて送信する
+pub async fn start(mut shutdown: broadcast::Receiver<()>) {
+    let (check_interval, batch_interval) = {
+        let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+        (config.check_interval, config.batch_interval)
+    };
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("通知のHTTPクライアントを作成できません: {}", e);
+            return;
+        }
+    };
+    let mut monitor = Monitor::default();
+    let mut check = tokio::time::interval(check_interval);
+    let mut batch = tokio::time::interval(batch_interval);
+
+    loop {
+        tokio::select! {
+            _ = check.tick() => {
+                let database_up = match transport() {
+                    Ok(transport) => transport.health_check().await,
+                    Err(_) => false,
+                };
+                let config = CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone();
+                for alert in monitor.check(&config, database_up, &sequence::peer_totals(), BUFFER_DEPTH.get(), Utc::now()) {
+                    events::publish(PipelineEvent::Alert(alert));
+                }
+            }
+            _ = batch.tick() => flush(&client).await,
+            _ = shutdown.recv() => break,
+        }
+    }
+    // 終了前に溜まっているアラートを送信する
+    flush(&client).await;
+}
+
+async fn flush(client: &reqwest::Client) {
+    let Pending { alerts, suppressed } = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+    if alerts.is_empty() {
+        return;
+    }
+    let endpoints = CONFIG.read().unwrap_or_else(|e| e.into_inner()).endpoints.clone();
+    let host = hostname();
+    for endpoint in &endpoints {
+        let body = payload(endpoint, &host, &alerts, suppressed);
+        let result = client
+            .post(endpoint.url())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => metrics::NOTIFICATIONS.with_label_values(&["sent"]).inc(),
+            Err(e) => {
+                metrics::NOTIFICATIONS.with_label_values(&["failed"]).inc();
+                // URLにトークンを含む場合があるため、エラーからURLを除く
+                warn!("アラートの通知に失敗しました ({}): {}", endpoint.kind(), e.without_url());
+            }
+        }
+    }
+}
+
+// 通知先ごとのリクエストの本文
+fn payload(endpoint: &NotifyEndpoint, host: &str, alerts: &[Alert], suppressed: usize) -> serde_json::Value {
+    match endpoint {
+        NotifyEndpoint::Webhook { .. } => json!({ "host": host, "alerts": alerts, "suppressed": suppressed }),
+        NotifyEndpoint::Slack { .. } => json!({ "text": text(host, alerts, suppressed) }),
+        NotifyEndpoint::Discord { .. } => {
+            let mut content = text(host, alerts, suppressed);
+            if content.chars().count() > DISCORD_MAX_CHARS {
+                content = content.chars().take(DISCORD_MAX_CHARS - 1).collect::<String>() + "…";
+            }
+            json!({ "content": content })
+        }
+    }
+}
+
+fn text(host: &str, alerts: &[Alert], suppressed: usize) -> String {
+    let mut text = format!("[rdb-tunnel {}] アラート {}件", host, alerts.len() + suppressed);
+    for alert in alerts {
+        text.push_str(&format!(
+            "\n• [{}] {} {}: {}",
+            alert.severity,
+            alert.timestamp.format("%H:%M:%S"),
+            alert.kind,
+            alert.message
+        ));
+    }
+    if suppressed > 0 {
+        text.push_str(&format!("\n(他 {}件を省略)", suppressed));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence::DeliveryTotals;
+
+    fn delivery(peer: &str, received: u64) -> PeerDelivery {
+        PeerDelivery { peer: peer.parse().unwrap(), totals: DeliveryTotals { received, ..Default::default() } }
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn alerts_on_state_transitions_only() {
+        let config = NotifyConfig { peer_timeout: Duration::from_secs(60), buffer_threshold: 100, ..Default::default() };
+        let mut monitor = Monitor::default();
+        let kinds = |alerts: Vec<Alert>| alerts.into_iter().map(|alert| alert.kind).collect::<Vec<_>>();
+
+        assert!(monitor.check(&config, true, &[delivery("10.0.0.2", 5)], 0, at(0)).is_empty());
+        assert_eq!(
+            kinds(monitor.check(&config, false, &[delivery("10.0.0.2", 5)], 100, at(60))),
+            ["database_unreachable", "peer_down", "buffer_overflow"]
+        );
+        // 状態が続いている間は再び発行しない
+        assert!(monitor.check(&config, false, &[delivery("10.0.0.2", 5)], 200, at(120)).is_empty());
+        assert_eq!(
+            kinds(monitor.check(&config, true, &[delivery("10.0.0.2", 6)], 0, at(180))),
+            ["database_recovered", "peer_up"]
+        );
+    }
+
+    #[test]
+    fn formats_payload_for_each_endpoint() {
+        let alerts = [alert("ban", Severity::Warning, "203.0.113.7 を遮断しました".to_string(), None)];
+        let slack = NotifyEndpoint::Slack { url: "https://hooks.slack.com/services/x".to_string() };
+        let text = payload(&slack, "node1", &alerts, 2)["text"].as_str().unwrap().to_string();
+        assert!(text.starts_with("[rdb-tunnel node1] アラート 3件\n• [warning] "));
+        assert!(text.ends_with("ban: 203.0.113.7 を遮断しました\n(他 2件を省略)"));
+
+        let webhook = NotifyEndpoint::Webhook { url: "https://example.com/hook".to_string() };
+        let body = payload(&webhook, "node1", &alerts, 0);
+        assert_eq!(body["alerts"][0]["kind"], "ban");
+
+        let many = vec![alerts[0].clone(); 100];
+        let discord = NotifyEndpoint::Discord { url: "https://discord.com/api/webhooks/x".to_string() };
+        let content = payload(&discord, "node1", &many, 0)["content"].as_str().unwrap().to_string();
+        assert_eq!(content.chars().count(), DISCORD_MAX_CHARS);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::config::ReassemblyConfig;
+use crate::packet_header::TcpHeader;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// キャプチャしたTCPのセグメントをシーケンス番号の順に並べ、片方向のストリームを再構成する。
+// FIN・RSTを受信した・max_stream_size に達した・timeout の間データが届かない場合にストリームを終了し、
+// YARAのスキャンなどの検査に渡す。再構成を使用する検査が無効な場合は何もしない
+
+// データが届かないストリームを確認する間隔
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref CONFIG: RwLock<ReassemblyConfig> = RwLock::new(ReassemblyConfig::default());
+    static ref STREAMS: Mutex<Reassembler> = Mutex::new(Reassembler::default());
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// enabled は再構成を使用する検査が1つ以上有効な場合にtrue
+pub fn configure(config: &ReassemblyConfig, enabled: bool) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+    ENABLED.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        STREAMS.lock().unwrap_or_else(|e| e.into_inner()).flows.clear();
+    }
+}
+
+// 再構成したストリーム (片方向)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stream {
+    pub src: (IpAddr, u16),
+    pub dst: (IpAddr, u16),
+    pub data: Vec<u8>,
+    // max_stream_size で打ち切った場合はtrue
+    pub truncated: bool,
+}
+
+#[derive(Debug)]
+struct Flow {
+    // ストリームの先頭のシーケンス番号
+    isn: u32,
+    data: Vec<u8>,
+    // 順序が入れ替わって届いたセグメント (ストリームの先頭からの位置 -> ペイロード)
+    pending: BTreeMap<usize, Vec<u8>>,
+    pending_size: usize,
+    truncated: bool,
+    // max_stream_size に達して検査に渡した場合はtrue (以降のデータは無視する)
+    emitted: bool,
+    last_seen: Instant,
+}
+
+impl Flow {
+    fn new(isn: u32, now: Instant) -> Self {
+        Self {
+            isn,
+            data: Vec::new(),
+            pending: BTreeMap::new(),
+            pending_size: 0,
+            truncated: false,
+            emitted: false,
+            last_seen: now,
+        }
+    }
+
+    fn insert(&mut self, offset: usize, payload: &[u8], max_size: usize) {
+        let end = offset + payload.len();
+        if end <= self.data.len() {
+            // 再送されたセグメント
+            return;
+        }
+        if offset > self.data.len() {
+            if end <= max_size && self.pending_size + payload.len() <= max_size {
+                let previous = self.pending.entry(offset).or_default();
+                if previous.len() < payload.len() {
+                    self.pending_size += payload.len() - previous.len();
+                    *previous = payload.to_vec();
+                }
+            }
+            return;
+        }
+        let new = &payload[self.data.len() - offset..];
+        let room = max_size.saturating_sub(self.data.len());
+        self.truncated |= new.len() > room;
+        self.data.extend_from_slice(&new[..new.len().min(room)]);
+    }
+
+    // セグメントを追加する。max_stream_size に達した場合はtrue
+    fn push(&mut self, seq: u32, payload: &[u8], max_size: usize) -> bool {
+        let offset = seq.wrapping_sub(self.isn);
+        // ストリームの先頭より前 (途中から観測したストリームの再送など)
+        if offset > u32::MAX / 2 || payload.is_empty() {
+            return self.truncated;
+        }
+        self.insert(offset as usize, payload, max_size);
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.data.len() {
+                break;
+            }
+            let (offset, segment) = entry.remove_entry();
+            self.pending_size -= segment.len();
+            self.insert(offset, &segment, max_size);
+        }
+        self.truncated || self.data.len() >= max_size
+    }
+}
+
+type FlowKey = ((IpAddr, u16), (IpAddr, u16));
+
+#[derive(Debug, Default)]
+struct Reassembler {
+    flows: HashMap<FlowKey, Flow>,
+    last_sweep: Option<Instant>,
+}
+
+impl Reassembler {
+    // 終了したストリームを返す (検査に渡していないデータがある場合のみ)
+    fn finish(&mut self, key: FlowKey) -> Option<Stream> {
+        let flow = self.flows.remove(&key)?;
+        if flow.emitted || flow.data.is_empty() {
+            return None;
+        }
+        Some(Stream { src: key.0, dst: key.1, data: flow.data, truncated: flow.truncated })
+    }
+
+    fn sweep(&mut self, config: &ReassemblyConfig, now: Instant) -> Vec<Stream> {
+        if self.last_sweep.is_some_and(|last| now.duration_since(last) < SWEEP_INTERVAL) {
+            return Vec::new();
+        }
+        self.last_sweep = Some(now);
+        let idle = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| now.duration_since(flow.last_seen) >= config.timeout)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        idle.into_iter().filter_map(|key| self.finish(key)).collect()
+    }
+
+    fn observe(
+        &mut self,
+        config: &ReassemblyConfig,
+        key: FlowKey,
+        seq: u32,
+        flags: u8,
+        payload: &[u8],
+        now: Instant,
+    ) -> Vec<Stream> {
+        let mut finished = self.sweep(config, now);
+        if flags & TcpHeader::SYN != 0 {
+            // 新しい接続 (同じ4タプルの前のストリームは終了する)
+            finished.extend(self.finish(key));
+            if self.flows.len() < config.max_streams {
+                self.flows.insert(key, Flow::new(seq.wrapping_add(1), now));
+            }
+            return finished;
+        }
+        if !payload.is_empty() && !self.flows.contains_key(&key) && self.flows.len() < config.max_streams {
+            // 接続の途中から観測したストリーム
+            self.flows.insert(key, Flow::new(seq, now));
+        }
+        let Some(flow) = self.flows.get_mut(&key) else {
+            return finished;
+        };
+        flow.last_seen = now;
+        let full = flow.push(seq, payload, config.max_stream_size);
+        if flags & (TcpHeader::FIN | TcpHeader::RST) != 0 {
+            finished.extend(self.finish(key));
+        } else if full && !flow.emitted {
+            // 接続が終了するまで残し、以降のデータは無視する
+            flow.emitted = true;
+            finished.push(Stream { src: key.0, dst: key.1, data: std::mem::take(&mut flow.data), truncated: true });
+            flow.pending.clear();
+            flow.pending_size = 0;
+        }
+        finished
+    }
+}
+
+// TCPのセグメントを再構成し、終了したストリームを返す
+pub fn observe(src: (IpAddr, u16), dst: (IpAddr, u16), seq: u32, flags: u8, payload: &[u8]) -> Vec<Stream> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    STREAMS.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, (src, dst), seq, flags, payload, Instant::now())
+}
+
+// データが届かないストリームを終了して返す。セグメントが届かない間も終了できるよう定期的に呼び出す
+pub fn sweep() -> Vec<Stream> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    STREAMS.lock().unwrap_or_else(|e| e.into_inner()).sweep(&config, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: FlowKey = (
+        (IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 10, 5)), 40000),
+        (IpAddr::V4(std::net::Ipv4Addr::new(198, 51, 100, 20)), 80),
+    );
+
+    #[test]
+    fn reorders_segments_and_ignores_retransmissions() {
+        let config = ReassemblyConfig::default();
+        let mut reassembler = Reassembler::default();
+        let now = Instant::now();
+        // シーケンス番号が一周する位置から始まる接続
+        let isn = u32::MAX - 3;
+        assert!(reassembler.observe(&config, CLIENT, isn, TcpHeader::SYN, b"", now).is_empty());
+        assert!(reassembler.observe(&config, CLIENT, isn.wrapping_add(7), 0, b"world", now).is_empty());
+        assert!(reassembler.observe(&config, CLIENT, isn.wrapping_add(1), 0, b"hello ", now).is_empty());
+        assert!(reassembler.observe(&config, CLIENT, isn.wrapping_add(1), 0, b"hello", now).is_empty());
+        let streams = reassembler.observe(&config, CLIENT, isn.wrapping_add(12), TcpHeader::FIN | TcpHeader::ACK, b"!", now);
+        assert_eq!(
+            streams,
+            [Stream { src: CLIENT.0, dst: CLIENT.1, data: b"hello world!".to_vec(), truncated: false }]
+        );
+        assert!(reassembler.flows.is_empty());
+    }
+
+    #[test]
+    fn emits_truncated_and_idle_streams() {
+        let config = ReassemblyConfig { max_stream_size: 8, ..Default::default() };
+        let mut reassembler = Reassembler::default();
+        let now = Instant::now();
+        // 途中から観測したストリームは最初のセグメントから再構成する
+        assert!(reassembler.observe(&config, CLIENT, 1000, TcpHeader::ACK, b"abcd", now).is_empty());
+        let streams = reassembler.observe(&config, CLIENT, 1004, TcpHeader::ACK, b"efghij", now);
+        assert_eq!((streams[0].data.as_slice(), streams[0].truncated), (&b"abcdefgh"[..], true));
+        // 打ち切った後のデータと終了は検査に渡さない
+        assert!(reassembler.observe(&config, CLIENT, 1010, TcpHeader::FIN, b"kl", now).is_empty());
+
+        let server = (CLIENT.1, CLIENT.0);
+        reassembler.observe(&config, server, 5000, TcpHeader::ACK, b"HTTP", now);
+        let streams = reassembler.observe(&config, CLIENT, 1, TcpHeader::SYN, b"", now + config.timeout);
+        assert_eq!((streams[0].src, streams[0].data.as_slice()), (server.0, &b"HTTP"[..]));
+
+        // 以降にセグメントが届かなくても、定期的な確認で終了する
+        reassembler.observe(&config, CLIENT, 2, TcpHeader::ACK, b"GET", now + config.timeout);
+        let streams = reassembler.sweep(&config, now + config.timeout * 2);
+        assert_eq!(streams[0].data, b"GET");
+        assert!(reassembler.flows.is_empty());
+    }
+}
@@ -0,0 +1,346 @@
+use crate::config::{SiemConfig, SiemFormat, SiemSink, SiemTransport};
+use crate::events::Alert;
+use crate::metrics;
+use crate::security::notify::Severity;
+use crate::telemetry::hostname;
+use crate::traffic::{self, DirectionalTotals, TrafficTotals};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{broadcast, mpsc};
+use tracing::warn;
+
+// アラートとピアごとの通信量 (フローレコード) をCEF (ArcSight) またはLEEF (QRadar) 形式に変換し、
+// syslog (UDP/TCP) でSIEMへ送信する。形式は送信先ごとに選択する
+
+const VENDOR: &str = "rdb-tunnel";
+const PRODUCT: &str = "rdb-tunnel";
+// 送信待ちのレコードの上限 (超えた分は破棄する)
+const QUEUE_CAPACITY: usize = 4096;
+// syslogのファシリティ (local0)
+const SYSLOG_FACILITY: u8 = 16;
+
+static QUEUE: OnceLock<mpsc::Sender<Record>> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+enum Record {
+    Alert(Alert),
+    // 前回の送信からの対向ノードとの通信量
+    Flow {
+        peer: IpAddr,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        totals: DirectionalTotals,
+    },
+}
+
+impl Record {
+    fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            Record::Alert(alert) => alert.timestamp,
+            Record::Flow { end, .. } => *end,
+        }
+    }
+
+    fn is_flow(&self) -> bool {
+        matches!(self, Record::Flow { .. })
+    }
+
+    fn severity(&self) -> Severity {
+        match self {
+            Record::Alert(alert) => Severity::of(alert),
+            Record::Flow { .. } => Severity::Info,
+        }
+    }
+}
+
+// 重大度 (CEFとLEEFは0-10)
+fn event_severity(severity: Severity) -> u8 {
+    match severity {
+        Severity::Info => 3,
+        Severity::Warning => 6,
+        Severity::Critical => 9,
+    }
+}
+
+fn syslog_severity(severity: Severity) -> u8 {
+    match severity {
+        Severity::Info => 6,
+        Severity::Warning => 4,
+        Severity::Critical => 2,
+    }
+}
+
+// ヘッダーでは \ と | をエスケープする
+fn escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+// CEFの拡張フィールドでは \ と = をエスケープし、改行は \n にする
+fn escape_cef(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('=', "\\=").replace('\r', "").replace('\n', "\\n")
+}
+
+// LEEFの属性はタブ区切りのため、値のタブと改行は空白にする
+fn escape_leef(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
+
+// (イベントID, 名前, 属性)。属性のキーはCEFの名前で、LEEFでは対応するキーに置き換える
+fn fields(record: &Record) -> (String, String, Vec<(&'static str, String)>) {
+    let mut attributes = vec![("rt", record.timestamp().timestamp_millis().to_string())];
+    match record {
+        Record::Alert(alert) => {
+            if let Some(src) = alert.src_ip {
+                attributes.push(("src", src.to_string()));
+            }
+            if let Some(dst) = alert.dst_ip {
+                attributes.push(("dst", dst.to_string()));
+            }
+            attributes.push(("msg", alert.message.clone()));
+            (alert.kind.clone(), alert.kind.replace('_', " "), attributes)
+        }
+        Record::Flow { peer, start, totals, .. } => {
+            attributes.extend([
+                ("start", start.timestamp_millis().to_string()),
+                ("dst", peer.to_string()),
+                ("out", totals.outbound.bytes.to_string()),
+                ("in", totals.inbound.bytes.to_string()),
+                ("cn1", totals.outbound.packets.to_string()),
+                ("cn2", totals.inbound.packets.to_string()),
+            ]);
+            ("flow".to_string(), "peer traffic".to_string(), attributes)
+        }
+    }
+}
+
+fn cef(record: &Record) -> String {
+    let (id, name, attributes) = fields(record);
+    let mut line = format!(
+        "CEF:0|{}|{}|{}|{}|{}|{}|",
+        escape_header(VENDOR),
+        escape_header(PRODUCT),
+        env!("CARGO_PKG_VERSION"),
+        escape_header(&id),
+        escape_header(&name),
+        event_severity(record.severity())
+    );
+    let mut extension = attributes.iter().map(|(key, value)| format!("{}={}", key, escape_cef(value))).collect::<Vec<_>>();
+    if record.is_flow() {
+        extension.extend(["cn1Label=outPackets".to_string(), "cn2Label=inPackets".to_string()]);
+    }
+    line.push_str(&extension.join(" "));
+    line
+}
+
+fn leef(record: &Record) -> String {
+    let (id, _, attributes) = fields(record);
+    let mut line = format!(
+        "LEEF:1.0|{}|{}|{}|{}|sev={}",
+        escape_header(VENDOR),
+        escape_header(PRODUCT),
+        env!("CARGO_PKG_VERSION"),
+        escape_header(&id),
+        event_severity(record.severity())
+    );
+    for (key, value) in attributes {
+        let key = match key {
+            "rt" => "devTime",
+            "out" => "srcBytes",
+            "in" => "dstBytes",
+            "cn1" => "srcPackets",
+            "cn2" => "dstPackets",
+            other => other,
+        };
+        let _ = write!(line, "\t{}={}", key, escape_leef(&value));
+    }
+    if record.is_flow() {
+        line.push_str("\tcat=flow");
+    }
+    line
+}
+
+// syslog (RFC 3164) のヘッダーを付ける
+fn syslog_line(record: &Record, format: SiemFormat, host: &str) -> String {
+    let body = match format {
+        SiemFormat::Cef => cef(record),
+        SiemFormat::Leef => leef(record),
+    };
+    format!(
+        "<{}>{} {} {}",
+        SYSLOG_FACILITY * 8 + syslog_severity(record.severity()),
+        record.timestamp().format("%b %e %H:%M:%S"),
+        host,
+        body
+    )
+}
+
+// 発行されたアラートを送信待ちに加える
+pub fn enqueue(alert: &Alert) {
+    if let Some(queue) = QUEUE.get() {
+        if queue.try_send(Record::Alert(alert.clone())).is_err() {
+            metrics::SIEM_EVENTS.with_label_values(&["dropped"]).inc();
+        }
+    }
+}
+
+// 送信先ごとの接続 (TCPは失敗した場合に次の送信で再接続する)
+struct Connection {
+    sink: SiemSink,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+}
+
+impl Connection {
+    async fn send(&mut self, line: &str) -> std::io::Result<()> {
+        match self.sink.transport {
+            SiemTransport::Udp => {
+                if self.udp.is_none() {
+                    let bind = if self.sink.address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" };
+                    self.udp = Some(UdpSocket::bind(bind).await?);
+                }
+                let socket = self.udp.as_ref().expect("connected");
+                socket.send_to(line.as_bytes(), &self.sink.address).await.map(|_| ())
+            }
+            SiemTransport::Tcp => {
+                if self.tcp.is_none() {
+                    self.tcp = Some(TcpStream::connect(&self.sink.address).await?);
+                }
+                let stream = self.tcp.as_mut().expect("connected");
+                // 改行で区切る (octet countingには対応しない)
+                let result = stream.write_all(format!("{}\n", line).as_bytes()).await;
+                if result.is_err() {
+                    self.tcp = None;
+                }
+                result
+            }
+        }
+    }
+}
+
+// アラートとフローレコードを送信する
+pub async fn start(config: SiemConfig, mut shutdown: broadcast::Receiver<()>) {
+    let (sender, mut receiver) = mpsc::channel(QUEUE_CAPACITY);
+    if QUEUE.set(sender.clone()).is_err() {
+        return;
+    }
+    let host = hostname();
+    let mut connections = config
+        .sinks
+        .iter()
+        .map(|sink| Connection { sink: sink.clone(), udp: None, tcp: None })
+        .collect::<Vec<_>>();
+    let mut flows = tokio::time::interval(config.flow_interval);
+    let mut previous: HashMap<IpAddr, DirectionalTotals> = HashMap::new();
+    let mut last_flow = Utc::now();
+
+    loop {
+        let record = tokio::select! {
+            Some(record) = receiver.recv() => record,
+            _ = flows.tick() => {
+                let now = Utc::now();
+                if connections.iter().any(|connection| connection.sink.flows) {
+                    for record in flow_records(&mut previous, last_flow, now) {
+                        let _ = sender.try_send(record);
+                    }
+                }
+                last_flow = now;
+                continue;
+            }
+            _ = shutdown.recv() => break,
+        };
+
+        for connection in &mut connections {
+            let sink = &connection.sink;
+            let wanted = match &record {
+                Record::Alert(_) => sink.alerts && record.severity() >= sink.min_severity,
+                Record::Flow { .. } => sink.flows,
+            };
+            if !wanted {
+                continue;
+            }
+            let line = syslog_line(&record, sink.format, &host);
+            match connection.send(&line).await {
+                Ok(()) => metrics::SIEM_EVENTS.with_label_values(&["sent"]).inc(),
+                Err(e) => {
+                    metrics::SIEM_EVENTS.with_label_values(&["failed"]).inc();
+                    warn!("SIEMへの送信に失敗しました ({}): {}", connection.sink.address, e);
+                }
+            }
+        }
+    }
+}
+
+// 前回からの通信量が増えた対向ノードのフローレコード
+fn flow_records(previous: &mut HashMap<IpAddr, DirectionalTotals>, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<Record> {
+    let delta = |current: TrafficTotals, last: TrafficTotals| TrafficTotals {
+        packets: current.packets.saturating_sub(last.packets),
+        bytes: current.bytes.saturating_sub(last.bytes),
+    };
+    traffic::peer_totals()
+        .into_iter()
+        .filter_map(|peer| {
+            let last = previous.insert(peer.address, peer.totals).unwrap_or_default();
+            let totals = DirectionalTotals {
+                outbound: delta(peer.totals.outbound, last.outbound),
+                inbound: delta(peer.totals.inbound, last.inbound),
+            };
+            (totals.outbound.packets + totals.inbound.packets > 0).then_some(Record::Flow { peer: peer.address, start, end, totals })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert() -> Record {
+        Record::Alert(Alert {
+            timestamp: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            kind: "ban".to_string(),
+            severity: "warning".to_string(),
+            message: "a=b | c\nd".to_string(),
+            src_ip: Some("203.0.113.7".parse().unwrap()),
+            dst_ip: None,
+        })
+    }
+
+    #[test]
+    fn formats_alerts_as_cef_and_leef() {
+        assert_eq!(
+            cef(&alert()),
+            format!(
+                "CEF:0|rdb-tunnel|rdb-tunnel|{}|ban|ban|6|rt=1700000000000 src=203.0.113.7 msg=a\\=b | c\\nd",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        assert_eq!(
+            leef(&alert()),
+            format!(
+                "LEEF:1.0|rdb-tunnel|rdb-tunnel|{}|ban|sev=6\tdevTime=1700000000000\tsrc=203.0.113.7\tmsg=a=b | c d",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        // local0.warning
+        assert!(syslog_line(&alert(), SiemFormat::Cef, "node1").starts_with("<132>Nov 14 22:13:20 node1 CEF:0|"));
+    }
+
+    #[test]
+    fn formats_flow_records() {
+        let flow = Record::Flow {
+            peer: "10.0.0.2".parse().unwrap(),
+            start: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            end: DateTime::from_timestamp(1_700_000_060, 0).unwrap(),
+            totals: DirectionalTotals {
+                outbound: TrafficTotals { packets: 3, bytes: 300 },
+                inbound: TrafficTotals { packets: 2, bytes: 120 },
+            },
+        };
+        assert!(cef(&flow).ends_with("|flow|peer traffic|3|rt=1700000060000 start=1700000000000 dst=10.0.0.2 out=300 in=120 cn1=3 cn2=2 cn1Label=outPackets cn2Label=inPackets"));
+        assert!(leef(&flow).ends_with("\tdst=10.0.0.2\tsrcBytes=300\tdstBytes=120\tsrcPackets=3\tdstPackets=2\tcat=flow"));
+    }
+}
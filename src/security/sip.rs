@@ -0,0 +1,231 @@
+use crate::config::SipConfig;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tracing::debug;
+
+// SIPのシグナリング (SDP) からRTP・RTCPのアドレスとポートを学習し、通話の間だけファイアウォールで許可する (ピンホール)。
+// 書き込む方向のSIPのみを解析し、sources に含まれる自拠点の端末のメディアのアドレスと、SIPの宛先 (通話の相手) の組を許可する。
+// ピンホールはルールより優先度の低い許可として扱い、一致するルール (遮断・一時的な遮断) がある場合は変更しない
+
+// 同時に保持する通話の数の上限
+const MAX_CALLS: usize = 4096;
+
+lazy_static! {
+    static ref CONFIG: RwLock<SipConfig> = RwLock::new(SipConfig::default());
+    static ref PINHOLES: Mutex<Pinholes> = Mutex::new(Pinholes::default());
+}
+
+pub fn configure(config: &SipConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Signal {
+    // SDPを含むメッセージ (INVITE・UPDATE・ACK・18x・2xxなど) のメディアのアドレス
+    Offer(Vec<(IpAddr, u16)>),
+    // BYE・CANCEL
+    End,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Message {
+    call_id: String,
+    signal: Signal,
+}
+
+// SDPの m= 行のメディアのアドレス (メディアごとの c= 行がない場合はセッションの c= 行を使う)
+fn media(sdp: &str) -> Vec<(IpAddr, u16)> {
+    let mut endpoints = Vec::new();
+    let mut session = None;
+    // (ポート, メディアの c= 行)
+    let mut current: Option<(u16, Option<IpAddr>)> = None;
+    let connection = |value: &str| value.split_whitespace().nth(2).and_then(|address| address.split('/').next()?.parse().ok());
+    for line in sdp.lines().map(str::trim_end) {
+        if let Some(value) = line.strip_prefix("m=") {
+            endpoints.extend(current.take().and_then(|(port, address)| Some((address.or(session)?, port))));
+            // ポート0は拒否されたメディア
+            current = value
+                .split_whitespace()
+                .nth(1)
+                .and_then(|port| port.split('/').next()?.parse().ok())
+                .filter(|&port| port != 0)
+                .map(|port| (port, None));
+        } else if let Some(value) = line.strip_prefix("c=") {
+            match current.as_mut() {
+                Some((_, address)) => *address = connection(value),
+                None => session = connection(value),
+            }
+        }
+    }
+    endpoints.extend(current.and_then(|(port, address)| Some((address.or(session)?, port))));
+    endpoints
+}
+
+fn parse(payload: &[u8]) -> Option<Message> {
+    let text = std::str::from_utf8(payload).ok()?;
+    let (head, body) = text.split_once("\r\n\r\n").unwrap_or((text, ""));
+    let mut lines = head.lines();
+    let start = lines.next()?;
+    let is_response = start.starts_with("SIP/2.0 ");
+    if !is_response && !start.ends_with("SIP/2.0") {
+        return None;
+    }
+    let call_id = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        // Call-ID の短縮形は "i"
+        (name.trim().eq_ignore_ascii_case("call-id") || name.trim() == "i").then(|| value.trim().to_string())
+    })?;
+    let method = start.split_whitespace().next()?;
+    let signal = if !is_response && matches!(method, "BYE" | "CANCEL") {
+        Signal::End
+    } else {
+        let endpoints = media(body);
+        if endpoints.is_empty() {
+            return None;
+        }
+        Signal::Offer(endpoints)
+    };
+    Some(Message { call_id, signal })
+}
+
+// 自拠点のメディアのアドレスと通話の相手のアドレスの組
+type Pinhole = ((IpAddr, u16), IpAddr);
+
+#[derive(Debug, Default)]
+struct Pinholes {
+    // 通話ごとのピンホールと期限
+    calls: HashMap<String, (Vec<Pinhole>, Instant)>,
+    media: HashMap<Pinhole, String>,
+}
+
+impl Pinholes {
+    fn end(&mut self, call_id: &str) {
+        if let Some((pinholes, _)) = self.calls.remove(call_id) {
+            for pinhole in pinholes {
+                if self.media.get(&pinhole).is_some_and(|owner| owner == call_id) {
+                    self.media.remove(&pinhole);
+                }
+            }
+        }
+    }
+
+    // peer は自拠点から送信したSIPのメッセージの宛先
+    fn apply(&mut self, config: &SipConfig, message: Message, peer: IpAddr, now: Instant) {
+        let endpoints = match message.signal {
+            Signal::End => return self.end(&message.call_id),
+            Signal::Offer(endpoints) => endpoints,
+        };
+        let expired = self.calls.iter().filter(|(_, (_, until))| *until <= now).map(|(call_id, _)| call_id.clone()).collect::<Vec<_>>();
+        for call_id in expired {
+            self.end(&call_id);
+        }
+        if !self.calls.contains_key(&message.call_id) && self.calls.len() >= MAX_CALLS {
+            return;
+        }
+        let until = now + config.max_call_duration;
+        let (known, expires) = self.calls.entry(message.call_id.clone()).or_insert_with(|| (Vec::new(), until));
+        *expires = until;
+        // RTPとRTCP (RTPのポート + 1)。ウェルノウンポートと sources 以外のアドレスは開けない
+        let local = |address: &IpAddr| config.sources.iter().any(|network| network.contains(*address));
+        for (address, port) in endpoints.into_iter().filter(|(address, port)| *port >= 1024 && local(address)) {
+            for endpoint in [(address, port), (address, port.saturating_add(1))] {
+                let pinhole = (endpoint, peer);
+                if !known.contains(&pinhole) {
+                    known.push(pinhole);
+                }
+                self.media.insert(pinhole, message.call_id.clone());
+            }
+        }
+    }
+
+    // 自拠点のメディアのアドレスと通話の相手の間のパケット (どちらの方向も) であればtrue
+    fn contains(&self, src: (IpAddr, u16), dst: (IpAddr, u16), now: Instant) -> bool {
+        [(src, dst.0), (dst, src.0)].iter().any(|pinhole| {
+            self.media
+                .get(pinhole)
+                .and_then(|call_id| self.calls.get(call_id))
+                .is_some_and(|(_, until)| *until > now)
+        })
+    }
+}
+
+// 書き込むSIPのメッセージ (UDPの ports 宛て・発) を解析する
+pub fn inspect(src_port: u16, dst: (IpAddr, u16), payload: &[u8]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || !(config.ports.contains(&src_port) || config.ports.contains(&dst.1)) {
+        return;
+    }
+    let Some(message) = parse(payload) else {
+        return;
+    };
+    debug!("SIP: {} -> {} {:?}", message.call_id, dst.0, message.signal);
+    PINHOLES.lock().unwrap_or_else(|e| e.into_inner()).apply(&config, message, dst.0, Instant::now());
+}
+
+// 通話中のRTP・RTCPのUDPパケットであればtrue (ファイアウォールのルールに一致しない場合のみ許可する)
+pub fn is_media(src: (IpAddr, u16), dst: (IpAddr, u16)) -> bool {
+    if !CONFIG.read().unwrap_or_else(|e| e.into_inner()).enabled {
+        return false;
+    }
+    PINHOLES.lock().unwrap_or_else(|e| e.into_inner()).contains(src, dst, Instant::now())
+}
+
+// RTP・RTCPに設定するDSCP
+pub fn media_dscp() -> Option<u8> {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).dscp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITE: &str = "INVITE sip:bob@198.51.100.20 SIP/2.0\r\n\
+        Via: SIP/2.0/UDP 192.168.10.5:5060\r\n\
+        Call-ID: a84b4c76e66710@pc33\r\n\
+        Content-Type: application/sdp\r\n\r\n\
+        v=0\r\no=alice 1 1 IN IP4 192.168.10.5\r\ns=-\r\nc=IN IP4 192.168.10.5\r\nt=0 0\r\n\
+        m=audio 49170 RTP/AVP 0\r\n\
+        m=video 0 RTP/AVP 31\r\n\
+        m=audio 49180 RTP/AVP 8\r\nc=IN IP4 192.168.10.6\r\n";
+
+    #[test]
+    fn learns_media_from_sdp() {
+        let message = parse(INVITE.as_bytes()).unwrap();
+        assert_eq!(message.call_id, "a84b4c76e66710@pc33");
+        assert_eq!(
+            message.signal,
+            Signal::Offer(vec![("192.168.10.5".parse().unwrap(), 49170), ("192.168.10.6".parse().unwrap(), 49180)])
+        );
+        let bye = "BYE sip:bob@198.51.100.20 SIP/2.0\r\ni: a84b4c76e66710@pc33\r\n\r\n";
+        assert_eq!(parse(bye.as_bytes()).unwrap().signal, Signal::End);
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn opens_pinholes_for_call_duration() {
+        let config = SipConfig { enabled: true, sources: vec!["192.168.10.5/32".parse().unwrap()], ..Default::default() };
+        let mut pinholes = Pinholes::default();
+        let now = Instant::now();
+        let peer = "198.51.100.20".parse().unwrap();
+        let local = ("192.168.10.5".parse().unwrap(), 49171);
+        let remote = (peer, 30000);
+        pinholes.apply(&config, parse(INVITE.as_bytes()).unwrap(), peer, now);
+        // RTCP (RTP + 1) も通過させる
+        assert!(pinholes.contains(remote, local, now));
+        assert!(pinholes.contains(local, remote, now));
+        assert!(!pinholes.contains(remote, ("192.168.10.5".parse().unwrap(), 5060), now));
+        assert!(!pinholes.contains(remote, local, now + config.max_call_duration));
+        // 通話の相手以外のアドレスと、sources 以外のメディアのアドレス (192.168.10.6) は許可しない
+        assert!(!pinholes.contains(("203.0.113.66".parse().unwrap(), 30000), local, now));
+        assert!(!pinholes.contains(remote, ("192.168.10.6".parse().unwrap(), 49180), now));
+
+        pinholes.apply(&config, parse(INVITE.as_bytes()).unwrap(), peer, now);
+        let bye = "BYE sip:bob@198.51.100.20 SIP/2.0\r\nCall-ID: a84b4c76e66710@pc33\r\n\r\n";
+        pinholes.apply(&config, parse(bye.as_bytes()).unwrap(), peer, now);
+        assert!(!pinholes.contains(remote, local, now));
+        assert!(pinholes.media.is_empty());
+    }
+}
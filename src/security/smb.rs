@@ -0,0 +1,189 @@
+use crate::bans::{self, Detector};
+use crate::config::SmbConfig;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use tracing::info;
+
+// ポート445のSMB2/3のヘッダを解析してツリー接続・ファイルのオープンを記録し、
+// 1つの送信元が多数のホストの445番に接続する動作 (ワームの感染拡大) を検知する。
+// TCPストリームの再構成は行わないため、NetBIOSセッションヘッダから始まるセグメントのみを解析する
+
+const SMB_PORT: u16 = 445;
+// SMB2のコマンド
+const SMB2_TREE_CONNECT: u16 = 0x0003;
+const SMB2_CREATE: u16 = 0x0005;
+// SMB2ヘッダのFlagsの応答のビット
+const SMB2_FLAGS_SERVER_TO_REDIR: u32 = 0x0000_0001;
+// 追跡する送信元の数の上限
+const MAX_TRACKED: usize = 16384;
+
+lazy_static! {
+    static ref CONFIG: RwLock<SmbConfig> = RwLock::new(SmbConfig::default());
+    static ref SCANS: Mutex<ScanTracker> = Mutex::new(ScanTracker::default());
+}
+
+pub fn configure(config: &SmbConfig) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Request {
+    TreeConnect { path: String },
+    Create { name: String },
+    Other(u16),
+}
+
+fn utf16(bytes: &[u8]) -> String {
+    let units = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect::<Vec<_>>();
+    String::from_utf16_lossy(&units)
+}
+
+// SMB2ヘッダからの位置と長さで示される文字列 (UTF-16LE)
+fn field(smb: &[u8], offset_at: usize) -> Option<String> {
+    let offset = u16::from_le_bytes([*smb.get(offset_at)?, *smb.get(offset_at + 1)?]) as usize;
+    let length = u16::from_le_bytes([*smb.get(offset_at + 2)?, *smb.get(offset_at + 3)?]) as usize;
+    smb.get(offset..offset + length).map(utf16)
+}
+
+// NetBIOSセッションヘッダに続くSMB2の要求を解析する (応答とSMB1は対象外)
+fn parse(payload: &[u8]) -> Option<Request> {
+    if payload.first()? != &0x00 {
+        return None;
+    }
+    let smb = payload.get(4..)?;
+    if smb.get(0..4)? != b"\xfeSMB" || smb.len() < 64 {
+        return None;
+    }
+    let command = u16::from_le_bytes([smb[12], smb[13]]);
+    let flags = u32::from_le_bytes([smb[16], smb[17], smb[18], smb[19]]);
+    if flags & SMB2_FLAGS_SERVER_TO_REDIR != 0 {
+        return None;
+    }
+    Some(match command {
+        // 本文の PathOffset (4バイト目) と PathLength
+        SMB2_TREE_CONNECT => Request::TreeConnect { path: field(smb, 64 + 4)? },
+        // 本文の NameOffset (44バイト目) と NameLength
+        SMB2_CREATE => Request::Create { name: field(smb, 64 + 44)? },
+        command => Request::Other(command),
+    })
+}
+
+#[derive(Debug, Default)]
+struct ScanTracker {
+    // 送信元ごとの window の開始時刻と接続したホスト
+    sources: HashMap<IpAddr, (Instant, HashSet<IpAddr>, bool)>,
+}
+
+impl ScanTracker {
+    // 445番への接続を記録し、max_hosts を超えた場合に接続したホストの数を返す
+    fn observe(&mut self, config: &SmbConfig, src: IpAddr, dst: IpAddr, now: Instant) -> Option<usize> {
+        if !self.sources.contains_key(&src) && self.sources.len() >= MAX_TRACKED {
+            self.sources.retain(|_, (start, _, _)| now.duration_since(*start) < config.window);
+            if self.sources.len() >= MAX_TRACKED {
+                return None;
+            }
+        }
+        let (start, hosts, alerted) = self.sources.entry(src).or_insert_with(|| (now, HashSet::new(), false));
+        if now.duration_since(*start) >= config.window {
+            *start = now;
+            hosts.clear();
+            *alerted = false;
+        }
+        hosts.insert(dst);
+        if hosts.len() <= config.max_hosts || std::mem::replace(alerted, true) {
+            return None;
+        }
+        Some(hosts.len())
+    }
+}
+
+// ポート445のTCPのパケットを検査する (syn は接続の開始)
+pub fn inspect(src: IpAddr, dst: IpAddr, dst_port: u16, syn: bool, payload: &[u8]) {
+    let config = CONFIG.read().unwrap_or_else(|e| e.into_inner());
+    if !config.enabled || dst_port != SMB_PORT {
+        return;
+    }
+    if syn {
+        let hosts = SCANS.lock().unwrap_or_else(|e| e.into_inner()).observe(&config, src, dst, Instant::now());
+        if let Some(hosts) = hosts {
+            raise(
+                "smb_worm",
+                Severity::Critical,
+                format!("{} が {}秒間に {}台 のホストのSMB (445番) に接続しました", src, config.window.as_secs(), hosts),
+                Some(src),
+                None,
+            );
+            bans::report(src, Detector::Scan);
+        }
+        return;
+    }
+    if !config.log_commands {
+        return;
+    }
+    match parse(payload) {
+        Some(Request::TreeConnect { path }) => info!("SMB ツリー接続: {} -> {} {}", src, dst, path),
+        Some(Request::Create { name }) => info!("SMB ファイルのオープン: {} -> {} {}", src, dst, name),
+        Some(Request::Other(_)) | None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(command: u16, body: &[u8]) -> Vec<u8> {
+        let mut smb = b"\xfeSMB".to_vec();
+        smb.resize(64, 0);
+        smb[4] = 64;
+        smb[12..14].copy_from_slice(&command.to_le_bytes());
+        smb.extend_from_slice(body);
+        let mut payload = vec![0x00];
+        payload.extend_from_slice(&(smb.len() as u32).to_be_bytes()[1..]);
+        payload.extend_from_slice(&smb);
+        payload
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parses_tree_connect_and_create() {
+        let path = utf16le(r"\\fileserver\share");
+        let mut body = vec![9, 0, 0, 0];
+        body.extend_from_slice(&72u16.to_le_bytes());
+        body.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        body.extend_from_slice(&path);
+        assert_eq!(parse(&request(SMB2_TREE_CONNECT, &body)), Some(Request::TreeConnect { path: r"\\fileserver\share".to_string() }));
+
+        let name = utf16le(r"docs\report.xlsx");
+        let mut body = vec![0; 56];
+        body[0] = 57;
+        body[44..46].copy_from_slice(&120u16.to_le_bytes());
+        body[46..48].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        body.extend_from_slice(&name);
+        assert_eq!(parse(&request(SMB2_CREATE, &body)), Some(Request::Create { name: r"docs\report.xlsx".to_string() }));
+
+        // 応答は対象外
+        let mut response = request(SMB2_CREATE, &body);
+        response[4 + 16] = 1;
+        assert_eq!(parse(&response), None);
+    }
+
+    #[test]
+    fn detects_many_hosts_probed_by_one_source() {
+        let config = SmbConfig { enabled: true, max_hosts: 3, ..Default::default() };
+        let mut tracker = ScanTracker::default();
+        let src: IpAddr = "192.168.10.66".parse().unwrap();
+        let now = Instant::now();
+        let detected = (1..=6)
+            .filter_map(|host| tracker.observe(&config, src, format!("192.168.10.{}", host).parse().unwrap(), now))
+            .collect::<Vec<_>>();
+        assert_eq!(detected, [4]);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::YaraConfig;
+use crate::security::notify::Severity;
+use crate::security::raise;
+use crate::security::reassembly::Stream;
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
+use yara::{Compiler, Rules};
+
+// 再構成したTCPストリームをYARAのルールでスキャンし、一致したルール名とオフセットをアラートとして発行する。
+// スキャンはキャプチャの処理を止めないよう、ブロッキング用のスレッドで行う
+
+#[derive(Default)]
+struct Scanner {
+    config: YaraConfig,
+    rules: Option<Arc<Rules>>,
+}
+
+lazy_static! {
+    static ref SCANNER: RwLock<Scanner> = RwLock::new(Scanner::default());
+}
+
+fn compile(paths: &[PathBuf]) -> Result<Rules, yara::Error> {
+    let mut compiler = Compiler::new()?;
+    for path in paths {
+        compiler = compiler.add_rules_file(path)?;
+    }
+    Ok(compiler.compile_rules()?)
+}
+
+// ルールを読み込む。読み込みに失敗した場合は前回のルールを使用する
+pub fn configure(config: &YaraConfig) {
+    let mut scanner = SCANNER.write().unwrap_or_else(|e| e.into_inner());
+    scanner.config = config.clone();
+    if !config.enabled {
+        scanner.rules = None;
+        return;
+    }
+    match compile(&config.rules) {
+        Ok(rules) => {
+            info!("YARAのルールを読み込みました: {}件", rules.get_rules().len());
+            scanner.rules = Some(Arc::new(rules));
+        }
+        Err(e) if scanner.rules.is_some() => warn!("YARAのルールを読み込めません (前回のルールを使用します): {}", e),
+        Err(e) => error!("YARAのルールを読み込めません: {}", e),
+    }
+}
+
+// 一致したルール名と、最初に一致した文字列とオフセット (文字列を使用しないルールはNone)
+type Found = (String, Option<(String, usize)>);
+
+fn matches(rules: &Rules, data: &[u8], timeout: i32) -> Result<Vec<Found>, yara::YaraError> {
+    Ok(rules
+        .scan_mem(data, timeout)?
+        .into_iter()
+        .map(|rule| {
+            let first = rule
+                .strings
+                .iter()
+                .flat_map(|string| string.matches.iter().map(move |found| (string.identifier, found.offset)))
+                .min_by_key(|(_, offset)| *offset)
+                .map(|(identifier, offset)| (identifier.to_string(), offset));
+            (rule.identifier.to_string(), first)
+        })
+        .collect())
+}
+
+fn scan_stream(rules: &Rules, stream: &Stream, timeout: i32) {
+    let found = match matches(rules, &stream.data, timeout) {
+        Ok(found) => found,
+        Err(e) => {
+            warn!("YARAのスキャンに失敗しました: {}:{} -> {}:{}: {}", stream.src.0, stream.src.1, stream.dst.0, stream.dst.1, e);
+            return;
+        }
+    };
+    for (rule, first) in found {
+        let location = match first {
+            Some((string, offset)) => format!("オフセット {} ({})", offset, string),
+            None => "条件のみ".to_string(),
+        };
+        raise(
+            "yara_match",
+            Severity::Critical,
+            format!(
+                "{}:{} から {}:{} へのストリームがYARAのルール {} に一致しました ({})",
+                stream.src.0, stream.src.1, stream.dst.0, stream.dst.1, rule, location
+            ),
+            Some(stream.src.0),
+            Some(stream.dst.0),
+        );
+    }
+}
+
+// 送信元または宛先が ports のストリームをスキャンする
+pub fn scan(stream: &Arc<Stream>) {
+    let (rules, timeout) = {
+        let scanner = SCANNER.read().unwrap_or_else(|e| e.into_inner());
+        let ports = &scanner.config.ports;
+        if !ports.is_empty() && !ports.contains(&stream.src.1) && !ports.contains(&stream.dst.1) {
+            return;
+        }
+        let Some(rules) = scanner.rules.clone() else {
+            return;
+        };
+        (rules, scanner.config.timeout.as_secs().clamp(1, i32::MAX as u64) as i32)
+    };
+    let stream = stream.clone();
+    let task = move || scan_stream(&rules, &stream, timeout);
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn_blocking(task)),
+        Err(_) => task(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_rule_name_and_first_offset() {
+        let rules = Compiler::new()
+            .unwrap()
+            .add_rules_str(
+                r#"rule eicar_like { strings: $a = "X5O!P%@AP" $b = "EICAR" condition: any of them }
+                   rule large_stream { condition: filesize > 1000 }"#,
+            )
+            .unwrap()
+            .compile_rules()
+            .unwrap();
+        let data = b"GET /download HTTP/1.1\r\n\r\nEICAR X5O!P%@AP";
+        assert_eq!(matches(&rules, data, 1).unwrap(), [("eicar_like".to_string(), Some(("$b".to_string(), 26)))]);
+        assert!(matches(&rules, b"GET / HTTP/1.1\r\n\r\n", 1).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::buffer_pool::FRAME_POOL;
+use crate::packet_header::{internet_checksum, transport_checksum, EthernetHeader, Ipv4Header, Ipv6Header};
+use bytes::Bytes;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::info;
+
+// オフロード (TSO/GSO) が有効なNICでキャプチャすると、MTUを超えるTCPフレームが届く。
+// そのままでは受信側の仮想NICに注入できないため、書き込む前にMSSごとのフレームに分割する
+
+// トンネルのMTU (IPパケットの最大長)
+static MTU: AtomicUsize = AtomicUsize::new(DEFAULT_MTU);
+pub const DEFAULT_MTU: usize = 1500;
+
+const TCP_PROTOCOL: u8 = 6;
+const TCP_FIN: u8 = 0x01;
+const TCP_PSH: u8 = 0x08;
+const TCP_CWR: u8 = 0x80;
+
+// 設定を反映する
+pub fn configure(mtu: usize) {
+    if mtu != DEFAULT_MTU {
+        info!("トンネルのMTU: {} bytes", mtu);
+    }
+    MTU.store(mtu, Ordering::Relaxed);
+}
+
+pub fn mtu() -> usize {
+    MTU.load(Ordering::Relaxed)
+}
+
+// 分割に必要なIPヘッダの情報
+struct IpLayout {
+    src_ip: IpAddr,
+    dst_ip: IpAddr,
+    header_len: usize,
+    // IPヘッダを含むパケット長 (フレームの末尾のパディングを除く)
+    packet_len: usize,
+}
+
+fn ip_layout(ip_packet: &[u8]) -> Option<IpLayout> {
+    match ip_packet.first()? >> 4 {
+        4 => {
+            let header_len = (ip_packet[0] & 0x0F) as usize * 4;
+            if header_len < Ipv4Header::LEN || ip_packet.len() < header_len || ip_packet[9] != TCP_PROTOCOL {
+                return None;
+            }
+            // 断片化されたパケットは分割しない
+            if u16::from_be_bytes([ip_packet[6], ip_packet[7]]) & 0x3FFF != 0 {
+                return None;
+            }
+            // 64KBを超えるGSOフレームは全長が0になるため、フレームの長さを使用する
+            let total_len = u16::from_be_bytes([ip_packet[2], ip_packet[3]]) as usize;
+            let packet_len = if total_len >= header_len { total_len.min(ip_packet.len()) } else { ip_packet.len() };
+            let src_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&ip_packet[12..16]).ok()?);
+            let dst_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&ip_packet[16..20]).ok()?);
+            Some(IpLayout { src_ip: src_ip.into(), dst_ip: dst_ip.into(), header_len, packet_len })
+        }
+        // 拡張ヘッダを含むパケットは分割しない
+        6 if ip_packet.len() >= Ipv6Header::LEN && ip_packet[6] == TCP_PROTOCOL => {
+            let payload_len = u16::from_be_bytes([ip_packet[4], ip_packet[5]]) as usize;
+            let packet_len = match payload_len {
+                0 => ip_packet.len(),
+                len => (Ipv6Header::LEN + len).min(ip_packet.len()),
+            };
+            let src_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&ip_packet[8..24]).ok()?);
+            let dst_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&ip_packet[24..40]).ok()?);
+            Some(IpLayout { src_ip: src_ip.into(), dst_ip: dst_ip.into(), header_len: Ipv6Header::LEN, packet_len })
+        }
+        _ => None,
+    }
+}
+
+// IPパケットがmtuを超えるTCPフレームを、mtuに収まるフレームに分割する。分割の対象でない場合はNone
+pub fn segment_tcp(frame: &[u8], mtu: usize) -> Option<Vec<Bytes>> {
+    let ether_type = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+    if ether_type != 0x0800 && ether_type != 0x86DD {
+        return None;
+    }
+    let ip_packet = &frame[EthernetHeader::LEN..];
+    let layout = ip_layout(ip_packet)?;
+    if layout.packet_len <= mtu {
+        return None;
+    }
+    let ip_packet = &ip_packet[..layout.packet_len];
+    let tcp_offset = layout.header_len;
+    let tcp_header_len = (*ip_packet.get(tcp_offset + 12)? >> 4) as usize * 4;
+    if tcp_header_len < 20 || ip_packet.len() < tcp_offset + tcp_header_len {
+        return None;
+    }
+    let headers_len = tcp_offset + tcp_header_len;
+    let mss = mtu.checked_sub(headers_len).filter(|&mss| mss > 0)?;
+
+    let tcp_header = &ip_packet[tcp_offset..headers_len];
+    let payload = &ip_packet[headers_len..];
+    let sequence = u32::from_be_bytes([tcp_header[4], tcp_header[5], tcp_header[6], tcp_header[7]]);
+    let identification = u16::from_be_bytes([ip_packet[4], ip_packet[5]]);
+    let count = payload.len().div_ceil(mss);
+
+    let segments = payload
+        .chunks(mss)
+        .enumerate()
+        .map(|(index, piece)| {
+            let mut segment = FRAME_POOL.get();
+            segment.extend_from_slice(&frame[..EthernetHeader::LEN]);
+            segment.extend_from_slice(&ip_packet[..headers_len]);
+            segment.extend_from_slice(piece);
+
+            let ip = &mut segment[EthernetHeader::LEN..];
+            if layout.src_ip.is_ipv4() {
+                ip[2..4].copy_from_slice(&((headers_len + piece.len()) as u16).to_be_bytes());
+                ip[4..6].copy_from_slice(&identification.wrapping_add(index as u16).to_be_bytes());
+                ip[10..12].fill(0);
+                let checksum = internet_checksum(&[&ip[..tcp_offset]]);
+                ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            } else {
+                ip[4..6].copy_from_slice(&((tcp_header_len + piece.len()) as u16).to_be_bytes());
+            }
+
+            let (_, tcp) = ip.split_at_mut(tcp_offset);
+            let (header, piece) = tcp.split_at_mut(tcp_header_len);
+            header[4..8].copy_from_slice(&sequence.wrapping_add((index * mss) as u32).to_be_bytes());
+            // FINとPSHは最後のセグメント、CWRは最初のセグメントにのみ付ける
+            if index + 1 < count {
+                header[13] &= !(TCP_FIN | TCP_PSH);
+            }
+            if index > 0 {
+                header[13] &= !TCP_CWR;
+            }
+            header[16..18].fill(0);
+            let checksum = transport_checksum(layout.src_ip, layout.dst_ip, TCP_PROTOCOL, header, piece);
+            header[16..18].copy_from_slice(&checksum.to_be_bytes());
+            segment.freeze()
+        })
+        .collect();
+    Some(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_header::{parse_ip_header, TcpHeader};
+
+    fn tcp_frame(src: IpAddr, dst: IpAddr, payload: &[u8]) -> Vec<u8> {
+        let mut tcp = TcpHeader::new(40000, 443, TcpHeader::ACK | TcpHeader::PSH | TcpHeader::FIN);
+        tcp.sequence = u32::MAX - 100;
+        tcp.options = vec![1, 1, 8, 10, 0, 0, 0, 1, 0, 0, 0, 2];
+        let tcp = tcp.to_bytes_with_checksum(src, dst, payload);
+        let length = (tcp.len() + payload.len()) as u16;
+        let (ether_type, ip) = match (src, dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => (0x0800, Ipv4Header::new(src, dst, 6, length).to_bytes().to_vec()),
+            (IpAddr::V6(src), IpAddr::V6(dst)) => (0x86DD, Ipv6Header::new(src, dst, 6, length).to_bytes().to_vec()),
+            _ => unreachable!(),
+        };
+        let mut frame = EthernetHeader { dst_mac: [2; 6], src_mac: [4; 6], ether_type }.to_bytes().to_vec();
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&tcp);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn splits_super_frame_into_valid_segments() {
+        let payload = (0..4000u32).map(|i| i as u8).collect::<Vec<_>>();
+        for (src, dst) in [
+            (IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])),
+            ("2001:db8::1".parse().unwrap(), "2001:db8::2".parse().unwrap()),
+        ] {
+            let segments = segment_tcp(&tcp_frame(src, dst, &payload), DEFAULT_MTU).unwrap();
+            let mut reassembled = Vec::new();
+            let mut next_sequence = u32::MAX - 100;
+            for (index, segment) in segments.iter().enumerate() {
+                let ip = &segment[EthernetHeader::LEN..];
+                assert!(ip.len() <= DEFAULT_MTU);
+                let header_len = if src.is_ipv4() { Ipv4Header::LEN } else { Ipv6Header::LEN };
+                if src.is_ipv4() {
+                    assert_eq!(internet_checksum(&[&ip[..header_len]]), 0);
+                }
+                let header = parse_ip_header(ip).unwrap();
+                let (tcp, piece) = ip[header_len..].split_at(32);
+                assert_eq!(transport_checksum(header.src_ip, header.dst_ip, 6, tcp, piece), 0);
+                assert_eq!(u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]), next_sequence);
+                let last = index + 1 == segments.len();
+                assert_eq!(tcp[13] & (TCP_FIN | TCP_PSH) != 0, last);
+                next_sequence = next_sequence.wrapping_add(piece.len() as u32);
+                reassembled.extend_from_slice(piece);
+            }
+            assert_eq!(segments.len(), 3);
+            assert_eq!(reassembled, payload);
+        }
+    }
+
+    #[test]
+    fn ignores_frames_within_mtu() {
+        let frame = tcp_frame(IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2]), &[0; 100]);
+        assert!(segment_tcp(&frame, DEFAULT_MTU).is_none());
+        assert!(segment_tcp(&frame[..20], DEFAULT_MTU).is_none());
+    }
+}
@@ -1,9 +1,58 @@
+use crate::config::DeviceConfig;
+use ipnetwork::IpNetwork;
 use pnet::datalink::{self, NetworkInterface};
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 
-pub fn select_device() -> Result<NetworkInterface, String> {
+pub fn select_device(config: &DeviceConfig) -> Result<NetworkInterface, String> {
     let interfaces = datalink::interfaces();
 
+    if config.is_empty() {
+        // 端末が無い環境 (systemdなど) では入力を待てないため、設定を必須とする
+        if !io::stdin().is_terminal() {
+            return Err("キャプチャするデバイスが指定されていません ([device] name / subnet / mac)".to_string());
+        }
+        return prompt_device(interfaces);
+    }
+
+    let candidates = interfaces
+        .into_iter()
+        .filter(|interface| matches(config, interface))
+        .collect::<Vec<_>>();
+
+    match candidates.as_slice() {
+        [interface] => Ok(interface.clone()),
+        [] => Err(format!("条件に一致するデバイスがありません: {}", config)),
+        _ => Err(format!(
+            "条件に一致するデバイスが複数あります ({}): {}",
+            config,
+            candidates.iter().map(|interface| interface.name.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    }
+}
+
+// 指定された条件を全て満たすか
+fn matches(config: &DeviceConfig, interface: &NetworkInterface) -> bool {
+    let name_matches = config.name.as_ref().is_none_or(|name| interface.name == *name);
+    let subnet_matches = config.subnet.is_none_or(|subnet| {
+        interface.ips.iter().any(|ip| subnet.contains(ip.ip()))
+    });
+    let mac_matches = config.mac.as_ref().is_none_or(|pattern| {
+        interface.mac.is_some_and(|mac| mac_matches(pattern, &mac.to_string()))
+    });
+    name_matches && subnet_matches && mac_matches
+}
+
+// "aa:bb:cc:*" のように末尾の * で前方一致する (大文字小文字は区別しない)
+fn mac_matches(pattern: &str, mac: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let mac = mac.to_ascii_lowercase();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => mac.starts_with(prefix),
+        None => mac == pattern,
+    }
+}
+
+fn prompt_device(interfaces: Vec<NetworkInterface>) -> Result<NetworkInterface, String> {
     println!("\n利用可能なネットワークインターフェース:");
     for (idx, interface) in interfaces.iter().enumerate() {
         println!("{}. {} ({})",
@@ -27,4 +76,22 @@ pub fn select_device() -> Result<NetworkInterface, String> {
     }
 
     Ok(interfaces[selection - 1].clone())
-}
\ No newline at end of file
+}
+
+// CLIで指定された値で設定を上書きする
+pub fn apply_overrides(
+    config: &mut DeviceConfig,
+    name: Option<String>,
+    subnet: Option<IpNetwork>,
+    mac: Option<String>,
+) {
+    if name.is_some() {
+        config.name = name;
+    }
+    if subnet.is_some() {
+        config.subnet = subnet;
+    }
+    if mac.is_some() {
+        config.mac = mac;
+    }
+}
@@ -0,0 +1,200 @@
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::interval;
+use tracing::warn;
+
+// 書き込むパケットに (送信元, 宛先) ごとの連番を付け、受信側で欠落と重複を数える。
+// 連番の上位32ビットは送信側の起動時刻 (秒)、下位32ビットはカウンタとし、送信側の再起動を区別する
+
+// 連番を管理する (送信元, 宛先) の組の上限 (これを超えた新しい組には連番を付けない)
+const MAX_TRACKED_FLOWS: usize = 4096;
+// 前後して届いたパケットを欠落・重複と区別するために記録する直近の連番の数
+const WINDOW: u32 = 64;
+
+lazy_static! {
+    static ref EPOCH: u32 = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs() as u32).unwrap_or(0);
+    static ref OUTGOING: DashMap<(IpAddr, IpAddr), AtomicU32> = DashMap::new();
+    static ref INCOMING: DashMap<(IpAddr, IpAddr), Mutex<ReceiveWindow>> = DashMap::new();
+}
+
+// 送信元ノードごとの受信状況 (起動時からの累計)
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DeliveryTotals {
+    pub received: u64,
+    // 連番の欠落から推定した損失数 (遅れて届いた分は差し引く)
+    pub lost: u64,
+    pub duplicates: u64,
+}
+
+impl DeliveryTotals {
+    // 前回からの増分に対する損失率 (%)
+    fn loss_percent_since(&self, previous: &DeliveryTotals) -> Option<f64> {
+        let lost = self.lost.saturating_sub(previous.lost);
+        let expected = self.received.saturating_sub(previous.received) + lost;
+        (expected > 0).then(|| lost as f64 / expected as f64 * 100.0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerDelivery {
+    pub peer: IpAddr,
+    #[serde(flatten)]
+    pub totals: DeliveryTotals,
+}
+
+// 1つの (送信元, 宛先) の組の受信状況
+#[derive(Debug)]
+struct ReceiveWindow {
+    epoch: u32,
+    highest: u32,
+    // ビットiが1の場合、highest - i を受信済み
+    received_bits: u64,
+    totals: DeliveryTotals,
+}
+
+impl ReceiveWindow {
+    fn new(epoch: u32, counter: u32) -> Self {
+        Self {
+            epoch,
+            highest: counter,
+            received_bits: 1,
+            totals: DeliveryTotals { received: 1, ..Default::default() },
+        }
+    }
+
+    fn observe(&mut self, epoch: u32, counter: u32) {
+        // 送信側が再起動した場合は、それまでの累計を引き継いで数え直す
+        if epoch != self.epoch {
+            let totals = self.totals;
+            *self = Self::new(epoch, counter);
+            self.totals.received += totals.received;
+            self.totals.lost = totals.lost;
+            self.totals.duplicates = totals.duplicates;
+            return;
+        }
+
+        let ahead = counter.wrapping_sub(self.highest) as i32;
+        if ahead > 0 {
+            let ahead = ahead as u32;
+            self.totals.lost += (ahead - 1) as u64;
+            self.received_bits = if ahead >= u64::BITS { 0 } else { self.received_bits << ahead };
+            self.received_bits |= 1;
+            self.highest = counter;
+            self.totals.received += 1;
+            return;
+        }
+
+        let behind = ahead.unsigned_abs();
+        if behind >= WINDOW {
+            // 記録範囲より古いパケットは欠落として数えた分を差し引けないため、受信数のみ数える
+            self.totals.received += 1;
+            return;
+        }
+        let bit = 1u64 << behind;
+        if self.received_bits & bit != 0 {
+            self.totals.duplicates += 1;
+        } else {
+            // 欠落として数えたパケットが遅れて届いた
+            self.received_bits |= bit;
+            self.totals.lost = self.totals.lost.saturating_sub(1);
+            self.totals.received += 1;
+        }
+    }
+}
+
+// 書き込むパケットの連番を採番する
+pub fn next(src_ip: IpAddr, dst_ip: IpAddr) -> Option<i64> {
+    let key = (src_ip, dst_ip);
+    let counter = match OUTGOING.get(&key) {
+        Some(counter) => counter.fetch_add(1, Ordering::Relaxed),
+        None if OUTGOING.len() < MAX_TRACKED_FLOWS => OUTGOING.entry(key).or_default().fetch_add(1, Ordering::Relaxed),
+        None => return None,
+    };
+    Some(((*EPOCH as i64) << 32) | counter as i64)
+}
+
+// 受信したパケットの連番を記録する
+pub fn observe(src_ip: IpAddr, dst_ip: IpAddr, seq: i64) {
+    let (epoch, counter) = ((seq >> 32) as u32, seq as u32);
+    let key = (src_ip, dst_ip);
+    if let Some(window) = INCOMING.get(&key) {
+        window.lock().unwrap_or_else(|e| e.into_inner()).observe(epoch, counter);
+    } else if INCOMING.len() < MAX_TRACKED_FLOWS {
+        INCOMING.entry(key).or_insert_with(|| Mutex::new(ReceiveWindow::new(epoch, counter)));
+    }
+}
+
+// 送信元ノードごとの受信状況
+pub fn peer_totals() -> Vec<PeerDelivery> {
+    let mut peers: BTreeMap<IpAddr, DeliveryTotals> = BTreeMap::new();
+    for entry in INCOMING.iter() {
+        let window = entry.value().lock().unwrap_or_else(|e| e.into_inner());
+        let totals = peers.entry(entry.key().0).or_default();
+        totals.received += window.totals.received;
+        totals.lost += window.totals.lost;
+        totals.duplicates += window.totals.duplicates;
+    }
+    peers.into_iter().map(|(peer, totals)| PeerDelivery { peer, totals }).collect()
+}
+
+// 区間内の損失率が閾値を超えた送信元ノードを定期的に警告する
+pub async fn start_loss_reporter(period: Duration, threshold_percent: f64) {
+    let mut interval_timer = interval(period);
+    interval_timer.tick().await;
+    let mut previous: HashMap<IpAddr, DeliveryTotals> = HashMap::new();
+
+    loop {
+        interval_timer.tick().await;
+        for PeerDelivery { peer, totals } in peer_totals() {
+            let last = previous.insert(peer, totals).unwrap_or_default();
+            match totals.loss_percent_since(&last) {
+                Some(loss) if loss > threshold_percent => warn!(
+                    "{} からのパケットの損失率が {:.1}% です (欠落 {}, 受信 {}, 重複 {})",
+                    peer,
+                    loss,
+                    totals.lost.saturating_sub(last.lost),
+                    totals.received.saturating_sub(last.received),
+                    totals.duplicates.saturating_sub(last.duplicates)
+                ),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_gaps_late_arrivals_and_duplicates() {
+        let mut window = ReceiveWindow::new(1, 0);
+        for counter in [1, 4, 5, 3, 5] {
+            window.observe(1, counter);
+        }
+        // 2が欠落、3は遅れて届き、5は重複
+        assert_eq!(window.totals.received, 5);
+        assert_eq!(window.totals.lost, 1);
+        assert_eq!(window.totals.duplicates, 1);
+
+        // 送信側の再起動では欠落として数えない
+        window.observe(2, 0);
+        window.observe(2, 1);
+        assert_eq!(window.totals.received, 7);
+        assert_eq!(window.totals.lost, 1);
+    }
+
+    #[test]
+    fn counter_wraps_around() {
+        let mut window = ReceiveWindow::new(1, u32::MAX);
+        window.observe(1, 1);
+        assert_eq!(window.totals.lost, 1);
+        assert_eq!(window.highest, 1);
+    }
+}
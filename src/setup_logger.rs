@@ -1,31 +1,130 @@
-use env_logger::{Builder, Target};
-use log::LevelFilter;
-use std::fs::File;
-use std::io::Write;
-
-pub fn setup_logger() -> Result<(), Box<dyn std::error::Error>> {
-    // ログファイルを開く
-    let file = File::create("application.log")?;
-
-    // ビルダーでロガーをカスタマイズ
-    Builder::new()
-        // ログレベルの設定
-        .filter_level(LevelFilter::Info)
-        // タイムスタンプ付きのフォーマット
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "{} [{}] {} - {}",
-                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                record.target(),  // モジュールパスが表示される
-                record.args()
-            )
-        })
+use crate::config::{LogConfig, LogFormat};
+use crate::messages;
+use crate::rotating_file::RotatingFileWriter;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+use tracing_subscriber::fmt::time::{ChronoLocal, ChronoUtc};
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+type FilterHandle = reload::Handle<EnvFilter, Layered<Vec<BoxedLayer>, Registry>>;
+
+// 実行中にログフィルタを変更するためのハンドル
+static FILTER_HANDLE: OnceLock<FilterHandle> = OnceLock::new();
+
+fn format_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'a> fmt::MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer()
+            .with_timer(ChronoLocal::new("%Y-%m-%d %H:%M:%S".to_string()))
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        // timestamp, level, target (モジュール), fields, 現在のスパンを1行のJSONとして出力する
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_timer(ChronoUtc::rfc_3339())
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+pub fn setup_logger(config: &LogConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let format = match dotenv::var("LOG_FORMAT") {
+        Ok(format) if !format.is_empty() => format.parse::<LogFormat>()?,
+        _ => config.format,
+    };
+    messages::configure(config)?;
+
+    // ログレベルの設定 (RUST_LOGで上書き可能)
+    let filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.level))?;
+
+    let mut layers = Vec::new();
+    if config.stdout {
+        // 標準出力
+        layers.push(format_layer(format, std::io::stdout, true));
+    }
+    if !config.file.as_os_str().is_empty() {
         // ファイルに出力
-        .target(Target::Pipe(Box::new(file)))
-        .target(Target::Stdout)
-        .init();
+        let writer = RotatingFileWriter::new(config.file.clone(), config)?;
+        layers.push(format_layer(format, Mutex::new(writer), false));
+    }
+
+    let (filter, handle) = reload::Layer::new(filter);
 
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()?;
+
+    let _ = FILTER_HANDLE.set(handle);
     Ok(())
-}
\ No newline at end of file
+}
+
+// ログフィルタを再起動せずに変更する (例: "info,rdb_tunnel::db_read=trace")
+pub fn set_log_filter(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    let handle = FILTER_HANDLE.get().ok_or("ロガーが初期化されていません")?;
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    info!("ログフィルタを変更しました: {}", directives);
+    Ok(())
+}
+
+// 現在のログフィルタ
+pub fn current_log_filter() -> Option<String> {
+    FILTER_HANDLE.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+// 同じ原因で繰り返し発生するログ (ポーリングや書き込みの失敗など) を一定間隔に1回に抑える
+pub struct LogThrottle {
+    interval: Duration,
+    // 前回出力した時刻と、その後に抑制した件数
+    state: Mutex<(Option<Instant>, u64)>,
+}
+
+impl LogThrottle {
+    pub const fn new(interval: Duration) -> Self {
+        Self { interval, state: Mutex::new((None, 0)) }
+    }
+
+    // 出力してよい場合は、前回の出力から抑制した件数を返す
+    pub fn allow(&self) -> Option<u64> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        match state.0 {
+            Some(last) if now.duration_since(last) < self.interval => {
+                state.1 += 1;
+                None
+            }
+            _ => {
+                let suppressed = std::mem::take(&mut state.1);
+                state.0 = Some(now);
+                Some(suppressed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_repeated_logs_within_interval() {
+        let throttle = LogThrottle::new(Duration::from_secs(60));
+        assert_eq!(throttle.allow(), Some(0));
+        assert_eq!(throttle.allow(), None);
+        assert_eq!(throttle.allow(), None);
+
+        let throttle = LogThrottle::new(Duration::ZERO);
+        assert_eq!(throttle.allow(), Some(0));
+        assert_eq!(throttle.allow(), Some(0));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::config::ShaperConfig;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// 送信元ごとに帯域を管理する対向ノード数の上限 (これを超えたノードには全体の上限のみ適用する)
+const MAX_SHAPED_PEERS: usize = 1024;
+// バイト数の上限が小さくても、最大長のフレームを通せるだけのバーストを確保する
+const MAX_FRAME_SIZE: f64 = 1514.0;
+
+// 注入するパケットの帯域制御。設定されていない場合は制限しない
+static SHAPER: RwLock<Option<Shaper>> = RwLock::new(None);
+
+// トークンバケット。不足分は後続のパケットの待ち時間として前借りする
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst: Duration, min_capacity: f64, now: Instant) -> Self {
+        let capacity = (rate as f64 * burst.as_secs_f64()).max(min_capacity);
+        Self { rate: rate as f64, capacity, tokens: capacity, updated_at: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    // amountを消費した場合に、トークンが貯まるまで待つ時間
+    fn delay(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+        let shortage = amount - self.tokens;
+        if shortage <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(shortage / self.rate)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+}
+
+// バイト数とパケット数の上限の組
+#[derive(Debug, Default)]
+struct Limits {
+    bytes: Option<TokenBucket>,
+    packets: Option<TokenBucket>,
+}
+
+impl Limits {
+    fn new(bytes_per_sec: Option<u64>, packets_per_sec: Option<u64>, burst: Duration, now: Instant) -> Self {
+        Self {
+            bytes: bytes_per_sec.map(|rate| TokenBucket::new(rate, burst, MAX_FRAME_SIZE, now)),
+            packets: packets_per_sec.map(|rate| TokenBucket::new(rate, burst, 1.0, now)),
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.bytes.is_none() && self.packets.is_none()
+    }
+
+    fn delay(&mut self, bytes: usize, now: Instant) -> Duration {
+        let bytes = self.bytes.as_mut().map_or(Duration::ZERO, |bucket| bucket.delay(bytes as f64, now));
+        let packets = self.packets.as_mut().map_or(Duration::ZERO, |bucket| bucket.delay(1.0, now));
+        bytes.max(packets)
+    }
+
+    fn take(&mut self, bytes: usize) {
+        if let Some(bucket) = &mut self.bytes {
+            bucket.take(bytes as f64);
+        }
+        if let Some(bucket) = &mut self.packets {
+            bucket.take(1.0);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shaper {
+    config: ShaperConfig,
+    state: Mutex<ShaperState>,
+}
+
+#[derive(Debug)]
+struct ShaperState {
+    global: Limits,
+    peers: HashMap<IpAddr, Limits>,
+}
+
+impl Shaper {
+    fn new(config: &ShaperConfig) -> Self {
+        let global = Limits::new(config.bytes_per_sec, config.packets_per_sec, config.burst, Instant::now());
+        Self { config: config.clone(), state: Mutex::new(ShaperState { global, peers: HashMap::new() }) }
+    }
+
+    fn reserve(&self, peer: IpAddr, bytes: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let ShaperState { global, peers } = &mut *state;
+
+        if !peers.contains_key(&peer) && peers.len() < MAX_SHAPED_PEERS {
+            let config = &self.config;
+            let limits = Limits::new(config.peer_bytes_per_sec, config.peer_packets_per_sec, config.burst, now);
+            if !limits.is_unlimited() {
+                peers.insert(peer, limits);
+            }
+        }
+        let mut peer_limits = peers.get_mut(&peer);
+
+        let peer_delay = peer_limits.as_mut().map_or(Duration::ZERO, |limits| limits.delay(bytes, now));
+        let delay = global.delay(bytes, now).max(peer_delay);
+        // 待ち時間が上限を超える場合は、トークンを消費せずに破棄する
+        if delay > self.config.max_delay {
+            return None;
+        }
+        global.take(bytes);
+        if let Some(limits) = peer_limits {
+            limits.take(bytes);
+        }
+        Some(delay)
+    }
+}
+
+// 設定を反映する (設定の再読み込み時は、それまでのトークンの状態を破棄する)
+pub fn configure(config: &ShaperConfig) {
+    let shaper = config.is_enabled().then(|| Shaper::new(config));
+    if shaper.is_some() {
+        info!(
+            "注入の帯域制御: 全体 {:?} bytes/s, {:?} pps / ノードごと {:?} bytes/s, {:?} pps (バースト {:?})",
+            config.bytes_per_sec,
+            config.packets_per_sec,
+            config.peer_bytes_per_sec,
+            config.peer_packets_per_sec,
+            config.burst
+        );
+    }
+    *SHAPER.write().unwrap_or_else(|e| e.into_inner()) = shaper;
+}
+
+// peerから届いたbytesのパケットを注入するまでに待つ時間。待ち時間が上限を超える場合はNone (破棄する)
+pub fn reserve(peer: IpAddr, bytes: usize) -> Option<Duration> {
+    match &*SHAPER.read().unwrap_or_else(|e| e.into_inner()) {
+        Some(shaper) => shaper.reserve(peer, bytes),
+        None => Some(Duration::ZERO),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delays_packets_beyond_burst() {
+        let now = Instant::now();
+        let mut limits = Limits::new(None, Some(10), Duration::from_millis(200), now);
+        // バースト (2パケット分) までは待たずに通す
+        for _ in 0..2 {
+            assert_eq!(limits.delay(100, now), Duration::ZERO);
+            limits.take(100);
+        }
+        assert_eq!(limits.delay(100, now), Duration::from_millis(100));
+        limits.take(100);
+        assert_eq!(limits.delay(100, now), Duration::from_millis(200));
+        // 時間が経過するとトークンが貯まる
+        assert_eq!(limits.delay(100, now + Duration::from_millis(300)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,24 @@
+use ipnetwork::IpNetwork;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use tracing::info;
+
+// スプリットトンネル。対向ノード側のサブネット宛のパケットだけを書き込み、それ以外はトンネルに流さない
+
+// 書き込む宛先のサブネット。空の場合は全てのパケットを書き込む
+static ALLOWED_SUBNETS: RwLock<Vec<IpNetwork>> = RwLock::new(Vec::new());
+
+// 設定を反映する
+pub fn configure(subnets: &[IpNetwork]) {
+    if !subnets.is_empty() {
+        let list = subnets.iter().map(|network| network.to_string()).collect::<Vec<_>>();
+        info!("トンネルに流す宛先のサブネット: {}", list.join(", "));
+    }
+    *ALLOWED_SUBNETS.write().unwrap_or_else(|e| e.into_inner()) = subnets.to_vec();
+}
+
+// 宛先がトンネルに流す対象かどうか (ARPは問い合わせ対象のアドレスで判定する)
+pub fn is_tunneled(dst_ip: IpAddr) -> bool {
+    let subnets = ALLOWED_SUBNETS.read().unwrap_or_else(|e| e.into_inner());
+    subnets.is_empty() || subnets.iter().any(|network| network.contains(dst_ip))
+}
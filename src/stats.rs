@@ -0,0 +1,146 @@
+use crate::database::database::{Database, PgPool};
+use crate::metrics::{self, LatencyTotals, StatsSnapshot};
+use crate::traffic::TrafficTotals;
+use serde::{Serialize, Serializer};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+use tracing::info;
+
+// コネクションプールの使用状況
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub name: &'static str,
+    pub connections: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    // 待機せずに取得できた回数
+    pub get_direct: u64,
+    // 空きを待って取得した回数
+    pub get_waited: u64,
+    // 待機中にタイムアウトした回数
+    pub get_timed_out: u64,
+    // 接続の取得待ちに費やした累計時間
+    #[serde(rename = "total_wait_time_ms", serialize_with = "serialize_millis")]
+    pub total_wait_time: Duration,
+}
+
+impl PoolStats {
+    pub fn from_pool(name: &'static str, pool: &PgPool) -> Self {
+        let state = pool.state();
+        Self {
+            name,
+            connections: state.connections,
+            idle: state.idle_connections,
+            in_use: state.connections.saturating_sub(state.idle_connections),
+            get_direct: state.statistics.get_direct,
+            get_waited: state.statistics.get_waited,
+            get_timed_out: state.statistics.get_timed_out,
+            total_wait_time: state.statistics.get_wait_time,
+        }
+    }
+
+    // 待機が発生した取得1回あたりの平均待ち時間
+    pub fn average_wait_time(&self) -> Duration {
+        if self.get_waited == 0 {
+            return Duration::ZERO;
+        }
+        self.total_wait_time / self.get_waited as u32
+    }
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+// プールの使用状況を定期的にログへ出力する
+pub async fn start_pool_stats_reporter(db: Arc<Database>, period: Duration) {
+    let mut interval_timer = interval(period);
+    interval_timer.tick().await;
+
+    loop {
+        interval_timer.tick().await;
+
+        for stats in db.pool_stats() {
+            info!(
+                "コネクションプール[{}]: 接続数 {} (使用中 {}, アイドル {}), 即時取得 {}, 待機あり取得 {}, タイムアウト {}, 平均待ち時間 {}ms",
+                stats.name,
+                stats.connections,
+                stats.in_use,
+                stats.idle,
+                stats.get_direct,
+                stats.get_waited,
+                stats.get_timed_out,
+                stats.average_wait_time().as_millis()
+            );
+        }
+    }
+}
+
+// 全プロトコルの合計 (送信, 受信)
+fn traffic_totals(snapshot: &StatsSnapshot) -> (TrafficTotals, TrafficTotals) {
+    snapshot.protocols.values().fold(Default::default(), |(outbound, inbound), totals| {
+        (
+            TrafficTotals {
+                packets: outbound.packets + totals.outbound.packets,
+                bytes: outbound.bytes + totals.outbound.bytes,
+            },
+            TrafficTotals {
+                packets: inbound.packets + totals.inbound.packets,
+                bytes: inbound.bytes + totals.inbound.bytes,
+            },
+        )
+    })
+}
+
+// 破棄したパケット数 (ファイアウォールでの遮断を含む)
+fn dropped_total(snapshot: &StatsSnapshot) -> u64 {
+    snapshot.packets_dropped.values().sum::<u64>() + snapshot.firewall_drops
+}
+
+// 前回からの平均レイテンシ (ミリ秒)。区間内に計測がない場合は "-"
+fn average_latency_ms(current: &LatencyTotals, previous: &LatencyTotals) -> String {
+    let count = current.count.saturating_sub(previous.count);
+    if count == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}ms", (current.sum_secs - previous.sum_secs) / count as f64 * 1000.0)
+}
+
+// Prometheusを用意しなくても状況を把握できるよう、区間内の統計を1行で定期的にログへ出力する
+pub async fn start_stats_summary_reporter(period: Duration) {
+    let mut interval_timer = interval(period);
+    interval_timer.tick().await;
+    let mut previous = metrics::snapshot(None);
+    let mut previous_at = Instant::now();
+
+    loop {
+        interval_timer.tick().await;
+        let current = metrics::snapshot(None);
+        let elapsed = previous_at.elapsed().as_secs_f64().max(f64::EPSILON);
+        previous_at = Instant::now();
+
+        let (outbound, inbound) = traffic_totals(&current);
+        let (previous_outbound, previous_inbound) = traffic_totals(&previous);
+        let pps = |current: &TrafficTotals, previous: &TrafficTotals| {
+            current.packets.saturating_sub(previous.packets) as f64 / elapsed
+        };
+        let mbps = |current: &TrafficTotals, previous: &TrafficTotals| {
+            current.bytes.saturating_sub(previous.bytes) as f64 * 8.0 / elapsed / 1_000_000.0
+        };
+
+        info!(
+            "統計: 送信 {:.0} pps ({:.2} Mbps), 受信 {:.0} pps ({:.2} Mbps), 書き込み待ち {}, DB挿入 {}, 破棄 {}, アラート {}",
+            pps(&outbound, &previous_outbound),
+            mbps(&outbound, &previous_outbound),
+            pps(&inbound, &previous_inbound),
+            mbps(&inbound, &previous_inbound),
+            current.buffer_depth,
+            average_latency_ms(&current.db_insert_latency, &previous.db_insert_latency),
+            dropped_total(&current).saturating_sub(dropped_total(&previous)),
+            current.idps_alerts.saturating_sub(previous.idps_alerts)
+        );
+
+        previous = current;
+    }
+}
@@ -0,0 +1,74 @@
+use crate::events::{self, PacketSummary, PipelineEvent};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Query;
+use axum::response::IntoResponse;
+use serde::Deserialize;
+use std::net::IpAddr;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+// 配信するパケットの絞り込み条件 (指定しない項目は全て一致とみなす)
+#[derive(Debug, Default, Deserialize)]
+pub struct PacketFilter {
+    // 送信元または宛先のIPアドレス
+    ip: Option<IpAddr>,
+    // 送信元または宛先のポート
+    port: Option<u16>,
+    // IPプロトコル番号 (6: TCP, 17: UDP など)
+    protocol: Option<u8>,
+}
+
+impl PacketFilter {
+    fn matches(&self, packet: &PacketSummary) -> bool {
+        self.ip.is_none_or(|ip| packet.src_ip == ip || packet.dst_ip == ip)
+            && self.port.is_none_or(|port| packet.src_port == port || packet.dst_port == port)
+            && self.protocol.is_none_or(|protocol| packet.ip_protocol == protocol)
+    }
+}
+
+// パケットの概要をJSONでリアルタイムに配信する
+// 例: /api/v1/stream/packets?ip=192.168.0.10&protocol=6
+pub async fn packets_handler(ws: WebSocketUpgrade, Query(filter): Query<PacketFilter>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| stream_packets(socket, filter))
+}
+
+async fn stream_packets(mut socket: WebSocket, filter: PacketFilter) {
+    debug!("パケットストリームの配信を開始します: {:?}", filter);
+    let mut receiver = events::subscribe();
+
+    loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let packet = match event {
+                    Ok(PipelineEvent::Packet(packet)) if filter.matches(&packet) => packet,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("パケットストリームの配信が追いつかないため {} 件を破棄しました", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let json = match serde_json::to_string(&packet) {
+                    Ok(json) => json,
+                    Err(e) => {
+                        warn!("パケット概要のシリアライズに失敗しました: {}", e);
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => {
+                // クライアントからの切断を検知する (受信したメッセージは使用しない)
+                match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+
+    debug!("パケットストリームの配信を終了しました");
+}
@@ -0,0 +1,167 @@
+use crate::config::RestartPolicy;
+use crate::error::{Error, Retryable};
+use crate::messages::{message, MessageId};
+use crate::metrics;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::future::Future;
+use std::panic::PanicHookInfo;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+use tracing::{error, warn, Instrument};
+
+// パイプラインのタスクを監視し、失敗した場合は方針に従って指数バックオフで再起動する。
+// DBやNICの一時的なエラーでトンネル全体が停止しないようにする
+
+// パニックをバックトレース付きでログに出力する (標準エラー出力の代わりに使用する)
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info: &PanicHookInfo| {
+        let location = info.location().map_or_else(|| "不明".to_string(), ToString::to_string);
+        let backtrace = Backtrace::force_capture();
+        error!("{}", message(MessageId::Panic, &[&panic_message(info.payload()), &location, &backtrace]));
+    }));
+}
+
+// パニックのペイロードからメッセージを取り出す
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("不明なパニック")
+}
+
+// タスクを別のtokioタスクで実行し、パニックをエラーとして返す。このFutureを破棄するとタスクも中止する
+async fn run_isolated<Fut>(label: &str, future: Fut) -> Result<(), Error>
+where
+    Fut: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    let mut tasks = JoinSet::new();
+    tasks.spawn(future.in_current_span());
+    match tasks.join_next().await {
+        Some(Ok(result)) => result,
+        Some(Err(e)) if e.is_panic() => {
+            metrics::TASK_PANICS.with_label_values(&[label]).inc();
+            Err(Error::Task(format!("パニックが発生しました: {}", panic_message(&*e.into_panic()))))
+        }
+        Some(Err(e)) => Err(Error::Task(e.to_string())),
+        None => Err(Error::Task("タスクが実行されませんでした".to_string())),
+    }
+}
+
+// 再起動までの待機時間。失敗が続くごとに倍にし、max_backoffで頭打ちにする
+pub fn backoff(policy: &RestartPolicy, restarts: u32) -> Duration {
+    policy
+        .initial_backoff
+        .checked_mul(1u32.checked_shl(restarts).unwrap_or(u32::MAX))
+        .map_or(policy.max_backoff, |delay| delay.min(policy.max_backoff))
+}
+
+// タスクを実行し、終了した場合は再起動する (labelはメトリクスのラベル)。
+// タスクは停止するまで動作し続けることを前提とするため、正常に終了した場合やパニックした場合も失敗として扱う。
+// 再試行しても回復しないエラー (Retryable) の場合、再起動しない方針の場合、再起動の回数が上限を超えた場合はエラーを返す
+pub async fn supervise<F, Fut>(task: &str, label: &str, policy: &RestartPolicy, mut run: F) -> Result<(), Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Error>> + Send + 'static,
+{
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        let reason = match run_isolated(label, run()).await {
+            Ok(()) => Error::Task("予期せず終了しました".to_string()),
+            Err(e) => e,
+        };
+        if started.elapsed() >= policy.reset_after {
+            restarts = 0;
+        }
+        if !reason.is_retryable() {
+            error!("{}", message(MessageId::TaskFatal, &[&task, &label, &reason]));
+            return Err(reason);
+        }
+        if !policy.restart || restarts >= policy.max_restarts {
+            error!("{}", message(MessageId::TaskFailed, &[&task, &label, &reason]));
+            return Err(reason);
+        }
+
+        let delay = backoff(policy, restarts);
+        restarts += 1;
+        metrics::TASK_RESTARTS.with_label_values(&[label]).inc();
+        warn!(
+            "{}",
+            message(
+                MessageId::TaskRestarting,
+                &[&task, &label, &reason, &delay.as_millis(), &restarts, &policy.max_restarts]
+            )
+        );
+        sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportError;
+
+    fn policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            max_restarts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn doubles_backoff_up_to_limit() {
+        let policy = policy(5);
+        let delays = (0..5).map(|restarts| backoff(&policy, restarts).as_millis()).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 4, 4]);
+        assert_eq!(backoff(&policy, 40), policy.max_backoff);
+    }
+
+    #[tokio::test]
+    async fn restarts_failed_task_until_limit() {
+        let mut runs = 0;
+        let result = supervise("テスト", "test", &policy(3), || {
+            runs += 1;
+            async { Err(Error::Task("failed".to_string())) }
+        })
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "failed");
+        assert_eq!(runs, 4);
+
+        // 再起動しない方針の場合は最初の失敗で終了する
+        let mut runs = 0;
+        let no_restart = RestartPolicy { restart: false, ..policy(3) };
+        let _ = supervise("テスト", "test", &no_restart, || {
+            runs += 1;
+            async { Ok(()) }
+        })
+        .await;
+        assert_eq!(runs, 1);
+
+        // 再試行しても回復しないエラーは再起動しない
+        let mut runs = 0;
+        let _ = supervise("テスト", "test", &policy(3), || {
+            runs += 1;
+            async { Err(TransportError::NoDatabase.into()) }
+        })
+        .await;
+        assert_eq!(runs, 1);
+    }
+
+    #[tokio::test]
+    async fn restarts_panicked_task() {
+        let mut runs = 0;
+        let result = supervise("テスト", "test", &policy(1), || {
+            runs += 1;
+            async { panic!("boom") }
+        })
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "パニックが発生しました: boom");
+        assert_eq!(runs, 2);
+        assert!(metrics::TASK_PANICS.with_label_values(&["test"]).get() >= 2);
+    }
+}
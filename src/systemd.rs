@@ -0,0 +1,79 @@
+use crate::health::{self, TaskState};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::interval;
+use tracing::{debug, warn};
+
+// systemdへ状態を通知する (NOTIFY_SOCKETが無い場合は何もしない)
+// 例: "READY=1", "STOPPING=1", "STATUS=..."
+#[cfg(target_os = "linux")]
+pub fn notify(state: &str) {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let result = (|| {
+        // "@"で始まる場合は抽象名前空間のソケット
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)
+    })();
+
+    match result {
+        Ok(_) => debug!("systemdへ通知しました: {}", state.replace('\n', " ")),
+        Err(e) => warn!("systemdへの通知に失敗しました ({}): {}", path, e),
+    }
+}
+
+// systemdはLinux専用のため、他のOSでは何もしない
+#[cfg(not(target_os = "linux"))]
+pub fn notify(_state: &str) {}
+
+pub fn ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={}", status));
+}
+
+pub fn stopping(status: &str) {
+    notify(&format!("STOPPING=1\nSTATUS={}", status));
+}
+
+// WatchdogSec= が設定されている場合のping間隔
+fn watchdog_timeout() -> Option<Duration> {
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    // WATCHDOG_PIDが指定されている場合は自プロセス宛の場合のみ有効
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+// パイプラインが動作している間だけWATCHDOG=1を送信する。
+// ループが停止するとpingが途絶え、systemdによって再起動される
+pub async fn start_watchdog(task_state: Arc<Mutex<TaskState>>) {
+    let Some(timeout) = watchdog_timeout() else {
+        return;
+    };
+    debug!("systemdのウォッチドッグを開始します: タイムアウト {:?}", timeout);
+
+    let mut interval_timer = interval(timeout / 2);
+    loop {
+        interval_timer.tick().await;
+
+        let (alive, _) = health::liveness(&task_state).await;
+        let heartbeat_fresh = health::pipeline_heartbeat_age().is_some_and(|age| age < timeout);
+        if alive && heartbeat_fresh {
+            notify("WATCHDOG=1");
+        } else {
+            warn!("パイプラインが応答していないため、ウォッチドッグへの通知を停止しています");
+        }
+    }
+}
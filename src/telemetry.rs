@@ -0,0 +1,96 @@
+use crate::config::TelemetryConfig;
+use crate::error::InitProcessError;
+use lazy_static::lazy_static;
+use opentelemetry::global::{self, BoxedTracer};
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::Histogram;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use std::time::Duration;
+use tracing::{error, info};
+
+const INSTRUMENTATION_NAME: &str = "rdb-tunnel";
+
+lazy_static! {
+    // キャプチャ時刻から注入までの時間 (ノード間の時刻のずれを含む)
+    pub static ref TUNNEL_LATENCY: Histogram<f64> = global::meter(INSTRUMENTATION_NAME)
+        .f64_histogram("rdb_tunnel.tunnel.latency")
+        .with_unit("s")
+        .with_description("Time from capture on the sending node to injection on this node")
+        .build();
+}
+
+// パイプラインのスパンを作成するトレーサー。テレメトリ無効時は何も記録しない
+pub fn tracer() -> BoxedTracer {
+    global::tracer(INSTRUMENTATION_NAME)
+}
+
+// 終了時に未送信のスパンとメトリクスを送信するためのハンドル
+pub struct TelemetryGuard {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl TelemetryGuard {
+    pub fn shutdown(&self) {
+        if let Err(e) = self.tracer_provider.shutdown() {
+            error!("トレースの送信終了に失敗しました: {}", e);
+        }
+        if let Err(e) = self.meter_provider.shutdown() {
+            error!("メトリクスの送信終了に失敗しました: {}", e);
+        }
+    }
+}
+
+pub fn init_telemetry(config: &TelemetryConfig) -> Result<Option<TelemetryGuard>, InitProcessError> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let endpoint = config.endpoint.trim_end_matches('/');
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone())
+        .with_attribute(KeyValue::new("host.name", hostname()))
+        .build();
+
+    let span_exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint))
+        .build()
+        .map_err(|e| InitProcessError::TelemetryError(e.to_string()))?;
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(span_exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio))))
+        .with_resource(resource.clone())
+        .build();
+
+    let metric_exporter = MetricExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/metrics", endpoint))
+        .build()
+        .map_err(|e| InitProcessError::TelemetryError(e.to_string()))?;
+    let reader = PeriodicReader::builder(metric_exporter)
+        .with_interval(Duration::from_secs(config.metrics_interval_secs.max(1)))
+        .build();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(resource)
+        .build();
+
+    global::set_tracer_provider(tracer_provider.clone());
+    global::set_meter_provider(meter_provider.clone());
+    info!("OpenTelemetryのエクスポートを開始しました: {}", endpoint);
+
+    Ok(Some(TelemetryGuard {
+        tracer_provider,
+        meter_provider,
+    }))
+}
+
+pub(crate) fn hostname() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .map(|name| name.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
@@ -0,0 +1,231 @@
+// テストで共有するフィクスチャとパイプラインの模擬環境
+use crate::db_read::{PacketError, PacketInfo, PacketPoller};
+use crate::packet_header::{EthernetHeader, Ipv4Header, Ipv6Header, TcpHeader, UdpHeader};
+use crate::transport::memory::MemoryTransport;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+pub const NODE_A: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+pub const NODE_B: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+// フィクスチャのUDPパケットの送信元ポート
+pub const UDP_SRC_PORT: u16 = 40000;
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// パケットバッファやトランスポートはプロセス共通のため、パイプライン全体のテストは順番に実行する
+pub static PIPELINE_LOCK: Mutex<()> = Mutex::const_new(());
+
+fn node_mac(ip: Ipv4Addr) -> [u8; 6] {
+    [0x02, 0, 0, 0, 0, ip.octets()[3]]
+}
+
+// IPv4/UDPのイーサネットフレーム (チェックサムは省略)
+pub fn udp_frame(src: Ipv4Addr, dst: Ipv4Addr, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+    let dst_mac = if dst.is_broadcast() { [0xff; 6] } else { node_mac(dst) };
+    let udp = UdpHeader { src_port: UDP_SRC_PORT, dst_port, payload_len: payload.len() as u16 };
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&EthernetHeader { dst_mac, src_mac: node_mac(src), ether_type: 0x0800 }.to_bytes());
+    frame.extend_from_slice(&Ipv4Header::new(src, dst, 17, (UdpHeader::LEN + payload.len()) as u16).to_bytes());
+    frame.extend_from_slice(&udp.to_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// senderがtargetのMACアドレスを問い合わせるARPリクエスト
+pub fn arp_request(sender: Ipv4Addr, target: Ipv4Addr) -> Vec<u8> {
+    let ethernet = EthernetHeader { dst_mac: [0xff; 6], src_mac: node_mac(sender), ether_type: 0x0806 };
+    let mut frame = ethernet.to_bytes().to_vec();
+    frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+    frame.extend_from_slice(&node_mac(sender));
+    frame.extend_from_slice(&sender.octets());
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&target.octets());
+    frame
+}
+
+// イーサネットヘッダに満たない不正なフレーム
+pub fn truncated_frame() -> Vec<u8> {
+    vec![0xff; 10]
+}
+
+// 受信側のパイプライン (subscribe -> 注入) を持つ模擬ノード
+pub struct Node {
+    injected: mpsc::UnboundedReceiver<PacketInfo>,
+    task: JoinHandle<Result<(), PacketError>>,
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Node {
+    // 受信の登録を済ませてから返すため、直後に送信したパケットも受信できる
+    pub fn start(transport: &MemoryTransport, ip: Ipv4Addr) -> Self {
+        let (sender, injected) = mpsc::unbounded_channel();
+        let poller = PacketPoller::with_channel(IpAddr::V4(ip), sender);
+        let receiver = transport.receiver();
+        let task = tokio::spawn(async move { MemoryTransport::forward(receiver, &poller).await });
+        Self { injected, task }
+    }
+
+    pub async fn receive(&mut self) -> PacketInfo {
+        timeout(RECEIVE_TIMEOUT, self.injected.recv())
+            .await
+            .expect("パケットが注入されませんでした")
+            .expect("注入先のチャネルが閉じられました")
+    }
+
+    pub fn assert_nothing_injected(&mut self) {
+        assert!(self.injected.try_recv().is_err(), "宛先以外のノードに注入されました");
+    }
+}
+
+// プロパティテストで生成するフレームの各層の値
+#[derive(Debug, Clone)]
+pub struct FrameSpec {
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    pub network: Network,
+    // ARPの場合は使用しない
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Network {
+    Ipv4 { src: Ipv4Addr, dst: Ipv4Addr, transport: Transport },
+    Ipv6 { src: Ipv6Addr, dst: Ipv6Addr, transport: Transport },
+    Arp { sender: Ipv4Addr, target: Ipv4Addr },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Transport {
+    // optionsはTCPオプションの長さ (4バイト単位)
+    Tcp { src_port: u16, dst_port: u16, options: u8 },
+    Udp { src_port: u16, dst_port: u16 },
+    // TCP・UDP以外のプロトコル番号 (ポートを持たない)
+    Other(u8),
+}
+
+impl Transport {
+    pub fn protocol(&self) -> u8 {
+        match self {
+            Transport::Tcp { .. } => 6,
+            Transport::Udp { .. } => 17,
+            Transport::Other(protocol) => *protocol,
+        }
+    }
+
+    pub fn ports(&self) -> (u16, u16) {
+        match *self {
+            Transport::Tcp { src_port, dst_port, .. } | Transport::Udp { src_port, dst_port } => (src_port, dst_port),
+            Transport::Other(_) => (0, 0),
+        }
+    }
+
+    fn header(&self, payload_len: usize) -> Vec<u8> {
+        match *self {
+            Transport::Tcp { src_port, dst_port, options } => {
+                let mut tcp = TcpHeader::new(src_port, dst_port, TcpHeader::ACK | TcpHeader::PSH);
+                // NOPで埋める
+                tcp.options = vec![1; options as usize * 4];
+                tcp.to_bytes()
+            }
+            Transport::Udp { src_port, dst_port } => {
+                UdpHeader { src_port, dst_port, payload_len: payload_len as u16 }.to_bytes().to_vec()
+            }
+            Transport::Other(_) => Vec::new(),
+        }
+    }
+}
+
+impl FrameSpec {
+    pub fn ether_type(&self) -> u16 {
+        match self.network {
+            Network::Ipv4 { .. } => 0x0800,
+            Network::Ipv6 { .. } => 0x86DD,
+            Network::Arp { .. } => 0x0806,
+        }
+    }
+
+    pub fn addresses(&self) -> (IpAddr, IpAddr) {
+        match self.network {
+            Network::Ipv4 { src, dst, .. } => (src.into(), dst.into()),
+            Network::Ipv6 { src, dst, .. } => (src.into(), dst.into()),
+            Network::Arp { sender, target } => (sender.into(), target.into()),
+        }
+    }
+
+    pub fn transport(&self) -> Option<Transport> {
+        match self.network {
+            Network::Ipv4 { transport, .. } | Network::Ipv6 { transport, .. } => Some(transport),
+            Network::Arp { .. } => None,
+        }
+    }
+
+    // イーサネットヘッダ以降のうち、ネットワーク層・トランスポート層のヘッダを除いた部分 (ARPはARPの本体)
+    pub fn data(&self, frame: &[u8]) -> Vec<u8> {
+        match self.network {
+            Network::Arp { .. } => frame[14..].to_vec(),
+            _ => self.payload.clone(),
+        }
+    }
+
+    pub fn to_frame(&self) -> Vec<u8> {
+        let ethernet = EthernetHeader { dst_mac: self.dst_mac, src_mac: self.src_mac, ether_type: self.ether_type() };
+        let mut frame = ethernet.to_bytes().to_vec();
+
+        match self.network {
+            Network::Ipv4 { src, dst, transport } => {
+                let segment = transport.header(self.payload.len());
+                let payload_len = (segment.len() + self.payload.len()) as u16;
+                frame.extend_from_slice(&Ipv4Header::new(src, dst, transport.protocol(), payload_len).to_bytes());
+                frame.extend_from_slice(&segment);
+                frame.extend_from_slice(&self.payload);
+            }
+            Network::Ipv6 { src, dst, transport } => {
+                let segment = transport.header(self.payload.len());
+                let payload_len = (segment.len() + self.payload.len()) as u16;
+                frame.extend_from_slice(&Ipv6Header::new(src, dst, transport.protocol(), payload_len).to_bytes());
+                frame.extend_from_slice(&segment);
+                frame.extend_from_slice(&self.payload);
+            }
+            Network::Arp { sender, target } => {
+                frame.extend_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, 1]);
+                frame.extend_from_slice(&self.src_mac);
+                frame.extend_from_slice(&sender.octets());
+                frame.extend_from_slice(&[0; 6]);
+                frame.extend_from_slice(&target.octets());
+            }
+        }
+        frame
+    }
+}
+
+fn transport_strategy() -> impl Strategy<Value = Transport> {
+    prop_oneof![
+        (any::<u16>(), any::<u16>(), 0u8..=10)
+            .prop_map(|(src_port, dst_port, options)| Transport::Tcp { src_port, dst_port, options }),
+        (any::<u16>(), any::<u16>()).prop_map(|(src_port, dst_port)| Transport::Udp { src_port, dst_port }),
+        any::<u8>()
+            .prop_filter("TCP・UDP以外", |protocol| *protocol != 6 && *protocol != 17)
+            .prop_map(Transport::Other),
+    ]
+}
+
+pub fn frame_spec() -> impl Strategy<Value = FrameSpec> {
+    let network = prop_oneof![
+        (any::<Ipv4Addr>(), any::<Ipv4Addr>(), transport_strategy())
+            .prop_map(|(src, dst, transport)| Network::Ipv4 { src, dst, transport }),
+        (any::<Ipv6Addr>(), any::<Ipv6Addr>(), transport_strategy())
+            .prop_map(|(src, dst, transport)| Network::Ipv6 { src, dst, transport }),
+        (any::<Ipv4Addr>(), any::<Ipv4Addr>()).prop_map(|(sender, target)| Network::Arp { sender, target }),
+    ];
+    (any::<[u8; 6]>(), any::<[u8; 6]>(), network, vec(any::<u8>(), 0..1400))
+        .prop_map(|(src_mac, dst_mac, network, payload)| FrameSpec { src_mac, dst_mac, network, payload })
+}
@@ -0,0 +1,281 @@
+use crate::cli::TopArgs;
+use crate::events::Alert;
+use crate::traffic::{DirectionalTotals, PeerTraffic};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// /api/v1/stats の応答のうち表示に使用する項目
+#[derive(Debug, Deserialize)]
+struct StatsView {
+    packets_written: u64,
+    packets_injected: u64,
+    packets_dropped: BTreeMap<String, u64>,
+    firewall_drops: u64,
+    buffer_depth: i64,
+    db_insert_latency: LatencyView,
+    poll_latency: LatencyView,
+    protocols: BTreeMap<String, DirectionalTotals>,
+    peers: Vec<PeerTraffic>,
+    pools: Vec<PoolView>,
+    recent_alerts: Vec<Alert>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct LatencyView {
+    count: u64,
+    sum_secs: f64,
+}
+
+impl LatencyView {
+    // 前回取得時からの平均 (ミリ秒)
+    fn average_ms_since(&self, previous: Option<&LatencyView>) -> Option<f64> {
+        let (count, sum) = match previous {
+            Some(previous) => (self.count.saturating_sub(previous.count), self.sum_secs - previous.sum_secs),
+            None => (self.count, self.sum_secs),
+        };
+        (count > 0).then(|| sum / count as f64 * 1000.0)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PoolView {
+    name: String,
+    connections: u32,
+    in_use: u32,
+    idle: u32,
+    get_timed_out: u64,
+}
+
+struct Sample {
+    stats: StatsView,
+    taken_at: Instant,
+}
+
+struct App {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    current: Option<Sample>,
+    previous: Option<Sample>,
+    error: Option<String>,
+}
+
+impl App {
+    async fn refresh(&mut self) {
+        let mut request = self.client.get(format!("{}/api/v1/stats", self.url.trim_end_matches('/')));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let result = async {
+            request
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<StatsView>()
+                .await
+        }
+        .await;
+
+        match result {
+            Ok(stats) => {
+                self.previous = self.current.take();
+                self.current = Some(Sample { stats, taken_at: Instant::now() });
+                self.error = None;
+            }
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+
+    fn elapsed_secs(&self) -> Option<f64> {
+        let (current, previous) = (self.current.as_ref()?, self.previous.as_ref()?);
+        Some(current.taken_at.duration_since(previous.taken_at).as_secs_f64()).filter(|secs| *secs > 0.0)
+    }
+
+    // 前回取得時からの1秒あたりの増加量
+    fn rate(&self, current: u64, previous: Option<u64>) -> f64 {
+        match (self.elapsed_secs(), previous) {
+            (Some(secs), Some(previous)) => current.saturating_sub(previous) as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+// 実行中のrdb-tunnelの管理APIから統計情報を取得して表示する
+pub async fn run(args: TopArgs) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut app = App {
+        client,
+        url: args.url,
+        token: args.token,
+        current: None,
+        previous: None,
+        error: None,
+    };
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, Duration::from_secs(args.interval.max(1))).await;
+    ratatui::restore();
+    result
+}
+
+async fn event_loop(terminal: &mut DefaultTerminal, app: &mut App, interval: Duration) -> Result<(), String> {
+    loop {
+        app.refresh().await;
+        terminal.draw(|frame| draw(frame, app)).map_err(|e| e.to_string())?;
+
+        // 次の更新までキー入力を待つ
+        let deadline = Instant::now() + interval;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let ready = tokio::task::block_in_place(|| event::poll(remaining)).map_err(|e| e.to_string())?;
+            if !ready {
+                break;
+            }
+            if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+                if key.kind == KeyEventKind::Press && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_latency(latency: Option<f64>) -> String {
+    latency.map(|ms| format!("{:.2} ms", ms)).unwrap_or_else(|| "-".to_string())
+}
+
+fn draw(frame: &mut Frame, app: &App) {
+    let [header, traffic, lower, footer] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Min(8),
+        Constraint::Min(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let footer_text = match &app.error {
+        Some(e) => Line::styled(format!("取得に失敗しました: {}", e), Style::default().fg(Color::Red)),
+        None => Line::from(format!("{}  q: 終了", app.url)),
+    };
+    frame.render_widget(Paragraph::new(footer_text), footer);
+
+    let Some(current) = &app.current else {
+        frame.render_widget(Paragraph::new("統計情報を取得しています...").block(Block::bordered().title("rdb-tunnel top")), header);
+        return;
+    };
+    let stats = &current.stats;
+    let previous = app.previous.as_ref().map(|sample| &sample.stats);
+
+    let dropped: u64 = stats.packets_dropped.values().sum();
+    let summary = vec![
+        Line::from(format!(
+            "書き込み {} ({:.0}/s)   注入 {} ({:.0}/s)   破棄 {}   FW遮断 {}",
+            stats.packets_written,
+            app.rate(stats.packets_written, previous.map(|p| p.packets_written)),
+            stats.packets_injected,
+            app.rate(stats.packets_injected, previous.map(|p| p.packets_injected)),
+            dropped,
+            stats.firewall_drops,
+        )),
+        Line::from(format!(
+            "書き込みバッファ {}   DB挿入 {}   ポーリング {}",
+            stats.buffer_depth,
+            format_latency(stats.db_insert_latency.average_ms_since(previous.map(|p| &p.db_insert_latency))),
+            format_latency(stats.poll_latency.average_ms_since(previous.map(|p| &p.poll_latency))),
+        )),
+        Line::from(
+            stats
+                .pools
+                .iter()
+                .map(|pool| format!(
+                    "プール[{}] {}/{} 使用中 (アイドル {}, タイムアウト {})",
+                    pool.name, pool.in_use, pool.connections, pool.idle, pool.get_timed_out
+                ))
+                .collect::<Vec<_>>()
+                .join("   "),
+        ),
+    ];
+    frame.render_widget(Paragraph::new(summary).block(Block::bordered().title("rdb-tunnel top")), header);
+
+    let [protocols, peers] = Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(traffic);
+    frame.render_widget(protocol_table(app, stats, previous), protocols);
+    frame.render_widget(peer_table(app, stats, previous), peers);
+
+    let alerts = stats
+        .recent_alerts
+        .iter()
+        .map(|alert| {
+            ListItem::new(format!(
+                "{} [{}] {}: {}",
+                alert.timestamp.format("%H:%M:%S"),
+                alert.severity,
+                alert.kind,
+                alert.message
+            ))
+        })
+        .collect::<Vec<_>>();
+    frame.render_widget(List::new(alerts).block(Block::bordered().title("直近のアラート")), lower);
+}
+
+fn header_row(cells: &[&'static str]) -> Row<'static> {
+    Row::new(cells.to_vec()).style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+fn protocol_table<'a>(app: &App, stats: &'a StatsView, previous: Option<&StatsView>) -> Table<'a> {
+    let rows = stats.protocols.iter().map(|(name, totals)| {
+        let last = previous.and_then(|p| p.protocols.get(name));
+        Row::new(vec![
+            name.clone(),
+            format!("{:.0}", app.rate(totals.outbound.packets, last.map(|t| t.outbound.packets))),
+            format_bytes(app.rate(totals.outbound.bytes, last.map(|t| t.outbound.bytes))),
+            format!("{:.0}", app.rate(totals.inbound.packets, last.map(|t| t.inbound.packets))),
+            format_bytes(app.rate(totals.inbound.bytes, last.map(|t| t.inbound.bytes))),
+        ])
+    });
+
+    Table::new(rows, [Constraint::Length(8), Constraint::Length(8), Constraint::Length(12), Constraint::Length(8), Constraint::Length(12)])
+        .header(header_row(&["proto", "送信pps", "送信/s", "受信pps", "受信/s"]))
+        .block(Block::bordered().title("プロトコル別"))
+}
+
+fn peer_table<'a>(app: &App, stats: &'a StatsView, previous: Option<&StatsView>) -> Table<'a> {
+    let last: HashMap<IpAddr, &DirectionalTotals> = previous
+        .map(|p| p.peers.iter().map(|peer| (peer.address, &peer.totals)).collect())
+        .unwrap_or_default();
+
+    let rows = stats.peers.iter().map(|peer| {
+        let last = last.get(&peer.address);
+        Row::new(vec![
+            peer.address.to_string(),
+            format_bytes(app.rate(peer.totals.outbound.bytes, last.map(|t| t.outbound.bytes))),
+            format_bytes(app.rate(peer.totals.inbound.bytes, last.map(|t| t.inbound.bytes))),
+            format_bytes(peer.totals.total_bytes() as f64),
+        ])
+    });
+
+    Table::new(rows, [Constraint::Min(16), Constraint::Length(12), Constraint::Length(12), Constraint::Length(12)])
+        .header(header_row(&["peer", "送信/s", "受信/s", "累計"]))
+        .block(Block::bordered().title("ピア別"))
+}
@@ -0,0 +1,208 @@
+use crate::events::Direction;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// ピアごと・ポートごとの集計数の上限 (これを超えた新しいピア・ポートは集計しない)
+const MAX_TRACKED_PEERS: usize = 1024;
+const MAX_TRACKED_PORTS: usize = 1024;
+
+lazy_static! {
+    static ref TRAFFIC: TrafficRegistry = TrafficRegistry::default();
+}
+
+// 起動時からの累計値
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TrafficTotals {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DirectionalTotals {
+    pub outbound: TrafficTotals,
+    pub inbound: TrafficTotals,
+}
+
+impl DirectionalTotals {
+    pub fn total_bytes(&self) -> u64 {
+        self.outbound.bytes + self.inbound.bytes
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerTraffic {
+    pub address: IpAddr,
+    #[serde(flatten)]
+    pub totals: DirectionalTotals,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortTraffic {
+    pub port: u16,
+    #[serde(flatten)]
+    pub totals: DirectionalTotals,
+}
+
+// パケットごとにロックを取らずに加算するカウンタ
+#[derive(Debug, Default)]
+struct Counters {
+    outbound_packets: AtomicU64,
+    outbound_bytes: AtomicU64,
+    inbound_packets: AtomicU64,
+    inbound_bytes: AtomicU64,
+}
+
+impl Counters {
+    fn add(&self, direction: Direction, bytes: usize) {
+        let (packets, total) = match direction {
+            Direction::Outbound => (&self.outbound_packets, &self.outbound_bytes),
+            Direction::Inbound => (&self.inbound_packets, &self.inbound_bytes),
+        };
+        packets.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn totals(&self) -> DirectionalTotals {
+        DirectionalTotals {
+            outbound: TrafficTotals {
+                packets: self.outbound_packets.load(Ordering::Relaxed),
+                bytes: self.outbound_bytes.load(Ordering::Relaxed),
+            },
+            inbound: TrafficTotals {
+                packets: self.inbound_packets.load(Ordering::Relaxed),
+                bytes: self.inbound_bytes.load(Ordering::Relaxed),
+            },
+        }
+    }
+}
+
+// プロトコル別は番号で引く固定長の配列、ポート別・ピア別はシャードごとにロックするDashMapで集計する
+struct TrafficRegistry {
+    by_protocol: Box<[Counters]>,
+    by_port: DashMap<u16, Counters>,
+    by_peer: DashMap<IpAddr, Counters>,
+}
+
+impl Default for TrafficRegistry {
+    fn default() -> Self {
+        Self {
+            by_protocol: (0..=u8::MAX).map(|_| Counters::default()).collect(),
+            by_port: DashMap::new(),
+            by_peer: DashMap::new(),
+        }
+    }
+}
+
+// 集計済みのキーは読み取りロックのみで加算し、新しいキーは上限に達するまで追加する (同時に追加した場合は上限をわずかに超えることがある)
+fn add_bounded<K: Eq + Hash>(map: &DashMap<K, Counters>, key: K, limit: usize, direction: Direction, bytes: usize) {
+    if let Some(counters) = map.get(&key) {
+        counters.add(direction, bytes);
+    } else if map.len() < limit {
+        map.entry(key).or_default().add(direction, bytes);
+    }
+}
+
+// 通信量の多い順に `limit` 件まで返す
+fn top<K: Eq + Hash + Copy, T>(
+    map: &DashMap<K, Counters>,
+    limit: usize,
+    to_entry: impl Fn(K, DirectionalTotals) -> T,
+) -> Vec<T> {
+    let mut entries = map.iter().map(|entry| (*entry.key(), entry.value().totals())).collect::<Vec<_>>();
+    entries.sort_by_key(|(_, totals)| std::cmp::Reverse(totals.total_bytes()));
+    entries.truncate(limit);
+    entries.into_iter().map(|(key, totals)| to_entry(key, totals)).collect()
+}
+
+// IPプロトコル番号を表示用の名前に変換する
+pub fn protocol_name(ip_protocol: u8) -> String {
+    match ip_protocol {
+        1 => "icmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        58 => "icmpv6".to_string(),
+        other => format!("ip-{}", other),
+    }
+}
+
+// サービス側とみなすポート。両方ある場合は小さい方 (エフェメラルポートでない方) とし、ポートがない場合は0
+pub fn service_port(src_port: u16, dst_port: u16) -> u16 {
+    match (src_port, dst_port) {
+        (0, port) | (port, 0) => port,
+        (src_port, dst_port) => src_port.min(dst_port),
+    }
+}
+
+// `peer` はトンネルの対向側のアドレス (送信時は宛先、受信時は送信元)。`port` が0の場合はポート別に集計しない
+pub fn record(direction: Direction, ip_protocol: u8, peer: IpAddr, port: u16, bytes: usize) {
+    TRAFFIC.by_protocol[ip_protocol as usize].add(direction, bytes);
+    if port != 0 {
+        add_bounded(&TRAFFIC.by_port, port, MAX_TRACKED_PORTS, direction, bytes);
+    }
+    add_bounded(&TRAFFIC.by_peer, peer, MAX_TRACKED_PEERS, direction, bytes);
+}
+
+pub fn protocol_totals() -> BTreeMap<String, DirectionalTotals> {
+    TRAFFIC
+        .by_protocol
+        .iter()
+        .enumerate()
+        .map(|(protocol, counters)| (protocol as u8, counters.totals()))
+        .filter(|(_, totals)| totals.outbound.packets + totals.inbound.packets > 0)
+        .map(|(protocol, totals)| (protocol_name(protocol), totals))
+        .collect()
+}
+
+pub fn top_peers(limit: usize) -> Vec<PeerTraffic> {
+    top(&TRAFFIC.by_peer, limit, |address, totals| PeerTraffic { address, totals })
+}
+
+// 集計中の全ピア (通信量の多い順)
+pub fn peer_totals() -> Vec<PeerTraffic> {
+    top_peers(usize::MAX)
+}
+
+pub fn top_ports(limit: usize) -> Vec<PortTraffic> {
+    top(&TRAFFIC.by_port, limit, |port, totals| PortTraffic { port, totals })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_port_prefers_well_known_port() {
+        assert_eq!(service_port(49152, 443), 443);
+        assert_eq!(service_port(53, 40000), 53);
+        assert_eq!(service_port(0, 0), 0);
+    }
+
+    #[test]
+    fn counts_concurrent_updates_without_loss() {
+        let map = DashMap::new();
+        add_bounded(&map, 443, 1, Direction::Outbound, 100);
+        std::thread::scope(|scope| {
+            for thread in 0..8u16 {
+                let map = &map;
+                scope.spawn(move || {
+                    for _ in 0..10_000 {
+                        add_bounded(map, 443, 1, Direction::Outbound, 100);
+                        // 上限に達しているため集計しない
+                        add_bounded(map, 1000 + thread, 1, Direction::Inbound, 1);
+                    }
+                });
+            }
+        });
+
+        let totals = map.get(&443).unwrap().totals();
+        assert_eq!(totals.outbound.packets, 80_001);
+        assert_eq!(totals.outbound.bytes, 8_000_100);
+        assert_eq!(totals.inbound.packets, 0);
+        assert_eq!(top(&map, 10, |port, _| port), vec![443]);
+    }
+}
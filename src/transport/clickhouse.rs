@@ -0,0 +1,344 @@
+use crate::chunk::Chunk;
+use crate::config::{ClickHouseConfig, TransportBackend};
+use crate::database::types::MacAddr;
+use crate::db_read::{PacketError, PacketInfo, PacketPoller};
+use crate::db_write::PacketData;
+use crate::health;
+use crate::transport::{inject_received, PacketTransport, TransportError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clickhouse::sql::Identifier;
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::time::{interval, timeout};
+use tracing::{debug, error, info};
+
+// テーブルをポーリングする間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// 挿入の反映が前後する分を遡って読み込む時間 (既に注入したパケットは指紋で除外する)
+const POLL_OVERLAP: Duration = Duration::from_secs(2);
+// ClickHouseへの接続確認のタイムアウト
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn clickhouse_error(e: clickhouse::error::Error) -> TransportError {
+    TransportError::ClickHouse(e.to_string())
+}
+
+// IPv4アドレスはIPv4射影アドレスとしてIPv6列に格納する
+fn to_column(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+fn from_column(ip: Ipv6Addr) -> IpAddr {
+    ip.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(IpAddr::V6(ip))
+}
+
+#[derive(Row, Serialize)]
+struct PacketRow<'a> {
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    ether_type: i32,
+    src_ip: Ipv6Addr,
+    dst_ip: Ipv6Addr,
+    src_port: i32,
+    dst_port: i32,
+    ip_protocol: i32,
+    #[serde(with = "clickhouse::serde::chrono::datetime64::micros")]
+    timestamp: DateTime<Utc>,
+    #[serde(with = "serde_bytes")]
+    data: &'a [u8],
+    #[serde(with = "serde_bytes")]
+    raw_packet: &'a [u8],
+    interface: &'a str,
+    seq: Option<i64>,
+    dscp: Option<i16>,
+    ecn: Option<i16>,
+    codec: i16,
+    chunk_id: Option<i64>,
+    chunk_index: Option<i16>,
+    chunk_count: Option<i16>,
+    encapsulation: Option<i16>,
+    inner_src_ip: Option<Ipv6Addr>,
+    inner_dst_ip: Option<Ipv6Addr>,
+    inner_src_port: Option<i32>,
+    inner_dst_port: Option<i32>,
+    inner_ip_protocol: Option<i32>,
+    vni: Option<i32>,
+}
+
+#[derive(Row, Deserialize)]
+struct ReceivedRow {
+    fingerprint: u64,
+    #[serde(with = "clickhouse::serde::chrono::datetime64::micros")]
+    inserted_at: DateTime<Utc>,
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    ether_type: i32,
+    src_ip: Ipv6Addr,
+    dst_ip: Ipv6Addr,
+    src_port: i32,
+    dst_port: i32,
+    ip_protocol: i32,
+    #[serde(with = "clickhouse::serde::chrono::datetime64::micros")]
+    timestamp: DateTime<Utc>,
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    raw_packet: Vec<u8>,
+    seq: Option<i64>,
+    codec: i16,
+    chunk_id: Option<i64>,
+    chunk_index: Option<i16>,
+    chunk_count: Option<i16>,
+}
+
+impl From<ReceivedRow> for PacketInfo {
+    fn from(row: ReceivedRow) -> Self {
+        PacketInfo {
+            src_mac: MacAddr(row.src_mac),
+            dst_mac: MacAddr(row.dst_mac),
+            ether_type: row.ether_type,
+            src_ip: from_column(row.src_ip),
+            dst_ip: from_column(row.dst_ip),
+            src_port: Some(row.src_port),
+            dst_port: Some(row.dst_port),
+            ip_protocol: row.ip_protocol,
+            timestamp: row.timestamp,
+            data: row.data,
+            raw_packet: row.raw_packet,
+            seq: row.seq,
+            codec: row.codec,
+            chunk: Chunk::from_columns(row.chunk_id, row.chunk_index, row.chunk_count),
+        }
+    }
+}
+
+// ClickHouseのテーブルを経由するトランスポート。
+// 非同期挿入で書き込み、日ごとのパーティションに保存するため、分析用のミラーとしても使用できる
+pub struct ClickHouseTransport {
+    config: ClickHouseConfig,
+    client: Client,
+    // テーブルは最初に使用する時点で作成する (起動時にサーバーへ接続できなくてもよいように)
+    table_ready: OnceCell<()>,
+}
+
+impl ClickHouseTransport {
+    pub fn new(config: &ClickHouseConfig) -> Self {
+        let mut client = Client::default()
+            .with_url(&config.url)
+            .with_database(&config.database)
+            // サーバー側でバッファリングし、書き込みが反映されるまで待つ
+            .with_option("async_insert", "1")
+            .with_option("wait_for_async_insert", "1");
+        if let Some(user) = &config.user {
+            client = client.with_user(user);
+        }
+        if let Some(password) = &config.password {
+            client = client.with_password(password);
+        }
+        info!("ClickHouseに接続します: {} (テーブル {}.{})", config.url, config.database, config.table);
+        Self { config: config.clone(), client, table_ready: OnceCell::new() }
+    }
+
+    async fn ensure_table(&self) -> Result<(), TransportError> {
+        self.table_ready
+            .get_or_try_init(|| async {
+                let ttl = self
+                    .config
+                    .ttl
+                    .map(|ttl| format!("TTL toDateTime(timestamp) + INTERVAL {} SECOND", ttl.as_secs()))
+                    .unwrap_or_default();
+                let query = format!(
+                    "CREATE TABLE IF NOT EXISTS ? (
+                        src_mac FixedString(6),
+                        dst_mac FixedString(6),
+                        ether_type Int32,
+                        src_ip IPv6,
+                        dst_ip IPv6,
+                        src_port Int32,
+                        dst_port Int32,
+                        ip_protocol Int32,
+                        timestamp DateTime64(6, 'UTC'),
+                        data String CODEC(ZSTD),
+                        raw_packet String CODEC(ZSTD),
+                        interface LowCardinality(String),
+                        seq Nullable(Int64),
+                        dscp Nullable(Int16),
+                        ecn Nullable(Int16),
+                        codec Int16 DEFAULT 0,
+                        chunk_id Nullable(Int64),
+                        chunk_index Nullable(Int16),
+                        chunk_count Nullable(Int16),
+                        encapsulation Nullable(Int16),
+                        inner_src_ip Nullable(IPv6),
+                        inner_dst_ip Nullable(IPv6),
+                        inner_src_port Nullable(Int32),
+                        inner_dst_port Nullable(Int32),
+                        inner_ip_protocol Nullable(Int32),
+                        vni Nullable(Int32),
+                        inserted_at DateTime64(6, 'UTC') DEFAULT now64(6),
+                        INDEX inserted_at_idx inserted_at TYPE minmax GRANULARITY 1
+                    )
+                    ENGINE = MergeTree
+                    PARTITION BY toDate(timestamp)
+                    ORDER BY (dst_ip, timestamp)
+                    {}",
+                    ttl
+                );
+                self.client
+                    .query(&query)
+                    .bind(Identifier(&self.config.table))
+                    .execute()
+                    .await
+                    .map_err(clickhouse_error)?;
+                // 列を追加する前に作成したテーブルにも追加する
+                for column in [
+                    "seq Nullable(Int64) AFTER interface",
+                    "dscp Nullable(Int16) AFTER seq",
+                    "ecn Nullable(Int16) AFTER dscp",
+                    "codec Int16 DEFAULT 0 AFTER ecn",
+                    "chunk_id Nullable(Int64) AFTER codec",
+                    "chunk_index Nullable(Int16) AFTER chunk_id",
+                    "chunk_count Nullable(Int16) AFTER chunk_index",
+                    "encapsulation Nullable(Int16) AFTER chunk_count",
+                    "inner_src_ip Nullable(IPv6) AFTER encapsulation",
+                    "inner_dst_ip Nullable(IPv6) AFTER inner_src_ip",
+                    "inner_src_port Nullable(Int32) AFTER inner_dst_ip",
+                    "inner_dst_port Nullable(Int32) AFTER inner_src_port",
+                    "inner_ip_protocol Nullable(Int32) AFTER inner_dst_port",
+                    "vni Nullable(Int32) AFTER inner_ip_protocol",
+                ] {
+                    self.client
+                        .query(&format!("ALTER TABLE ? ADD COLUMN IF NOT EXISTS {}", column))
+                        .bind(Identifier(&self.config.table))
+                        .execute()
+                        .await
+                        .map_err(clickhouse_error)?;
+                }
+                info!("ClickHouseのテーブル {} を使用します", self.config.table);
+                Ok(())
+            })
+            .await
+            .copied()
+    }
+}
+
+#[async_trait]
+impl PacketTransport for ClickHouseTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::ClickHouse
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        self.ensure_table().await?;
+
+        let mut insert = self
+            .client
+            .insert::<PacketRow>(&self.config.table)
+            .await
+            .map_err(clickhouse_error)?;
+        for packet in packets {
+            insert
+                .write(&PacketRow {
+                    src_mac: packet.src_mac.0,
+                    dst_mac: packet.dst_mac.0,
+                    ether_type: packet.ether_type.as_i32(),
+                    src_ip: to_column(packet.src_ip.ip()),
+                    dst_ip: to_column(packet.dst_ip.ip()),
+                    src_port: packet.src_port,
+                    dst_port: packet.dst_port,
+                    ip_protocol: packet.ip_protocol.as_i32(),
+                    timestamp: packet.timestamp,
+                    data: &packet.data,
+                    raw_packet: &packet.raw_packet,
+                    interface: &packet.interface,
+                    seq: packet.seq,
+                    dscp: packet.dscp,
+                    ecn: packet.ecn,
+                    codec: packet.codec.id(),
+                    chunk_id: packet.chunk_id,
+                    chunk_index: packet.chunk_index,
+                    chunk_count: packet.chunk_count,
+                    encapsulation: packet.encapsulation,
+                    inner_src_ip: packet.inner_src_ip.as_ref().map(|ip| to_column(ip.ip())),
+                    inner_dst_ip: packet.inner_dst_ip.as_ref().map(|ip| to_column(ip.ip())),
+                    inner_src_port: packet.inner_src_port,
+                    inner_dst_port: packet.inner_dst_port,
+                    inner_ip_protocol: packet.inner_ip_protocol,
+                    vni: packet.vni,
+                })
+                .await
+                .map_err(clickhouse_error)?;
+        }
+        insert.end().await.map_err(clickhouse_error)?;
+        debug!("{}個のパケットをClickHouseへ書き込みました", packets.len());
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        self.ensure_table().await?;
+        info!("ClickHouseからパケットの受信を開始します: テーブル {}", self.config.table);
+
+        // 起動前に書き込まれたパケットは注入しない
+        let mut cursor = Utc::now();
+        // 重複して読み込む範囲で注入済みのパケット (指紋 -> 挿入時刻)
+        let mut seen: HashMap<u64, DateTime<Utc>> = HashMap::new();
+        let overlap = chrono::Duration::from_std(POLL_OVERLAP).unwrap_or_else(|_| chrono::Duration::zero());
+        let mut interval = interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            health::record_poller_heartbeat();
+
+            let fetched = self
+                .client
+                .query(
+                    "SELECT cityHash64(src_mac, timestamp, raw_packet, interface) AS fingerprint, inserted_at,
+                            src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+                            ip_protocol, timestamp, data, raw_packet, seq, codec, chunk_id, chunk_index, chunk_count
+                     FROM ?
+                     WHERE inserted_at > fromUnixTimestamp64Micro(?, 'UTC')
+                       AND (dst_ip = toIPv6(?)
+                            OR dst_ip = toIPv6('255.255.255.255')
+                            OR isIPAddressInRange(IPv6NumToString(dst_ip), '::ffff:224.0.0.0/100')
+                            OR isIPAddressInRange(IPv6NumToString(dst_ip), 'ff00::/8'))
+                     ORDER BY inserted_at",
+                )
+                .bind(Identifier(&self.config.table))
+                .bind((cursor - overlap).timestamp_micros())
+                .bind(to_column(poller.node_ip()).to_string())
+                .fetch_all::<ReceivedRow>()
+                .await;
+            let rows = match fetched {
+                Ok(rows) => rows,
+                Err(e) => {
+                    error!("パケット処理中にエラーが発生しました: {:?}", e);
+                    continue;
+                }
+            };
+
+            let mut packets = Vec::new();
+            for row in rows {
+                cursor = cursor.max(row.inserted_at);
+                if seen.insert(row.fingerprint, row.inserted_at).is_some() {
+                    continue;
+                }
+                packets.push(PacketInfo::from(row));
+            }
+            seen.retain(|_, inserted_at| *inserted_at > cursor - overlap);
+            inject_received(poller, packets).await?;
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        let ping = self.client.query("SELECT 1").fetch_one::<u8>();
+        matches!(timeout(HEALTH_CHECK_TIMEOUT, ping).await, Ok(Ok(_)))
+    }
+}
@@ -0,0 +1,115 @@
+use crate::config::{KafkaConfig, TransportBackend};
+use crate::db_read::{PacketError, PacketPoller};
+use crate::db_write::PacketData;
+use crate::transport::wire::WirePacket;
+use crate::transport::{inject_received, receive_with_heartbeat, PacketTransport, TransportError};
+use async_trait::async_trait;
+use futures::future::join_all;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::Message;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+// 送信の完了を待つ時間の上限
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// ブローカーへの接続確認のタイムアウト
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Kafkaのトピックを経由するトランスポート。
+// パケットは宛先IPアドレスをキーとして送信し、ノードごとのコンシューマーグループで全てのパーティションを受信する
+pub struct KafkaTransport {
+    config: KafkaConfig,
+    producer: FutureProducer,
+}
+
+impl KafkaTransport {
+    pub fn new(config: &KafkaConfig) -> Result<Self, TransportError> {
+        let producer = client_config(config).create()?;
+        info!("Kafkaに接続します: {} (トピック {})", config.brokers, config.topic);
+        Ok(Self { config: config.clone(), producer })
+    }
+}
+
+fn client_config(config: &KafkaConfig) -> ClientConfig {
+    let mut client_config = ClientConfig::new();
+    client_config.set("bootstrap.servers", &config.brokers);
+    for (key, value) in &config.options {
+        client_config.set(key, value);
+    }
+    client_config
+}
+
+#[async_trait]
+impl PacketTransport for KafkaTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Kafka
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        let mut messages = Vec::with_capacity(packets.len());
+        for packet in packets {
+            messages.push((packet.dst_ip.ip().to_string(), WirePacket::encode(packet)?));
+        }
+
+        let deliveries = messages.iter().map(|(key, payload)| {
+            let record = FutureRecord::to(&self.config.topic).key(key).payload(payload);
+            self.producer.send(record, SEND_TIMEOUT)
+        });
+        for result in join_all(deliveries).await {
+            result.map_err(|(e, _)| e)?;
+        }
+        debug!("{}個のパケットをKafkaへ送信しました", packets.len());
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        // 新しいノードは直近のメッセージから受信し、再起動後はコミット済みの位置から再開する
+        let group_id = format!("rdb-tunnel-{}", poller.node_ip());
+        let consumer: StreamConsumer = client_config(&self.config)
+            .set("group.id", &group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "latest")
+            .create()
+            .map_err(TransportError::from)?;
+        consumer
+            .subscribe(&[self.config.topic.as_str()])
+            .map_err(TransportError::from)?;
+        info!("Kafkaからパケットの受信を開始します: トピック {} (グループ {})", self.config.topic, group_id);
+
+        loop {
+            let Some(message) = receive_with_heartbeat(self, consumer.recv()).await else {
+                continue;
+            };
+            let message = message.map_err(TransportError::from)?;
+
+            let packet = match message.payload().map(WirePacket::decode) {
+                Some(Ok(packet)) => Some(packet),
+                Some(Err(e)) => {
+                    warn!("Kafkaのメッセージを解析できないためスキップします: {}", e);
+                    None
+                }
+                None => None,
+            };
+            inject_received(poller, packet).await?;
+            consumer
+                .commit_message(&message, CommitMode::Async)
+                .map_err(TransportError::from)?;
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        let producer = self.producer.clone();
+        let topic = self.config.topic.clone();
+        // メタデータの取得はブロッキング呼び出しのため、ランタイムのスレッドを占有しないようにする
+        tokio::task::spawn_blocking(move || {
+            producer
+                .client()
+                .fetch_metadata(Some(&topic), HEALTH_CHECK_TIMEOUT)
+                .is_ok()
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
@@ -0,0 +1,175 @@
+use crate::chunk::Chunk;
+use crate::config::TransportBackend;
+use crate::db_read::{PacketError, PacketInfo, PacketPoller};
+use crate::db_write::PacketData;
+use crate::health;
+use crate::transport::{inject_received, PacketTransport, TransportError, HEARTBEAT_INTERVAL};
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+// 受信側が処理しきれずに溜められるパケット数の上限
+const CHANNEL_CAPACITY: usize = 4096;
+
+// プロセス内のチャネルを経由するトランスポート。
+// 複製したインスタンスは同じチャネルを共有するため、1つのプロセス内で複数のノードを模擬できる
+#[derive(Clone)]
+pub struct MemoryTransport {
+    sender: broadcast::Sender<PacketInfo>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    // 登録済みの受信口からノード宛のパケットを注入し続ける
+    pub async fn forward(
+        mut receiver: broadcast::Receiver<PacketInfo>,
+        poller: &PacketPoller,
+    ) -> Result<(), PacketError> {
+        loop {
+            let received = timeout(HEARTBEAT_INTERVAL, receiver.recv()).await;
+            health::record_poller_heartbeat();
+            let packet = match received {
+                Ok(Ok(packet)) => packet,
+                Ok(Err(RecvError::Lagged(skipped))) => {
+                    warn!("受信が追いつかないため{}個のパケットを破棄しました", skipped);
+                    continue;
+                }
+                // 送信側はトランスポートが保持しているため、トランスポートを破棄した場合のみ閉じられる
+                Ok(Err(RecvError::Closed)) => return Ok(()),
+                // プロセス内のチャネルは途切れないため、パケットが届かなくても受信できている
+                Err(_) => {
+                    health::record_poll_success();
+                    continue;
+                }
+            };
+
+            inject_received(poller, [packet]).await?;
+        }
+    }
+
+    // 配送を始める前に受信口を登録する (テスト用)
+    #[cfg(test)]
+    pub fn receiver(&self) -> broadcast::Receiver<PacketInfo> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for MemoryTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn to_packet_info(packet: &PacketData) -> PacketInfo {
+    PacketInfo {
+        src_mac: packet.src_mac.clone(),
+        dst_mac: packet.dst_mac.clone(),
+        ether_type: packet.ether_type.as_i32(),
+        src_ip: packet.src_ip.ip(),
+        dst_ip: packet.dst_ip.ip(),
+        src_port: Some(packet.src_port),
+        dst_port: Some(packet.dst_port),
+        ip_protocol: packet.ip_protocol.as_i32(),
+        timestamp: packet.timestamp,
+        data: packet.data.to_vec(),
+        raw_packet: packet.raw_packet.to_vec(),
+        seq: packet.seq,
+        codec: packet.codec.id(),
+        chunk: Chunk::from_columns(packet.chunk_id, packet.chunk_index, packet.chunk_count),
+    }
+}
+
+#[async_trait]
+impl PacketTransport for MemoryTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Memory
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        for packet in packets {
+            // 受信側がいない場合は破棄する
+            let _ = self.sender.send(to_packet_info(packet));
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        let receiver = self.sender.subscribe();
+        info!("プロセス内のチャネルからパケットの受信を開始します");
+        Self::forward(receiver, poller).await
+    }
+
+    async fn health_check(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_write::parse_and_analyze_packet;
+    use crate::test_support::{udp_frame, Node, NODE_A, NODE_B, UDP_SRC_PORT};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    // 送信側のパイプライン (フレームの解析 -> publish) で送信する
+    async fn send_udp(transport: &MemoryTransport, src: Ipv4Addr, dst: Ipv4Addr, payload: &[u8]) {
+        let frame = udp_frame(src, dst, 5000, payload);
+        let mut packet = parse_and_analyze_packet(frame.into()).await.expect("フレームを解析できません");
+        packet.interface = "test0".to_string();
+        transport.publish(&[packet]).await.expect("送信に失敗しました");
+    }
+
+    #[tokio::test]
+    async fn delivers_unicast_only_to_destination_node() {
+        let transport = MemoryTransport::new();
+        let mut node_a = Node::start(&transport, NODE_A);
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        send_udp(&transport, NODE_A, NODE_B, b"hello").await;
+
+        let packet = node_b.receive().await;
+        assert_eq!(packet.src_ip, IpAddr::V4(NODE_A));
+        assert_eq!(packet.dst_ip, IpAddr::V4(NODE_B));
+        assert_eq!(packet.src_port, Some(UDP_SRC_PORT as i32));
+        assert_eq!(packet.dst_port, Some(5000));
+        assert_eq!(packet.raw_packet, udp_frame(NODE_A, NODE_B, 5000, b"hello"));
+
+        // 後続のパケットが届いた時点で、先に送信したパケットが届いていないことを確認する
+        send_udp(&transport, NODE_B, NODE_A, b"reply").await;
+        assert_eq!(node_a.receive().await.dst_ip, IpAddr::V4(NODE_A));
+        node_a.assert_nothing_injected();
+        node_b.assert_nothing_injected();
+    }
+
+    #[tokio::test]
+    async fn delivers_broadcast_to_every_node() {
+        let transport = MemoryTransport::new();
+        let mut node_a = Node::start(&transport, NODE_A);
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        send_udp(&transport, NODE_A, Ipv4Addr::BROADCAST, b"discover").await;
+
+        assert_eq!(node_a.receive().await.dst_ip, IpAddr::V4(Ipv4Addr::BROADCAST));
+        assert_eq!(node_b.receive().await.dst_ip, IpAddr::V4(Ipv4Addr::BROADCAST));
+    }
+
+    #[tokio::test]
+    async fn preserves_packet_order() {
+        let transport = MemoryTransport::new();
+        let mut node_b = Node::start(&transport, NODE_B);
+
+        for payload in [b"1", b"2", b"3"] {
+            send_udp(&transport, NODE_A, NODE_B, payload).await;
+        }
+
+        for payload in [b"1", b"2", b"3"] {
+            assert!(node_b.receive().await.raw_packet.ends_with(payload));
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::config::TransportBackend;
+use crate::db_read::{PacketError, PacketPoller};
+use crate::db_write::PacketData;
+use crate::transport::{PacketTransport, TransportError};
+use async_trait::async_trait;
+use tracing::warn;
+
+// 送信したパケットを分析用のバックエンドにも書き込むトランスポート。
+// 受信とヘルスチェックは中継に使用するバックエンドのみで行い、ミラーへの書き込みの失敗は中継を止めない
+pub struct MirroredTransport {
+    primary: Box<dyn PacketTransport>,
+    mirror: Box<dyn PacketTransport>,
+}
+
+impl MirroredTransport {
+    pub fn new(primary: Box<dyn PacketTransport>, mirror: Box<dyn PacketTransport>) -> Self {
+        Self { primary, mirror }
+    }
+}
+
+#[async_trait]
+impl PacketTransport for MirroredTransport {
+    fn backend(&self) -> TransportBackend {
+        self.primary.backend()
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        self.primary.publish(packets).await?;
+        if let Err(e) = self.mirror.publish(packets).await {
+            warn!("{}へのミラーに失敗しました: {}", self.mirror.backend(), e);
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        self.primary.subscribe(poller).await
+    }
+
+    async fn health_check(&self) -> bool {
+        self.primary.health_check().await
+    }
+}
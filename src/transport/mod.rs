@@ -0,0 +1,224 @@
+use crate::config::{TransportBackend, TransportConfig};
+use crate::database::database::Database;
+use crate::database::error::DbError;
+use crate::db_read::{PacketError, PacketInfo, PacketPoller, MAX_RESUME_AGE};
+use crate::db_write::PacketData;
+use crate::error::Retryable;
+use crate::health;
+use async_trait::async_trait;
+use chrono::Utc;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::timeout;
+use tracing::{info, trace};
+
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod memory;
+#[cfg(feature = "clickhouse")]
+pub mod mirror;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod timescale;
+#[cfg_attr(not(any(feature = "kafka", feature = "redis", feature = "mqtt", feature = "nats")), allow(dead_code))]
+pub mod wire;
+
+// メッセージが届かない間も稼働状態を報告する間隔
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+static TRANSPORT: OnceLock<Box<dyn PacketTransport>> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    #[error("データベースエラー: {0}")]
+    Database(#[from] DbError),
+
+    #[cfg(feature = "clickhouse")]
+    #[error("ClickHouseエラー: {0}")]
+    ClickHouse(String),
+
+    #[cfg(feature = "kafka")]
+    #[error("Kafkaエラー: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+
+    #[cfg(feature = "redis")]
+    #[error("Redisエラー: {0}")]
+    Redis(#[from] ::redis::RedisError),
+
+    #[cfg(feature = "mqtt")]
+    #[error("MQTTエラー: {0}")]
+    Mqtt(String),
+
+    #[cfg(feature = "nats")]
+    #[error("NATSエラー: {0}")]
+    Nats(String),
+
+    #[cfg(feature = "sqlite")]
+    #[error("SQLiteエラー: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+
+    #[cfg_attr(not(any(feature = "kafka", feature = "redis", feature = "mqtt", feature = "nats")), allow(dead_code))]
+    #[error("パケットの変換に失敗しました: {0}")]
+    Encoding(String),
+
+    #[error("トランスポートが初期化されていません")]
+    Uninitialized,
+
+    #[error("トランスポートは既に初期化されています")]
+    AlreadyInitialized,
+
+    #[error("データベースに接続していません")]
+    NoDatabase,
+}
+
+impl Retryable for TransportError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            TransportError::Database(e) => e.is_retryable(),
+            #[cfg(feature = "clickhouse")]
+            TransportError::ClickHouse(_) => true,
+            #[cfg(feature = "kafka")]
+            TransportError::Kafka(_) => true,
+            #[cfg(feature = "redis")]
+            TransportError::Redis(_) => true,
+            #[cfg(feature = "mqtt")]
+            TransportError::Mqtt(_) => true,
+            #[cfg(feature = "nats")]
+            TransportError::Nats(_) => true,
+            // ファイルのロックなどの一時的な失敗を含む
+            #[cfg(feature = "sqlite")]
+            TransportError::Sqlite(_) => true,
+            TransportError::Encoding(_)
+            | TransportError::Uninitialized
+            | TransportError::AlreadyInitialized
+            | TransportError::NoDatabase => false,
+        }
+    }
+}
+
+// パケットの中継経路。キャプチャしたパケットを送信し、ノード宛のパケットを受信して注入する
+#[async_trait]
+pub trait PacketTransport: Send + Sync {
+    fn backend(&self) -> TransportBackend;
+
+    // キャプチャしたパケットを一括で送信する
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError>;
+
+    // ノード宛のパケットを受信し、pollerで注入し続ける (エラーで終了した場合は呼び出し側で再起動する)
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError>;
+
+    // バックエンドに接続できるか
+    async fn health_check(&self) -> bool;
+}
+
+// 設定に応じたトランスポートを作成する。timescaleの場合は接続済みのデータベースを使用する
+pub async fn init_transport(config: &TransportConfig, database: Option<Arc<Database>>) -> Result<(), TransportError> {
+    let transport: Box<dyn PacketTransport> = match config.backend {
+        TransportBackend::Timescale => {
+            Box::new(timescale::TimescaleTransport::new(database.ok_or(TransportError::NoDatabase)?))
+        }
+        TransportBackend::Memory => Box::new(memory::MemoryTransport::new()),
+        #[cfg(feature = "kafka")]
+        TransportBackend::Kafka => Box::new(kafka::KafkaTransport::new(&config.kafka)?),
+        // 設定の読み込み時に拒否している
+        #[cfg(not(feature = "kafka"))]
+        TransportBackend::Kafka => unreachable!("kafkaフィーチャーが無効です"),
+        #[cfg(feature = "redis")]
+        TransportBackend::Redis => Box::new(redis::RedisTransport::connect(&config.redis).await?),
+        #[cfg(not(feature = "redis"))]
+        TransportBackend::Redis => unreachable!("redisフィーチャーが無効です"),
+        #[cfg(feature = "mqtt")]
+        TransportBackend::Mqtt => Box::new(mqtt::MqttTransport::new(&config.mqtt)?),
+        #[cfg(not(feature = "mqtt"))]
+        TransportBackend::Mqtt => unreachable!("mqttフィーチャーが無効です"),
+        #[cfg(feature = "nats")]
+        TransportBackend::Nats => Box::new(nats::NatsTransport::connect(&config.nats).await?),
+        #[cfg(not(feature = "nats"))]
+        TransportBackend::Nats => unreachable!("natsフィーチャーが無効です"),
+        #[cfg(feature = "sqlite")]
+        TransportBackend::Sqlite => Box::new(sqlite::SqliteTransport::open(&config.sqlite)?),
+        #[cfg(not(feature = "sqlite"))]
+        TransportBackend::Sqlite => unreachable!("sqliteフィーチャーが無効です"),
+        #[cfg(feature = "clickhouse")]
+        TransportBackend::ClickHouse => Box::new(clickhouse::ClickHouseTransport::new(&config.clickhouse)),
+        #[cfg(not(feature = "clickhouse"))]
+        TransportBackend::ClickHouse => unreachable!("clickhouseフィーチャーが無効です"),
+    };
+    #[cfg(feature = "clickhouse")]
+    let transport: Box<dyn PacketTransport> =
+        if config.clickhouse.mirror && config.backend != TransportBackend::ClickHouse {
+            info!("送信したパケットをClickHouseにミラーします");
+            Box::new(mirror::MirroredTransport::new(
+                transport,
+                Box::new(clickhouse::ClickHouseTransport::new(&config.clickhouse)),
+            ))
+        } else {
+            transport
+        };
+    info!("トランスポート: {}", transport.backend());
+    TRANSPORT.set(transport).map_err(|_| TransportError::AlreadyInitialized)
+}
+
+pub fn transport() -> Result<&'static dyn PacketTransport, TransportError> {
+    TRANSPORT
+        .get()
+        .map(|transport| transport.as_ref())
+        .ok_or(TransportError::Uninitialized)
+}
+
+// receiveの完了を待ち、HEARTBEAT_INTERVALごとに稼働状態を報告する。届かなかった場合はNoneを返す
+#[cfg_attr(not(any(feature = "kafka", feature = "mqtt", feature = "nats")), allow(dead_code))]
+pub(crate) async fn receive_with_heartbeat<F: Future>(transport: &dyn PacketTransport, receive: F) -> Option<F::Output> {
+    let received = timeout(HEARTBEAT_INTERVAL, receive).await;
+    health::record_poller_heartbeat();
+    match received {
+        Ok(output) => Some(output),
+        Err(_) => {
+            // メッセージが届かない間は、接続できていれば受信できているとみなす
+            if transport.health_check().await {
+                health::record_poll_success();
+            }
+            None
+        }
+    }
+}
+
+// 受信したパケットのうちノード宛のものを注入する
+pub(crate) async fn inject_received(
+    poller: &PacketPoller,
+    packets: impl IntoIterator<Item = PacketInfo>,
+) -> Result<(), PacketError> {
+    // 停止中に溜まったパケットは、ポーリングの再開時と同様に注入しない
+    let oldest = Utc::now() - chrono::Duration::from_std(MAX_RESUME_AGE).unwrap_or_else(|_| chrono::Duration::zero());
+    let packets: Vec<_> = packets
+        .into_iter()
+        .filter(|packet| {
+            packet.timestamp >= oldest && poller.is_addressed_to_node(packet) && poller.should_process_packet(packet)
+        })
+        .collect();
+    if !packets.is_empty() {
+        let (sent, failed) = poller.send_packets(packets).await?;
+        trace!("パケット処理完了 - 成功: {}, 失敗: {}", sent, failed);
+    }
+    health::record_poll_success();
+    Ok(())
+}
+
+// パイプライン全体のテストで使う、プロセス共通のメモリトランスポート
+#[cfg(test)]
+pub fn init_memory_transport() -> memory::MemoryTransport {
+    static MEMORY: OnceLock<memory::MemoryTransport> = OnceLock::new();
+    let memory = MEMORY.get_or_init(memory::MemoryTransport::new);
+    TRANSPORT.get_or_init(|| Box::new(memory.clone()));
+    memory.clone()
+}
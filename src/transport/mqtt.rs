@@ -0,0 +1,157 @@
+use crate::config::{MqttConfig, TransportBackend};
+use crate::db_read::{PacketError, PacketPoller};
+use crate::db_write::PacketData;
+use crate::transport::wire::WirePacket;
+use crate::transport::{inject_received, receive_with_heartbeat, PacketTransport, TransportError};
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, SubscribeFilter};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, warn};
+
+// クライアントからイベントループへ渡す要求の上限
+const REQUEST_CAPACITY: usize = 1024;
+// パケットのJSON表現が収まるサイズ (既定の10KBではジャンボフレームが送れない)
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+// 送信要求がイベントループに受け付けられるまで待つ時間の上限
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// 切断後に再接続を試みるまでの間隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// MQTTブローカーを経由するトランスポート。
+// パケットは宛先ノードのトピック (<prefix>/node/<IPアドレス>) またはブロードキャスト用のトピックへQoS 1で送信する
+pub struct MqttTransport {
+    config: MqttConfig,
+    client: AsyncClient,
+    connected: Arc<AtomicBool>,
+}
+
+impl MqttTransport {
+    pub fn new(config: &MqttConfig) -> Result<Self, TransportError> {
+        // 送信用の接続はセッションを保持しない
+        let options = mqtt_options(config, format!("rdb-tunnel-publisher-{}", std::process::id()), true);
+        let (client, event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+        let connected = Arc::new(AtomicBool::new(false));
+        tokio::spawn(drive_publisher(event_loop, connected.clone()));
+        info!("MQTTブローカーに接続します: {}:{} (トピック {}/#)", config.host, config.port, config.topic_prefix);
+        Ok(Self { config: config.clone(), client, connected })
+    }
+
+    fn node_topic(&self, ip: IpAddr) -> String {
+        format!("{}/node/{}", self.config.topic_prefix, ip)
+    }
+
+    fn broadcast_topic(&self) -> String {
+        format!("{}/broadcast", self.config.topic_prefix)
+    }
+}
+
+fn mqtt_options(config: &MqttConfig, client_id: String, clean_session: bool) -> MqttOptions {
+    let mut options = MqttOptions::new(client_id, config.host.clone(), config.port);
+    options
+        .set_keep_alive(KEEP_ALIVE)
+        .set_clean_session(clean_session)
+        .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+    if let Some(username) = &config.username {
+        options.set_credentials(username.clone(), config.password.clone().unwrap_or_default());
+    }
+    options
+}
+
+// 送信用のイベントループを回し続ける。エラー後に再度pollすると再接続される
+async fn drive_publisher(mut event_loop: EventLoop, connected: Arc<AtomicBool>) {
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                connected.store(true, Ordering::Relaxed);
+                info!("MQTTブローカーに接続しました");
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if connected.swap(false, Ordering::Relaxed) {
+                    warn!("MQTTブローカーから切断されました: {}", e);
+                } else {
+                    debug!("MQTTブローカーに接続できません: {}", e);
+                }
+                sleep(RECONNECT_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PacketTransport for MqttTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Mqtt
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        let send = async {
+            for packet in packets {
+                let dst_ip = packet.dst_ip.ip();
+                let topic = if PacketPoller::is_broadcast_ip(&dst_ip) {
+                    self.broadcast_topic()
+                } else {
+                    self.node_topic(dst_ip)
+                };
+                self.client
+                    .publish(topic, QoS::AtLeastOnce, false, WirePacket::encode(packet)?)
+                    .await
+                    .map_err(|e| TransportError::Mqtt(e.to_string()))?;
+            }
+            Ok::<(), TransportError>(())
+        };
+        timeout(SEND_TIMEOUT, send)
+            .await
+            .map_err(|_| TransportError::Mqtt("送信がタイムアウトしました".to_string()))??;
+        debug!("{}個のパケットをMQTTブローカーへ送信しました", packets.len());
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        // ノードごとにセッションを保持し、停止中に届いたQoS 1のメッセージを再開時に受信する
+        let mut options = mqtt_options(&self.config, format!("rdb-tunnel-{}", poller.node_ip()), false);
+        // 注入を終えてからPUBACKを返し、途中で終了した場合はブローカーに再送させる
+        options.set_manual_acks(true);
+        let (client, mut event_loop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+        let topics = [self.node_topic(poller.node_ip()), self.broadcast_topic()];
+        client
+            .subscribe_many(topics.iter().map(|topic| SubscribeFilter::new(topic.clone(), QoS::AtLeastOnce)))
+            .await
+            .map_err(|e| TransportError::Mqtt(e.to_string()))?;
+        info!("MQTTブローカーからパケットの受信を開始します: {}", topics.join(", "));
+
+        loop {
+            let Some(event) = receive_with_heartbeat(self, event_loop.poll()).await else {
+                continue;
+            };
+            let event = event.map_err(|e| TransportError::Mqtt(e.to_string()))?;
+
+            let Event::Incoming(Packet::Publish(message)) = event else {
+                continue;
+            };
+
+            let packet = match WirePacket::decode(&message.payload) {
+                Ok(packet) => Some(packet),
+                Err(e) => {
+                    warn!("MQTTのメッセージを解析できないためスキップします: {}", e);
+                    None
+                }
+            };
+            inject_received(poller, packet).await?;
+            client
+                .ack(&message)
+                .await
+                .map_err(|e| TransportError::Mqtt(e.to_string()))?;
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
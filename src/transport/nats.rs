@@ -0,0 +1,165 @@
+use crate::config::{NatsConfig, TransportBackend};
+use crate::db_read::{PacketError, PacketPoller};
+use crate::db_write::PacketData;
+use crate::transport::wire::WirePacket;
+use crate::transport::{inject_received, receive_with_heartbeat, PacketTransport, TransportError};
+use async_nats::connection::State;
+use async_nats::jetstream::consumer::{pull, AckPolicy, DeliverPolicy};
+use async_nats::jetstream::{self, stream};
+use async_nats::{Client, ConnectOptions};
+use async_trait::async_trait;
+use futures::future::join_all;
+use futures::StreamExt;
+use std::future::IntoFuture;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+// 送信の完了 (JetStreamのACK) を待つ時間の上限
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+// ACKされなかったメッセージを再送するまでの時間
+const ACK_WAIT: Duration = Duration::from_secs(30);
+// 注入に失敗し続けるメッセージを再送する回数の上限
+const MAX_DELIVER: i64 = 5;
+
+fn nats_error(e: impl std::fmt::Display) -> TransportError {
+    TransportError::Nats(e.to_string())
+}
+
+// NATS JetStreamを経由するトランスポート。
+// パケットは宛先ノードのサブジェクトへ送信し、ノードごとの永続コンシューマーで受信してACKする
+pub struct NatsTransport {
+    config: NatsConfig,
+    client: Client,
+    jetstream: jetstream::Context,
+    // ストリームは最初に使用する時点で作成する (起動時にサーバーへ接続できなくてもよいように)
+    stream: OnceCell<stream::Stream>,
+}
+
+impl NatsTransport {
+    pub async fn connect(config: &NatsConfig) -> Result<Self, TransportError> {
+        let mut options = ConnectOptions::new().name("rdb-tunnel").retry_on_initial_connect();
+        if let Some(username) = &config.username {
+            options = options.user_and_password(username.clone(), config.password.clone().unwrap_or_default());
+        }
+        let client = options.connect(config.url.as_str()).await.map_err(nats_error)?;
+        info!("NATSに接続します: {} (ストリーム {})", config.url, config.stream);
+        Ok(Self {
+            config: config.clone(),
+            jetstream: jetstream::new(client.clone()),
+            client,
+            stream: OnceCell::new(),
+        })
+    }
+
+    async fn stream(&self) -> Result<&stream::Stream, TransportError> {
+        self.stream
+            .get_or_try_init(|| async {
+                let stream = self
+                    .jetstream
+                    .get_or_create_stream(stream::Config {
+                        name: self.config.stream.clone(),
+                        subjects: vec![format!("{}.>", self.config.subject_prefix)],
+                        max_age: self.config.max_age,
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(nats_error)?;
+                info!("JetStreamのストリーム {} を使用します", self.config.stream);
+                Ok(stream)
+            })
+            .await
+    }
+
+    // サブジェクトのトークンに '.' は使えないため、IPアドレスの区切りを '_' に置き換える
+    fn node_subject(&self, ip: IpAddr) -> String {
+        let token = ip.to_string().replace(['.', ':'], "_");
+        format!("{}.node.{}", self.config.subject_prefix, token)
+    }
+
+    fn broadcast_subject(&self) -> String {
+        format!("{}.broadcast", self.config.subject_prefix)
+    }
+}
+
+#[async_trait]
+impl PacketTransport for NatsTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Nats
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        self.stream().await?;
+
+        let mut acks = Vec::with_capacity(packets.len());
+        for packet in packets {
+            let dst_ip = packet.dst_ip.ip();
+            let subject = if PacketPoller::is_broadcast_ip(&dst_ip) {
+                self.broadcast_subject()
+            } else {
+                self.node_subject(dst_ip)
+            };
+            let payload = WirePacket::encode(packet)?;
+            acks.push(self.jetstream.publish(subject, payload.into()).await.map_err(nats_error)?);
+        }
+
+        let results = timeout(SEND_TIMEOUT, join_all(acks.into_iter().map(IntoFuture::into_future)))
+            .await
+            .map_err(|_| nats_error("JetStreamのACKがタイムアウトしました"))?;
+        for result in results {
+            result.map_err(nats_error)?;
+        }
+        debug!("{}個のパケットをNATSへ送信しました", packets.len());
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        // 新しいノードは直近のメッセージから受信し、再起動後はコンシューマーの位置から再開する
+        let durable_name = format!("rdb-tunnel-{}", poller.node_ip().to_string().replace(['.', ':'], "_"));
+        let subjects = vec![self.node_subject(poller.node_ip()), self.broadcast_subject()];
+        let consumer = self
+            .stream()
+            .await?
+            .get_or_create_consumer(
+                &durable_name,
+                pull::Config {
+                    durable_name: Some(durable_name.clone()),
+                    filter_subjects: subjects.clone(),
+                    deliver_policy: DeliverPolicy::New,
+                    ack_policy: AckPolicy::Explicit,
+                    ack_wait: ACK_WAIT,
+                    max_deliver: MAX_DELIVER,
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(nats_error)?;
+        let mut messages = consumer.messages().await.map_err(nats_error)?;
+        info!("NATSからパケットの受信を開始します: {} (コンシューマー {})", subjects.join(", "), durable_name);
+
+        loop {
+            let message = match receive_with_heartbeat(self, messages.next()).await {
+                Some(Some(message)) => message.map_err(nats_error)?,
+                Some(None) => return Err(nats_error("メッセージの受信が終了しました").into()),
+                None => continue,
+            };
+
+            let packet = match WirePacket::decode(&message.payload) {
+                Ok(packet) => Some(packet),
+                Err(e) => {
+                    warn!("NATSのメッセージを解析できないためスキップします: {}", e);
+                    None
+                }
+            };
+            // 注入に失敗した場合はACKせずに終了し、ACK_WAITの経過後にサーバーから再送させる
+            inject_received(poller, packet).await?;
+            message.ack().await.map_err(nats_error)?;
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        self.client.connection_state() == State::Connected
+    }
+}
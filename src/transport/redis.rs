@@ -0,0 +1,137 @@
+use crate::config::{RedisConfig, TransportBackend};
+use crate::db_read::{PacketError, PacketPoller};
+use crate::db_write::PacketData;
+use crate::health;
+use crate::transport::wire::WirePacket;
+use crate::transport::{inject_received, PacketTransport, TransportError};
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::streams::{StreamMaxlen, StreamReadOptions, StreamReadReply};
+use redis::{AsyncCommands, AsyncConnectionConfig, Client};
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+// ストリームのエントリーでパケットを格納するフィールド名
+const PACKET_FIELD: &str = "packet";
+// 一度に読み込むエントリーの最大数
+const READ_COUNT: usize = 256;
+// XREADGROUPで待機する時間 (この間隔で稼働状態を報告する)
+const BLOCK_INTERVAL: Duration = Duration::from_secs(5);
+// Redisへの接続確認のタイムアウト
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Redis Streamsを経由するトランスポート。
+// 全ノードが1つのストリームへ追記し、ノードごとのコンシューマーグループで受信してACKする
+pub struct RedisTransport {
+    config: RedisConfig,
+    client: Client,
+    // 送信とヘルスチェック用の接続 (切断時は自動で再接続される)
+    connection: ConnectionManager,
+}
+
+impl RedisTransport {
+    pub async fn connect(config: &RedisConfig) -> Result<Self, TransportError> {
+        let client = Client::open(config.url.as_str())?;
+        let connection = ConnectionManager::new(client.clone()).await?;
+        info!("Redisに接続しました: ストリーム {}", config.stream);
+        Ok(Self { config: config.clone(), client, connection })
+    }
+}
+
+#[async_trait]
+impl PacketTransport for RedisTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Redis
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        let mut pipe = redis::pipe();
+        for packet in packets {
+            let payload = WirePacket::encode(packet)?;
+            pipe.xadd_maxlen(
+                &self.config.stream,
+                StreamMaxlen::Approx(self.config.max_len),
+                "*",
+                &[(PACKET_FIELD, payload)],
+            )
+            .ignore();
+        }
+        let mut connection = self.connection.clone();
+        pipe.query_async::<()>(&mut connection).await?;
+        debug!("{}個のパケットをRedisへ送信しました", packets.len());
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        // ブロッキング読み込みは他のコマンドを待たせるため、専用の接続を使用する
+        let connection_config = AsyncConnectionConfig::new().set_response_timeout(Some(BLOCK_INTERVAL * 2));
+        let mut connection = self
+            .client
+            .get_multiplexed_async_connection_with_config(&connection_config)
+            .await
+            .map_err(TransportError::from)?;
+
+        // 新しいノードは直近のエントリーから受信し、再起動後はグループの位置から再開する
+        let group = format!("rdb-tunnel-{}", poller.node_ip());
+        let created: Result<(), redis::RedisError> =
+            connection.xgroup_create_mkstream(&self.config.stream, &group, "$").await;
+        match created {
+            Ok(()) => info!("コンシューマーグループ {} を作成しました", group),
+            Err(e) if e.code() == Some("BUSYGROUP") => {}
+            Err(e) => return Err(TransportError::from(e).into()),
+        }
+        info!("Redisからパケットの受信を開始します: ストリーム {} (グループ {})", self.config.stream, group);
+
+        // 前回ACKせずに終了したエントリーを先に処理してから、新しいエントリーを待つ
+        let mut cursor = "0";
+        loop {
+            let options = StreamReadOptions::default()
+                .group(&group, "rdb-tunnel")
+                .count(READ_COUNT)
+                .block(BLOCK_INTERVAL.as_millis() as usize);
+            let reply: Option<StreamReadReply> = connection
+                .xread_options(&[&self.config.stream], &[cursor], &options)
+                .await
+                .map_err(TransportError::from)?;
+            health::record_poller_heartbeat();
+            health::record_poll_success();
+
+            let entries: Vec<_> = reply
+                .into_iter()
+                .flat_map(|reply| reply.keys)
+                .flat_map(|key| key.ids)
+                .collect();
+            if entries.is_empty() {
+                cursor = ">";
+                continue;
+            }
+
+            let mut packets = Vec::new();
+            for entry in &entries {
+                let packet = match entry.get::<Vec<u8>>(PACKET_FIELD).map(|payload| WirePacket::decode(&payload)) {
+                    Some(Ok(packet)) => packet,
+                    Some(Err(e)) => {
+                        warn!("Redisのエントリー {} を解析できないためスキップします: {}", entry.id, e);
+                        continue;
+                    }
+                    None => continue,
+                };
+                packets.push(packet);
+            }
+            inject_received(poller, packets).await?;
+
+            let ids: Vec<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+            let _: usize = connection
+                .xack(&self.config.stream, &group, &ids)
+                .await
+                .map_err(TransportError::from)?;
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        let mut connection = self.connection.clone();
+        let ping = timeout(HEALTH_CHECK_TIMEOUT, redis::cmd("PING").query_async::<String>(&mut connection)).await;
+        matches!(ping, Ok(Ok(_)))
+    }
+}
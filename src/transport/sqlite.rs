@@ -0,0 +1,284 @@
+use crate::chunk::Chunk;
+use crate::config::{SqliteConfig, TransportBackend};
+use crate::database::types::MacAddr;
+use crate::db_read::{PacketError, PacketInfo, PacketPoller};
+use crate::db_write::PacketData;
+use crate::health;
+use crate::transport::{inject_received, PacketTransport, TransportError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::interval;
+use tracing::{debug, error, info, warn};
+
+// packetsテーブルをポーリングする間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// 一度に読み込む行数の上限
+const FETCH_LIMIT: i64 = 1000;
+// 他のプロセスが書き込み中の場合に待つ時間
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS packets (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        src_mac BLOB NOT NULL,
+        dst_mac BLOB NOT NULL,
+        ether_type INTEGER NOT NULL,
+        src_ip TEXT NOT NULL,
+        dst_ip TEXT NOT NULL,
+        src_port INTEGER NOT NULL,
+        dst_port INTEGER NOT NULL,
+        ip_protocol INTEGER NOT NULL,
+        timestamp INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        raw_packet BLOB NOT NULL,
+        interface TEXT NOT NULL,
+        seq INTEGER,
+        dscp INTEGER,
+        ecn INTEGER,
+        codec INTEGER,
+        chunk_id INTEGER,
+        chunk_index INTEGER,
+        chunk_count INTEGER,
+        encapsulation INTEGER,
+        inner_src_ip TEXT,
+        inner_dst_ip TEXT,
+        inner_src_port INTEGER,
+        inner_dst_port INTEGER,
+        inner_ip_protocol INTEGER,
+        vni INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS packets_timestamp_idx ON packets (timestamp);
+";
+// 作成後に追加した列と型
+const ADDED_COLUMNS: &[(&str, &str)] = &[
+    ("seq", "INTEGER"),
+    ("dscp", "INTEGER"),
+    ("ecn", "INTEGER"),
+    ("codec", "INTEGER"),
+    ("chunk_id", "INTEGER"),
+    ("chunk_index", "INTEGER"),
+    ("chunk_count", "INTEGER"),
+    ("encapsulation", "INTEGER"),
+    ("inner_src_ip", "TEXT"),
+    ("inner_dst_ip", "TEXT"),
+    ("inner_src_port", "INTEGER"),
+    ("inner_dst_port", "INTEGER"),
+    ("inner_ip_protocol", "INTEGER"),
+    ("vni", "INTEGER"),
+];
+
+// SQLiteのファイルを経由するトランスポート。TimescaleDBを用意せずに開発やCIでパイプライン全体を動かすためのもの。
+// 同じファイルを開いた複数のプロセスの間でパケットを中継する
+pub struct SqliteTransport {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteTransport {
+    pub fn open(config: &SqliteConfig) -> Result<Self, TransportError> {
+        let connection = Connection::open(&config.path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // 書き込み中も他のプロセスから読み込めるようにする
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        // 列を追加する前に作成したファイルにも追加する
+        for (column, column_type) in ADDED_COLUMNS {
+            let exists: bool = connection.query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('packets') WHERE name = ?1",
+                [column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                connection.execute_batch(&format!("ALTER TABLE packets ADD COLUMN {} {}", column, column_type))?;
+            }
+        }
+        info!("SQLiteのデータベースを開きました: {}", config.path.display());
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    // rusqliteの呼び出しはブロッキングのため、ランタイムのスレッドを占有しないようにする
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, TransportError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut connection)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+        .map_err(TransportError::from)
+    }
+}
+
+// 行をパケットに変換する。不正な値の行はNoneを返す
+fn packet_from_row(row: &Row) -> rusqlite::Result<Option<PacketInfo>> {
+    let mac = |index| -> rusqlite::Result<Option<MacAddr>> {
+        Ok(row.get::<_, Vec<u8>>(index)?.try_into().ok().map(MacAddr))
+    };
+    let ip = |index| -> rusqlite::Result<Option<IpAddr>> { Ok(row.get::<_, String>(index)?.parse().ok()) };
+
+    let (Some(src_mac), Some(dst_mac), Some(src_ip), Some(dst_ip), Some(timestamp)) = (
+        mac(1)?,
+        mac(2)?,
+        ip(4)?,
+        ip(5)?,
+        DateTime::<Utc>::from_timestamp_micros(row.get(9)?),
+    ) else {
+        return Ok(None);
+    };
+
+    Ok(Some(PacketInfo {
+        src_mac,
+        dst_mac,
+        ether_type: row.get(3)?,
+        src_ip,
+        dst_ip,
+        src_port: Some(row.get(6)?),
+        dst_port: Some(row.get(7)?),
+        ip_protocol: row.get(8)?,
+        timestamp,
+        data: row.get(10)?,
+        raw_packet: row.get(11)?,
+        seq: row.get(12)?,
+        // 列を追加する前に書き込まれた行はNULL (圧縮なし)
+        codec: row.get::<_, Option<i16>>(13)?.unwrap_or_default(),
+        chunk: Chunk::from_columns(row.get(14)?, row.get(15)?, row.get(16)?),
+    }))
+}
+
+#[async_trait]
+impl PacketTransport for SqliteTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Sqlite
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        let rows: Vec<_> = packets
+            .iter()
+            .map(|packet| {
+                (
+                    packet.src_mac.0.to_vec(),
+                    packet.dst_mac.0.to_vec(),
+                    packet.ether_type.as_i32(),
+                    packet.src_ip.ip().to_string(),
+                    packet.dst_ip.ip().to_string(),
+                    packet.src_port,
+                    packet.dst_port,
+                    packet.ip_protocol.as_i32(),
+                    packet.timestamp.timestamp_micros(),
+                    packet.data.clone(),
+                    packet.raw_packet.clone(),
+                    packet.interface.clone(),
+                    packet.seq,
+                    packet.dscp,
+                    packet.ecn,
+                    packet.codec.id(),
+                    (packet.chunk_id, packet.chunk_index, packet.chunk_count),
+                    (
+                        packet.encapsulation,
+                        packet.inner_src_ip.as_ref().map(|ip| ip.ip().to_string()),
+                        packet.inner_dst_ip.as_ref().map(|ip| ip.ip().to_string()),
+                        packet.inner_src_port,
+                        packet.inner_dst_port,
+                        packet.inner_ip_protocol,
+                        packet.vni,
+                    ),
+                )
+            })
+            .collect();
+
+        let count = rows.len();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare_cached(
+                    "INSERT INTO packets (
+                        src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+                        ip_protocol, timestamp, data, raw_packet, interface, seq, dscp, ecn, codec,
+                        chunk_id, chunk_index, chunk_count, encapsulation, inner_src_ip, inner_dst_ip,
+                        inner_src_port, inner_dst_port, inner_ip_protocol, vni
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+                        ?20, ?21, ?22, ?23, ?24, ?25, ?26)",
+                )?;
+                for row in rows {
+                    let inner = row.17;
+                    statement.execute(params![
+                        row.0, row.1, row.2, row.3, row.4, row.5, row.6, row.7, row.8, &*row.9, &*row.10, row.11,
+                        row.12, row.13, row.14, row.15, row.16.0, row.16.1, row.16.2, inner.0, inner.1, inner.2,
+                        inner.3, inner.4, inner.5, inner.6
+                    ])?;
+                }
+            }
+            transaction.commit()
+        })
+        .await?;
+        debug!("{}個のパケットをSQLiteへ書き込みました", count);
+        Ok(())
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        // 起動前に書き込まれたパケットは注入しない
+        let mut cursor: i64 = self
+            .with_connection(|connection| {
+                connection.query_row("SELECT COALESCE(MAX(id), 0) FROM packets", [], |row| row.get(0))
+            })
+            .await?;
+        info!("SQLiteからパケットの受信を開始します (id {} 以降)", cursor);
+
+        let mut interval = interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            health::record_poller_heartbeat();
+
+            let after = cursor;
+            let fetched = self
+                .with_connection(move |connection| {
+                    let mut statement = connection.prepare_cached(
+                        "SELECT id, src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+                                ip_protocol, timestamp, data, raw_packet, seq, codec,
+                                chunk_id, chunk_index, chunk_count
+                         FROM packets WHERE id > ?1 ORDER BY id LIMIT ?2",
+                    )?;
+                    let rows = statement.query_map(params![after, FETCH_LIMIT], |row| {
+                        Ok((row.get::<_, i64>(0)?, packet_from_row(row)?))
+                    })?;
+                    rows.collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .await;
+            let rows = match fetched {
+                Ok(rows) => rows,
+                Err(e) => {
+                    error!("パケット処理中にエラーが発生しました: {:?}", e);
+                    continue;
+                }
+            };
+            health::record_poll_success();
+            let Some((last_id, _)) = rows.last() else {
+                continue;
+            };
+            cursor = *last_id;
+
+            let mut packets = Vec::new();
+            for (id, packet) in rows {
+                let Some(packet) = packet else {
+                    warn!("SQLiteの行 {} を解析できないためスキップします", id);
+                    continue;
+                };
+                packets.push(packet);
+            }
+            inject_received(poller, packets).await?;
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        self.with_connection(|connection| connection.query_row("SELECT 1", [], |row| row.get::<_, i64>(0)))
+            .await
+            .is_ok()
+    }
+}
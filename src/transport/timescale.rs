@@ -0,0 +1,160 @@
+#[cfg(feature = "replication")]
+use crate::config::PollMode;
+use crate::config::TransportBackend;
+use crate::database::database::Database;
+use crate::database::error::DbError;
+#[cfg(feature = "replication")]
+use crate::db_replication;
+use crate::db_read::{PacketError, PacketPoller};
+use crate::db_write::PacketData;
+use crate::health;
+use crate::messages::{message, MessageId};
+use crate::retry::retry;
+use crate::setup_logger::LogThrottle;
+use crate::transport::{PacketTransport, TransportError};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval, timeout};
+use tokio_postgres::types::ToSql;
+use tracing::{debug, error};
+
+// DB接続確認のタイムアウト
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+// packetsテーブルをポーリングする間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// データベースの停止中などに、ポーリングの失敗を毎回ログへ出力しないようにする
+static POLL_ERRORS: LogThrottle = LogThrottle::new(Duration::from_secs(10));
+
+// PostgreSQL/TimescaleDBのpacketsテーブルを経由するトランスポート
+pub struct TimescaleTransport {
+    db: Arc<Database>,
+}
+
+impl TimescaleTransport {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl PacketTransport for TimescaleTransport {
+    fn backend(&self) -> TransportBackend {
+        TransportBackend::Timescale
+    }
+
+    async fn publish(&self, packets: &[PacketData]) -> Result<(), TransportError> {
+        Ok(write_packets(&self.db, packets).await?)
+    }
+
+    async fn subscribe(&self, poller: &PacketPoller) -> Result<(), PacketError> {
+        #[cfg(feature = "replication")]
+        if poller.mode() == PollMode::Replication {
+            return db_replication::stream_packets(&self.db, poller).await;
+        }
+        poller.restore_cursor(&self.db).await?;
+        let mut interval = interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            health::record_poller_heartbeat();
+
+            if let Err(e) = poller.poll_and_send_packets(&self.db).await {
+                if let Some(suppressed) = POLL_ERRORS.allow() {
+                    error!("{}", message(MessageId::PollFailed, &[&e, &suppressed]));
+                }
+            }
+        }
+    }
+
+    async fn health_check(&self) -> bool {
+        let check = async {
+            let client = self.db.pool.get().await.ok()?;
+            client.simple_query("SELECT 1").await.ok()
+        };
+        matches!(timeout(DB_CHECK_TIMEOUT, check).await, Ok(Some(_)))
+    }
+}
+
+// 1回のINSERTで挿入する行数
+pub const CHUNK_SIZE: usize = 1000;
+// 1行あたりのパラメータ数
+const INSERT_COLUMNS: usize = 26;
+
+// 複数行のINSERT文と、その順に並べたパラメータを組み立てる
+pub fn insert_statement(chunk: &[PacketData]) -> (String, Vec<&(dyn ToSql + Sync)>) {
+    let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(chunk.len() * INSERT_COLUMNS);
+    for packet in chunk {
+        params.extend_from_slice(&[
+            &packet.src_mac,
+            &packet.dst_mac,
+            &packet.ether_type,
+            &packet.src_ip,
+            &packet.dst_ip,
+            &packet.src_port,
+            &packet.dst_port,
+            &packet.ip_protocol,
+            &packet.timestamp,
+            &packet.data,
+            &packet.raw_packet,
+            &packet.interface,
+            &packet.seq,
+            &packet.dscp,
+            &packet.ecn,
+            &packet.codec,
+            &packet.chunk_id,
+            &packet.chunk_index,
+            &packet.chunk_count,
+            &packet.encapsulation,
+            &packet.inner_src_ip,
+            &packet.inner_dst_ip,
+            &packet.inner_src_port,
+            &packet.inner_dst_port,
+            &packet.inner_ip_protocol,
+            &packet.vni,
+        ]);
+    }
+
+    let placeholders: Vec<String> = (0..chunk.len())
+        .map(|i| {
+            let row = (1..=INSERT_COLUMNS)
+                .map(|column| format!("${}", i * INSERT_COLUMNS + column))
+                .collect::<Vec<_>>();
+            format!("({})", row.join(","))
+        })
+        .collect();
+
+    let query = format!(
+        "INSERT INTO packets (
+            src_mac, dst_mac, ether_type, src_ip, dst_ip, src_port, dst_port,
+            ip_protocol, timestamp, data, raw_packet, interface, seq, dscp, ecn, codec,
+            chunk_id, chunk_index, chunk_count, encapsulation, inner_src_ip, inner_dst_ip,
+            inner_src_port, inner_dst_port, inner_ip_protocol, vni
+        ) VALUES {}",
+        placeholders.join(",")
+    );
+    (query, params)
+}
+
+// 一時的なエラーで失敗した場合は、バッチを破棄せずトランザクション全体を再試行する
+async fn write_packets(db: &Database, packets: &[PacketData]) -> Result<(), DbError> {
+    let start_time = std::time::Instant::now();
+    let processed = retry("insert_packets", || async {
+        let mut client = db.pool.get().await?;
+        let transaction = client.transaction().await?;
+
+        let mut processed = 0;
+        for chunk in packets.chunks(CHUNK_SIZE) {
+            let (query, params) = insert_statement(chunk);
+            transaction.execute(&query, &params).await?;
+            processed += chunk.len();
+        }
+
+        transaction.commit().await?;
+        Ok::<_, DbError>(processed)
+    })
+    .await?;
+    debug!("{}個のパケットを{}秒で一括挿入しました",
+        processed, start_time.elapsed().as_secs_f64());
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use crate::chunk::Chunk;
+use crate::database::types::MacAddr;
+use crate::db_read::PacketInfo;
+use crate::db_write::PacketData;
+use crate::transport::TransportError;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+// メッセージブローカーで送受信するパケットの形式 (JSON)
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WirePacket {
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    pub ether_type: i32,
+    pub src_ip: IpAddr,
+    pub dst_ip: IpAddr,
+    pub src_port: i32,
+    pub dst_port: i32,
+    pub ip_protocol: i32,
+    pub timestamp: DateTime<Utc>,
+    #[serde(with = "base64_bytes")]
+    pub data: Bytes,
+    #[serde(with = "base64_bytes")]
+    pub raw_packet: Bytes,
+    pub interface: String,
+    // 連番に対応していないノードが送信したパケットには含まれない
+    #[serde(default)]
+    pub seq: Option<i64>,
+    #[serde(default)]
+    pub dscp: Option<i16>,
+    #[serde(default)]
+    pub ecn: Option<i16>,
+    #[serde(default)]
+    pub codec: i16,
+    #[serde(default)]
+    pub chunk_id: Option<i64>,
+    #[serde(default)]
+    pub chunk_index: Option<i16>,
+    #[serde(default)]
+    pub chunk_count: Option<i16>,
+    // カプセル化されたパケットの内側の情報 (受信側では使用しない)
+    #[serde(default)]
+    pub encapsulation: Option<i16>,
+    #[serde(default)]
+    pub inner_src_ip: Option<IpAddr>,
+    #[serde(default)]
+    pub inner_dst_ip: Option<IpAddr>,
+    #[serde(default)]
+    pub inner_src_port: Option<i32>,
+    #[serde(default)]
+    pub inner_dst_port: Option<i32>,
+    #[serde(default)]
+    pub inner_ip_protocol: Option<i32>,
+    #[serde(default)]
+    pub vni: Option<i32>,
+}
+
+impl WirePacket {
+    pub fn encode(packet: &PacketData) -> Result<Vec<u8>, TransportError> {
+        let wire = WirePacket {
+            src_mac: packet.src_mac.0,
+            dst_mac: packet.dst_mac.0,
+            ether_type: packet.ether_type.as_i32(),
+            src_ip: packet.src_ip.ip(),
+            dst_ip: packet.dst_ip.ip(),
+            src_port: packet.src_port,
+            dst_port: packet.dst_port,
+            ip_protocol: packet.ip_protocol.as_i32(),
+            timestamp: packet.timestamp,
+            data: packet.data.0.clone(),
+            raw_packet: packet.raw_packet.0.clone(),
+            interface: packet.interface.clone(),
+            seq: packet.seq,
+            dscp: packet.dscp,
+            ecn: packet.ecn,
+            codec: packet.codec.id(),
+            chunk_id: packet.chunk_id,
+            chunk_index: packet.chunk_index,
+            chunk_count: packet.chunk_count,
+            encapsulation: packet.encapsulation,
+            inner_src_ip: packet.inner_src_ip.as_ref().map(|ip| ip.ip()),
+            inner_dst_ip: packet.inner_dst_ip.as_ref().map(|ip| ip.ip()),
+            inner_src_port: packet.inner_src_port,
+            inner_dst_port: packet.inner_dst_port,
+            inner_ip_protocol: packet.inner_ip_protocol,
+            vni: packet.vni,
+        };
+        serde_json::to_vec(&wire).map_err(|e| TransportError::Encoding(e.to_string()))
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<PacketInfo, TransportError> {
+        let wire: WirePacket =
+            serde_json::from_slice(payload).map_err(|e| TransportError::Encoding(e.to_string()))?;
+        Ok(PacketInfo {
+            src_mac: MacAddr(wire.src_mac),
+            dst_mac: MacAddr(wire.dst_mac),
+            ether_type: wire.ether_type,
+            src_ip: wire.src_ip,
+            dst_ip: wire.dst_ip,
+            // データベースと同様に、ポートがないパケットは0として扱う
+            src_port: Some(wire.src_port),
+            dst_port: Some(wire.dst_port),
+            ip_protocol: wire.ip_protocol,
+            timestamp: wire.timestamp,
+            data: wire.data.into(),
+            raw_packet: wire.raw_packet.into(),
+            seq: wire.seq,
+            codec: wire.codec,
+            chunk: Chunk::from_columns(wire.chunk_id, wire.chunk_index, wire.chunk_count),
+        })
+    }
+}
+
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map(Bytes::from).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_write::parse_and_analyze_packet;
+    use crate::test_support::frame_spec;
+    use futures::executor::block_on;
+    use proptest::prelude::*;
+
+    proptest! {
+        // ブローカーを経由しても、受信側で解析時と同じ値が得られる
+        #[test]
+        fn decode_reproduces_encoded_packet(spec in frame_spec()) {
+            let packet = block_on(parse_and_analyze_packet(spec.to_frame().into())).unwrap();
+            let decoded = WirePacket::decode(&WirePacket::encode(&packet).unwrap()).unwrap();
+
+            prop_assert_eq!(decoded.src_mac.0, packet.src_mac.0);
+            prop_assert_eq!(decoded.dst_mac.0, packet.dst_mac.0);
+            prop_assert_eq!(decoded.ether_type, packet.ether_type.as_i32());
+            prop_assert_eq!(decoded.src_ip, packet.src_ip.ip());
+            prop_assert_eq!(decoded.dst_ip, packet.dst_ip.ip());
+            prop_assert_eq!(decoded.src_port, Some(packet.src_port));
+            prop_assert_eq!(decoded.dst_port, Some(packet.dst_port));
+            prop_assert_eq!(decoded.ip_protocol, packet.ip_protocol.as_i32());
+            prop_assert_eq!(decoded.timestamp, packet.timestamp);
+            prop_assert_eq!(&decoded.data[..], &packet.data[..]);
+            prop_assert_eq!(&decoded.raw_packet[..], &packet.raw_packet[..]);
+            prop_assert_eq!(decoded.seq, packet.seq);
+            prop_assert_eq!(decoded.codec, packet.codec.id());
+        }
+    }
+}
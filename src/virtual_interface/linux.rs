@@ -0,0 +1,263 @@
+use super::{PolicyRule, VirtualInterface};
+use crate::config::{InterfaceConfig, InterfaceMode};
+use crate::error::InitProcessError;
+use futures::TryStreamExt;
+use ipnetwork::IpNetwork;
+use netlink_packet_route::link::LinkAttribute;
+use netlink_packet_route::route::RouteMessage;
+use netlink_packet_route::rule::{RuleAction, RuleMessage};
+use rtnetlink::{new_connection, Handle};
+use std::net::IpAddr;
+use tun_tap::{Iface, Mode};
+
+// Linux: TAP (L2) / TUN (L3) デバイスをrtnetlinkで設定する
+pub struct TapInterface {
+    iface: Iface,
+}
+
+impl TapInterface {
+    pub async fn create(config: &InterfaceConfig, address: IpNetwork) -> Result<Self, InitProcessError> {
+        // 既存のデバイスを開くと他のインスタンスとフレームを奪い合うため、同名のインターフェースがある場合は作成しない
+        if !config.name.contains('%') {
+            let (connection, handle, _) = new_connection()
+                .map_err(|e| InitProcessError::VirtualInterfaceError(format!("netlink接続の作成に失敗: {}", e)))?;
+            tokio::spawn(connection);
+            if find_link(&handle, &config.name).await.is_some() {
+                return Err(InitProcessError::VirtualInterfaceError(format!(
+                    "インターフェース {} は既に存在します ([interface] name で別の名前を指定してください)",
+                    config.name
+                )));
+            }
+        }
+
+        let mode = match config.mode {
+            InterfaceMode::Tap => Mode::Tap,
+            InterfaceMode::Tun => Mode::Tun,
+        };
+        let iface = Iface::new(&config.name, mode)
+            .map_err(|e| InitProcessError::VirtualInterfaceError(e.to_string()))?;
+        setup_interface(iface.name(), address, config).await?;
+        Ok(Self { iface })
+    }
+}
+
+impl VirtualInterface for TapInterface {
+    fn name(&self) -> &str {
+        self.iface.name()
+    }
+}
+
+// netlinkコネクションを作成し、インターフェースのIDを取得する
+async fn link_handle(name: &str) -> Result<(Handle, u32), InitProcessError> {
+    let (connection, handle, _) = new_connection()
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("netlink接続の作成に失敗: {}", e)))?;
+    tokio::spawn(connection);
+
+    let interface = handle.link().get()
+        .match_name(name.to_string())
+        .execute()
+        .try_next()
+        .await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("インターフェース情報の取得に失敗: {}", e)))?
+        .ok_or_else(|| InitProcessError::VirtualInterfaceError("インターフェースが見つかりません".to_string()))?;
+
+    Ok((handle, interface.header.index))
+}
+
+async fn setup_interface(name: &str, ip_net: IpNetwork, config: &InterfaceConfig) -> Result<(), InitProcessError> {
+    let (handle, if_index) = link_handle(name).await?;
+
+    // MACアドレスの設定 (有効化する前に行う)
+    if let Some(mac) = &config.mac {
+        handle.link().set(if_index)
+            .address(mac.0.to_vec())
+            .execute()
+            .await
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("MACアドレスの設定に失敗: {}", e)))?;
+    }
+
+    // MTU・送信キュー長の設定
+    if let Some(mtu) = config.mtu {
+        handle.link().set(if_index)
+            .mtu(mtu)
+            .execute()
+            .await
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("MTUの設定に失敗: {}", e)))?;
+    }
+    if let Some(txqueuelen) = config.txqueuelen {
+        let mut request = handle.link().set(if_index);
+        request.message_mut().attributes.push(LinkAttribute::TxQueueLen(txqueuelen));
+        request.execute().await
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("送信キュー長の設定に失敗: {}", e)))?;
+    }
+
+    // IPv6の無効化 (アドレスを設定する前に行う)
+    if config.disable_ipv6 {
+        let path = format!("/proc/sys/net/ipv6/conf/{}/disable_ipv6", name);
+        std::fs::write(&path, "1")
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("{} の書き込みに失敗: {}", path, e)))?;
+    }
+
+    // IPアドレスの設定
+    for address in std::iter::once(ip_net).chain(config.addresses.iter().copied()) {
+        handle.address().add(
+            if_index,
+            address.ip(),
+            address.prefix(),
+        ).execute().await
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("IPアドレス {} の設定に失敗: {}", address, e)))?;
+    }
+
+    // インターフェースの有効化
+    handle.link().set(if_index)
+        .up()
+        .execute()
+        .await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("インターフェースの有効化に失敗: {}", e)))?;
+
+    Ok(())
+}
+
+// 仮想NIC経由の経路のメッセージ。gatewayを指定しない場合はインターフェースに直接送る
+fn route_message(
+    handle: &Handle,
+    if_index: u32,
+    destination: IpNetwork,
+    gateway: Option<IpAddr>,
+    table: u32,
+) -> Result<RouteMessage, InitProcessError> {
+    let request = handle.route().add().output_interface(if_index).table_id(table);
+    let message = match (destination, gateway) {
+        (IpNetwork::V4(destination), gateway @ (None | Some(IpAddr::V4(_)))) => {
+            let mut request = request.v4().destination_prefix(destination.network(), destination.prefix());
+            if let Some(IpAddr::V4(gateway)) = gateway {
+                request = request.gateway(gateway);
+            }
+            request.message_mut().clone()
+        }
+        (IpNetwork::V6(destination), gateway @ (None | Some(IpAddr::V6(_)))) => {
+            let mut request = request.v6().destination_prefix(destination.network(), destination.prefix());
+            if let Some(IpAddr::V6(gateway)) = gateway {
+                request = request.gateway(gateway);
+            }
+            request.message_mut().clone()
+        }
+        _ => {
+            return Err(InitProcessError::VirtualInterfaceError(format!(
+                "経路 {} とゲートウェイのアドレスファミリーが一致しません",
+                destination
+            )))
+        }
+    };
+    Ok(message)
+}
+
+// 仮想NIC経由の経路を追加する (既に存在する場合は置き換える)
+pub async fn add_route(
+    name: &str,
+    destination: IpNetwork,
+    gateway: Option<IpAddr>,
+    table: u32,
+) -> Result<(), InitProcessError> {
+    let (handle, if_index) = link_handle(name).await?;
+    let mut request = handle.route().add().replace();
+    *request.message_mut() = route_message(&handle, if_index, destination, gateway, table)?;
+    request.execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("経路 {} の追加に失敗: {}", destination, e)))
+}
+
+// 仮想NIC経由の経路を削除する
+pub async fn delete_route(
+    name: &str,
+    destination: IpNetwork,
+    gateway: Option<IpAddr>,
+    table: u32,
+) -> Result<(), InitProcessError> {
+    let (handle, if_index) = link_handle(name).await?;
+    let message = route_message(&handle, if_index, destination, gateway, table)?;
+    handle.route().del(message).execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("経路 {} の削除に失敗: {}", destination, e)))
+}
+
+// ポリシールーティングの規則のメッセージ
+fn rule_message(handle: &Handle, rule: &PolicyRule) -> RuleMessage {
+    let mut request = handle.rule().add().table_id(rule.table).priority(rule.priority).action(RuleAction::ToTable);
+    if let Some(fwmark) = rule.fwmark {
+        request = request.fw_mark(fwmark);
+    }
+    match rule.source {
+        Some(IpNetwork::V4(source)) => request.v4().source_prefix(source.network(), source.prefix()).message_mut().clone(),
+        Some(IpNetwork::V6(source)) => request.v6().source_prefix(source.network(), source.prefix()).message_mut().clone(),
+        None if rule.ipv6 => request.v6().message_mut().clone(),
+        None => request.v4().message_mut().clone(),
+    }
+}
+
+// ポリシールーティングの規則を追加する
+pub async fn add_rule(rule: &PolicyRule) -> Result<(), InitProcessError> {
+    let (connection, handle, _) = new_connection()
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("netlink接続の作成に失敗: {}", e)))?;
+    tokio::spawn(connection);
+    let mut request = handle.rule().add();
+    *request.message_mut() = rule_message(&handle, rule);
+    request.execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("規則 {} の追加に失敗: {}", rule, e)))
+}
+
+// ポリシールーティングの規則を削除する
+pub async fn delete_rule(rule: &PolicyRule) -> Result<(), InitProcessError> {
+    let (connection, handle, _) = new_connection()
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("netlink接続の作成に失敗: {}", e)))?;
+    tokio::spawn(connection);
+    let message = rule_message(&handle, rule);
+    handle.rule().del(message).execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("規則 {} の削除に失敗: {}", rule, e)))
+}
+
+// 名前からインターフェースのIDを取得する (存在しない場合はNone)
+async fn find_link(handle: &Handle, name: &str) -> Option<u32> {
+    let mut links = handle.link().get().match_name(name.to_string()).execute();
+    links.try_next().await.ok().flatten().map(|link| link.header.index)
+}
+
+// ブリッジを作成して有効化する。同名のインターフェースが既に存在する場合はそのまま使用し、falseを返す
+pub async fn create_bridge(name: &str) -> Result<bool, InitProcessError> {
+    let (connection, handle, _) = new_connection()
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("netlink接続の作成に失敗: {}", e)))?;
+    tokio::spawn(connection);
+
+    let created = match find_link(&handle, name).await {
+        Some(_) => false,
+        None => {
+            handle.link().add().bridge(name.to_string()).execute().await
+                .map_err(|e| InitProcessError::VirtualInterfaceError(format!("ブリッジ {} の作成に失敗: {}", name, e)))?;
+            true
+        }
+    };
+    let (handle, if_index) = link_handle(name).await?;
+    handle.link().set(if_index).up().execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("ブリッジ {} の有効化に失敗: {}", name, e)))?;
+    Ok(created)
+}
+
+// インターフェースをブリッジに接続する。bridgeがNoneの場合はブリッジから外す
+pub async fn set_bridge_port(name: &str, bridge: Option<&str>) -> Result<(), InitProcessError> {
+    let (handle, if_index) = link_handle(name).await?;
+    let request = handle.link().set(if_index);
+    let request = match bridge {
+        Some(bridge) => {
+            let (_, bridge_index) = link_handle(bridge).await?;
+            request.controller(bridge_index).up()
+        }
+        None => request.nocontroller(),
+    };
+    request.execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("{} のブリッジの設定に失敗: {}", name, e)))
+}
+
+// インターフェースを削除する
+pub async fn delete_link(name: &str) -> Result<(), InitProcessError> {
+    let (handle, if_index) = link_handle(name).await?;
+    handle.link().del(if_index).execute().await
+        .map_err(|e| InitProcessError::VirtualInterfaceError(format!("{} の削除に失敗: {}", name, e)))
+}
@@ -0,0 +1,197 @@
+use super::VirtualInterface;
+use crate::config::{InterfaceConfig, InterfaceMode};
+use crate::database::types::MacAddr;
+use crate::error::InitProcessError;
+use ipnetwork::IpNetwork;
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::Command;
+use tracing::warn;
+
+const UTUN_CONTROL_NAME: &CStr = c"com.apple.net.utun_control";
+// fethペアの作成を試みるインデックスの上限
+const MAX_FETH_PAIRS: u32 = 64;
+
+fn error(message: impl Into<String>) -> InitProcessError {
+    InitProcessError::VirtualInterfaceError(message.into())
+}
+
+// ifconfigを実行する (失敗時は標準エラー出力を含めて返す)
+fn ifconfig(args: &[&str]) -> Result<(), InitProcessError> {
+    let output = Command::new("ifconfig")
+        .args(args)
+        .output()
+        .map_err(|e| error(format!("ifconfigの実行に失敗: {}", e)))?;
+    if !output.status.success() {
+        return Err(error(format!(
+            "ifconfig {} に失敗: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+pub async fn create(config: &InterfaceConfig, address: IpNetwork) -> Result<Box<dyn VirtualInterface>, InitProcessError> {
+    if config.txqueuelen.is_some() || config.disable_ipv6 {
+        warn!("[interface] txqueuelen と disable_ipv6 はmacOSでは使用できないため無視します");
+    }
+    let interface: Box<dyn VirtualInterface> = match config.mode {
+        InterfaceMode::Tap => Box::new(FethInterface::create(address, config.mac.as_ref())?),
+        InterfaceMode::Tun => Box::new(UtunInterface::create(address)?),
+    };
+
+    let name = interface.name();
+    if let Some(mtu) = config.mtu {
+        ifconfig(&[name, "mtu", &mtu.to_string()])?;
+    }
+    // 追加のアドレスはエイリアスとして設定する
+    for address in &config.addresses {
+        let ip = address.ip().to_string();
+        match (config.mode, address.is_ipv4()) {
+            (InterfaceMode::Tun, true) => {
+                ifconfig(&[name, "inet", &ip, &ip, "netmask", &address.mask().to_string(), "alias"])?
+            }
+            (_, true) => ifconfig(&[name, "inet", &address.to_string(), "alias"])?,
+            (_, false) => ifconfig(&[name, "inet6", &ip, "prefixlen", &address.prefix().to_string(), "alias"])?,
+        }
+    }
+    Ok(interface)
+}
+
+// macOS (L2): fethペア。片側にアドレスを設定し、もう片側でフレームを送受信する
+pub struct FethInterface {
+    name: String,
+    peer: String,
+}
+
+impl FethInterface {
+    fn create(address: IpNetwork, mac: Option<&MacAddr>) -> Result<Self, InitProcessError> {
+        // 使用中のインデックスは作成に失敗するため、空いているペアを探す
+        let (name, peer) = (0..MAX_FETH_PAIRS)
+            .map(|index| (format!("feth{}", index * 2), format!("feth{}", index * 2 + 1)))
+            .find(|(name, peer)| {
+                if ifconfig(&[name, "create"]).is_err() {
+                    return false;
+                }
+                if ifconfig(&[peer, "create"]).is_err() {
+                    let _ = ifconfig(&[name, "destroy"]);
+                    return false;
+                }
+                true
+            })
+            .ok_or_else(|| error("利用可能なfethインターフェースがありません"))?;
+
+        let interface = Self { name, peer };
+        ifconfig(&[&interface.name, "peer", &interface.peer])?;
+        if let Some(mac) = mac {
+            ifconfig(&[&interface.name, "lladdr", &mac.to_string()])?;
+        }
+        let family = if address.is_ipv4() { "inet" } else { "inet6" };
+        ifconfig(&[&interface.name, family, &address.to_string(), "up"])?;
+        ifconfig(&[&interface.peer, "up"])?;
+        Ok(interface)
+    }
+}
+
+impl VirtualInterface for FethInterface {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for FethInterface {
+    fn drop(&mut self) {
+        for name in [&self.peer, &self.name] {
+            if let Err(e) = ifconfig(&[name, "destroy"]) {
+                warn!("fethインターフェースの削除に失敗しました: {}", e);
+            }
+        }
+    }
+}
+
+// macOS (L3): utunデバイス。ソケットを閉じると削除される
+pub struct UtunInterface {
+    name: String,
+    _fd: OwnedFd,
+}
+
+impl UtunInterface {
+    fn create(address: IpNetwork) -> Result<Self, InitProcessError> {
+        let (fd, name) = open_utun().map_err(|e| error(format!("utunデバイスの作成に失敗: {}", e)))?;
+
+        let ip = address.ip().to_string();
+        if address.is_ipv4() {
+            // utunはポイントツーポイントのため、宛先にも自身のアドレスを指定する
+            ifconfig(&[&name, "inet", &ip, &ip, "netmask", &address.mask().to_string(), "up"])?;
+        } else {
+            ifconfig(&[&name, "inet6", &ip, "prefixlen", &address.prefix().to_string(), "up"])?;
+        }
+        Ok(Self { name, _fd: fd })
+    }
+}
+
+impl VirtualInterface for UtunInterface {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn open_utun() -> io::Result<(OwnedFd, String)> {
+    // SAFETY: 戻り値を確認し、成功した場合のみOwnedFdとして所有する
+    let fd = unsafe { libc::socket(libc::PF_SYSTEM, libc::SOCK_DGRAM, libc::SYSPROTO_CONTROL) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: ctl_info/sockaddr_ctlはゼロ初期化が有効なC構造体
+    let mut info: libc::ctl_info = unsafe { mem::zeroed() };
+    for (dst, src) in info.ctl_name.iter_mut().zip(UTUN_CONTROL_NAME.to_bytes_with_nul()) {
+        *dst = *src as libc::c_char;
+    }
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::CTLIOCGINFO, &mut info) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addr: libc::sockaddr_ctl = unsafe { mem::zeroed() };
+    addr.sc_len = mem::size_of::<libc::sockaddr_ctl>() as u8;
+    addr.sc_family = libc::AF_SYSTEM as u8;
+    addr.ss_sysaddr = libc::AF_SYS_CONTROL as u16;
+    addr.sc_id = info.ctl_id;
+    // 0を指定すると空いているutun番号が割り当てられる
+    addr.sc_unit = 0;
+    let result = unsafe {
+        libc::connect(
+            fd.as_raw_fd(),
+            &addr as *const libc::sockaddr_ctl as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ctl>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut name = [0u8; libc::IFNAMSIZ];
+    let mut len = name.len() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SYSPROTO_CONTROL,
+            libc::UTUN_OPT_IFNAME,
+            name.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let name = CStr::from_bytes_until_nul(&name)
+        .map_err(io::Error::other)?
+        .to_string_lossy()
+        .into_owned();
+    Ok((fd, name))
+}
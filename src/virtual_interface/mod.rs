@@ -0,0 +1,144 @@
+use crate::config::InterfaceConfig;
+use crate::error::InitProcessError;
+use ipnetwork::IpNetwork;
+use std::fmt;
+#[cfg(not(target_os = "linux"))]
+use std::net::IpAddr;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "windows")]
+mod windows;
+
+// OSごとの仮想NIC。ドロップすると削除される
+pub trait VirtualInterface: Send + Sync {
+    fn name(&self) -> &str;
+}
+
+// 仮想NIC経由の経路の追加・削除と、ポリシールーティングの規則の追加・削除 (Linuxのみ)
+#[cfg(target_os = "linux")]
+pub use linux::{add_route, add_rule, delete_route, delete_rule};
+// ブリッジの作成・削除と接続 (Linuxのみ)
+#[cfg(target_os = "linux")]
+pub use linux::{create_bridge, delete_link, set_bridge_port};
+
+// メインの経路表のID
+pub const MAIN_TABLE: u32 = 254;
+
+// ポリシールーティングの規則。送信元アドレスとfwmarkの両方を指定した場合は両方に一致するパケットが対象になる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub source: Option<IpNetwork>,
+    pub fwmark: Option<u32>,
+    // 送信元を指定しない場合のアドレスファミリー
+    pub ipv6: bool,
+    pub table: u32,
+    pub priority: u32,
+}
+
+impl fmt::Display for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "priority {}", self.priority)?;
+        match self.source {
+            Some(source) => write!(f, " from {}", source)?,
+            None if self.ipv6 => write!(f, " from ::/0")?,
+            None => write!(f, " from all")?,
+        }
+        if let Some(fwmark) = self.fwmark {
+            write!(f, " fwmark {:#x}", fwmark)?;
+        }
+        write!(f, " lookup {}", self.table)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> InitProcessError {
+    InitProcessError::VirtualInterfaceError("経路・ブリッジの自動設定はLinuxのみ対応しています".to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn add_route(
+    _name: &str,
+    _destination: IpNetwork,
+    _gateway: Option<IpAddr>,
+    _table: u32,
+) -> Result<(), InitProcessError> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn delete_route(
+    _name: &str,
+    _destination: IpNetwork,
+    _gateway: Option<IpAddr>,
+    _table: u32,
+) -> Result<(), InitProcessError> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn add_rule(_rule: &PolicyRule) -> Result<(), InitProcessError> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn delete_rule(_rule: &PolicyRule) -> Result<(), InitProcessError> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn create_bridge(_name: &str) -> Result<bool, InitProcessError> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn set_bridge_port(_name: &str, _bridge: Option<&str>) -> Result<(), InitProcessError> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn delete_link(_name: &str) -> Result<(), InitProcessError> {
+    Err(unsupported())
+}
+
+// 仮想NICを作成し、アドレスを設定して有効化する
+#[cfg(target_os = "linux")]
+pub async fn create_virtual_interface(
+    config: &InterfaceConfig,
+    address: IpNetwork,
+) -> Result<Box<dyn VirtualInterface>, InitProcessError> {
+    Ok(Box::new(linux::TapInterface::create(config, address).await?))
+}
+
+// macOSではインターフェース名を指定できないため、作成された名前 (fethN/utunN) を使用する
+#[cfg(target_os = "macos")]
+pub async fn create_virtual_interface(
+    config: &InterfaceConfig,
+    address: IpNetwork,
+) -> Result<Box<dyn VirtualInterface>, InitProcessError> {
+    macos::create(config, address).await
+}
+
+#[cfg(target_os = "windows")]
+pub async fn create_virtual_interface(
+    config: &InterfaceConfig,
+    address: IpNetwork,
+) -> Result<Box<dyn VirtualInterface>, InitProcessError> {
+    if config.mode == crate::config::InterfaceMode::Tap {
+        tracing::warn!("wintunはL3のみに対応しているため、tunモードで作成します");
+    }
+    if config.mtu.is_some() || config.txqueuelen.is_some() || !config.addresses.is_empty() || config.disable_ipv6 {
+        tracing::warn!("[interface] mtu, txqueuelen, addresses, disable_ipv6 はWindowsでは使用できないため無視します");
+    }
+    Ok(Box::new(windows::WintunInterface::create(&config.name, address).await?))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub async fn create_virtual_interface(
+    _config: &InterfaceConfig,
+    _address: IpNetwork,
+) -> Result<Box<dyn VirtualInterface>, InitProcessError> {
+    Err(InitProcessError::VirtualInterfaceError("このOSには対応していません".to_string()))
+}
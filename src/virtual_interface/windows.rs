@@ -0,0 +1,51 @@
+use super::VirtualInterface;
+use crate::error::InitProcessError;
+use ipnetwork::IpNetwork;
+use std::sync::Arc;
+use wintun::{Adapter, Wintun};
+
+// wintun.dllが配置されていない場合はこのパスから読み込む
+const WINTUN_DLL: &str = "wintun.dll";
+const TUNNEL_TYPE: &str = "rdb-tunnel";
+
+// Windows: wintunアダプタ (L3) の作成とアドレスの設定のみ対応する。
+// キャプチャと注入はpnet (datalink) のEthernetのチャンネルを前提としており、wintunのL3のパケットを送受信する経路は未実装
+pub struct WintunInterface {
+    name: String,
+    _adapter: Arc<Adapter>,
+    _wintun: Wintun,
+}
+
+impl WintunInterface {
+    pub async fn create(name: &str, address: IpNetwork) -> Result<Self, InitProcessError> {
+        // SAFETY: wintun.dllは署名済みの公式配布物を使用する前提
+        let wintun = unsafe { wintun::load().or_else(|_| wintun::load_from_path(WINTUN_DLL)) }
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("wintun.dllの読み込みに失敗: {}", e)))?;
+
+        // 既存のアダプタを開くと他のインスタンスとパケットを奪い合うため、同名のアダプタがある場合は作成しない
+        if Adapter::open(&wintun, name).is_ok() {
+            return Err(InitProcessError::VirtualInterfaceError(format!(
+                "wintunアダプタ {} は既に存在します ([interface] name で別の名前を指定してください)",
+                name
+            )));
+        }
+        let adapter = Adapter::create(&wintun, name, TUNNEL_TYPE, None)
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("wintunアダプタの作成に失敗: {}", e)))?;
+
+        adapter
+            .set_network_addresses_tuple(address.ip(), address.mask(), None)
+            .map_err(|e| InitProcessError::VirtualInterfaceError(format!("IPアドレスの設定に失敗: {}", e)))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            _adapter: adapter,
+            _wintun: wintun,
+        })
+    }
+}
+
+impl VirtualInterface for WintunInterface {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}